@@ -0,0 +1,104 @@
+//! Stand-in for the DPU of an e2e test node. It receives the tables hamgrd programs over ZMQ, like
+//! orchagent on a real DPU, and reports the HA scope state the DPU would: the HA role requested in
+//! DASH_HA_SCOPE_TABLE is confirmed right away, or dead if the scope is disabled.
+use anyhow::Result;
+use clap::Parser;
+use std::collections::HashMap;
+use swss_common::{CxxString, DbConnector, KeyOpFieldValues, KeyOperation, ZmqConsumerStateTable, ZmqServer};
+use tracing::info;
+
+// ids of DPU_APPL_DB and DPU_STATE_DB in the node's db config
+const DPU_APPL_DB: i32 = 15;
+const DPU_STATE_DB: i32 = 14;
+
+#[derive(Parser, Debug)]
+#[command(name = "dpu-sim")]
+struct Args {
+    // ZMQ endpoint hamgrd programs the DPU tables to, midplane_ipv4:orchagent_zmq_port of the DPU.
+    #[arg(long, default_value = "tcp://127.0.0.1:8100")]
+    zmq_endpoint: String,
+    #[arg(long, default_value_t = 6379)]
+    redis_port: u16,
+}
+
+/// The HA state of the DPU, per HA scope.
+struct HaScopes {
+    state_db: DbConnector,
+    // (ha_role, ha_term, ha_role_start_time) by HA scope
+    roles: HashMap<String, (String, u64, i64)>,
+}
+
+impl HaScopes {
+    fn apply(&mut self, kfv: &KeyOpFieldValues) -> Result<()> {
+        let key = format!("DASH_HA_SCOPE_STATE|{}", kfv.key);
+        if kfv.operation == KeyOperation::Del {
+            self.roles.remove(&kfv.key);
+            self.state_db.del(&key)?;
+            return Ok(());
+        }
+
+        let field = |name: &str| kfv.field_values.get(name).and_then(|v| v.to_str().ok());
+        let ha_role = match field("disable") {
+            Some("true") => "dead",
+            _ => field("ha_role").unwrap_or("dead"),
+        };
+        let now = chrono::Utc::now().timestamp_millis();
+        let entry = self.roles.entry(kfv.key.clone()).or_insert((String::new(), 0, now));
+        if entry.0 != ha_role {
+            info!("{}: ha_role {} -> {ha_role}", kfv.key, entry.0);
+            *entry = (ha_role.to_string(), entry.1 + 1, now);
+        }
+
+        let (ha_role, ha_term, ha_role_start_time) = entry.clone();
+        let fields = [
+            ("last_updated_time", now.to_string()),
+            ("ha_role", ha_role),
+            ("ha_role_start_time", ha_role_start_time.to_string()),
+            ("ha_term", ha_term.to_string()),
+            ("activate_role_pending", "false".to_string()),
+            ("flow_reconcile_pending", "false".to_string()),
+            ("brainsplit_recover_pending", "false".to_string()),
+        ];
+        for (field, value) in fields {
+            self.state_db.hset(&key, field, &CxxString::new(value))?;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+
+    let appl_db = || DbConnector::new_tcp(DPU_APPL_DB, "127.0.0.1", args.redis_port, 0);
+    let mut zmqs = ZmqServer::new(&args.zmq_endpoint)?;
+    let mut ha_scope_table = ZmqConsumerStateTable::new(appl_db()?, "DASH_HA_SCOPE_TABLE", &mut zmqs, None, None)?;
+    // hamgrd programs these too. They are only drained.
+    let mut ha_set_table = ZmqConsumerStateTable::new(appl_db()?, "DASH_HA_SET_TABLE", &mut zmqs, None, None)?;
+    let mut bfd_table = ZmqConsumerStateTable::new(appl_db()?, "BFD_SESSION_TABLE", &mut zmqs, None, None)?;
+    info!("dpu-sim listening at {}", args.zmq_endpoint);
+
+    let mut ha_scopes = HaScopes {
+        state_db: DbConnector::new_tcp(DPU_STATE_DB, "127.0.0.1", args.redis_port, 0)?,
+        roles: HashMap::new(),
+    };
+    loop {
+        tokio::select! {
+            res = ha_scope_table.read_data_async() => {
+                res?;
+                for kfv in ha_scope_table.pops_async().await? {
+                    ha_scopes.apply(&kfv)?;
+                }
+            }
+            res = ha_set_table.read_data_async() => {
+                res?;
+                ha_set_table.pops_async().await?;
+            }
+            res = bfd_table.read_data_async() => {
+                res?;
+                bfd_table.pops_async().await?;
+            }
+        }
+    }
+}
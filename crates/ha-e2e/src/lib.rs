@@ -0,0 +1,15 @@
+//! Multi-node end-to-end test framework
+//!
+//! Each [`Node`] is a container on a private docker network running its own redis, swbusd, hamgrd
+//! and `dpu-sim`, which stands in for the DPU of the node. A [`Topology`] starts the nodes, writes
+//! the config that makes them peers and lets scenarios drive HA config and assert on the resulting
+//! DB state of every node.
+//!
+//! The tests need docker and the node image, see docker/Dockerfile. They are ignored by default:
+//!
+//!   cargo test -p ha-e2e -- --ignored
+pub mod node;
+pub mod topology;
+
+pub use node::{Db, Node, Process};
+pub use topology::Topology;
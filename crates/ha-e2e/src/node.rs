@@ -0,0 +1,231 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bollard::container::{Config, CreateContainerOptions, NetworkingConfig, RemoveContainerOptions};
+use bollard::exec::{CreateExecOptions, StartExecOptions};
+use bollard::models::{EndpointIpamConfig, EndpointSettings, HostConfig, PortBinding};
+use bollard::Docker;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use swss_common::{CxxString, DbConnector};
+use tokio::time::sleep;
+
+/// Image of the nodes. Can be overridden with HA_E2E_IMAGE.
+const DEFAULT_IMAGE: &str = "sonic-dash-ha-e2e";
+const REDIS_PORT: &str = "6379/tcp";
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Redis databases of a node. The ids match test_utils/hamgrd/database_config.json, which is the
+/// db config of the node image.
+#[derive(Clone, Copy, Debug)]
+pub enum Db {
+    Appl = 0,
+    Config = 4,
+    State = 6,
+    ChassisState = 13,
+    DpuState = 14,
+    DpuAppl = 15,
+}
+
+/// The daemons of a node.
+#[derive(Clone, Copy, Debug)]
+pub enum Process {
+    Swbusd,
+    Hamgrd,
+    DpuSim,
+}
+
+impl Process {
+    fn name(&self) -> &'static str {
+        match self {
+            Process::Swbusd => "swbusd",
+            Process::Hamgrd => "hamgrd",
+            Process::DpuSim => "dpu-sim",
+        }
+    }
+}
+
+/// A switch with a single DPU, running in its own container.
+pub struct Node {
+    pub name: String,
+    /// Address of the node on the test network, which is also its Loopback0 address
+    pub ip: Ipv4Addr,
+    pub slot_id: u32,
+    docker: Docker,
+    container_id: String,
+    redis_port: u16,
+}
+
+impl Node {
+    pub(crate) async fn start(docker: &Docker, network: &str, name: &str, ip: Ipv4Addr, slot_id: u32) -> Result<Self> {
+        let image = std::env::var("HA_E2E_IMAGE").unwrap_or_else(|_| DEFAULT_IMAGE.to_string());
+        let endpoint = EndpointSettings {
+            ipam_config: Some(EndpointIpamConfig {
+                ipv4_address: Some(ip.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let config = Config {
+            image: Some(image.clone()),
+            hostname: Some(name.to_string()),
+            exposed_ports: Some(HashMap::from([(REDIS_PORT.to_string(), HashMap::new())])),
+            host_config: Some(HostConfig {
+                // publish redis on a random host port so the test can reach every node's db
+                port_bindings: Some(HashMap::from([(
+                    REDIS_PORT.to_string(),
+                    Some(vec![PortBinding {
+                        host_ip: Some("127.0.0.1".to_string()),
+                        host_port: None,
+                    }]),
+                )])),
+                ..Default::default()
+            }),
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: HashMap::from([(network.to_string(), endpoint)]),
+            }),
+            ..Default::default()
+        };
+        let options = CreateContainerOptions {
+            name: name.to_string(),
+            platform: None,
+        };
+        let container = docker
+            .create_container(Some(options), config)
+            .await
+            .context(format!("creating container {name} from {image}"))?;
+        docker
+            .start_container::<String>(&container.id, None)
+            .await
+            .context(format!("starting container {name}"))?;
+
+        let redis_port = Self::published_redis_port(docker, &container.id).await?;
+        let node = Node {
+            name: name.to_string(),
+            ip,
+            slot_id,
+            docker: docker.clone(),
+            container_id: container.id,
+            redis_port,
+        };
+        node.wait_for_redis().await?;
+        Ok(node)
+    }
+
+    async fn published_redis_port(docker: &Docker, container_id: &str) -> Result<u16> {
+        let info = docker.inspect_container(container_id, None).await?;
+        let port = info
+            .network_settings
+            .and_then(|s| s.ports)
+            .and_then(|mut ports| ports.remove(REDIS_PORT))
+            .flatten()
+            .and_then(|bindings| bindings.into_iter().find_map(|b| b.host_port))
+            .ok_or_else(|| anyhow!("redis port of {container_id} is not published"))?;
+        Ok(port.parse()?)
+    }
+
+    async fn wait_for_redis(&self) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while self.db(Db::Config).is_err() {
+            if Instant::now() > deadline {
+                bail!("redis of {} did not come up", self.name);
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    pub fn db(&self, db: Db) -> Result<DbConnector> {
+        Ok(DbConnector::new_tcp(db as i32, "127.0.0.1", self.redis_port, 0)?)
+    }
+
+    pub fn hset(&self, db: Db, key: &str, fields: &[(&str, &str)]) -> Result<()> {
+        let conn = self.db(db)?;
+        for (field, value) in fields {
+            conn.hset(key, field, &CxxString::new(value))?;
+        }
+        Ok(())
+    }
+
+    pub fn hgetall(&self, db: Db, key: &str) -> Result<HashMap<String, String>> {
+        let mut fields = HashMap::new();
+        for (field, value) in self.db(db)?.hgetall(key)? {
+            fields.insert(field, value.to_str()?.to_string());
+        }
+        Ok(fields)
+    }
+
+    pub fn del(&self, db: Db, key: &str) -> Result<()> {
+        self.db(db)?.del(key)?;
+        Ok(())
+    }
+
+    /// Wait until `field` of `key` is `expected`. The error shows the last seen entry.
+    pub async fn wait_for_field(
+        &self,
+        db: Db,
+        key: &str,
+        field: &str,
+        expected: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let entry = self.hgetall(db, key)?;
+            if entry.get(field).map(String::as_str) == Some(expected) {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "{}: {db:?} {key} {field} is not {expected} after {timeout:?}: {entry:?}",
+                    self.name
+                );
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Start a daemon in the background. Its output goes to /var/log/<process>.log in the container.
+    pub async fn start_process(&self, process: Process) -> Result<()> {
+        let name = process.name();
+        let cmd = match process {
+            Process::Swbusd | Process::Hamgrd => format!("exec {name} -s {}", self.slot_id),
+            Process::DpuSim => format!("exec {name}"),
+        };
+        self.exec(&format!("{cmd} >>/var/log/{name}.log 2>&1")).await
+    }
+
+    pub async fn stop_process(&self, process: Process) -> Result<()> {
+        self.exec(&format!("pkill -x {}", process.name())).await
+    }
+
+    async fn exec(&self, cmd: &str) -> Result<()> {
+        let exec = self
+            .docker
+            .create_exec(
+                &self.container_id,
+                CreateExecOptions {
+                    cmd: Some(vec!["sh", "-c", cmd]),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let options = StartExecOptions {
+            detach: true,
+            ..Default::default()
+        };
+        self.docker
+            .start_exec(&exec.id, Some(options))
+            .await
+            .context(format!("{}: running {cmd}", self.name))?;
+        Ok(())
+    }
+
+    pub(crate) async fn remove(&self) -> Result<()> {
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        self.docker.remove_container(&self.container_id, Some(options)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::node::{Db, Node, Process};
+use anyhow::{Context, Result};
+use bollard::models::{Ipam, IpamConfig};
+use bollard::network::CreateNetworkOptions;
+use bollard::Docker;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const SWBUS_PORT: &str = "23606";
+const ORCHAGENT_ZMQ_PORT: &str = "8100";
+
+// every topology gets its own 10.250.<n>.0/24 so tests can run in parallel
+static NEXT_SUBNET: AtomicU8 = AtomicU8::new(0);
+
+/// A set of nodes in the same region and cluster. Node `i` is switch<i>, whose DPU is
+/// switch<i>_dpu0 and is the only member of vdpu<i>.
+pub struct Topology {
+    docker: Docker,
+    network: String,
+    pub nodes: Vec<Node>,
+}
+
+impl Topology {
+    /// Start `count` nodes on a new docker network `name`, with their base config written but no
+    /// daemon running yet.
+    pub async fn start(name: &str, count: u8) -> Result<Self> {
+        let docker = Docker::connect_with_local_defaults().context("connecting to docker")?;
+        let subnet = NEXT_SUBNET.fetch_add(1, Ordering::Relaxed);
+        let options = CreateNetworkOptions {
+            name: name.to_string(),
+            driver: "bridge".to_string(),
+            ipam: Ipam {
+                config: Some(vec![IpamConfig {
+                    subnet: Some(format!("10.250.{subnet}.0/24")),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        docker
+            .create_network(options)
+            .await
+            .context(format!("creating network {name}"))?;
+
+        let mut topology = Topology {
+            docker,
+            network: name.to_string(),
+            nodes: Vec::new(),
+        };
+        for i in 0..count {
+            let node_name = format!("{name}-switch{i}");
+            let ip = Ipv4Addr::new(10, 250, subnet, 10 + i);
+            // keep what was started so far if a node fails to start, teardown removes it
+            match Node::start(&topology.docker, name, &node_name, ip, 0).await {
+                Ok(node) => topology.nodes.push(node),
+                Err(e) => {
+                    topology.teardown().await.ok();
+                    return Err(e);
+                }
+            }
+        }
+        topology.write_base_config()?;
+        Ok(topology)
+    }
+
+    fn write_base_config(&self) -> Result<()> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            let ip = node.ip.to_string();
+            node.hset(
+                Db::Config,
+                "DEVICE_METADATA|localhost",
+                &[("region", "region-e2e"), ("cluster", "cluster-e2e")],
+            )?;
+            node.hset(Db::Config, "LOOPBACK_INTERFACE|Loopback0", &[("NULL", "NULL")])?;
+            node.hset(
+                Db::Config,
+                &format!("LOOPBACK_INTERFACE|Loopback0|{ip}/32"),
+                &[("NULL", "NULL")],
+            )?;
+
+            node.hset(
+                Db::Config,
+                &format!("DPU|switch{i}_dpu0"),
+                &[
+                    ("dpu_id", "0"),
+                    ("state", "up"),
+                    ("vdpu_id", format!("vdpu{i}").as_str()),
+                    ("pa_ipv4", Self::dpu_pa_ipv4(i).to_string().as_str()),
+                    // dpu-sim listens next to hamgrd in the node
+                    ("midplane_ipv4", "127.0.0.1"),
+                    ("orchagent_zmq_port", ORCHAGENT_ZMQ_PORT),
+                    ("swbus_port", SWBUS_PORT),
+                ],
+            )?;
+            for (j, peer) in self.nodes.iter().enumerate().filter(|(j, _)| *j != i) {
+                node.hset(
+                    Db::Config,
+                    &format!("REMOTE_DPU|switch{j}_dpu0"),
+                    &[
+                        ("dpu_id", "0"),
+                        ("type", "cluster"),
+                        ("npu_ipv4", peer.ip.to_string().as_str()),
+                        ("pa_ipv4", Self::dpu_pa_ipv4(j).to_string().as_str()),
+                        ("swbus_port", SWBUS_PORT),
+                    ],
+                )?;
+            }
+            for k in 0..self.nodes.len() {
+                node.hset(
+                    Db::Config,
+                    &format!("VDPU|vdpu{k}"),
+                    &[("main_dpu_ids", format!("switch{k}_dpu0").as_str())],
+                )?;
+            }
+            node.hset(
+                Db::Config,
+                "DASH_HA_GLOBAL_CONFIG|GLOBAL",
+                &[
+                    ("dpu_bfd_probe_interval_in_ms", "1000"),
+                    ("dpu_bfd_probe_multiplier", "3"),
+                    ("cp_data_channel_port", "6000"),
+                    ("dp_channel_dst_port", "7000"),
+                    ("dp_channel_src_port_min", "7001"),
+                    ("dp_channel_src_port_max", "7010"),
+                    ("dp_channel_probe_interval_ms", "500"),
+                    ("dp_channel_probe_fail_threshold", "5"),
+                    ("vnet_name", "Vnet55"),
+                ],
+            )?;
+
+            // pmon and the BFD sessions of the DPU report up
+            node.hset(
+                Db::ChassisState,
+                "DPU_STATE|dpu0",
+                &[
+                    ("dpu_midplane_link_state", "up"),
+                    ("dpu_control_plane_state", "up"),
+                    ("dpu_data_plane_state", "up"),
+                ],
+            )?;
+            let peer_ips: Vec<String> = self.nodes.iter().map(|n| n.ip.to_string()).collect();
+            node.hset(
+                Db::DpuState,
+                "DASH_BFD_PROBE_STATE|dpu0",
+                &[("v4_bfd_up_sessions", peer_ips.join(",").as_str())],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// PA address of the DPU of node `i`.
+    pub fn dpu_pa_ipv4(i: usize) -> Ipv4Addr {
+        Ipv4Addr::new(18, i as u8, 202, 1)
+    }
+
+    /// Start swbusd, the DPU simulator and hamgrd on every node.
+    pub async fn start_daemons(&self) -> Result<()> {
+        for node in &self.nodes {
+            node.start_process(Process::Swbusd).await?;
+            node.start_process(Process::DpuSim).await?;
+            node.start_process(Process::Hamgrd).await?;
+        }
+        Ok(())
+    }
+
+    /// Configure HA set `ha_set` over the vDPUs of `members` on every node.
+    pub fn add_ha_set(&self, ha_set: &str, members: &[usize]) -> Result<()> {
+        let vdpu_ids: Vec<String> = members.iter().map(|i| format!("vdpu{i}")).collect();
+        for node in &self.nodes {
+            node.hset(
+                Db::Appl,
+                &format!("DASH_HA_SET_CONFIG_TABLE:{ha_set}"),
+                &[
+                    ("version", "1"),
+                    ("vip_v4", "3.2.1.0"),
+                    ("owner", "dpu"),
+                    ("scope", "dpu"),
+                    ("vdpu_ids", vdpu_ids.join(",").as_str()),
+                    ("preferred_vdpu_ids", vdpu_ids[0].as_str()),
+                    ("preferred_standalone_vdpu_index", "0"),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Set the desired HA state of the DPU-scope HA scope of node `i` in `ha_set`.
+    pub fn set_desired_ha_state(&self, i: usize, ha_set: &str, desired_ha_state: &str) -> Result<()> {
+        let version = chrono::Utc::now().timestamp_millis().to_string();
+        self.nodes[i].hset(
+            Db::Appl,
+            &format!("DASH_HA_SCOPE_CONFIG_TABLE:vdpu{i}:{ha_set}"),
+            &[
+                ("version", version.as_str()),
+                ("disable", "false"),
+                ("desired_ha_state", desired_ha_state),
+                ("approved_pending_operation_ids", ""),
+            ],
+        )
+    }
+
+    /// Key of the HA scope of node `i` in its STATE_DB/DASH_HA_SCOPE_STATE.
+    pub fn ha_scope_state_key(i: usize, ha_set: &str) -> String {
+        format!("DASH_HA_SCOPE_STATE|vdpu{i}|{ha_set}")
+    }
+
+    /// Remove the nodes and the network.
+    pub async fn teardown(self) -> Result<()> {
+        for node in &self.nodes {
+            node.remove().await?;
+        }
+        self.docker.remove_network(&self.network).await?;
+        Ok(())
+    }
+}
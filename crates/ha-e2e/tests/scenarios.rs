@@ -0,0 +1,98 @@
+use anyhow::Result;
+use ha_e2e::{Db, Process, Topology};
+use std::time::Duration;
+
+const HA_SET: &str = "haset0";
+const CONVERGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn wait_for_ha_state(topology: &Topology, i: usize, expected: &str) -> Result<()> {
+    topology.nodes[i]
+        .wait_for_field(
+            Db::State,
+            &Topology::ha_scope_state_key(i, HA_SET),
+            "local_ha_state",
+            expected,
+            CONVERGE_TIMEOUT,
+        )
+        .await
+}
+
+/// Two nodes in an HA set, node 0 active and node 1 standby.
+async fn start_pair(name: &str) -> Result<Topology> {
+    let topology = Topology::start(name, 2).await?;
+    topology.start_daemons().await?;
+    topology.add_ha_set(HA_SET, &[0, 1])?;
+    topology.set_desired_ha_state(0, HA_SET, "active")?;
+    topology.set_desired_ha_state(1, HA_SET, "standby")?;
+    Ok(topology)
+}
+
+async fn peer_up(topology: &Topology) -> Result<()> {
+    wait_for_ha_state(topology, 0, "active").await?;
+    wait_for_ha_state(topology, 1, "standby").await?;
+
+    // each DPU is programmed with the other as its peer
+    for (i, peer) in [(0, 1), (1, 0)] {
+        let peer_ip = Topology::dpu_pa_ipv4(peer).to_string();
+        topology.nodes[i]
+            .wait_for_field(
+                Db::DpuAppl,
+                &format!("DASH_HA_SET_TABLE:{HA_SET}"),
+                "peer_ip",
+                &peer_ip,
+                CONVERGE_TIMEOUT,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+async fn failover(topology: &Topology) -> Result<()> {
+    topology.nodes[0].stop_process(Process::Hamgrd).await?;
+    topology.set_desired_ha_state(1, HA_SET, "standalone")?;
+    wait_for_ha_state(topology, 1, "standalone").await
+}
+
+async fn recovery(topology: &Topology) -> Result<()> {
+    topology.nodes[0].start_process(Process::Hamgrd).await?;
+    topology.set_desired_ha_state(0, HA_SET, "standby")?;
+    wait_for_ha_state(topology, 0, "standby").await
+}
+
+/// Run `scenario` on a fresh pair and tear it down whatever the outcome.
+async fn run<F>(name: &str, scenario: F) -> Result<()>
+where
+    F: AsyncFnOnce(&Topology) -> Result<()>,
+{
+    let topology = start_pair(name).await?;
+    let res = scenario(&topology).await;
+    topology.teardown().await?;
+    res
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the sonic-dash-ha-e2e image"]
+async fn test_peer_up() -> Result<()> {
+    run("ha-e2e-peer-up", peer_up).await
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the sonic-dash-ha-e2e image"]
+async fn test_failover() -> Result<()> {
+    run("ha-e2e-failover", async |topology: &Topology| {
+        peer_up(topology).await?;
+        failover(topology).await
+    })
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the sonic-dash-ha-e2e image"]
+async fn test_recovery() -> Result<()> {
+    run("ha-e2e-recovery", async |topology: &Topology| {
+        peer_up(topology).await?;
+        failover(topology).await?;
+        recovery(topology).await
+    })
+    .await
+}
@@ -0,0 +1,267 @@
+//! Generates the sonic-db table structs and their ParseFieldValues impls from schema/db_tables.yaml. See
+//! the schema file for the format.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+const SCHEMA: &str = "schema/db_tables.yaml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TableSchema {
+    #[serde(rename = "struct")]
+    struct_name: String,
+    #[serde(default)]
+    doc: String,
+    table_name: String,
+    key_separator: String,
+    db_name: String,
+    #[serde(default)]
+    is_dpu: bool,
+    #[serde(default)]
+    skip_serializing_none: bool,
+    #[serde(default)]
+    lenient: bool,
+    #[serde(default)]
+    derives: Vec<String>,
+    fields: Vec<FieldSchema>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldSchema {
+    name: String,
+    #[serde(rename = "type")]
+    field_type: String,
+    #[serde(default)]
+    optional: bool,
+    rename: Option<String>,
+    #[serde(default)]
+    doc: String,
+}
+
+impl FieldSchema {
+    fn is_list(&self) -> bool {
+        self.field_type == "list"
+    }
+
+    /// Rust type of the field, without the Option wrapper of optional fields.
+    fn base_type(&self) -> &str {
+        match self.field_type.as_str() {
+            "string" => "String",
+            "list" => "Vec<String>",
+            "ipv4" => "std::net::Ipv4Addr",
+            "ipv6" => "std::net::Ipv6Addr",
+            "ip" => "std::net::IpAddr",
+            other => other,
+        }
+    }
+
+    fn rust_type(&self) -> String {
+        let ty = self.base_type();
+        match self.optional {
+            true => format!("Option<{ty}>"),
+            false => ty.to_string(),
+        }
+    }
+
+    fn db_name(&self) -> &str {
+        self.rename.as_deref().unwrap_or(&self.name)
+    }
+}
+
+fn write_doc(out: &mut String, indent: &str, doc: &str) {
+    for line in doc.lines() {
+        writeln!(out, "{indent}/// {line}").unwrap();
+    }
+}
+
+fn generate_table(out: &mut String, table: &TableSchema) {
+    assert_eq!(
+        table.key_separator.chars().count(),
+        1,
+        "{}: key_separator must be a single character",
+        table.struct_name
+    );
+
+    write_doc(out, "", &table.doc);
+    if table.fields.iter().any(FieldSchema::is_list) {
+        writeln!(out, "#[serde_as]").unwrap();
+    }
+    if table.skip_serializing_none {
+        writeln!(out, "#[skip_serializing_none]").unwrap();
+    }
+    let mut derives = vec!["Serialize".to_string(), "Deserialize".to_string()];
+    derives.extend(table.derives.iter().cloned());
+    derives.extend(["JsonSchema".to_string(), "SonicDb".to_string()]);
+    writeln!(out, "#[derive({})]", derives.join(", ")).unwrap();
+    write!(
+        out,
+        "#[sonicdb(table_name = {:?}, key_separator = {:?}, db_name = {:?}",
+        table.table_name, table.key_separator, table.db_name
+    )
+    .unwrap();
+    if table.is_dpu {
+        write!(out, ", is_dpu = \"true\"").unwrap();
+    }
+    writeln!(out, ")]").unwrap();
+
+    writeln!(out, "pub struct {} {{", table.struct_name).unwrap();
+    for field in &table.fields {
+        write_doc(out, "    ", &field.doc);
+        if let Some(rename) = &field.rename {
+            writeln!(out, "    #[serde(rename = {rename:?})]").unwrap();
+        }
+        if field.is_list() {
+            // lists are stored as a comma separated string
+            match field.optional {
+                true => {
+                    writeln!(out, "    #[schemars(with = \"Option<String>\")]").unwrap();
+                    writeln!(
+                        out,
+                        "    #[serde_as(as = \"Option<StringWithSeparator::<CommaSeparator, String>>\")]"
+                    )
+                    .unwrap();
+                }
+                false => {
+                    writeln!(out, "    #[schemars(with = \"String\")]").unwrap();
+                    writeln!(
+                        out,
+                        "    #[serde_as(as = \"StringWithSeparator::<CommaSeparator, String>\")]"
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        writeln!(out, "    pub {}: {},", field.name, field.rust_type()).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    generate_parse_field_values(out, table);
+}
+
+fn generate_parse_field_values(out: &mut String, table: &TableSchema) {
+    let names: Vec<&str> = table.fields.iter().map(|f| f.name.as_str()).collect();
+    let db_names: Vec<String> = table.fields.iter().map(|f| format!("{:?}", f.db_name())).collect();
+    writeln!(out, "impl ParseFieldValues for {} {{", table.struct_name).unwrap();
+    writeln!(out, "    fn field_names() -> &'static [&'static str] {{").unwrap();
+    writeln!(out, "        &[{}]", db_names.join(", ")).unwrap();
+    writeln!(out, "    }}\n").unwrap();
+    if table.lenient {
+        writeln!(out, "    fn lenient() -> bool {{").unwrap();
+        writeln!(out, "        true").unwrap();
+        writeln!(out, "    }}\n").unwrap();
+    }
+    writeln!(
+        out,
+        "    fn parse_field_values(key: &str, fvs: &FieldValues) -> std::result::Result<Self, Vec<FieldError>> {{"
+    )
+    .unwrap();
+    // fields of lenient tables that are optional never fail
+    match table.fields.iter().all(|f| table.lenient && f.optional) {
+        true => writeln!(out, "        let errors = Vec::new();"),
+        false => writeln!(out, "        let mut errors = Vec::new();"),
+    }
+    .unwrap();
+    for field in &table.fields {
+        match field.optional {
+            true if table.lenient => writeln!(
+                out,
+                "        let {} = parse_lenient_field::<{}>({:?}, key, fvs, {:?});",
+                field.name,
+                field.base_type(),
+                table.table_name,
+                field.db_name()
+            ),
+            // optional fields may be missing in entries written by older tooling
+            true => writeln!(
+                out,
+                "        let {} = parse_optional_field::<{}>({:?}, key, fvs, {:?}, &mut errors);",
+                field.name,
+                field.base_type(),
+                table.table_name,
+                field.db_name()
+            ),
+            false => writeln!(
+                out,
+                "        let {} = parse_field::<{}>(key, fvs, {:?}, &mut errors);",
+                field.name,
+                field.rust_type(),
+                field.db_name()
+            ),
+        }
+        .unwrap();
+    }
+    // fields added by newer tooling are ignored
+    writeln!(
+        out,
+        "        note_unknown_fields({:?}, key, fvs, Self::field_names());",
+        table.table_name
+    )
+    .unwrap();
+    let somes: Vec<String> = names.iter().map(|n| format!("Some({n})")).collect();
+    writeln!(
+        out,
+        "        let ({},) = ({},) else {{",
+        somes.join(", "),
+        names.join(", ")
+    )
+    .unwrap();
+    writeln!(out, "            return Err(errors);").unwrap();
+    writeln!(out, "        }};").unwrap();
+    writeln!(out, "        Ok(Self {{ {} }})", names.join(", ")).unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+}
+
+/// Generates `db_key_separator`, the key separator shared by the tables of each db.
+fn generate_db_key_separators(out: &mut String, tables: &[TableSchema]) {
+    let mut separators = BTreeMap::new();
+    for table in tables {
+        let separator = separators
+            .entry(table.db_name.as_str())
+            .or_insert(table.key_separator.as_str());
+        assert_eq!(
+            *separator, table.key_separator,
+            "{}: key_separator differs from the other tables of {}",
+            table.struct_name, table.db_name
+        );
+    }
+
+    writeln!(
+        out,
+        "/// The key separator of the tables of `db_name`, None for a db without tables in the schema."
+    )
+    .unwrap();
+    writeln!(out, "pub fn db_key_separator(db_name: &str) -> Option<char> {{").unwrap();
+    writeln!(out, "    match db_name {{").unwrap();
+    for (db_name, separator) in separators {
+        writeln!(
+            out,
+            "        {db_name:?} => Some({:?}),",
+            separator.chars().next().unwrap()
+        )
+        .unwrap();
+    }
+    writeln!(out, "        _ => None,").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={SCHEMA}");
+
+    let schema = std::fs::read_to_string(SCHEMA).unwrap_or_else(|e| panic!("failed to read {SCHEMA}: {e}"));
+    let tables: Vec<TableSchema> =
+        serde_yaml::from_str(&schema).unwrap_or_else(|e| panic!("failed to parse {SCHEMA}: {e}"));
+
+    let mut out = String::from("// @generated by build.rs from schema/db_tables.yaml. Do not edit.\n\n");
+    for table in &tables {
+        generate_table(&mut out, table);
+    }
+    generate_db_key_separators(&mut out, &tables);
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("db_tables.rs"), out).unwrap();
+}
@@ -6,19 +6,33 @@ pub mod ha_scope;
 pub mod ha_set;
 pub mod vdpu;
 
+#[cfg(test)]
+pub mod explore;
+#[cfg(test)]
+pub mod fixtures;
+#[cfg(test)]
+pub mod scenario;
 #[cfg(test)]
 pub mod test;
+use crate::db_structs::HamgrdActorSnapshotTable;
+use crate::generation::GenerationCheckedTable;
+#[cfg(feature = "dpu")]
+use crate::orchagent_lag::{ApplStateAcks, LagLimits, LagMonitoredTable};
+use crate::TableSource;
 use anyhow::Result as AnyhowResult;
 use std::sync::Arc;
-use swbus_actor::{spawn, Actor, ActorMessage};
+use swbus_actor::{Actor, ActorMessage, State};
 use swbus_edge::swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_edge::swbus_proto::result::*;
 use swbus_edge::swbus_proto::swbus::{swbus_message::Body, DataRequest, ServicePath, SwbusErrorCode, SwbusMessage};
 use swbus_edge::SwbusEdgeRuntime;
-use swss_common::{
-    KeyOpFieldValues, KeyOperation, SonicDbTable, SubscriberStateTable, ZmqClient, ZmqProducerStateTable,
+use swss_common::{KeyOpFieldValues, KeyOperation, SonicDbTable};
+#[cfg(feature = "dpu")]
+use swss_common::{ZmqClient, ZmqProducerStateTable};
+use swss_common_bridge::{
+    consumer::{ConsumerBridge, KeyFilter},
+    producer::{spawn_producer_bridge, BatchPolicy, RetryPolicy},
 };
-use swss_common_bridge::{consumer::ConsumerBridge, producer::spawn_producer_bridge};
 use tokio::sync::mpsc::{channel, Receiver};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
@@ -30,6 +44,11 @@ pub trait DbBasedActor: Actor {
     where
         Self: Sized;
 
+    /// Keys of the table entries the actor creator is fed, so that actors are only created for them.
+    fn key_filter() -> KeyFilter {
+        KeyFilter::any()
+    }
+
     async fn start_actor_creator<T>(edge_runtime: Arc<SwbusEdgeRuntime>) -> AnyhowResult<Vec<ConsumerBridge>>
     where
         Self: Sized,
@@ -44,14 +63,14 @@ pub trait DbBasedActor: Actor {
 
         tokio::task::spawn(ac.run());
 
-        let config_db = crate::db_for_table::<T>().await?;
-        let sst = SubscriberStateTable::new_async(config_db, T::table_name(), None, None).await?;
+        let sst = crate::tables::subscribe::<T>().await?;
         let addr = crate::common_bridge_sp::<T>(&edge_runtime);
         let base_addr = edge_runtime.get_base_sp();
         Ok(vec![ConsumerBridge::spawn(
             edge_runtime.clone(),
             addr,
             sst,
+            Self::key_filter(),
             move |kfv: &KeyOpFieldValues| {
                 let mut addr = base_addr.clone();
                 addr.resource_type = Self::name().to_owned();
@@ -63,9 +82,23 @@ pub trait DbBasedActor: Actor {
     }
 }
 
+/// Save snapshots of the state of the actor `name` with `id` to STATE_DB, and restore the snapshot its
+/// previous instance saved, see [`swbus_actor::snapshot`]. Returns the actor state of that snapshot.
+pub async fn enable_snapshots(state: &mut State, name: &str, id: &str) -> Option<serde_json::Value> {
+    let table = match crate::tables::open_table::<HamgrdActorSnapshotTable>().await {
+        Ok(table) => table,
+        Err(e) => {
+            error!("Failed to open the snapshot table, {name} {id} runs without snapshots: {e:#}");
+            return None;
+        }
+    };
+    let key = format!("{name}{}{id}", HamgrdActorSnapshotTable::source_key_separator());
+    state.enable_snapshots(table, key).await
+}
+
 pub struct ActorCreator<F, T>
 where
-    F: Fn(String) -> AnyhowResult<T>,
+    F: Fn(String) -> AnyhowResult<T> + Clone + Send + 'static,
     T: Actor,
 {
     sp: ServicePath,
@@ -77,7 +110,7 @@ where
 // Connection worker facade
 impl<F, T> ActorCreator<F, T>
 where
-    F: Fn(String) -> AnyhowResult<T>,
+    F: Fn(String) -> AnyhowResult<T> + Clone + Send + 'static,
     T: Actor,
 {
     pub fn new(sp: ServicePath, rt: Arc<SwbusEdgeRuntime>, public: bool, create_fn: F) -> Self {
@@ -140,6 +173,12 @@ where
                 "actor doesn't exist: not from common-bridge".to_string(),
             ));
         }
+        if crate::shutdown::is_draining() {
+            return Err(SwbusError::route(
+                SwbusErrorCode::NoRoute,
+                "actor doesn't exist: hamgrd is shutting down".to_string(),
+            ));
+        }
         if let Some(Body::DataRequest(DataRequest { payload })) = &msg.body {
             match ActorMessage::deserialize(payload) {
                 Ok(actor_msg) => {
@@ -164,7 +203,18 @@ where
                             format!("Failed to create actor {}. Error: {}", sp.to_swbusd_service_path(), e),
                         )
                     })?;
-                    spawn(actor, &destination.resource_type, &destination.resource_id);
+                    // a crashed actor is recreated the same way, and starts over from its init
+                    let create_fn = self.create_fn.clone();
+                    let key = kfv.key.clone();
+                    crate::slot::current()
+                        .actor_runtime()
+                        .expect("the actor runtime of the slot is set before the actor creators start")
+                        .spawn_supervised(
+                            actor,
+                            move || create_fn(key.clone()),
+                            &destination.resource_type,
+                            &destination.resource_id,
+                        );
                 }
                 Err(_) => {
                     // log a message
@@ -212,9 +262,7 @@ where
     T: SonicDbTable + 'static,
     F: Fn(&KeyOpFieldValues) -> bool + Sync + Send + 'static,
 {
-    let db = crate::db_for_table::<T>().await?;
-
-    let sst = SubscriberStateTable::new_async(db, T::table_name(), None, None).await?;
+    let sst = crate::tables::subscribe::<T>().await?;
 
     let addr = crate::common_bridge_sp::<T>(&edge_runtime);
 
@@ -224,6 +272,7 @@ where
             edge_runtime,
             addr,
             sst,
+            KeyFilter::any(),
             move |kfv: &KeyOpFieldValues| {
                 let key = match single_entry {
                     true => T::table_name().to_owned(),
@@ -239,6 +288,7 @@ where
             edge_runtime,
             addr,
             sst,
+            KeyFilter::any(),
             move |kfv: &KeyOpFieldValues| {
                 let mut addr = base_addr.clone();
                 addr.resource_type = actor_name.to_owned();
@@ -256,6 +306,15 @@ where
     }
 }
 
+/// Writes to orchagent collected into one batch, so that bringing up an HA set with many entries
+/// doesn't wake orchagent up once per entry.
+#[cfg(feature = "dpu")]
+const ZMQ_BATCH: BatchPolicy = BatchPolicy {
+    max_entries: 128,
+    max_delay: std::time::Duration::from_millis(5),
+};
+
+#[cfg(feature = "dpu")]
 pub async fn spawn_zmq_producer_bridge<T>(
     edge_runtime: Arc<SwbusEdgeRuntime>,
     zmq_endpoint: &str,
@@ -263,18 +322,119 @@ pub async fn spawn_zmq_producer_bridge<T>(
 where
     T: SonicDbTable + 'static,
 {
+    #[cfg(feature = "dev-sim")]
+    if crate::sim::is_active() {
+        return spawn_sim_producer_bridge::<T>(edge_runtime).await;
+    }
     if let Ok(zmqc) = ZmqClient::new(zmq_endpoint) {
         let dpu_appl_db = crate::db_for_table::<T>().await?;
         let zpst = ZmqProducerStateTable::new(dpu_appl_db, T::table_name(), zmqc, true).unwrap();
 
+        let dead_letters = crate::dead_letters::StateDbDeadLetters::new(T::table_name()).await?;
+
         let sp = crate::common_bridge_sp::<T>(&edge_runtime);
         info!(
             "spawned ZMQ producer bridge for {} at {}",
             T::table_name(),
             sp.to_longest_path()
         );
-        Ok(spawn_producer_bridge(edge_runtime.clone(), sp, zpst))
+        // Writes of a superseded hamgrd are dropped
+        let table = GenerationCheckedTable {
+            table: zpst,
+            generation: crate::generation::generation(),
+        };
+        // Writes are rejected while orchagent doesn't acknowledge the ones before
+        let acks = ApplStateAcks::new(T::table_name()).await?;
+        let table = LagMonitoredTable::new(table, T::table_name(), acks, LagLimits::default());
+        Ok(spawn_producer_bridge(
+            edge_runtime.clone(),
+            sp,
+            table,
+            RetryPolicy::default(),
+            ZMQ_BATCH,
+            dead_letters,
+        ))
     } else {
         anyhow::bail!("Failed to connect to ZMQ server at {}", zmq_endpoint);
     }
 }
+
+/// Stands in for the ZMQ producer bridge of `T` with `--dev-sim`. The writes go to the in-memory
+/// store, where they can be inspected in the dump.
+#[cfg(feature = "dev-sim")]
+async fn spawn_sim_producer_bridge<T>(edge_runtime: Arc<SwbusEdgeRuntime>) -> AnyhowResult<JoinHandle<()>>
+where
+    T: SonicDbTable + 'static,
+{
+    let dead_letters = crate::dead_letters::StateDbDeadLetters::new(T::table_name()).await?;
+    let sp = crate::common_bridge_sp::<T>(&edge_runtime);
+    info!(
+        "spawned in-memory producer bridge for {} at {}",
+        T::table_name(),
+        sp.to_longest_path()
+    );
+    let table = GenerationCheckedTable {
+        table: crate::sim::SimTable::new(&T::source_db_name(), T::table_name()),
+        generation: crate::generation::generation(),
+    };
+    Ok(spawn_producer_bridge(
+        edge_runtime.clone(),
+        sp,
+        table,
+        RetryPolicy::default(),
+        BatchPolicy::default(),
+        dead_letters,
+    ))
+}
+
+/// Stands in for the ZMQ producer bridge of `T` without the `dpu` feature. The writes are logged
+/// instead of sent to orchagent, so the actors still get their writes acknowledged.
+#[cfg(not(feature = "dpu"))]
+pub async fn spawn_zmq_producer_bridge<T>(
+    edge_runtime: Arc<SwbusEdgeRuntime>,
+    _zmq_endpoint: &str,
+) -> AnyhowResult<JoinHandle<()>>
+where
+    T: SonicDbTable + 'static,
+{
+    #[cfg(feature = "dev-sim")]
+    if crate::sim::is_active() {
+        return spawn_sim_producer_bridge::<T>(edge_runtime).await;
+    }
+    let dead_letters = crate::dead_letters::StateDbDeadLetters::new(T::table_name()).await?;
+    let sp = crate::common_bridge_sp::<T>(&edge_runtime);
+    info!(
+        "spawned logging producer bridge for {} at {}, DPU support is not built in",
+        T::table_name(),
+        sp.to_longest_path()
+    );
+    let table = GenerationCheckedTable {
+        table: LoggingProducerTable(T::table_name()),
+        generation: crate::generation::generation(),
+    };
+    Ok(spawn_producer_bridge(
+        edge_runtime.clone(),
+        sp,
+        table,
+        RetryPolicy::default(),
+        BatchPolicy::default(),
+        dead_letters,
+    ))
+}
+
+/// A producer table that only logs the writes to the table it is named after.
+#[cfg(not(feature = "dpu"))]
+struct LoggingProducerTable(&'static str);
+
+#[cfg(not(feature = "dpu"))]
+impl swss_common_bridge::producer::ProducerTable for LoggingProducerTable {
+    async fn set(&mut self, key: &str, fvs: swss_common::FieldValues) -> swbus_actor::Result<()> {
+        info!("{}: set {key} {fvs:?}", self.0);
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> swbus_actor::Result<()> {
+        info!("{}: del {key}", self.0);
+        Ok(())
+    }
+}
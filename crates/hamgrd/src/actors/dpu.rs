@@ -1,25 +1,51 @@
+mod bfd_dampening;
+
 use crate::actors::{spawn_consumer_bridge_for_actor, ActorCreator};
+use crate::config_validation::parse_config;
 use crate::db_structs::{
-    BfdSessionTable, DashBfdProbeState, DashHaGlobalConfig, Dpu, DpuPmonStateType, DpuState, RemoteDpu,
+    now_in_millis, parse_entry, update_field_values, BfdSessionTable, ChassisModuleTable, DashBfdProbeState,
+    DashHaDpuStateTable, DashHaGlobalConfig, Dpu, DpuOperStatus, DpuPmonStateType, DpuState, PcieDetachInfo, RemoteDpu,
+    DPU_CONFIG_CACHE,
+};
+use crate::ha_actor_messages::{
+    ActorRegistration, BfdDampeningRelease, DpuActorState, HaSetPlacement, HamgrdShutdown, RegistrationType,
+    StateSequencer,
 };
-use crate::ha_actor_messages::{ActorRegistration, DpuActorState, RegistrationType};
+use crate::HamgrdContext;
 use crate::ServicePath;
 use anyhow::{anyhow, Result};
+use bfd_dampening::{BfdDampening, FLAP_THRESHOLD, FLAP_WINDOW};
 use std::collections::HashSet;
+use std::net::IpAddr;
 use std::sync::Arc;
-use swbus_actor::{state::incoming::Incoming, state::outgoing::Outgoing, Actor, ActorMessage, Context, State};
+use swbus_actor::{
+    state::incoming::Incoming, state::internal::Internal, state::outgoing::Outgoing, Actor, ActorMessage, Context,
+    State,
+};
 use swbus_edge::SwbusEdgeRuntime;
-use swss_common::{KeyOpFieldValues, KeyOperation, SonicDbTable, SubscriberStateTable};
-use swss_common_bridge::consumer::ConsumerBridge;
-use tracing::{debug, error, info, instrument};
+use swss_common::{FieldValues, KeyOpFieldValues, KeyOperation, SonicDbTable};
+use swss_common_bridge::consumer::{ConsumerBridge, KeyFilter};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info, instrument, warn};
 
 use super::spawn_consumer_bridge_for_actor_with_selector;
 
+/// CHASSIS_MODULE_TABLE oper_status of a DPU that is powered on and booted
+const MODULE_ONLINE: &str = "Online";
+
+/// CHASSIS_MODULE_TABLE oper_status of a DPU that is not powered
+const MODULE_POWERED_OFF: [&str; 2] = ["PoweredDown", "Empty"];
+
+/// Whether `key` is the name of the DPU in `slot`, e.g. DPU0 for slot 0, the key of the platform tables.
+fn is_dpu_key(key: &str, slot: u32) -> bool {
+    key.strip_prefix("DPU").and_then(|id| id.parse::<u32>().ok()) == Some(slot)
+}
+
 pub enum DpuData {
     LocalDpu {
         dpu: Dpu,
         is_managed: bool,
-        npu_ipv4: String,
+        npu_ipv4: Option<String>,
         npu_ipv6: Option<String>,
     },
     RemoteDpu(RemoteDpu),
@@ -34,6 +60,15 @@ pub struct DpuActor {
 
     /// Consumer bridges
     bridges: Vec<ConsumerBridge>,
+
+    /// Numbers the DPU state updates sent to registered actors
+    state_seq: StateSequencer,
+
+    /// Ignores the BFD sessions that flap too often
+    bfd_dampening: BfdDampening,
+
+    /// Whether a BfdDampeningRelease is on its way to this actor
+    bfd_release_pending: bool,
 }
 
 impl DpuActor {
@@ -42,6 +77,9 @@ impl DpuActor {
             id: key,
             dpu: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
         };
         Ok(actor)
     }
@@ -64,6 +102,31 @@ impl DpuActor {
         Ok(swss_serde::from_field_values(&dpu_state_kfv.field_values)?)
     }
 
+    /// The CHASSIS_MODULE_TABLE entry of the DPU, if chassisd published one.
+    fn get_module_state(incoming: &Incoming) -> Option<ChassisModuleTable> {
+        let kfv: KeyOpFieldValues = incoming
+            .get(ChassisModuleTable::table_name())
+            .ok()?
+            .deserialize_data()
+            .ok()?;
+        if kfv.operation == KeyOperation::Del {
+            return None;
+        }
+        swss_serde::from_field_values(&kfv.field_values).ok()
+    }
+
+    /// Whether pcied detached the DPU from PCIe. The PCIE_DETACH_INFO entry only exists while it is.
+    fn is_pcie_detached(incoming: &Incoming) -> bool {
+        let Some(kfv) = incoming
+            .get(PcieDetachInfo::table_name())
+            .ok()
+            .and_then(|msg| msg.deserialize_data::<KeyOpFieldValues>().ok())
+        else {
+            return false;
+        };
+        kfv.operation == KeyOperation::Set
+    }
+
     fn get_bfd_probe_state(incoming: &Incoming) -> Result<DashBfdProbeState> {
         let bfd_probe_kfv: KeyOpFieldValues = incoming.get(DashBfdProbeState::table_name())?.deserialize_data()?;
         Ok(swss_serde::from_field_values(&bfd_probe_kfv.field_values)?)
@@ -72,7 +135,7 @@ impl DpuActor {
     fn get_dash_ha_global_config(incoming: &Incoming) -> Result<DashHaGlobalConfig> {
         let ha_global_config_kfv: KeyOpFieldValues =
             incoming.get(DashHaGlobalConfig::table_name())?.deserialize_data()?;
-        Ok(swss_serde::from_field_values(&ha_global_config_kfv.field_values)?)
+        parse_entry(&ha_global_config_kfv.key, &ha_global_config_kfv.field_values)
     }
 
     // DpuActor is spawned in response to swss-common-bridge message for DPU and REMOTE_DPU table
@@ -88,14 +151,14 @@ impl DpuActor {
         tokio::task::spawn(dpu_ac.run());
 
         // dpu actor is spawned for both local dpu and remote dpu
-        let config_db = crate::db_for_table::<Dpu>().await?;
-        let sst = SubscriberStateTable::new_async(config_db, Self::dpu_table_name(), None, None).await?;
+        let sst = crate::tables::subscribe::<Dpu>().await?;
         let addr = crate::common_bridge_sp::<Dpu>(&edge_runtime);
         let base_addr = edge_runtime.get_base_sp();
         bridges.push(ConsumerBridge::spawn(
             edge_runtime.clone(),
             addr,
             sst,
+            KeyFilter::any(),
             move |kfv: &KeyOpFieldValues| {
                 let mut addr = base_addr.clone();
                 addr.resource_type = Self::name().to_owned();
@@ -105,14 +168,14 @@ impl DpuActor {
             |_| true,
         ));
 
-        let config_db = crate::db_for_table::<RemoteDpu>().await?;
-        let sst = SubscriberStateTable::new_async(config_db, Self::remote_dpu_table_name(), None, None).await?;
+        let sst = crate::tables::subscribe::<RemoteDpu>().await?;
         let addr = crate::common_bridge_sp::<RemoteDpu>(&edge_runtime);
         let base_addr = edge_runtime.get_base_sp();
         bridges.push(ConsumerBridge::spawn(
             edge_runtime.clone(),
             addr,
             sst,
+            KeyFilter::any(),
             move |kfv: &KeyOpFieldValues| {
                 let mut addr = base_addr.clone();
                 addr.resource_type = Self::name().to_owned();
@@ -125,20 +188,26 @@ impl DpuActor {
     }
 
     async fn handle_dpu_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
-        let (_internal, incoming, outgoing) = state.get_all();
+        let (internal, incoming, outgoing) = state.get_all();
         let dpu_kfv: KeyOpFieldValues = incoming.get(key)?.deserialize_data()?;
+        DPU_CONFIG_CACHE.invalidate();
         if dpu_kfv.operation == KeyOperation::Del {
             context.stop();
             return Ok(());
         }
 
-        let dpu: Dpu = swss_serde::from_field_values(&dpu_kfv.field_values)?;
-        let npu_ipv4: String = crate::get_npu_ipv4(context.get_edge_runtime())
-            .ok_or_else(|| anyhow!("npu_ipv4 taken from Loopback0 must be available"))?
-            .to_string();
-        let npu_ipv6: Option<String> = crate::get_npu_ipv6(context.get_edge_runtime()).map(|ip| ip.to_string());
+        let Some(dpu) = parse_config::<Dpu>(internal, &dpu_kfv).await? else {
+            return Ok(());
+        };
+        let hamgrd = HamgrdContext::of(context.get_edge_runtime());
+        let npu_ipv4: Option<String> = hamgrd.npu_ipv4().map(|ip| ip.to_string());
+        let npu_ipv6: Option<String> = hamgrd.npu_ipv6().map(|ip| ip.to_string());
+        if npu_ipv4.is_none() && npu_ipv6.is_none() {
+            return Err(anyhow!("npu_ipv4 or npu_ipv6 taken from Loopback0 must be available"));
+        }
         let dpu_id = dpu.dpu_id;
-        let is_managed = dpu.dpu_id == crate::get_slot_id(context.get_edge_runtime());
+        let is_managed = dpu.dpu_id == hamgrd.dpu_id();
+        let maintenance = dpu.maintenance.unwrap_or(false);
 
         let first_time = self.dpu.is_none();
         self.dpu = Some(DpuData::LocalDpu {
@@ -194,22 +263,41 @@ impl DpuActor {
                         Self::name(),
                         Some(&self.id),
                         true, /* key will be DPU_STATE only */
-                        move |kfv: &KeyOpFieldValues| {
-                            let dev = &kfv.key[3..];
-                            if let Ok(slot) = dev.parse::<u32>() {
-                                return slot == dpu_id;
-                            }
-                            false
-                        },
+                        move |kfv: &KeyOpFieldValues| is_dpu_key(&kfv.key, dpu_id),
+                    )
+                    .await?,
+                );
+
+                // CHASSIS_MODULE_TABLE and PCIE_DETACH_INFO of this DPU, for its power and PCIe state.
+                // Keys are CHASSIS_MODULE_TABLE and PCIE_DETACH_INFO
+                self.bridges.push(
+                    spawn_consumer_bridge_for_actor_with_selector::<ChassisModuleTable, _>(
+                        context.get_edge_runtime().clone(),
+                        Self::name(),
+                        Some(&self.id),
+                        true,
+                        move |kfv: &KeyOpFieldValues| is_dpu_key(&kfv.key, dpu_id),
+                    )
+                    .await?,
+                );
+                self.bridges.push(
+                    spawn_consumer_bridge_for_actor_with_selector::<PcieDetachInfo, _>(
+                        context.get_edge_runtime().clone(),
+                        Self::name(),
+                        Some(&self.id),
+                        true,
+                        move |kfv: &KeyOpFieldValues| is_dpu_key(&kfv.key, dpu_id),
                     )
                     .await?,
                 );
             }
+            self.update_maintenance_state(internal, maintenance).await?;
+            self.update_oper_status(internal, incoming).await?;
         } else {
             debug!(
                 "DPU {} is not local. local DPU slot is {}",
                 self.id,
-                crate::get_slot_id(context.get_edge_runtime())
+                HamgrdContext::of(context.get_edge_runtime()).dpu_id()
             );
         }
 
@@ -232,13 +320,14 @@ impl DpuActor {
             }
         };
         let bfd_probe_state = match Self::get_bfd_probe_state(incoming) {
-            Ok(bfd_probe_state) => Some(bfd_probe_state),
+            Ok(bfd_probe_state) => Some(self.bfd_dampening.apply(&bfd_probe_state)),
             Err(e) => {
                 info!("Not able to get BFD probe state. Error: {}", e);
                 None
             }
         };
         let final_state = match (&dpu_state, &bfd_probe_state) {
+            _ if !Self::is_hardware_up(incoming) => false,
             (Some(dpu_state), Some(bfd_probe_state)) => {
                 let pmon_dpu_up = dpu_state.dpu_midplane_link_state == DpuPmonStateType::Up
                     && dpu_state.dpu_control_plane_state == DpuPmonStateType::Up
@@ -254,6 +343,125 @@ impl DpuActor {
         (final_state, dpu_state, bfd_probe_state)
     }
 
+    /// Whether the DPU is powered on and attached to PCIe. A DPU without a CHASSIS_MODULE_TABLE entry is
+    /// taken as powered on, not every platform publishes one.
+    fn is_hardware_up(incoming: &Incoming) -> bool {
+        let powered = Self::get_module_state(incoming)
+            .and_then(|module| module.oper_status)
+            .is_none_or(|status| status == MODULE_ONLINE);
+        powered && !Self::is_pcie_detached(incoming)
+    }
+
+    /// The operational status of the DPU, given whether it is up for HA.
+    fn calculate_oper_status(incoming: &Incoming, up: bool) -> DpuOperStatus {
+        let module_status = Self::get_module_state(incoming).and_then(|module| module.oper_status);
+        if module_status
+            .as_deref()
+            .is_some_and(|status| MODULE_POWERED_OFF.contains(&status))
+        {
+            return DpuOperStatus::PoweredOff;
+        }
+        if up {
+            return DpuOperStatus::Up;
+        }
+        if !Self::is_hardware_up(incoming) {
+            return DpuOperStatus::Down;
+        }
+        match Self::get_dpu_state(incoming) {
+            Ok(pmon)
+                if pmon.dpu_midplane_link_state == DpuPmonStateType::Up
+                    && pmon.dpu_control_plane_state == DpuPmonStateType::Up =>
+            {
+                DpuOperStatus::Degraded
+            }
+            _ => DpuOperStatus::Down,
+        }
+    }
+
+    /// Record the operational status of the DPU in STATE_DB when it changes.
+    async fn update_oper_status(&self, internal: &mut Internal, incoming: &Incoming) -> Result<()> {
+        let (up, _, _) = self.calculate_dpu_state(incoming);
+        let oper_status = Self::calculate_oper_status(incoming, up);
+        let fvs = self.dpu_state_table(internal).await?;
+        let current: DashHaDpuStateTable = swss_serde::from_field_values(fvs).unwrap_or_default();
+        if current.oper_status == Some(oper_status) {
+            return Ok(());
+        }
+        info!("DPU {} is {:?}", self.id, oper_status);
+        let dpu_state = DashHaDpuStateTable {
+            oper_status: Some(oper_status),
+            oper_status_last_updated_time_in_ms: Some(now_in_millis()),
+            ..current
+        };
+        update_field_values(fvs, &dpu_state)
+    }
+
+    /// Notify the registered actors of a change of the DPU state and record its operational status.
+    async fn handle_dpu_state_change(&mut self, state: &mut State) -> Result<()> {
+        let (internal, incoming, outgoing) = state.get_all();
+        self.update_dpu_state(incoming, outgoing, None)?;
+        self.update_oper_status(internal, incoming).await
+    }
+
+    /// The field values of the entry of the DPU in DASH_HA_DPU_STATE_TABLE
+    async fn dpu_state_table<'a>(&self, internal: &'a mut Internal) -> Result<&'a mut FieldValues> {
+        if !internal.has_entry(DashHaDpuStateTable::table_name(), &self.id) {
+            let table = crate::tables::open_table::<DashHaDpuStateTable>().await?;
+            internal
+                .add(DashHaDpuStateTable::table_name(), table, self.id.clone())
+                .await;
+        }
+        Ok(internal.get_mut(DashHaDpuStateTable::table_name()))
+    }
+
+    /// Record in STATE_DB whether the DPU is in planned maintenance. The HA scope actors learn it from the
+    /// DPU state and hand the active role over to the peer.
+    async fn update_maintenance_state(&self, internal: &mut Internal, maintenance: bool) -> Result<()> {
+        let fvs = self.dpu_state_table(internal).await?;
+        let current: Option<DashHaDpuStateTable> = swss_serde::from_field_values(fvs).ok();
+        if current.as_ref().is_some_and(|state| state.maintenance == maintenance) {
+            return Ok(());
+        }
+        match maintenance {
+            true => info!("DPU {} entered maintenance", self.id),
+            false => info!("DPU {} is in service", self.id),
+        }
+        let dpu_state = DashHaDpuStateTable {
+            maintenance,
+            maintenance_last_updated_time_in_ms: now_in_millis(),
+            ..current.unwrap_or_default()
+        };
+        update_field_values(fvs, &dpu_state)
+    }
+
+    /// Record the HA sets served by the DPU, from the placements of the vDPUs it is a member of.
+    async fn handle_ha_set_placement(&self, state: &mut State) -> Result<()> {
+        let (internal, incoming, _outgoing) = state.get_all();
+        let mut ha_set_ids: Vec<String> = incoming
+            .get_by_prefix(HaSetPlacement::msg_key_prefix())
+            .iter()
+            .filter_map(|entry| entry.msg.deserialize_data::<HaSetPlacement>().ok())
+            .filter(|placement| placement.serving)
+            .flat_map(|placement| placement.ha_set_ids)
+            .collect();
+        ha_set_ids.sort();
+        ha_set_ids.dedup();
+
+        let fvs = self.dpu_state_table(internal).await?;
+        let current: DashHaDpuStateTable = swss_serde::from_field_values(fvs).unwrap_or_default();
+        let ha_set_ids = (!ha_set_ids.is_empty()).then_some(ha_set_ids);
+        if current.ha_set_ids == ha_set_ids {
+            return Ok(());
+        }
+        info!(
+            "DPU {} serves HA sets {:?}",
+            self.id,
+            ha_set_ids.as_deref().unwrap_or_default()
+        );
+        let dpu_state = DashHaDpuStateTable { ha_set_ids, ..current };
+        update_field_values(fvs, &dpu_state)
+    }
+
     // target_actor is the actor that needs to be notified about the DPU state. If None, all
     fn update_dpu_state(
         &mut self,
@@ -285,7 +493,10 @@ impl DpuActor {
             DpuData::RemoteDpu(rdpu) => DpuActorState::from_remote_dpu(&self.id, rdpu),
         };
         dpu_state.up = up;
-        let msg = DpuActorState::new_actor_msg(&self.id, &dpu_state)?;
+        dpu_state.dampened_bfd_sessions = self.bfd_dampening.dampened_sessions();
+        let msg = self
+            .state_seq
+            .stamp(DpuActorState::new_actor_msg(&self.id, &dpu_state)?);
 
         if let Some(target_actor_sp) = target_actor {
             outgoing.send(target_actor_sp, msg);
@@ -310,6 +521,50 @@ impl DpuActor {
         Ok(())
     }
 
+    // A BFD session flap is only taken into account if the session doesn't flap too often. Returns whether
+    // the DPU state has to be updated.
+    fn handle_bfd_probe_state(&mut self, incoming: &Incoming, outgoing: &mut Outgoing) -> Result<bool> {
+        let now = Instant::now();
+        if let Ok(bfd_probe_state) = Self::get_bfd_probe_state(incoming) {
+            let changed = self.bfd_dampening.observe(&bfd_probe_state, now);
+            self.schedule_bfd_dampening_release(outgoing, now)?;
+            if !changed {
+                debug!(
+                    "BFD sessions {:?} are dampened. Skip DPU state update",
+                    self.bfd_dampening.dampened_sessions()
+                );
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Returns whether a dampened BFD session was released and the DPU state has to be updated.
+    fn handle_bfd_dampening_release(&mut self, outgoing: &mut Outgoing) -> Result<bool> {
+        let now = Instant::now();
+        self.bfd_release_pending = false;
+        let released = self.bfd_dampening.release(now);
+        self.schedule_bfd_dampening_release(outgoing, now)?;
+        Ok(released)
+    }
+
+    // Wake this actor up when the next dampened BFD session can be released, unless it is already due to.
+    fn schedule_bfd_dampening_release(&mut self, outgoing: &mut Outgoing, now: Instant) -> Result<()> {
+        if self.bfd_release_pending {
+            return Ok(());
+        }
+        let Some(delay) = self.bfd_dampening.next_release(now) else {
+            return Ok(());
+        };
+        outgoing.send_after(
+            outgoing.from_my_sp(Self::name(), &self.id),
+            BfdDampeningRelease::new_actor_msg()?,
+            delay,
+        );
+        self.bfd_release_pending = true;
+        Ok(())
+    }
+
     fn update_bfd_session(
         &self,
         peer_ip: &str,
@@ -321,20 +576,31 @@ impl DpuActor {
             debug!("DPU is not managed by this HA instance. Ignore BFD session creation");
             return Ok(());
         };
+        // the session is local to the PA address of the same family as the peer
+        let local_addr = match peer_ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => dpu.pa_ipv4.map(IpAddr::V4),
+            Ok(IpAddr::V6(_)) => dpu.pa_ipv6.map(IpAddr::V6),
+            Err(_) => return Err(anyhow!("BFD peer {peer_ip} is not an IP address")),
+        };
+        let Some(local_addr) = local_addr else {
+            warn!("DPU has no PA address of the family of BFD peer {peer_ip}. Skip BFD session creation");
+            return Ok(());
+        };
         let bfd_session = BfdSessionTable {
             tx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
             rx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
             multiplier: global_cfg.dpu_bfd_probe_multiplier,
             multihop: true,
-            local_addr: dpu.pa_ipv4.clone(),
+            local_addr: local_addr.to_string(),
             session_type: Some("passive".to_string()),
             shutdown: false,
+            local_discriminator: None,
+            remote_discriminator: None,
         };
 
         let fv = swss_serde::to_field_values(&bfd_session)?;
-        let sep = BfdSessionTable::key_separator();
         let kfv = KeyOpFieldValues {
-            key: format!("default{sep}default{sep}{peer_ip}"),
+            key: BfdSessionTable::key(peer_ip),
             operation: KeyOperation::Set,
             field_values: fv,
         };
@@ -362,16 +628,21 @@ impl DpuActor {
                     return None;
                 };
 
-                Some(remote_dpu.npu_ipv4.clone())
+                remote_dpu.npu_ip().map(|ip| ip.to_string())
             })
             .collect::<HashSet<String>>()
             .into_iter()
             .collect();
 
-        let Some(DpuData::LocalDpu { ref npu_ipv4, .. }) = self.dpu else {
+        let Some(DpuData::LocalDpu {
+            ref npu_ipv4,
+            ref npu_ipv6,
+            ..
+        }) = self.dpu
+        else {
             return Ok(());
         };
-        remote_npus.push(npu_ipv4.clone());
+        remote_npus.extend(npu_ipv4.clone().or_else(|| npu_ipv6.clone()));
         remote_npus.sort();
         for npu in remote_npus {
             self.update_bfd_session(&npu, &global_cfg, outgoing)?;
@@ -379,20 +650,22 @@ impl DpuActor {
         Ok(())
     }
 
-    fn handle_remote_dpu_message_to_remote_dpu(
+    async fn handle_remote_dpu_message_to_remote_dpu(
         &mut self,
         state: &mut State,
         key: &str,
         context: &mut Context,
     ) -> Result<()> {
-        let (_internal, incoming, outgoing) = state.get_all();
+        let (internal, incoming, outgoing) = state.get_all();
         let dpu_kfv: KeyOpFieldValues = incoming.get(key)?.deserialize_data()?;
         if dpu_kfv.operation == KeyOperation::Del {
             context.stop();
             return Ok(());
         }
 
-        let rdpu: RemoteDpu = swss_serde::from_field_values(&dpu_kfv.field_values)?;
+        let Some(rdpu) = parse_config::<RemoteDpu>(internal, &dpu_kfv).await? else {
+            return Ok(());
+        };
         self.dpu = Some(DpuData::RemoteDpu(rdpu));
         // notify dependent actors about the state of this DPU
         self.update_dpu_state(incoming, outgoing, None)?;
@@ -407,19 +680,34 @@ impl DpuActor {
 
         // create bfd session
         let global_cfg = Self::get_dash_ha_global_config(incoming)?;
-        self.update_bfd_session(&remote_dpu.npu_ipv4, &global_cfg, outgoing)?;
+        let Some(npu_ip) = remote_dpu.npu_ip() else {
+            return Err(anyhow!("Remote DPU {} has no NPU address", dpu_kfv.key));
+        };
+        self.update_bfd_session(&npu_ip.to_string(), &global_cfg, outgoing)?;
         Ok(())
     }
 
     async fn handle_remote_dpu_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
         if self.dpu.is_none() || matches!(self.dpu.as_ref().unwrap(), DpuData::RemoteDpu(_)) {
-            self.handle_remote_dpu_message_to_remote_dpu(state, key, context)
+            self.handle_remote_dpu_message_to_remote_dpu(state, key, context).await
         } else {
             self.handle_remote_dpu_message_to_local_dpu(state, key)
         }
     }
 
-    fn handle_dash_ha_global_config(&mut self, state: &mut State) -> Result<()> {
+    async fn handle_dash_ha_global_config(&mut self, state: &mut State) -> Result<()> {
+        let (internal, incoming, _) = state.get_all();
+        let global_cfg_kfv: KeyOpFieldValues = incoming.get(DashHaGlobalConfig::table_name())?.deserialize_data()?;
+        let Some(global_cfg) = parse_config::<DashHaGlobalConfig>(internal, &global_cfg_kfv).await? else {
+            return Ok(());
+        };
+        crate::ha_timers::apply(&global_cfg);
+        self.bfd_dampening.set_limits(
+            global_cfg.dpu_bfd_flap_dampening_threshold.unwrap_or(FLAP_THRESHOLD),
+            global_cfg
+                .dpu_bfd_flap_dampening_window_in_ms
+                .map_or(FLAP_WINDOW, |window| Duration::from_millis(window.into())),
+        );
         self.update_bfd_sessions(state)?;
         Ok(())
     }
@@ -433,6 +721,11 @@ impl DpuActor {
 }
 
 impl Actor for DpuActor {
+    async fn init(&mut self, state: &mut State) -> Result<()> {
+        crate::actors::enable_snapshots(state, Self::name(), &self.id).await;
+        Ok(())
+    }
+
     #[instrument(name="handle_message", level="info", skip_all, fields(actor=format!("dpu/{}", self.id), key=key))]
     async fn handle_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
         let (_internal, incoming, outgoing) = state.get_all();
@@ -454,9 +747,24 @@ impl Actor for DpuActor {
         if !self.is_local_managed() {
             return Ok(());
         } else if key == DashHaGlobalConfig::table_name() {
-            return self.handle_dash_ha_global_config(state);
-        } else if key == DpuState::table_name() || key == DashBfdProbeState::table_name() {
-            return self.update_dpu_state(incoming, outgoing, None);
+            return self.handle_dash_ha_global_config(state).await;
+        } else if key == DpuState::table_name()
+            || key == ChassisModuleTable::table_name()
+            || key == PcieDetachInfo::table_name()
+        {
+            return self.handle_dpu_state_change(state).await;
+        } else if key == DashBfdProbeState::table_name() {
+            if self.handle_bfd_probe_state(incoming, outgoing)? {
+                return self.handle_dpu_state_change(state).await;
+            }
+        } else if BfdDampeningRelease::is_my_msg(key) {
+            if self.handle_bfd_dampening_release(outgoing)? {
+                return self.handle_dpu_state_change(state).await;
+            }
+        } else if HaSetPlacement::is_my_msg(key) {
+            return self.handle_ha_set_placement(state).await;
+        } else if HamgrdShutdown::is_my_msg(key) {
+            // nothing to hand over, the DPU keeps running without hamgrd
         } else {
             error!("Unknown message received: {}", key);
         }
@@ -468,12 +776,15 @@ impl Actor for DpuActor {
 #[cfg(test)]
 mod test {
     use crate::actors::{
-        dpu::DpuActor,
+        dpu::{bfd_dampening::BfdDampening, DpuActor},
         test::{self, *},
     };
-    use crate::db_structs::{BfdSessionTable, DashBfdProbeState, DashHaGlobalConfig, Dpu, DpuState, RemoteDpu};
+    use crate::db_structs::{
+        BfdSessionTable, ChassisModuleTable, DashBfdProbeState, DashHaDpuStateTable, DashHaGlobalConfig, Dpu, DpuState,
+        PcieDetachInfo, RemoteDpu,
+    };
 
-    use crate::ha_actor_messages::DpuActorState;
+    use crate::ha_actor_messages::{DpuActorState, HaSetPlacement, StateSequencer};
     use std::time::Duration;
     use swss_common::SonicDbTable;
     use swss_common_testing::Redis;
@@ -513,9 +824,11 @@ mod test {
             rx_interval: dash_global_cfg.dpu_bfd_probe_interval_in_ms,
             multiplier: dash_global_cfg.dpu_bfd_probe_multiplier,
             multihop: true,
-            local_addr: dpu_actor_state_wo_bfd.pa_ipv4.clone(),
+            local_addr: dpu_actor_state_wo_bfd.pa_ip(),
             session_type: Some("passive".to_string()),
             shutdown: false,
+            local_discriminator: None,
+            remote_discriminator: None,
         };
         let bfd_fvs = serde_json::to_value(to_field_values(&bfd).unwrap()).unwrap();
 
@@ -523,6 +836,9 @@ mod test {
             id: dpu_actor_state_wo_bfd.dpu_name.clone(),
             dpu: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
         };
         let handle = runtime.spawn(dpu_actor, "dpu", "switch0_dpu0");
 
@@ -548,6 +864,7 @@ mod test {
             send! { key: "REMOTE_DPU|switch3_dpu0", data: { "key": "REMOTE_DPU|switch3_dpu0", "operation": "Set", "field_values": serde_json::to_value(&remote_dpu3_fvs).unwrap()}},
             recv! { key: "switch0_dpu0", data: {"key": "default:default:10.0.3.0",  "operation": "Set", "field_values": bfd_fvs},
                     addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            chkgolden! { name: "dpu_actor", tables: [] },
 
             send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values":serde_json::to_value(to_field_values(&dpu_bfd_up_state).unwrap()).unwrap()} },
             recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_up_state, addr: runtime.sp("vdpu", "test-vdpu") },
@@ -572,6 +889,201 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn dpu_actor_dampens_flapping_bfd_session() {
+        let _ = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let dpu_pmon_up_state = make_dpu_pmon_state(true);
+        let dpu_bfd_up_state = make_dpu_bfd_state(vec!["10.0.0.0"], vec![]);
+        let dpu_bfd_down_state = make_dpu_bfd_state(vec![], vec![]);
+        let dpu_actor_state_wo_bfd = make_local_dpu_actor_state(0, 0, true, Some(dpu_pmon_up_state.clone()), None);
+        let mut dash_global_cfg = make_dash_ha_global_config();
+        dash_global_cfg.dpu_bfd_flap_dampening_threshold = Some(1);
+        dash_global_cfg.dpu_bfd_flap_dampening_window_in_ms = Some(1000);
+        let dash_global_cfg_fvs = serde_json::to_value(to_field_values(&dash_global_cfg).unwrap()).unwrap();
+        let bfd_up_fvs = serde_json::to_value(to_field_values(&dpu_bfd_up_state).unwrap()).unwrap();
+        let bfd_down_fvs = serde_json::to_value(to_field_values(&dpu_bfd_down_state).unwrap()).unwrap();
+
+        let mut dpu_actor_up_state = dpu_actor_state_wo_bfd.clone();
+        dpu_actor_up_state.up = true;
+        dpu_actor_up_state.dpu_bfd_state = Some(dpu_bfd_up_state.clone());
+
+        let mut dpu_actor_bfd_down_state = dpu_actor_up_state.clone();
+        dpu_actor_bfd_down_state.up = false;
+        dpu_actor_bfd_down_state.dpu_bfd_state = Some(dpu_bfd_down_state.clone());
+
+        // the session came back up, but it is held down until it stops flapping
+        let mut dpu_actor_dampened_state = dpu_actor_bfd_down_state.clone();
+        dpu_actor_dampened_state.dampened_bfd_sessions = vec!["10.0.0.0".to_string()];
+
+        let dpu_fvs = serde_json::to_value(to_field_values(&to_local_dpu(&dpu_actor_state_wo_bfd)).unwrap()).unwrap();
+        let bfd = BfdSessionTable {
+            tx_interval: dash_global_cfg.dpu_bfd_probe_interval_in_ms,
+            rx_interval: dash_global_cfg.dpu_bfd_probe_interval_in_ms,
+            multiplier: dash_global_cfg.dpu_bfd_probe_multiplier,
+            multihop: true,
+            local_addr: dpu_actor_state_wo_bfd.pa_ip(),
+            session_type: Some("passive".to_string()),
+            shutdown: false,
+            local_discriminator: None,
+            remote_discriminator: None,
+        };
+        let bfd_fvs = serde_json::to_value(to_field_values(&bfd).unwrap()).unwrap();
+
+        let dpu_actor = DpuActor {
+            id: dpu_actor_state_wo_bfd.dpu_name.clone(),
+            dpu: None,
+            bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
+        };
+        let handle = runtime.spawn(dpu_actor, "dpu", "switch0_dpu0");
+
+        #[rustfmt::skip]
+        let commands = [
+            send! { key: DpuState::table_name(), data: { "key": "DPU1", "operation": "Set", "field_values": serde_json::to_value(to_field_values(&dpu_pmon_up_state).unwrap()).unwrap()} },
+            send! { key: Dpu::table_name(), data: { "key": "switch0_dpu0", "operation": "Set", "field_values": dpu_fvs},
+                    addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+            send! { key: DashHaGlobalConfig::table_name(), data: { "key": DashHaGlobalConfig::table_name(), "operation": "Set", "field_values": dash_global_cfg_fvs} },
+            recv! { key: "switch0_dpu0", data: {"key": "default:default:10.0.0.0",  "operation": "Set", "field_values": bfd_fvs},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            send! { key: "DPUStateRegister|vdpu/test-vdpu", data: { "active": true}, addr: runtime.sp("vdpu", "test-vdpu") },
+            recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_state_wo_bfd, addr: runtime.sp("vdpu", "test-vdpu") },
+
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_up_fvs} },
+            recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_up_state, addr: runtime.sp("vdpu", "test-vdpu") },
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_down_fvs} },
+            recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_bfd_down_state, addr: runtime.sp("vdpu", "test-vdpu") },
+
+            // The second flap within the window suppresses the session, later flaps are not sent out
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_up_fvs} },
+            recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_dampened_state, addr: runtime.sp("vdpu", "test-vdpu") },
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_down_fvs} },
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_up_fvs} },
+
+            // Released once the flaps age out of the window
+            recv! { key: "DPUStateUpdate|switch0_dpu0", data: dpu_actor_up_state, addr: runtime.sp("vdpu", "test-vdpu") },
+
+            send! { key: Dpu::table_name(), data: { "key": DpuActor::dpu_table_name(), "operation": "Del", "field_values": dpu_fvs},
+                addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+        ];
+        test::run_commands(&runtime, runtime.sp("dpu", "switch0_dpu0"), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(1), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    const STATE_TIMES: &str = "maintenance_last_updated_time_in_ms,oper_status_last_updated_time_in_ms";
+
+    #[tokio::test]
+    async fn dpu_actor_records_ha_set_placement() {
+        let _ = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let dpu_actor_state = make_local_dpu_actor_state(0, 0, true, None, None);
+        let dpu_fvs = serde_json::to_value(to_field_values(&to_local_dpu(&dpu_actor_state)).unwrap()).unwrap();
+
+        let dpu_actor = DpuActor {
+            id: dpu_actor_state.dpu_name.clone(),
+            dpu: None,
+            bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
+        };
+        let handle = runtime.spawn(dpu_actor, "dpu", "switch0_dpu0");
+
+        #[rustfmt::skip]
+        let commands = [
+            send! { key: Dpu::table_name(), data: { "key": "switch0_dpu0", "operation": "Set", "field_values": dpu_fvs},
+                    addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+
+            // Only the HA sets of the vDPUs the DPU serves are recorded
+            send! { key: HaSetPlacement::msg_key("vdpu0"), data: { "ha_set_ids": ["haset0", "haset1"], "serving": true },
+                    addr: runtime.sp("vdpu", "vdpu0") },
+            send! { key: HaSetPlacement::msg_key("vdpu1"), data: { "ha_set_ids": ["haset2"], "serving": false },
+                    addr: runtime.sp("vdpu", "vdpu1") },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "ha_set_ids": "haset0,haset1", "oper_status": "down" }, exclude: STATE_TIMES },
+
+            send! { key: HaSetPlacement::msg_key("vdpu1"), data: { "ha_set_ids": ["haset2"], "serving": true },
+                    addr: runtime.sp("vdpu", "vdpu1") },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "ha_set_ids": "haset0,haset1,haset2", "oper_status": "down" }, exclude: STATE_TIMES },
+
+            // withdrawn placements
+            send! { key: HaSetPlacement::msg_key("vdpu0"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp("vdpu", "vdpu0") },
+            send! { key: HaSetPlacement::msg_key("vdpu1"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp("vdpu", "vdpu1") },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "down" }, exclude: STATE_TIMES },
+
+            send! { key: Dpu::table_name(), data: { "key": DpuActor::dpu_table_name(), "operation": "Del", "field_values": dpu_fvs},
+                addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+        ];
+        test::run_commands(&runtime, runtime.sp("dpu", "switch0_dpu0"), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(1), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    #[tokio::test]
+    async fn dpu_actor_records_oper_status() {
+        let _ = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let dpu_actor_state = make_local_dpu_actor_state(0, 0, true, None, None);
+        let dpu_fvs = serde_json::to_value(to_field_values(&to_local_dpu(&dpu_actor_state)).unwrap()).unwrap();
+        let pmon_up_fvs = serde_json::to_value(to_field_values(&make_dpu_pmon_state(true)).unwrap()).unwrap();
+        let bfd_up_fvs =
+            serde_json::to_value(to_field_values(&make_dpu_bfd_state(vec!["10.0.0.0"], vec![])).unwrap()).unwrap();
+
+        let dpu_actor = DpuActor {
+            id: dpu_actor_state.dpu_name.clone(),
+            dpu: None,
+            bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
+        };
+        let handle = runtime.spawn(dpu_actor, "dpu", "switch0_dpu0");
+
+        #[rustfmt::skip]
+        let commands = [
+            send! { key: Dpu::table_name(), data: { "key": "switch0_dpu0", "operation": "Set", "field_values": dpu_fvs},
+                    addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "down" }, exclude: STATE_TIMES },
+
+            // the data plane is not confirmed by BFD yet
+            send! { key: DpuState::table_name(), data: { "key": "DPU0", "operation": "Set", "field_values": pmon_up_fvs} },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "degraded" }, exclude: STATE_TIMES },
+            send! { key: DashBfdProbeState::table_name(), data: { "key": "", "operation": "Set", "field_values": bfd_up_fvs} },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "up" }, exclude: STATE_TIMES },
+
+            // detached from PCIe and attached again
+            send! { key: PcieDetachInfo::table_name(), data: { "key": "DPU0", "operation": "Set", "field_values": { "dpu_state": "detaching" }} },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "down" }, exclude: STATE_TIMES },
+            send! { key: PcieDetachInfo::table_name(), data: { "key": "DPU0", "operation": "Del", "field_values": {}} },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "up" }, exclude: STATE_TIMES },
+
+            send! { key: ChassisModuleTable::table_name(), data: { "key": "DPU0", "operation": "Set", "field_values": { "oper_status": "PoweredDown" }} },
+            chkdb! { type: DashHaDpuStateTable, key: "switch0_dpu0",
+                     data: { "maintenance": "false", "oper_status": "powered-off" }, exclude: STATE_TIMES },
+
+            send! { key: Dpu::table_name(), data: { "key": DpuActor::dpu_table_name(), "operation": "Del", "field_values": dpu_fvs},
+                addr: crate::common_bridge_sp::<Dpu>(&runtime.get_swbus_edge()) },
+        ];
+        test::run_commands(&runtime, runtime.sp("dpu", "switch0_dpu0"), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(1), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
     #[tokio::test]
     async fn remote_dpu_actor() {
         let _ = Redis::start_config_db();
@@ -582,6 +1094,9 @@ mod test {
             id: "test-rdpu".into(),
             dpu: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            bfd_dampening: BfdDampening::default(),
+            bfd_release_pending: false,
         };
 
         let handle = runtime.spawn(rdpu_actor, "dpu", "test-rdpu");
@@ -0,0 +1,240 @@
+use crate::db_structs::DashBfdProbeState;
+use std::collections::{BTreeMap, VecDeque};
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Flaps of a BFD session within the window above which its state changes are suppressed
+pub(super) const FLAP_THRESHOLD: u32 = 3;
+
+/// How long a flap counts against a BFD session
+pub(super) const FLAP_WINDOW: Duration = Duration::from_secs(60);
+
+struct Session {
+    v6: bool,
+    // Last state reported by the DPU
+    up: bool,
+    // State the DPU is evaluated with, frozen while the session is suppressed
+    held_up: bool,
+    flaps: VecDeque<Instant>,
+    suppressed: bool,
+}
+
+/// Keeps a flapping DPU BFD session from driving the DPU up and down.
+///
+/// The sessions are the peer NPU IPs in the up session lists of DASH_BFD_PROBE_STATE; a session
+/// flaps when it enters or leaves a list. After more than `threshold` flaps within `window`, the
+/// session is suppressed: the DPU is evaluated with the state the session had when it got
+/// suppressed, until the flaps age out of the window.
+pub(super) struct BfdDampening {
+    threshold: u32,
+    window: Duration,
+    sessions: BTreeMap<String, Session>,
+}
+
+impl Default for BfdDampening {
+    fn default() -> Self {
+        Self::new(FLAP_THRESHOLD, FLAP_WINDOW)
+    }
+}
+
+impl BfdDampening {
+    pub(super) fn new(threshold: u32, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            sessions: BTreeMap::new(),
+        }
+    }
+
+    /// Change the limits, e.g. after DASH_HA_GLOBAL_CONFIG changed. A threshold of 0 disables
+    /// dampening.
+    pub(super) fn set_limits(&mut self, threshold: u32, window: Duration) {
+        self.threshold = threshold;
+        self.window = window;
+    }
+
+    /// Account for a new DASH_BFD_PROBE_STATE. Returns whether the DPU must be re-evaluated, i.e.
+    /// whether a session that isn't suppressed changed or a session got suppressed or released.
+    pub(super) fn observe(&mut self, probe: &DashBfdProbeState, now: Instant) -> bool {
+        let mut changed = false;
+        let up_sessions = probe
+            .v4_bfd_up_sessions
+            .iter()
+            .map(|peer| (peer, false))
+            .chain(probe.v6_bfd_up_sessions.iter().map(|peer| (peer, true)));
+        for (peer, v6) in up_sessions {
+            self.sessions.entry(peer.clone()).or_insert_with(|| {
+                // a new session comes up without flapping
+                changed = true;
+                Session {
+                    v6,
+                    up: true,
+                    held_up: true,
+                    flaps: VecDeque::new(),
+                    suppressed: false,
+                }
+            });
+        }
+
+        for (peer, session) in self.sessions.iter_mut() {
+            let up = if session.v6 {
+                probe.v6_bfd_up_sessions.contains(peer)
+            } else {
+                probe.v4_bfd_up_sessions.contains(peer)
+            };
+            if up == session.up {
+                continue;
+            }
+            session.up = up;
+            session.flaps.push_back(now);
+            if !session.suppressed && self.threshold > 0 && session.flaps.len() > self.threshold as usize {
+                warn!(
+                    "BFD session to {peer} flapped {} times within {:?}, suppressing it as {}",
+                    session.flaps.len(),
+                    self.window,
+                    if session.held_up { "up" } else { "down" }
+                );
+                session.suppressed = true;
+                changed = true;
+            }
+            if !session.suppressed {
+                session.held_up = up;
+                changed = true;
+            }
+        }
+        self.release(now) || changed
+    }
+
+    /// Forget the flaps that aged out of the window and release the sessions that no longer flap
+    /// too often. Returns whether a session was released.
+    pub(super) fn release(&mut self, now: Instant) -> bool {
+        let mut released = false;
+        for (peer, session) in self.sessions.iter_mut() {
+            while session
+                .flaps
+                .front()
+                .is_some_and(|flap| now.duration_since(*flap) >= self.window)
+            {
+                session.flaps.pop_front();
+            }
+            if session.suppressed && (self.threshold == 0 || session.flaps.len() <= self.threshold as usize) {
+                info!(
+                    "BFD session to {peer} is stable again, releasing it as {}",
+                    if session.up { "up" } else { "down" }
+                );
+                session.suppressed = false;
+                session.held_up = session.up;
+                released = true;
+            }
+        }
+        released
+    }
+
+    /// How long until the next suppressed session can be released, if any is suppressed.
+    pub(super) fn next_release(&self, now: Instant) -> Option<Duration> {
+        self.sessions
+            .values()
+            .filter(|session| session.suppressed)
+            .filter_map(|session| {
+                // released once enough flaps aged out to get back to the threshold
+                let excess = session.flaps.len().saturating_sub(self.threshold as usize);
+                session.flaps.get(excess.saturating_sub(1))
+            })
+            .map(|flap| (*flap + self.window).saturating_duration_since(now))
+            .min()
+    }
+
+    /// The BFD probe state the DPU is evaluated with: `probe` with the suppressed sessions in the
+    /// state they were suppressed in.
+    pub(super) fn apply(&self, probe: &DashBfdProbeState) -> DashBfdProbeState {
+        let mut dampened = probe.clone();
+        for (peer, session) in self.sessions.iter().filter(|(_, session)| session.suppressed) {
+            let up_sessions = if session.v6 {
+                &mut dampened.v6_bfd_up_sessions
+            } else {
+                &mut dampened.v4_bfd_up_sessions
+            };
+            if !session.held_up {
+                up_sessions.retain(|up_peer| up_peer != peer);
+            } else if !up_sessions.contains(peer) {
+                up_sessions.push(peer.clone());
+            }
+        }
+        dampened
+    }
+
+    /// Peer IPs of the suppressed sessions.
+    pub(super) fn dampened_sessions(&self) -> Vec<String> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| session.suppressed)
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::test::make_dpu_bfd_state;
+
+    #[test]
+    fn flapping_session_is_suppressed_until_stable() {
+        let mut dampening = BfdDampening::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        let both_up = make_dpu_bfd_state(vec!["10.0.0.0", "10.0.1.0"], vec![]);
+        let peer_down = make_dpu_bfd_state(vec!["10.0.0.0"], vec![]);
+
+        assert!(dampening.observe(&both_up, start));
+        // Up to the threshold, every flap is re-evaluated
+        assert!(dampening.observe(&peer_down, start + Duration::from_secs(1)));
+        assert!(dampening.observe(&both_up, start + Duration::from_secs(2)));
+        assert_eq!(dampening.apply(&both_up), both_up);
+
+        // The third flap suppresses the session as up
+        assert!(dampening.observe(&peer_down, start + Duration::from_secs(3)));
+        assert_eq!(dampening.dampened_sessions(), vec!["10.0.1.0".to_string()]);
+        assert_eq!(
+            dampening.apply(&peer_down).v4_bfd_up_sessions,
+            both_up.v4_bfd_up_sessions
+        );
+        assert!(!dampening.observe(&both_up, start + Duration::from_secs(4)));
+        assert!(!dampening.observe(&peer_down, start + Duration::from_secs(5)));
+
+        // Released once the flaps age out, as the last reported state
+        assert_eq!(
+            dampening.next_release(start + Duration::from_secs(5)),
+            Some(Duration::from_secs(8))
+        );
+        assert!(!dampening.release(start + Duration::from_secs(12)));
+        assert!(dampening.release(start + Duration::from_secs(13)));
+        assert!(dampening.dampened_sessions().is_empty());
+        assert_eq!(dampening.next_release(start + Duration::from_secs(13)), None);
+        assert_eq!(dampening.apply(&peer_down), peer_down);
+    }
+
+    #[test]
+    fn sessions_are_dampened_independently() {
+        let mut dampening = BfdDampening::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+        let v4_up = make_dpu_bfd_state(vec!["10.0.0.0"], vec!["10::"]);
+        let v4_down = make_dpu_bfd_state(vec![], vec!["10::"]);
+        let v6_down = make_dpu_bfd_state(vec![], vec![]);
+
+        dampening.observe(&v4_up, now);
+        dampening.observe(&v4_down, now);
+        dampening.observe(&v4_up, now);
+        assert_eq!(dampening.dampened_sessions(), vec!["10.0.0.0".to_string()]);
+
+        // The stable v6 session still drives the DPU, the flapping v4 one is held down
+        assert!(dampening.observe(&v6_down, now));
+        let dampened = dampening.apply(&v6_down);
+        assert!(dampened.v4_bfd_up_sessions.is_empty());
+        assert!(dampened.v6_bfd_up_sessions.is_empty());
+
+        // Disabling dampening releases everything
+        dampening.set_limits(0, Duration::from_secs(10));
+        assert!(dampening.release(now));
+        assert!(dampening.dampened_sessions().is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+//! Exhaustive exploration of event interleavings against actors.
+//!
+//! An exploration is a set of threads, each an ordered list of events, e.g. the roles a DPU goes
+//! through in a switchover, a BFD transition, a config change. Every interleaving of the threads
+//! that keeps the order within each thread is run against a fresh set of actors started by a
+//! [`Model`], and the invariants of the model are checked after every event, once the actor the
+//! event is sent to has handled it. Events only feed the actors; the invariants are checked on what
+//! the actors output, e.g. with [`Outputs`].
+//!
+//! The number of interleavings is the multinomial of the thread lengths. An exploration runs at most
+//! [`DEFAULT_MAX_RUNS`] of them, spread evenly over all of them, unless `HAMGRD_EXPLORE_ALL` is set in
+//! the environment (`make test-explore`).
+use crate::actors::test::{run_commands, Command};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swbus_actor::{ActorMessage, ActorRuntime};
+use swbus_edge::simple_client::{IncomingMessage, MessageBody, OutgoingMessage, SimpleSwbusEdgeClient};
+use swbus_edge::swbus_proto::swbus::{ManagementRequestType, ServicePath, SwbusErrorCode};
+use tokio::task::JoinHandle;
+
+/// Interleavings run by an exploration unless `HAMGRD_EXPLORE_ALL` is set.
+pub const DEFAULT_MAX_RUNS: usize = 16;
+
+pub struct Event {
+    name: String,
+    aut: ServicePath,
+    commands: Vec<Command>,
+}
+
+impl Event {
+    /// Event `name`, which runs `commands` against actor `aut`
+    pub fn new(name: &str, aut: ServicePath, commands: impl IntoIterator<Item = Command>) -> Self {
+        Event {
+            name: name.to_string(),
+            aut,
+            commands: commands.into_iter().collect(),
+        }
+    }
+}
+
+/// The system under exploration.
+pub trait Model {
+    /// Start the actors in their initial state, on a fresh runtime.
+    async fn start(&mut self) -> ActorRuntime;
+
+    /// Check the invariants of the model. Called after every event and once after start.
+    async fn check(&mut self, runtime: &ActorRuntime) -> Result<(), String>;
+
+    /// Stop the actors, so the next interleaving starts from scratch.
+    async fn stop(&mut self, runtime: ActorRuntime);
+}
+
+#[derive(Default)]
+pub struct Exploration {
+    threads: Vec<Vec<Event>>,
+}
+
+pub struct Violation {
+    /// Names of the events run, up to the one after which the invariant broke
+    pub trace: Vec<String>,
+    pub error: String,
+}
+
+pub struct ExplorationReport {
+    pub runs: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl Exploration {
+    /// Add a thread of events, which run in the given order in every interleaving.
+    pub fn thread(mut self, events: impl IntoIterator<Item = Event>) -> Self {
+        self.threads.push(events.into_iter().collect());
+        self
+    }
+
+    pub async fn run(&self, model: &mut impl Model) -> ExplorationReport {
+        let lens: Vec<usize> = self.threads.iter().map(Vec::len).collect();
+        let mut interleavings = interleavings(&lens);
+        if std::env::var_os("HAMGRD_EXPLORE_ALL").is_none() {
+            interleavings = spread(interleavings, DEFAULT_MAX_RUNS);
+        }
+        let mut report = ExplorationReport {
+            runs: interleavings.len(),
+            violations: Vec::new(),
+        };
+
+        for interleaving in &interleavings {
+            let runtime = model.start().await;
+            let mut trace = Vec::new();
+            let mut result = model.check(&runtime).await;
+            let mut next = vec![0; self.threads.len()];
+
+            for &thread in interleaving {
+                if result.is_err() {
+                    break;
+                }
+                let event = &self.threads[thread][next[thread]];
+                next[thread] += 1;
+                trace.push(event.name.clone());
+                run_commands(&runtime, event.aut.clone(), &event.commands).await;
+                settle(&runtime, &event.aut).await;
+                result = model.check(&runtime).await;
+            }
+
+            if let Err(error) = result {
+                report.violations.push(Violation { trace, error });
+            }
+            model.stop(runtime).await;
+        }
+        report
+    }
+}
+
+impl ExplorationReport {
+    pub fn assert_no_violation(&self) {
+        if self.violations.is_empty() {
+            return;
+        }
+        let violations: Vec<String> = self
+            .violations
+            .iter()
+            .map(|v| format!("{} after {}", v.error, v.trace.join(" -> ")))
+            .collect();
+        panic!(
+            "{} of {} interleavings violated the invariants:\n{}",
+            self.violations.len(),
+            self.runs,
+            violations.join("\n")
+        );
+    }
+}
+
+/// All interleavings of threads of the given lengths, each as the sequence of threads to take the
+/// next event from.
+fn interleavings(lens: &[usize]) -> Vec<Vec<usize>> {
+    fn expand(remaining: &mut [usize], prefix: &mut Vec<usize>, all: &mut Vec<Vec<usize>>) {
+        if remaining.iter().all(|&n| n == 0) {
+            all.push(prefix.clone());
+            return;
+        }
+        for thread in 0..remaining.len() {
+            if remaining[thread] == 0 {
+                continue;
+            }
+            remaining[thread] -= 1;
+            prefix.push(thread);
+            expand(remaining, prefix, all);
+            prefix.pop();
+            remaining[thread] += 1;
+        }
+    }
+
+    let mut all = Vec::new();
+    expand(&mut lens.to_vec(), &mut Vec::new(), &mut all);
+    all
+}
+
+/// `max` (at least 2) of the interleavings, evenly spaced so that the first and the last are kept.
+fn spread(interleavings: Vec<Vec<usize>>, max: usize) -> Vec<Vec<usize>> {
+    let total = interleavings.len();
+    if total <= max {
+        return interleavings;
+    }
+    let mut picked = (0..max).map(|i| i * (total - 1) / (max - 1)).peekable();
+    interleavings
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| picked.next_if_eq(index).is_some())
+        .map(|(_, interleaving)| interleaving)
+        .collect()
+}
+
+/// The last message each actor sent to an address, e.g. the bridge of a table the actors program.
+/// It answers every request like the bridge would, so the actors run as they do against it.
+pub struct Outputs {
+    addr: ServicePath,
+    flush: SimpleSwbusEdgeClient,
+    last: Arc<Mutex<HashMap<ServicePath, ActorMessage>>>,
+    task: JoinHandle<()>,
+}
+
+impl Outputs {
+    /// Record what the actors send to `addr`. Nothing else may listen on `addr` meanwhile.
+    pub fn record(runtime: &ActorRuntime, addr: ServicePath) -> Self {
+        let client = SimpleSwbusEdgeClient::new(runtime.get_swbus_edge(), addr.clone(), true, false);
+        let flush_sp = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/explore-outputs").unwrap();
+        let flush = SimpleSwbusEdgeClient::new(runtime.get_swbus_edge(), flush_sp.clone(), true, false);
+        let last = Arc::new(Mutex::new(HashMap::new()));
+
+        let recorded = last.clone();
+        let task = tokio::spawn(async move {
+            while let Some(msg) = client.recv().await {
+                let MessageBody::Request { payload } = msg.body else {
+                    continue;
+                };
+                if msg.source != flush_sp {
+                    let am = ActorMessage::deserialize(&payload).unwrap();
+                    recorded.lock().unwrap().insert(msg.source.clone(), am);
+                }
+                let ack = OutgoingMessage {
+                    destination: msg.source,
+                    body: MessageBody::Response {
+                        request_id: msg.id,
+                        error_code: SwbusErrorCode::Ok,
+                        error_message: "".to_string(),
+                        response_body: None,
+                    },
+                };
+                if client.send(ack).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Outputs {
+            addr,
+            flush,
+            last,
+            task,
+        }
+    }
+
+    /// The data of the last message `actor` sent, once every message sent before the call is recorded.
+    pub async fn last(&self, actor: &ServicePath) -> Option<serde_json::Value> {
+        // The messages are received in order, so once the flush request is answered, the messages
+        // sent before it, e.g. by an actor that has settled, are recorded.
+        let msg = OutgoingMessage {
+            destination: self.addr.clone(),
+            body: MessageBody::Request {
+                payload: ActorMessage::new("flush", &()).unwrap().serialize().into(),
+            },
+        };
+        let sent_id = self.flush.send(msg).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match self.flush.recv().await {
+                    Some(IncomingMessage {
+                        body: MessageBody::Response { request_id, .. },
+                        ..
+                    }) if request_id == sent_id => break,
+                    Some(_) => continue,
+                    None => panic!("swbus edge is closed"),
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("timed out flushing the outputs to {}", self.addr.to_longest_path()));
+
+        self.last.lock().unwrap().get(actor).map(|am| am.data.clone())
+    }
+}
+
+impl Drop for Outputs {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Wait until `aut` has handled every message sent to it so far. The actor handles messages in
+/// order, so once it answers a state query, the messages before it have been handled and their
+/// changes committed.
+pub async fn settle(runtime: &ActorRuntime, aut: &ServicePath) {
+    let sp = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/explore").unwrap();
+    let client = SimpleSwbusEdgeClient::new(runtime.get_swbus_edge(), sp, true, false);
+    let msg = OutgoingMessage {
+        destination: aut.clone(),
+        body: MessageBody::ManagementRequest {
+            request: ManagementRequestType::HamgrdGetActorState,
+            args: HashMap::new(),
+        },
+    };
+    let sent_id = client.send(msg).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match client.recv().await {
+                Some(IncomingMessage {
+                    body: MessageBody::Response { request_id, .. },
+                    ..
+                }) if request_id == sent_id => break,
+                Some(_) => continue,
+                None => panic!("swbus edge is closed"),
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("timed out waiting for {} to settle", aut.to_longest_path()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{interleavings, spread};
+
+    #[test]
+    fn interleavings_keep_the_order_within_threads() {
+        assert_eq!(
+            interleavings(&[2, 1]),
+            vec![vec![0, 0, 1], vec![0, 1, 0], vec![1, 0, 0]]
+        );
+        assert_eq!(interleavings(&[3, 1, 1, 1]).len(), 120);
+        assert_eq!(interleavings(&[0, 2]), vec![vec![1, 1]]);
+    }
+
+    #[test]
+    fn spread_keeps_the_first_and_the_last_interleaving() {
+        let all = interleavings(&[3, 1, 1, 1]);
+        let picked = spread(all.clone(), 4);
+        assert_eq!(picked.len(), 4);
+        assert_eq!(picked.first(), all.first());
+        assert_eq!(picked.last(), all.last());
+        assert_eq!(spread(all.clone(), 200), all);
+    }
+}
@@ -0,0 +1,147 @@
+//! Declarative CONFIG_DB and APPL_DB content for tests.
+//!
+//! A [`DbFixture`] collects entries built from the db_structs types and writes each one to the db
+//! and table of its type, so tests don't hand-write field maps and keys that drift from the schema.
+//! The shortcuts follow the naming of the actor test kit: DPU `dpu` of switch `switch` is
+//! `switch{switch}_dpu{dpu}` and is the only main DPU of vDPU `vdpu{switch}-{dpu}`, and switches
+//! 2n and 2n+1 form the HA sets `haset{n}-{dpu}`.
+use crate::actors::test::{make_dpu_object, make_dpu_scope_ha_set_config, make_remote_dpu_object};
+use crate::db_structs::*;
+use crate::TableSource;
+use serde::Serialize;
+use swss_common::{DbConnector, FieldValues, SonicDbTable, Table};
+
+struct Entry {
+    db: &'static str,
+    table: &'static str,
+    key: String,
+    fvs: FieldValues,
+}
+
+#[derive(Default)]
+pub struct DbFixture {
+    entries: Vec<Entry>,
+}
+
+pub fn dpu_name(switch: u16, dpu: u32) -> String {
+    format!("switch{switch}_dpu{dpu}")
+}
+
+pub fn vdpu_id(switch: u16, dpu: u32) -> String {
+    format!("vdpu{switch}-{dpu}")
+}
+
+/// Key of the DPU scope of `vdpu_id` in HA set `ha_set_id`, which is also the id of its actor.
+pub fn ha_scope_key(vdpu_id: &str, ha_set_id: &str) -> String {
+    format!("{vdpu_id}{}{ha_set_id}", DashHaScopeConfigTable::source_key_separator())
+}
+
+pub fn make_ha_scope_config(desired_ha_state: &str) -> DashHaScopeConfigTable {
+    DashHaScopeConfigTable {
+        version: 1,
+        disable: false,
+        desired_ha_state: desired_ha_state.to_string(),
+        approved_pending_operation_ids: None,
+        planned_switchover_id: None,
+        planned_switchover_timeout_in_ms: None,
+        preferred_winner: None,
+        flow_sync_timeout_in_ms: None,
+    }
+}
+
+impl DbFixture {
+    /// Add `obj` as entry `key` of its table. The other methods are shortcuts for this one.
+    pub fn entry<T: SonicDbTable + Serialize>(mut self, key: &str, obj: &T) -> Self {
+        assert!(!T::is_dpu(), "{} is in the DPU db", T::table_name());
+        self.entries.push(Entry {
+            db: T::db_name(),
+            table: T::table_name(),
+            key: key.to_string(),
+            fvs: swss_serde::to_field_values(obj).unwrap(),
+        });
+        self
+    }
+
+    /// A DPU local to this switch, in the DPU table
+    pub fn dpu(self, switch: u16, dpu: u32) -> Self {
+        self.entry(&dpu_name(switch, dpu), &make_dpu_object(switch, dpu))
+    }
+
+    /// A DPU of another switch, in the REMOTE_DPU table
+    pub fn remote_dpu(self, switch: u16, dpu: u32) -> Self {
+        self.entry(&dpu_name(switch, dpu), &make_remote_dpu_object(switch, dpu))
+    }
+
+    pub fn vdpu(self, switch: u16, dpu: u32) -> Self {
+        let vdpu = VDpu {
+            main_dpu_ids: vec![dpu_name(switch, dpu)],
+            backup_dpu_ids: None,
+            composition: None,
+            min_members_up: None,
+        };
+        self.entry(&vdpu_id(switch, dpu), &vdpu)
+    }
+
+    /// The DPU-scope HA set of DPU `dpu` of `switch` and its peer switch
+    pub fn ha_set(self, switch: u16, dpu: u16) -> Self {
+        let (ha_set_id, ha_set) = make_dpu_scope_ha_set_config(switch, dpu);
+        self.entry(&ha_set_id, &ha_set)
+    }
+
+    /// The DPU scope of DPU `dpu` of `switch` in its HA set
+    pub fn ha_scope(self, switch: u16, dpu: u16, desired_ha_state: &str) -> Self {
+        let (ha_set_id, _) = make_dpu_scope_ha_set_config(switch, dpu);
+        let key = ha_scope_key(&vdpu_id(switch, dpu as u32), &ha_set_id);
+        self.entry(&key, &make_ha_scope_config(desired_ha_state))
+    }
+
+    /// Everything the DPU scope of DPU `dpu` of `switch` needs: both DPUs of the HA set, their vDPUs,
+    /// the HA set and the scope.
+    pub fn dpu_scope_ha_pair(self, switch: u16, dpu: u16, desired_ha_state: &str) -> Self {
+        let peer = switch ^ 1;
+        self.dpu(switch, dpu as u32)
+            .remote_dpu(peer, dpu as u32)
+            .vdpu(switch, dpu as u32)
+            .vdpu(peer, dpu as u32)
+            .ha_set(switch, dpu)
+            .ha_scope(switch, dpu, desired_ha_state)
+    }
+
+    /// Write the entries in the order they were added.
+    pub fn write(self) {
+        for entry in self.entries {
+            let db = DbConnector::new_named(entry.db, false, 0).unwrap();
+            let table = Table::new(db, entry.table).unwrap();
+            table.set(&entry.key, entry.fvs).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common_testing::Redis;
+
+    fn read<T: SonicDbTable + serde::de::DeserializeOwned>(key: &str) -> T {
+        let db = DbConnector::new_named(T::db_name(), false, 0).unwrap();
+        let table = Table::new(db, T::table_name()).unwrap();
+        swss_serde::from_table(&table, key).unwrap()
+    }
+
+    #[test]
+    fn fixture_entries_parse_back() {
+        let _redis = Redis::start_config_db();
+        DbFixture::default().dpu_scope_ha_pair(0, 0, "active").write();
+
+        let dpu: Dpu = read("switch0_dpu0");
+        assert_eq!(dpu, make_dpu_object(0, 0));
+        let remote_dpu: RemoteDpu = read("switch1_dpu0");
+        assert_eq!(remote_dpu.dpu_id, 0);
+        let vdpu: VDpu = read("vdpu1-0");
+        assert_eq!(vdpu.main_dpu_ids, vec!["switch1_dpu0"]);
+        let ha_set: DashHaSetConfigTable = read("haset0-0");
+        assert_eq!(ha_set.vdpu_ids, vec!["vdpu0-0", "vdpu1-0"]);
+        let ha_scope: DashHaScopeConfigTable = read("vdpu0-0:haset0-0");
+        assert_eq!(ha_scope, make_ha_scope_config("active"));
+    }
+}
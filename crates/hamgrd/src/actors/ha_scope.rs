@@ -1,17 +1,30 @@
-use crate::actors::{spawn_consumer_bridge_for_actor, DbBasedActor};
+use crate::actors::{spawn_consumer_bridge_for_actor_with_selector, DbBasedActor};
+use crate::alarms::{update_alarm, HaAlarmType};
+use crate::audit::{self, AuditEntry, AuditEvent};
 use crate::db_structs::*;
-use crate::ha_actor_messages::{ActorRegistration, HaSetActorState, RegistrationType, VDpuActorState};
+use crate::ha_actor_messages::{
+    ActorRegistration, FlowSyncTimeout, HaSetActorState, HamgrdShutdown, PeerHaRole, PeerPlannedExit,
+    PlannedSwitchover, PlannedSwitchoverRequest, PlannedSwitchoverTimeout, RegistrationType, SwitchoverPhase,
+    SwitchoverStep, VDpuActorState,
+};
+use crate::ha_events::{self, HaEvent};
+use crate::ha_report;
+use crate::ha_timers;
+use crate::TableSource;
 use crate::{HaSetActor, VDpuActor};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
 use swbus_actor::{
     state::{incoming::Incoming, internal::Internal, outgoing::Outgoing},
     Actor, ActorMessage, Context, State,
 };
-use swss_common::Table;
+use swbus_edge::swbus_proto::swbus::ServicePath;
 use swss_common::{KeyOpFieldValues, KeyOperation, SonicDbTable};
-use swss_common_bridge::consumer::ConsumerBridge;
-use tracing::{debug, error, info, instrument};
+use swss_common_bridge::consumer::{ConsumerBridge, KeyFilter};
+use tracing::{debug, error, info, instrument, warn};
 use uuid::Uuid;
 
 pub struct HaScopeActor {
@@ -22,11 +35,117 @@ pub struct HaScopeActor {
     bridges: Vec<ConsumerBridge>,
     // we need to keep track the previous dpu_ha_scope_state to detect state change
     dpu_ha_scope_state: Option<DpuDashHaScopeState>,
+    // ENI-scope only. Placement of the ENI, which tells the HA set the ENI lives on
+    eni_placement: Option<DashEniPlacementTable>,
+    // Dropped once the HA set turns out to be DPU scope, which has no ENI placement
+    eni_placement_bridge: Option<ConsumerBridge>,
+    // DPU scope of a switch-owned HA set only. The role hamgrd drives from BFD and the peer reachability
+    switch_driven_role: Option<&'static str>,
+    // The last planned switchover, kept after it ends until desired_ha_state changes
+    switchover: Option<Switchover>,
+    // The hamgrd of the peer was unreachable in the last HA set state
+    peer_unreachable: bool,
+    // The role of the peer DPU, reported by the HA scope actor of the peer once a partition heals
+    peer_ha_role: Option<String>,
+    // Lost a split brain, the DPU is held standby while the peer is active or until desired_ha_state changes
+    split_brain_demoted: bool,
+    // The DPU is in planned maintenance, it hands the active role over to the peer and is held standby
+    maintenance: bool,
+    // The time an approved activate_role of a standby DPU started to wait for the flow sync to complete
+    activate_role_held_since: Option<i64>,
+    // The flow sync didn't complete in time, the held activate_role is no longer gated on it
+    flow_sync_timed_out: bool,
+    // The events of the current callback, emitted once it succeeds
+    pending_events: Vec<HaEvent>,
+}
+
+/// The fields of the actor that follow the state it commits, saved before each callback and restored if
+/// the callback fails, since its state changes and messages are dropped then.
+struct SavedFields {
+    switchover: Option<Switchover>,
+    peer_unreachable: bool,
+    peer_ha_role: Option<String>,
+    split_brain_demoted: bool,
+    maintenance: bool,
+    switch_driven_role: Option<&'static str>,
+    eni_placement: Option<DashEniPlacementTable>,
+    activate_role_held_since: Option<i64>,
+    flow_sync_timed_out: bool,
+}
+
+/// A planned switchover, which hands the active role of the HA scope from the DPU of the initiator to
+/// the peer DPU. The hamgrd of the active DPU initiates it and the hamgrd of the peer follows:
+/// 1. draining: the initiator moves its DPU to switching_to_standby and tells the peer to `Drain`, the
+///    peer moves its DPU to switching_to_active and reports `Drained` once the DPU confirms it.
+/// 2. flipping: once its DPU confirms switching_to_standby too, the initiator moves it to standby and
+///    tells the peer to `Flip`, the peer moves its DPU to active and reports `Done` once confirmed.
+///
+/// A phase that doesn't end in time on either side rolls both DPUs back to desired_ha_state. The steps
+/// carry the deadline of the phase on the initiator, so a step the peer doesn't ack in time rolls the
+/// switchover back too, and a step arriving late is dropped instead of moving a switchover that has
+/// given up on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Switchover {
+    id: String,
+    initiator: bool,
+    phase: SwitchoverPhase,
+    // HA scope actor of the peer DPU
+    peer_sp: ServicePath,
+    // Initiator only. The peer reported the end of the current phase.
+    peer_confirmed: bool,
+    // Peer only. The end of the current phase is reported to the initiator.
+    reported: bool,
+    // Deadline of the current phase on the initiator in unix milliseconds, sent with the steps
+    deadline_in_ms: Option<u64>,
+    start_time_in_ms: i64,
+}
+
+impl Switchover {
+    /// The role the local DPU takes in the current phase, None to follow desired_ha_state.
+    fn ha_role(&self) -> Option<&'static str> {
+        match (self.phase, self.initiator) {
+            (SwitchoverPhase::Failed, _) => None,
+            (SwitchoverPhase::Draining, true) => Some("switching_to_standby"),
+            (SwitchoverPhase::Draining, false) => Some("switching_to_active"),
+            (_, true) => Some("standby"),
+            (_, false) => Some("active"),
+        }
+    }
+
+    fn in_progress(&self) -> bool {
+        matches!(self.phase, SwitchoverPhase::Draining | SwitchoverPhase::Flipping)
+    }
+}
+
+/// What the actor keeps in its snapshots, so that a restarted actor picks up where it was.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HaScopeSnapshot {
+    // The last DPU HA scope state, so that the pending operations and role changes the DPU already
+    // reported are not taken for new ones
+    dpu_ha_scope_state: Option<DpuDashHaScopeState>,
+    // The DPU stays held standby after a lost split brain or in maintenance
+    split_brain_demoted: bool,
+    maintenance: bool,
+    // The switchover, which keeps its phase and rolls back in time if it is in progress
+    switchover: Option<Switchover>,
+}
+
+impl HaScopeSnapshot {
+    fn from_value(snapshot: serde_json::Value) -> serde_json::Result<Self> {
+        // Snapshots of older versions only hold the DPU HA scope state
+        if snapshot.get("split_brain_demoted").is_none() {
+            return Ok(HaScopeSnapshot {
+                dpu_ha_scope_state: serde_json::from_value(snapshot)?,
+                ..Default::default()
+            });
+        }
+        serde_json::from_value(snapshot)
+    }
 }
 
 impl DbBasedActor for HaScopeActor {
     fn new(key: String) -> Result<Self> {
-        if let Some((vdpu_id, ha_scope_id)) = key.split_once(DashHaScopeConfigTable::key_separator()) {
+        if let Some((vdpu_id, ha_scope_id)) = key.split_once(DashHaScopeConfigTable::source_key_separator()) {
             Ok(HaScopeActor {
                 id: key.to_string(),
                 vdpu_id: vdpu_id.to_string(),
@@ -34,6 +153,17 @@ impl DbBasedActor for HaScopeActor {
                 dash_ha_scope_config: None,
                 bridges: Vec::new(),
                 dpu_ha_scope_state: None,
+                eni_placement: None,
+                eni_placement_bridge: None,
+                switch_driven_role: None,
+                switchover: None,
+                peer_unreachable: false,
+                peer_ha_role: None,
+                split_brain_demoted: false,
+                maintenance: false,
+                activate_role_held_since: None,
+                flow_sync_timed_out: false,
+                pending_events: Vec::new(),
             })
         } else {
             Err(anyhow::anyhow!("Invalid key format for HA scope actor: {}", key))
@@ -44,6 +174,16 @@ impl DbBasedActor for HaScopeActor {
         DashHaScopeConfigTable::table_name()
     }
 
+    /// Only the HA scopes of the vDPU of the slot, if it is known.
+    fn key_filter() -> KeyFilter {
+        match crate::slot::current().vdpu_id() {
+            Some(vdpu_id) => {
+                KeyFilter::glob(&[format!("{vdpu_id}{}*", DashHaScopeConfigTable::source_key_separator())])
+            }
+            None => KeyFilter::any(),
+        }
+    }
+
     fn name() -> &'static str {
         "ha-scope"
     }
@@ -60,8 +200,17 @@ impl HaScopeActor {
         msg.deserialize_data().ok()
     }
 
+    /// The HA set of this scope. For an ENI scope, it is the HA set the ENI is placed on. For a DPU
+    /// scope, ha_scope_id is the HA set id.
+    fn ha_set_id(&self) -> &str {
+        match self.eni_placement {
+            Some(ref placement) => &placement.ha_set_id,
+            None => &self.ha_scope_id,
+        }
+    }
+
     fn get_haset(&self, incoming: &Incoming) -> Option<HaSetActorState> {
-        let key = HaSetActorState::msg_key(&self.ha_scope_id);
+        let key = HaSetActorState::msg_key(self.ha_set_id());
         let Ok(msg) = incoming.get(&key) else {
             return None;
         };
@@ -153,6 +302,144 @@ impl HaScopeActor {
         };
         vdpu.dpu.is_managed
     }
+
+    /// The HA scope actor of the peer vDPU, in the hamgrd managing it.
+    fn get_peer_sp(&self, incoming: &Incoming, outgoing: &Outgoing) -> Option<ServicePath> {
+        let peer = self.get_haset(incoming)?.peer?;
+        let peer_id = format!(
+            "{}{}{}",
+            peer.vdpu_id,
+            DashHaScopeConfigTable::source_key_separator(),
+            self.ha_scope_id
+        );
+        Some(peer.actor_sp(&outgoing.from_my_sp(Self::name(), &self.id), Self::name(), &peer_id))
+    }
+
+    /// The role the DPU is asked to take: desired_ha_state, unless a planned switchover moved it, the DPU
+    /// is in maintenance, a split brain demoted it or hamgrd drives the role of the DPU scope.
+    fn get_target_ha_role<'a>(&'a self, dash_ha_scope_config: &'a DashHaScopeConfigTable) -> &'a str {
+        self.switchover
+            .as_ref()
+            .and_then(|switchover| switchover.ha_role())
+            .or((self.maintenance || self.split_brain_demoted).then_some("standby"))
+            .or(self.switch_driven_role)
+            .unwrap_or(&dash_ha_scope_config.desired_ha_state)
+    }
+
+    /// The role hamgrd drives the DPU to, if this is the DPU scope of an HA set owned by the switch.
+    fn get_switch_driven_role(&self, incoming: &Incoming) -> Option<&'static str> {
+        let dash_ha_scope_config = self.dash_ha_scope_config.as_ref()?;
+        let haset = self.get_haset(incoming)?;
+        if !is_dpu_scope(&haset.ha_set) || haset.ha_set.owner.as_deref() != Some("switch") {
+            return None;
+        }
+        let bfd_state = self.get_vdpu(incoming)?.dpu.dpu_bfd_state.unwrap_or_default();
+        let bfd_up = !bfd_state.v4_bfd_up_sessions.is_empty() || !bfd_state.v6_bfd_up_sessions.is_empty();
+        let peer_lost = !haset.up || haset.peer_unreachable;
+        dpu_scope_ha_role(&dash_ha_scope_config.desired_ha_state, bfd_up, peer_lost)
+    }
+
+    /// Whether both the local and the peer DPU hold an active role.
+    fn in_split_brain(&self) -> bool {
+        is_active_role(self.get_dpu_ha_role()) && self.peer_ha_role.as_deref().is_some_and(is_active_role)
+    }
+
+    fn get_dpu_ha_role(&self) -> &str {
+        self.dpu_ha_scope_state.as_ref().map_or("none", |s| s.ha_role.as_str())
+    }
+
+    /// The time to wait for each phase of a planned switchover, from the HA scope config or the HA timers.
+    fn get_switchover_timeout(&self) -> Duration {
+        self.dash_ha_scope_config
+            .as_ref()
+            .and_then(|config| config.planned_switchover_timeout_in_ms)
+            .map_or(ha_timers::current().planned_switchover_timeout, |ms| {
+                Duration::from_millis(ms as u64)
+            })
+    }
+
+    /// The time an approved activate_role waits for the flow sync, from the HA scope config or the HA timers.
+    fn get_flow_sync_timeout(&self) -> Duration {
+        self.dash_ha_scope_config
+            .as_ref()
+            .and_then(|config| config.flow_sync_timeout_in_ms)
+            .map_or(ha_timers::current().flow_sync_timeout, |ms| {
+                Duration::from_millis(ms as u64)
+            })
+    }
+
+    /// The flow sync progress of the HA scope, aggregated over the ENIs the DPU reports in
+    /// DASH_HA_FLOW_SYNC_STATE.
+    fn get_flow_sync_progress(&self, incoming: &Incoming) -> Option<FlowSyncProgress> {
+        let prefix = format!("{}|", DpuDashHaFlowSyncState::table_name());
+        let enis: Vec<DpuDashHaFlowSyncState> = incoming
+            .get_by_prefix(&prefix)
+            .into_iter()
+            .filter_map(|entry| entry.msg.deserialize_data::<KeyOpFieldValues>().ok())
+            .filter(|kfv| kfv.operation == KeyOperation::Set)
+            .filter_map(|kfv| match swss_serde::from_field_values(&kfv.field_values) {
+                Ok(eni) => Some(eni),
+                Err(e) => {
+                    error!("Failed to deserialize DASH_HA_FLOW_SYNC_STATE {}: {}", kfv.key, e);
+                    None
+                }
+            })
+            .collect();
+        aggregate_flow_sync(&enis)
+    }
+
+    /// Whether an approved activate_role is held: a DPU asked to be standby isn't ready to back the active
+    /// DPU up until it has the flows of the active DPU, unless the flow sync timed out.
+    fn flow_sync_gates_activation(&self, incoming: &Incoming) -> bool {
+        let Some(dash_ha_scope_config) = self.dash_ha_scope_config.as_ref() else {
+            return false;
+        };
+        if self.flow_sync_timed_out || self.get_target_ha_role(dash_ha_scope_config) != "standby" {
+            return false;
+        }
+        self.get_flow_sync_progress(incoming)
+            .is_none_or(|progress| progress.state != "completed")
+    }
+
+    /// The types of the approved operations that are still pending.
+    fn get_approved_operations(&self, internal: &Internal) -> Result<Vec<String>> {
+        let Some(approved_ops) = self
+            .dash_ha_scope_config
+            .as_ref()
+            .and_then(|config| config.approved_pending_operation_ids.as_ref())
+            .filter(|ids| !ids.is_empty())
+        else {
+            return Ok(Vec::new());
+        };
+        let pending_operations = self.get_pending_operations(internal, None)?;
+        // an approved operation that is not pending anymore has been removed from pending list
+        Ok(approved_ops
+            .iter()
+            .filter_map(|op_id| pending_operations.get(op_id).cloned())
+            .collect())
+    }
+
+    fn scope_name(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.vdpu_id,
+            NpuDashHaScopeState::source_key_separator(),
+            self.ha_scope_id
+        )
+    }
+
+    /// Record a transition of this HA scope in the audit log.
+    fn audit(&self, event: AuditEvent, old_state: &str, new_state: &str, trigger: &str) {
+        let actor = format!("{}/{}", Self::name(), self.id);
+        audit::record(AuditEntry::new(
+            event,
+            &actor,
+            &self.scope_name(),
+            old_state,
+            new_state,
+            trigger,
+        ));
+    }
 }
 
 // Implements internal action functions for HaScopeActor
@@ -173,7 +460,17 @@ impl HaScopeActor {
         };
 
         let msg = ActorRegistration::new_actor_msg(active, RegistrationType::HaSetState, &self.id)?;
-        outgoing.send(outgoing.from_my_sp(HaSetActor::name(), &self.ha_scope_id), msg);
+        outgoing.send(outgoing.from_my_sp(HaSetActor::name(), self.ha_set_id()), msg);
+        Ok(())
+    }
+
+    /// Report the role of the DPU to the HA scope actor of the peer.
+    fn send_ha_role_to_peer(&self, state: &mut State) -> Result<()> {
+        let (_internal, incoming, outgoing) = state.get_all();
+        let Some(peer_sp) = self.get_peer_sp(incoming, outgoing) else {
+            return Ok(());
+        };
+        outgoing.send(peer_sp, PeerHaRole::new_actor_msg(&self.id, self.get_dpu_ha_role())?);
         Ok(())
     }
 
@@ -181,43 +478,37 @@ impl HaScopeActor {
         let Some(dash_ha_scope_config) = self.dash_ha_scope_config.as_ref() else {
             return Ok(());
         };
-        let (internal, _incoming, outgoing) = state.get_all();
+        let (internal, incoming, outgoing) = state.get_all();
 
         let mut activate_role_requested = false;
         let mut flow_reconcile_requested = false;
-        if let Some(approved_ops) = dash_ha_scope_config.approved_pending_operation_ids.as_ref() {
-            if !approved_ops.is_empty() {
-                let pending_operations = self.get_pending_operations(internal, None)?;
-                for op_id in approved_ops {
-                    let Some(op) = pending_operations.get(op_id) else {
-                        // has been removed from pending list
-                        continue;
-                    };
-                    match op.as_str() {
-                        "switchover" => {
-                            // todo: this is for switch driven ha
-                        }
-                        "activate_role" => {
-                            activate_role_requested = true;
-                        }
-                        "flow_reconcile" => {
-                            flow_reconcile_requested = true;
-                        }
-                        "brainsplit_recover" => {
-                            // todo: what's the action here?
-                        }
-                        _ => {
-                            error!("Unknown operation type {}", op);
-                        }
-                    }
+        for op in self.get_approved_operations(internal)? {
+            match op.as_str() {
+                "switchover" => {
+                    // todo: this is for switch driven ha
+                }
+                "activate_role" => {
+                    activate_role_requested = true;
+                }
+                "flow_reconcile" => {
+                    flow_reconcile_requested = true;
+                }
+                "brainsplit_recover" => {
+                    // todo: what's the action here?
+                }
+                _ => {
+                    error!("Unknown operation type {}", op);
                 }
             }
         }
+        // an activate_role held for the flow sync is requested once the flow sync is done
+        let activate_role_requested = (activate_role_requested || self.activate_role_held_since.is_some())
+            && !self.flow_sync_gates_activation(incoming);
 
         let dash_ha_scope = DashHaScopeTable {
             version: dash_ha_scope_config.version,
             disable: dash_ha_scope_config.disable,
-            ha_role: dash_ha_scope_config.desired_ha_state.clone(), /*todo, how switching_to_active is derived. Is it relevant to dpu driven mode */
+            ha_role: self.get_target_ha_role(dash_ha_scope_config).to_string(),
             flow_reconcile_requested,
             activate_role_requested,
         };
@@ -260,7 +551,7 @@ impl HaScopeActor {
         let Some(haset) = self.get_haset(incoming) else {
             debug!(
                 "HA-SET {} has not been received. Skip DASH_HA_SCOPE_STATE update",
-                &self.ha_scope_id
+                self.ha_set_id()
             );
             return Ok(());
         };
@@ -295,9 +586,11 @@ impl HaScopeActor {
         npu_ha_scope_state.local_vdpu_up_bfd_sessions_v6 = bfd_state.v6_bfd_up_sessions.clone();
         // Local vDPU BFD sessions v6 last updated time in milliseconds.
         npu_ha_scope_state.local_vdpu_up_bfd_sessions_v6_update_time_in_ms = bfd_state.v6_bfd_up_sessions_timestamp;
+        // The list of peer IPs (NPU IP) of the BFD sessions that flap too often to be taken into account.
+        npu_ha_scope_state.local_vdpu_dampened_bfd_sessions =
+            (!vdpu.dpu.dampened_bfd_sessions.is_empty()).then_some(vdpu.dpu.dampened_bfd_sessions);
 
-        let fvs = swss_serde::to_field_values(&npu_ha_scope_state)?;
-        internal.get_mut(NpuDashHaScopeState::table_name()).clone_from(&fvs);
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
 
         Ok(())
     }
@@ -343,8 +636,7 @@ impl HaScopeActor {
         npu_ha_scope_state.pending_operation_types = Some(pending_operation_types);
         npu_ha_scope_state.pending_operation_list_last_updated_time_in_ms = Some(now_in_millis());
 
-        let fvs = swss_serde::to_field_values(&npu_ha_scope_state)?;
-        internal.get_mut(NpuDashHaScopeState::table_name()).clone_from(&fvs);
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
 
         Ok(())
     }
@@ -375,7 +667,7 @@ impl HaScopeActor {
         npu_ha_scope_state.local_ha_state_last_updated_reason = Some("dpu initiated".to_string());
 
         // The target HA state in ASIC. This is the state that hamgrd generates and asking DPU to move to.
-        npu_ha_scope_state.local_target_asic_ha_state = Some(dash_ha_scope_config.desired_ha_state.clone());
+        npu_ha_scope_state.local_target_asic_ha_state = Some(self.get_target_ha_role(dash_ha_scope_config).to_string());
         // The HA state that ASIC acked.
         npu_ha_scope_state.local_acked_asic_ha_state = Some(dpu_ha_scope_state.ha_role.clone());
 
@@ -383,11 +675,493 @@ impl HaScopeActor {
         npu_ha_scope_state.local_target_term = Some(dpu_ha_scope_state.ha_term.clone());
         npu_ha_scope_state.local_acked_term = Some(dpu_ha_scope_state.ha_term);
 
-        let fvs = swss_serde::to_field_values(&npu_ha_scope_state)?;
-        internal.get_mut(NpuDashHaScopeState::table_name()).clone_from(&fvs);
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
+
+        self.update_ha_scope_summary(state)
+    }
+
+    /// Update the role of the DPU and the state of the peer in DASH_HA_SCOPE_SUMMARY_TABLE
+    fn update_ha_scope_summary(&self, state: &mut State) -> Result<()> {
+        let (internal, incoming, _outgoing) = state.get_all();
+        let dpu_ha_scope_state = self.get_dpu_ha_scope_state(incoming);
+        let peer_state = match self.get_haset(incoming) {
+            None => ha_report::PEER_STATE_UNKNOWN,
+            Some(haset) if haset.peer_unreachable => ha_report::PEER_STATE_UNREACHABLE,
+            Some(_) => ha_report::PEER_STATE_REACHABLE,
+        };
+        ha_report::update_summary(internal, &self.scope_name(), |summary| {
+            if let Some(dpu_ha_scope_state) = dpu_ha_scope_state {
+                summary.ha_role = dpu_ha_scope_state.ha_role;
+                summary.ha_role_start_time_in_ms = dpu_ha_scope_state.ha_role_start_time;
+            }
+            summary.peer_state = peer_state.to_string();
+            summary.peer_ha_role = self.peer_ha_role.clone().unwrap_or_default();
+        })
+    }
+
+    /// Update eni_steering_targets in NPU DASH_HA_SCOPE_STATE of an ENI scope
+    fn update_npu_ha_scope_state_steering(&self, state: &mut State) -> Result<()> {
+        let Some(ref placement) = self.eni_placement else {
+            return Ok(());
+        };
+        let (internal, incoming, _outgoing) = state.get_all();
+        let Some(mut npu_ha_scope_state) = self.get_npu_ha_scope_state(internal) else {
+            return Ok(());
+        };
+        let Some(haset) = self.get_haset(incoming) else {
+            debug!(
+                "HA-SET {} has not been received. Skip ENI steering update",
+                self.ha_set_id()
+            );
+            return Ok(());
+        };
+        let local_ha_role = self.dpu_ha_scope_state.as_ref().map(|s| s.ha_role.as_str());
+        let targets = eni_steering_targets(placement, &haset.ha_set, local_ha_role);
+        if npu_ha_scope_state.eni_steering_targets.as_ref() == Some(&targets) {
+            return Ok(());
+        }
+        info!("ENI {} is steered to {:?}", self.ha_scope_id, targets);
+        npu_ha_scope_state.eni_steering_targets = Some(targets);
+
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
+        Ok(())
+    }
+
+    /// Update the switchover fields in NPU DASH_HA_SCOPE_STATE with the progress of the planned switchover
+    fn update_npu_ha_scope_state_switchover(&self, state: &mut State) -> Result<()> {
+        let Some(ref switchover) = self.switchover else {
+            return Ok(());
+        };
+        let internal = state.internal();
+        let Some(mut npu_ha_scope_state) = self.get_npu_ha_scope_state(internal) else {
+            return Ok(());
+        };
+
+        npu_ha_scope_state.switchover_id = Some(switchover.id.clone());
+        npu_ha_scope_state.switchover_start_time_in_ms = Some(switchover.start_time_in_ms);
+        if switchover.in_progress() {
+            npu_ha_scope_state.switchover_state = Some("in_progress".to_string());
+            npu_ha_scope_state.switchover_phase = Some(switchover.phase.as_str().to_string());
+            npu_ha_scope_state.switchover_end_time_in_ms = None;
+        } else {
+            npu_ha_scope_state.switchover_state = Some(switchover.phase.as_str().to_string());
+            npu_ha_scope_state.switchover_phase = None;
+            npu_ha_scope_state.switchover_end_time_in_ms = Some(now_in_millis());
+        }
+
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
+        Ok(())
+    }
+
+    /// Update the flow sync fields in NPU DASH_HA_SCOPE_STATE with the progress reported by the DPU
+    fn update_npu_ha_scope_state_flow_sync(&self, state: &mut State) -> Result<()> {
+        let (internal, incoming, _outgoing) = state.get_all();
+        let Some(mut npu_ha_scope_state) = self.get_npu_ha_scope_state(internal) else {
+            return Ok(());
+        };
+        let progress = self.get_flow_sync_progress(incoming);
+
+        npu_ha_scope_state.flow_sync_session_id = progress.as_ref().map(|p| p.session_id.clone());
+        npu_ha_scope_state.flow_sync_session_state = progress.as_ref().map(|p| p.state.to_string());
+        npu_ha_scope_state.flow_sync_session_start_time_in_ms = progress.as_ref().map(|p| p.start_time_in_ms);
+        npu_ha_scope_state.flow_sync_progress_percent = progress.as_ref().map(|p| p.percent);
+        npu_ha_scope_state.flow_sync_enis = progress.as_ref().map(|p| p.enis);
+        npu_ha_scope_state.flow_sync_enis_completed = progress.as_ref().map(|p| p.enis_completed);
+
+        update_field_values(internal.get_mut(NpuDashHaScopeState::table_name()), &npu_ha_scope_state)?;
+        Ok(())
+    }
+
+    /// Hold a newly approved activate_role until the flow sync completes or times out.
+    fn hold_activate_role(&mut self, state: &mut State) -> Result<()> {
+        if self.activate_role_held_since.is_some() {
+            return Ok(());
+        }
+        let approved = self.get_approved_operations(state.internal())?;
+        if !approved.iter().any(|op| op == "activate_role") {
+            return Ok(());
+        }
+        self.flow_sync_timed_out = false;
+        if !self.flow_sync_gates_activation(state.incoming()) {
+            return Ok(());
+        }
+
+        let held_since_in_ms = now_in_millis();
+        let timeout = self.get_flow_sync_timeout();
+        info!("Holding activate_role until the flow sync completes, for up to {timeout:?}");
+        let outgoing = state.outgoing();
+        outgoing.send_after(
+            outgoing.from_my_sp(Self::name(), &self.id),
+            FlowSyncTimeout::new_actor_msg(held_since_in_ms)?,
+            timeout,
+        );
+        self.activate_role_held_since = Some(held_since_in_ms);
+        Ok(())
+    }
+
+    /// Request the held activate_role to the DPU once the flow sync no longer gates it.
+    fn release_activate_role(&mut self, state: &mut State, trigger: &str) -> Result<()> {
+        if self.activate_role_held_since.is_none() || self.flow_sync_gates_activation(state.incoming()) {
+            return Ok(());
+        }
+        info!("Requesting the held activate_role: {trigger}");
+        self.update_dpu_ha_scope_table(state)?;
+        self.activate_role_held_since = None;
+        Ok(())
+    }
+
+    /// Recompute the role hamgrd drives a switch-owned DPU scope to. Returns whether it changed.
+    fn refresh_switch_driven_role(&mut self, incoming: &Incoming) -> bool {
+        let role = self.get_switch_driven_role(incoming);
+        if role == self.switch_driven_role {
+            return false;
+        }
+        let desired_ha_state = self
+            .dash_ha_scope_config
+            .as_ref()
+            .map_or("", |config| config.desired_ha_state.as_str());
+        let trigger = match role {
+            Some("standby") => "no BFD session to the DPU is up",
+            Some(_) => "the peer is lost",
+            None => "BFD sessions and the peer are up",
+        };
+        info!("DPU scope role {:?} -> {:?}: {trigger}", self.switch_driven_role, role);
+        self.audit(
+            AuditEvent::RoleChange,
+            self.switch_driven_role.unwrap_or(desired_ha_state),
+            role.unwrap_or(desired_ha_state),
+            trigger,
+        );
+        self.switch_driven_role = role;
+        true
+    }
+
+    /// Follow the maintenance flag of the DPU: once it is set, hand the active role over to the peer with
+    /// a planned switchover. The DPU is held standby until the flag is cleared. Returns whether it changed.
+    fn refresh_maintenance(&mut self, state: &mut State) -> Result<bool> {
+        let maintenance = self.get_vdpu(state.incoming()).is_some_and(|vdpu| vdpu.dpu.maintenance);
+        if maintenance == self.maintenance {
+            return Ok(false);
+        }
+        self.audit(
+            AuditEvent::OperatorAction,
+            &format!("maintenance={}", self.maintenance),
+            &format!("maintenance={maintenance}"),
+            "DPU maintenance flag",
+        );
+        self.maintenance = maintenance;
+        if !maintenance {
+            info!("DPU is out of maintenance, it may take the active role again");
+            return Ok(true);
+        }
+        if self.get_dpu_ha_role() == "active" {
+            // one switchover per term the DPU is active in
+            let term = self.dpu_ha_scope_state.as_ref().map_or("", |s| s.ha_term.as_str());
+            let switchover_id = format!("maintenance-{}-{term}", self.vdpu_id);
+            if let Err(e) = self.start_planned_switchover(state, &switchover_id) {
+                warn!("DPU is in maintenance, but its active role can't be handed over to the peer: {e:#}");
+            }
+        } else {
+            info!("DPU is in maintenance, it is held standby");
+        }
+        Ok(true)
+    }
+
+    /// Move the planned switchover to `phase`: program the role of the phase to the DPU, record the
+    /// progress in NPU DASH_HA_SCOPE_STATE and, if the switchover is still in progress, arm the timeout
+    /// of the phase.
+    fn enter_switchover_phase(&mut self, state: &mut State, phase: SwitchoverPhase, trigger: &str) -> Result<()> {
+        let timeout = self.get_switchover_timeout();
+        let Some(ref mut switchover) = self.switchover else {
+            return Ok(());
+        };
+        info!("Planned switchover {} is {}", switchover.id, phase.as_str());
+        // a new switchover enters its first phase from none
+        let old_phase = match switchover.phase == phase {
+            true => "",
+            false => switchover.phase.as_str(),
+        };
+        let trigger = format!("planned switchover {}: {trigger}", switchover.id);
+        let cause = format!("planned switchover {}", switchover.id);
+        switchover.phase = phase;
+        switchover.peer_confirmed = false;
+        switchover.reported = false;
+        if switchover.in_progress() && switchover.initiator {
+            switchover.deadline_in_ms = Some((now_in_millis() as u64).saturating_add(timeout.as_millis() as u64));
+        }
+        if switchover.in_progress() {
+            arm_switchover_timeout(state, &self.id, switchover, timeout)?;
+        }
+
+        // the role of a completed switchover is the one of the flipping phase, the DPU already holds it
+        self.audit(AuditEvent::Switchover, old_phase, phase.as_str(), &trigger);
+
+        if phase != SwitchoverPhase::Completed {
+            self.update_dpu_ha_scope_table(state)?;
+        } else {
+            ha_report::record_switchover(state.internal(), &self.scope_name(), &cause)?;
+        }
+        self.update_npu_ha_scope_state_ha_state(state)?;
+        self.update_npu_ha_scope_state_switchover(state)
+    }
+
+    /// Start a planned switchover that hands the active role of the DPU over to the peer DPU.
+    fn start_planned_switchover(&mut self, state: &mut State, switchover_id: &str) -> Result<()> {
+        if let Some(ref switchover) = self.switchover {
+            if switchover.id == switchover_id {
+                // requested again, e.g. by both the config and a management message
+                return Ok(());
+            }
+            if switchover.in_progress() {
+                bail!("planned switchover {} is in progress", switchover.id);
+            }
+        }
+        if self.get_dpu_ha_role() != "active" {
+            bail!(
+                "DPU is {}, only an active DPU can be switched over",
+                self.get_dpu_ha_role()
+            );
+        }
+        let (_internal, incoming, outgoing) = state.get_all();
+        let Some(peer_sp) = self.get_peer_sp(incoming, outgoing) else {
+            bail!("peer of HA set {} is unknown", self.ha_set_id());
+        };
+        if self.get_haset(incoming).is_some_and(|haset| haset.peer_unreachable) {
+            bail!("the hamgrd of the peer of HA set {} is unreachable", self.ha_set_id());
+        }
+
+        info!(
+            "Starting planned switchover {switchover_id} to {}",
+            peer_sp.to_longest_path()
+        );
+        self.switchover = Some(Switchover {
+            id: switchover_id.to_string(),
+            initiator: true,
+            phase: SwitchoverPhase::Draining,
+            peer_sp,
+            peer_confirmed: false,
+            reported: false,
+            deadline_in_ms: None,
+            start_time_in_ms: now_in_millis(),
+        });
+        self.enter_switchover_phase(state, SwitchoverPhase::Draining, "requested")?;
+        self.send_switchover_step(state, SwitchoverStep::Drain)
+    }
+
+    /// Take part in the planned switchover `switchover_id` started by the hamgrd of the peer DPU at
+    /// `peer_sp`, taking over the active role. `deadline_in_ms` is the deadline of the draining phase on
+    /// the peer.
+    fn follow_planned_switchover(
+        &mut self,
+        state: &mut State,
+        switchover_id: &str,
+        peer_sp: ServicePath,
+        deadline_in_ms: Option<u64>,
+    ) -> Result<()> {
+        let busy = self.switchover.as_ref().filter(|switchover| switchover.in_progress());
+        if let Some(switchover) = busy {
+            warn!(
+                "Rejecting planned switchover {switchover_id}, planned switchover {} is in progress",
+                switchover.id
+            );
+        } else if self.get_dpu_ha_role() != "standby" {
+            warn!(
+                "Rejecting planned switchover {switchover_id}, DPU is {}",
+                self.get_dpu_ha_role()
+            );
+        } else if self.maintenance {
+            warn!("Rejecting planned switchover {switchover_id}, DPU is in maintenance");
+        } else {
+            info!(
+                "Following planned switchover {switchover_id} from {}",
+                peer_sp.to_longest_path()
+            );
+            self.switchover = Some(Switchover {
+                id: switchover_id.to_string(),
+                initiator: false,
+                phase: SwitchoverPhase::Draining,
+                peer_sp,
+                peer_confirmed: false,
+                reported: false,
+                deadline_in_ms,
+                start_time_in_ms: now_in_millis(),
+            });
+            return self.enter_switchover_phase(state, SwitchoverPhase::Draining, "drain requested by the peer");
+        }
+
+        let msg = PlannedSwitchover::new_actor_msg(&self.id, switchover_id, SwitchoverStep::Abort)?;
+        state.outgoing().send(peer_sp, msg);
+        Ok(())
+    }
+
+    /// Move the planned switchover on once the DPU confirmed its role in the current phase and, on the
+    /// initiator, the peer reported the end of the phase.
+    fn advance_planned_switchover(&mut self, state: &mut State) -> Result<()> {
+        let Some(switchover) = self.switchover.clone().filter(|switchover| switchover.in_progress()) else {
+            return Ok(());
+        };
+        if Some(self.get_dpu_ha_role()) != switchover.ha_role() {
+            return Ok(());
+        }
+
+        let (step, next_phase) = match (switchover.initiator, switchover.phase) {
+            (true, _) if !switchover.peer_confirmed => return Ok(()),
+            (true, SwitchoverPhase::Draining) => (Some(SwitchoverStep::Flip), SwitchoverPhase::Flipping),
+            (true, _) => (None, SwitchoverPhase::Completed),
+            (false, _) if switchover.reported => return Ok(()),
+            (false, SwitchoverPhase::Draining) => (Some(SwitchoverStep::Drained), SwitchoverPhase::Draining),
+            (false, _) => (Some(SwitchoverStep::Done), SwitchoverPhase::Completed),
+        };
+
+        if next_phase == switchover.phase {
+            // the peer waits for the initiator to move on
+            self.switchover.as_mut().unwrap().reported = true;
+        } else {
+            let trigger = format!("{} is done", switchover.phase.as_str());
+            self.enter_switchover_phase(state, next_phase, &trigger)?;
+        }
+        // sent once the phase is entered, with its deadline
+        match step {
+            Some(step) => self.send_switchover_step(state, step),
+            None => Ok(()),
+        }
+    }
 
+    /// Send `step` of the planned switchover to the peer, with the deadline of the current phase.
+    fn send_switchover_step(&self, state: &mut State, step: SwitchoverStep) -> Result<()> {
+        let Some(ref switchover) = self.switchover else {
+            return Ok(());
+        };
+        let mut msg = PlannedSwitchover::new_actor_msg(&self.id, &switchover.id, step)?;
+        if let Some(deadline_in_ms) = switchover.deadline_in_ms {
+            msg = msg.with_deadline(deadline_in_ms);
+        }
+        state.outgoing().send(switchover.peer_sp.clone(), msg);
         Ok(())
     }
+
+    /// Roll the planned switchover back to the roles before it, telling the peer unless it is the peer
+    /// that asked for it.
+    fn roll_back_planned_switchover(&mut self, state: &mut State, reason: &str, notify_peer: bool) -> Result<()> {
+        let Some(switchover) = self.switchover.clone().filter(|switchover| switchover.in_progress()) else {
+            return Ok(());
+        };
+        let reason = format!("planned switchover {} is rolled back: {reason}", switchover.id);
+        warn!("{}: {reason}", self.scope_name());
+        if notify_peer {
+            let msg = PlannedSwitchover::new_actor_msg(&self.id, &switchover.id, SwitchoverStep::Abort)?;
+            state.outgoing().send(switchover.peer_sp, msg);
+        }
+        self.pending_events.push(HaEvent::new(
+            ha_events::HaEventType::HaSwitchoverRolledBack,
+            ha_events::HaEventSeverity::Minor,
+            &self.scope_name(),
+            &reason,
+        ));
+        self.enter_switchover_phase(state, SwitchoverPhase::Failed, &reason)
+    }
+}
+
+/// Compute the next hops of ENI traffic in switch-driven HA, most preferred first.
+///
+/// The next hops are the DPUs of the HA set, ordered by address so that `pinned_next_hop_index` refers
+/// to the same DPU on both switches. A pinned next hop always comes first. Otherwise the local DPU
+/// comes first if it holds the active (or standalone) role for the ENI, and the peer DPU if not.
+/// Whether a DPU in `ha_role` takes traffic as the active side.
+fn is_active_role(ha_role: &str) -> bool {
+    matches!(ha_role, "active" | "standalone")
+}
+
+/// Whether the local DPU stays active when both DPUs of an HA scope are active: the preferred winner if
+/// one is configured, otherwise the DPU with the lower DPU id, then the lower vDPU id. DPUs are given as
+/// (DPU id, vDPU id).
+fn wins_split_brain(preferred_winner: Option<&str>, local: (u32, &str), peer: (u32, &str)) -> bool {
+    match preferred_winner.filter(|winner| !winner.is_empty()) {
+        Some(winner) if winner == local.1 => true,
+        Some(winner) if winner == peer.1 => false,
+        _ => local < peer,
+    }
+}
+
+/// Whether `ha_set` is DPU scope, the scope of HA sets that don't set one.
+fn is_dpu_scope(ha_set: &DashHaSetTable) -> bool {
+    matches!(ha_set.scope.as_deref(), None | Some("dpu"))
+}
+
+/// The role of a switch-owned DPU scope that is desired to be `desired_ha_state`, None to keep it.
+/// A DPU that no NPU reaches over BFD goes standby so that the traffic moves to the peer, and a DPU
+/// whose peer is lost goes standalone. Nothing changes when both are down.
+fn dpu_scope_ha_role(desired_ha_state: &str, bfd_up: bool, peer_lost: bool) -> Option<&'static str> {
+    if !matches!(desired_ha_state, "active" | "standby") {
+        return None;
+    }
+    match (bfd_up, peer_lost) {
+        (false, false) => Some("standby"),
+        (true, true) => Some("standalone"),
+        _ => None,
+    }
+}
+
+/// Flow sync progress of an HA scope, aggregated over the ENIs in its latest flow sync session.
+#[derive(Debug, Clone, PartialEq)]
+struct FlowSyncProgress {
+    session_id: String,
+    state: &'static str,
+    // average sync percentage of the ENIs
+    percent: u32,
+    start_time_in_ms: i64,
+    enis: u32,
+    enis_completed: u32,
+}
+
+/// Aggregate the flow sync state of the ENIs of an HA scope. Only the ENIs of the latest session count:
+/// the session fails if any of its ENIs fails and completes once all of them complete.
+fn aggregate_flow_sync(enis: &[DpuDashHaFlowSyncState]) -> Option<FlowSyncProgress> {
+    let latest = enis.iter().max_by_key(|eni| eni.start_time_in_ms)?;
+    let session: Vec<_> = enis.iter().filter(|eni| eni.session_id == latest.session_id).collect();
+    let enis_completed = session.iter().filter(|eni| eni.state == "completed").count();
+    let state = if session.iter().any(|eni| eni.state == "failed") {
+        "failed"
+    } else if enis_completed == session.len() {
+        "completed"
+    } else {
+        "in_progress"
+    };
+    let percent_sum: u32 = session
+        .iter()
+        .map(|eni| match eni.state.as_str() {
+            "completed" => 100,
+            _ => eni.sync_percent.min(100),
+        })
+        .sum();
+    Some(FlowSyncProgress {
+        session_id: latest.session_id.clone(),
+        state,
+        percent: percent_sum / session.len() as u32,
+        start_time_in_ms: session.iter().map(|eni| eni.start_time_in_ms).min().unwrap_or_default(),
+        enis: session.len() as u32,
+        enis_completed: enis_completed as u32,
+    })
+}
+
+fn eni_steering_targets(
+    placement: &DashEniPlacementTable,
+    ha_set: &DashHaSetTable,
+    local_ha_role: Option<&str>,
+) -> Vec<String> {
+    let mut next_hops = vec![ha_set.local_ip.clone(), ha_set.peer_ip.clone()];
+    next_hops.sort_by_key(|ip| (ip.parse::<IpAddr>().ok(), ip.clone()));
+
+    let preferred = match placement.pinned_next_hop_index {
+        Some(index) if (index as usize) < next_hops.len() => next_hops[index as usize].clone(),
+        _ => match local_ha_role {
+            Some("active") | Some("standalone") => ha_set.local_ip.clone(),
+            _ => ha_set.peer_ip.clone(),
+        },
+    };
+    next_hops.retain(|ip| *ip != preferred);
+    next_hops.insert(0, preferred);
+    next_hops
 }
 
 // Implements messages handlers for HaScopeActor
@@ -396,7 +1170,7 @@ impl HaScopeActor {
     /// Updates the actor's internal config and performs any necessary initialization or subscriptions.
     /// Update DPU DASH_HA_SCOPE_TABLE
     /// Update NPU DASH_HA_SCOPE_STATE if approved_pending_operation_ids is not empty
-    fn handle_dash_ha_scope_config_table_message(
+    async fn handle_dash_ha_scope_config_table_message(
         &mut self,
         state: &mut State,
         key: &str,
@@ -416,11 +1190,34 @@ impl HaScopeActor {
         }
         let first_time = self.dash_ha_scope_config.is_none();
         let dash_ha_scope_config: DashHaScopeConfigTable = swss_serde::from_field_values(&kfv.field_values)?;
+        let old_dash_ha_scope_config = self.dash_ha_scope_config.replace(dash_ha_scope_config);
 
-        // Update internal config
-        self.dash_ha_scope_config = Some(dash_ha_scope_config);
+        // a finished switchover no longer overrides the role once the controller sets a new one
+        let desired_ha_state_changed = old_dash_ha_scope_config.as_ref().map(|c| &c.desired_ha_state)
+            != self.dash_ha_scope_config.as_ref().map(|c| &c.desired_ha_state);
+        if desired_ha_state_changed && self.switchover.as_ref().is_some_and(|s| !s.in_progress()) {
+            self.switchover = None;
+        }
+        if desired_ha_state_changed {
+            self.split_brain_demoted = false;
+        }
+        self.audit_config_change(old_dash_ha_scope_config.as_ref());
+        self.refresh_switch_driven_role(incoming);
 
         if first_time {
+            // Subscribe to the placement of the ENI in case this is an ENI scope. There is no
+            // placement for a DPU scope.
+            let eni_id = self.ha_scope_id.clone();
+            self.eni_placement_bridge = Some(
+                spawn_consumer_bridge_for_actor_with_selector::<DashEniPlacementTable, _>(
+                    context.get_edge_runtime().clone(),
+                    Self::name(),
+                    Some(&self.id),
+                    true,
+                    move |kfv: &KeyOpFieldValues| kfv.key == eni_id,
+                )
+                .await?,
+            );
             // Subscribe to the vDPU Actor for state updates.
             self.register_to_vdpu_actor(outgoing, true)?;
             // Subscribe to the ha-set Actor for state updates.
@@ -432,8 +1229,15 @@ impl HaScopeActor {
             return Ok(());
         }
 
+        // a standby DPU activates its role once the flow sync completes
+        self.hold_activate_role(state)?;
+
         // update the DASH_HA_SCOPE_TABLE in DPU
         self.update_dpu_ha_scope_table(state)?;
+        if self.activate_role_held_since.is_some() && !self.flow_sync_gates_activation(state.incoming()) {
+            // the DPU is no longer asked to be standby, the held activate_role is requested with the table
+            self.activate_role_held_since = None;
+        }
 
         // update the NPU DASH_HA_SCOPE_STATE because some fields are derived from dash_ha_scope_config
         self.update_npu_ha_scope_state_ha_state(state)?;
@@ -452,12 +1256,75 @@ impl HaScopeActor {
             self.update_npu_ha_scope_state_pending_operations(state, Vec::new(), approved_pending_operation_ids)?;
         }
 
-        Ok(())
-    }
-
-    /// Handles VDPU state update messages for this HA scope.
-    /// If the vdpu is unmanaged, the actor is put in dormant state. Otherwise, the actor subscribes to the
+        // a new planned switchover id starts the switchover on the active side, the peer joins when
+        // it is told to drain
+        let switchover_id = self
+            .dash_ha_scope_config
+            .as_ref()
+            .unwrap()
+            .planned_switchover_id
+            .clone()
+            .filter(|id| !id.is_empty());
+        let old_switchover_id = old_dash_ha_scope_config.and_then(|c| c.planned_switchover_id);
+        if let Some(switchover_id) = switchover_id.filter(|id| Some(id) != old_switchover_id.as_ref()) {
+            if self.get_dpu_ha_role() == "active" {
+                self.start_planned_switchover(state, &switchover_id)?;
+            } else {
+                debug!(
+                    "DPU is {}, planned switchover {switchover_id} is started by the peer",
+                    self.get_dpu_ha_role()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the operator actions in a DASH_HA_SCOPE_CONFIG_TABLE update, i.e. a new desired_ha_state,
+    /// approved pending operations and a planned switchover.
+    fn audit_config_change(&self, old: Option<&DashHaScopeConfigTable>) {
+        let Some(new) = self.dash_ha_scope_config.as_ref() else {
+            return;
+        };
+        let trigger = format!("DASH_HA_SCOPE_CONFIG_TABLE version {}", new.version);
+        let old_desired_ha_state = old.map_or("", |c| c.desired_ha_state.as_str());
+        if old_desired_ha_state != new.desired_ha_state {
+            self.audit(
+                AuditEvent::OperatorAction,
+                &format!("desired_ha_state={old_desired_ha_state}"),
+                &format!("desired_ha_state={}", new.desired_ha_state),
+                &trigger,
+            );
+        }
+
+        let old_approved = old.and_then(|c| c.approved_pending_operation_ids.as_ref());
+        for op_id in new.approved_pending_operation_ids.iter().flatten() {
+            if !old_approved.is_some_and(|ids| ids.contains(op_id)) {
+                self.audit(
+                    AuditEvent::OperatorAction,
+                    &format!("operation {op_id} pending"),
+                    &format!("operation {op_id} approved"),
+                    &trigger,
+                );
+            }
+        }
+
+        let old_switchover_id = old.and_then(|c| c.planned_switchover_id.as_deref()).unwrap_or("");
+        let new_switchover_id = new.planned_switchover_id.as_deref().unwrap_or("");
+        if !new_switchover_id.is_empty() && old_switchover_id != new_switchover_id {
+            self.audit(
+                AuditEvent::OperatorAction,
+                &format!("planned_switchover_id={old_switchover_id}"),
+                &format!("planned_switchover_id={new_switchover_id}"),
+                &trigger,
+            );
+        }
+    }
+
+    /// Handles VDPU state update messages for this HA scope.
+    /// If the vdpu is unmanaged, the actor is put in dormant state. Otherwise, the actor subscribes to the
     /// DASH_HA_SCOPE_STATE table and updates the NPU HA scope state.
+    #[cfg_attr(not(feature = "dpu"), allow(unused_variables))]
     async fn handle_vdpu_state_update(&mut self, state: &mut State, context: &mut Context) -> Result<()> {
         let (internal, incoming, _outgoing) = state.get_all();
         let Some(vdpu) = self.get_vdpu(incoming) else {
@@ -475,19 +1342,14 @@ impl HaScopeActor {
         let swss_key = format!(
             "{}{}{}",
             self.vdpu_id,
-            NpuDashHaScopeState::key_separator(),
+            NpuDashHaScopeState::source_key_separator(),
             self.ha_scope_id
         );
         if !internal.has_entry(NpuDashHaScopeState::table_name(), &swss_key) {
-            let db = crate::db_for_table::<NpuDashHaScopeState>().await?;
-            let table = Table::new_async(db, NpuDashHaScopeState::table_name()).await?;
-            internal.add(NpuDashHaScopeState::table_name(), table, swss_key).await;
-        }
-
-        if self.bridges.is_empty() {
             // subscribe to dpu DASH_HA_SCOPE_STATE
+            #[cfg(feature = "dpu")]
             self.bridges.push(
-                spawn_consumer_bridge_for_actor::<DpuDashHaScopeState>(
+                crate::actors::spawn_consumer_bridge_for_actor::<DpuDashHaScopeState>(
                     context.get_edge_runtime().clone(),
                     Self::name(),
                     Some(&self.id),
@@ -495,24 +1357,107 @@ impl HaScopeActor {
                 )
                 .await?,
             );
+            // subscribe to the flow sync state of the ENIs of this scope in dpu DASH_HA_FLOW_SYNC_STATE
+            #[cfg(feature = "dpu")]
+            {
+                let prefix = format!("{}{}", self.ha_scope_id, DpuDashHaFlowSyncState::source_key_separator());
+                self.bridges.push(
+                    spawn_consumer_bridge_for_actor_with_selector::<DpuDashHaFlowSyncState, _>(
+                        context.get_edge_runtime().clone(),
+                        Self::name(),
+                        Some(&self.id),
+                        false,
+                        move |kfv: &KeyOpFieldValues| kfv.key.starts_with(&prefix),
+                    )
+                    .await?,
+                );
+            }
+            ha_report::add_summary(internal, &swss_key).await?;
+            let table = crate::tables::open_table::<NpuDashHaScopeState>().await?;
+            internal.add(NpuDashHaScopeState::table_name(), table, swss_key).await;
         }
+
+        // the BFD sessions of the vDPU drive the role of a switch-owned DPU scope
+        let mut role_changed = self.refresh_switch_driven_role(state.incoming());
+        // a DPU in maintenance hands the active role over to the peer
+        role_changed |= self.refresh_maintenance(state)?;
+
         // ha_scope_table in dpu has no info derived from vDPU but it won't be programed until we receive vDPU which confirms the vDPU is managed
         self.update_dpu_ha_scope_table(state)?;
         self.update_npu_ha_scope_state_base(state)?;
+        if role_changed {
+            self.update_npu_ha_scope_state_ha_state(state)?;
+        }
         Ok(())
     }
 
     /// Handles HaSet state update messages for this HA scope.
     /// Update NPU DASH_HA_SCOPE_STATE
+    /// Roll back the planned switchover in progress if the hamgrd of the peer is unreachable
+    /// Drive the role of a switch-owned DPU scope if the peer is lost
     fn handle_haset_state_update(&mut self, state: &mut State) -> Result<()> {
+        let Some(haset) = self.get_haset(state.incoming()) else {
+            return Ok(());
+        };
+        if self.eni_placement.is_none() && is_dpu_scope(&haset.ha_set) && self.eni_placement_bridge.take().is_some() {
+            debug!(
+                "HA set {} is DPU scope, stop watching the ENI placement",
+                self.ha_set_id()
+            );
+        }
+        if haset.peer_unreachable {
+            self.roll_back_planned_switchover(state, "the hamgrd of the peer is unreachable", false)?;
+        }
+        // both DPUs may have taken the active role while the partition lasted
+        let healed = self.peer_unreachable && !haset.peer_unreachable;
+        self.peer_unreachable = haset.peer_unreachable;
+        if healed && self.vdpu_is_managed(state.incoming()) {
+            info!("The hamgrd of the peer is reachable again, checking for a split brain");
+            self.peer_ha_role = None;
+            self.send_ha_role_to_peer(state)?;
+        }
         self.update_npu_ha_scope_state_base(state)?;
-        Ok(())
+        self.update_npu_ha_scope_state_steering(state)?;
+        if self.refresh_switch_driven_role(state.incoming()) && self.vdpu_is_managed(state.incoming()) {
+            self.update_dpu_ha_scope_table(state)?;
+            self.update_npu_ha_scope_state_ha_state(state)?;
+        }
+        self.update_ha_scope_summary(state)
+    }
+
+    /// Handles DASH_ENI_PLACEMENT_TABLE updates of the ENI of this HA scope.
+    /// Move the registration to the HA set the ENI is placed on and update the ENI steering targets.
+    fn handle_eni_placement_update(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let (_internal, incoming, outgoing) = state.get_all();
+        let kfv: KeyOpFieldValues = incoming.get(key)?.deserialize_data()?;
+        let placement: Option<DashEniPlacementTable> = match kfv.operation {
+            KeyOperation::Set => Some(swss_serde::from_field_values(&kfv.field_values)?),
+            KeyOperation::Del => None,
+        };
+
+        let old_ha_set_id = self.ha_set_id().to_string();
+        let new_ha_set_id = placement
+            .as_ref()
+            .map_or(self.ha_scope_id.as_str(), |p| p.ha_set_id.as_str());
+        if old_ha_set_id != new_ha_set_id {
+            info!(
+                "ENI {} is moved from HA set {} to {}",
+                self.ha_scope_id, old_ha_set_id, new_ha_set_id
+            );
+            self.register_to_haset_actor(outgoing, false)?;
+            self.eni_placement = placement;
+            self.register_to_haset_actor(outgoing, true)?;
+        } else {
+            self.eni_placement = placement;
+        }
+
+        self.update_npu_ha_scope_state_steering(state)
     }
 
     /// Handles DPU DASH_HA_SCOPE_STATE update messages for this HA scope.
     /// Update NPU DASH_HA_SCOPE_STATE ha_state related fields
     /// Update NPU DASH_HA_SCOPE_STATE pending operation list if there are new operations requested by DPU
-    fn handle_dpu_ha_scope_state_update(&mut self, state: &mut State) -> Result<()> {
+    async fn handle_dpu_ha_scope_state_update(&mut self, state: &mut State) -> Result<()> {
         let (_internal, incoming, _) = state.get_all();
         // calculate operation requested by dpu
         let Some(new_dpu_ha_scope_state) = self.get_dpu_ha_scope_state(incoming) else {
@@ -521,10 +1466,11 @@ impl HaScopeActor {
         };
         let mut operations: Vec<(String, String)> = Vec::new();
 
-        // if hamgrd is restarted, we will lose the cached old state. In this case, we will treat
-        // all pending operations as new and request the sdn controller via npu dash_ha_scope_state
-        // to take action. If these have been notified to sdn controller prior to hamgrd restart,
-        // they will be no change to dash_ha_scope_state and no action will be taken by sdn controller.
+        // the cached old state is restored from the snapshot if hamgrd is restarted. If there is no
+        // snapshot, we will treat all pending operations as new and request the sdn controller via npu
+        // dash_ha_scope_state to take action. If these have been notified to sdn controller prior to
+        // hamgrd restart, they will be no change to dash_ha_scope_state and no action will be taken by
+        // sdn controller.
         let old_dpu_ha_scope_state = self.dpu_ha_scope_state.as_ref().cloned().unwrap_or_default();
         if new_dpu_ha_scope_state.activate_role_pending && !old_dpu_ha_scope_state.activate_role_pending {
             operations.push((Uuid::new_v4().to_string(), "activate_role".to_string()));
@@ -538,23 +1484,449 @@ impl HaScopeActor {
             operations.push((Uuid::new_v4().to_string(), "flow_reconcile".to_string()));
         }
 
+        if self.dpu_ha_scope_state.is_some() && new_dpu_ha_scope_state.ha_role != old_dpu_ha_scope_state.ha_role {
+            let scope = format!(
+                "{}{}{}",
+                self.vdpu_id,
+                NpuDashHaScopeState::source_key_separator(),
+                self.ha_scope_id
+            );
+            let reason = format!("DPU confirmed ha_role in term {}", new_dpu_ha_scope_state.ha_term);
+            self.pending_events.push(HaEvent::role_change(
+                &scope,
+                &old_dpu_ha_scope_state.ha_role,
+                &new_dpu_ha_scope_state.ha_role,
+                &reason,
+            ));
+            self.audit(
+                AuditEvent::RoleChange,
+                &old_dpu_ha_scope_state.ha_role,
+                &new_dpu_ha_scope_state.ha_role,
+                &reason,
+            );
+        }
+
+        self.update_ha_alarms(state, &old_dpu_ha_scope_state, &new_dpu_ha_scope_state)
+            .await?;
+
+        let ha_role_changed = new_dpu_ha_scope_state.ha_role != old_dpu_ha_scope_state.ha_role;
         self.dpu_ha_scope_state = Some(new_dpu_ha_scope_state);
+        // the peer checks for a split brain with every role reported since the partition healed
+        if ha_role_changed && self.peer_ha_role.is_some() {
+            self.send_ha_role_to_peer(state)?;
+        }
 
         self.update_npu_ha_scope_state_ha_state(state)?;
+        self.update_npu_ha_scope_state_steering(state)?;
 
         if !operations.is_empty() {
             self.update_npu_ha_scope_state_pending_operations(state, operations, Vec::new())?;
         }
 
+        self.advance_planned_switchover(state)
+    }
+
+    /// Handles the shutdown of this hamgrd.
+    /// Tell the HA scope actor of the peer, in the hamgrd managing the peer vDPU, that this is a planned exit.
+    fn handle_hamgrd_shutdown(&self, state: &mut State) -> Result<()> {
+        let (_internal, incoming, outgoing) = state.get_all();
+        if !self.vdpu_is_managed(incoming) {
+            return Ok(());
+        }
+        let Some(peer_sp) = self.get_peer_sp(incoming, outgoing) else {
+            return Ok(());
+        };
+        info!("Notifying {} of the planned exit", peer_sp.to_longest_path());
+        outgoing.send(peer_sp, PeerPlannedExit::new_actor_msg(&self.id, now_in_millis())?);
+        Ok(())
+    }
+
+    /// Handles the planned exit of the hamgrd managing the peer vDPU.
+    fn handle_peer_planned_exit(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let entry = state.incoming().get_entry(key)?;
+        let PeerPlannedExit { exit_time_in_ms } = entry.msg.deserialize_data()?;
+        let scope = format!(
+            "{}{}{}",
+            self.vdpu_id,
+            NpuDashHaScopeState::source_key_separator(),
+            self.ha_scope_id
+        );
+        let reason = format!(
+            "planned exit of the hamgrd of {} at {exit_time_in_ms}",
+            entry.source.to_longest_path()
+        );
+        info!("{scope}: {reason}");
+        self.pending_events.push(HaEvent::new(
+            ha_events::HaEventType::HaPeerPlannedExit,
+            ha_events::HaEventSeverity::Info,
+            &scope,
+            &reason,
+        ));
+        Ok(())
+    }
+
+    /// Handles the role of the peer DPU, reported once the partition from the peer heals.
+    /// If both DPUs are active, the loser of the tie-breaker is demoted to standby and the split brain is
+    /// raised until either DPU leaves the active role.
+    async fn handle_peer_ha_role(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let PeerHaRole { ha_role } = state.incoming().get(key)?.deserialize_data()?;
+        let first_report = self.peer_ha_role.is_none();
+        self.peer_ha_role = Some(ha_role);
+        let incoming = state.incoming();
+        let (Some(vdpu), Some(peer)) = (self.get_vdpu(incoming), self.get_haset(incoming).and_then(|h| h.peer)) else {
+            return Ok(());
+        };
+        // the first report of the peer is answered, in case the peer saw the partition heal first
+        if first_report {
+            self.send_ha_role_to_peer(state)?;
+        }
+
+        let resource = self.scope_name();
+        let split_brain = self.in_split_brain();
+        if split_brain && !self.split_brain_demoted {
+            let preferred_winner = self
+                .dash_ha_scope_config
+                .as_ref()
+                .and_then(|config| config.preferred_winner.as_deref());
+            let local = (vdpu.dpu.dpu_id, self.vdpu_id.as_str());
+            let wins = wins_split_brain(preferred_winner, local, (peer.dpu_id, peer.vdpu_id.as_str()));
+            let reason = format!(
+                "both DPUs are active, {} stays active",
+                if wins { &self.vdpu_id } else { &peer.vdpu_id }
+            );
+            warn!("{resource}: split brain with {}: {reason}", peer.vdpu_id);
+            let event = update_alarm(state.internal(), &resource, HaAlarmType::SplitBrain, true, &reason).await?;
+            self.pending_events.extend(event);
+            let new_role = if wins { self.get_dpu_ha_role() } else { "standby" };
+            self.audit(AuditEvent::SplitBrain, self.get_dpu_ha_role(), new_role, &reason);
+            if !wins {
+                ha_report::record_switchover(state.internal(), &resource, &format!("split brain: {reason}"))?;
+                self.split_brain_demoted = true;
+                self.update_dpu_ha_scope_table(state)?;
+                self.update_npu_ha_scope_state_ha_state(state)?;
+            }
+        } else if !split_brain {
+            let peer_ha_role = self.peer_ha_role.as_deref().unwrap_or_default();
+            let reason = format!("the peer DPU is {peer_ha_role}");
+            let event = update_alarm(state.internal(), &resource, HaAlarmType::SplitBrain, false, &reason).await?;
+            self.pending_events.extend(event);
+            // the demotion holds only while the peer is active
+            if self.split_brain_demoted && !is_active_role(peer_ha_role) {
+                self.split_brain_demoted = false;
+                let desired_ha_state = self
+                    .dash_ha_scope_config
+                    .as_ref()
+                    .map_or("", |config| config.desired_ha_state.as_str());
+                self.audit(AuditEvent::SplitBrain, "standby", desired_ha_state, &reason);
+                self.update_dpu_ha_scope_table(state)?;
+                self.update_npu_ha_scope_state_ha_state(state)?;
+            }
+        }
+        self.update_ha_scope_summary(state)
+    }
+
+    /// Handles a request to switch the active role of the DPU over to the peer DPU.
+    fn handle_planned_switchover_request(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let PlannedSwitchoverRequest { switchover_id } = state.incoming().get(key)?.deserialize_data()?;
+        let old_switchover_id = self.switchover.as_ref().map_or("", |s| s.id.as_str());
+        self.audit(
+            AuditEvent::OperatorAction,
+            &format!("planned_switchover_id={old_switchover_id}"),
+            &format!("planned_switchover_id={switchover_id}"),
+            "PlannedSwitchoverRequest",
+        );
+        self.start_planned_switchover(state, &switchover_id)
+    }
+
+    /// Handles the steps of a planned switchover from the HA scope actor of the peer DPU.
+    fn handle_planned_switchover(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let entry = state.incoming().get_entry(key)?;
+        let source = entry.source.clone();
+        // steps arriving after their deadline are dropped by the incoming table already
+        let deadline_in_ms = entry.msg.deadline;
+        let PlannedSwitchover { switchover_id, step } = entry.msg.deserialize_data()?;
+        let Some(switchover) = self.switchover.as_mut().filter(|s| s.id == switchover_id) else {
+            if step == SwitchoverStep::Drain {
+                return self.follow_planned_switchover(state, &switchover_id, source, deadline_in_ms);
+            }
+            debug!("Ignoring {step:?} of unknown planned switchover {switchover_id}");
+            return Ok(());
+        };
+        if !switchover.in_progress() {
+            debug!("Ignoring {step:?} of finished planned switchover {switchover_id}");
+            return Ok(());
+        }
+
+        match step {
+            SwitchoverStep::Drain => Ok(()),
+            SwitchoverStep::Drained | SwitchoverStep::Done if switchover.initiator => {
+                switchover.peer_confirmed = true;
+                self.advance_planned_switchover(state)
+            }
+            SwitchoverStep::Flip if !switchover.initiator && switchover.phase == SwitchoverPhase::Draining => {
+                switchover.deadline_in_ms = deadline_in_ms;
+                self.enter_switchover_phase(state, SwitchoverPhase::Flipping, "flip requested by the peer")?;
+                // the DPU may hold the role already if the flip was redelivered
+                self.advance_planned_switchover(state)
+            }
+            SwitchoverStep::Abort => self.roll_back_planned_switchover(state, "aborted by the peer", false),
+            _ => {
+                warn!("Unexpected {step:?} in planned switchover {switchover_id}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles the timeout of a phase of a planned switchover, rolling it back if it is still in the phase.
+    fn handle_planned_switchover_timeout(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let PlannedSwitchoverTimeout { switchover_id, phase } = state.incoming().get(key)?.deserialize_data()?;
+        let timed_out = self
+            .switchover
+            .as_ref()
+            .is_some_and(|s| s.id == switchover_id && s.phase == phase);
+        if !timed_out {
+            return Ok(());
+        }
+        let reason = format!("{} timed out", phase.as_str());
+        self.roll_back_planned_switchover(state, &reason, true)
+    }
+
+    /// Handles a step of a planned switchover the peer didn't ack before the deadline of the phase,
+    /// rolling the switchover back if it is still in progress.
+    fn handle_planned_switchover_step_timeout(&mut self, state: &mut State, msg: &ActorMessage) -> Result<()> {
+        let PlannedSwitchover { switchover_id, step } = msg.deserialize_data()?;
+        if !self.switchover.as_ref().is_some_and(|s| s.id == switchover_id) {
+            return Ok(());
+        }
+        let reason = format!("the peer didn't ack {step:?} in time");
+        self.roll_back_planned_switchover(state, &reason, true)
+    }
+
+    /// Handles DPU DASH_HA_FLOW_SYNC_STATE updates of the ENIs of this HA scope.
+    /// Update the flow sync progress in NPU DASH_HA_SCOPE_STATE
+    /// Request the held activate_role once the flow sync completes
+    async fn handle_flow_sync_state_update(&mut self, state: &mut State) -> Result<()> {
+        if self
+            .get_flow_sync_progress(state.incoming())
+            .is_some_and(|progress| progress.state == "completed")
+        {
+            self.flow_sync_timed_out = false;
+        }
+        self.update_npu_ha_scope_state_flow_sync(state)?;
+        self.update_flow_sync_alarm(state).await?;
+        self.release_activate_role(state, "flow sync is completed")
+    }
+
+    /// Handles the timeout of the flow sync, requesting the activate_role if it is still held for it.
+    async fn handle_flow_sync_timeout(&mut self, state: &mut State, key: &str) -> Result<()> {
+        let FlowSyncTimeout { held_since_in_ms } = state.incoming().get(key)?.deserialize_data()?;
+        if self.activate_role_held_since != Some(held_since_in_ms) {
+            return Ok(());
+        }
+        warn!(
+            "{}: flow sync didn't complete in {:?}",
+            self.scope_name(),
+            self.get_flow_sync_timeout()
+        );
+        self.flow_sync_timed_out = true;
+        self.update_flow_sync_alarm(state).await?;
+        self.release_activate_role(state, "flow sync timed out")
+    }
+
+    /// Set or clear HA alarms based on the DPU DASH_HA_SCOPE_STATE transition.
+    /// - unplanned_failover: DPU left active role while active is still the desired state.
+    /// - split_brain: DPU reports brainsplit recovery pending.
+    /// - bulk_sync_failure: flow sync session reported failed in NPU DASH_HA_SCOPE_STATE or timed out.
+    async fn update_ha_alarms(
+        &mut self,
+        state: &mut State,
+        old: &DpuDashHaScopeState,
+        new: &DpuDashHaScopeState,
+    ) -> Result<()> {
+        let Some(ref dash_ha_scope_config) = self.dash_ha_scope_config else {
+            return Ok(());
+        };
+        let resource = format!(
+            "{}{}{}",
+            self.vdpu_id,
+            NpuDashHaScopeState::source_key_separator(),
+            self.ha_scope_id
+        );
+        // a role handed over in a planned switchover is not a failover
+        let desired_ha_state = self.get_target_ha_role(dash_ha_scope_config);
+        let internal = state.internal();
+
+        if old.ha_role == "active" && new.ha_role != "active" && desired_ha_state == "active" {
+            let reason = format!("ha_role changed from {} to {} unexpectedly", old.ha_role, new.ha_role);
+            let event = update_alarm(internal, &resource, HaAlarmType::UnplannedFailover, true, &reason).await?;
+            self.pending_events.extend(event);
+            ha_report::record_switchover(internal, &resource, &format!("unplanned failover: {reason}"))?;
+        } else if new.ha_role == desired_ha_state {
+            let reason = format!("ha_role is back to {}", new.ha_role);
+            let event = update_alarm(internal, &resource, HaAlarmType::UnplannedFailover, false, &reason).await?;
+            self.pending_events.extend(event);
+        }
+
+        // a split brain found with the peer is cleared once either DPU leaves the active role
+        let split_brain = is_active_role(&new.ha_role) && self.peer_ha_role.as_deref().is_some_and(is_active_role);
+        let reason = match (new.brainsplit_recover_pending, split_brain) {
+            (true, _) => "brainsplit recovery is pending on DPU",
+            (false, true) => "both DPUs are active",
+            (false, false) => "brainsplit recovered",
+        };
+        let event = update_alarm(
+            internal,
+            &resource,
+            HaAlarmType::SplitBrain,
+            new.brainsplit_recover_pending || split_brain,
+            reason,
+        )
+        .await?;
+        self.pending_events.extend(event);
+
+        self.update_flow_sync_alarm(state).await
+    }
+
+    /// Set or clear the bulk_sync_failure alarm: the flow sync session reported failed in NPU
+    /// DASH_HA_SCOPE_STATE or didn't complete in time for the held activate_role.
+    async fn update_flow_sync_alarm(&mut self, state: &mut State) -> Result<()> {
+        let internal = state.internal();
+        let flow_sync_state = self
+            .get_npu_ha_scope_state(internal)
+            .and_then(|s| s.flow_sync_session_state);
+        let (failed, reason) = match flow_sync_state {
+            Some(ref flow_sync_state) if flow_sync_state != "completed" && self.flow_sync_timed_out => {
+                (true, format!("flow sync session is {flow_sync_state} and timed out"))
+            }
+            Some(flow_sync_state) => (
+                flow_sync_state == "failed",
+                format!("flow sync session is {flow_sync_state}"),
+            ),
+            None if self.flow_sync_timed_out => (true, "flow sync session didn't start in time".to_string()),
+            None => return Ok(()),
+        };
+        let event = update_alarm(
+            internal,
+            &self.scope_name(),
+            HaAlarmType::BulkSyncFailure,
+            failed,
+            &reason,
+        )
+        .await?;
+        self.pending_events.extend(event);
         Ok(())
     }
 }
 
 impl Actor for HaScopeActor {
+    async fn init(&mut self, state: &mut State) -> Result<()> {
+        let Some(snapshot) = crate::actors::enable_snapshots(state, Self::name(), &self.id).await else {
+            return Ok(());
+        };
+        let snapshot = HaScopeSnapshot::from_value(snapshot).unwrap_or_else(|e| {
+            error!("Failed to restore the HA scope state from the snapshot: {e}");
+            HaScopeSnapshot::default()
+        });
+        self.dpu_ha_scope_state = snapshot.dpu_ha_scope_state;
+        self.split_brain_demoted = snapshot.split_brain_demoted;
+        self.maintenance = snapshot.maintenance;
+        self.switchover = snapshot.switchover;
+        // The timeout of the phase is lost with the previous instance, it fires at the same deadline
+        if let Some(switchover) = self.switchover.as_ref().filter(|switchover| switchover.in_progress()) {
+            let timeout = switchover
+                .deadline_in_ms
+                .map_or(self.get_switchover_timeout(), |deadline| {
+                    Duration::from_millis(deadline.saturating_sub(now_in_millis() as u64))
+                });
+            info!(
+                "Planned switchover {} restored while {}",
+                switchover.id,
+                switchover.phase.as_str()
+            );
+            arm_switchover_timeout(state, &self.id, switchover, timeout)?;
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        let snapshot = HaScopeSnapshot {
+            dpu_ha_scope_state: self.dpu_ha_scope_state.clone(),
+            split_brain_demoted: self.split_brain_demoted,
+            maintenance: self.maintenance,
+            switchover: self.switchover.clone(),
+        };
+        Some(serde_json::to_value(&snapshot).unwrap())
+    }
+
     #[instrument(name="handle_message", level="info", skip_all, fields(actor=format!("ha-scope/{}", self.id), key=key))]
     async fn handle_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
+        let saved = self.save_fields();
+        let res = self.dispatch_message(state, key, context).await;
+        self.finish_callback(saved, &res);
+        res
+    }
+
+    async fn handle_timeout(&mut self, state: &mut State, msg: &ActorMessage, _context: &mut Context) -> Result<()> {
+        let saved = self.save_fields();
+        let res = self.dispatch_timeout(state, msg);
+        self.finish_callback(saved, &res);
+        res
+    }
+}
+
+/// Arm the timeout of the current phase of the in progress `switchover` of the actor `id`.
+fn arm_switchover_timeout(state: &mut State, id: &str, switchover: &Switchover, timeout: Duration) -> Result<()> {
+    let outgoing = state.outgoing();
+    outgoing.send_after(
+        outgoing.from_my_sp(HaScopeActor::name(), id),
+        PlannedSwitchoverTimeout::new_actor_msg(&switchover.id, switchover.phase)?,
+        timeout,
+    );
+    Ok(())
+}
+
+impl HaScopeActor {
+    fn save_fields(&self) -> SavedFields {
+        SavedFields {
+            switchover: self.switchover.clone(),
+            peer_unreachable: self.peer_unreachable,
+            peer_ha_role: self.peer_ha_role.clone(),
+            split_brain_demoted: self.split_brain_demoted,
+            maintenance: self.maintenance,
+            switch_driven_role: self.switch_driven_role,
+            eni_placement: self.eni_placement.clone(),
+            activate_role_held_since: self.activate_role_held_since,
+            flow_sync_timed_out: self.flow_sync_timed_out,
+        }
+    }
+
+    /// Emit the events of a callback that succeeded, or restore the fields saved before a callback that
+    /// failed and drop its events, whose state changes are dropped too.
+    fn finish_callback(&mut self, saved: SavedFields, res: &Result<()>) {
+        if res.is_ok() {
+            for event in self.pending_events.drain(..) {
+                ha_events::emit(event);
+            }
+            return;
+        }
+        self.pending_events.clear();
+        self.switchover = saved.switchover;
+        self.peer_unreachable = saved.peer_unreachable;
+        self.peer_ha_role = saved.peer_ha_role;
+        self.split_brain_demoted = saved.split_brain_demoted;
+        self.maintenance = saved.maintenance;
+        self.switch_driven_role = saved.switch_driven_role;
+        self.eni_placement = saved.eni_placement;
+        self.activate_role_held_since = saved.activate_role_held_since;
+        self.flow_sync_timed_out = saved.flow_sync_timed_out;
+    }
+
+    async fn dispatch_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
         if key == Self::table_name() {
-            if let Err(e) = self.handle_dash_ha_scope_config_table_message(state, key, context) {
+            if let Err(e) = self
+                .handle_dash_ha_scope_config_table_message(state, key, context)
+                .await
+            {
                 error!("handle_dash_ha_scope_config_table_message failed: {e}");
             }
             return Ok(());
@@ -565,39 +1937,189 @@ impl Actor for HaScopeActor {
         }
 
         if VDpuActorState::is_my_msg(key) {
+            let (_internal, incoming, outgoing) = state.get_all();
+            ActorRegistration::refresh_on_gap(incoming, outgoing, key, RegistrationType::VDPUState, &self.id)?;
             return self.handle_vdpu_state_update(state, context).await;
         }
         if HaSetActorState::is_my_msg(key) {
+            let (_internal, incoming, outgoing) = state.get_all();
+            ActorRegistration::refresh_on_gap(incoming, outgoing, key, RegistrationType::HaSetState, &self.id)?;
             return self.handle_haset_state_update(state);
         }
+        if key == DashEniPlacementTable::table_name() {
+            return self.handle_eni_placement_update(state, key);
+        }
+        if HamgrdShutdown::is_my_msg(key) {
+            return self.handle_hamgrd_shutdown(state);
+        }
+        if PeerPlannedExit::is_my_msg(key) {
+            return self.handle_peer_planned_exit(state, key);
+        }
+        if PeerHaRole::is_my_msg(key) {
+            return self.handle_peer_ha_role(state, key).await;
+        }
+        if PlannedSwitchoverRequest::is_my_msg(key) {
+            return self.handle_planned_switchover_request(state, key);
+        }
+        if PlannedSwitchover::is_my_msg(key) {
+            return self.handle_planned_switchover(state, key);
+        }
+        if PlannedSwitchoverTimeout::is_my_msg(key) {
+            return self.handle_planned_switchover_timeout(state, key);
+        }
+        if FlowSyncTimeout::is_my_msg(key) {
+            return self.handle_flow_sync_timeout(state, key).await;
+        }
+        if key.starts_with(DpuDashHaFlowSyncState::table_name()) {
+            return self.handle_flow_sync_state_update(state).await;
+        }
         if key.starts_with(DpuDashHaScopeState::table_name()) {
             // dpu ha scope state update
-            return self.handle_dpu_ha_scope_state_update(state);
+            return self.handle_dpu_ha_scope_state_update(state).await;
         }
 
         Ok(())
     }
+
+    fn dispatch_timeout(&mut self, state: &mut State, msg: &ActorMessage) -> Result<()> {
+        if PlannedSwitchover::is_my_msg(&msg.key) {
+            return self.handle_planned_switchover_step_timeout(state, msg);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
         actors::{
-            ha_scope::HaScopeActor,
+            explore::{self, Event, Exploration, Model, Outputs},
+            ha_scope::{aggregate_flow_sync, eni_steering_targets, wins_split_brain, HaScopeActor, HaScopeSnapshot},
             ha_set::HaSetActor,
+            scenario::Scenario,
             test::{self, *},
             vdpu::VDpuActor,
             DbBasedActor,
         },
         db_structs::{
-            now_in_millis, DashHaScopeConfigTable, DashHaScopeTable, DpuDashHaScopeState, NpuDashHaScopeState,
+            now_in_millis, DashEniPlacementTable, DashHaAlarmTable, DashHaScopeConfigTable, DashHaScopeSummaryTable,
+            DashHaScopeTable, DashHaSetTable, DpuDashHaFlowSyncState, DpuDashHaScopeState, NpuDashHaScopeState,
         },
         ha_actor_messages::*,
     };
     use std::time::Duration;
-    use swss_common::{SonicDbTable, Table};
+    use swbus_actor::ActorRuntime;
+    use swbus_edge::swbus_proto::swbus::ServicePath;
+    use swss_common::{FieldValues, SonicDbTable, Table};
     use swss_common_testing::*;
     use swss_serde::to_field_values;
+    use tokio::task::JoinHandle;
+
+    #[test]
+    fn test_wins_split_brain() {
+        // the preferred winner stays active, whatever the DPU ids
+        assert!(wins_split_brain(Some("vdpu1-0"), (1, "vdpu1-0"), (0, "vdpu0-0")));
+        assert!(!wins_split_brain(Some("vdpu1-0"), (0, "vdpu0-0"), (1, "vdpu1-0")));
+        // otherwise the lower DPU id, then the lower vDPU id
+        assert!(wins_split_brain(None, (0, "vdpu1-0"), (1, "vdpu0-0")));
+        assert!(wins_split_brain(Some(""), (0, "vdpu0-0"), (0, "vdpu1-0")));
+        assert!(!wins_split_brain(Some("vdpu2-0"), (0, "vdpu1-0"), (0, "vdpu0-0")));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let snapshot = HaScopeSnapshot {
+            dpu_ha_scope_state: Some(DpuDashHaScopeState {
+                ha_role: "standby".to_string(),
+                ..Default::default()
+            }),
+            split_brain_demoted: true,
+            maintenance: false,
+            switchover: None,
+        };
+        let restored = HaScopeSnapshot::from_value(serde_json::to_value(&snapshot).unwrap()).unwrap();
+        assert_eq!(restored.dpu_ha_scope_state, snapshot.dpu_ha_scope_state);
+        assert!(restored.split_brain_demoted);
+
+        // Snapshots of older versions only hold the DPU HA scope state
+        let restored =
+            HaScopeSnapshot::from_value(serde_json::to_value(&snapshot.dpu_ha_scope_state).unwrap()).unwrap();
+        assert_eq!(restored.dpu_ha_scope_state, snapshot.dpu_ha_scope_state);
+        assert!(!restored.split_brain_demoted);
+        let restored = HaScopeSnapshot::from_value(serde_json::Value::Null).unwrap();
+        assert_eq!(restored.dpu_ha_scope_state, None);
+    }
+
+    #[test]
+    fn test_eni_steering_targets() {
+        let mut placement = DashEniPlacementTable {
+            version: "1".to_string(),
+            eni_mac: "00:11:22:33:44:55".to_string(),
+            ha_set_id: "haset0-0".to_string(),
+            pinned_next_hop_index: None,
+        };
+        let (_, ha_set_switch0) = make_dpu_scope_ha_set_obj(0, 0);
+        let (_, ha_set_switch1) = make_dpu_scope_ha_set_obj(1, 0);
+
+        // the DPU holding the active role comes first
+        assert_eq!(
+            eni_steering_targets(&placement, &ha_set_switch0, Some("active")),
+            vec!["18.0.0.0", "18.0.1.0"]
+        );
+        assert_eq!(
+            eni_steering_targets(&placement, &ha_set_switch1, Some("standby")),
+            vec!["18.0.0.0", "18.0.1.0"]
+        );
+        assert_eq!(
+            eni_steering_targets(&placement, &ha_set_switch0, None),
+            vec!["18.0.1.0", "18.0.0.0"]
+        );
+
+        // pinned next hop is the same DPU on both switches regardless of the role
+        placement.pinned_next_hop_index = Some(1);
+        assert_eq!(
+            eni_steering_targets(&placement, &ha_set_switch0, Some("active")),
+            vec!["18.0.1.0", "18.0.0.0"]
+        );
+        assert_eq!(
+            eni_steering_targets(&placement, &ha_set_switch1, Some("standby")),
+            vec!["18.0.1.0", "18.0.0.0"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_flow_sync() {
+        let eni = |session_id: &str, state: &str, sync_percent: u32, start_time_in_ms: i64| DpuDashHaFlowSyncState {
+            session_id: session_id.to_string(),
+            state: state.to_string(),
+            sync_percent,
+            start_time_in_ms,
+        };
+        assert_eq!(aggregate_flow_sync(&[]), None);
+
+        // the ENIs of an older session don't count
+        let mut enis = vec![
+            eni("session0", "failed", 10, 100),
+            eni("session1", "in_progress", 50, 200),
+            eni("session1", "completed", 90, 210),
+        ];
+        let progress = aggregate_flow_sync(&enis).unwrap();
+        assert_eq!(progress.session_id, "session1");
+        assert_eq!(progress.state, "in_progress");
+        assert_eq!(progress.percent, 75);
+        assert_eq!(progress.start_time_in_ms, 200);
+        assert_eq!((progress.enis, progress.enis_completed), (2, 1));
+
+        // the session completes once all of its ENIs complete, and fails if any of them fails
+        enis[1] = eni("session1", "completed", 100, 200);
+        let progress = aggregate_flow_sync(&enis).unwrap();
+        assert_eq!(
+            (progress.state, progress.percent, progress.enis_completed),
+            ("completed", 100, 2)
+        );
+        enis.push(eni("session1", "failed", 20, 220));
+        assert_eq!(aggregate_flow_sync(&enis).unwrap().state, "failed");
+    }
 
     #[tokio::test]
     async fn ha_scope_planned_up_then_down() {
@@ -690,6 +2212,7 @@ mod test {
 
             // Write to NPU DASH_HA_SCOPE_STATE through internal state
             chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_ha_scope_state_fvs2 },
+            chkgolden! { name: "ha_scope_planned_up", tables: [NpuDashHaScopeState] },
 
             // Send DPU DASH_HA_SCOPE_STATE with role activation request to the actor
             send! { key: DpuDashHaScopeState::table_name(), data: {"key": DpuDashHaScopeState::table_name(), "operation": "Set",
@@ -796,4 +2319,790 @@ mod test {
             panic!("timeout waiting for actor to terminate");
         }
     }
+
+    #[tokio::test]
+    async fn ha_scope_unplanned_failover_scenario() {
+        // To enable trace, set ENABLE_TRACE=1 to run test
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        // prepare test data
+        let (ha_set_id, ha_set_obj) = make_dpu_scope_ha_set_obj(0, 0);
+        let dpu_mon = make_dpu_pmon_state(true);
+        let bfd_up = make_dpu_bfd_state(vec!["10.0.0.0", "10.0.1.0"], Vec::new());
+        let dpu0 = make_local_dpu_actor_state(0, 0, true, Some(dpu_mon.clone()), Some(bfd_up));
+        let (vdpu0_id, vdpu0_up) = make_vdpu_actor_state(true, &dpu0);
+        let dpu0 = make_local_dpu_actor_state(0, 0, true, Some(dpu_mon), Some(make_dpu_bfd_state(vec![], vec![])));
+        let (_, vdpu0_bfd_down) = make_vdpu_actor_state(true, &dpu0);
+
+        let dpu_active = make_dpu_ha_scope_state("active");
+        let mut dpu_standalone = make_dpu_ha_scope_state("standalone");
+        dpu_standalone.ha_term = "2".to_string();
+
+        // NPU DASH_HA_SCOPE_STATE along the scenario
+        let npu_state_base = make_npu_ha_scope_state(&vdpu0_up, &ha_set_obj);
+        let mut npu_state_active = npu_state_base.clone();
+        update_npu_ha_scope_state_by_dpu_scope_state(&mut npu_state_active, &dpu_active, "active");
+        let mut npu_state_bfd_down = npu_state_active.clone();
+        update_npu_ha_scope_state_by_vdpu(&mut npu_state_bfd_down, &vdpu0_bfd_down);
+        let mut npu_state_standalone = npu_state_bfd_down.clone();
+        update_npu_ha_scope_state_by_dpu_scope_state(&mut npu_state_standalone, &dpu_standalone, "active");
+        let mut npu_state_final = npu_state_standalone.clone();
+        npu_state_final.local_target_asic_ha_state = Some("standalone".to_string());
+        let [npu_state_base, npu_state_active, npu_state_bfd_down, npu_state_standalone, npu_state_final] = [
+            npu_state_base,
+            npu_state_active,
+            npu_state_bfd_down,
+            npu_state_standalone,
+            npu_state_final,
+        ]
+        .map(|s| to_field_values(&s).unwrap());
+
+        let scope_id = format!("{vdpu0_id}:{ha_set_id}");
+        let scope_id_in_state = format!("{vdpu0_id}|{ha_set_id}");
+        let ha_scope_actor = HaScopeActor::new(scope_id.clone()).unwrap();
+        let handle = runtime.spawn(ha_scope_actor, HaScopeActor::name(), &scope_id);
+
+        let config_bridge = crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge());
+        let dpu_bridge = crate::common_bridge_sp::<DashHaScopeTable>(&runtime.get_swbus_edge());
+        let dpu_state = |state: &DpuDashHaScopeState| {
+            send! { key: DpuDashHaScopeState::table_name(), data: {"key": DpuDashHaScopeState::table_name(), "operation": "Set",
+            "field_values": serde_json::to_value(to_field_values(state).unwrap()).unwrap() }}
+        };
+
+        #[rustfmt::skip]
+        let scenario = Scenario::default()
+            .track::<NpuDashHaScopeState>(&scope_id_in_state, &["local_ha_state", "local_target_asic_ha_state", "local_acked_term", "local_vdpu_up_bfd_sessions_v4"])
+            .at(Duration::ZERO, "setup", [
+                send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope_id, "operation": "Set",
+                        "field_values": {"version": "1", "disable": "false", "desired_ha_state": "active", "approved_pending_operation_ids": "" }},
+                        addr: config_bridge },
+                recv! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, &scope_id), data: { "active": true }, addr: runtime.sp(VDpuActor::name(), &vdpu0_id) },
+                recv! { key: ActorRegistration::msg_key(RegistrationType::HaSetState, &scope_id), data: { "active": true }, addr: runtime.sp(HaSetActor::name(), &ha_set_id) },
+                send! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": true, "ha_set": &ha_set_obj }, addr: runtime.sp(HaSetActor::name(), &ha_set_id) },
+                send! { key: VDpuActorState::msg_key(&vdpu0_id), data: vdpu0_up, addr: runtime.sp("vdpu", &vdpu0_id) },
+                recv! { key: &ha_set_id, data: { "key": &ha_set_id, "operation": "Set",
+                        "field_values": {"version": "1", "ha_role": "active", "disable": "false",  "activate_role_requested": "false", "flow_reconcile_requested": "false" }},
+                        addr: dpu_bridge },
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_base },
+            ])
+            .at(Duration::ZERO, "dpu active", [
+                dpu_state(&dpu_active),
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_active },
+            ])
+            .at(Duration::from_millis(100), "bfd down", [
+                send! { key: VDpuActorState::msg_key(&vdpu0_id), data: vdpu0_bfd_down, addr: runtime.sp("vdpu", &vdpu0_id) },
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_bfd_down },
+            ])
+            // the peer is lost, the local state doesn't change until the DPU acts on it
+            .at(Duration::from_secs(2), "peer lost", [
+                send! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": false, "ha_set": &ha_set_obj }, addr: runtime.sp(HaSetActor::name(), &ha_set_id) },
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_bfd_down },
+            ])
+            .at(Duration::from_millis(2200), "dpu standalone", [
+                dpu_state(&dpu_standalone),
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_standalone },
+            ])
+            .at(Duration::from_secs(5), "config standalone", [
+                send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope_id, "operation": "Set",
+                        "field_values": {"version": "2", "disable": "false", "desired_ha_state": "standalone", "approved_pending_operation_ids": "" }},
+                        addr: config_bridge },
+                recv! { key: &ha_set_id, data: { "key": &ha_set_id, "operation": "Set",
+                        "field_values": {"version": "2", "ha_role": "standalone", "disable": "false",  "activate_role_requested": "false", "flow_reconcile_requested": "false" }},
+                        addr: dpu_bridge },
+                chkdb! { type: NpuDashHaScopeState, key: &scope_id_in_state, data: npu_state_final },
+            ]);
+
+        let report = scenario
+            .run(&runtime, runtime.sp(HaScopeActor::name(), &scope_id))
+            .await;
+
+        let bfd_up = "10.0.0.0,10.0.1.0";
+        assert_eq!(
+            report.trajectory(),
+            vec![
+                ("setup", vec!["none", "none", "none", bfd_up]),
+                ("dpu active", vec!["active", "active", "1", bfd_up]),
+                ("bfd down", vec!["active", "active", "1", ""]),
+                ("peer lost", vec!["active", "active", "1", ""]),
+                ("dpu standalone", vec!["standalone", "active", "2", ""]),
+                ("config standalone", vec!["standalone", "standalone", "2", ""]),
+            ]
+        );
+        // the DPU takes over 200ms after the peer is lost, the rest is hamgrd
+        let failover_time = report.time_between("peer lost", "dpu standalone");
+        assert!(
+            failover_time < Duration::from_secs(1),
+            "failover took {failover_time:?}"
+        );
+
+        #[rustfmt::skip]
+        let commands = [
+            send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope_id, "operation": "Del",
+                    "field_values": {"version": "2", "disable": "false", "desired_ha_state": "standalone", "approved_pending_operation_ids": "" }},
+                    addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) },
+        ];
+        test::run_commands(&runtime, runtime.sp(HaScopeActor::name(), &scope_id), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(3), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    fn config_cmd(runtime: &ActorRuntime, scope_id: &str, version: &str, desired_ha_state: &str) -> Command {
+        send! { key: DashHaScopeConfigTable::table_name(), data: { "key": scope_id, "operation": "Set",
+        "field_values": {"version": version, "disable": "false", "desired_ha_state": desired_ha_state, "approved_pending_operation_ids": "" }},
+        addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) }
+    }
+
+    fn dpu_table_cmd(runtime: &ActorRuntime, ha_set_id: &str, version: &str, ha_role: &str) -> Command {
+        recv! { key: ha_set_id, data: { "key": ha_set_id, "operation": "Set",
+        "field_values": {"version": version, "ha_role": ha_role, "disable": "false",  "activate_role_requested": "false", "flow_reconcile_requested": "false" }},
+        addr: crate::common_bridge_sp::<DashHaScopeTable>(&runtime.get_swbus_edge()) }
+    }
+
+    fn dpu_state_cmd(state: &DpuDashHaScopeState) -> Command {
+        send! { key: DpuDashHaScopeState::table_name(), data: {"key": DpuDashHaScopeState::table_name(), "operation": "Set",
+        "field_values": serde_json::to_value(to_field_values(state).unwrap()).unwrap() }}
+    }
+
+    fn switchover_cmd(peer_sp: &ServicePath, peer_scope_id: &str, switchover_id: &str, step: &str) -> Command {
+        send! { key: PlannedSwitchover::msg_key(peer_scope_id), data: { "switchover_id": switchover_id, "step": step }, addr: peer_sp.clone() }
+    }
+
+    fn recv_switchover_cmd(peer_sp: &ServicePath, scope_id: &str, switchover_id: &str, step: &str) -> Command {
+        recv! { key: PlannedSwitchover::msg_key(scope_id), data: { "switchover_id": switchover_id, "step": step }, addr: peer_sp }
+    }
+
+    /// Bring up the scope with its HA set and DPU, the HA set knowing the peer vDPU.
+    fn setup_with_peer_cmds(
+        runtime: &ActorRuntime,
+        scope: &ScopeSetup,
+        config: Command,
+        peer: &HaSetPeer,
+    ) -> Vec<Command> {
+        #[rustfmt::skip]
+        let commands = vec![
+            config,
+            recv! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, &scope.scope_id), data: { "active": true }, addr: runtime.sp(VDpuActor::name(), &scope.vdpu_id) },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::HaSetState, &scope.scope_id), data: { "active": true }, addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+            send! { key: HaSetActorState::msg_key(&scope.ha_set_id), data: { "up": true, "ha_set": &scope.ha_set_obj, "peer": peer }, addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+            send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: &scope.vdpu_state, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+            dpu_table_cmd(runtime, &scope.ha_set_id, "1", scope.desired_ha_state),
+            dpu_state_cmd(&scope.dpu_state),
+        ];
+        commands
+    }
+
+    /// NPU DASH_HA_SCOPE_STATE of `scope` with DPU `dpu_state`, asked to be `target_ha_state` by switchover
+    /// `switchover_id` in `switchover_state`.
+    fn switchover_npu_state(
+        scope: &ScopeSetup,
+        dpu_state: &DpuDashHaScopeState,
+        target_ha_state: &str,
+        switchover_id: &str,
+        switchover_state: &str,
+        switchover_phase: Option<&str>,
+    ) -> FieldValues {
+        let mut state = make_npu_ha_scope_state(&scope.vdpu_state, &scope.ha_set_obj);
+        update_npu_ha_scope_state_by_dpu_scope_state(&mut state, dpu_state, target_ha_state);
+        state.switchover_id = Some(switchover_id.to_string());
+        state.switchover_state = Some(switchover_state.to_string());
+        state.switchover_phase = switchover_phase.map(str::to_string);
+        to_field_values(&state).unwrap()
+    }
+
+    const SWITCHOVER_TIMES: &str = "switchover_start_time_in_ms,switchover_end_time_in_ms";
+
+    async fn stop_scope(runtime: &ActorRuntime, scope: &ScopeSetup, handle: JoinHandle<()>) {
+        #[rustfmt::skip]
+        let commands = [
+            send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope.scope_id, "operation": "Del",
+                    "field_values": {"version": "1", "disable": "false", "desired_ha_state": scope.desired_ha_state, "approved_pending_operation_ids": "" }},
+                    addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) },
+        ];
+        test::run_commands(runtime, runtime.sp(HaScopeActor::name(), &scope.scope_id), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(3), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    #[tokio::test]
+    async fn ha_scope_planned_switchover() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        // switch 0 hands the active role over to switch 1
+        let scope = ScopeSetup::new(0, "active");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+
+        let dpu_active = &scope.dpu_state;
+        let dpu_switching = make_dpu_ha_scope_state("switching_to_standby");
+        let mut dpu_standby = make_dpu_ha_scope_state("standby");
+        dpu_standby.ha_term = "2".to_string();
+        let id = "switchover0";
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "active"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            // draining: the DPU drains to the peer, which is told to do the same
+            send! { key: PlannedSwitchoverRequest::msg_key(), data: { "switchover_id": id } },
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "drain"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "switching_to_standby"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, dpu_active, "switching_to_standby", id, "in_progress", Some("draining")),
+                    exclude: SWITCHOVER_TIMES },
+
+            // the phase ends once both the DPU and the peer are drained
+            dpu_state_cmd(&dpu_switching),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &dpu_switching, "switching_to_standby", id, "in_progress", Some("draining")),
+                    exclude: SWITCHOVER_TIMES },
+            switchover_cmd(&peer_sp, &peer_scope_id, id, "drained"),
+
+            // flipping: the DPU goes standby and the peer active
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "flip"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "standby"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &dpu_switching, "standby", id, "in_progress", Some("flipping")),
+                    exclude: SWITCHOVER_TIMES },
+            dpu_state_cmd(&dpu_standby),
+            switchover_cmd(&peer_sp, &peer_scope_id, id, "done"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &dpu_standby, "standby", id, "completed", None),
+                    exclude: SWITCHOVER_TIMES },
+            chkdb! { type: DashHaScopeSummaryTable, key: &scope.scope_id_in_state,
+                    data: { "ha_role": "standby", "peer_state": "reachable", "peer_ha_role": "",
+                            "last_switchover_cause": "planned switchover switchover0", "switchover_count": "1" },
+                    exclude: "ha_role_start_time_in_ms,last_switchover_time_in_ms,update_count,last_updated_time_in_ms" },
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn ha_scope_follows_planned_switchover() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(1, "10.0.1.0", "10::").await;
+
+        // switch 1 takes the active role over from switch 0
+        let scope = ScopeSetup::new(1, "standby");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu0-0".to_string(),
+            npu_ip: "10.0.0.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+
+        let dpu_switching = make_dpu_ha_scope_state("switching_to_active");
+        let mut dpu_active = make_dpu_ha_scope_state("active");
+        dpu_active.ha_term = "2".to_string();
+        let id = "switchover0";
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "standby"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            switchover_cmd(&peer_sp, &peer_scope_id, id, "drain"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "switching_to_active"),
+            dpu_state_cmd(&dpu_switching),
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "drained"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &dpu_switching, "switching_to_active", id, "in_progress", Some("draining")),
+                    exclude: SWITCHOVER_TIMES },
+
+            switchover_cmd(&peer_sp, &peer_scope_id, id, "flip"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "active"),
+            dpu_state_cmd(&dpu_active),
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "done"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &dpu_active, "active", id, "completed", None),
+                    exclude: SWITCHOVER_TIMES },
+
+            // the active role sticks until the controller catches up
+            config_cmd(&runtime, &scope.scope_id, "2", "active"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "2", "active"),
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn ha_scope_planned_switchover_rolls_back_on_peer_timeout() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        let scope = ScopeSetup::new(0, "active");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+        let id = "switchover0";
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "active"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            // requested in the config, the peer never answers
+            send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope.scope_id, "operation": "Set",
+                    "field_values": {"version": "2", "disable": "false", "desired_ha_state": "active", "approved_pending_operation_ids": "",
+                                     "planned_switchover_id": id, "planned_switchover_timeout_in_ms": "100" }},
+                    addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "2", "active"),
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "drain"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "2", "switching_to_standby"),
+
+            // rolled back to active, telling the peer to do the same
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "abort"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "2", "active"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &scope.dpu_state, "active", id, "failed", None),
+                    exclude: SWITCHOVER_TIMES },
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn ha_scope_planned_switchover_rolls_back_when_peer_hamgrd_is_unreachable() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        let scope = ScopeSetup::new(0, "active");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+        let id = "switchover0";
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "active"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            send! { key: PlannedSwitchoverRequest::msg_key(), data: { "switchover_id": id } },
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, id, "drain"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "switching_to_standby"),
+
+            // the peer hamgrd stops answering pings, the DPU is rolled back to active without waiting for it
+            send! { key: HaSetActorState::msg_key(&scope.ha_set_id), data: { "up": true, "ha_set": &scope.ha_set_obj, "peer": &peer, "peer_unreachable": true },
+                    addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "active"),
+            chkdb! { type: NpuDashHaScopeState, key: &scope.scope_id_in_state,
+                    data: switchover_npu_state(&scope, &scope.dpu_state, "active", id, "failed", None),
+                    exclude: SWITCHOVER_TIMES },
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn ha_scope_split_brain_demotes_the_loser() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        let scope = ScopeSetup::new(0, "active");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let alarm_key = format!("{}|split_brain", scope.scope_id_in_state);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+        let haset_cmd = |peer_unreachable: bool| {
+            send! { key: HaSetActorState::msg_key(&scope.ha_set_id), data: { "up": true, "ha_set": &scope.ha_set_obj, "peer": &peer, "peer_unreachable": peer_unreachable },
+            addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) }
+        };
+
+        // the peer DPU is the preferred winner
+        #[rustfmt::skip]
+        let config = send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope.scope_id, "operation": "Set",
+                "field_values": {"version": "1", "disable": "false", "desired_ha_state": "active", "approved_pending_operation_ids": "", "preferred_winner": &peer.vdpu_id }},
+                addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) };
+        let mut commands = setup_with_peer_cmds(&runtime, &scope, config, &peer);
+        #[rustfmt::skip]
+        commands.extend([
+            // the partition heals, both sides report their role
+            haset_cmd(true),
+            haset_cmd(false),
+            recv! { key: PeerHaRole::msg_key(&scope.scope_id), data: { "ha_role": "active" }, addr: peer_sp },
+            send! { key: PeerHaRole::msg_key(&peer_scope_id), data: { "ha_role": "active" }, addr: peer_sp.clone() },
+            recv! { key: PeerHaRole::msg_key(&scope.scope_id), data: { "ha_role": "active" }, addr: peer_sp },
+
+            // both DPUs are active, the local DPU loses
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "standby"),
+            chkdb! { type: DashHaAlarmTable, key: &alarm_key,
+                    data: { "alarm_type": "split_brain", "status": "set", "description": "both DPUs are active, vdpu1-0 stays active" },
+                    exclude: "severity,set_time_in_ms" },
+
+            // the DPU confirms the demotion, which clears the split brain
+            dpu_state_cmd(&make_dpu_ha_scope_state("standby")),
+            recv! { key: PeerHaRole::msg_key(&scope.scope_id), data: { "ha_role": "standby" }, addr: peer_sp },
+            chkdb! { type: DashHaAlarmTable, key: &alarm_key,
+                    data: { "alarm_type": "split_brain", "status": "clear", "description": "brainsplit recovered" },
+                    exclude: "severity,set_time_in_ms,clear_time_in_ms" },
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn ha_scope_in_maintenance_hands_over_and_stays_standby() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        let scope = ScopeSetup::new(0, "active");
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let peer_scope_id = format!("{}:{}", peer.vdpu_id, scope.ha_set_id);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let peer_sp = peer.actor_sp(&aut, HaScopeActor::name(), &peer_scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+        let mut vdpu_maintenance = serde_json::to_value(&scope.vdpu_state).unwrap();
+        vdpu_maintenance["dpu"]["maintenance"] = true.into();
+        let id = format!("maintenance-{}-1", scope.vdpu_id);
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "active"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            // the flag starts a planned switchover to the peer
+            send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: &vdpu_maintenance, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+            recv_switchover_cmd(&peer_sp, &scope.scope_id, &id, "drain"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "switching_to_standby"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "switching_to_standby"),
+
+            // the peer can't take over, the DPU still doesn't stay active
+            switchover_cmd(&peer_sp, &peer_scope_id, &id, "abort"),
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "standby"),
+
+            // once the flag is cleared, the DPU takes desired_ha_state again
+            send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: &scope.vdpu_state, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "active"),
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    #[tokio::test]
+    async fn switch_owned_dpu_scope_role_follows_bfd_and_peer() {
+        sonic_common::log::init_logger_for_test();
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+
+        let mut scope = ScopeSetup::new(0, "active");
+        scope.ha_set_obj.owner = Some("switch".to_string());
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let dpu = make_local_dpu_actor_state(
+            0,
+            0,
+            true,
+            Some(make_dpu_pmon_state(true)),
+            Some(make_dpu_bfd_state(vec![], vec![])),
+        );
+        let (_, vdpu_bfd_down) = make_vdpu_actor_state(true, &dpu);
+        let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let handle = runtime.spawn(
+            HaScopeActor::new(scope.scope_id.clone()).unwrap(),
+            HaScopeActor::name(),
+            &scope.scope_id,
+        );
+
+        let mut commands = setup_with_peer_cmds(
+            &runtime,
+            &scope,
+            config_cmd(&runtime, &scope.scope_id, "1", "active"),
+            &peer,
+        );
+        #[rustfmt::skip]
+        commands.extend([
+            // no NPU reaches the DPU anymore, the traffic moves to the peer
+            send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: &vdpu_bfd_down, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "standby"),
+            send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: &scope.vdpu_state, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "active"),
+
+            // the peer is lost, the DPU runs on its own
+            send! { key: HaSetActorState::msg_key(&scope.ha_set_id), data: { "up": true, "ha_set": &scope.ha_set_obj, "peer": &peer, "peer_unreachable": true },
+                    addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+            dpu_table_cmd(&runtime, &scope.ha_set_id, "1", "standalone"),
+        ]);
+        test::run_commands(&runtime, aut, &commands).await;
+
+        stop_scope(&runtime, &scope, handle).await;
+    }
+
+    struct ScopeSetup {
+        scope_id: String,
+        scope_id_in_state: String,
+        vdpu_id: String,
+        vdpu_state: VDpuActorState,
+        ha_set_id: String,
+        ha_set_obj: DashHaSetTable,
+        desired_ha_state: &'static str,
+        dpu_state: DpuDashHaScopeState,
+    }
+
+    impl ScopeSetup {
+        fn new(switch: u16, desired_ha_state: &'static str) -> Self {
+            let bfd_up = make_dpu_bfd_state(vec!["10.0.0.0", "10.0.1.0"], Vec::new());
+            let dpu = make_local_dpu_actor_state(switch, 0, true, Some(make_dpu_pmon_state(true)), Some(bfd_up));
+            let (vdpu_id, vdpu_state) = make_vdpu_actor_state(true, &dpu);
+            let (ha_set_id, ha_set_obj) = make_dpu_scope_ha_set_obj(switch, 0);
+            ScopeSetup {
+                scope_id: format!("{vdpu_id}:{ha_set_id}"),
+                scope_id_in_state: format!("{vdpu_id}|{ha_set_id}"),
+                vdpu_id,
+                vdpu_state,
+                ha_set_id,
+                ha_set_obj,
+                desired_ha_state,
+                dpu_state: make_dpu_ha_scope_state(desired_ha_state),
+            }
+        }
+    }
+
+    /// The DPU scopes of both switches of an HA set, driven by the same hamgrd so that what they
+    /// output can be checked together.
+    struct HaSetModel {
+        scopes: Vec<ScopeSetup>,
+        redis: Option<Redis>,
+        handles: Vec<JoinHandle<()>>,
+        // what the actors program to their DPU's DASH_HA_SCOPE_TABLE
+        dpu_tables: Option<Outputs>,
+    }
+
+    impl Model for HaSetModel {
+        async fn start(&mut self) -> ActorRuntime {
+            self.redis = Some(Redis::start_config_db());
+            let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+            let dpu_tables = crate::common_bridge_sp::<DashHaScopeTable>(&runtime.get_swbus_edge());
+            self.dpu_tables = Some(Outputs::record(&runtime, dpu_tables));
+            for scope in &self.scopes {
+                let actor = HaScopeActor::new(scope.scope_id.clone()).unwrap();
+                self.handles
+                    .push(runtime.spawn(actor, HaScopeActor::name(), &scope.scope_id));
+
+                let vdpu_state = &scope.vdpu_state;
+                #[rustfmt::skip]
+                let commands = [
+                    config_cmd(&runtime, &scope.scope_id, "1", scope.desired_ha_state),
+                    recv! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, &scope.scope_id), data: { "active": true }, addr: runtime.sp(VDpuActor::name(), &scope.vdpu_id) },
+                    recv! { key: ActorRegistration::msg_key(RegistrationType::HaSetState, &scope.scope_id), data: { "active": true }, addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+                    send! { key: HaSetActorState::msg_key(&scope.ha_set_id), data: { "up": true, "ha_set": &scope.ha_set_obj }, addr: runtime.sp(HaSetActor::name(), &scope.ha_set_id) },
+                    send! { key: VDpuActorState::msg_key(&scope.vdpu_id), data: vdpu_state, addr: runtime.sp("vdpu", &scope.vdpu_id) },
+                    dpu_state_cmd(&scope.dpu_state),
+                ];
+                let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+                test::run_commands(&runtime, aut.clone(), &commands).await;
+                explore::settle(&runtime, &aut).await;
+            }
+            runtime
+        }
+
+        /// - never dual-active: the actors never drive both DPUs to the active role at once. A DPU
+        ///   that lost its peer is driven standalone on purpose, which doesn't count.
+        /// - the target role an actor reports in its NPU DASH_HA_SCOPE_STATE is the role it last
+        ///   programmed to its DPU
+        async fn check(&mut self, runtime: &ActorRuntime) -> Result<(), String> {
+            let dpu_tables = self.dpu_tables.as_ref().unwrap();
+            let db = crate::db_for_table::<NpuDashHaScopeState>().await.unwrap();
+            let table = Table::new(db, NpuDashHaScopeState::table_name()).unwrap();
+
+            let mut programmed = Vec::new();
+            for scope in &self.scopes {
+                let aut = runtime.sp(HaScopeActor::name(), &scope.scope_id);
+                let Some(last) = dpu_tables.last(&aut).await else {
+                    return Err(format!("{} never programmed its DPU", scope.scope_id));
+                };
+                let role = last["field_values"]["ha_role"].as_str().unwrap_or("none").to_string();
+
+                let state: NpuDashHaScopeState = swss_serde::from_table(&table, &scope.scope_id_in_state)
+                    .map_err(|e| format!("cannot read {}: {e}", scope.scope_id_in_state))?;
+                if let Some(target) = state.local_target_asic_ha_state {
+                    if target != role {
+                        return Err(format!(
+                            "{} reports target {target} but programmed {role}",
+                            scope.scope_id
+                        ));
+                    }
+                }
+                programmed.push(role);
+            }
+
+            if programmed.iter().all(|role| role == "active") {
+                return Err(format!("dual-active, programmed roles {programmed:?}"));
+            }
+            Ok(())
+        }
+
+        async fn stop(&mut self, runtime: ActorRuntime) {
+            for scope in &self.scopes {
+                #[rustfmt::skip]
+                let commands = [
+                    send! { key: DashHaScopeConfigTable::table_name(), data: { "key": &scope.scope_id, "operation": "Del",
+                            "field_values": {"version": "1", "disable": "false", "desired_ha_state": scope.desired_ha_state, "approved_pending_operation_ids": "" }},
+                            addr: crate::common_bridge_sp::<DashHaScopeConfigTable>(&runtime.get_swbus_edge()) },
+                ];
+                test::run_commands(&runtime, runtime.sp(HaScopeActor::name(), &scope.scope_id), &commands).await;
+            }
+            for handle in self.handles.drain(..) {
+                if tokio::time::timeout(Duration::from_secs(3), handle).await.is_err() {
+                    panic!("timeout waiting for actor to terminate");
+                }
+            }
+            self.dpu_tables = None;
+            self.redis = None;
+        }
+    }
+
+    // Runs a spread of the interleavings, all of them with: make test-explore
+    #[tokio::test]
+    async fn ha_scope_switchover_interleavings() {
+        sonic_common::log::init_logger_for_test();
+        // the controller hands the active role of switch 0 over to switch 1
+        let mut model = HaSetModel {
+            scopes: vec![ScopeSetup::new(0, "active"), ScopeSetup::new(1, "standby")],
+            redis: None,
+            handles: Vec::new(),
+            dpu_tables: None,
+        };
+
+        // addresses only depend on the edge service path, which is the same on every runtime
+        let runtime = test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let aut = |scope: &ScopeSetup| runtime.sp(HaScopeActor::name(), &scope.scope_id);
+        let [scope0, scope1] = &model.scopes[..] else {
+            unreachable!()
+        };
+
+        let mut dpu1_activation_pending = make_dpu_ha_scope_state("standby");
+        dpu1_activation_pending.activate_role_pending = true;
+        let mut dpu0_standby = make_dpu_ha_scope_state("standby");
+        dpu0_standby.ha_term = "2".to_string();
+        let mut dpu1_active = make_dpu_ha_scope_state("active");
+        dpu1_active.ha_term = "2".to_string();
+        let dpu0 = make_local_dpu_actor_state(
+            0,
+            0,
+            true,
+            Some(make_dpu_pmon_state(true)),
+            Some(make_dpu_bfd_state(vec![], vec![])),
+        );
+        let (_, vdpu0_bfd_down) = make_vdpu_actor_state(true, &dpu0);
+
+        #[rustfmt::skip]
+        let exploration = Exploration::default()
+            // the DPUs agree on the order of the switchover among themselves
+            .thread([
+                Event::new("dpu1 activation pending", aut(scope1), [dpu_state_cmd(&dpu1_activation_pending)]),
+                Event::new("dpu0 standby", aut(scope0), [dpu_state_cmd(&dpu0_standby)]),
+                Event::new("dpu1 active", aut(scope1), [dpu_state_cmd(&dpu1_active)]),
+            ])
+            .thread([Event::new("dpu0 bfd down", aut(scope0), [
+                send! { key: VDpuActorState::msg_key(&scope0.vdpu_id), data: vdpu0_bfd_down, addr: runtime.sp("vdpu", &scope0.vdpu_id) },
+            ])])
+            .thread([Event::new("peer lost", aut(scope1), [
+                send! { key: HaSetActorState::msg_key(&scope1.ha_set_id), data: { "up": false, "ha_set": &scope1.ha_set_obj }, addr: runtime.sp(HaSetActor::name(), &scope1.ha_set_id) },
+            ])])
+            // the controller demotes switch 0 before it promotes switch 1
+            .thread([
+                Event::new("config dpu0 standby", aut(scope0), [config_cmd(&runtime, &scope0.scope_id, "2", "standby")]),
+                Event::new("config dpu1 active", aut(scope1), [config_cmd(&runtime, &scope1.scope_id, "2", "active")]),
+            ]);
+
+        let report = exploration.run(&mut model).await;
+        let runs = match std::env::var_os("HAMGRD_EXPLORE_ALL") {
+            Some(_) => 420,
+            None => explore::DEFAULT_MAX_RUNS,
+        };
+        assert_eq!(report.runs, runs);
+        report.assert_no_violation();
+    }
 }
@@ -1,14 +1,18 @@
 use crate::actors::vdpu::VDpuActor;
 use crate::actors::{spawn_consumer_bridge_for_actor, DbBasedActor};
 use crate::db_structs::*;
-use crate::ha_actor_messages::{ActorRegistration, HaSetActorState, RegistrationType, VDpuActorState};
+use crate::ha_actor_messages::{
+    ActorRegistration, HaSetActorState, HaSetPeer, PeerHamgrdState, PeerMonitorRegistration, RegistrationType,
+    StateSequencer, VDpuActorState,
+};
 use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use swbus_actor::{
     state::{incoming::Incoming, internal::Internal, outgoing::Outgoing},
     Actor, ActorMessage, Context, State,
 };
-use swss_common::Table;
-use swss_common::{KeyOpFieldValues, KeyOperation, SonicDbTable};
+use swss_common::{FieldValues, KeyOpFieldValues, KeyOperation, SonicDbTable};
 use swss_common_bridge::consumer::ConsumerBridge;
 use tracing::{debug, error, info, instrument};
 
@@ -16,6 +20,12 @@ pub struct HaSetActor {
     id: String,
     dash_ha_set_config: Option<DashHaSetConfigTable>,
     bridges: Vec<ConsumerBridge>,
+    /// Numbers the HA set state updates sent to registered actors
+    state_seq: StateSequencer,
+    /// The peer registered to the peer monitor
+    monitored_peer: Option<HaSetPeer>,
+    /// BFD sessions programmed to the local DPU, by key
+    bfd_sessions: BTreeMap<String, BfdSessionTable>,
 }
 
 impl DbBasedActor for HaSetActor {
@@ -24,6 +34,9 @@ impl DbBasedActor for HaSetActor {
             id: key,
             dash_ha_set_config: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            monitored_peer: None,
+            bfd_sessions: BTreeMap::new(),
         };
         Ok(actor)
     }
@@ -38,6 +51,7 @@ impl DbBasedActor for HaSetActor {
 }
 
 struct VDpuStateExt {
+    vdpu_id: String,
     vdpu: VDpuActorState,
     is_primary: bool,
 }
@@ -56,7 +70,7 @@ impl HaSetActor {
             }
         };
 
-        match swss_serde::from_field_values(&kfv.field_values) {
+        match parse_entry(&kfv.key, &kfv.field_values) {
             Ok(state) => Some(state),
             Err(e) => {
                 error!("Failed to deserialize DASH_HA_GLOBAL_CONFIG from field values: {}", e);
@@ -89,6 +103,13 @@ impl HaSetActor {
             }
         };
 
+        // an HA set is DPU scope unless it says otherwise
+        let scope = dash_ha_set_config.scope.as_deref().unwrap_or("dpu");
+        if !matches!(scope, "dpu" | "eni") {
+            error!("Unknown scope {scope} of HA set {}. Skip dash-ha-set update", self.id);
+            return Ok(None);
+        }
+
         let Some(global_cfg) = Self::get_dash_global_config(incoming) else {
             return Ok(None);
         };
@@ -98,10 +119,10 @@ impl HaSetActor {
             vip_v4: dash_ha_set_config.vip_v4.clone(),
             vip_v6: dash_ha_set_config.vip_v6.clone(),
             owner: dash_ha_set_config.owner.clone(),
-            scope: dash_ha_set_config.scope.clone(),
-            local_npu_ip: local_vdpu.dpu.npu_ipv4.clone(),
-            local_ip: local_vdpu.dpu.pa_ipv4.clone(),
-            peer_ip: remote_vdpu.dpu.pa_ipv4.clone(),
+            scope: Some(scope.to_string()),
+            local_npu_ip: local_vdpu.dpu.npu_ip(),
+            local_ip: local_vdpu.dpu.pa_ip(),
+            peer_ip: remote_vdpu.dpu.pa_ip(),
             cp_data_channel_port: global_cfg.cp_data_channel_port,
             dp_channel_dst_port: global_cfg.dp_channel_dst_port,
             dp_channel_src_port_min: global_cfg.dp_channel_src_port_min,
@@ -112,8 +133,46 @@ impl HaSetActor {
         Ok(Some(dash_ha_set))
     }
 
+    /// The first vDPU of the HA set that is not managed by this hamgrd.
+    fn peer(vdpus: &[VDpuStateExt]) -> Option<HaSetPeer> {
+        let peer = vdpus.iter().find(|vdpu_ext| !vdpu_ext.vdpu.dpu.is_managed)?;
+        Some(HaSetPeer {
+            vdpu_id: peer.vdpu_id.clone(),
+            npu_ip: peer.vdpu.dpu.npu_ip(),
+            dpu_id: peer.vdpu.dpu.dpu_id,
+        })
+    }
+
+    /// Whether the peer monitor found the hamgrd of the peer unreachable.
+    fn peer_unreachable(incoming: &Incoming) -> bool {
+        incoming
+            .get(PeerHamgrdState::msg_key())
+            .ok()
+            .and_then(|msg| msg.deserialize_data::<PeerHamgrdState>().ok())
+            .is_some_and(|state| !state.reachable)
+    }
+
+    fn ha_set_state_msg(
+        &mut self,
+        dash_ha_set: DashHaSetTable,
+        peer: Option<HaSetPeer>,
+        incoming: &Incoming,
+    ) -> ActorMessage {
+        let peer_unreachable = peer.is_some() && Self::peer_unreachable(incoming);
+        self.state_seq
+            .stamp(HaSetActorState::new_actor_msg(true, &self.id, dash_ha_set, peer, peer_unreachable).unwrap())
+    }
+
+    /// Have the peer monitor ping the hamgrd of `peer`, or stop pinging with `None`.
+    fn register_to_peer_monitor(&mut self, outgoing: &mut Outgoing, peer: Option<HaSetPeer>) -> Result<()> {
+        let msg = PeerMonitorRegistration::new_actor_msg(&self.id, peer.clone())?;
+        outgoing.send(outgoing.from_my_sp(crate::peer_monitor::NAME, "0"), msg);
+        self.monitored_peer = peer;
+        Ok(())
+    }
+
     fn update_dash_ha_set_table(
-        &self,
+        &mut self,
         vdpus: &[VDpuStateExt],
         incoming: &Incoming,
         outgoing: &mut Outgoing,
@@ -131,7 +190,12 @@ impl HaSetActor {
         let msg = ActorMessage::new(self.id.clone(), &kfv)?;
         outgoing.send(outgoing.common_bridge_sp::<DashHaSetTable>(), msg);
 
-        let msg = HaSetActorState::new_actor_msg(true, &self.id, dash_ha_set).unwrap();
+        let peer = Self::peer(vdpus);
+        if peer != self.monitored_peer {
+            self.register_to_peer_monitor(outgoing, peer.clone())?;
+        }
+
+        let msg = self.ha_set_state_msg(dash_ha_set, peer, incoming);
         let peer_actors = ActorRegistration::get_registered_actors(incoming, RegistrationType::HaSetState);
         for actor_sp in peer_actors {
             outgoing.send(actor_sp, msg.clone());
@@ -139,6 +203,89 @@ impl HaSetActor {
         Ok(())
     }
 
+    /// The BFD sessions from the local DPU to the DPU of the peer vDPU, one per address family both of
+    /// them have a PA address of, by key. Both ends of a session compute it the same way: they agree on
+    /// the discriminators, and the end with the lower address initiates while the other responds.
+    fn bfd_sessions(vdpus: &[VDpuStateExt], global_cfg: &DashHaGlobalConfig) -> BTreeMap<String, BfdSessionTable> {
+        let local = vdpus.iter().find(|vdpu_ext| vdpu_ext.vdpu.dpu.is_managed);
+        let peer = vdpus.iter().find(|vdpu_ext| !vdpu_ext.vdpu.dpu.is_managed);
+        let (Some(local), Some(peer)) = (local, peer) else {
+            return BTreeMap::new();
+        };
+        let (local, peer) = (&local.vdpu.dpu, &peer.vdpu.dpu);
+        // the peer is behind another NPU unless both DPUs are in the same switch
+        let multihop = local.npu_ip() != peer.npu_ip();
+
+        [(&local.pa_ipv4, &peer.pa_ipv4), (&local.pa_ipv6, &peer.pa_ipv6)]
+            .into_iter()
+            .filter_map(|(local_addr, peer_addr)| {
+                let local_addr: IpAddr = local_addr.as_deref()?.parse().ok()?;
+                let peer_addr: IpAddr = peer_addr.as_deref()?.parse().ok()?;
+                let (local_str, peer_str) = (local_addr.to_string(), peer_addr.to_string());
+                let session = BfdSessionTable {
+                    tx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
+                    rx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
+                    multiplier: global_cfg.dpu_bfd_probe_multiplier,
+                    multihop,
+                    shutdown: false,
+                    session_type: (local_addr > peer_addr).then(|| "passive".to_string()),
+                    local_discriminator: Some(BfdSessionTable::discriminator(&local_str, &peer_str)),
+                    remote_discriminator: Some(BfdSessionTable::discriminator(&peer_str, &local_str)),
+                    local_addr: local_str,
+                };
+                Some((BfdSessionTable::key(&peer_str), session))
+            })
+            .collect()
+    }
+
+    /// Program `sessions` to the local DPU: delete the sessions no longer needed, e.g. because the address
+    /// of the peer changed, and set the new or changed ones.
+    fn program_bfd_sessions(
+        &mut self,
+        sessions: BTreeMap<String, BfdSessionTable>,
+        outgoing: &mut Outgoing,
+    ) -> Result<()> {
+        let stale = self.bfd_sessions.keys().filter(|key| !sessions.contains_key(*key));
+        for key in stale {
+            info!("Deleting BFD session {key} of HA set {}", self.id);
+            let kfv = KeyOpFieldValues {
+                key: key.clone(),
+                operation: KeyOperation::Del,
+                field_values: FieldValues::new(),
+            };
+            let msg = ActorMessage::new(self.id.clone(), &kfv)?;
+            outgoing.send(outgoing.common_bridge_sp::<BfdSessionTable>(), msg);
+        }
+
+        for (key, session) in &sessions {
+            if self.bfd_sessions.get(key) == Some(session) {
+                continue;
+            }
+            let fv = swss_serde::to_field_values(session)?;
+            let kfv = KeyOpFieldValues {
+                key: key.clone(),
+                operation: KeyOperation::Set,
+                field_values: fv,
+            };
+            let msg = ActorMessage::new(self.id.clone(), &kfv)?;
+            outgoing.send(outgoing.common_bridge_sp::<BfdSessionTable>(), msg);
+        }
+        self.bfd_sessions = sessions;
+        Ok(())
+    }
+
+    fn update_bfd_sessions(
+        &mut self,
+        vdpus: &[VDpuStateExt],
+        incoming: &Incoming,
+        outgoing: &mut Outgoing,
+    ) -> Result<()> {
+        let Some(global_cfg) = Self::get_dash_global_config(incoming) else {
+            return Ok(());
+        };
+        self.program_bfd_sessions(Self::bfd_sessions(vdpus, &global_cfg), outgoing)
+    }
+
     async fn update_vnet_route_tunnel_table(
         &self,
         vdpus: &Vec<VDpuStateExt>,
@@ -158,8 +305,7 @@ impl HaSetActor {
         );
 
         if !internal.has_entry(VnetRouteTunnelTable::table_name(), &swss_key) {
-            let db = crate::db_for_table::<VnetRouteTunnelTable>().await?;
-            let table = Table::new_async(db, VnetRouteTunnelTable::table_name()).await?;
+            let table = crate::tables::open_table::<VnetRouteTunnelTable>().await?;
             internal.add(VnetRouteTunnelTable::table_name(), table, swss_key).await;
         }
 
@@ -170,13 +316,13 @@ impl HaSetActor {
 
         for vdpu_ext in vdpus {
             if vdpu_ext.vdpu.dpu.is_managed {
-                // if it is locally managed dpu, use dpu pa_ipv4 as endpoint
-                endpoint.push(vdpu_ext.vdpu.dpu.pa_ipv4.clone());
+                // if it is locally managed dpu, use dpu PA address as endpoint
+                endpoint.push(vdpu_ext.vdpu.dpu.pa_ip());
             } else {
-                endpoint.push(vdpu_ext.vdpu.dpu.npu_ipv4.clone());
+                endpoint.push(vdpu_ext.vdpu.dpu.npu_ip());
             }
 
-            endpoint_monitor.push(vdpu_ext.vdpu.dpu.pa_ipv4.clone());
+            endpoint_monitor.push(vdpu_ext.vdpu.dpu.pa_ip());
             primary.push(vdpu_ext.is_primary.to_string());
             check_directly_connected |= vdpu_ext.vdpu.dpu.is_managed;
         }
@@ -191,9 +337,7 @@ impl HaSetActor {
             tx_monitor_timer: global_cfg.dpu_bfd_probe_interval_in_ms,
             check_directly_connected: Some(check_directly_connected),
         };
-        let fvs = swss_serde::to_field_values(&vnet_route)?;
-
-        internal.get_mut(VnetRouteTunnelTable::table_name()).clone_from(&fvs);
+        update_field_values(internal.get_mut(VnetRouteTunnelTable::table_name()), &vnet_route)?;
         Ok(())
     }
 
@@ -237,10 +381,11 @@ impl HaSetActor {
         let mut seen = std::collections::HashSet::new();
         if let Some(prefered_vdpu_ids) = ha_set_cfg.preferred_vdpu_ids.as_ref() {
             for id in prefered_vdpu_ids.iter().filter(|id| !id.is_empty()) {
-                result.push(
-                    self.get_vdpu(incoming, id)
-                        .map(|vdpu| VDpuStateExt { vdpu, is_primary: true }),
-                );
+                result.push(self.get_vdpu(incoming, id).map(|vdpu| VDpuStateExt {
+                    vdpu_id: id.clone(),
+                    vdpu,
+                    is_primary: true,
+                }));
                 seen.insert(id);
             }
         }
@@ -252,6 +397,7 @@ impl HaSetActor {
             .filter(|id| !id.is_empty() && !seen.contains(id))
         {
             result.push(self.get_vdpu(incoming, id).map(|vdpu| VDpuStateExt {
+                vdpu_id: id.clone(),
                 vdpu,
                 is_primary: false,
             }));
@@ -285,13 +431,17 @@ impl HaSetActor {
         if dpu_kfv.operation == KeyOperation::Del {
             // unregister from the DPU Actor
             self.register_to_vdpu_actor(outgoing, false).await?;
+            if self.monitored_peer.is_some() {
+                self.register_to_peer_monitor(outgoing, None)?;
+            }
+            self.program_bfd_sessions(BTreeMap::new(), outgoing)?;
 
             context.stop();
             return Ok(());
         }
         let first_time = self.dash_ha_set_config.is_none();
 
-        self.dash_ha_set_config = Some(swss_serde::from_field_values(&dpu_kfv.field_values)?);
+        self.dash_ha_set_config = Some(parse_entry(&dpu_kfv.key, &dpu_kfv.field_values)?);
 
         // Subscribe to the DPU Actor for state updates.
         self.register_to_vdpu_actor(outgoing, true).await?;
@@ -313,6 +463,7 @@ impl HaSetActor {
         };
 
         self.update_dash_ha_set_table(&vdpus, incoming, outgoing)?;
+        self.update_bfd_sessions(&vdpus, incoming, outgoing)?;
 
         Ok(())
     }
@@ -322,23 +473,44 @@ impl HaSetActor {
         let Some(vdpus) = self.get_vdpus_if_ready(incoming) else {
             return Ok(());
         };
-        // global config update affects Vxlan tunnel and dash-ha-set in DPU
+        // global config update affects Vxlan tunnel, dash-ha-set and BFD sessions in DPU
         self.update_dash_ha_set_table(&vdpus, incoming, outgoing)?;
+        self.update_bfd_sessions(&vdpus, incoming, outgoing)?;
         self.update_vnet_route_tunnel_table(&vdpus, incoming, internal).await?;
         Ok(())
     }
 
     async fn handle_vdpu_state_update(&mut self, state: &mut State) -> Result<()> {
         let (internal, incoming, outgoing) = state.get_all();
-        // vdpu update affects dash-ha-set in DPU and vxlan tunnel
+        // vdpu update affects dash-ha-set and BFD sessions in DPU and vxlan tunnel
         let Some(vdpus) = self.get_vdpus_if_ready(incoming) else {
             return Ok(());
         };
         self.update_dash_ha_set_table(&vdpus, incoming, outgoing)?;
+        self.update_bfd_sessions(&vdpus, incoming, outgoing)?;
         self.update_vnet_route_tunnel_table(&vdpus, incoming, internal).await?;
         Ok(())
     }
 
+    async fn handle_peer_hamgrd_state(&mut self, state: &mut State) -> Result<()> {
+        let (_, incoming, outgoing) = state.get_all();
+        let PeerHamgrdState { reachable, .. } = incoming.get(PeerHamgrdState::msg_key())?.deserialize_data()?;
+        info!("The hamgrd of peer {:?} is reachable: {reachable}", self.monitored_peer);
+        let Some(vdpus) = self.get_vdpus_if_ready(incoming) else {
+            return Ok(());
+        };
+        let Some(dash_ha_set) = self.prepare_dash_ha_set_table_data(&vdpus, incoming)? else {
+            return Ok(());
+        };
+
+        // The HA scope actors decide on switchovers with the peer reachability in the HA set state
+        let msg = self.ha_set_state_msg(dash_ha_set, Self::peer(&vdpus), incoming);
+        for actor_sp in ActorRegistration::get_registered_actors(incoming, RegistrationType::HaSetState) {
+            outgoing.send(actor_sp, msg.clone());
+        }
+        Ok(())
+    }
+
     async fn handle_haset_state_registration(&mut self, state: &mut State, key: &str) -> Result<()> {
         let (_, incoming, outgoing) = state.get_all();
 
@@ -352,8 +524,7 @@ impl HaSetActor {
                 return Ok(());
             };
 
-            let msg = HaSetActorState::new_actor_msg(true, &self.id, dash_ha_set).unwrap();
-
+            let msg = self.ha_set_state_msg(dash_ha_set, Self::peer(&vdpus), incoming);
             outgoing.send(entry.source.clone(), msg);
         }
         Ok(())
@@ -361,8 +532,25 @@ impl HaSetActor {
 }
 
 impl Actor for HaSetActor {
+    async fn init(&mut self, state: &mut State) -> Result<()> {
+        crate::actors::enable_snapshots(state, Self::name(), &self.id).await;
+        Ok(())
+    }
+
     #[instrument(name="handle_message", level="info", skip_all, fields(actor=format!("ha-set/{}", self.id), key=key))]
     async fn handle_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
+        // The BFD sessions follow the messages of the callback, which are dropped if it fails
+        let bfd_sessions = self.bfd_sessions.clone();
+        let res = self.dispatch_message(state, key, context).await;
+        if res.is_err() {
+            self.bfd_sessions = bfd_sessions;
+        }
+        res
+    }
+}
+
+impl HaSetActor {
+    async fn dispatch_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
         if key == Self::table_name() {
             if let Err(e) = self.handle_dash_ha_set_config_table_message(state, key, context).await {
                 error!("handle_dash_ha_set_config_table_message failed: {e}");
@@ -375,11 +563,15 @@ impl Actor for HaSetActor {
         }
 
         if VDpuActorState::is_my_msg(key) {
+            let (_internal, incoming, outgoing) = state.get_all();
+            ActorRegistration::refresh_on_gap(incoming, outgoing, key, RegistrationType::VDPUState, &self.id)?;
             return self.handle_vdpu_state_update(state).await;
         } else if key == DashHaGlobalConfig::table_name() {
             return self.handle_dash_ha_global_config(state).await;
         } else if ActorRegistration::is_my_msg(key, RegistrationType::HaSetState) {
             return self.handle_haset_state_registration(state, key).await;
+        } else if PeerHamgrdState::is_my_msg(key) {
+            return self.handle_peer_hamgrd_state(state).await;
         }
         Ok(())
     }
@@ -394,9 +586,10 @@ mod test {
             vdpu::VDpuActor,
             DbBasedActor,
         },
-        db_structs::{DashHaGlobalConfig, DashHaSetConfigTable, DashHaSetTable, VnetRouteTunnelTable},
+        db_structs::{BfdSessionTable, DashHaGlobalConfig, DashHaSetConfigTable, DashHaSetTable, VnetRouteTunnelTable},
         ha_actor_messages::*,
     };
+    use std::collections::BTreeMap;
     use std::time::Duration;
     use swss_common::SonicDbTable;
     use swss_common_testing::*;
@@ -426,14 +619,8 @@ mod test {
         let ha_set_obj_fvs = serde_json::to_value(swss_serde::to_field_values(&ha_set_obj).unwrap()).unwrap();
 
         let expected_vnet_route = VnetRouteTunnelTable {
-            endpoint: vec![
-                vdpu0_state_obj.dpu.pa_ipv4.clone(),
-                vdpu1_state_obj.dpu.npu_ipv4.clone(),
-            ],
-            endpoint_monitor: Some(vec![
-                vdpu0_state_obj.dpu.pa_ipv4.clone(),
-                vdpu1_state_obj.dpu.pa_ipv4.clone(),
-            ]),
+            endpoint: vec![vdpu0_state_obj.dpu.pa_ip(), vdpu1_state_obj.dpu.npu_ip()],
+            endpoint_monitor: Some(vec![vdpu0_state_obj.dpu.pa_ip(), vdpu1_state_obj.dpu.pa_ip()]),
             monitoring: None,
             primary: Some(vec!["true".to_string(), "false".to_string()]),
             rx_monitor_timer: global_cfg.dpu_bfd_probe_interval_in_ms,
@@ -442,10 +629,48 @@ mod test {
         };
         let expected_vnet_route = swss_serde::to_field_values(&expected_vnet_route).unwrap();
 
+        let expected_peer = HaSetPeer {
+            vdpu_id: vdpu1_id.clone(),
+            npu_ip: vdpu1_state_obj.dpu.npu_ip(),
+            dpu_id: vdpu1_state_obj.dpu.dpu_id,
+        };
+
+        // the local DPU has the lower addresses, so it initiates the sessions to the peer DPU
+        let bfd_session_fvs = |local_addr: &str, peer_addr: &str| {
+            let session = BfdSessionTable {
+                tx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
+                rx_interval: global_cfg.dpu_bfd_probe_interval_in_ms,
+                multiplier: global_cfg.dpu_bfd_probe_multiplier,
+                multihop: true,
+                shutdown: false,
+                local_addr: local_addr.to_string(),
+                session_type: None,
+                local_discriminator: Some(BfdSessionTable::discriminator(local_addr, peer_addr)),
+                remote_discriminator: Some(BfdSessionTable::discriminator(peer_addr, local_addr)),
+            };
+            serde_json::to_value(swss_serde::to_field_values(&session).unwrap()).unwrap()
+        };
+        let bfd_v4_fvs = bfd_session_fvs("18.0.0.0", "18.0.1.0");
+        let bfd_v6_fvs = bfd_session_fvs("18::", "18:0:1::");
+
+        // the peer DPU moves to another PA address
+        let mut moved_dpu1 = dpu1.clone();
+        moved_dpu1.pa_ipv4 = Some("18.0.1.1".to_string());
+        let (_, moved_vdpu1_state_obj) = make_vdpu_actor_state(true, &moved_dpu1);
+        let moved_vdpu1_state = serde_json::to_value(&moved_vdpu1_state_obj).unwrap();
+        let (_, mut moved_ha_set_obj) = make_dpu_scope_ha_set_obj(0, 0);
+        moved_ha_set_obj.peer_ip = "18.0.1.1".to_string();
+        let moved_ha_set_obj_fvs =
+            serde_json::to_value(swss_serde::to_field_values(&moved_ha_set_obj).unwrap()).unwrap();
+        let moved_bfd_v4_fvs = bfd_session_fvs("18.0.0.0", "18.0.1.1");
+
         let ha_set_actor = HaSetActor {
             id: ha_set_id.clone(),
             dash_ha_set_config: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            monitored_peer: None,
+            bfd_sessions: BTreeMap::new(),
         };
 
         let handle = runtime.spawn(ha_set_actor, HaSetActor::name(), &ha_set_id);
@@ -471,12 +696,44 @@ mod test {
             recv! { key: &ha_set_id, data: {"key": &ha_set_id,  "operation": "Set", "field_values": ha_set_obj_fvs},
                     addr: crate::common_bridge_sp::<DashHaSetTable>(&runtime.get_swbus_edge()) },
             // Verify that haset actor state is sent to ha-scope actor
-            recv! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": true, "ha_set": &ha_set_obj },
+            recv! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": true, "ha_set": &ha_set_obj, "peer": &expected_peer },
+                    addr: runtime.sp("ha-scope", &format!("vdpu0:{ha_set_id}")) },
+            // Verify that the peer is registered to the peer monitor
+            recv! { key: PeerMonitorRegistration::msg_key(&ha_set_id), data: { "peer": &expected_peer },
+                    addr: runtime.sp(crate::peer_monitor::NAME, "0") },
+            // Verify that the BFD sessions to the peer DPU are programmed
+            recv! { key: &ha_set_id, data: {"key": "default:default:18.0.1.0", "operation": "Set", "field_values": bfd_v4_fvs},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            recv! { key: &ha_set_id, data: {"key": "default:default:18:0:1::", "operation": "Set", "field_values": bfd_v6_fvs},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            // Simulate the peer hamgrd becoming unreachable, which is passed on to the ha-scope actor
+            send! { key: PeerHamgrdState::msg_key(), data: { "reachable": false, "consecutive_failures": 3 },
+                    addr: runtime.sp(crate::peer_monitor::NAME, "0") },
+            recv! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": true, "ha_set": &ha_set_obj, "peer": &expected_peer, "peer_unreachable": true },
                     addr: runtime.sp("ha-scope", &format!("vdpu0:{ha_set_id}")) },
             chkdb! { type: VnetRouteTunnelTable, key: &format!("{}:{}", global_cfg.vnet_name.unwrap(), ha_set_cfg.vip_v4), data: expected_vnet_route },
+            chkgolden! { name: "ha_set_actor", tables: [VnetRouteTunnelTable] },
+            // Simulate the peer DPU moving to another address, which replaces the BFD session to the old one
+            send! { key: VDpuActorState::msg_key(&vdpu1_id), data: moved_vdpu1_state, addr: runtime.sp("vdpu", &vdpu1_id) },
+            recv! { key: &ha_set_id, data: {"key": &ha_set_id,  "operation": "Set", "field_values": moved_ha_set_obj_fvs},
+                    addr: crate::common_bridge_sp::<DashHaSetTable>(&runtime.get_swbus_edge()) },
+            recv! { key: HaSetActorState::msg_key(&ha_set_id), data: { "up": true, "ha_set": &moved_ha_set_obj, "peer": &expected_peer, "peer_unreachable": true },
+                    addr: runtime.sp("ha-scope", &format!("vdpu0:{ha_set_id}")) },
+            recv! { key: &ha_set_id, data: {"key": "default:default:18.0.1.0", "operation": "Del", "field_values": {}},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            recv! { key: &ha_set_id, data: {"key": "default:default:18.0.1.1", "operation": "Set", "field_values": moved_bfd_v4_fvs},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
             // simulate delete of ha-set entry
             send! { key: HaSetActor::table_name(), data: { "key": HaSetActor::table_name(), "operation": "Del", "field_values": ha_set_cfg_fvs },
                     addr: crate::common_bridge_sp::<DashHaSetConfigTable>(&runtime.get_swbus_edge()) },
+            // Verify that the peer is unregistered from the peer monitor
+            recv! { key: PeerMonitorRegistration::msg_key(&ha_set_id), data: { "peer": null },
+                    addr: runtime.sp(crate::peer_monitor::NAME, "0") },
+            // Verify that the BFD sessions to the peer DPU are removed
+            recv! { key: &ha_set_id, data: {"key": "default:default:18.0.1.1", "operation": "Del", "field_values": {}},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
+            recv! { key: &ha_set_id, data: {"key": "default:default:18:0:1::", "operation": "Del", "field_values": {}},
+                    addr: crate::common_bridge_sp::<BfdSessionTable>(&runtime.get_swbus_edge()) },
         ];
 
         test::run_commands(&runtime, runtime.sp(HaSetActor::name(), &ha_set_id), &commands).await;
@@ -508,14 +765,8 @@ mod test {
         let vdpu1_state = serde_json::to_value(&vdpu1_state_obj).unwrap();
 
         let expected_vnet_route = VnetRouteTunnelTable {
-            endpoint: vec![
-                vdpu0_state_obj.dpu.npu_ipv4.clone(),
-                vdpu1_state_obj.dpu.npu_ipv4.clone(),
-            ],
-            endpoint_monitor: Some(vec![
-                vdpu0_state_obj.dpu.pa_ipv4.clone(),
-                vdpu1_state_obj.dpu.pa_ipv4.clone(),
-            ]),
+            endpoint: vec![vdpu0_state_obj.dpu.npu_ip(), vdpu1_state_obj.dpu.npu_ip()],
+            endpoint_monitor: Some(vec![vdpu0_state_obj.dpu.pa_ip(), vdpu1_state_obj.dpu.pa_ip()]),
             monitoring: None,
             primary: Some(vec!["true".to_string(), "false".to_string()]),
             rx_monitor_timer: global_cfg.dpu_bfd_probe_interval_in_ms,
@@ -527,6 +778,9 @@ mod test {
             id: ha_set_id.clone(),
             dash_ha_set_config: None,
             bridges: Vec::new(),
+            state_seq: StateSequencer::default(),
+            monitored_peer: None,
+            bfd_sessions: BTreeMap::new(),
         };
 
         let handle = runtime.spawn(ha_set_actor, HaSetActor::name(), &ha_set_id);
@@ -548,6 +802,7 @@ mod test {
             // Verify that the DASH_HA_SET_TABLE was updated
             chkdb! { type: VnetRouteTunnelTable, key: &format!("{}:{}", global_cfg.vnet_name.unwrap(), ha_set_cfg.vip_v4),
                     data: expected_vnet_route },
+            chkgolden! { name: "remote_ha_set_actor", tables: [VnetRouteTunnelTable] },
             // simulate delete of ha-set entry
             send! { key: HaSetActor::table_name(), data: { "key": HaSetActor::table_name(), "operation": "Del", "field_values": ha_set_cfg_fvs },
                     addr: crate::common_bridge_sp::<DashHaSetConfigTable>(&runtime.get_swbus_edge()) },
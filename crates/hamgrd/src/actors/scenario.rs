@@ -0,0 +1,136 @@
+//! Timed failover scenarios on top of the actor test kit.
+//!
+//! A scenario is a list of named events, each a batch of test [`Command`]s scheduled at an offset
+//! from the start of the scenario, e.g. BFD down at 100ms, peer lost at 2s, config change at 5s.
+//! Events run in order of their offset, each to completion, so checks in an event see the effect
+//! of every event before it. Fields of a tracked entry are recorded after every event, which gives
+//! the trajectory of the HA state machine, and the report tells how long the failover took.
+use crate::actors::test::{run_commands, Command};
+use std::time::Duration;
+use swbus_actor::ActorRuntime;
+use swbus_edge::swbus_proto::swbus::ServicePath;
+use swss_common::{SonicDbTable, Table};
+use tokio::time::{sleep_until, Instant};
+use tracing::debug;
+
+struct Event {
+    at: Duration,
+    name: String,
+    commands: Vec<Command>,
+}
+
+struct Tracked {
+    db: String,
+    is_dpu: bool,
+    table: String,
+    key: String,
+    fields: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Scenario {
+    events: Vec<Event>,
+    tracked: Option<Tracked>,
+}
+
+/// What happened to an event of a scenario. Times are from the start of the scenario.
+pub struct EventReport {
+    pub name: String,
+    pub started: Duration,
+    pub completed: Duration,
+    /// Tracked fields after the event, "none" for a missing field
+    pub state: Vec<String>,
+}
+
+pub struct ScenarioReport {
+    pub events: Vec<EventReport>,
+}
+
+impl Scenario {
+    /// Run `commands` as event `name`, `at` from the start of the scenario. Events at the same
+    /// offset run in the order they are added.
+    pub fn at(mut self, at: Duration, name: &str, commands: impl IntoIterator<Item = Command>) -> Self {
+        self.events.push(Event {
+            at,
+            name: name.to_string(),
+            commands: commands.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Record `fields` of entry `key` of table `T` after every event.
+    pub fn track<T: SonicDbTable>(mut self, key: &str, fields: &[&str]) -> Self {
+        self.tracked = Some(Tracked {
+            db: T::db_name().to_string(),
+            is_dpu: T::is_dpu(),
+            table: T::table_name().to_string(),
+            key: key.to_string(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        });
+        self
+    }
+
+    pub async fn run(mut self, runtime: &ActorRuntime, aut: ServicePath) -> ScenarioReport {
+        self.events.sort_by_key(|e| e.at);
+        let start = Instant::now();
+        let mut report = ScenarioReport { events: Vec::new() };
+
+        for event in &self.events {
+            // an event starts late if the ones before it overran its offset
+            sleep_until(start + event.at).await;
+            let started = start.elapsed();
+            debug!("scenario event {} started at {started:?}", event.name);
+            run_commands(runtime, aut.clone(), &event.commands).await;
+            let completed = start.elapsed();
+
+            let state = match self.tracked {
+                Some(ref tracked) => tracked.read().await,
+                None => Vec::new(),
+            };
+            report.events.push(EventReport {
+                name: event.name.clone(),
+                started,
+                completed,
+                state,
+            });
+        }
+        report
+    }
+}
+
+impl Tracked {
+    async fn read(&self) -> Vec<String> {
+        let db = crate::db_named(&self.db, self.is_dpu).await.unwrap();
+        let mut table = Table::new(db, &self.table).unwrap();
+        let fvs = table.get_async(&self.key).await.unwrap().unwrap_or_default();
+        self.fields
+            .iter()
+            .map(|field| match fvs.get(field) {
+                Some(value) => value.to_str().unwrap().to_string(),
+                None => "none".to_string(),
+            })
+            .collect()
+    }
+}
+
+impl ScenarioReport {
+    /// Tracked fields after each event, by event name.
+    pub fn trajectory(&self) -> Vec<(&str, Vec<&str>)> {
+        self.events
+            .iter()
+            .map(|e| (e.name.as_str(), e.state.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    /// Time from the start of event `from` until event `to` has completed, i.e. until its checks
+    /// have passed.
+    pub fn time_between(&self, from: &str, to: &str) -> Duration {
+        let event = |name: &str| {
+            self.events
+                .iter()
+                .find(|e| e.name == name)
+                .unwrap_or_else(|| panic!("no event {name} in scenario"))
+        };
+        event(to).completed.saturating_sub(event(from).started)
+    }
+}
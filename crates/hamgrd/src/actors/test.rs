@@ -1,9 +1,11 @@
 use crate::db_structs::*;
 use crate::ha_actor_messages::*;
-use crate::RuntimeData;
+use crate::HamgrdContext;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, future::Future, time::Duration};
+use std::collections::{BTreeMap, HashMap};
+use std::{future::Future, path::PathBuf, time::Duration};
 use std::{net::Ipv4Addr, net::Ipv6Addr, sync::Arc};
 use swbus_actor::{ActorMessage, ActorRuntime};
 use swbus_edge::{
@@ -93,6 +95,19 @@ macro_rules! chkdb {
 }
 pub use chkdb;
 
+/// Compare what the actor has programmed to the DPU and the content of `tables` against the golden
+/// file `golden/<name>.json`. Run the tests with UPDATE_GOLDEN=1 to write the golden files instead.
+#[macro_export]
+macro_rules! chkgolden {
+    (name: $name:expr, tables: [$($type:ty),* $(,)?]) => {
+        $crate::actors::test::Command::ChkGolden {
+            name: String::from($name),
+            tables: vec![$((String::from(<$type>::db_name()), <$type>::is_dpu(), String::from(<$type>::table_name()))),*],
+        }
+    };
+}
+pub use chkgolden;
+
 pub enum Command {
     Send {
         key: String,
@@ -113,12 +128,89 @@ pub enum Command {
         data: Value,
         exclude: String,
     },
+    ChkGolden {
+        name: String,
+        // (db, is_dpu, table)
+        tables: Vec<(String, bool, String)>,
+    },
+}
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// entries by key, None if the last operation on the key is a Del
+type EntryOps = BTreeMap<String, Option<BTreeMap<String, String>>>;
+
+/// Snapshot of the DB outputs of an actor, compared against a golden file.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+struct GoldenSnapshot {
+    /// Last operation received by each swss-common bridge, by `<db>|<table>`. This is the state
+    /// the actor programmed to the DPU.
+    programmed: BTreeMap<String, EntryOps>,
+    /// Content of the checked tables, by `<db>|<table>`.
+    tables: BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl GoldenSnapshot {
+    fn record_programmed(&mut self, addr: &ServicePath, data: &Value) {
+        let key = data["key"].as_str().unwrap_or_default().to_string();
+        let fvs = match data["operation"].as_str() {
+            Some("Del") => None,
+            _ => Some(serde_json::from_value(data["field_values"].clone()).unwrap()),
+        };
+        self.programmed
+            .entry(addr.resource_id.clone())
+            .or_default()
+            .insert(key, fvs);
+    }
+
+    async fn read_tables(&mut self, tables: &[(String, bool, String)]) {
+        for (db_name, is_dpu, table_name) in tables {
+            let db = crate::db_named(db_name, *is_dpu).await.unwrap();
+            let mut table = Table::new(db, table_name).unwrap();
+            let mut entries = BTreeMap::new();
+            for key in table.get_keys().unwrap() {
+                if let Some(fvs) = table.get_async(&key).await.unwrap() {
+                    let fvs = fvs
+                        .iter()
+                        .map(|(field, value)| (field.clone(), value.to_str().unwrap().to_string()))
+                        .collect();
+                    entries.insert(key, fvs);
+                }
+            }
+            self.tables.insert(format!("{db_name}|{table_name}"), entries);
+        }
+    }
+
+    /// Replace the values that change from run to run, timestamps and generated operation ids.
+    fn redact(&mut self) {
+        let programmed = self.programmed.values_mut().flat_map(|t| t.values_mut().flatten());
+        let tables = self.tables.values_mut().flat_map(|t| t.values_mut());
+        for fvs in programmed.chain(tables) {
+            for (field, value) in fvs.iter_mut() {
+                if field.contains("time") || field == "pending_operation_ids" {
+                    *value = "<redacted>".to_string();
+                }
+            }
+        }
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(GOLDEN_DIR).join(format!("{name}.json"))
+}
+
+fn read_golden(name: &str) -> Option<GoldenSnapshot> {
+    let path = golden_path(name);
+    let golden = std::fs::read_to_string(&path).ok()?;
+    Some(serde_json::from_str(&golden).unwrap_or_else(|e| panic!("invalid golden file {}: {e}", path.display())))
 }
 
 pub async fn run_commands(runtime: &ActorRuntime, aut: ServicePath, commands: &[Command]) {
     use Command::*;
 
     let mut clients: HashMap<ServicePath, SimpleSwbusEdgeClient> = HashMap::new();
+    // bridge operations received so far, for ChkGolden
+    let mut recorded = GoldenSnapshot::default();
 
     // Pre-populate clients
     for cmd in commands {
@@ -129,7 +221,7 @@ pub async fn run_commands(runtime: &ActorRuntime, aut: ServicePath, commands: &[
                     clients.insert(addr.clone(), client);
                 }
             }
-            ChkDb { .. } => {}
+            ChkDb { .. } | ChkGolden { .. } => {}
         }
     }
 
@@ -142,7 +234,7 @@ pub async fn run_commands(runtime: &ActorRuntime, aut: ServicePath, commands: &[
                 let msg = OutgoingMessage {
                     destination: aut.clone(),
                     body: MessageBody::Request {
-                        payload: am.serialize(),
+                        payload: am.serialize().into(),
                     },
                 };
                 let sent_id = client.send(msg).await.unwrap();
@@ -202,6 +294,9 @@ pub async fn run_commands(runtime: &ActorRuntime, aut: ServicePath, commands: &[
                 println!("got {}", am.key);
                 assert_eq!(&am.key, key);
                 assert_eq!(&am.data, data);
+                if addr.resource_type == "swss-common-bridge" {
+                    recorded.record_programmed(addr, &am.data);
+                }
 
                 let ack = OutgoingMessage {
                     destination: aut.clone(),
@@ -273,6 +368,46 @@ pub async fn run_commands(runtime: &ActorRuntime, aut: ServicePath, commands: &[
                     panic!("{error}");
                 }
             }
+
+            ChkGolden { name, tables } => {
+                let golden = read_golden(name);
+                let update = std::env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1");
+                if golden.is_none() && !update {
+                    panic!(
+                        "golden file {} is missing, run with UPDATE_GOLDEN=1 to create it",
+                        golden_path(name).display()
+                    );
+                }
+
+                // Retry like ChkDb, the actor may not have committed its last writes yet
+                let mut attempt = 1;
+                let snapshot = loop {
+                    let mut snapshot = GoldenSnapshot {
+                        programmed: recorded.programmed.clone(),
+                        ..Default::default()
+                    };
+                    snapshot.read_tables(tables).await;
+                    snapshot.redact();
+                    if update || attempt == 5 || golden.as_ref() == Some(&snapshot) {
+                        break snapshot;
+                    }
+                    attempt += 1;
+                    sleep(Duration::from_millis(100)).await;
+                };
+
+                let actual = serde_json::to_string_pretty(&snapshot).unwrap() + "\n";
+                if update {
+                    std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+                    std::fs::write(golden_path(name), actual).unwrap();
+                } else if golden.as_ref() != Some(&snapshot) {
+                    let expected = serde_json::to_string_pretty(golden.as_ref().unwrap()).unwrap() + "\n";
+                    pretty_assertions::assert_eq!(
+                        expected,
+                        actual,
+                        "{name} differs from the golden file, rerun with UPDATE_GOLDEN=1 if intended"
+                    );
+                }
+            }
         }
     }
 }
@@ -289,13 +424,13 @@ pub async fn create_edge_runtime() -> SwbusEdgeRuntime {
 pub async fn create_actor_runtime(slot: u32, npu_ipv4: &str, npu_ipv6: &str) -> ActorRuntime {
     let mut edge = create_edge_runtime().await;
 
-    let runtime_data = RuntimeData::new(
+    let hamgrd_context = HamgrdContext::new(
         slot,
         Some(npu_ipv4.parse::<Ipv4Addr>().expect("Bad ipv4 address is provided")),
         Some(npu_ipv6.parse::<Ipv6Addr>().expect("Bad ipv6 address is provided")),
     );
 
-    edge.set_runtime_env(Box::new(runtime_data));
+    edge.set_runtime_env(Box::new(hamgrd_context));
     ActorRuntime::new(Arc::new(edge))
 }
 
@@ -308,6 +443,12 @@ pub fn make_dash_ha_global_config() -> DashHaGlobalConfig {
     DashHaGlobalConfig {
         dpu_bfd_probe_interval_in_ms: Some(1000),
         dpu_bfd_probe_multiplier: Some(3),
+        dpu_bfd_flap_dampening_threshold: None,
+        dpu_bfd_flap_dampening_window_in_ms: None,
+        planned_switchover_timeout_in_ms: None,
+        flow_sync_timeout_in_ms: None,
+        peer_monitor_interval_in_ms: None,
+        peer_monitor_failure_threshold: None,
         cp_data_channel_port: Some(12345),
         dp_channel_dst_port: Some(23456),
         dp_channel_src_port_min: Some(34567),
@@ -322,26 +463,28 @@ pub fn make_dpu_object(switch: u16, dpu: u32) -> Dpu {
     let switch_pair_id = switch / 2;
     Dpu {
         state: Some("up".to_string()),
-        vip_ipv4: Some(format!("3.2.{switch_pair_id}.{dpu}")),
-        vip_ipv6: Some(normalize_ipv6(&format!("3:2:{switch_pair_id}::{dpu}"))),
-        pa_ipv4: format!("18.0.{switch}.{dpu}"),
-        pa_ipv6: Some(normalize_ipv6(&format!("18:0:{switch}::{dpu}"))),
+        vip_ipv4: Some(format!("3.2.{switch_pair_id}.{dpu}").parse().unwrap()),
+        vip_ipv6: Some(format!("3:2:{switch_pair_id}::{dpu}").parse().unwrap()),
+        pa_ipv4: Some(format!("18.0.{switch}.{dpu}").parse().unwrap()),
+        pa_ipv6: Some(format!("18:0:{switch}::{dpu}").parse().unwrap()),
         dpu_id: dpu,
         vdpu_id: Some(format!("vdpu{}", switch * 8 + dpu as u16)),
         orchagent_zmq_port: 8100,
         swbus_port: 23606 + dpu as u16,
-        midplane_ipv4: format!("169.254.1.{dpu}"),
+        midplane_ipv4: Some(format!("169.254.1.{dpu}").parse().unwrap()),
+        midplane_ipv6: None,
+        maintenance: None,
     }
 }
 
 pub fn make_remote_dpu_object(switch: u16, dpu: u32) -> RemoteDpu {
     RemoteDpu {
-        pa_ipv4: format!("18.0.{switch}.{dpu}"),
-        pa_ipv6: Some(normalize_ipv6(&format!("18:0:{switch}::{dpu}"))),
+        pa_ipv4: Some(format!("18.0.{switch}.{dpu}").parse().unwrap()),
+        pa_ipv6: Some(format!("18:0:{switch}::{dpu}").parse().unwrap()),
         dpu_id: dpu,
         swbus_port: 23606 + dpu as u16,
-        npu_ipv4: format!("10.0.{switch}.{dpu}"),
-        npu_ipv6: Some(normalize_ipv6(&format!("10:0:{switch}::{dpu}"))),
+        npu_ipv4: Some(format!("10.0.{switch}.{dpu}").parse().unwrap()),
+        npu_ipv6: Some(format!("10:0:{switch}::{dpu}").parse().unwrap()),
     }
 }
 
@@ -383,7 +526,7 @@ pub fn make_local_dpu_actor_state(
         &format!("switch{switch}_dpu{dpu}"),
         &dpu_obj,
         is_managed,
-        &format!("10.0.{switch}.{dpu}"),
+        &Some(format!("10.0.{switch}.{dpu}")),
         &Some(normalize_ipv6(&format!("10:0:{switch}::{dpu}"))),
         dpu_pmon_state,
         dpu_bfd_state,
@@ -401,15 +544,17 @@ pub fn to_local_dpu(dpu_actor_state: &DpuActorState) -> Dpu {
     }
     Dpu {
         state: dpu_actor_state.state.clone(),
-        vip_ipv4: dpu_actor_state.vip_ipv4.clone(),
-        vip_ipv6: dpu_actor_state.vip_ipv6.clone(),
-        pa_ipv4: dpu_actor_state.pa_ipv4.clone(),
-        pa_ipv6: dpu_actor_state.pa_ipv6.clone(),
+        vip_ipv4: dpu_actor_state.vip_ipv4.as_ref().map(|ip| ip.parse().unwrap()),
+        vip_ipv6: dpu_actor_state.vip_ipv6.as_ref().map(|ip| ip.parse().unwrap()),
+        pa_ipv4: dpu_actor_state.pa_ipv4.as_ref().map(|ip| ip.parse().unwrap()),
+        pa_ipv6: dpu_actor_state.pa_ipv6.as_ref().map(|ip| ip.parse().unwrap()),
         dpu_id: dpu_actor_state.dpu_id,
         vdpu_id: dpu_actor_state.vdpu_id.clone(),
         orchagent_zmq_port: dpu_actor_state.orchagent_zmq_port,
         swbus_port: dpu_actor_state.swbus_port,
-        midplane_ipv4: dpu_actor_state.midplane_ipv4.as_ref().unwrap().clone(),
+        midplane_ipv4: dpu_actor_state.midplane_ipv4.as_ref().map(|ip| ip.parse().unwrap()),
+        midplane_ipv6: dpu_actor_state.midplane_ipv6.as_ref().map(|ip| ip.parse().unwrap()),
+        maintenance: dpu_actor_state.maintenance.then_some(true),
     }
 }
 
@@ -429,6 +574,11 @@ pub fn make_vdpu_actor_state(up: bool, dpu_state: &DpuActorState) -> (String, VD
         VDpuActorState {
             up,
             dpu: dpu_state.clone(),
+            members: vec![VDpuMemberState {
+                dpu_id: dpu_state.dpu_name.clone(),
+                is_main: true,
+                up: dpu_state.up,
+            }],
         },
     )
 }
@@ -506,6 +656,8 @@ pub fn make_npu_ha_scope_state(vdpu_state_obj: &VDpuActorState, ha_set_obj: &Das
     scope_state.local_vdpu_up_bfd_sessions_v4_update_time_in_ms = bfd_state.v4_bfd_up_sessions_timestamp;
     scope_state.local_vdpu_up_bfd_sessions_v6 = bfd_state.v6_bfd_up_sessions.clone();
     scope_state.local_vdpu_up_bfd_sessions_v6_update_time_in_ms = bfd_state.v6_bfd_up_sessions_timestamp;
+    scope_state.local_vdpu_dampened_bfd_sessions = (!vdpu_state_obj.dpu.dampened_bfd_sessions.is_empty())
+        .then(|| vdpu_state_obj.dpu.dampened_bfd_sessions.clone());
 
     scope_state
 }
@@ -533,6 +685,8 @@ pub fn update_npu_ha_scope_state_by_vdpu(
     npu_ha_scope_state.local_vdpu_up_bfd_sessions_v4_update_time_in_ms = bfd_state.v4_bfd_up_sessions_timestamp;
     npu_ha_scope_state.local_vdpu_up_bfd_sessions_v6 = bfd_state.v6_bfd_up_sessions.clone();
     npu_ha_scope_state.local_vdpu_up_bfd_sessions_v6_update_time_in_ms = bfd_state.v6_bfd_up_sessions_timestamp;
+    npu_ha_scope_state.local_vdpu_dampened_bfd_sessions = (!vdpu_state_obj.dpu.dampened_bfd_sessions.is_empty())
+        .then(|| vdpu_state_obj.dpu.dampened_bfd_sessions.clone());
 }
 
 pub fn update_npu_ha_scope_state_by_dpu_scope_state(
@@ -1,8 +1,13 @@
 use crate::actors::dpu::DpuActor;
+use crate::actors::ha_set::HaSetActor;
 use crate::actors::DbBasedActor;
+use crate::config_validation::parse_config;
 use crate::db_structs::VDpu;
-use crate::ha_actor_messages::{ActorRegistration, DpuActorState, RegistrationType, VDpuActorState};
+use crate::ha_actor_messages::{
+    ActorRegistration, DpuActorState, HaSetPlacement, RegistrationType, StateSequencer, VDpuActorState, VDpuMemberState,
+};
 use anyhow::Result;
+use std::collections::HashMap;
 use swbus_actor::Context;
 use swbus_actor::{state::incoming::Incoming, state::outgoing::Outgoing, Actor, State};
 use swss_common::{KeyOpFieldValues, KeyOperation, SonicDbTable};
@@ -12,11 +17,20 @@ pub struct VDpuActor {
     /// The id of this vdpu
     id: String,
     vdpu: Option<VDpu>,
+    /// Numbers the vDPU state updates sent to registered actors
+    state_seq: StateSequencer,
+    /// The HA set placement last sent to each member DPU
+    placements: HashMap<String, HaSetPlacement>,
 }
 
 impl DbBasedActor for VDpuActor {
     fn new(key: String) -> Result<Self> {
-        let actor = VDpuActor { id: key, vdpu: None };
+        let actor = VDpuActor {
+            id: key,
+            vdpu: None,
+            state_seq: StateSequencer::default(),
+            placements: HashMap::new(),
+        };
         Ok(actor)
     }
 
@@ -29,33 +43,78 @@ impl DbBasedActor for VDpuActor {
     }
 }
 
+/// The member DPUs of a vDPU with their main/backup designation, main DPUs first.
+fn member_dpus(vdpu: &VDpu) -> Vec<(&str, bool)> {
+    let main = vdpu.main_dpu_ids.iter().map(|id| (id.as_str(), true));
+    let backup = vdpu.backup_dpu_ids.iter().flatten().map(|id| (id.as_str(), false));
+    let mut members: Vec<(&str, bool)> = Vec::new();
+    for (id, is_main) in main.chain(backup) {
+        if !id.is_empty() && !members.iter().any(|(m, _)| *m == id) {
+            members.push((id, is_main));
+        }
+    }
+    members
+}
+
+/// How the member DPUs of a vDPU serve it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Composition {
+    /// A single member serves the vDPU, the others take over when it goes down
+    Failover,
+    /// All main DPUs serve the vDPU together. Backup DPUs are not used.
+    Composed,
+}
+
+fn composition(vdpu: &VDpu) -> Composition {
+    match vdpu.composition.as_deref() {
+        Some("composed") => Composition::Composed,
+        _ => Composition::Failover,
+    }
+}
+
 impl VDpuActor {
     async fn register_to_dpu_actor(&self, outgoing: &mut Outgoing, active: bool) -> Result<()> {
-        if self.vdpu.is_none() {
+        let Some(ref vdpu) = self.vdpu else {
             return Ok(());
-        }
+        };
         let msg = ActorRegistration::new_actor_msg(active, RegistrationType::DPUState, &self.id)?;
-        for dpu_id in &self.vdpu.as_ref().unwrap().main_dpu_ids {
+        for (dpu_id, _) in member_dpus(vdpu) {
             outgoing.send(outgoing.from_my_sp(DpuActor::name(), dpu_id), msg.clone());
         }
         Ok(())
     }
 
     async fn handle_vdpu_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
-        let (_internal, incoming, outgoing) = state.get_all();
+        let (internal, incoming, outgoing) = state.get_all();
         let dpu_kfv: KeyOpFieldValues = incoming.get(key)?.deserialize_data()?;
         if dpu_kfv.operation == KeyOperation::Del {
-            // unregister from the DPU Actor
+            // withdraw the HA sets from the member DPUs and unregister from the DPU Actor
+            self.withdraw_placements(outgoing, |_| true)?;
             self.register_to_dpu_actor(outgoing, false).await?;
             context.stop();
             return Ok(());
         }
 
-        self.vdpu = Some(swss_serde::from_field_values(&dpu_kfv.field_values)?);
+        let Some(vdpu) = parse_config::<VDpu>(internal, &dpu_kfv).await? else {
+            return Ok(());
+        };
+        if let Some(ref old_vdpu) = self.vdpu {
+            // unregister from the DPUs that are no longer members of the vDPU
+            let new_members = member_dpus(&vdpu);
+            let msg = ActorRegistration::new_actor_msg(false, RegistrationType::DPUState, &self.id)?;
+            for (dpu_id, _) in member_dpus(old_vdpu) {
+                if !new_members.iter().any(|(id, _)| *id == dpu_id) {
+                    outgoing.send(outgoing.from_my_sp(DpuActor::name(), dpu_id), msg.clone());
+                }
+            }
+        }
+        let new_members: Vec<String> = member_dpus(&vdpu).iter().map(|(id, _)| id.to_string()).collect();
+        self.withdraw_placements(outgoing, |dpu_id| !new_members.iter().any(|id| id == dpu_id))?;
+        self.vdpu = Some(vdpu);
 
         // Subscribe to the DPU Actor for state updates
         self.register_to_dpu_actor(outgoing, true).await?;
-        Ok(())
+        self.update_placements(incoming, outgoing)
     }
 
     async fn handle_dpu_state_update(&mut self, incoming: &Incoming, outgoing: &mut Outgoing) -> Result<()> {
@@ -63,34 +122,119 @@ impl VDpuActor {
             // vdpu data is not available yet
             return Ok(());
         };
-        let msg = vdpu_state.to_actor_msg(&self.id)?;
+        let msg = self.state_seq.stamp(vdpu_state.to_actor_msg(&self.id)?);
         let peer_actors = ActorRegistration::get_registered_actors(incoming, RegistrationType::VDPUState);
         for actor_sp in peer_actors {
             outgoing.send(actor_sp, msg.clone());
         }
+        self.update_placements(incoming, outgoing)
+    }
+
+    /// Send the HA sets registered to the vDPU state to the DPU actor of each member whose placement
+    /// changed. In failover composition only the DPU serving the vDPU hosts them while the vDPU is up,
+    /// in composed composition every main DPU that is up does.
+    fn update_placements(&mut self, incoming: &Incoming, outgoing: &mut Outgoing) -> Result<()> {
+        let Some(ref vdpu) = self.vdpu else {
+            return Ok(());
+        };
+        let mut ha_set_ids: Vec<String> =
+            ActorRegistration::get_registered_actors(incoming, RegistrationType::VDPUState)
+                .into_iter()
+                .filter(|sp| sp.resource_type == HaSetActor::name())
+                .map(|sp| sp.resource_id)
+                .collect();
+        ha_set_ids.sort();
+        ha_set_ids.dedup();
+        let vdpu_state = self.calculate_vdpu_state(incoming);
+
+        for (dpu_id, is_main) in member_dpus(vdpu) {
+            let serving = vdpu_state.as_ref().is_some_and(|state| match composition(vdpu) {
+                Composition::Failover => state.up && state.dpu.dpu_name == dpu_id,
+                Composition::Composed => is_main && state.members.iter().any(|m| m.dpu_id == dpu_id && m.up),
+            });
+            let placement = HaSetPlacement {
+                ha_set_ids: ha_set_ids.clone(),
+                serving,
+            };
+            // a member that was never sent a placement hosts nothing
+            if self.placements.get(dpu_id).cloned().unwrap_or_default() == placement {
+                continue;
+            }
+            outgoing.send(
+                outgoing.from_my_sp(DpuActor::name(), dpu_id),
+                placement.to_actor_msg(&self.id)?,
+            );
+            if placement == HaSetPlacement::default() {
+                self.placements.remove(dpu_id);
+            } else {
+                self.placements.insert(dpu_id.to_string(), placement);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send an empty placement to the member DPUs matching `filter`, which no longer host the HA sets
+    /// of the vDPU.
+    fn withdraw_placements(&mut self, outgoing: &mut Outgoing, filter: impl Fn(&str) -> bool) -> Result<()> {
+        let withdrawn: Vec<String> = self.placements.keys().filter(|id| filter(id)).cloned().collect();
+        for dpu_id in withdrawn {
+            self.placements.remove(&dpu_id);
+            outgoing.send(
+                outgoing.from_my_sp(DpuActor::name(), &dpu_id),
+                HaSetPlacement::default().to_actor_msg(&self.id)?,
+            );
+        }
         Ok(())
     }
 
+    /// Calculate the vDPU state from the state of its member DPUs. In failover composition the vDPU is
+    /// served by the first main DPU that is up, or the first backup DPU that is up if none of the main
+    /// DPUs is. If no member is up, the vDPU is down and reports the first main DPU with known state, if
+    /// any. In composed composition the vDPU is up when at least `min_members_up` of its main DPUs are
+    /// up, and reports the first main DPU that is up, or the first main DPU with known state.
     fn calculate_vdpu_state(&self, incoming: &Incoming) -> Option<VDpuActorState> {
-        if self.vdpu.as_ref().unwrap().main_dpu_ids.is_empty() {
-            return None;
+        let vdpu = self.vdpu.as_ref()?;
+        let mut members = Vec::new();
+        let mut dpus = Vec::new();
+        for (dpu_id, is_main) in member_dpus(vdpu) {
+            let Ok(msg) = incoming.get(&DpuActorState::msg_key(dpu_id)) else {
+                // dpu data is not available yet
+                continue;
+            };
+            match msg.deserialize_data::<DpuActorState>() {
+                Ok(dpu) => {
+                    members.push(VDpuMemberState {
+                        dpu_id: dpu_id.to_string(),
+                        is_main,
+                        up: dpu.up,
+                    });
+                    dpus.push(dpu);
+                }
+                Err(e) => error!("Failed to deserialize DpuActorState of {dpu_id}: {e}"),
+            }
         }
-        // only one dpu is supported for now
-        let dpu_id = &self.vdpu.as_ref().unwrap().main_dpu_ids[0];
-        let msg = incoming.get(&format!("{}{}", DpuActorState::msg_key_prefix(), dpu_id));
 
-        let Ok(msg) = msg else {
-            // dpu data is not available yet
-            return None;
+        let (up, serving) = match composition(vdpu) {
+            Composition::Failover => {
+                let serving = members
+                    .iter()
+                    .position(|m| m.up)
+                    .or_else(|| members.iter().position(|m| m.is_main))?;
+                (members[serving].up, serving)
+            }
+            Composition::Composed => {
+                let main_count = member_dpus(vdpu).iter().filter(|(_, is_main)| *is_main).count();
+                let min_up = vdpu.min_members_up.map_or(main_count, |n| n as usize);
+                let main_up = members.iter().filter(|m| m.is_main && m.up).count();
+                let serving = members
+                    .iter()
+                    .position(|m| m.is_main && m.up)
+                    .or_else(|| members.iter().position(|m| m.is_main))?;
+                (main_up >= min_up, serving)
+            }
         };
-
-        if let Ok(dpu) = msg.deserialize_data::<DpuActorState>() {
-            let vdpu = VDpuActorState { up: dpu.up, dpu };
-            Some(vdpu)
-        } else {
-            error!("Failed to deserialize DpuActorState from the message");
-            None
-        }
+        let dpu = dpus.swap_remove(serving);
+        Some(VDpuActorState { up, dpu, members })
     }
 
     async fn handle_vdpu_state_registration(
@@ -102,20 +246,37 @@ impl VDpuActor {
         let entry = incoming.get_entry(key)?;
         let ActorRegistration { active, .. } = entry.msg.deserialize_data()?;
         if active {
-            let Some(vdpu_state) = self.calculate_vdpu_state(incoming) else {
-                // vdpu data is not available yet
-                return Ok(());
-            };
-            let msg = vdpu_state.to_actor_msg(&self.id)?;
-            outgoing.send(entry.source.clone(), msg);
+            if let Some(vdpu_state) = self.calculate_vdpu_state(incoming) {
+                let msg = self.state_seq.stamp(vdpu_state.to_actor_msg(&self.id)?);
+                outgoing.send(entry.source.clone(), msg);
+            }
         }
-        Ok(())
+        // the HA sets registered to the vDPU are the ones placed on it
+        self.update_placements(incoming, outgoing)
     }
 }
 
 impl Actor for VDpuActor {
+    async fn init(&mut self, state: &mut State) -> Result<()> {
+        crate::actors::enable_snapshots(state, Self::name(), &self.id).await;
+        Ok(())
+    }
+
     #[instrument(name="handle_message", level="info", skip_all, fields(actor=format!("vdpu/{}", self.id), key=key))]
     async fn handle_message(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
+        let (vdpu, placements) = (self.vdpu.clone(), self.placements.clone());
+        let res = self.dispatch(state, key, context).await;
+        if res.is_err() {
+            // the placement messages are dropped with the rest of the state, so the DPUs never got them
+            self.vdpu = vdpu;
+            self.placements = placements;
+        }
+        res
+    }
+}
+
+impl VDpuActor {
+    async fn dispatch(&mut self, state: &mut State, key: &str, context: &mut Context) -> Result<()> {
         if key == Self::table_name() {
             return self.handle_vdpu_message(state, key, context).await;
         }
@@ -127,6 +288,7 @@ impl Actor for VDpuActor {
         let (_internal, incoming, outgoing) = state.get_all();
 
         if DpuActorState::is_my_msg(key) {
+            ActorRegistration::refresh_on_gap(incoming, outgoing, key, RegistrationType::DPUState, &self.id)?;
             return self.handle_dpu_state_update(incoming, outgoing).await;
         } else if ActorRegistration::is_my_msg(key, RegistrationType::VDPUState) {
             return self.handle_vdpu_state_registration(key, incoming, outgoing).await;
@@ -138,23 +300,26 @@ impl Actor for VDpuActor {
 
 #[cfg(test)]
 mod test {
-    use crate::db_structs::VDpu;
+    use crate::db_structs::{DashHaConfigValidationTable, VDpu};
     use crate::ha_actor_messages::{ActorRegistration, RegistrationType};
     use crate::{
         actors::{
             dpu::DpuActor,
             ha_set::HaSetActor,
-            test::{self, make_remote_dpu_actor_state, recv, send},
+            test::{self, chkdb, chkgolden, make_remote_dpu_actor_state, recv, send},
             vdpu::VDpuActor,
             DbBasedActor,
         },
         ha_actor_messages::*,
     };
+    use std::collections::HashMap;
     use std::time::Duration;
     use swss_common::SonicDbTable;
+    use swss_common_testing::Redis;
 
     #[tokio::test]
     async fn vdpu_actor() {
+        let _redis = Redis::start_config_db();
         let runtime = test::create_actor_runtime(1, "10.0.0.0", "10::").await;
 
         let dpu_actor_down_state = make_remote_dpu_actor_state(1, 0);
@@ -163,35 +328,216 @@ mod test {
         let vdpu_actor = VDpuActor {
             id: "test-vdpu".into(),
             vdpu: None,
+            state_seq: StateSequencer::default(),
+            placements: HashMap::new(),
         };
 
         let handle = runtime.spawn(vdpu_actor, VDpuActor::name(), "test-vdpu");
 
         #[rustfmt::skip]
         let commands = [
+            // Invalid VDPU config is reported and not applied
+            send! { key: VDpu::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Set", "field_values": {}},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            chkdb! { type: DashHaConfigValidationTable, key: "VDPU|VDPU",
+                     data: { "status": "invalid", "errors": "main_dpu_ids: mandatory field is missing", "invalid_fields": "main_dpu_ids" }, exclude: "last_validated_time_in_ms" },
+
             // Receiving DPU config-db object from swss-common bridge
             send! { key: VDpu::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Set", "field_values": {"main_dpu_ids": "switch1_dpu0" }},
                     addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
             recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
                     addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            chkdb! { type: DashHaConfigValidationTable, key: "VDPU|VDPU",
+                     data: { "status": "valid", "errors": "", "invalid_fields": "" }, exclude: "last_validated_time_in_ms" },
+            chkgolden! { name: "vdpu_actor", tables: [DashHaConfigValidationTable] },
 
-            // receive VDPU state registration
+            // receive VDPU state registration, the HA set is placed on the DPU
             send! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, "test-ha-set"), data: { "active": true},
                     addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
 
             // receive DPU state update
             send! { key: DpuActorState::msg_key("switch1_dpu0"), data: dpu_actor_up_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
-            recv! { key: VDpuActorState::msg_key("test-vdpu"), data: { "up": true, "dpu": dpu_actor_up_state },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": dpu_actor_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": true }] },
                     addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
 
             // receive DPU down update
             send! { key: DpuActorState::msg_key("switch1_dpu0"), data: dpu_actor_down_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
-            recv! { key: VDpuActorState::msg_key("test-vdpu"), data: { "up": false, "dpu": dpu_actor_down_state },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": false, "dpu": dpu_actor_down_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": false }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+
+            // the HA set is withdrawn from the DPU when the vDPU is removed
+            send! { key: VDpuActor::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Del", "field_values": {"main_dpu_ids": "switch1_dpu0"}},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+
+        ];
+
+        test::run_commands(&runtime, runtime.sp(VDpuActor::name(), "test-vdpu"), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(1), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    #[tokio::test]
+    async fn vdpu_actor_with_backup_dpu() {
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(1, "10.0.0.0", "10::").await;
+
+        let main_down_state = make_remote_dpu_actor_state(1, 0);
+        let mut main_up_state = main_down_state.clone();
+        main_up_state.up = true;
+        let mut backup_up_state = make_remote_dpu_actor_state(1, 1);
+        backup_up_state.up = true;
+        let vdpu_actor = VDpuActor {
+            id: "test-vdpu".into(),
+            vdpu: None,
+            state_seq: StateSequencer::default(),
+            placements: HashMap::new(),
+        };
+
+        let handle = runtime.spawn(vdpu_actor, VDpuActor::name(), "test-vdpu");
+
+        #[rustfmt::skip]
+        let commands = [
+            // Register to both main and backup DPUs
+            send! { key: VDpu::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Set",
+                    "field_values": {"main_dpu_ids": "switch1_dpu0", "backup_dpu_ids": "switch1_dpu1" }},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            send! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, "test-ha-set"), data: { "active": true},
                     addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            // main DPU is down, vDPU is down
+            send! { key: DpuActorState::msg_key("switch1_dpu0"), data: main_down_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": false, "dpu": main_down_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": false }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+
+            // backup DPU takes over
+            send! { key: DpuActorState::msg_key("switch1_dpu1"), data: backup_up_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": backup_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": false },
+                                                                             { "dpu_id": "switch1_dpu1", "is_main": false, "up": true }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            // main DPU is back
+            send! { key: DpuActorState::msg_key("switch1_dpu0"), data: main_up_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": main_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": true },
+                                                                           { "dpu_id": "switch1_dpu1", "is_main": false, "up": true }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            // backup DPU is removed from the vDPU
+            send! { key: VDpu::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Set", "field_values": {"main_dpu_ids": "switch1_dpu0" }},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
 
             send! { key: VDpuActor::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Del", "field_values": {"main_dpu_ids": "switch1_dpu0"}},
                     addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+        ];
 
+        test::run_commands(&runtime, runtime.sp(VDpuActor::name(), "test-vdpu"), &commands).await;
+        if tokio::time::timeout(Duration::from_secs(1), handle).await.is_err() {
+            panic!("timeout waiting for actor to terminate");
+        }
+    }
+
+    #[tokio::test]
+    async fn composed_vdpu_actor() {
+        let _redis = Redis::start_config_db();
+        let runtime = test::create_actor_runtime(1, "10.0.0.0", "10::").await;
+
+        let dpu0_down_state = make_remote_dpu_actor_state(1, 0);
+        let mut dpu0_up_state = dpu0_down_state.clone();
+        dpu0_up_state.up = true;
+        let mut dpu1_up_state = make_remote_dpu_actor_state(1, 1);
+        dpu1_up_state.up = true;
+        let vdpu_actor = VDpuActor {
+            id: "test-vdpu".into(),
+            vdpu: None,
+            state_seq: StateSequencer::default(),
+            placements: HashMap::new(),
+        };
+
+        let handle = runtime.spawn(vdpu_actor, VDpuActor::name(), "test-vdpu");
+
+        #[rustfmt::skip]
+        let commands = [
+            // A vDPU composed of 2 DPUs that is up as long as one of them is
+            send! { key: VDpu::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Set",
+                    "field_values": {"main_dpu_ids": "switch1_dpu0,switch1_dpu1", "composition": "composed", "min_members_up": "1" }},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: ActorRegistration::msg_key(RegistrationType::DPUState, "test-vdpu"), data: { "active": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            send! { key: ActorRegistration::msg_key(RegistrationType::VDPUState, "test-ha-set"), data: { "active": true},
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            // both members serve the vDPU once they are up
+            send! { key: DpuActorState::msg_key("switch1_dpu0"), data: dpu0_up_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": dpu0_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": true }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            send! { key: DpuActorState::msg_key("switch1_dpu1"), data: dpu1_up_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": dpu0_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": true },
+                                                                           { "dpu_id": "switch1_dpu1", "is_main": true, "up": true }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": true },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
+
+            // the vDPU stays up with one member down
+            send! { key: DpuActorState::msg_key("switch1_dpu0"), data: dpu0_down_state, addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: VDpuActorState::msg_key("test-vdpu"),
+                    data: { "up": true, "dpu": dpu1_up_state, "members": [{ "dpu_id": "switch1_dpu0", "is_main": true, "up": false },
+                                                                           { "dpu_id": "switch1_dpu1", "is_main": true, "up": true }] },
+                    addr: runtime.sp(HaSetActor::name(), "test-ha-set") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": ["test-ha-set"], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+
+            // the HA set is withdrawn from every member when the vDPU is removed
+            send! { key: VDpuActor::table_name(), data: { "key": VDpuActor::table_name(), "operation": "Del", "field_values": {}},
+                    addr: crate::common_bridge_sp::<VDpu>(&runtime.get_swbus_edge()) },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu0") },
+            recv! { key: HaSetPlacement::msg_key("test-vdpu"), data: { "ha_set_ids": [], "serving": false },
+                    addr: runtime.sp(DpuActor::name(), "switch1_dpu1") },
         ];
 
         test::run_commands(&runtime, runtime.sp(VDpuActor::name(), "test-vdpu"), &commands).await;
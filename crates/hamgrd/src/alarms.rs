@@ -0,0 +1,165 @@
+//! HA alarms
+//!
+//! Alarms are written to STATE_DB/DASH_HA_ALARM_TABLE with set/clear semantics. Each alarm is an
+//! internal table entry of the owning actor, so it is committed together with the rest of the actor
+//! state and rolled back if the actor callback fails. The event of a set or clear is handed back to the
+//! actor, to be emitted once the callback succeeds.
+use crate::db_structs::{now_in_millis, update_field_values, DashHaAlarmTable};
+use crate::ha_events::{HaEvent, HaEventSeverity, HaEventType};
+use crate::TableSource;
+use anyhow::Result;
+use swbus_actor::state::internal::Internal;
+use swss_common::SonicDbTable;
+
+pub const ALARM_STATUS_SET: &str = "set";
+pub const ALARM_STATUS_CLEAR: &str = "clear";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaAlarmType {
+    UnplannedFailover,
+    SplitBrain,
+    BulkSyncFailure,
+}
+
+impl HaAlarmType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HaAlarmType::UnplannedFailover => "unplanned_failover",
+            HaAlarmType::SplitBrain => "split_brain",
+            HaAlarmType::BulkSyncFailure => "bulk_sync_failure",
+        }
+    }
+
+    pub fn severity(&self) -> HaEventSeverity {
+        match self {
+            HaAlarmType::UnplannedFailover => HaEventSeverity::Critical,
+            HaAlarmType::SplitBrain => HaEventSeverity::Critical,
+            HaAlarmType::BulkSyncFailure => HaEventSeverity::Major,
+        }
+    }
+
+    fn internal_key(&self) -> String {
+        format!("{}|{}", DashHaAlarmTable::table_name(), self.as_str())
+    }
+}
+
+fn get_alarm(internal: &Internal, alarm: HaAlarmType) -> Option<DashHaAlarmTable> {
+    let fvs = internal.get(&alarm.internal_key());
+    if fvs.is_empty() {
+        return None;
+    }
+    swss_serde::from_field_values(fvs).ok()
+}
+
+/// Set or clear `alarm` for `resource`. The DB is only written on a set <-> clear transition, which
+/// returns the event to emit once the internal state is committed. Clearing an alarm that was never set
+/// is a no-op.
+pub async fn update_alarm(
+    internal: &mut Internal,
+    resource: &str,
+    alarm: HaAlarmType,
+    raised: bool,
+    description: &str,
+) -> Result<Option<HaEvent>> {
+    let internal_key = alarm.internal_key();
+    let swss_key = format!(
+        "{}{}{}",
+        resource,
+        DashHaAlarmTable::source_key_separator(),
+        alarm.as_str()
+    );
+
+    if !internal.has_entry(&internal_key, &swss_key) {
+        let mut table = crate::tables::open_table::<DashHaAlarmTable>().await?;
+        // an alarm left set by a previous hamgrd instance still needs to be cleared
+        if !raised && table.get_async(&swss_key).await?.is_none() {
+            return Ok(None);
+        }
+        internal.add(&internal_key, table, swss_key).await;
+    }
+
+    let current = get_alarm(internal, alarm);
+    let is_set = current.as_ref().is_some_and(|a| a.status == ALARM_STATUS_SET);
+    if is_set == raised {
+        return Ok(None);
+    }
+
+    let mut entry = current.unwrap_or_default();
+    entry.alarm_type = alarm.as_str().to_string();
+    entry.severity = alarm.severity().as_str().to_string();
+    entry.description = description.to_string();
+    if raised {
+        entry.status = ALARM_STATUS_SET.to_string();
+        entry.set_time_in_ms = Some(now_in_millis());
+    } else {
+        entry.status = ALARM_STATUS_CLEAR.to_string();
+        entry.clear_time_in_ms = Some(now_in_millis());
+    }
+
+    update_field_values(internal.get_mut(&internal_key), &entry)?;
+
+    let (event_type, severity) = match raised {
+        true => (HaEventType::HaAlarmSet, alarm.severity()),
+        false => (HaEventType::HaAlarmClear, HaEventSeverity::Info),
+    };
+    let reason = format!("{}: {}", alarm.as_str(), description);
+    Ok(Some(HaEvent::new(event_type, severity, resource, &reason)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common_testing::Redis;
+
+    #[tokio::test]
+    async fn alarm_set_and_clear() {
+        let _redis = Redis::start_config_db();
+        let mut internal = Internal::default();
+        let key = HaAlarmType::SplitBrain.internal_key();
+
+        // clearing an alarm that was never set doesn't create the entry
+        let event = update_alarm(&mut internal, "vdpu0|haset0", HaAlarmType::SplitBrain, false, "")
+            .await
+            .unwrap();
+        assert!(event.is_none());
+        assert!(!internal.has_entry(&key, "vdpu0|haset0|split_brain"));
+
+        let event = update_alarm(
+            &mut internal,
+            "vdpu0|haset0",
+            HaAlarmType::SplitBrain,
+            true,
+            "brainsplit detected",
+        )
+        .await
+        .unwrap();
+        assert_eq!(event.unwrap().event_type, HaEventType::HaAlarmSet);
+        let alarm = get_alarm(&internal, HaAlarmType::SplitBrain).unwrap();
+        assert_eq!(alarm.status, ALARM_STATUS_SET);
+        assert_eq!(alarm.severity, "critical");
+        assert!(alarm.set_time_in_ms.is_some());
+        assert!(alarm.clear_time_in_ms.is_none());
+
+        // setting again keeps the original set time and description
+        let event = update_alarm(&mut internal, "vdpu0|haset0", HaAlarmType::SplitBrain, true, "again")
+            .await
+            .unwrap();
+        assert!(event.is_none());
+        assert_eq!(get_alarm(&internal, HaAlarmType::SplitBrain).unwrap(), alarm);
+
+        let event = update_alarm(
+            &mut internal,
+            "vdpu0|haset0",
+            HaAlarmType::SplitBrain,
+            false,
+            "recovered",
+        )
+        .await
+        .unwrap();
+        assert_eq!(event.unwrap().event_type, HaEventType::HaAlarmClear);
+        let alarm = get_alarm(&internal, HaAlarmType::SplitBrain).unwrap();
+        assert_eq!(alarm.status, ALARM_STATUS_CLEAR);
+        assert_eq!(alarm.description, "recovered");
+        assert!(alarm.clear_time_in_ms.is_some());
+    }
+}
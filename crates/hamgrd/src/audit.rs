@@ -0,0 +1,329 @@
+//! Audit log of the HA state transitions
+//!
+//! Every role change, planned switchover phase, split brain, change of the reachability of a peer hamgrd and
+//! operator action is recorded with [`record`] as an [`AuditEntry`]: when it happened, what triggered
+//! it, the state before and after, and the actor that made it. Once [`start`]ed, the entries are
+//! appended to STATE_DB/DASH_HA_AUDIT_TABLE, and to a JSONL file if one is configured, from a background
+//! task so that the actors never wait on the writes. Both are capped like a ring buffer: the table keeps
+//! the last `capacity` entries of every slot, and the file is rotated to `<file>.1` once it holds
+//! `capacity` entries.
+use crate::db_structs::{now_in_millis, DashHaAuditTable};
+use crate::tables::DbTable;
+use crate::TableSource;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::sync::mpsc::{channel, Sender};
+use tracing::{error, info};
+
+/// Entries kept by default, in the table for every slot and in the file.
+pub const DEFAULT_CAPACITY: u64 = 1000;
+
+const QUEUE_SIZE: usize = 1000;
+
+static AUDIT_LOG: OnceLock<Sender<AuditEntry>> = OnceLock::new();
+
+static AUDIT_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    RoleChange,
+    Switchover,
+    SplitBrain,
+    PeerReachability,
+    OperatorAction,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::RoleChange => "role_change",
+            AuditEvent::Switchover => "switchover",
+            AuditEvent::SplitBrain => "split_brain",
+            AuditEvent::PeerReachability => "peer_reachability",
+            AuditEvent::OperatorAction => "operator_action",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditEntry {
+    // The slot the transition happened in, e.g. dpu0
+    pub slot: String,
+    pub event: AuditEvent,
+    // The actor that made the transition, as <actor name>/<actor id>
+    pub actor: String,
+    // The HA scope or resource the transition is about
+    pub scope: String,
+    pub old_state: String,
+    pub new_state: String,
+    // The event that triggered the transition
+    pub trigger: String,
+    pub time_in_ms: i64,
+}
+
+impl AuditEntry {
+    pub fn new(event: AuditEvent, actor: &str, scope: &str, old_state: &str, new_state: &str, trigger: &str) -> Self {
+        AuditEntry {
+            slot: crate::slot::current().name(),
+            event,
+            actor: actor.to_string(),
+            scope: scope.to_string(),
+            old_state: old_state.to_string(),
+            new_state: new_state.to_string(),
+            trigger: trigger.to_string(),
+            time_in_ms: now_in_millis(),
+        }
+    }
+}
+
+/// Start writing the audit log to STATE_DB, and to `file` if set. Must be called in a tokio runtime.
+pub fn start(capacity: u64, file: Option<PathBuf>) -> Result<()> {
+    let jsonl = file.clone().map(|path| JsonlFile::open(path, capacity)).transpose()?;
+    let (tx, mut rx) = channel::<AuditEntry>(QUEUE_SIZE);
+    AUDIT_LOG
+        .set(tx)
+        .map_err(|_| anyhow!("the audit log is started already"))?;
+    if let Some(file) = file {
+        AUDIT_FILE.get_or_init(|| file);
+    }
+    let mut writer = AuditWriter::new(capacity, jsonl);
+    tokio::task::spawn(async move {
+        while let Some(entry) = rx.recv().await {
+            writer.write(&entry).await;
+        }
+    });
+    Ok(())
+}
+
+/// The JSONL files the audit log is written to, the rotated one first. Empty if it has no file.
+pub fn files() -> Vec<PathBuf> {
+    match AUDIT_FILE.get() {
+        Some(path) => vec![rotated_path(path), path.clone()],
+        None => Vec::new(),
+    }
+}
+
+/// Record an HA state transition. It is only logged until the audit log is started.
+pub fn record(entry: AuditEntry) {
+    info!(
+        "Audit {} of {} by {} on {}: {:?} -> {:?} on {}",
+        entry.event.as_str(),
+        entry.scope,
+        entry.actor,
+        entry.slot,
+        entry.old_state,
+        entry.new_state,
+        entry.trigger
+    );
+    let Some(tx) = AUDIT_LOG.get() else {
+        return;
+    };
+    if let Err(e) = tx.try_send(entry) {
+        error!("Failed to queue audit entry: {e}");
+    }
+}
+
+struct AuditWriter {
+    // Opened on the first entry, and again after a failure
+    table: Option<DbTable>,
+    capacity: u64,
+    // Sequence number of the next entry of every slot, resumed from the table
+    next_seq: HashMap<String, u64>,
+    file: Option<JsonlFile>,
+}
+
+impl AuditWriter {
+    fn new(capacity: u64, file: Option<JsonlFile>) -> Self {
+        AuditWriter {
+            table: None,
+            capacity: capacity.max(1),
+            next_seq: HashMap::new(),
+            file,
+        }
+    }
+
+    async fn write(&mut self, entry: &AuditEntry) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.append(entry) {
+                error!("Failed to write audit entry to {}: {e:#}", file.path.display());
+            }
+        }
+        if let Err(e) = self.write_table(entry).await {
+            error!("Failed to write audit entry to STATE_DB: {e:#}");
+            self.table = None;
+        }
+    }
+
+    async fn write_table(&mut self, entry: &AuditEntry) -> Result<()> {
+        if self.table.is_none() {
+            self.table = Some(crate::tables::open_table::<DashHaAuditTable>().await?);
+        }
+        let seq = self.next_seq(&entry.slot).await?;
+        let table = self.table.as_mut().unwrap();
+
+        let fvs = swss_serde::to_field_values(&DashHaAuditTable {
+            event: entry.event.as_str().to_string(),
+            actor: entry.actor.clone(),
+            scope: entry.scope.clone(),
+            old_state: entry.old_state.clone(),
+            new_state: entry.new_state.clone(),
+            trigger: entry.trigger.clone(),
+            time_in_ms: entry.time_in_ms,
+        })?;
+        table.set_async(&audit_key(&entry.slot, seq), fvs).await?;
+        if seq >= self.capacity {
+            table.del_async(&audit_key(&entry.slot, seq - self.capacity)).await?;
+        }
+        self.next_seq.insert(entry.slot.clone(), seq + 1);
+        Ok(())
+    }
+
+    /// The sequence number of the next entry of `slot`. The first time, it follows the entries written
+    /// before hamgrd restarted, and the entries beyond the capacity are removed.
+    async fn next_seq(&mut self, slot: &str) -> Result<u64> {
+        if let Some(seq) = self.next_seq.get(slot) {
+            return Ok(*seq);
+        }
+        let table = self.table.as_mut().unwrap();
+        let prefix = format!("{slot}{}", DashHaAuditTable::source_key_separator());
+        let mut seqs: Vec<u64> = table
+            .get_keys_async()
+            .await?
+            .iter()
+            .filter_map(|key| key.strip_prefix(&prefix)?.parse().ok())
+            .collect();
+        seqs.sort_unstable();
+        let next = seqs.last().map_or(0, |seq| seq + 1);
+        for seq in seqs.into_iter().filter(|seq| seq + self.capacity < next) {
+            table.del_async(&audit_key(slot, seq)).await?;
+        }
+        self.next_seq.insert(slot.to_string(), next);
+        Ok(next)
+    }
+}
+
+fn audit_key(slot: &str, seq: u64) -> String {
+    format!("{slot}{}{seq}", DashHaAuditTable::source_key_separator())
+}
+
+/// A JSONL file rotated to `<path>.1` once it holds `capacity` entries.
+struct JsonlFile {
+    path: PathBuf,
+    capacity: u64,
+    entries: u64,
+}
+
+impl JsonlFile {
+    fn open(path: PathBuf, capacity: u64) -> Result<Self> {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => content.lines().count() as u64,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context(format!("reading {}", path.display())),
+        };
+        Ok(JsonlFile {
+            path,
+            capacity: capacity.max(1),
+            entries,
+        })
+    }
+
+    fn append(&mut self, entry: &AuditEntry) -> Result<()> {
+        if self.entries >= self.capacity {
+            std::fs::rename(&self.path, rotated_path(&self.path))
+                .context(format!("rotating {}", self.path.display()))?;
+            self.entries = 0;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("opening {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::{DbConnector, SonicDbTable, Table};
+    use swss_common_testing::Redis;
+
+    fn role_change(old_role: &str, new_role: &str) -> AuditEntry {
+        AuditEntry::new(
+            AuditEvent::RoleChange,
+            "ha-scope/vdpu0:haset0",
+            "vdpu0|haset0",
+            old_role,
+            new_role,
+            "DPU confirmed ha_role in term 2",
+        )
+    }
+
+    #[tokio::test]
+    async fn audit_table_keeps_the_last_entries() {
+        let _redis = Redis::start_config_db();
+        let mut writer = AuditWriter::new(2, None);
+        writer.write(&role_change("dead", "active")).await;
+        writer.write(&role_change("active", "standby")).await;
+        writer.write(&role_change("standby", "active")).await;
+
+        let db = DbConnector::new_named("STATE_DB", false, 0).unwrap();
+        let table = Table::new(db, DashHaAuditTable::table_name()).unwrap();
+        let mut keys = table.get_keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["dpu0|1", "dpu0|2"]);
+        let entry: DashHaAuditTable = swss_serde::from_table(&table, "dpu0|2").unwrap();
+        assert_eq!(entry.event, "role_change");
+        assert_eq!(entry.actor, "ha-scope/vdpu0:haset0");
+        assert_eq!(entry.old_state, "standby");
+        assert_eq!(entry.new_state, "active");
+
+        // A restarted hamgrd carries on after the last entry, with a smaller capacity
+        let mut writer = AuditWriter::new(1, None);
+        writer.write(&role_change("active", "dead")).await;
+        assert_eq!(table.get_keys().unwrap(), vec!["dpu0|3"]);
+    }
+
+    #[test]
+    fn audit_file_is_rotated() {
+        let dir = std::env::temp_dir().join(format!("hamgrd-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+
+        let mut file = JsonlFile::open(path.clone(), 2).unwrap();
+        file.append(&role_change("dead", "active")).unwrap();
+        file.append(&role_change("active", "standby")).unwrap();
+        // Reopened, e.g. after a restart, the file is full already
+        let mut file = JsonlFile::open(path.clone(), 2).unwrap();
+        file.append(&role_change("standby", "active")).unwrap();
+
+        let lines = |path: &Path| -> Vec<serde_json::Value> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let rotated = lines(&rotated_path(&path));
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(rotated[0]["new_state"], "active");
+        let current = lines(&path);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["event"], "role_change");
+        assert_eq!(current[0]["old_state"], "standby");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
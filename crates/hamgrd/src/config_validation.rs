@@ -0,0 +1,448 @@
+//! Validation of consumed CONFIG_DB entries
+//!
+//! Each config table consumed by hamgrd declares the constraints of its sonic-yang model (mandatory
+//! leaves, enums and ranges) as a list of [`FieldRule`]s. Entries are checked against them and parsed
+//! field by field before they are applied, and every invalid field is written to
+//! STATE_DB/DASH_HA_CONFIG_VALIDATION_TABLE so a bad entry shows up with precise errors instead of
+//! being silently ignored or failing deserialization at the first bad field.
+use crate::db_structs::{
+    note_defaulted_field, now_in_millis, update_field_values, DashHaConfigValidationTable, DashHaGlobalConfig, Dpu,
+    FieldError, ParseFieldValues, RemoteDpu, VDpu,
+};
+use crate::TableSource;
+use anyhow::Result;
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use swbus_actor::state::internal::Internal;
+use swss_common::{FieldValues, KeyOpFieldValues, SonicDbTable};
+use tracing::{error, info, warn};
+
+pub const VALIDATION_STATUS_VALID: &str = "valid";
+pub const VALIDATION_STATUS_INVALID: &str = "invalid";
+/// Applied with some optional fields of a lenient table defaulted
+pub const VALIDATION_STATUS_DEGRADED: &str = "degraded";
+
+#[derive(Clone, Copy, Debug)]
+pub enum FieldType {
+    String,
+    Enum(&'static [&'static str]),
+    Uint { min: u64, max: u64 },
+    Ipv4,
+    Ipv6,
+}
+
+const PORT: FieldType = FieldType::Uint { min: 1, max: 65535 };
+const UINT32: FieldType = FieldType::Uint {
+    min: 0,
+    max: u32::MAX as u64,
+};
+const POSITIVE_UINT32: FieldType = FieldType::Uint {
+    min: 1,
+    max: u32::MAX as u64,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FieldRule {
+    pub name: &'static str,
+    pub mandatory: bool,
+    pub field_type: FieldType,
+    /// A mandatory field may be missing if this field is set instead, like the IPv6 counterpart of
+    /// an IPv4 address in IPv6-only deployments.
+    pub alternative: Option<&'static str>,
+}
+
+const fn mandatory(name: &'static str, field_type: FieldType) -> FieldRule {
+    FieldRule {
+        name,
+        mandatory: true,
+        field_type,
+        alternative: None,
+    }
+}
+
+/// A field that is mandatory unless `alternative` is set.
+const fn mandatory_unless(name: &'static str, field_type: FieldType, alternative: &'static str) -> FieldRule {
+    FieldRule {
+        name,
+        mandatory: true,
+        field_type,
+        alternative: Some(alternative),
+    }
+}
+
+const fn optional(name: &'static str, field_type: FieldType) -> FieldRule {
+    FieldRule {
+        name,
+        mandatory: false,
+        field_type,
+        alternative: None,
+    }
+}
+
+/// The constraints of the sonic-yang model of a config table.
+pub trait YangModel: SonicDbTable {
+    fn fields() -> &'static [FieldRule];
+}
+
+impl YangModel for Dpu {
+    fn fields() -> &'static [FieldRule] {
+        const FIELDS: &[FieldRule] = &[
+            optional("state", FieldType::Enum(&["up", "down"])),
+            optional("vip_ipv4", FieldType::Ipv4),
+            optional("vip_ipv6", FieldType::Ipv6),
+            mandatory_unless("pa_ipv4", FieldType::Ipv4, "pa_ipv6"),
+            optional("pa_ipv6", FieldType::Ipv6),
+            mandatory("dpu_id", UINT32),
+            optional("vdpu_id", FieldType::String),
+            mandatory("orchagent_zmq_port", PORT),
+            mandatory("swbus_port", PORT),
+            mandatory_unless("midplane_ipv4", FieldType::Ipv4, "midplane_ipv6"),
+            optional("midplane_ipv6", FieldType::Ipv6),
+            optional("maintenance", FieldType::Enum(&["true", "false"])),
+        ];
+        FIELDS
+    }
+}
+
+impl YangModel for RemoteDpu {
+    fn fields() -> &'static [FieldRule] {
+        const FIELDS: &[FieldRule] = &[
+            mandatory_unless("pa_ipv4", FieldType::Ipv4, "pa_ipv6"),
+            optional("pa_ipv6", FieldType::Ipv6),
+            mandatory_unless("npu_ipv4", FieldType::Ipv4, "npu_ipv6"),
+            optional("npu_ipv6", FieldType::Ipv6),
+            mandatory("dpu_id", UINT32),
+            mandatory("swbus_port", PORT),
+        ];
+        FIELDS
+    }
+}
+
+impl YangModel for VDpu {
+    fn fields() -> &'static [FieldRule] {
+        const FIELDS: &[FieldRule] = &[
+            mandatory("main_dpu_ids", FieldType::String),
+            optional("backup_dpu_ids", FieldType::String),
+            optional("composition", FieldType::Enum(&["failover", "composed"])),
+            optional("min_members_up", POSITIVE_UINT32),
+        ];
+        FIELDS
+    }
+}
+
+impl YangModel for DashHaGlobalConfig {
+    fn fields() -> &'static [FieldRule] {
+        const FIELDS: &[FieldRule] = &[
+            optional("cp_data_channel_port", PORT),
+            optional("dp_channel_dst_port", PORT),
+            optional("dp_channel_src_port_min", PORT),
+            optional("dp_channel_src_port_max", PORT),
+            optional("dp_channel_probe_interval_ms", POSITIVE_UINT32),
+            optional("dp_channel_probe_fail_threshold", POSITIVE_UINT32),
+            optional("dpu_bfd_probe_interval_in_ms", POSITIVE_UINT32),
+            optional("dpu_bfd_probe_multiplier", POSITIVE_UINT32),
+            optional("dpu_bfd_flap_dampening_threshold", UINT32),
+            optional("dpu_bfd_flap_dampening_window_in_ms", POSITIVE_UINT32),
+            optional("planned_switchover_timeout_in_ms", POSITIVE_UINT32),
+            optional("flow_sync_timeout_in_ms", POSITIVE_UINT32),
+            optional("peer_monitor_interval_in_ms", POSITIVE_UINT32),
+            optional("peer_monitor_failure_threshold", POSITIVE_UINT32),
+            optional("vnet_name", FieldType::String),
+        ];
+        FIELDS
+    }
+}
+
+fn check_value(field_type: FieldType, value: &str) -> std::result::Result<(), String> {
+    match field_type {
+        FieldType::String => Ok(()),
+        FieldType::Enum(values) => match values.contains(&value) {
+            true => Ok(()),
+            false => Err(format!("'{value}' is not one of {values:?}")),
+        },
+        FieldType::Uint { min, max } => match value.parse::<u64>() {
+            Ok(v) if (min..=max).contains(&v) => Ok(()),
+            Ok(_) => Err(format!("{value} is out of range {min}..{max}")),
+            Err(_) => Err(format!("'{value}' is not an unsigned integer")),
+        },
+        FieldType::Ipv4 => value
+            .parse::<Ipv4Addr>()
+            .map(|_| ())
+            .map_err(|_| format!("'{value}' is not an IPv4 address")),
+        FieldType::Ipv6 => value
+            .parse::<Ipv6Addr>()
+            .map(|_| ())
+            .map_err(|_| format!("'{value}' is not an IPv6 address")),
+    }
+}
+
+fn field_error(key: &str, field: &str, reason: String) -> FieldError {
+    FieldError {
+        key: key.to_string(),
+        field: field.to_string(),
+        reason,
+    }
+}
+
+/// Check `fvs` against the yang model of `T`. Returns the list of violations, empty if it conforms.
+pub fn validate<T: YangModel>(key: &str, fvs: &FieldValues) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    for rule in T::fields() {
+        let Some(value) = fvs.get(rule.name) else {
+            match rule.alternative {
+                Some(alternative) if fvs.contains_key(alternative) => {}
+                Some(alternative) => errors.push(field_error(
+                    key,
+                    rule.name,
+                    format!("mandatory field is missing, and so is {alternative}"),
+                )),
+                None if rule.mandatory => {
+                    errors.push(field_error(key, rule.name, "mandatory field is missing".to_string()))
+                }
+                None => {}
+            }
+            continue;
+        };
+        let Ok(value) = value.to_str() else {
+            errors.push(field_error(key, rule.name, "value is not valid UTF-8".to_string()));
+            continue;
+        };
+        if let Err(e) = check_value(rule.field_type, value) {
+            errors.push(field_error(key, rule.name, e));
+        }
+    }
+    errors
+}
+
+/// Validate the config entry in `kfv` against the yang model of `T`, parse it into `T` and record all
+/// invalid fields in DASH_HA_CONFIG_VALIDATION_TABLE. Returns `None` if the entry can't be applied.
+///
+/// If `T` is lenient, optional fields that violate the model are left out, so they fall back to their
+/// default, and the entry is applied with status "degraded".
+pub async fn parse_config<T>(internal: &mut Internal, kfv: &KeyOpFieldValues) -> Result<Option<T>>
+where
+    T: YangModel + ParseFieldValues,
+{
+    let mut errors = validate::<T>(&kfv.key, &kfv.field_values);
+    let mut fvs = Cow::Borrowed(&kfv.field_values);
+    let mut defaulted = Vec::new();
+    if T::lenient() {
+        let is_optional = |e: &FieldError| T::fields().iter().any(|r| r.name == e.field && !r.mandatory);
+        (defaulted, errors) = errors.into_iter().partition(is_optional);
+        for e in &defaulted {
+            note_defaulted_field(T::table_name(), &kfv.key, &e.field, &e.reason);
+            fvs.to_mut().remove(&e.field);
+        }
+    }
+    let parsed = match T::parse_field_values(&kfv.key, &fvs) {
+        Ok(parsed) => Some(parsed),
+        Err(parse_errors) => {
+            // the yang violation of a field is more descriptive than its parse error
+            for e in parse_errors {
+                if !errors.iter().any(|y| y.field == e.field) {
+                    errors.push(e);
+                }
+            }
+            None
+        }
+    };
+    record_validation::<T>(internal, &kfv.key, &errors, &defaulted).await?;
+    Ok(parsed.filter(|_| errors.is_empty()))
+}
+
+async fn record_validation<T: SonicDbTable>(
+    internal: &mut Internal,
+    key: &str,
+    errors: &[FieldError],
+    defaulted: &[FieldError],
+) -> Result<()> {
+    let swss_key = format!(
+        "{}{}{}",
+        T::table_name(),
+        DashHaConfigValidationTable::source_key_separator(),
+        key
+    );
+    let internal_key = format!("{}|{}", DashHaConfigValidationTable::table_name(), swss_key);
+
+    if !internal.has_entry(&internal_key, &swss_key) {
+        let table = crate::tables::open_table::<DashHaConfigValidationTable>().await?;
+        internal.add(&internal_key, table, swss_key.clone()).await;
+    }
+
+    let status = match (errors.is_empty(), defaulted.is_empty()) {
+        (false, _) => VALIDATION_STATUS_INVALID,
+        (true, false) => VALIDATION_STATUS_DEGRADED,
+        (true, true) => VALIDATION_STATUS_VALID,
+    };
+    let errors: Vec<&FieldError> = errors.iter().chain(defaulted).collect();
+    let mut invalid_fields: Vec<String> = Vec::new();
+    for e in &errors {
+        if !invalid_fields.contains(&e.field) {
+            invalid_fields.push(e.field.clone());
+        }
+    }
+    let result = DashHaConfigValidationTable {
+        status: status.to_string(),
+        errors: errors
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.reason))
+            .collect::<Vec<_>>()
+            .join(";"),
+        invalid_fields,
+        last_validated_time_in_ms: now_in_millis(),
+    };
+    let previous: Option<DashHaConfigValidationTable> = swss_serde::from_field_values(internal.get(&internal_key)).ok();
+    if previous
+        .as_ref()
+        .is_some_and(|p| p.status == result.status && p.errors == result.errors)
+    {
+        return Ok(());
+    }
+
+    match status {
+        VALIDATION_STATUS_VALID => info!("{swss_key} passed validation"),
+        VALIDATION_STATUS_DEGRADED => warn!(
+            "{swss_key} applied with defaulted fields: {}",
+            result.errors.replace(';', "; ")
+        ),
+        _ => error!("{swss_key} failed validation: {}", result.errors.replace(';', "; ")),
+    }
+    update_field_values(internal.get_mut(&internal_key), &result)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db_structs::parse_warnings;
+    use swss_common::{CxxString, KeyOperation};
+    use swss_common_testing::Redis;
+
+    fn fvs(fields: &[(&str, &str)]) -> FieldValues {
+        fields.iter().map(|(k, v)| (k.to_string(), CxxString::new(v))).collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let dpu = fvs(&[
+            ("state", "up"),
+            ("pa_ipv4", "1.2.3.4"),
+            ("dpu_id", "0"),
+            ("orchagent_zmq_port", "8100"),
+            ("swbus_port", "23606"),
+            ("midplane_ipv4", "127.0.0.1"),
+        ]);
+        assert!(validate::<Dpu>("dpu0", &dpu).is_empty());
+
+        let dpu = fvs(&[
+            ("state", "maybe"),
+            ("pa_ipv4", "1.2.3"),
+            ("pa_ipv6", "1.2.3.4"),
+            ("dpu_id", "-1"),
+            ("orchagent_zmq_port", "0"),
+            ("swbus_port", "23606"),
+        ]);
+        let errors: Vec<String> = validate::<Dpu>("dpu0", &dpu)
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.reason))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "state: 'maybe' is not one of [\"up\", \"down\"]",
+                "pa_ipv4: '1.2.3' is not an IPv4 address",
+                "pa_ipv6: '1.2.3.4' is not an IPv6 address",
+                "dpu_id: '-1' is not an unsigned integer",
+                "orchagent_zmq_port: 0 is out of range 1..65535",
+                "midplane_ipv4: mandatory field is missing, and so is midplane_ipv6",
+            ]
+        );
+
+        // IPv6-only deployments set only the IPv6 addresses
+        let dpu = fvs(&[
+            ("pa_ipv6", "fc00::1"),
+            ("dpu_id", "0"),
+            ("orchagent_zmq_port", "8100"),
+            ("swbus_port", "23606"),
+            ("midplane_ipv6", "fd00::1"),
+        ]);
+        assert!(validate::<Dpu>("dpu0", &dpu).is_empty());
+        let rdpu = fvs(&[
+            ("pa_ipv6", "fc00::2"),
+            ("npu_ipv6", "fc00::100"),
+            ("dpu_id", "0"),
+            ("swbus_port", "23606"),
+        ]);
+        assert!(validate::<RemoteDpu>("rdpu0", &rdpu).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parse_config() {
+        let _redis = Redis::start_config_db();
+        let mut internal = Internal::default();
+        let mut kfv = KeyOpFieldValues {
+            key: "vdpu0".to_string(),
+            operation: KeyOperation::Set,
+            field_values: FieldValues::new(),
+        };
+        let internal_key = "DASH_HA_CONFIG_VALIDATION_TABLE|VDPU|vdpu0";
+
+        assert!(parse_config::<VDpu>(&mut internal, &kfv).await.unwrap().is_none());
+        let result: DashHaConfigValidationTable = swss_serde::from_field_values(internal.get(internal_key)).unwrap();
+        assert_eq!(result.status, VALIDATION_STATUS_INVALID);
+        assert_eq!(result.errors, "main_dpu_ids: mandatory field is missing");
+        assert_eq!(result.invalid_fields, vec!["main_dpu_ids"]);
+
+        kfv.field_values = fvs(&[("main_dpu_ids", "dpu0")]);
+        let vdpu = parse_config::<VDpu>(&mut internal, &kfv).await.unwrap().unwrap();
+        assert_eq!(vdpu.main_dpu_ids, vec!["dpu0"]);
+        let result: DashHaConfigValidationTable = swss_serde::from_field_values(internal.get(internal_key)).unwrap();
+        assert_eq!(result.status, VALIDATION_STATUS_VALID);
+        assert!(result.errors.is_empty());
+        assert!(result.invalid_fields.is_empty());
+
+        // all invalid fields are reported at once
+        let kfv = KeyOpFieldValues {
+            key: "rdpu0".to_string(),
+            operation: KeyOperation::Set,
+            field_values: fvs(&[("pa_ipv4", "1.2.3"), ("dpu_id", "x"), ("swbus_port", "23606")]),
+        };
+        assert!(parse_config::<RemoteDpu>(&mut internal, &kfv).await.unwrap().is_none());
+        let result: DashHaConfigValidationTable =
+            swss_serde::from_field_values(internal.get("DASH_HA_CONFIG_VALIDATION_TABLE|REMOTE_DPU|rdpu0")).unwrap();
+        assert_eq!(result.invalid_fields, vec!["pa_ipv4", "npu_ipv4", "dpu_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_config_lenient() {
+        let _redis = Redis::start_config_db();
+        let mut internal = Internal::default();
+        let kfv = KeyOpFieldValues {
+            key: "GLOBAL".to_string(),
+            operation: KeyOperation::Set,
+            field_values: fvs(&[
+                ("cp_data_channel_port", "7000"),
+                ("dp_channel_probe_interval_ms", "0"),
+                ("dpu_bfd_probe_multiplier", "x"),
+            ]),
+        };
+
+        // the invalid optional fields fall back to their default instead of rejecting the entry
+        let config = parse_config::<DashHaGlobalConfig>(&mut internal, &kfv)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.cp_data_channel_port, Some(7000));
+        assert_eq!(config.dp_channel_probe_interval_ms, None);
+        assert_eq!(config.dpu_bfd_probe_multiplier, None);
+        let result: DashHaConfigValidationTable =
+            swss_serde::from_field_values(internal.get("DASH_HA_CONFIG_VALIDATION_TABLE|DASH_HA_GLOBAL_CONFIG|GLOBAL"))
+                .unwrap();
+        assert_eq!(result.status, VALIDATION_STATUS_DEGRADED);
+        assert_eq!(
+            result.invalid_fields,
+            vec!["dp_channel_probe_interval_ms", "dpu_bfd_probe_multiplier"]
+        );
+        assert!(parse_warnings()["DASH_HA_GLOBAL_CONFIG|dpu_bfd_probe_multiplier|malformed"] >= 1);
+    }
+}
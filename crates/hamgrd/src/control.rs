@@ -0,0 +1,222 @@
+//! Local control socket
+//!
+//! A unix domain socket that exposes the management commands of hamgrd without going through swbusd,
+//! so hamgrd can still be debugged when the bus is down. Each connection sends one command per line
+//! and gets one JSON line back, e.g. `echo health | socat - UNIX-CONNECT:/var/run/hamgrd/dpu0.sock`.
+//! The socket is only accessible to the user hamgrd runs as.
+//!
+//! Supported commands:
+//! - `health`: swbusd connectivity and running actors.
+//! - `log-level [<level>]`: get or set the log level.
+//! - `actors`: list running actors.
+//! - `dump-state <resource_type>/<resource_id>`: dump the state of an actor.
+//! - `techsupport [<path>]`: collect a techsupport dump, in subdirectory `<path>` of `/var/dump` if given.
+use crate::mgmt_client::ManagementClient;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use sonic_common::log;
+use std::fs::{DirBuilder, Permissions};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_edge::swbus_proto::swbus::{
+    request_response::ResponseBody, ManagementRequestType, SwbusErrorCode, SwbusMessage,
+};
+use swbus_edge::SwbusEdgeRuntime;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Time to wait for an actor to respond to a state dump.
+const DUMP_STATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait for the techsupport dump to complete.
+const TECHSUPPORT_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn default_socket_path(slot_id: u32) -> String {
+    format!("/var/run/hamgrd/dpu{slot_id}.sock")
+}
+
+pub struct ControlServer {
+    rt: Arc<SwbusEdgeRuntime>,
+    client: Mutex<ManagementClient>,
+}
+
+impl ControlServer {
+    /// Bind the control socket at `path`, replacing any stale socket left by a previous instance, and
+    /// start serving requests. The socket is made accessible to the owner only.
+    pub fn spawn(rt: Arc<SwbusEdgeRuntime>, path: &str) -> Result<JoinHandle<()>> {
+        let path = Path::new(path);
+        if let Some(dir) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .context(format!("creating {}", dir.display()))?;
+        }
+        if path.exists() {
+            std::fs::remove_file(path).context(format!("removing stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path).context(format!("binding {}", path.display()))?;
+        std::fs::set_permissions(path, Permissions::from_mode(0o600))
+            .context(format!("restricting access to {}", path.display()))?;
+        info!("Control socket is listening on {}", path.display());
+
+        let client = Mutex::new(ManagementClient::new(rt.clone(), "control"));
+        let server = Arc::new(ControlServer { rt, client });
+        Ok(tokio::task::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let server = server.clone();
+                        tokio::task::spawn(async move { server.serve(stream).await });
+                    }
+                    Err(e) => error!("Failed to accept control connection: {e}"),
+                }
+            }
+        }))
+    }
+
+    async fn serve(&self, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match self.handle_command(&line).await {
+                Ok(result) => json!({ "result": result }),
+                Err(e) => json!({ "error": format!("{e:#}") }),
+            };
+            let mut response = response.to_string();
+            response.push('\n');
+            if writer.write_all(response.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn handle_command(&self, line: &str) -> Result<Value> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+
+        match command {
+            "health" => Ok(self.health().await),
+            "log-level" => {
+                if let Some(level) = arg {
+                    log::set_log_level(level).map_err(|e| anyhow!("{e}"))?;
+                }
+                Ok(json!(log::get_log_level()))
+            }
+            "actors" => Ok(json!(running_actors())),
+            "dump-state" => {
+                let actor = arg.ok_or(anyhow!("usage: dump-state <resource_type>/<resource_id>"))?;
+                self.dump_state(actor).await
+            }
+            "techsupport" => self.techsupport(arg).await,
+            _ => Err(anyhow!("unknown command: {command}")),
+        }
+    }
+
+    async fn health(&self) -> Value {
+        json!({
+            "swbusd_connected": self.rt.swbusd_connected().await,
+            "actors": running_actors().len(),
+        })
+    }
+
+    async fn dump_state(&self, actor: &str) -> Result<Value> {
+        let (resource_type, resource_id) = actor
+            .split_once('/')
+            .ok_or(anyhow!("actor must be in the form of <resource_type>/<resource_id>"))?;
+        let destination = self.rt.new_sp(resource_type, resource_id);
+
+        let mut client = self.client.lock().await;
+        let request = client.new_request(destination, ManagementRequestType::HamgrdGetActorState, &[]);
+        let payload = query_payload(&mut client, request, DUMP_STATE_TIMEOUT).await?;
+        Ok(serde_json::from_str(&payload)?)
+    }
+
+    async fn techsupport(&self, path: Option<&str>) -> Result<Value> {
+        let destination = self.rt.new_sp("techsupport", "0");
+        let arguments: Vec<_> = path.map(|path| ("path", path)).into_iter().collect();
+
+        let mut client = self.client.lock().await;
+        let request = client.new_request(destination, ManagementRequestType::HamgrdTechsupportDump, &arguments);
+        let tarball = query_payload(&mut client, request, TECHSUPPORT_TIMEOUT).await?;
+        Ok(json!(tarball))
+    }
+}
+
+fn running_actors() -> Vec<String> {
+    match crate::slot::current().actor_runtime() {
+        Some(runtime) => runtime
+            .actors()
+            .iter()
+            .map(|sp| format!("{}/{}", sp.resource_type, sp.resource_id))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+async fn query_payload(client: &mut ManagementClient, request: SwbusMessage, wait: Duration) -> Result<String> {
+    let response = client
+        .request(request, wait)
+        .await
+        .ok_or(anyhow!("request timed out"))?;
+    if response.error_code != SwbusErrorCode::Ok as i32 {
+        return Err(anyhow!("{}", response.error_message));
+    }
+    match response.response_body {
+        Some(ResponseBody::ManagementQueryResult(result)) => Ok(result.value),
+        _ => Err(anyhow!("unexpected response: {response:?}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::test::create_actor_runtime;
+
+    async fn send_command(stream: &mut BufReader<UnixStream>, command: &str) -> Value {
+        stream
+            .get_mut()
+            .write_all(format!("{command}\n").as_bytes())
+            .await
+            .unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn control_socket_commands() {
+        let runtime = create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let path = std::env::temp_dir().join(format!("hamgrd-test-{}.sock", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let handle = ControlServer::spawn(runtime.get_swbus_edge(), path).unwrap();
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = BufReader::new(UnixStream::connect(path).await.unwrap());
+
+        let health = send_command(&mut stream, "health").await;
+        assert_eq!(health["result"]["swbusd_connected"], json!(false));
+
+        let unknown = send_command(&mut stream, "reboot").await;
+        assert_eq!(unknown["error"], json!("unknown command: reboot"));
+
+        let dump = send_command(&mut stream, "dump-state vdpu").await;
+        assert!(dump["error"]
+            .as_str()
+            .unwrap()
+            .contains("<resource_type>/<resource_id>"));
+
+        handle.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
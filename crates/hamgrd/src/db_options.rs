@@ -0,0 +1,249 @@
+//! Database connection options
+//!
+//! By default hamgrd loads the SONiC database config from its standard path and connects to every
+//! db over the unix socket the config gives. To run in containers, tests and on other platforms, the
+//! config path, the redis endpoint and the timeouts can be set on the command line, or through the
+//! environment variables named in [`DbArgs`] when not on the command line.
+use crate::readiness::DATABASE_GLOBAL_CONFIG;
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+use swss_common::DbConnector;
+use tokio::time::timeout;
+
+const DEFAULT_DB_TIMEOUT_MS: u32 = 11000;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static DB_OPTIONS: RwLock<Option<DbOptions>> = RwLock::new(None);
+
+#[derive(Args, Debug, Default)]
+pub struct DbArgs {
+    // Path of the database config. Env HAMGRD_DB_CONFIG, defaults to
+    // /var/run/redis/sonic-db/database_global.json.
+    #[arg(long)]
+    pub db_config: Option<String>,
+    // Connect to every db at this redis host over TCP, instead of the instance the database config
+    // gives for it. Env HAMGRD_REDIS_HOST.
+    #[arg(long)]
+    pub redis_host: Option<String>,
+    // Port of --redis-host. Env HAMGRD_REDIS_PORT, defaults to 6379.
+    #[arg(long)]
+    pub redis_port: Option<u16>,
+    // Connect to the instances of the database config over TCP instead of their unix sockets.
+    #[arg(long)]
+    pub db_tcp: bool,
+    // Timeout of db operations in milliseconds. Env HAMGRD_DB_TIMEOUT_MS, defaults to 11000.
+    #[arg(long)]
+    pub db_timeout_ms: Option<u32>,
+    // Timeout of the operations on one db, e.g. DPU_APPL_DB=2000.
+    #[arg(long, value_parser = parse_db_timeout)]
+    pub db_timeout: Vec<(String, u32)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DbOptions {
+    pub config_path: String,
+    pub redis_endpoint: Option<(String, u16)>,
+    pub tcp: bool,
+    pub timeout_ms: u32,
+    pub timeouts_ms: HashMap<String, u32>,
+    /// Give up connecting to a db after this long
+    pub connect_timeout: Duration,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        DbOptions {
+            config_path: DATABASE_GLOBAL_CONFIG.to_string(),
+            redis_endpoint: None,
+            tcp: false,
+            timeout_ms: DEFAULT_DB_TIMEOUT_MS,
+            timeouts_ms: HashMap::new(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+}
+
+impl DbOptions {
+    /// Options from the command line, falling back to `env` and then to the defaults.
+    pub fn from_args(args: &DbArgs, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let defaults = DbOptions::default();
+        let redis_host = args.redis_host.clone().or_else(|| env("HAMGRD_REDIS_HOST"));
+        let redis_port = match args.redis_port {
+            Some(port) => port,
+            None => env("HAMGRD_REDIS_PORT")
+                .map(|port| port.parse().context("HAMGRD_REDIS_PORT"))
+                .transpose()?
+                .unwrap_or(6379),
+        };
+        let timeout_ms = match args.db_timeout_ms {
+            Some(ms) => ms,
+            None => env("HAMGRD_DB_TIMEOUT_MS")
+                .map(|ms| ms.parse().context("HAMGRD_DB_TIMEOUT_MS"))
+                .transpose()?
+                .unwrap_or(defaults.timeout_ms),
+        };
+        Ok(DbOptions {
+            config_path: args
+                .db_config
+                .clone()
+                .or_else(|| env("HAMGRD_DB_CONFIG"))
+                .unwrap_or(defaults.config_path),
+            redis_endpoint: redis_host.map(|host| (host, redis_port)),
+            tcp: args.db_tcp,
+            timeout_ms,
+            timeouts_ms: args.db_timeout.iter().cloned().collect(),
+            connect_timeout: defaults.connect_timeout,
+        })
+    }
+
+    pub fn timeout_ms(&self, db_name: &str) -> u32 {
+        self.timeouts_ms.get(db_name).copied().unwrap_or(self.timeout_ms)
+    }
+
+    /// Connect to `db_name` of `container_name`, "" for the host's own databases.
+    pub async fn connect(&self, db_name: &str, container_name: &str) -> Result<DbConnector> {
+        let timeout_ms = self.timeout_ms(db_name);
+        let connect = async {
+            match &self.redis_endpoint {
+                Some((host, port)) => {
+                    let db_id = db_id(Path::new(&self.config_path), db_name, container_name)?;
+                    Ok(DbConnector::new_tcp_async(db_id, host, *port, timeout_ms).await?)
+                }
+                None => Ok(DbConnector::new_keyed_async(db_name, self.tcp, timeout_ms, container_name, "").await?),
+            }
+        };
+        timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| anyhow!("Connecting to db `{db_name}` timed out"))?
+            .map_err(|e: anyhow::Error| anyhow!("Connecting to db `{db_name}`: {e:#}"))
+    }
+}
+
+/// Use `options` for all db connections from now on.
+pub fn set_db_options(options: DbOptions) {
+    *DB_OPTIONS.write().unwrap() = Some(options);
+}
+
+pub fn db_options() -> DbOptions {
+    DB_OPTIONS.read().unwrap().clone().unwrap_or_default()
+}
+
+fn parse_db_timeout(s: &str) -> Result<(String, u32), String> {
+    let (db, ms) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <DB>=<MILLISECONDS>, got '{s}'"))?;
+    let ms = ms.parse().map_err(|e| format!("invalid timeout of {db}: {e}"))?;
+    Ok((db.to_string(), ms))
+}
+
+#[derive(Deserialize)]
+struct DatabaseConfig {
+    #[serde(rename = "INCLUDES", default)]
+    includes: Vec<DatabaseConfigInclude>,
+    #[serde(rename = "DATABASES", default)]
+    databases: HashMap<String, DatabaseEntry>,
+}
+
+#[derive(Deserialize)]
+struct DatabaseConfigInclude {
+    include: String,
+    #[serde(default)]
+    container_name: String,
+}
+
+#[derive(Deserialize)]
+struct DatabaseEntry {
+    id: i32,
+}
+
+/// The id of `db_name` of `container_name` in the database config at `config_path`. A global config
+/// refers to the config of each container, relative to its own path.
+fn db_id(config_path: &Path, db_name: &str, container_name: &str) -> Result<i32> {
+    let config: DatabaseConfig = serde_json::from_str(
+        &std::fs::read_to_string(config_path).with_context(|| format!("reading {}", config_path.display()))?,
+    )
+    .with_context(|| format!("parsing {}", config_path.display()))?;
+    if !config.includes.is_empty() {
+        let include = config
+            .includes
+            .iter()
+            .find(|include| include.container_name == container_name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} has no config of container `{container_name}`",
+                    config_path.display()
+                )
+            })?;
+        let path = config_path.parent().unwrap_or(Path::new("")).join(&include.include);
+        return db_id(&path, db_name, "");
+    }
+    config
+        .databases
+        .get(db_name)
+        .map(|db| db.id)
+        .ok_or_else(|| anyhow!("{} has no db `{db_name}`", config_path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn options_from_args_env_and_defaults() {
+        let env: HashMap<&str, &str> = [
+            ("HAMGRD_DB_CONFIG", "/etc/db.json"),
+            ("HAMGRD_REDIS_HOST", "10.0.0.1"),
+            ("HAMGRD_DB_TIMEOUT_MS", "3000"),
+        ]
+        .into();
+        let args = DbArgs {
+            redis_port: Some(6380),
+            db_timeout: vec![parse_db_timeout("DPU_APPL_DB=500").unwrap()],
+            ..Default::default()
+        };
+        let options = DbOptions::from_args(&args, |key| env.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(options.config_path, "/etc/db.json");
+        assert_eq!(options.redis_endpoint, Some(("10.0.0.1".to_string(), 6380)));
+        assert_eq!(options.timeout_ms("APPL_DB"), 3000);
+        assert_eq!(options.timeout_ms("DPU_APPL_DB"), 500);
+
+        let options = DbOptions::from_args(&DbArgs::default(), |_| None).unwrap();
+        assert_eq!(options, DbOptions::default());
+        assert!(parse_db_timeout("APPL_DB").is_err());
+        assert!(DbOptions::from_args(&DbArgs::default(), |_| Some("x".to_string())).is_err());
+    }
+
+    #[test]
+    fn db_id_follows_container_includes() {
+        let dir = std::env::temp_dir().join(format!("hamgrd-db-options-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("redis0")).unwrap();
+        std::fs::write(
+            dir.join("database_global.json"),
+            r#"{"INCLUDES": [{"include": "database_config.json"},
+                             {"include": "redis0/database_config.json", "container_name": "dpu0"}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("database_config.json"),
+            r#"{"DATABASES": {"APPL_DB": {"id": 0, "instance": "redis"}, "STATE_DB": {"id": 6}}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("redis0/database_config.json"),
+            r#"{"DATABASES": {"DPU_APPL_DB": {"id": 15}}}"#,
+        )
+        .unwrap();
+
+        let global = dir.join("database_global.json");
+        assert_eq!(db_id(&global, "STATE_DB", "").unwrap(), 6);
+        assert_eq!(db_id(&global, "DPU_APPL_DB", "dpu0").unwrap(), 15);
+        assert!(db_id(&global, "DPU_APPL_DB", "").is_err());
+        assert!(db_id(&global, "APPL_DB", "dpu1").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,97 +1,213 @@
+use crate::TableSource;
 use anyhow::{Context, Result};
 use chrono::DateTime;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::{formats::CommaSeparator, serde_as, skip_serializing_none, StringWithSeparator};
 use sonicdb_derive::SonicDb;
-use swss_common::{DbConnector, Table};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use swss_common::{DbConnector, FieldValues, SonicDbTable, Table};
 use swss_serde::from_table;
+use tracing::{debug, warn};
 
 /// Format: "Tue Jun 04 09:00:00 PM UTC 2024"
 const TIMESTAMP_FORMAT: &str = "%a %b %d %I:%M:%S %p UTC %Y";
 
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2112-ha-global-configurations>
-#[derive(Serialize, Deserialize, Default, Debug, SonicDb)]
-#[sonicdb(table_name = "DASH_HA_GLOBAL_CONFIG", key_separator = "|", db_name = "CONFIG_DB")]
-pub struct DashHaGlobalConfig {
-    // The port of control plane data channel, used for bulk sync.
-    pub cp_data_channel_port: Option<u16>,
-    // The destination port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_dst_port: Option<u16>,
-    // The min source port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_src_port_min: Option<u16>,
-    // The max source port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_src_port_max: Option<u16>,
-    // The interval of sending each DPU-to-DPU data path probe.
-    pub dp_channel_probe_interval_ms: Option<u32>,
-    // The number of probe failure needed to consider data plane channel is dead.
-    pub dp_channel_probe_fail_threshold: Option<u32>,
-    // The interval of DPU BFD probe in milliseconds.
-    pub dpu_bfd_probe_interval_in_ms: Option<u32>,
-    // The number of DPU BFD probe failure before probe down.
-    pub dpu_bfd_probe_multiplier: Option<u32>,
-    // The name of the vnet used for VNET tunnel route
-    pub vnet_name: Option<String>,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2111-dpu--vdpu-definitions>
-#[skip_serializing_none]
-#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug, SonicDb)]
-#[sonicdb(table_name = "DPU", key_separator = "|", db_name = "CONFIG_DB")]
-pub struct Dpu {
-    pub state: Option<String>,
-    pub vip_ipv4: Option<String>,
-    pub vip_ipv6: Option<String>,
-    pub pa_ipv4: String,
-    pub pa_ipv6: Option<String>,
-    pub dpu_id: u32,
-    pub vdpu_id: Option<String>,
-    pub orchagent_zmq_port: u16,
-    pub swbus_port: u16,
-    pub midplane_ipv4: String,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2111-dpu--vdpu-definitions>
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize, Clone, SonicDb)]
-#[sonicdb(table_name = "REMOTE_DPU", key_separator = "|", db_name = "CONFIG_DB")]
-pub struct RemoteDpu {
-    pub pa_ipv4: String,
-    pub pa_ipv6: Option<String>,
-    pub npu_ipv4: String,
-    pub npu_ipv6: Option<String>,
-    pub dpu_id: u32,
-    pub swbus_port: u16,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2111-dpu--vdpu-definitions>
-#[serde_as]
-#[derive(Deserialize, Clone, SonicDb)]
-#[sonicdb(table_name = "VDPU", key_separator = "|", db_name = "CONFIG_DB")]
-pub struct VDpu {
-    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    pub main_dpu_ids: Vec<String>,
+/// A field of a table entry that could not be parsed into its typed value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FieldError {
+    pub key: String,
+    pub field: String,
+    pub reason: String,
 }
 
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize, SonicDb)]
-#[sonicdb(
-    table_name = "BFD_SESSION_TABLE",
-    key_separator = ":",
-    db_name = "DPU_APPL_DB",
-    is_dpu = "true"
-)]
-pub struct BfdSessionTable {
-    pub tx_interval: Option<u32>,
-    pub rx_interval: Option<u32>,
-    pub multiplier: Option<u32>,
-    pub multihop: bool,
-    pub shutdown: bool,
-    pub local_addr: String,
-    #[serde(rename = "type")]
-    pub session_type: Option<String>,
-}
-
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default, Debug)]
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.key, self.field, self.reason)
+    }
+}
+
+/// Field by field parsing of a table entry. Unlike `swss_serde::from_field_values`, which stops at
+/// the first bad field, all invalid fields of the entry are reported.
+pub trait ParseFieldValues: Sized {
+    /// Names of the fields of the table as stored in the db.
+    fn field_names() -> &'static [&'static str];
+
+    /// In lenient tables a malformed optional field falls back to its default with a warning instead
+    /// of rejecting the whole entry.
+    fn lenient() -> bool {
+        false
+    }
+
+    fn parse_field_values(key: &str, fvs: &FieldValues) -> std::result::Result<Self, Vec<FieldError>>;
+}
+
+/// Number of entries parsed with a warning per "<table>|<field>|<warning>": fields written by a
+/// different schema version than ours, e.g. newer tooling added a field we don't know yet, or
+/// malformed fields of lenient tables.
+static PARSE_WARNINGS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+fn count_parse_warning(table: &str, field: &str, warning: &str) -> u64 {
+    let mut warnings = PARSE_WARNINGS.lock().unwrap();
+    let count = warnings.entry(format!("{table}|{field}|{warning}")).or_insert(0);
+    *count += 1;
+    *count
+}
+
+fn note_schema_mismatch(table: &str, key: &str, field: &str, warning: &str) {
+    // only log the first occurrence, the rest is in the counters
+    if count_parse_warning(table, field, warning) == 1 {
+        warn!("{table}|{key}: {warning} field {field}");
+    }
+}
+
+/// Record that a malformed optional field of a lenient table fell back to its default.
+pub fn note_defaulted_field(table: &str, key: &str, field: &str, reason: &str) {
+    count_parse_warning(table, field, "malformed");
+    warn!("{table}|{key}: malformed field {field} falls back to its default: {reason}");
+}
+
+/// Snapshot of the parse warning counters.
+pub fn parse_warnings() -> BTreeMap<String, u64> {
+    PARSE_WARNINGS.lock().unwrap().clone()
+}
+
+fn parse_field<T: DeserializeOwned>(
+    key: &str,
+    fvs: &FieldValues,
+    field: &str,
+    errors: &mut Vec<FieldError>,
+) -> Option<T> {
+    match swss_serde::from_field_value(fvs.get(field)) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            errors.push(FieldError {
+                key: key.to_string(),
+                field: field.to_string(),
+                reason: e.to_string(),
+            });
+            None
+        }
+    }
+}
+
+/// Parse an optional field. A missing field takes the default and is counted, since it usually
+/// means the entry was written by older tooling. That is routine, so it is only logged at debug.
+fn parse_optional_field<T: DeserializeOwned>(
+    table: &str,
+    key: &str,
+    fvs: &FieldValues,
+    field: &str,
+    errors: &mut Vec<FieldError>,
+) -> Option<Option<T>> {
+    if !fvs.contains_key(field) {
+        count_parse_warning(table, field, "missing optional");
+        debug!("{table}|{key}: missing optional field {field}");
+        return Some(None);
+    }
+    parse_field(key, fvs, field, errors)
+}
+
+/// Parse an optional field of a lenient table, which never fails.
+fn parse_lenient_field<T: DeserializeOwned>(
+    table: &str,
+    key: &str,
+    fvs: &FieldValues,
+    field: &str,
+) -> Option<Option<T>> {
+    let mut errors = Vec::new();
+    let value = parse_optional_field(table, key, fvs, field, &mut errors);
+    for e in errors {
+        note_defaulted_field(table, key, field, &e.reason);
+    }
+    Some(value.flatten())
+}
+
+/// Parse a table entry field by field. The error lists every invalid field.
+pub fn parse_entry<T: ParseFieldValues>(key: &str, fvs: &FieldValues) -> Result<T> {
+    T::parse_field_values(key, fvs).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        anyhow::anyhow!("invalid entry: {}", errors.join("; "))
+    })
+}
+
+/// Count the fields of the entry that are not in our schema. They are ignored when parsing.
+fn note_unknown_fields(table: &str, key: &str, fvs: &FieldValues, field_names: &[&str]) {
+    let unknown = |f: &&String| !field_names.contains(&f.as_str());
+    for field in fvs.keys().filter(unknown) {
+        note_schema_mismatch(table, key, field, "unknown");
+    }
+}
+
+/// Write `value` into the field values of an existing entry. Fields that are not part of `T`, e.g.
+/// added by a newer release, are kept as they are so they round-trip through our writes.
+pub fn update_field_values<T: Serialize + ParseFieldValues>(fvs: &mut FieldValues, value: &T) -> Result<()> {
+    let new_fvs = swss_serde::to_field_values(value)?;
+    fvs.retain(|field, _| !T::field_names().contains(&field.as_str()));
+    fvs.extend(new_fvs);
+    Ok(())
+}
+
+// Table structs generated from schema/db_tables.yaml. Tables that need custom serialization are
+// defined below.
+include!(concat!(env!("OUT_DIR"), "/db_tables.rs"));
+
+/// The IPv4 address if set, otherwise the IPv6 one.
+fn preferred_ip(ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) -> Option<IpAddr> {
+    ipv4.map(IpAddr::V4).or(ipv6.map(IpAddr::V6))
+}
+
+impl Dpu {
+    /// The PA address, IPv6 in IPv6-only deployments.
+    pub fn pa_ip(&self) -> Option<IpAddr> {
+        preferred_ip(self.pa_ipv4, self.pa_ipv6)
+    }
+
+    /// The midplane address orchagent is reached at, IPv6 in IPv6-only deployments.
+    pub fn midplane_ip(&self) -> Option<IpAddr> {
+        preferred_ip(self.midplane_ipv4, self.midplane_ipv6)
+    }
+}
+
+impl RemoteDpu {
+    /// The PA address, IPv6 in IPv6-only deployments.
+    pub fn pa_ip(&self) -> Option<IpAddr> {
+        preferred_ip(self.pa_ipv4, self.pa_ipv6)
+    }
+
+    /// The address of the NPU of the DPU, IPv6 in IPv6-only deployments.
+    pub fn npu_ip(&self) -> Option<IpAddr> {
+        preferred_ip(self.npu_ipv4, self.npu_ipv6)
+    }
+}
+
+impl BfdSessionTable {
+    /// The key of the session to `peer_ip` in the default VRF.
+    pub fn key(peer_ip: &str) -> String {
+        let sep = Self::source_key_separator();
+        format!("default{sep}default{sep}{peer_ip}")
+    }
+
+    /// The discriminator of the `local_addr` end of the session between `local_addr` and `peer_addr`.
+    /// Both ends derive it from the addresses alone, so the local discriminator of one end is the remote
+    /// discriminator of the other without any exchange.
+    pub fn discriminator(local_addr: &str, peer_addr: &str) -> u32 {
+        // FNV-1a. 0 is not a valid discriminator.
+        let hash = format!("{local_addr}|{peer_addr}")
+            .bytes()
+            .fold(0x811c_9dc5_u32, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            });
+        hash.max(1)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DpuPmonStateType {
     Up,
@@ -100,8 +216,20 @@ pub enum DpuPmonStateType {
     Unknown,
 }
 
+/// Operational status of a DPU, consolidated from its power, PCIe, pmon and BFD states by the DPU actor.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum DpuOperStatus {
+    /// Powered, attached, and pmon and BFD report it up
+    Up,
+    /// Reachable over the midplane with a running control plane, but its data plane or BFD is down
+    Degraded,
+    Down,
+    PoweredOff,
+}
+
 /// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/pmon/smartswitch-pmon.md#dpu_state-definition>
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, SonicDb)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema, SonicDb)]
 #[sonicdb(table_name = "DPU_STATE", key_separator = "|", db_name = "CHASSIS_STATE_DB")]
 pub struct DpuState {
     #[serde(default)]
@@ -111,6 +239,7 @@ pub struct DpuState {
         deserialize_with = "timestamp_deserialize",
         serialize_with = "timestamp_serialize"
     )]
+    #[schemars(with = "String")]
     pub dpu_midplane_link_time: i64,
     #[serde(default)]
     pub dpu_control_plane_state: DpuPmonStateType,
@@ -119,6 +248,7 @@ pub struct DpuState {
         deserialize_with = "timestamp_deserialize",
         serialize_with = "timestamp_serialize"
     )]
+    #[schemars(with = "String")]
     pub dpu_control_plane_time: i64,
     #[serde(default)]
     pub dpu_data_plane_state: DpuPmonStateType,
@@ -127,6 +257,7 @@ pub struct DpuState {
         deserialize_with = "timestamp_deserialize",
         serialize_with = "timestamp_serialize"
     )]
+    #[schemars(with = "String")]
     pub dpu_data_plane_time: i64,
 }
 
@@ -145,7 +276,7 @@ impl Default for DpuState {
 
 /// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/BFD/SmartSwitchDpuLivenessUsingBfd.md#27-dpu-bfd-session-state-updates>
 #[serde_as]
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, SonicDb)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, JsonSchema, SonicDb)]
 #[sonicdb(
     table_name = "DASH_BFD_PROBE_STATE",
     key_separator = "|",
@@ -154,6 +285,7 @@ impl Default for DpuState {
 )]
 pub struct DashBfdProbeState {
     #[serde(default)]
+    #[schemars(with = "String")]
     #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
     pub v4_bfd_up_sessions: Vec<String>,
     #[serde(
@@ -161,8 +293,10 @@ pub struct DashBfdProbeState {
         deserialize_with = "timestamp_deserialize",
         serialize_with = "timestamp_serialize"
     )]
+    #[schemars(with = "String")]
     pub v4_bfd_up_sessions_timestamp: i64,
     #[serde(default)]
+    #[schemars(with = "String")]
     #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
     pub v6_bfd_up_sessions: Vec<String>,
     #[serde(
@@ -170,6 +304,7 @@ pub struct DashBfdProbeState {
         deserialize_with = "timestamp_deserialize",
         serialize_with = "timestamp_serialize"
     )]
+    #[schemars(with = "String")]
     pub v6_bfd_up_sessions_timestamp: i64,
 }
 
@@ -213,233 +348,27 @@ pub fn now_in_millis() -> i64 {
     chrono::Utc::now().timestamp_millis()
 }
 
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2121-ha-set-configurations>
-#[serde_as]
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize, SonicDb)]
-#[sonicdb(table_name = "DASH_HA_SET_CONFIG_TABLE", key_separator = ":", db_name = "APPL_DB")]
-pub struct DashHaSetConfigTable {
-    pub version: String,
-    pub vip_v4: String,
-    pub vip_v6: Option<String>,
-    // dpu or switch
-    pub owner: Option<String>,
-    // dpu or eni
-    pub scope: Option<String>,
-    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    pub vdpu_ids: Vec<String>,
-    pub pinned_vdpu_bfd_probe_states: Option<String>,
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub preferred_vdpu_ids: Option<Vec<String>>,
-    pub preferred_standalone_vdpu_index: Option<u32>,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2311-ha-set-configurations>
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize, Default, PartialEq, Eq, SonicDb)]
-#[sonicdb(
-    table_name = "DASH_HA_SET_TABLE",
-    key_separator = ":",
-    db_name = "DPU_APPL_DB",
-    is_dpu = "true"
-)]
-pub struct DashHaSetTable {
-    // Config version.
-    pub version: String,
-    // IPv4 Data path VIP.
-    pub vip_v4: String,
-    // IPv4 Data path VIP.
-    pub vip_v6: Option<String>,
-    // Owner of HA state machine. It can be controller, switch.
-    pub owner: Option<String>,
-    // Scope of HA set. It can be dpu, eni.
-    pub scope: Option<String>,
-    // The IP address of local NPU. It can be IPv4 or IPv6. Used for setting up the BFD session.
-    pub local_npu_ip: String,
-    // The IP address of local DPU.
-    pub local_ip: String,
-    // The IP address of peer DPU.
-    pub peer_ip: String,
-    // The port of control plane data channel, used for bulk sync.
-    pub cp_data_channel_port: Option<u16>,
-    // The destination port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_dst_port: Option<u16>,
-    // The min source port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_src_port_min: Option<u16>,
-    // The max source port used when tunneling packetse via DPU-to-DPU data plane channel.
-    pub dp_channel_src_port_max: Option<u16>,
-    // The interval of sending each DPU-to-DPU data path probe.
-    pub dp_channel_probe_interval_ms: Option<u32>,
-    // The number of probe failure needed to consider data plane channel is dead.
-    pub dp_channel_probe_fail_threshold: Option<u32>,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/vxlan/Overlay%20ECMP%20ehancements.md#22-app-db>
-#[skip_serializing_none]
-#[serde_as]
-#[derive(Debug, Deserialize, Serialize, PartialEq, SonicDb)]
-#[sonicdb(table_name = "VNET_ROUTE_TUNNEL_TABLE", key_separator = ":", db_name = "APPL_DB")]
-pub struct VnetRouteTunnelTable {
-    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    pub endpoint: Vec<String>,
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub endpoint_monitor: Option<Vec<String>>,
-    pub monitoring: Option<String>,
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub primary: Option<Vec<String>>,
-    pub rx_monitor_timer: Option<u32>,
-    pub tx_monitor_timer: Option<u32>,
-    pub check_directly_connected: Option<bool>,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-dpu-scope-dpu-driven-setup.md#2122-ha-scope-configurations>
-#[skip_serializing_none]
-#[serde_as]
-#[derive(Debug, Deserialize, Serialize, PartialEq, SonicDb)]
-#[sonicdb(table_name = "DASH_HA_SCOPE_CONFIG_TABLE", key_separator = ":", db_name = "APPL_DB")]
-pub struct DashHaScopeConfigTable {
-    pub version: u32,
-    pub disable: bool,
-    pub desired_ha_state: String,
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub approved_pending_operation_ids: Option<Vec<String>>,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2312-ha-scope-configurations>
-#[skip_serializing_none]
-#[serde_as]
-#[derive(Debug, Deserialize, Serialize, PartialEq, SonicDb)]
-#[sonicdb(
-    table_name = "DASH_HA_SCOPE_TABLE",
-    key_separator = ":",
-    db_name = "DPU_APPL_DB",
-    is_dpu = "true"
-)]
-pub struct DashHaScopeTable {
-    pub version: u32,
-    pub disable: bool,
-    pub ha_role: String,
-    pub flow_reconcile_requested: bool,
-    pub activate_role_requested: bool,
+pub fn get_dpu_config_from_db(dpu_id: u32) -> Result<Dpu> {
+    read_dpu_table()?
+        .remove(&dpu_id)
+        .ok_or_else(|| anyhow::anyhow!("DPU entry not found for slot {}", dpu_id))
 }
 
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2342-ha-scope-state>
-#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Clone, SonicDb)]
-#[sonicdb(
-    table_name = "DASH_HA_SCOPE_STATE",
-    key_separator = "|",
-    db_name = "DPU_STATE_DB",
-    is_dpu = "true"
-)]
-pub struct DpuDashHaScopeState {
-    // The last update time of this state in milliseconds.
-    pub last_updated_time: i64,
-    // The current HA role confirmed by ASIC. Please refer to the HA states defined in HA HLD.
-    pub ha_role: String,
-    // The time when HA role is moved into current one in milliseconds.
-    pub ha_role_start_time: i64,
-    // The current term confirmed by ASIC.
-    pub ha_term: String,
-    // DPU is pending on role activation.
-    pub activate_role_pending: bool,
-    // Flow reconcile is requested and pending approval.
-    pub flow_reconcile_pending: bool,
-    // Brainsplit is detected, and DPU is pending on recovery.
-    pub brainsplit_recover_pending: bool,
-}
-
-/// <https://github.com/sonic-net/SONiC/blob/master/doc/smart-switch/high-availability/smart-switch-ha-detailed-design.md#2342-ha-scope-state>
-#[skip_serializing_none]
-#[serde_as]
-#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Clone, SonicDb)]
-#[sonicdb(table_name = "DASH_HA_SCOPE_STATE", key_separator = "|", db_name = "STATE_DB")]
-pub struct NpuDashHaScopeState {
-    // HA scope creation time in milliseconds.
-    pub creation_time_in_ms: i64, /*todo: where is this from */
-    // Last heartbeat time in milliseconds. This is used for leak detection.
-    // Heartbeat time happens once per minute and will not change the last state updated time.
-    pub last_heartbeat_time_in_ms: i64, /*todo: what is heartbeat */
-    // Data path VIP of the DPU or ENI
-    pub vip_v4: String,
-    // Data path VIP of the DPU or ENI
-    pub vip_v6: Option<String>,
-    // The IP address of the DPU.
-    pub local_ip: String,
-    // The IP address of the peer DPU.
-    pub peer_ip: String,
-
-    // The state of the HA state machine. This is the state in NPU hamgrd.
-    // The state of the HA state machine. This is the state in NPU hamgrd.
-    pub local_ha_state: Option<String>,
-    // The time when local target HA state is set.
-    pub local_ha_state_last_updated_time_in_ms: Option<i64>,
-    // The reason of the last HA state change.
-    pub local_ha_state_last_updated_reason: Option<String>,
-    // The target HA state in ASIC. This is the state that hamgrd generates and asking DPU to move to.
-    pub local_target_asic_ha_state: Option<String>,
-    // The HA state that ASIC acked.
-    pub local_acked_asic_ha_state: Option<String>,
-    // The current target term of the HA state machine.
-    pub local_target_term: Option<String>,
-    // The current term that acked by ASIC.
-    pub local_acked_term: Option<String>,
-    // The state of the HA state machine in peer DPU.
-    pub peer_ha_state: Option<String>, /*todo: we don't know peer dpu state */
-    // The current term in peer DPU.
-    pub peer_term: Option<String>,
-
-    // The state of local vDPU midplane. The value can be "unknown", "up", "down".
-    pub local_vdpu_midplane_state: DpuPmonStateType,
-    // Local vDPU midplane state last updated time in milliseconds.
-    pub local_vdpu_midplane_state_last_updated_time_in_ms: i64,
-    // The state of local vDPU control plane, which includes DPU OS and certain required firmware. The value can be "unknown", "up", "down".
-    pub local_vdpu_control_plane_state: DpuPmonStateType,
-    // Local vDPU control plane state last updated time in milliseconds.
-    pub local_vdpu_control_plane_state_last_updated_time_in_ms: i64,
-    // The state of local vDPU data plane, which includes DPU hardware / ASIC and certain required firmware. The value can be "unknown", "up", "down".
-    pub local_vdpu_data_plane_state: DpuPmonStateType,
-    // Local vDPU data plane state last updated time in milliseconds.
-    pub local_vdpu_data_plane_state_last_updated_time_in_ms: i64,
-    // The list of IPv4 peer IPs (NPU IP) of the BFD sessions in up state.
-    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    pub local_vdpu_up_bfd_sessions_v4: Vec<String>,
-    // Local vDPU BFD sessions v4 last updated time in milliseconds.
-    pub local_vdpu_up_bfd_sessions_v4_update_time_in_ms: i64,
-    // The list of IPv6 peer IPs (NPU IP) of the BFD sessions in up state.
-    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    pub local_vdpu_up_bfd_sessions_v6: Vec<String>,
-    // Local vDPU BFD sessions v6 last updated time in milliseconds.
-    pub local_vdpu_up_bfd_sessions_v6_update_time_in_ms: i64,
-
-    // GUIDs of pending operation IDs, connected by ","
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub pending_operation_ids: Option<Vec<String>>,
-    // Type of pending operations, e.g. "switchover", "activate_role", "flow_reconcile", "brainsplit_recover". Connected by ","
-    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, String>>")]
-    pub pending_operation_types: Option<Vec<String>>,
-    // Last updated time of the pending operation list.
-    pub pending_operation_list_last_updated_time_in_ms: Option<i64>,
-    // Switchover ID (GUID).
-    pub switchover_id: Option<String>,
-    // Switchover state. It can be "pending_approval", "approved", "in_progress", "completed", "failed"
-    pub switchover_state: Option<String>,
-    // The time when operation is created.
-    pub switchover_start_time_in_ms: Option<i64>,
-    // The time when operation is ended.
-    pub switchover_end_time_in_ms: Option<i64>,
-    // The time when operation is approved.
-    pub switchover_approved_time_in_ms: Option<i64>,
-    // Flow sync session ID.
-    pub flow_sync_session_id: Option<String>,
-    // Flow sync session state. It can be "in_progress", "completed", "failed"
-    pub flow_sync_session_state: Option<String>,
-    // Flow sync start time in milliseconds.
-    pub flow_sync_session_start_time_in_ms: Option<i64>,
-    // The IP endpoint of the server that flow records are sent to.
-    pub flow_sync_session_target_server: Option<String>,
-}
+/// Read every entry of the DPU table, keyed by DPU id.
+fn read_dpu_table() -> Result<BTreeMap<u32, Dpu>> {
+    let mut dpus = BTreeMap::new();
+
+    #[cfg(feature = "dev-sim")]
+    if crate::sim::is_active() {
+        let table = crate::sim::SimTable::new("CONFIG_DB", Dpu::table_name());
+        for key in table.keys() {
+            let dpu: Dpu = swss_serde::from_field_values(&table.get(&key).unwrap_or_default())
+                .context(format!("reading DPU entry {key}"))?;
+            dpus.insert(dpu.dpu_id, dpu);
+        }
+        return Ok(dpus);
+    }
 
-pub fn get_dpu_config_from_db(dpu_id: u32) -> Result<Dpu> {
     let db = DbConnector::new_named("CONFIG_DB", false, 0).context("connecting config_db")?;
     let table = Table::new(db, "DPU").context("opening DPU table")?;
 
@@ -447,21 +376,147 @@ pub fn get_dpu_config_from_db(dpu_id: u32) -> Result<Dpu> {
 
     for key in keys {
         let dpu: Dpu = from_table(&table, &key).context(format!("reading DPU entry {key}"))?;
+        dpus.insert(dpu.dpu_id, dpu);
+    }
+    Ok(dpus)
+}
+
+/// Process-wide read-through cache of the DPU table. A miss, an expired TTL or an invalidation
+/// reloads the whole table in one scan, so the slots of a multi-slot hamgrd share a single read.
+/// DpuActor invalidates it on every DPU table change notification; the TTL bounds how stale it gets
+/// if a notification is missed.
+pub struct DpuConfigCache {
+    ttl: Duration,
+    table: Mutex<Option<(Instant, BTreeMap<u32, Dpu>)>>,
+}
+
+impl DpuConfigCache {
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            table: Mutex::new(None),
+        }
+    }
 
-        // find the DPU entry for the slot
-        if dpu.dpu_id == dpu_id {
-            return Ok(dpu);
-        } else {
-            continue;
+    pub fn get(&self, dpu_id: u32) -> Result<Dpu> {
+        let mut table = self.table.lock().unwrap();
+        if let Some((read_at, dpus)) = table.as_ref() {
+            if read_at.elapsed() < self.ttl {
+                if let Some(dpu) = dpus.get(&dpu_id) {
+                    return Ok(dpu.clone());
+                }
+            }
         }
+        let dpus = read_dpu_table()?;
+        let dpu = dpus.get(&dpu_id).cloned();
+        *table = Some((Instant::now(), dpus));
+        dpu.ok_or_else(|| anyhow::anyhow!("DPU entry not found for slot {}", dpu_id))
+    }
+
+    pub fn invalidate(&self) {
+        self.table.lock().unwrap().take();
+    }
+}
+
+pub static DPU_CONFIG_CACHE: DpuConfigCache = DpuConfigCache::new(Duration::from_secs(30));
+
+/// Number of times the config tables are read before giving up on a stable snapshot.
+const SNAPSHOT_MAX_READS: u32 = 5;
+
+type RawTable = BTreeMap<String, FieldValues>;
+
+async fn read_raw_table<T: SonicDbTable + 'static>() -> Result<RawTable> {
+    let mut table = crate::tables::open_table::<T>().await?;
+    let mut entries = RawTable::new();
+    for key in table.get_keys_async().await? {
+        if let Some(fvs) = table.get_async(&key).await? {
+            entries.insert(key, fvs);
+        }
+    }
+    Ok(entries)
+}
+
+async fn read_raw_config_tables() -> Result<[RawTable; 4]> {
+    Ok([
+        read_raw_table::<Dpu>().await?,
+        read_raw_table::<VDpu>().await?,
+        read_raw_table::<DashHaSetConfigTable>().await?,
+        read_raw_table::<DashHaScopeConfigTable>().await?,
+    ])
+}
+
+fn parse_raw_table<T: ParseFieldValues + SonicDbTable>(raw: &RawTable) -> BTreeMap<String, T> {
+    let mut entries = BTreeMap::new();
+    for (key, fvs) in raw {
+        match parse_entry(key, fvs) {
+            Ok(entry) => {
+                entries.insert(key.clone(), entry);
+            }
+            Err(e) => warn!("Skipping {}|{key}: {e:#}", T::table_name()),
+        }
+    }
+    entries
+}
+
+/// A best effort consistent view of the DPU, VDPU, HA set and HA scope config tables. It is only logged
+/// on startup and compared to the running actors by the health check; the actors get their config from
+/// their bridges, so nothing is reconciled from it. Entries are keyed by their table key; entries that
+/// fail to parse are skipped.
+#[derive(Default)]
+pub struct ConfigSnapshot {
+    pub dpus: BTreeMap<String, Dpu>,
+    pub vdpus: BTreeMap<String, VDpu>,
+    pub ha_sets: BTreeMap<String, DashHaSetConfigTable>,
+    pub ha_scopes: BTreeMap<String, DashHaScopeConfigTable>,
+}
+
+impl ConfigSnapshot {
+    /// Redis has no cursor across tables, so the tables are read until two consecutive reads agree.
+    /// This makes it unlikely that a table was read before and another after a concurrent config
+    /// change, but doesn't rule it out, e.g. a change made to a table that was read already and
+    /// reverted before it is read again goes unnoticed.
+    pub async fn read() -> Result<Self> {
+        let mut previous = read_raw_config_tables().await?;
+        for _ in 1..SNAPSHOT_MAX_READS {
+            let current = read_raw_config_tables().await?;
+            if current == previous {
+                let [dpus, vdpus, ha_sets, ha_scopes] = &current;
+                return Ok(Self {
+                    dpus: parse_raw_table(dpus),
+                    vdpus: parse_raw_table(vdpus),
+                    ha_sets: parse_raw_table(ha_sets),
+                    ha_scopes: parse_raw_table(ha_scopes),
+                });
+            }
+            previous = current;
+        }
+        Err(anyhow::anyhow!(
+            "config tables kept changing over {SNAPSHOT_MAX_READS} reads"
+        ))
+    }
+
+    /// References from VDPUs to DPUs and from HA sets to VDPUs that point to missing entries.
+    pub fn dangling_references(&self) -> Vec<String> {
+        let mut dangling = Vec::new();
+        for (key, vdpu) in &self.vdpus {
+            let dpu_ids = vdpu.main_dpu_ids.iter().chain(vdpu.backup_dpu_ids.iter().flatten());
+            for dpu_id in dpu_ids.filter(|id| !self.dpus.contains_key(*id)) {
+                dangling.push(format!("VDPU {key} refers to missing DPU {dpu_id}"));
+            }
+        }
+        for (key, ha_set) in &self.ha_sets {
+            for vdpu_id in ha_set.vdpu_ids.iter().filter(|id| !self.vdpus.contains_key(*id)) {
+                dangling.push(format!("HA set {key} refers to missing VDPU {vdpu_id}"));
+            }
+        }
+        dangling
     }
-    Err(anyhow::anyhow!("DPU entry not found for slot {}", dpu_id))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::net::Ipv4Addr;
+    use crate::actors::fixtures::DbFixture;
     use swss_common::{FieldValues, KeyOpFieldValues};
     use swss_common_testing::*;
     #[test]
@@ -480,10 +535,160 @@ mod test {
         }"#;
         let kfv: KeyOpFieldValues = serde_json::from_str(json).unwrap();
         let dpu: Dpu = swss_serde::from_field_values(&kfv.field_values).unwrap();
-        assert!(dpu.pa_ipv4 == "1.2.3.4");
+        assert!(dpu.pa_ipv4 == Some(Ipv4Addr::new(1, 2, 3, 4)));
         assert!(dpu.dpu_id == 1);
     }
 
+    #[test]
+    fn test_bfd_session_discriminator() {
+        // both ends of a session must derive the same values, so they must not change between releases
+        assert_eq!(BfdSessionTable::discriminator("18.0.0.0", "18.0.1.0"), 2305087158);
+        assert_eq!(BfdSessionTable::discriminator("18.0.1.0", "18.0.0.0"), 2279896792);
+        assert_eq!(BfdSessionTable::key("18:0:1::"), "default:default:18:0:1::");
+    }
+
+    #[test]
+    fn test_parse_field_values() {
+        let json = r#"
+        {
+            "pa_ipv4": "1.2.3.4",
+            "pa_ipv6": "fc00::1",
+            "dpu_id": "1",
+            "orchagent_zmq_port": "99999",
+            "swbus_port": "23606",
+            "midplane_ipv4": "127.0.0"
+        }"#;
+        let fvs: FieldValues = serde_json::from_str(json).unwrap();
+        let errors = Dpu::parse_field_values("dpu0", &fvs).unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["orchagent_zmq_port", "midplane_ipv4"]);
+        assert!(errors.iter().all(|e| e.key == "dpu0"));
+
+        let mut fvs = fvs;
+        fvs.insert("orchagent_zmq_port".to_string(), "8100".into());
+        fvs.insert("midplane_ipv4".to_string(), "127.0.0.1".into());
+        let dpu = Dpu::parse_field_values("dpu0", &fvs).unwrap();
+        assert_eq!(dpu.pa_ipv6, Some("fc00::1".parse().unwrap()));
+        assert_eq!(dpu.midplane_ipv4, Some(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(dpu, swss_serde::from_field_values::<Dpu>(&fvs).unwrap());
+
+        let errors = RemoteDpu::parse_field_values("rdpu0", &FieldValues::new()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "rdpu0: dpu_id: field is missing");
+    }
+
+    #[test]
+    fn test_ipv6_only_dpu() {
+        let json = r#"
+        {
+            "pa_ipv6": "fc00::1",
+            "dpu_id": "1",
+            "orchagent_zmq_port": "8100",
+            "swbus_port": "23606",
+            "midplane_ipv6": "fd00::1"
+        }"#;
+        let fvs: FieldValues = serde_json::from_str(json).unwrap();
+        let dpu = Dpu::parse_field_values("dpu0", &fvs).unwrap();
+        assert_eq!(dpu.pa_ip(), Some("fc00::1".parse().unwrap()));
+        assert_eq!(dpu.midplane_ip(), Some("fd00::1".parse().unwrap()));
+
+        // IPv4 is preferred when both are set
+        let dpu = Dpu {
+            midplane_ipv4: Some(Ipv4Addr::new(169, 254, 1, 1)),
+            ..dpu
+        };
+        assert_eq!(dpu.midplane_ip(), Some(IpAddr::V4(Ipv4Addr::new(169, 254, 1, 1))));
+    }
+
+    #[test]
+    fn test_schema_version_tolerance() {
+        let json = r#"
+        {
+            "endpoint": "1.2.3.4",
+            "endpoint_monitor": "1.2.3.5",
+            "from_newer_release": "x"
+        }"#;
+        let mut fvs: FieldValues = serde_json::from_str(json).unwrap();
+        let vnet = VnetRouteTunnelTable::parse_field_values("default|1.0.0.0/32", &fvs).unwrap();
+        assert!(vnet.monitoring.is_none());
+        let warnings = parse_warnings();
+        assert!(warnings["VNET_ROUTE_TUNNEL_TABLE|from_newer_release|unknown"] >= 1);
+        assert!(warnings["VNET_ROUTE_TUNNEL_TABLE|monitoring|missing optional"] >= 1);
+
+        let vnet = VnetRouteTunnelTable {
+            endpoint: vec!["2.2.3.4".into()],
+            endpoint_monitor: None,
+            ..vnet
+        };
+        update_field_values(&mut fvs, &vnet).unwrap();
+        assert!(fvs["endpoint"] == "2.2.3.4");
+        assert!(!fvs.contains_key("endpoint_monitor"));
+        assert!(fvs["from_newer_release"] == "x");
+    }
+
+    #[test]
+    fn test_parse_lenient_table() {
+        let json = r#"
+        {
+            "version": "1",
+            "vip_v4": "1.1.1.1",
+            "vdpu_ids": "vdpu0,vdpu1",
+            "preferred_standalone_vdpu_index": "x"
+        }"#;
+        let fvs: FieldValues = serde_json::from_str(json).unwrap();
+        let ha_set: DashHaSetConfigTable = parse_entry("haset0", &fvs).unwrap();
+        assert_eq!(ha_set.vdpu_ids, vec!["vdpu0", "vdpu1"]);
+        assert!(ha_set.preferred_standalone_vdpu_index.is_none());
+        assert!(parse_warnings()["DASH_HA_SET_CONFIG_TABLE|preferred_standalone_vdpu_index|malformed"] >= 1);
+
+        // mandatory fields are still strict
+        let mut fvs = fvs;
+        fvs.remove("vip_v4");
+        let err = parse_entry::<DashHaSetConfigTable>("haset0", &fvs).unwrap_err();
+        assert_eq!(err.to_string(), "invalid entry: haset0: vip_v4: field is missing");
+    }
+
+    #[tokio::test]
+    async fn test_config_snapshot() {
+        let _redis = Redis::start_config_db();
+        populate_configdb_for_test();
+        let vdpu = VDpu {
+            main_dpu_ids: vec!["6".to_string(), "8".to_string()],
+            backup_dpu_ids: None,
+            composition: None,
+            min_members_up: None,
+        };
+        let ha_set = DashHaSetConfigTable {
+            version: "1".to_string(),
+            vip_v4: "3.2.1.0".to_string(),
+            vip_v6: None,
+            owner: None,
+            scope: None,
+            vdpu_ids: vec!["vdpu6".to_string(), "vdpu7".to_string()],
+            pinned_vdpu_bfd_probe_states: None,
+            preferred_vdpu_ids: None,
+            preferred_standalone_vdpu_index: None,
+        };
+        DbFixture::default()
+            .entry("vdpu6", &vdpu)
+            .entry("haset0", &ha_set)
+            .write();
+
+        let snapshot = ConfigSnapshot::read().await.unwrap();
+        assert_eq!(snapshot.dpus.keys().collect::<Vec<_>>(), vec!["6", "7"]);
+        assert_eq!(snapshot.dpus["6"].dpu_id, 6);
+        assert_eq!(snapshot.vdpus["vdpu6"].main_dpu_ids, vec!["6", "8"]);
+        assert_eq!(snapshot.ha_sets["haset0"].vdpu_ids, vec!["vdpu6", "vdpu7"]);
+        assert!(snapshot.ha_scopes.is_empty());
+        assert_eq!(
+            snapshot.dangling_references(),
+            vec![
+                "VDPU vdpu6 refers to missing DPU 8",
+                "HA set haset0 refers to missing VDPU vdpu7"
+            ]
+        );
+    }
+
     #[test]
     fn test_serde_vnet_route_tunnel() {
         let json = r#"
@@ -520,36 +725,74 @@ mod test {
         let expected = Dpu {
             state: None,
             vip_ipv6: None,
-            pa_ipv4: "1.2.3.6".to_string(),
-            vip_ipv4: Some("4.5.6.6".to_string()),
+            pa_ipv4: Some(Ipv4Addr::new(1, 2, 3, 6)),
+            vip_ipv4: Some(Ipv4Addr::new(4, 5, 6, 6)),
             pa_ipv6: None,
             dpu_id: 6,
             orchagent_zmq_port: 8100,
             swbus_port: 23612,
-            midplane_ipv4: "169.254.1.6".to_string(),
+            midplane_ipv4: Some(Ipv4Addr::new(169, 254, 1, 6)),
+            midplane_ipv6: None,
             vdpu_id: Some("vpdu6".to_string()),
+            maintenance: None,
         };
 
         assert_eq!(config_fromdb, expected);
     }
 
-    fn populate_configdb_for_test() {
-        let db: DbConnector = DbConnector::new_named("CONFIG_DB", false, 0).unwrap();
+    #[test]
+    fn test_dpu_config_cache() {
+        let _ = Redis::start_config_db();
+        populate_configdb_for_test();
+        let cache = DpuConfigCache::new(Duration::from_secs(60));
+        assert_eq!(cache.get(7).unwrap().swbus_port, 23613);
+
+        let db = DbConnector::new_named("CONFIG_DB", false, 0).unwrap();
         let table = Table::new(db, "DPU").unwrap();
+        table
+            .set("7", vec![("swbus_port".to_string(), "23700".to_string())])
+            .unwrap();
+        // the whole table was read by the first lookup, and is served from the cache until invalidated
+        assert_eq!(cache.get(6).unwrap().swbus_port, 23612);
+        assert_eq!(cache.get(7).unwrap().swbus_port, 23613);
+        cache.invalidate();
+        assert_eq!(cache.get(7).unwrap().swbus_port, 23700);
+
+        // a DPU missing from the cached table is looked up again
+        let mut dpu = get_dpu_config_from_db(7).unwrap();
+        dpu.dpu_id = 8;
+        DbFixture::default().entry("8", &dpu).write();
+        assert_eq!(cache.get(8).unwrap().dpu_id, 8);
+        assert!(cache.get(9).is_err());
+
+        let cache = DpuConfigCache::new(Duration::ZERO);
+        table
+            .set("7", vec![("swbus_port".to_string(), "23701".to_string())])
+            .unwrap();
+        assert_eq!(cache.get(7).unwrap().swbus_port, 23701);
+    }
 
+    fn populate_configdb_for_test() {
         // create local dpu table first
+        let mut fixture = DbFixture::default();
         for d in 6..8 {
-            let dpu_fvs = vec![
-                ("pa_ipv4".to_string(), Ipv4Addr::new(1, 2, 3, d).to_string()),
-                ("vip_ipv4".to_string(), Ipv4Addr::new(4, 5, 6, d).to_string()),
-                ("dpu_id".to_string(), d.to_string()),
-                ("orchagent_zmq_port".to_string(), "8100".to_string()),
-                ("swbus_port".to_string(), (23606 + d as u16).to_string()),
-                ("midplane_ipv4".to_string(), Ipv4Addr::new(169, 254, 1, d).to_string()),
-                ("vdpu_id".to_string(), format!("vpdu{d}")),
-            ];
-            table.set(&d.to_string(), dpu_fvs).unwrap();
+            let dpu = Dpu {
+                state: None,
+                vip_ipv4: Some(Ipv4Addr::new(4, 5, 6, d)),
+                vip_ipv6: None,
+                pa_ipv4: Some(Ipv4Addr::new(1, 2, 3, d)),
+                pa_ipv6: None,
+                dpu_id: d.into(),
+                vdpu_id: Some(format!("vpdu{d}")),
+                orchagent_zmq_port: 8100,
+                swbus_port: 23606 + u16::from(d),
+                midplane_ipv4: Some(Ipv4Addr::new(169, 254, 1, d)),
+                midplane_ipv6: None,
+                maintenance: None,
+            };
+            fixture = fixture.entry(&d.to_string(), &dpu);
         }
+        fixture.write();
     }
 
     #[test]
@@ -0,0 +1,113 @@
+//! Dead letters of the producer bridges
+//!
+//! A write to a DPU table that still fails after the bridge retried it is recorded in
+//! STATE_DB/DASH_HA_BRIDGE_DEAD_LETTER_TABLE and raised as an HA event, so the failure is visible
+//! without the bridge retrying forever. The entry is removed once a later write to the same key
+//! succeeds.
+use crate::db_structs::{now_in_millis, DashHaBridgeDeadLetterTable};
+use crate::ha_events::{self, HaEvent, HaEventSeverity, HaEventType};
+use crate::tables::DbTable;
+use crate::TableSource;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use swss_common::KeyOperation;
+use swss_common_bridge::producer::{DeadLetterSink, FailedWrite};
+use tracing::error;
+
+pub struct StateDbDeadLetters {
+    table_name: &'static str,
+    table: DbTable,
+}
+
+impl StateDbDeadLetters {
+    /// Dead letters of the producer bridge of `table_name`
+    pub async fn new(table_name: &'static str) -> Result<Self> {
+        let table = crate::tables::open_table::<DashHaBridgeDeadLetterTable>().await?;
+        Ok(StateDbDeadLetters { table_name, table })
+    }
+
+    fn swss_key(&self, key: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.table_name,
+            DashHaBridgeDeadLetterTable::source_key_separator(),
+            key
+        )
+    }
+}
+
+impl DeadLetterSink for StateDbDeadLetters {
+    async fn record(&mut self, write: &FailedWrite) {
+        let swss_key = self.swss_key(&write.kfv.key);
+        let field_values: BTreeMap<&str, String> = write
+            .kfv
+            .field_values
+            .iter()
+            .map(|(field, value)| (field.as_str(), value.to_string_lossy().into_owned()))
+            .collect();
+        let entry = DashHaBridgeDeadLetterTable {
+            operation: match write.kfv.operation {
+                KeyOperation::Set => "SET".to_string(),
+                KeyOperation::Del => "DEL".to_string(),
+            },
+            field_values: serde_json::to_string(&field_values).unwrap(),
+            attempts: write.attempts,
+            error: write.error.clone(),
+            failed_time_in_ms: now_in_millis(),
+        };
+
+        let fvs = swss_serde::to_field_values(&entry).unwrap();
+        if let Err(e) = self.table.set_async(&swss_key, fvs).await {
+            error!("Failed to record dead letter {swss_key}: {e:#}");
+        }
+
+        let reason = format!("gave up after {} attempts: {}", write.attempts, write.error);
+        ha_events::emit(HaEvent::new(
+            HaEventType::HaWriteFailed,
+            HaEventSeverity::Major,
+            &swss_key,
+            &reason,
+        ));
+    }
+
+    async fn clear(&mut self, key: &str) {
+        let swss_key = self.swss_key(key);
+        if let Err(e) = self.table.del_async(&swss_key).await {
+            error!("Failed to clear dead letter {swss_key}: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::{CxxString, DbConnector, FieldValues, KeyOpFieldValues, SonicDbTable, Table};
+    use swss_common_testing::Redis;
+
+    #[tokio::test]
+    async fn dead_letter_record_and_clear() {
+        let _redis = Redis::start_config_db();
+        let mut dead_letters = StateDbDeadLetters::new("DASH_HA_SET_TABLE").await.unwrap();
+        let write = FailedWrite {
+            kfv: KeyOpFieldValues {
+                key: "haset0".to_string(),
+                operation: KeyOperation::Set,
+                field_values: FieldValues::from([("version".to_string(), CxxString::new("1"))]),
+            },
+            attempts: 5,
+            error: "zmq is down".to_string(),
+        };
+        dead_letters.record(&write).await;
+
+        let db = DbConnector::new_named("STATE_DB", false, 0).unwrap();
+        let table = Table::new(db, DashHaBridgeDeadLetterTable::table_name()).unwrap();
+        let entry: DashHaBridgeDeadLetterTable = swss_serde::from_table(&table, "DASH_HA_SET_TABLE|haset0").unwrap();
+        assert_eq!(entry.operation, "SET");
+        assert_eq!(entry.field_values, r#"{"version":"1"}"#);
+        assert_eq!(entry.attempts, 5);
+        assert_eq!(entry.error, "zmq is down");
+
+        dead_letters.clear("haset0").await;
+        assert!(table.get("DASH_HA_SET_TABLE|haset0").unwrap().is_none());
+    }
+}
@@ -0,0 +1,110 @@
+//! Exit codes
+//!
+//! hamgrd exits with a code telling why, following sysexits.h, so that supervisord restart policies and
+//! operators can tell a failure a restart may fix, like an unreachable database, from one that needs an
+//! operator, like a bad config. Before exiting, the reason is logged and recorded in
+//! STATE_DB/HAMGRD_EXIT_TABLE, best effort since STATE_DB may be what failed.
+use crate::db_structs::{now_in_millis, HamgrdExitTable};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::error;
+
+/// Time the exit record may take, so that an unreachable STATE_DB doesn't hold up the exit.
+const RECORD_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The command line or the config in CONFIG_DB is invalid
+    ConfigError,
+    DbUnavailable,
+    SwbusdUnreachable,
+    /// A bug
+    Internal,
+}
+
+impl ExitReason {
+    pub fn code(self) -> i32 {
+        match self {
+            ExitReason::ConfigError => 78,       // EX_CONFIG
+            ExitReason::DbUnavailable => 69,     // EX_UNAVAILABLE
+            ExitReason::SwbusdUnreachable => 68, // EX_NOHOST
+            ExitReason::Internal => 70,          // EX_SOFTWARE
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExitReason::ConfigError => "config_error",
+            ExitReason::DbUnavailable => "db_unavailable",
+            ExitReason::SwbusdUnreachable => "swbusd_unreachable",
+            ExitReason::Internal => "internal_error",
+        }
+    }
+
+    /// Whether restarting hamgrd may get past the failure.
+    pub fn is_recoverable(self) -> bool {
+        self != ExitReason::ConfigError
+    }
+}
+
+/// Log and record why hamgrd fails, then exit with the code of `reason`.
+pub async fn exit(reason: ExitReason, error: anyhow::Error) -> ! {
+    error!(
+        reason = reason.as_str(),
+        exit_code = reason.code(),
+        recoverable = reason.is_recoverable(),
+        "hamgrd exiting: {error:#}"
+    );
+    match timeout(RECORD_TIMEOUT, record(reason, &error)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Failed to record the exit reason: {e:#}"),
+        Err(_) => error!("Recording the exit reason timed out"),
+    }
+    std::process::exit(reason.code())
+}
+
+async fn record(reason: ExitReason, error: &anyhow::Error) -> Result<()> {
+    let entry = HamgrdExitTable {
+        reason: reason.as_str().to_string(),
+        exit_code: reason.code(),
+        recoverable: reason.is_recoverable(),
+        error: format!("{error:#}"),
+        exit_time_in_ms: now_in_millis(),
+    };
+    let mut table = crate::tables::open_table::<HamgrdExitTable>().await?;
+    // The process exits, so every slot it runs exits with it
+    for slot in crate::slot::all() {
+        table
+            .set_async(&slot.name(), swss_serde::to_field_values(&entry)?)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use swss_common::{DbConnector, SonicDbTable, Table};
+    use swss_common_testing::Redis;
+
+    #[tokio::test]
+    async fn exit_reason_is_recorded() {
+        let _redis = Redis::start_config_db();
+        record(
+            ExitReason::ConfigError,
+            &anyhow!("no cluster route").context("swbusd config"),
+        )
+        .await
+        .unwrap();
+
+        let db = DbConnector::new_named("STATE_DB", false, 0).unwrap();
+        let table = Table::new(db, HamgrdExitTable::table_name()).unwrap();
+        let entry: HamgrdExitTable = swss_serde::from_table(&table, &crate::slot::current().name()).unwrap();
+        assert_eq!(entry.reason, "config_error");
+        assert_eq!(entry.exit_code, 78);
+        assert!(!entry.recoverable);
+        assert_eq!(entry.error, "swbusd config: no cluster route");
+    }
+}
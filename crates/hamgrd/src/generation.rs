@@ -0,0 +1,211 @@
+//! Write generations
+//!
+//! The hamgrd of every slot has a generation, persisted in STATE_DB/HAMGRD_GENERATION_TABLE and bumped
+//! when hamgrd starts. A running hamgrd reads the persisted generation every [`WATCH_INTERVAL`]. Once
+//! it finds a newer one, another hamgrd took over the slot and this one is a zombie:
+//! [`GenerationCheckedTable`] drops all its further writes to the DPU tables, so they can't undo the
+//! ones of the newer hamgrd.
+//!
+//! This is the only fencing there is. A zombie keeps writing for up to [`WATCH_INTERVAL`] after it
+//! was replaced, and its writes to the STATE_DB tables of hamgrd are not fenced. The generation is
+//! not written into the entries: orchagent, which consumes the DPU tables, wouldn't check it.
+use crate::db_structs::{now_in_millis, HamgrdGenerationTable};
+use crate::tables::DbTable;
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use swss_common::{FieldValues, KeyOpFieldValues};
+use swss_common_bridge::producer::ProducerTable;
+use tracing::{error, info, warn};
+
+/// How often a running hamgrd checks that no newer generation took over.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The generation of the slot the caller runs in, see [`crate::slot`].
+pub fn generation() -> &'static Generation {
+    crate::slot::current().generation()
+}
+
+pub struct Generation {
+    generation: AtomicU64,
+    superseded: AtomicBool,
+}
+
+impl Generation {
+    pub(crate) const fn new() -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            superseded: AtomicBool::new(false),
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Whether a newer generation took over.
+    pub fn superseded(&self) -> bool {
+        self.superseded.load(Ordering::Relaxed)
+    }
+
+    /// Start a new generation, when hamgrd starts. Returns the new generation.
+    pub async fn bump(&self) -> Result<u64> {
+        let mut table = generation_table().await?;
+        let generation = read_persisted(&mut table).await?.max(self.current()) + 1;
+        let entry = HamgrdGenerationTable {
+            generation,
+            started_time_in_ms: now_in_millis(),
+        };
+        table
+            .set_async(&generation_key(), swss_serde::to_field_values(&entry)?)
+            .await?;
+        self.generation.store(generation, Ordering::Relaxed);
+        info!("Starting generation {generation}");
+        Ok(generation)
+    }
+
+    /// Check whether another hamgrd started a newer generation. Once that happened, this process is
+    /// a zombie and all its writes are stale.
+    pub async fn check_superseded(&self) -> Result<bool> {
+        let persisted = read_persisted(&mut generation_table().await?).await?;
+        if persisted > self.current() && !self.superseded.swap(true, Ordering::Relaxed) {
+            error!(
+                "Generation {persisted} took over from generation {}, dropping all further writes",
+                self.current()
+            );
+        }
+        Ok(self.superseded())
+    }
+
+    /// Check for a newer generation every [`WATCH_INTERVAL`], until one is found.
+    pub async fn watch(&self) {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.check_superseded().await {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => warn!("Failed to check the hamgrd generation: {e:#}"),
+            }
+        }
+    }
+}
+
+fn generation_key() -> String {
+    crate::slot::current().name()
+}
+
+async fn generation_table() -> Result<DbTable> {
+    crate::tables::open_table::<HamgrdGenerationTable>().await
+}
+
+async fn read_persisted(table: &mut DbTable) -> Result<u64> {
+    let Some(fvs) = table.get_async(&generation_key()).await? else {
+        return Ok(0);
+    };
+    let entry: HamgrdGenerationTable = swss_serde::from_field_values(&fvs)?;
+    Ok(entry.generation)
+}
+
+/// A producer table that drops all writes once a newer generation took over.
+pub struct GenerationCheckedTable<T> {
+    pub table: T,
+    pub generation: &'static Generation,
+}
+
+impl<T> GenerationCheckedTable<T> {
+    /// Whether a write to `key` must be dropped.
+    fn is_stale(&self, key: &str) -> bool {
+        if !self.generation.superseded() {
+            return false;
+        }
+        warn!(
+            "Dropping write to {key}, generation {} was superseded",
+            self.generation.current()
+        );
+        true
+    }
+}
+
+impl<T: ProducerTable> ProducerTable for GenerationCheckedTable<T> {
+    async fn set(&mut self, key: &str, fvs: FieldValues) -> swbus_actor::Result<()> {
+        if self.is_stale(key) {
+            return Ok(());
+        }
+        self.table.set(key, fvs).await
+    }
+
+    async fn del(&mut self, key: &str) -> swbus_actor::Result<()> {
+        if self.is_stale(key) {
+            return Ok(());
+        }
+        self.table.del(key).await
+    }
+
+    async fn apply_batch(&mut self, kfvs: Vec<KeyOpFieldValues>) -> swbus_actor::Result<()> {
+        let kfvs = kfvs.into_iter().filter(|kfv| !self.is_stale(&kfv.key)).collect();
+        self.table.apply_batch(kfvs).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::KeyOperation;
+    use swss_common_testing::Redis;
+
+    /// Records the keys written.
+    struct RecordingTable(Vec<String>);
+
+    impl ProducerTable for RecordingTable {
+        async fn set(&mut self, key: &str, _fvs: FieldValues) -> swbus_actor::Result<()> {
+            self.0.push(key.to_string());
+            Ok(())
+        }
+
+        async fn del(&mut self, key: &str) -> swbus_actor::Result<()> {
+            self.0.push(key.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn generation_lifecycle() {
+        let _redis = Redis::start_config_db();
+        // Not the generation of the slot, which the other tests write with
+        let generation: &'static Generation = Box::leak(Box::new(Generation::new()));
+        let first = generation.bump().await.unwrap();
+        let second = generation.bump().await.unwrap();
+        assert_eq!(second, first + 1);
+        assert!(!generation.check_superseded().await.unwrap());
+
+        let mut table = GenerationCheckedTable {
+            table: RecordingTable(Vec::new()),
+            generation,
+        };
+        table.set("current", FieldValues::new()).await.unwrap();
+        let kfv = |key: &str| KeyOpFieldValues {
+            key: key.to_string(),
+            operation: KeyOperation::Set,
+            field_values: FieldValues::new(),
+        };
+        table.apply_batch(vec![kfv("batched")]).await.unwrap();
+        assert_eq!(table.table.0, vec!["current".to_string(), "batched".to_string()]);
+
+        // Another hamgrd takes over
+        let takeover = HamgrdGenerationTable {
+            generation: second + 1,
+            started_time_in_ms: now_in_millis(),
+        };
+        let mut generations = generation_table().await.unwrap();
+        generations
+            .set_async(&generation_key(), swss_serde::to_field_values(&takeover).unwrap())
+            .await
+            .unwrap();
+        assert!(generation.check_superseded().await.unwrap());
+        table.set("zombie", FieldValues::new()).await.unwrap();
+        table.del("zombie").await.unwrap();
+        table.apply_batch(vec![kfv("zombie")]).await.unwrap();
+        assert_eq!(table.table.0, vec!["current".to_string(), "batched".to_string()]);
+    }
+}
@@ -5,9 +5,51 @@ use anyhow::Result;
 use chrono::{format::ParseError, DateTime, TimeZone, Utc};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::skip_serializing_none;
-use std::{collections::HashMap, hash::Hash};
-use swbus_actor::{state::incoming::Incoming, ActorMessage};
+use std::{collections::HashMap, hash::Hash, time::SystemTime};
+use swbus_actor::{
+    actor_message::{SequenceNumber, Value},
+    state::{incoming::Incoming, outgoing::Outgoing},
+    ActorMessage,
+};
 use swbus_edge::swbus_proto::swbus::ServicePath;
+use tracing::warn;
+
+/// Numbers the state updates an actor sends, so that receivers can drop the ones that arrive out
+/// of order and notice the ones that never arrive. A state that didn't change since the last
+/// update, e.g. sent to an actor that just registered, keeps its number.
+pub struct StateSequencer {
+    epoch: u64,
+    seq: u64,
+    last_data: Option<Value>,
+}
+
+impl Default for StateSequencer {
+    fn default() -> Self {
+        // nanoseconds since the unix epoch, so that a restarted actor numbers after its predecessor
+        let epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        Self {
+            epoch,
+            seq: 0,
+            last_data: None,
+        }
+    }
+}
+
+impl StateSequencer {
+    pub fn stamp(&mut self, msg: ActorMessage) -> ActorMessage {
+        if self.last_data.as_ref() != Some(&msg.data) {
+            self.seq += 1;
+            self.last_data = Some(msg.data.clone());
+        }
+        msg.with_seq(SequenceNumber {
+            epoch: self.epoch,
+            seq: self.seq,
+        })
+    }
+}
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -19,19 +61,26 @@ pub struct DpuActorState {
     pub dpu_name: String,
     pub up: bool,
     pub state: Option<String>,
-    pub npu_ipv4: String,
+    pub npu_ipv4: Option<String>,
     pub npu_ipv6: Option<String>,
     pub vip_ipv4: Option<String>,
     pub vip_ipv6: Option<String>,
-    pub pa_ipv4: String,
+    pub pa_ipv4: Option<String>,
     pub pa_ipv6: Option<String>,
     pub dpu_id: u32,
     pub vdpu_id: Option<String>,
     pub orchagent_zmq_port: u16,
     pub swbus_port: u16,
     pub midplane_ipv4: Option<String>,
+    pub midplane_ipv6: Option<String>,
     pub dpu_pmon_state: Option<DpuState>,
     pub dpu_bfd_state: Option<DashBfdProbeState>,
+    // Peer IPs of the BFD sessions that flap too often to be taken into account
+    #[serde(default)]
+    pub dampened_bfd_sessions: Vec<String>,
+    // The DPU is in planned maintenance and must not hold the active role
+    #[serde(default)]
+    pub maintenance: bool,
 }
 
 impl DpuActorState {
@@ -39,7 +88,7 @@ impl DpuActorState {
         dpu_name: &str,
         dpu: &Dpu,
         is_managed: bool,
-        npu_ipv4: &str,
+        npu_ipv4: &Option<String>,
         npu_ipv6: &Option<String>,
         pmon_state: Option<DpuState>,
         bfd_state: Option<DashBfdProbeState>,
@@ -50,19 +99,22 @@ impl DpuActorState {
             is_managed,
             up: false,
             state: dpu.state.clone(),
-            npu_ipv4: npu_ipv4.to_string(),
+            npu_ipv4: npu_ipv4.clone(),
             npu_ipv6: npu_ipv6.clone(),
-            vip_ipv4: dpu.vip_ipv4.clone(),
-            vip_ipv6: dpu.vip_ipv6.clone(),
-            pa_ipv4: dpu.pa_ipv4.clone(),
-            pa_ipv6: dpu.pa_ipv6.clone(),
+            vip_ipv4: dpu.vip_ipv4.map(|ip| ip.to_string()),
+            vip_ipv6: dpu.vip_ipv6.map(|ip| ip.to_string()),
+            pa_ipv4: dpu.pa_ipv4.map(|ip| ip.to_string()),
+            pa_ipv6: dpu.pa_ipv6.map(|ip| ip.to_string()),
             dpu_id: dpu.dpu_id,
             vdpu_id: dpu.vdpu_id.clone(),
             orchagent_zmq_port: dpu.orchagent_zmq_port,
             swbus_port: dpu.swbus_port,
-            midplane_ipv4: Some(dpu.midplane_ipv4.clone()),
+            midplane_ipv4: dpu.midplane_ipv4.map(|ip| ip.to_string()),
+            midplane_ipv6: dpu.midplane_ipv6.map(|ip| ip.to_string()),
             dpu_pmon_state: pmon_state,
             dpu_bfd_state: bfd_state,
+            dampened_bfd_sessions: Vec::new(),
+            maintenance: dpu.maintenance.unwrap_or(false),
         }
     }
 
@@ -73,22 +125,42 @@ impl DpuActorState {
             is_managed: false,
             up: false,
             state: None,
-            npu_ipv4: rdpu.npu_ipv4.clone(),
-            npu_ipv6: rdpu.npu_ipv6.clone(),
+            npu_ipv4: rdpu.npu_ipv4.map(|ip| ip.to_string()),
+            npu_ipv6: rdpu.npu_ipv6.map(|ip| ip.to_string()),
             vip_ipv4: None,
             vip_ipv6: None,
-            pa_ipv4: rdpu.pa_ipv4.clone(),
-            pa_ipv6: rdpu.pa_ipv6.clone(),
+            pa_ipv4: rdpu.pa_ipv4.map(|ip| ip.to_string()),
+            pa_ipv6: rdpu.pa_ipv6.map(|ip| ip.to_string()),
             dpu_id: rdpu.dpu_id,
             vdpu_id: None,
             orchagent_zmq_port: 0,
             swbus_port: rdpu.swbus_port,
             midplane_ipv4: None,
+            midplane_ipv6: None,
             dpu_pmon_state: None,
             dpu_bfd_state: None,
+            dampened_bfd_sessions: Vec::new(),
+            maintenance: false,
         }
     }
 
+    /// The address of the NPU of the DPU, IPv6 in IPv6-only deployments. Empty if it has neither, which
+    /// the config validation rules out.
+    pub fn npu_ip(&self) -> String {
+        self.npu_ipv4
+            .clone()
+            .or_else(|| self.npu_ipv6.clone())
+            .unwrap_or_default()
+    }
+
+    /// The PA address of the DPU, IPv6 in IPv6-only deployments.
+    pub fn pa_ip(&self) -> String {
+        self.pa_ipv4
+            .clone()
+            .or_else(|| self.pa_ipv6.clone())
+            .unwrap_or_default()
+    }
+
     pub fn new_actor_msg(my_id: &str, dpu: &DpuActorState) -> Result<ActorMessage> {
         ActorMessage::new(Self::msg_key(my_id), &dpu)
     }
@@ -106,15 +178,35 @@ impl DpuActorState {
     }
 }
 
+/// State of a member DPU of a vDPU
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VDpuMemberState {
+    pub dpu_id: String,
+    // If true, this is a main DPU. Otherwise, it is a backup DPU.
+    pub is_main: bool,
+    pub up: bool,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq)]
 pub struct VDpuActorState {
     pub up: bool,
+    // The DPU currently serving the vDPU
     pub dpu: DpuActorState,
+    // All member DPUs whose state is known, main DPUs first
+    #[serde(default)]
+    pub members: Vec<VDpuMemberState>,
 }
 
 impl VDpuActorState {
     pub fn new_actor_msg(up: bool, my_id: &str, dpu: DpuActorState) -> Result<ActorMessage> {
-        ActorMessage::new(Self::msg_key(my_id), &Self { up, dpu })
+        ActorMessage::new(
+            Self::msg_key(my_id),
+            &Self {
+                up,
+                dpu,
+                members: Vec::new(),
+            },
+        )
     }
 
     pub fn to_actor_msg(&self, my_id: &str) -> Result<ActorMessage> {
@@ -134,15 +226,63 @@ impl VDpuActorState {
     }
 }
 
+/// Sent by a vDPU actor to the DPU actor of each of its members with the HA sets placed on the vDPU,
+/// whenever they or the members serving the vDPU change. A member that leaves the vDPU gets an empty
+/// placement.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct HaSetPlacement {
+    pub ha_set_ids: Vec<String>,
+    // If true, the DPU serves the vDPU and hosts the HA sets. Otherwise, it is a standby member.
+    pub serving: bool,
+}
+
+impl HaSetPlacement {
+    pub fn to_actor_msg(&self, vdpu_id: &str) -> Result<ActorMessage> {
+        ActorMessage::new(Self::msg_key(vdpu_id), self)
+    }
+
+    pub fn msg_key_prefix() -> &'static str {
+        "HaSetPlacement|"
+    }
+
+    pub fn msg_key(vdpu_id: &str) -> String {
+        format!("{}{}", Self::msg_key_prefix(), vdpu_id)
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key.starts_with(Self::msg_key_prefix())
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq)]
 pub struct HaSetActorState {
     pub up: bool,
     pub ha_set: DashHaSetTable,
+    // The vDPU of the HA set that is managed by another hamgrd
+    #[serde(default)]
+    pub peer: Option<HaSetPeer>,
+    // The hamgrd managing the peer vDPU doesn't answer the pings of the peer monitor
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub peer_unreachable: bool,
 }
 
 impl HaSetActorState {
-    pub fn new_actor_msg(up: bool, my_id: &str, ha_set: DashHaSetTable) -> Result<ActorMessage> {
-        ActorMessage::new(Self::msg_key(my_id), &Self { up: true, ha_set })
+    pub fn new_actor_msg(
+        up: bool,
+        my_id: &str,
+        ha_set: DashHaSetTable,
+        peer: Option<HaSetPeer>,
+        peer_unreachable: bool,
+    ) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(my_id),
+            &Self {
+                up: true,
+                ha_set,
+                peer,
+                peer_unreachable,
+            },
+        )
     }
 
     pub fn to_actor_msg(&self, my_id: &str) -> Result<ActorMessage> {
@@ -162,6 +302,332 @@ impl HaSetActorState {
     }
 }
 
+/// The peer vDPU of an HA set and where the hamgrd managing it runs.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HaSetPeer {
+    pub vdpu_id: String,
+    /// The NPU address swbusd of the peer DPU is named after, see [`DpuActorState::npu_ip`]
+    #[serde(alias = "npu_ipv4")]
+    pub npu_ip: String,
+    pub dpu_id: u32,
+}
+
+impl HaSetPeer {
+    /// The service path of actor `resource_type/resource_id` in the hamgrd of the peer, given the
+    /// service path of an actor in this hamgrd.
+    pub fn actor_sp(&self, my_sp: &ServicePath, resource_type: &str, resource_id: &str) -> ServicePath {
+        let mut sp = my_sp.clone();
+        sp.node_id = format!("{}-dpu{}", self.npu_ip, self.dpu_id);
+        sp.resource_type = resource_type.into();
+        sp.resource_id = resource_id.into();
+        sp
+    }
+
+    /// The service path of the hamgrd of the peer itself, which answers pings.
+    pub fn hamgrd_sp(&self, my_sp: &ServicePath) -> ServicePath {
+        self.actor_sp(my_sp, "", "")
+    }
+}
+
+/// Sent by an HA set actor to the peer monitor to have the hamgrd of its peer pinged, or no longer
+/// pinged if `peer` is None.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerMonitorRegistration {
+    pub peer: Option<HaSetPeer>,
+}
+
+impl PeerMonitorRegistration {
+    pub fn new_actor_msg(my_id: &str, peer: Option<HaSetPeer>) -> Result<ActorMessage> {
+        ActorMessage::new(Self::msg_key(my_id), &Self { peer })
+    }
+
+    pub fn msg_key_prefix() -> &'static str {
+        "PeerMonitorRegistration|"
+    }
+
+    pub fn msg_key(my_id: &str) -> String {
+        format!("{}{}", Self::msg_key_prefix(), my_id)
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key.starts_with(Self::msg_key_prefix())
+    }
+}
+
+/// Sent by the peer monitor to a registered HA set actor on registration, and whenever the hamgrd of
+/// its peer becomes unreachable or reachable again.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct PeerHamgrdState {
+    pub reachable: bool,
+    // Pings not answered in a row
+    pub consecutive_failures: u32,
+}
+
+impl PeerHamgrdState {
+    pub fn new_actor_msg(reachable: bool, consecutive_failures: u32) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(),
+            &Self {
+                reachable,
+                consecutive_failures,
+            },
+        )
+    }
+
+    pub fn msg_key() -> &'static str {
+        "PeerHamgrdState"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
+/// Sent to every actor when hamgrd drains before exiting.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct HamgrdShutdown {
+    // The signal that started the shutdown
+    pub reason: String,
+}
+
+impl HamgrdShutdown {
+    pub fn new_actor_msg(reason: &str) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(),
+            &Self {
+                reason: reason.to_string(),
+            },
+        )
+    }
+
+    pub fn msg_key() -> &'static str {
+        "HamgrdShutdown"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
+/// Sent by an HA scope actor to the HA scope actor of its peer when its hamgrd exits on purpose, so
+/// the peer can tell a planned exit from a failure.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerPlannedExit {
+    pub exit_time_in_ms: i64,
+}
+
+impl PeerPlannedExit {
+    pub fn new_actor_msg(my_id: &str, exit_time_in_ms: i64) -> Result<ActorMessage> {
+        ActorMessage::new(Self::msg_key(my_id), &Self { exit_time_in_ms })
+    }
+
+    pub fn msg_key_prefix() -> &'static str {
+        "PeerPlannedExit|"
+    }
+
+    pub fn msg_key(my_id: &str) -> String {
+        format!("{}{}", Self::msg_key_prefix(), my_id)
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key.starts_with(Self::msg_key_prefix())
+    }
+}
+
+/// Sent by an HA scope actor to the HA scope actor of its peer with the role of its DPU once the hamgrd
+/// of the peer is reachable again, and on every change of the role after that, to detect both DPUs
+/// being active after a network partition.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerHaRole {
+    pub ha_role: String,
+}
+
+impl PeerHaRole {
+    pub fn new_actor_msg(my_id: &str, ha_role: &str) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(my_id),
+            &Self {
+                ha_role: ha_role.to_string(),
+            },
+        )
+    }
+
+    pub fn msg_key_prefix() -> &'static str {
+        "PeerHaRole|"
+    }
+
+    pub fn msg_key(my_id: &str) -> String {
+        format!("{}{}", Self::msg_key_prefix(), my_id)
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key.starts_with(Self::msg_key_prefix())
+    }
+}
+
+/// Asks an HA scope actor to hand the active role of its DPU over to the peer DPU, like a new
+/// planned_switchover_id in DASH_HA_SCOPE_CONFIG_TABLE does. Any swbus client can send it.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlannedSwitchoverRequest {
+    pub switchover_id: String,
+}
+
+impl PlannedSwitchoverRequest {
+    pub fn new_actor_msg(switchover_id: &str) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(),
+            &Self {
+                switchover_id: switchover_id.to_string(),
+            },
+        )
+    }
+
+    pub fn msg_key() -> &'static str {
+        "PlannedSwitchoverRequest"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
+/// Phase of a planned switchover.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchoverPhase {
+    // Both DPUs move to the switching roles, so that new flows go to the DPU taking over
+    Draining,
+    // Both DPUs move to their final roles
+    Flipping,
+    Completed,
+    // Rolled back to the roles before the switchover
+    Failed,
+}
+
+impl SwitchoverPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwitchoverPhase::Draining => "draining",
+            SwitchoverPhase::Flipping => "flipping",
+            SwitchoverPhase::Completed => "completed",
+            SwitchoverPhase::Failed => "failed",
+        }
+    }
+}
+
+/// Step of a planned switchover, exchanged between the HA scope actors of both DPUs of the HA scope.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchoverStep {
+    // From the active side: start draining to the peer DPU
+    Drain,
+    // From the peer: the peer DPU is ready to take over
+    Drained,
+    // From the active side: take over the active role
+    Flip,
+    // From the peer: the peer DPU is active
+    Done,
+    // From either side: roll back
+    Abort,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlannedSwitchover {
+    pub switchover_id: String,
+    pub step: SwitchoverStep,
+}
+
+impl PlannedSwitchover {
+    pub fn new_actor_msg(my_id: &str, switchover_id: &str, step: SwitchoverStep) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(my_id),
+            &Self {
+                switchover_id: switchover_id.to_string(),
+                step,
+            },
+        )
+    }
+
+    pub fn msg_key_prefix() -> &'static str {
+        "PlannedSwitchover|"
+    }
+
+    pub fn msg_key(my_id: &str) -> String {
+        format!("{}{}", Self::msg_key_prefix(), my_id)
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key.starts_with(Self::msg_key_prefix())
+    }
+}
+
+/// Sent by an HA scope actor to itself when a phase of a planned switchover starts, to roll the
+/// switchover back if it is still in the phase when the message arrives.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlannedSwitchoverTimeout {
+    pub switchover_id: String,
+    pub phase: SwitchoverPhase,
+}
+
+impl PlannedSwitchoverTimeout {
+    pub fn new_actor_msg(switchover_id: &str, phase: SwitchoverPhase) -> Result<ActorMessage> {
+        ActorMessage::new(
+            Self::msg_key(),
+            &Self {
+                switchover_id: switchover_id.to_string(),
+                phase,
+            },
+        )
+    }
+
+    pub fn msg_key() -> &'static str {
+        "PlannedSwitchoverTimeout"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
+/// Sent by an HA scope actor to itself when it starts holding an approved activate_role until the flow
+/// sync completes, to activate the role anyway if it is still held when the message arrives.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct FlowSyncTimeout {
+    pub held_since_in_ms: i64,
+}
+
+impl FlowSyncTimeout {
+    pub fn new_actor_msg(held_since_in_ms: i64) -> Result<ActorMessage> {
+        ActorMessage::new(Self::msg_key(), &Self { held_since_in_ms })
+    }
+
+    pub fn msg_key() -> &'static str {
+        "FlowSyncTimeout"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
+/// Sent by a DPU actor to itself when a dampened BFD session may be stable again.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct BfdDampeningRelease {}
+
+impl BfdDampeningRelease {
+    pub fn new_actor_msg() -> Result<ActorMessage> {
+        ActorMessage::new(Self::msg_key(), &Self {})
+    }
+
+    pub fn msg_key() -> &'static str {
+        "BfdDampeningRelease"
+    }
+
+    pub fn is_my_msg(key: &str) -> bool {
+        key == Self::msg_key()
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq)]
 pub struct ActorRegistration {
     pub active: bool,
@@ -209,4 +675,76 @@ impl ActorRegistration {
     pub fn is_my_msg(key: &str, reg_type: RegistrationType) -> bool {
         key.starts_with(Self::msg_key_prefix(reg_type))
     }
+
+    /// If updates were missed before the state update `key`, register to its sender again so it
+    /// sends its current state.
+    pub fn refresh_on_gap(
+        incoming: &Incoming,
+        outgoing: &mut Outgoing,
+        key: &str,
+        reg_type: RegistrationType,
+        my_id: &str,
+    ) -> Result<()> {
+        let entry = incoming.get_entry(key)?;
+        if entry.missed > 0 {
+            warn!(
+                "Missed {} updates of {key} from {}, requesting a refresh",
+                entry.missed,
+                entry.source.to_longest_path()
+            );
+            outgoing.send(entry.source.clone(), Self::new_actor_msg(true, reg_type, my_id)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state_sequencer_numbers_changed_states() {
+        let mut sequencer = StateSequencer::default();
+        let seqs: Vec<u64> = [1, 1, 2, 1]
+            .iter()
+            .map(|data| {
+                let msg = ActorMessage::new("state", data).unwrap();
+                sequencer.stamp(msg).seq.unwrap().seq
+            })
+            .collect();
+        assert_eq!(seqs, vec![1, 1, 2, 3]);
+
+        // A restarted actor numbers after its predecessor
+        let epoch = sequencer.epoch;
+        assert!(StateSequencer::default().epoch > epoch);
+    }
+
+    #[test]
+    fn ha_set_peer_actor_sp() {
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 2,
+        };
+        let my_sp =
+            ServicePath::from_string("region-a.cluster-a.10.0.0.0-dpu0/hamgrd/0/ha-scope/vdpu0:haset0").unwrap();
+        let sp = peer.actor_sp(&my_sp, "ha-scope", "vdpu1:haset0");
+        assert_eq!(
+            sp.to_longest_path(),
+            "region-a.cluster-a.10.0.1.0-dpu2/hamgrd/0/ha-scope/vdpu1:haset0"
+        );
+        assert_eq!(
+            peer.hamgrd_sp(&my_sp).to_longest_path(),
+            "region-a.cluster-a.10.0.1.0-dpu2/hamgrd/0"
+        );
+
+        // swbusd of IPv6-only NPUs are named after their IPv6 address
+        let peer = HaSetPeer {
+            npu_ip: "fc00::101".to_string(),
+            ..peer
+        };
+        let sp = peer.actor_sp(&my_sp, "ha-scope", "vdpu1:haset0");
+        assert_eq!(sp.node_id, "fc00::101-dpu2");
+        assert_eq!(ServicePath::from_string(&sp.to_longest_path()).unwrap(), sp);
+    }
 }
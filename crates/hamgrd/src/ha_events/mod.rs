@@ -0,0 +1,314 @@
+//! HA lifecycle events
+//!
+//! HA events are always logged through tracing. They can also be forwarded to external systems by
+//! registering [`EventExporter`]s, each with an [`EventFilter`] on severity and event type:
+//! - [`syslog::SyslogExporter`] sends RFC 5424 messages with the event fields as structured data.
+//! - `webhook::WebhookExporter` posts events as JSON to an HTTP endpoint (feature `webhook`).
+use crate::db_structs::now_in_millis;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sonic_common::metrics::CounterVec;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use tracing::{error, info, warn};
+
+pub mod syslog;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+static EXPORTERS: Mutex<Vec<(EventFilter, Box<dyn EventExporter>)>> = Mutex::new(Vec::new());
+
+/// Number of recent events kept for techsupport dumps.
+const HISTORY_LEN: usize = 1000;
+
+static HISTORY: Mutex<VecDeque<HaEvent>> = Mutex::new(VecDeque::new());
+
+static EVENTS: LazyLock<Arc<CounterVec>> =
+    LazyLock::new(|| CounterVec::register("hamgrd_ha_events_total", "HA events emitted, by type.", &["event_type"]));
+
+static ROLE_TRANSITIONS: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "hamgrd_ha_state_transitions_total",
+        "HA role changes of the HA scopes, by old and new role.",
+        &["from", "to"],
+    )
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HaEventType {
+    HaRoleChange,
+    HaAlarmSet,
+    HaAlarmClear,
+    HaWriteFailed,
+    HaActorCrash,
+    HaActorStuck,
+    HaOrchagentStuck,
+    HaPeerPlannedExit,
+    HaSwitchoverRolledBack,
+    HaPeerUnreachable,
+    HaPeerReachable,
+}
+
+impl HaEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HaEventType::HaRoleChange => "HA_ROLE_CHANGE",
+            HaEventType::HaAlarmSet => "HA_ALARM_SET",
+            HaEventType::HaAlarmClear => "HA_ALARM_CLEAR",
+            HaEventType::HaWriteFailed => "HA_WRITE_FAILED",
+            HaEventType::HaActorCrash => "HA_ACTOR_CRASH",
+            HaEventType::HaActorStuck => "HA_ACTOR_STUCK",
+            HaEventType::HaOrchagentStuck => "HA_ORCHAGENT_STUCK",
+            HaEventType::HaPeerPlannedExit => "HA_PEER_PLANNED_EXIT",
+            HaEventType::HaSwitchoverRolledBack => "HA_SWITCHOVER_ROLLED_BACK",
+            HaEventType::HaPeerUnreachable => "HA_PEER_UNREACHABLE",
+            HaEventType::HaPeerReachable => "HA_PEER_REACHABLE",
+        }
+    }
+}
+
+impl FromStr for HaEventType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "HA_ROLE_CHANGE" => Ok(HaEventType::HaRoleChange),
+            "HA_ALARM_SET" => Ok(HaEventType::HaAlarmSet),
+            "HA_ALARM_CLEAR" => Ok(HaEventType::HaAlarmClear),
+            "HA_WRITE_FAILED" => Ok(HaEventType::HaWriteFailed),
+            "HA_ACTOR_CRASH" => Ok(HaEventType::HaActorCrash),
+            "HA_ACTOR_STUCK" => Ok(HaEventType::HaActorStuck),
+            "HA_ORCHAGENT_STUCK" => Ok(HaEventType::HaOrchagentStuck),
+            "HA_PEER_PLANNED_EXIT" => Ok(HaEventType::HaPeerPlannedExit),
+            "HA_SWITCHOVER_ROLLED_BACK" => Ok(HaEventType::HaSwitchoverRolledBack),
+            "HA_PEER_UNREACHABLE" => Ok(HaEventType::HaPeerUnreachable),
+            "HA_PEER_REACHABLE" => Ok(HaEventType::HaPeerReachable),
+            _ => Err(anyhow!("unknown HA event type: {s}")),
+        }
+    }
+}
+
+/// Severity of an event, from the most to the least severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HaEventSeverity {
+    Critical,
+    Major,
+    Minor,
+    Info,
+}
+
+impl HaEventSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HaEventSeverity::Critical => "critical",
+            HaEventSeverity::Major => "major",
+            HaEventSeverity::Minor => "minor",
+            HaEventSeverity::Info => "info",
+        }
+    }
+}
+
+impl FromStr for HaEventSeverity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "critical" => Ok(HaEventSeverity::Critical),
+            "major" => Ok(HaEventSeverity::Major),
+            "minor" => Ok(HaEventSeverity::Minor),
+            "info" => Ok(HaEventSeverity::Info),
+            _ => Err(anyhow!("unknown HA event severity: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HaEvent {
+    pub event_type: HaEventType,
+    pub severity: HaEventSeverity,
+    // The time the event is raised in milliseconds.
+    pub time_in_ms: i64,
+    // The HA scope or resource the event is about.
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_role: Option<String>,
+    pub reason: String,
+}
+
+impl HaEvent {
+    pub fn new(event_type: HaEventType, severity: HaEventSeverity, scope: &str, reason: &str) -> Self {
+        HaEvent {
+            event_type,
+            severity,
+            time_in_ms: now_in_millis(),
+            scope: scope.to_string(),
+            old_role: None,
+            new_role: None,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn role_change(scope: &str, old_role: &str, new_role: &str, reason: &str) -> Self {
+        HaEvent {
+            old_role: Some(old_role.to_string()),
+            new_role: Some(new_role.to_string()),
+            ..HaEvent::new(HaEventType::HaRoleChange, HaEventSeverity::Info, scope, reason)
+        }
+    }
+}
+
+/// Selects the events forwarded to an exporter.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    // Least severe event to forward. All severities are forwarded if not set.
+    pub min_severity: Option<HaEventSeverity>,
+    // Event types to forward. All types are forwarded if empty.
+    pub event_types: Vec<HaEventType>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &HaEvent) -> bool {
+        if self.min_severity.is_some_and(|min| event.severity > min) {
+            return false;
+        }
+        self.event_types.is_empty() || self.event_types.contains(&event.event_type)
+    }
+}
+
+/// A destination HA events are forwarded to. `export` is called from actor callbacks, so it must not
+/// block; exporters talking to remote systems should queue the event and send it in the background.
+pub trait EventExporter: Send {
+    fn name(&self) -> &str;
+
+    fn export(&self, event: &HaEvent) -> Result<()>;
+}
+
+pub fn add_exporter(exporter: Box<dyn EventExporter>, filter: EventFilter) {
+    info!("Exporting HA events to {} with {:?}", exporter.name(), filter);
+    EXPORTERS.lock().unwrap().push((filter, exporter));
+}
+
+/// Report an HA event.
+pub fn emit(event: HaEvent) {
+    match event.severity {
+        HaEventSeverity::Critical | HaEventSeverity::Major => warn!(
+            "{} on {}: {} (old_role: {:?}, new_role: {:?})",
+            event.event_type.as_str(),
+            event.scope,
+            event.reason,
+            event.old_role,
+            event.new_role
+        ),
+        _ => info!(
+            "{} on {}: {} (old_role: {:?}, new_role: {:?})",
+            event.event_type.as_str(),
+            event.scope,
+            event.reason,
+            event.old_role,
+            event.new_role
+        ),
+    }
+
+    EVENTS.with_label_values(&[event.event_type.as_str()]).inc();
+    if let (Some(old_role), Some(new_role)) = (&event.old_role, &event.new_role) {
+        ROLE_TRANSITIONS.with_label_values(&[old_role, new_role]).inc();
+    }
+
+    {
+        let mut history = HISTORY.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+    }
+
+    for (filter, exporter) in EXPORTERS.lock().unwrap().iter() {
+        if !filter.matches(&event) {
+            continue;
+        }
+        if let Err(e) = exporter.export(&event) {
+            error!("Failed to export HA event to {}: {e:#}", exporter.name());
+        }
+    }
+}
+
+/// The most recent events, oldest first.
+pub fn history() -> Vec<HaEvent> {
+    HISTORY.lock().unwrap().iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_filter() {
+        let role_change = HaEvent::role_change("vdpu0|haset0", "active", "standby", "dpu initiated");
+        let alarm = HaEvent::new(HaEventType::HaAlarmSet, HaEventSeverity::Major, "vdpu0|haset0", "x");
+
+        assert!(EventFilter::default().matches(&role_change));
+
+        let filter = EventFilter {
+            min_severity: Some("major".parse().unwrap()),
+            event_types: Vec::new(),
+        };
+        assert!(!filter.matches(&role_change));
+        assert!(filter.matches(&alarm));
+
+        let filter = EventFilter {
+            min_severity: None,
+            event_types: vec!["ha_role_change".parse().unwrap()],
+        };
+        assert!(filter.matches(&role_change));
+        assert!(!filter.matches(&alarm));
+
+        assert!("fatal".parse::<HaEventSeverity>().is_err());
+    }
+
+    #[test]
+    fn test_event_history() {
+        let scope = format!("history-{}", uuid::Uuid::new_v4());
+        emit(HaEvent::new(
+            HaEventType::HaActorStuck,
+            HaEventSeverity::Major,
+            &scope,
+            "first",
+        ));
+        emit(HaEvent::new(
+            HaEventType::HaActorStuck,
+            HaEventSeverity::Major,
+            &scope,
+            "second",
+        ));
+
+        let reasons: Vec<_> = history()
+            .into_iter()
+            .filter(|event| event.scope == scope)
+            .map(|event| event.reason)
+            .collect();
+        assert_eq!(reasons, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_event_json() {
+        let mut event = HaEvent::role_change("vdpu0|haset0", "active", "standby", "dpu initiated");
+        event.time_in_ms = 1718032542000;
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event_type": "HA_ROLE_CHANGE",
+                "severity": "info",
+                "time_in_ms": 1718032542000_i64,
+                "scope": "vdpu0|haset0",
+                "old_role": "active",
+                "new_role": "standby",
+                "reason": "dpu initiated",
+            })
+        );
+    }
+}
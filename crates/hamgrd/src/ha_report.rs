@@ -0,0 +1,117 @@
+//! HA scope summary for `show dash ha`
+//!
+//! The summary of an HA scope is written to STATE_DB/DASH_HA_SCOPE_SUMMARY_TABLE on the NPU. It is an
+//! internal table entry of the HA scope actor, so the changes made while handling a message are
+//! written when the actor commits its state, in a single write of the whole entry. None of the fields
+//! is optional, so that write replaces all of them and the CLI never shows fields of two different
+//! updates side by side. `update_count` counts the updates, so that a reader can tell whether two
+//! reads saw the same one.
+use crate::db_structs::{now_in_millis, update_field_values, DashHaScopeSummaryTable};
+use anyhow::Result;
+use swbus_actor::state::internal::Internal;
+use swss_common::SonicDbTable;
+
+pub const PEER_STATE_UNKNOWN: &str = "unknown";
+pub const PEER_STATE_REACHABLE: &str = "reachable";
+pub const PEER_STATE_UNREACHABLE: &str = "unreachable";
+
+fn internal_key() -> &'static str {
+    DashHaScopeSummaryTable::table_name()
+}
+
+/// Create the summary entry of `scope`, or take over the one a previous hamgrd instance left.
+pub async fn add_summary(internal: &mut Internal, scope: &str) -> Result<()> {
+    if internal.has_entry(internal_key(), scope) {
+        return Ok(());
+    }
+    let table = crate::tables::open_table::<DashHaScopeSummaryTable>().await?;
+    internal.add(internal_key(), table, scope).await;
+    Ok(())
+}
+
+/// The summary of `scope`, `None` until it is added.
+pub fn get_summary(internal: &Internal, scope: &str) -> Option<DashHaScopeSummaryTable> {
+    if !internal.has_entry(internal_key(), scope) {
+        return None;
+    }
+    let fvs = internal.get(internal_key());
+    if fvs.is_empty() {
+        return Some(DashHaScopeSummaryTable {
+            peer_state: PEER_STATE_UNKNOWN.to_string(),
+            ..Default::default()
+        });
+    }
+    Some(swss_serde::from_field_values(fvs).unwrap_or_default())
+}
+
+/// Apply `update` to the summary of `scope`. The entry is only written if `update` changes it, and
+/// not at all until it is added with [`add_summary`].
+pub fn update_summary(
+    internal: &mut Internal,
+    scope: &str,
+    update: impl FnOnce(&mut DashHaScopeSummaryTable),
+) -> Result<()> {
+    let Some(current) = get_summary(internal, scope) else {
+        return Ok(());
+    };
+    let mut summary = current.clone();
+    update(&mut summary);
+    if summary == current && !internal.get(internal_key()).is_empty() {
+        return Ok(());
+    }
+    summary.update_count = current.update_count + 1;
+    summary.last_updated_time_in_ms = now_in_millis();
+    update_field_values(internal.get_mut(internal_key()), &summary)
+}
+
+/// Record that the active role of `scope` moved between the DPUs because of `cause`.
+pub fn record_switchover(internal: &mut Internal, scope: &str, cause: &str) -> Result<()> {
+    update_summary(internal, scope, |summary| {
+        summary.last_switchover_cause = cause.to_string();
+        summary.last_switchover_time_in_ms = now_in_millis();
+        summary.switchover_count += 1;
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common_testing::Redis;
+
+    #[tokio::test]
+    async fn summary_updates() {
+        let _redis = Redis::start_config_db();
+        let mut internal = Internal::default();
+        let scope = "vdpu0|haset0";
+
+        // nothing is written before the entry is added
+        update_summary(&mut internal, scope, |summary| summary.ha_role = "active".to_string()).unwrap();
+        assert!(get_summary(&internal, scope).is_none());
+
+        add_summary(&mut internal, scope).await.unwrap();
+        update_summary(&mut internal, scope, |summary| {
+            summary.ha_role = "active".to_string();
+            summary.ha_role_start_time_in_ms = 1000;
+            summary.peer_state = PEER_STATE_REACHABLE.to_string();
+        })
+        .unwrap();
+        let summary = get_summary(&internal, scope).unwrap();
+        assert_eq!(summary.ha_role, "active");
+        assert_eq!(summary.peer_state, PEER_STATE_REACHABLE);
+        assert_eq!(summary.update_count, 1);
+
+        // an update that changes nothing is not counted
+        update_summary(&mut internal, scope, |summary| summary.ha_role = "active".to_string()).unwrap();
+        assert_eq!(get_summary(&internal, scope).unwrap().update_count, 1);
+
+        record_switchover(&mut internal, scope, "planned switchover switchover0").unwrap();
+        record_switchover(&mut internal, scope, "unplanned failover").unwrap();
+        let summary = get_summary(&internal, scope).unwrap();
+        assert_eq!(summary.last_switchover_cause, "unplanned failover");
+        assert_eq!(summary.switchover_count, 2);
+        assert_eq!(summary.update_count, 3);
+        // the fields of the last update are kept together
+        assert_eq!(summary.ha_role, "active");
+        assert_eq!(summary.ha_role_start_time_in_ms, 1000);
+    }
+}
@@ -0,0 +1,111 @@
+//! HA timers
+//!
+//! The timers HA behavior depends on have built-in defaults and can be set in DASH_HA_GLOBAL_CONFIG. The
+//! DPU actor of the local DPU consumes the table and [`apply`]s every change. The HA scope actors read
+//! the [`current`] timers each time they arm one, and the peer monitor follows the changes with
+//! [`subscribe`], so a change takes effect without restarting anything. The BFD hold time is the probe
+//! interval times the multiplier, which the DPU actor applies to its BFD sessions itself.
+use crate::db_structs::DashHaGlobalConfig;
+use crate::peer_monitor::PeerMonitorPolicy;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::info;
+
+/// Time to wait for each phase of a planned switchover, unless set in DASH_HA_SCOPE_CONFIG_TABLE.
+const PLANNED_SWITCHOVER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time an approved activate_role waits for the flow sync, unless set in DASH_HA_SCOPE_CONFIG_TABLE.
+const FLOW_SYNC_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HaTimers {
+    /// Time to wait for each phase of a planned switchover, unless set for the HA scope
+    pub planned_switchover_timeout: Duration,
+    /// Time an approved activate_role waits for the flow sync, unless set for the HA scope
+    pub flow_sync_timeout: Duration,
+    /// How the peer hamgrd is pinged. It is lost after `interval` times `failure_threshold`.
+    pub peer_monitor: PeerMonitorPolicy,
+}
+
+impl Default for HaTimers {
+    fn default() -> Self {
+        Self {
+            planned_switchover_timeout: PLANNED_SWITCHOVER_TIMEOUT,
+            flow_sync_timeout: FLOW_SYNC_TIMEOUT,
+            peer_monitor: PeerMonitorPolicy::default(),
+        }
+    }
+}
+
+impl HaTimers {
+    /// The timers set in `config`, the defaults for the others.
+    pub fn from_config(config: &DashHaGlobalConfig) -> Self {
+        let millis = |ms: Option<u32>, default: Duration| ms.map_or(default, |ms| Duration::from_millis(ms.into()));
+        let default = Self::default();
+        Self {
+            planned_switchover_timeout: millis(
+                config.planned_switchover_timeout_in_ms,
+                default.planned_switchover_timeout,
+            ),
+            flow_sync_timeout: millis(config.flow_sync_timeout_in_ms, default.flow_sync_timeout),
+            peer_monitor: PeerMonitorPolicy {
+                interval: millis(config.peer_monitor_interval_in_ms, default.peer_monitor.interval),
+                failure_threshold: config
+                    .peer_monitor_failure_threshold
+                    .unwrap_or(default.peer_monitor.failure_threshold),
+            },
+        }
+    }
+}
+
+static TIMERS: LazyLock<watch::Sender<HaTimers>> = LazyLock::new(|| watch::Sender::new(HaTimers::default()));
+
+/// The timers in effect.
+pub fn current() -> HaTimers {
+    *TIMERS.borrow()
+}
+
+/// Follow the changes of the timers.
+pub fn subscribe() -> watch::Receiver<HaTimers> {
+    TIMERS.subscribe()
+}
+
+/// Put the timers set in `config` into effect. The DPU actors of all slots apply the same config, only
+/// a change is logged and notified.
+pub fn apply(config: &DashHaGlobalConfig) {
+    let timers = HaTimers::from_config(config);
+    let changed = TIMERS.send_if_modified(|current| {
+        if *current == timers {
+            return false;
+        }
+        *current = timers;
+        true
+    });
+    if changed {
+        info!("HA timers changed to {timers:?}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unset_timers_are_defaulted() {
+        assert_eq!(
+            HaTimers::from_config(&DashHaGlobalConfig::default()),
+            HaTimers::default()
+        );
+
+        let config = DashHaGlobalConfig {
+            flow_sync_timeout_in_ms: Some(60000),
+            peer_monitor_failure_threshold: Some(5),
+            ..Default::default()
+        };
+        let timers = HaTimers::from_config(&config);
+        assert_eq!(timers.flow_sync_timeout, Duration::from_secs(60));
+        assert_eq!(timers.planned_switchover_timeout, PLANNED_SWITCHOVER_TIMEOUT);
+        assert_eq!(timers.peer_monitor.failure_threshold, 5);
+        assert_eq!(timers.peer_monitor.interval, PeerMonitorPolicy::default().interval);
+    }
+}
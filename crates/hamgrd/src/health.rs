@@ -0,0 +1,270 @@
+//! Health check
+//!
+//! Handles `HAMGRD_HEALTH_CHECK` management requests sent to `<hamgrd>/health/0`, for the systemd
+//! watchdog and container probes through `swbus-cli hamgrd health`. hamgrd is live as long as the
+//! slot answers and none of its actors was given up by its supervisor, since a restart is the only way
+//! to get those back. It is ready when it is live, its producer bridges are running, the databases
+//! answer, it is connected to swbusd and every actor the config calls for is running.
+use crate::actors::{dpu::DpuActor, ha_scope::HaScopeActor, ha_set::HaSetActor, vdpu::VDpuActor, DbBasedActor};
+use crate::db_structs::{ConfigSnapshot, HamgrdStartupStatusTable};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swbus_actor::supervisor::ActorHealthEvent;
+use swbus_edge::swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_edge::swbus_proto::swbus::{
+    request_response::ResponseBody, swbus_message::Body, HealthCheck, HealthReport, ManagementRequestType, ServicePath,
+    SwbusErrorCode, SwbusMessage,
+};
+use swbus_edge::SwbusEdgeRuntime;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::error;
+
+/// Time each database probe may take before the databases are considered down.
+const DB_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Actors given up by their supervisor, by slot id.
+static DOWN_ACTORS: Mutex<BTreeMap<u32, BTreeSet<String>>> = Mutex::new(BTreeMap::new());
+
+/// Record an actor health event of the current slot. Actors that are down fail the liveness check.
+pub fn record_actor_health(event: &ActorHealthEvent) {
+    if let ActorHealthEvent::Down { actor, .. } = event {
+        DOWN_ACTORS
+            .lock()
+            .unwrap()
+            .entry(crate::slot::current().id())
+            .or_default()
+            .insert(format!("{}/{}", actor.resource_type, actor.resource_id));
+    }
+}
+
+pub struct HealthChecker {
+    sp: ServicePath,
+    rt: Arc<SwbusEdgeRuntime>,
+    handler_rx: Receiver<SwbusMessage>,
+    id_generator: MessageIdGenerator,
+    producer_bridges: Vec<JoinHandle<()>>,
+}
+
+impl HealthChecker {
+    /// Checker of the slot of `rt`, whose producer bridges run as `producer_bridges`.
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, producer_bridges: Vec<JoinHandle<()>>) -> Self {
+        let sp = rt.new_sp("health", "0");
+        let (handler_tx, handler_rx) = channel::<SwbusMessage>(10);
+        rt.add_handler(sp.clone(), handler_tx);
+
+        Self {
+            sp,
+            rt,
+            handler_rx,
+            id_generator: MessageIdGenerator::new(),
+            producer_bridges,
+        }
+    }
+
+    pub async fn run(mut self) {
+        while let Some(msg) = self.handler_rx.recv().await {
+            let Some(Body::ManagementRequest(ref mgmt_request)) = msg.body else {
+                continue;
+            };
+
+            let (code, error_message, body) = match ManagementRequestType::try_from(mgmt_request.request) {
+                Ok(ManagementRequestType::HamgrdHealthCheck) => {
+                    let report = self.check().await;
+                    (
+                        SwbusErrorCode::Ok,
+                        String::new(),
+                        Some(ResponseBody::HealthReport(report)),
+                    )
+                }
+                _ => (
+                    SwbusErrorCode::InvalidArgs,
+                    format!("Unsupported request type: {}", mgmt_request.request),
+                    None,
+                ),
+            };
+            let response = SwbusMessage::new_response(
+                &msg,
+                Some(&self.sp),
+                code,
+                &error_message,
+                self.id_generator.generate(),
+                body,
+            );
+            if self.rt.send(response).await.is_err() {
+                error!("Failed to send health check response to swbus");
+            }
+        }
+    }
+
+    async fn check(&self) -> HealthReport {
+        let slot = crate::slot::current();
+        let down_actors: Vec<String> = DOWN_ACTORS
+            .lock()
+            .unwrap()
+            .get(&slot.id())
+            .map(|actors| actors.iter().cloned().collect())
+            .unwrap_or_default();
+        let running_actors: BTreeSet<String> = match slot.actor_runtime() {
+            Some(runtime) => runtime
+                .actors()
+                .iter()
+                .map(|sp| format!("{}/{}", sp.resource_type, sp.resource_id))
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        let stopped_bridges = self.producer_bridges.iter().filter(|h| h.is_finished()).count();
+        let snapshot = probe(ConfigSnapshot::read()).await;
+        let state_db = probe(async {
+            let mut table = crate::tables::open_table::<HamgrdStartupStatusTable>().await?;
+            table.get_async(&slot.name()).await
+        })
+        .await;
+
+        let mut checks = vec![
+            liveness("actors_down", down_actors.is_empty(), down_actors.join(", ")),
+            readiness(
+                "producer_bridges",
+                stopped_bridges == 0,
+                format!("{} of {} stopped", stopped_bridges, self.producer_bridges.len()),
+            ),
+            readiness(
+                "swbusd",
+                self.rt.swbusd_connected().await,
+                "connected to swbusd".to_string(),
+            ),
+        ];
+        let (databases_ok, databases_detail) = match (&snapshot, &state_db) {
+            (Ok(_), Ok(_)) => (true, "config and state databases answer".to_string()),
+            (Err(e), _) => (false, format!("reading config: {e:#}")),
+            (_, Err(e)) => (false, format!("reading STATE_DB: {e:#}")),
+        };
+        checks.push(readiness("databases", databases_ok, databases_detail));
+        checks.push(match &snapshot {
+            Ok(snapshot) => {
+                let missing: Vec<String> = expected_actors(snapshot)
+                    .into_iter()
+                    .filter(|actor| !running_actors.contains(actor))
+                    .collect();
+                readiness(
+                    "actors",
+                    missing.is_empty(),
+                    match missing.is_empty() {
+                        true => format!("{} running", running_actors.len()),
+                        false => format!("not running: {}", missing.join(", ")),
+                    },
+                )
+            }
+            Err(_) => readiness("actors", false, "config is not readable".to_string()),
+        });
+        report(checks)
+    }
+}
+
+/// The actors the actor creators create for the entries of `snapshot`, as `<resource_type>/<resource_id>`.
+fn expected_actors(snapshot: &ConfigSnapshot) -> Vec<String> {
+    let actors = |name: &str, keys: Vec<&String>| -> Vec<String> {
+        keys.into_iter().map(|key| format!("{name}/{key}")).collect()
+    };
+    let ha_set_filter = <HaSetActor as DbBasedActor>::key_filter();
+    let ha_scope_filter = <HaScopeActor as DbBasedActor>::key_filter();
+    [
+        actors(DpuActor::name(), snapshot.dpus.keys().collect()),
+        actors(VDpuActor::name(), snapshot.vdpus.keys().collect()),
+        actors(
+            HaSetActor::name(),
+            snapshot
+                .ha_sets
+                .keys()
+                .filter(|key| ha_set_filter.matches(key))
+                .collect(),
+        ),
+        actors(
+            HaScopeActor::name(),
+            snapshot
+                .ha_scopes
+                .keys()
+                .filter(|key| ha_scope_filter.matches(key))
+                .collect(),
+        ),
+    ]
+    .concat()
+}
+
+async fn probe<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    timeout(DB_PROBE_TIMEOUT, future)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out after {DB_PROBE_TIMEOUT:?}")))
+}
+
+fn liveness(name: &str, ok: bool, detail: String) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        ok,
+        detail,
+        liveness: true,
+    }
+}
+
+fn readiness(name: &str, ok: bool, detail: String) -> HealthCheck {
+    HealthCheck {
+        liveness: false,
+        ..liveness(name, ok, detail)
+    }
+}
+
+/// The report of `checks`. Ready needs every check to pass, live only the liveness checks.
+fn report(checks: Vec<HealthCheck>) -> HealthReport {
+    HealthReport {
+        live: checks.iter().filter(|check| check.liveness).all(|check| check.ok),
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actors::fixtures::make_ha_scope_config;
+    use crate::actors::test::{make_dpu_object, make_dpu_scope_ha_set_config};
+
+    #[test]
+    fn report_is_live_unless_a_liveness_check_fails() {
+        let report = super::report(vec![
+            liveness("actors_down", true, String::new()),
+            readiness("swbusd", false, String::new()),
+        ]);
+        assert!(report.live);
+        assert!(!report.ready);
+
+        let report = super::report(vec![
+            liveness("actors_down", false, "vdpu/vdpu0".to_string()),
+            readiness("swbusd", true, String::new()),
+        ]);
+        assert!(!report.live);
+        assert!(!report.ready);
+
+        let report = super::report(vec![readiness("swbusd", true, String::new())]);
+        assert!(report.live);
+        assert!(report.ready);
+    }
+
+    #[test]
+    fn expected_actors_follow_the_config() {
+        let mut snapshot = ConfigSnapshot::default();
+        snapshot.dpus.insert("switch0_dpu0".to_string(), make_dpu_object(0, 0));
+        let (ha_set_id, ha_set) = make_dpu_scope_ha_set_config(0, 0);
+        snapshot.ha_sets.insert(ha_set_id, ha_set);
+        snapshot
+            .ha_scopes
+            .insert("vdpu0-0:haset0-0".to_string(), make_ha_scope_config("active"));
+        assert_eq!(
+            expected_actors(&snapshot),
+            vec!["dpu/switch0_dpu0", "ha-set/haset0-0", "ha-scope/vdpu0-0:haset0-0"]
+        );
+    }
+}
@@ -1,78 +1,401 @@
 use anyhow::{anyhow, Ok};
 use clap::Parser;
 use sonic_common::log;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use swbus_actor::{supervisor::ActorHealthEvent, watchdog::WatchdogPolicy, ActorRuntime};
+use swbus_config::SwbusConfig;
+use swbus_edge::mailbox::{MailboxConfig, OverflowPolicy};
+use swbus_edge::{
+    simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::ServicePath, RuntimeEnv, SwbusEdgeRuntime,
+    SwbusEdgeRuntimeBuilder,
 };
-use swbus_actor::{set_global_runtime, ActorRuntime};
-use swbus_config::swbus_config_from_db;
-use swbus_edge::{simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::ServicePath, RuntimeEnv, SwbusEdgeRuntime};
-use swss_common::{sonic_db_config_initialize_global, DbConnector, SonicDbTable};
+use swss_common::{DbConnector, SonicDbTable};
 use swss_common_bridge::consumer::ConsumerBridge;
-use tokio::{signal, task::JoinHandle, time::timeout};
-use tracing::error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 mod actors;
+mod alarms;
+mod audit;
+mod config_validation;
+mod control;
+mod db_options;
 mod db_structs;
+mod dead_letters;
+mod exit;
+mod generation;
 mod ha_actor_messages;
+mod ha_events;
+mod ha_report;
+mod ha_timers;
+mod health;
+mod mgmt_client;
+#[cfg(feature = "dpu")]
+mod orchagent_lag;
+mod peer_monitor;
+mod readiness;
+mod schema;
+mod shutdown;
+#[cfg(feature = "dev-sim")]
+mod sim;
+mod slot;
+mod tables;
+mod techsupport;
 use actors::spawn_zmq_producer_bridge;
 use actors::{dpu::DpuActor, ha_scope::HaScopeActor, ha_set::HaSetActor, vdpu::VDpuActor, DbBasedActor};
 use anyhow::Result;
 use db_structs::{
     BfdSessionTable, DashHaScopeConfigTable, DashHaScopeTable, DashHaSetConfigTable, DashHaSetTable, Dpu, VDpu,
 };
+use exit::ExitReason;
 use lazy_static::lazy_static;
+use slot::Slot;
 use std::any::Any;
 
 lazy_static! {
-    static ref DPU_SLOT_ID: Mutex<u8> = Mutex::new(0);
+    // Tables consumed from another db than the one in their SonicDbTable metadata, by table name
+    static ref TABLE_SOURCES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+    // Mailboxes of the actors set by --actor-mailbox, by actor type
+    static ref ACTOR_MAILBOXES: Mutex<HashMap<String, MailboxConfig>> = Mutex::new(HashMap::new());
+    // Token read from --swbus-auth-token-file, presented to the swbusd of every slot
+    static ref SWBUS_AUTH_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+    // Certificates set by --swbus-tls-*, to connect to the swbusd of every slot over TLS
+    static ref SWBUS_TLS: Mutex<Option<swbus_config::TlsConfig>> = Mutex::new(None);
 }
 
 #[derive(Parser, Debug)]
 #[command(name = "hamgrd")]
 struct Args {
     // The slot id of the DPU. It will read configuration from DPU table in config_db that matches the slot_id.
-    #[arg(short = 's', long)]
-    slot_id: u32,
+    // Several slot ids, e.g. 0,1,2,3, run the DPUs of all these slots in this process.
+    #[arg(short = 's', long, value_delimiter = ',', required_unless_present = "dump_schema")]
+    slot_id: Vec<u32>,
+    // The vDPU served by the DPU of each slot, in the order of --slot-id. HA scope actors are only created
+    // for the HA scopes of the vDPU of their slot, or for all HA scopes if not set.
+    #[arg(long, value_delimiter = ',')]
+    vdpu_id: Vec<String>,
+    // Worker threads of the runtime of every slot. Defaults to the CPUs divided among the slots.
+    #[arg(long)]
+    slot_worker_threads: Option<usize>,
+    // Path of the local control socket. Defaults to /var/run/hamgrd/dpu<slot_id>.sock. Only with a single slot.
+    #[arg(long)]
+    control_socket: Option<String>,
+    // Also send HA events to syslog as RFC 5424 messages with structured data.
+    #[arg(long)]
+    structured_syslog: bool,
+    // Post HA events as JSON to this URL.
+    #[cfg(feature = "webhook")]
+    #[arg(long)]
+    event_webhook: Option<String>,
+    // Only export HA events at or above this severity: critical, major, minor or info.
+    #[arg(long)]
+    event_min_severity: Option<ha_events::HaEventSeverity>,
+    // Only export these HA event types, e.g. HA_ROLE_CHANGE,HA_ALARM_SET. All types if not set.
+    #[arg(long, value_delimiter = ',')]
+    event_types: Vec<ha_events::HaEventType>,
+    // Also append the audit log of the HA state transitions to this JSONL file.
+    #[arg(long)]
+    audit_file: Option<String>,
+    // Entries kept in the audit log, per slot in STATE_DB and in the audit file before it is rotated.
+    #[arg(long, default_value_t = audit::DEFAULT_CAPACITY)]
+    audit_capacity: u64,
+    // Write the JSON Schema of every sonic-db table used by hamgrd to this directory and exit.
+    #[arg(long)]
+    dump_schema: Option<String>,
+    // Consume a table from CONFIG_DB or APPL_DB instead of its default db, e.g.
+    // DASH_HA_SET_CONFIG_TABLE=CONFIG_DB, for deployments where the input is pushed by another daemon.
+    #[arg(long, value_parser = parse_table_source)]
+    table_source: Vec<(String, String)>,
+    // Size and overflow policy of the mailbox of the actors of a type, e.g. dpu=1000 or dpu=1000:reject.
+    // Actors hold up to 10000 messages if not set. When their mailbox is full, they block all deliveries
+    // of the edge runtime (block, the default), or reject the new messages, which their senders resend
+    // later (reject). The drop policies of swbus-edge are refused, since the table updates and actor
+    // messages hamgrd actors receive are never resent once dropped.
+    #[arg(long, value_parser = parse_actor_mailbox)]
+    actor_mailbox: Vec<(String, MailboxConfig)>,
+    // Serve Prometheus metrics at http://<address>/metrics.
+    #[arg(long)]
+    metrics_address: Option<SocketAddr>,
+    // Present the token in this file to swbusd, which authenticates its clients if it has client_auth set.
+    #[arg(long)]
+    swbus_auth_token_file: Option<String>,
+    // Connect to swbusd over mutual TLS, verifying its certificate with this CA certificate in PEM. If not
+    // set, the TLS files of the swbusd config are used when swbusd has TLS set.
+    #[arg(long, requires_all = ["swbus_tls_cert", "swbus_tls_key"])]
+    swbus_tls_ca_cert: Option<PathBuf>,
+    // Certificate in PEM to present to swbusd
+    #[arg(long, requires = "swbus_tls_ca_cert")]
+    swbus_tls_cert: Option<PathBuf>,
+    // Key of the certificate in PEM
+    #[arg(long, requires = "swbus_tls_ca_cert")]
+    swbus_tls_key: Option<PathBuf>,
+    // Name the certificate of swbusd is verified against, instead of its IP address
+    #[arg(long, requires = "swbus_tls_ca_cert")]
+    swbus_tls_domain_name: Option<String>,
+    #[command(flatten)]
+    db: db_options::DbArgs,
+    #[cfg(feature = "dev-sim")]
+    #[command(flatten)]
+    sim: sim::SimArgs,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    if let Some(dir) = &args.dump_schema {
+        if let Err(e) = schema::dump_schemas(dir) {
+            eprintln!("Failed to dump schema: {e:#}");
+            std::process::exit(ExitReason::Internal.code());
+        }
+        return;
+    }
+
     if let Err(e) = log::init("hamgrd", true) {
         eprintln!("Failed to initialize logging: {e}");
     }
 
-    set_dpu_slot_id(args.slot_id as u8);
-    sonic_db_config_initialize_global("/var/run/redis/sonic-db/database_global.json").unwrap();
+    add_event_exporters(&args);
 
-    // Read swbusd config from redis or yaml file
-    let swbus_config = swbus_config_from_db(args.slot_id).unwrap();
+    if let Some(addr) = args.metrics_address {
+        tokio::spawn(async move {
+            if let Err(e) = sonic_common::metrics::serve(addr).await {
+                error!("Failed to serve metrics at {addr}: {e}");
+            }
+        });
+    }
 
-    let mut swbus_sp = swbus_config.get_swbusd_service_path().unwrap_or_else(|| {
-        error!("No cluster route found in swbusd config");
-        std::process::exit(1);
-    });
+    for (table, db) in &args.table_source {
+        info!("Consuming {table} from {db}");
+        set_table_source(table, db);
+    }
+    for (actor, mailbox) in &args.actor_mailbox {
+        info!(
+            "Mailbox of {actor} actors: {} messages, {} when full",
+            mailbox.size, mailbox.overflow
+        );
+        ACTOR_MAILBOXES.lock().unwrap().insert(actor.clone(), *mailbox);
+    }
+
+    if let Some(path) = &args.swbus_auth_token_file {
+        match std::fs::read_to_string(path) {
+            std::io::Result::Ok(token) => *SWBUS_AUTH_TOKEN.lock().unwrap() = Some(token.trim().to_string()),
+            Err(e) => {
+                exit::exit(
+                    ExitReason::ConfigError,
+                    anyhow!("Reading the swbus auth token from {path}: {e}"),
+                )
+                .await
+            }
+        }
+    }
+
+    if let (Some(ca_cert), Some(cert), Some(key)) = (&args.swbus_tls_ca_cert, &args.swbus_tls_cert, &args.swbus_tls_key)
+    {
+        *SWBUS_TLS.lock().unwrap() = Some(swbus_config::TlsConfig {
+            ca_cert: ca_cert.clone(),
+            cert: cert.clone(),
+            key: key.clone(),
+            domain_name: args.swbus_tls_domain_name.clone(),
+        });
+    }
+
+    let slot_ids = args.slot_id.clone();
+    if slot_ids.len() > 1 && args.control_socket.is_some() {
+        exit::exit(
+            ExitReason::ConfigError,
+            anyhow!("--control-socket needs a single --slot-id, every slot has its own socket"),
+        )
+        .await;
+    }
+    if !args.vdpu_id.is_empty() && args.vdpu_id.len() != slot_ids.len() {
+        exit::exit(
+            ExitReason::ConfigError,
+            anyhow!("--vdpu-id needs one vDPU for every --slot-id"),
+        )
+        .await;
+    }
+
+    let db_options = match db_options::DbOptions::from_args(&args.db, |key| std::env::var(key).ok()) {
+        Result::Ok(db_options) => db_options,
+        Err(e) => exit::exit(ExitReason::ConfigError, e.context("Invalid db options")).await,
+    };
+    let db_config = db_options.config_path.clone();
+    db_options::set_db_options(db_options);
+
+    #[cfg(feature = "dev-sim")]
+    if let Err(e) = sim::start(&args.sim) {
+        exit::exit(ExitReason::ConfigError, e.context("Invalid --dev-sim seed")).await;
+    }
+
+    // Wait for the dependencies that come up together with hamgrd
+    if !tables::in_memory() {
+        readiness::wait_for_database_config(&db_config, &readiness::Backoff::default()).await;
+    }
+
+    if let Err(e) = audit::start(args.audit_capacity, args.audit_file.clone().map(Into::into)) {
+        exit::exit(ExitReason::ConfigError, e.context("Starting the audit log")).await;
+    }
+
+    // Every slot runs independently in a runtime of its own, see `slot`
+    let worker_threads = args
+        .slot_worker_threads
+        .unwrap_or_else(|| slot::default_worker_threads(slot_ids.len()));
+    let (signal_tx, signal_rx) = watch::channel(None);
+    let mut slots = Vec::new();
+    for (i, slot_id) in slot_ids.into_iter().enumerate() {
+        let (slot, runtime) = match Slot::start(slot_id, args.vdpu_id.get(i).cloned(), worker_threads) {
+            std::io::Result::Ok(started) => started,
+            Err(e) => {
+                exit::exit(
+                    ExitReason::Internal,
+                    anyhow!("Starting the runtime of slot {slot_id}: {e}"),
+                )
+                .await
+            }
+        };
+        let control_socket = args.control_socket.clone();
+        let signal_rx = signal_rx.clone();
+        let thread = std::thread::Builder::new()
+            .name(format!("hamgrd-{}", slot.name()))
+            .spawn(move || {
+                slot.enter();
+                runtime.block_on(run_slot(slot, control_socket, signal_rx));
+            });
+        match thread {
+            std::io::Result::Ok(thread) => slots.push(thread),
+            Err(e) => {
+                exit::exit(
+                    ExitReason::Internal,
+                    anyhow!("Starting the thread of slot {slot_id}: {e}"),
+                )
+                .await
+            }
+        }
+    }
+
+    // Drain on SIGTERM or SIGINT before exiting
+    let signal = match shutdown::wait_for_signal().await {
+        Result::Ok(signal) => signal,
+        Err(e) => exit::exit(ExitReason::Internal, e.context("Installing the signal handlers")).await,
+    };
+    signal_tx.send_replace(Some(signal));
+    for thread in slots {
+        let name = thread.thread().name().unwrap_or_default().to_string();
+        let joined = tokio::task::spawn_blocking(move || thread.join()).await;
+        if !joined.is_ok_and(|joined| joined.is_ok()) {
+            error!("{name} panicked before it drained");
+        }
+    }
+    #[cfg(feature = "dev-sim")]
+    sim::write_dump(&args.sim);
+}
+
+/// Run `slot` until a signal is received on `signal_rx`, then drain it.
+async fn run_slot(
+    slot: &'static Slot,
+    control_socket: Option<String>,
+    mut signal_rx: watch::Receiver<Option<&'static str>>,
+) {
+    let (swbus_edge, actor_creators) = tokio::select! {
+        started = start_slot(slot, control_socket) => started,
+        // Nothing to drain before the slot started
+        _ = signal_rx.wait_for(Option::is_some) => return,
+    };
+    let signal = signal_rx
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|signal| *signal)
+        .unwrap_or("shutdown");
+    shutdown::drain(swbus_edge, actor_creators, signal).await;
+}
+
+/// Start the actors of `slot` and everything they need. Returns the edge runtime of the slot and the
+/// bridges that feed the actor creators.
+async fn start_slot(
+    slot: &'static Slot,
+    control_socket: Option<String>,
+) -> (Arc<SwbusEdgeRuntime>, Vec<ConsumerBridge>) {
+    let slot_id = slot.id();
+    let backoff = readiness::Backoff::default();
+    let mut startup_status = readiness::StartupStatus::new(slot_id);
+    let (dpu, swbus_config) = readiness::wait_for_dpu_config(slot_id, &backoff, &mut startup_status).await;
+    readiness::wait_for_swbusd(swbus_config.endpoint, &backoff, &mut startup_status).await;
+    startup_status.ready().await;
+
+    let Some(mut swbus_sp) = swbus_config.get_swbusd_service_path() else {
+        exit::exit(
+            ExitReason::ConfigError,
+            anyhow!("No cluster route found in swbusd config"),
+        )
+        .await
+    };
 
     swbus_sp.service_type = "hamgrd".into();
     swbus_sp.service_id = "0".into();
 
-    let dpu = db_structs::get_dpu_config_from_db(args.slot_id).unwrap();
+    // Log a consistent view of the config, which may have changed while hamgrd was down. It is only
+    // reported: the actors get the config from their bridges.
+    match db_structs::ConfigSnapshot::read().await {
+        Result::Ok(snapshot) => {
+            info!(
+                "Startup config: {} DPUs, {} VDPUs, {} HA sets, {} HA scopes",
+                snapshot.dpus.len(),
+                snapshot.vdpus.len(),
+                snapshot.ha_sets.len(),
+                snapshot.ha_scopes.len()
+            );
+            for dangling in snapshot.dangling_references() {
+                warn!("Startup config: {dangling}");
+            }
+        }
+        Err(e) => error!("Failed to read startup config snapshot: {e:#}"),
+    }
 
-    let runtime_data = RuntimeData::new(args.slot_id, swbus_config.npu_ipv4, swbus_config.npu_ipv6);
+    // Tells a previous hamgrd that is still running to stop writing
+    if let Err(e) = slot.generation().bump().await {
+        error!("Failed to start a new generation, a previous hamgrd is not fenced: {e:#}");
+    }
+    tokio::task::spawn(slot.generation().watch());
 
-    // Setup swbus and actor runtime
-    let mut swbus_edge = SwbusEdgeRuntime::new(format!("http://{}", swbus_config.endpoint), swbus_sp.clone());
-    swbus_edge.set_runtime_env(Box::new(runtime_data));
+    let hamgrd_context = HamgrdContext::new(slot_id, swbus_config.npu_ipv4, swbus_config.npu_ipv6);
 
-    swbus_edge.start().await.unwrap();
+    // Setup swbus and actor runtime. Every DPU has a swbusd of its own, so every slot has an edge runtime.
+    let swbus_edge_builder = match swbus_edge_builder(&swbus_config, swbus_sp.clone()) {
+        Result::Ok(builder) => builder.runtime_env(Box::new(hamgrd_context)),
+        Err(e) => exit::exit(ExitReason::ConfigError, e).await,
+    };
+    let swbus_edge = match swbus_edge_builder.build().await {
+        Result::Ok(swbus_edge) => swbus_edge,
+        Err(e) => {
+            exit::exit(
+                ExitReason::SwbusdUnreachable,
+                anyhow!("Starting the swbus edge runtime: {e}"),
+            )
+            .await
+        }
+    };
     let swbus_edge = Arc::new(swbus_edge);
-    let actor_runtime = ActorRuntime::new(swbus_edge.clone());
-    set_global_runtime(actor_runtime);
+    let mut actor_runtime = ActorRuntime::new(swbus_edge.clone());
+    actor_runtime.set_health_handler(report_actor_health);
+    // Actors are supervised, so a stuck one is restarted from scratch
+    actor_runtime.set_watchdog(WatchdogPolicy {
+        abort: true,
+        ..Default::default()
+    });
+    for (actor, mailbox) in ACTOR_MAILBOXES.lock().unwrap().iter() {
+        actor_runtime.set_mailbox(actor, *mailbox);
+    }
+    slot.set_actor_runtime(actor_runtime);
 
     // Start zmq common bridge provider for DPU tables
-    let _producer_handles = spawn_producer_bridges(swbus_edge.clone(), &dpu).await.unwrap();
+    let producer_handles = match spawn_producer_bridges(swbus_edge.clone(), &dpu).await {
+        Result::Ok(handles) => handles,
+        Err(e) => exit::exit(ExitReason::DbUnavailable, e.context("Starting the producer bridges")).await,
+    };
 
     // run a sink to drain all messages that are not handled by any actor
     let sink = SimpleSwbusEdgeClient::new(swbus_edge.clone(), swbus_sp, true /*public*/, true /*sink*/);
@@ -83,43 +406,189 @@ async fn main() {
         }
     });
 
-    let _bridges = start_actor_creators(&swbus_edge).await.unwrap();
+    let actor_creators = match start_actor_creators(&swbus_edge).await {
+        Result::Ok(bridges) => bridges,
+        Err(e) => exit::exit(ExitReason::DbUnavailable, e.context("Starting the actor creators")).await,
+    };
+
+    // Handle techsupport dump requests
+    tokio::task::spawn(techsupport::TechsupportDumper::new(swbus_edge.clone()).run());
+
+    // Handle health checks
+    tokio::task::spawn(health::HealthChecker::new(swbus_edge.clone(), producer_handles).run());
+
+    // Ping the hamgrd of the peers of the HA sets
+    tokio::task::spawn(peer_monitor::PeerMonitor::new(swbus_edge.clone(), ha_timers::current().peer_monitor).run());
 
-    // Wait for Ctrl+C to exit
-    signal::ctrl_c().await.expect("Failed to install Ctrl+C handler");
+    // Local control socket for debugging when swbusd is not reachable
+    let control_socket = control_socket.unwrap_or_else(|| control::default_socket_path(slot_id));
+    if let Err(e) = control::ControlServer::spawn(swbus_edge.clone(), &control_socket) {
+        error!("Failed to start control socket: {e:#}");
+    }
+
+    (swbus_edge, actor_creators)
 }
 
-fn set_dpu_slot_id(slot_id: u8) {
-    let mut data = DPU_SLOT_ID.lock().unwrap();
-    *data = slot_id;
+/// Builder of the edge runtime connected to the swbusd of `swbus_config` as `sp`, with the token and the
+/// TLS certificates set on the command line. The TLS files of `swbus_config` are used if no certificates
+/// are set on the command line, since swbusd only accepts TLS connections when it has TLS set.
+fn swbus_edge_builder(swbus_config: &SwbusConfig, sp: ServicePath) -> Result<SwbusEdgeRuntimeBuilder> {
+    let tls = SWBUS_TLS.lock().unwrap().clone().or_else(|| swbus_config.tls.clone());
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let mut builder = SwbusEdgeRuntime::builder(format!("{scheme}://{}", swbus_config.endpoint), sp);
+    if let Some(tls) = tls {
+        let tls = swbus_edge::core_client::TlsConfig::from_files(
+            &tls.ca_cert,
+            Some((&tls.cert, &tls.key)),
+            tls.domain_name.clone(),
+        )
+        .map_err(|e| anyhow!("Reading the swbus TLS certificates: {e}"))?;
+        builder = builder.tls(tls);
+    }
+    if let Some(token) = SWBUS_AUTH_TOKEN.lock().unwrap().clone() {
+        builder = builder.auth_token(token);
+    }
+    Ok(builder)
 }
 
-fn get_dpu_slot_id() -> u8 {
-    let data = DPU_SLOT_ID.lock().unwrap();
-    *data
+fn add_event_exporters(args: &Args) {
+    let filter = ha_events::EventFilter {
+        min_severity: args.event_min_severity,
+        event_types: args.event_types.clone(),
+    };
+
+    if args.structured_syslog {
+        match ha_events::syslog::SyslogExporter::new() {
+            Result::Ok(exporter) => ha_events::add_exporter(Box::new(exporter), filter.clone()),
+            Err(e) => error!("Failed to enable structured syslog: {e}"),
+        }
+    }
+
+    #[cfg(feature = "webhook")]
+    if let Some(url) = &args.event_webhook {
+        match ha_events::webhook::WebhookExporter::new(url) {
+            Result::Ok(exporter) => ha_events::add_exporter(Box::new(exporter), filter.clone()),
+            Err(e) => error!("Failed to create event webhook: {e}"),
+        }
+    }
+}
+
+fn report_actor_health(event: &ActorHealthEvent) {
+    health::record_actor_health(event);
+    let (event_type, actor, severity, reason) = match event {
+        ActorHealthEvent::Restarting { actor, panic, restarts } => (
+            ha_events::HaEventType::HaActorCrash,
+            actor,
+            ha_events::HaEventSeverity::Major,
+            format!("restarting after crash {restarts}: {panic}"),
+        ),
+        ActorHealthEvent::Down { actor, reason } => (
+            ha_events::HaEventType::HaActorCrash,
+            actor,
+            ha_events::HaEventSeverity::Critical,
+            format!("down: {reason}"),
+        ),
+        ActorHealthEvent::Escalated { actor, parent, reason } => (
+            ha_events::HaEventType::HaActorCrash,
+            actor,
+            ha_events::HaEventSeverity::Major,
+            format!("crash escalated to {}: {reason}", parent.to_longest_path()),
+        ),
+        ActorHealthEvent::Stuck {
+            actor,
+            activity,
+            busy_for,
+            aborted,
+        } => (
+            ha_events::HaEventType::HaActorStuck,
+            actor,
+            ha_events::HaEventSeverity::Major,
+            format!(
+                "stuck on {activity} for {busy_for:?}{}",
+                if *aborted { ", aborted" } else { "" }
+            ),
+        ),
+    };
+    let scope = format!("{}/{}", actor.resource_type, actor.resource_id);
+    ha_events::emit(ha_events::HaEvent::new(event_type, severity, &scope, &reason));
 }
 
 async fn db_named(name: &str, is_dpu: bool) -> anyhow::Result<DbConnector> {
     let container_name = match is_dpu {
-        true => format!("dpu{}", get_dpu_slot_id()),
+        true => slot::current().name(),
         false => "".into(),
     };
-    let db = timeout(
-        Duration::from_secs(5),
-        DbConnector::new_keyed_async(name, false, 11000, &container_name, ""),
-    )
-    .await
-    .map_err(|_| anyhow!("Connecting to db `{name}` timed out"))?
-    .map_err(|e| anyhow!("Connecting to db `{name}`: {e}"))?;
-    Ok(db)
+    db_options::db_options().connect(name, &container_name).await
+}
+
+fn parse_table_source(s: &str) -> Result<(String, String), String> {
+    let (table, db) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <TABLE>=<DB>, got '{s}'"))?;
+    match db {
+        "CONFIG_DB" | "APPL_DB" => Result::Ok((table.to_string(), db.to_string())),
+        _ => Err(format!(
+            "{table} can only be consumed from CONFIG_DB or APPL_DB, not {db}"
+        )),
+    }
+}
+
+fn parse_actor_mailbox(s: &str) -> Result<(String, MailboxConfig), String> {
+    let (actor, mailbox) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <ACTOR>=<SIZE>[:block|:reject], got '{s}'"))?;
+    let (size, overflow) = mailbox.split_once(':').unwrap_or((mailbox, "block"));
+    let size = match size.parse() {
+        Result::Ok(size) if size > 0 => size,
+        _ => return Err(format!("invalid mailbox size of {actor} actors: {size}")),
+    };
+    let overflow: OverflowPolicy = overflow
+        .parse()
+        .map_err(|_| format!("invalid overflow policy of {actor} actors: {overflow}, expected block or reject"))?;
+    if !matches!(overflow, OverflowPolicy::Block | OverflowPolicy::Reject) {
+        // A dropped table update or actor message is never resent, which would leave the actor out of sync
+        return Err(format!(
+            "{actor} actors can't drop messages, their updates are not resent: {overflow}"
+        ));
+    }
+    Result::Ok((actor.to_string(), MailboxConfig { size, overflow }))
+}
+
+fn set_table_source(table: &str, db: &str) {
+    TABLE_SOURCES.lock().unwrap().insert(table.to_string(), db.to_string());
 }
 
+/// Where hamgrd reads and writes a table: the db of its SonicDbTable metadata unless --table-source
+/// overrides it. Keys are stored with the separator of that db, so keys of a table are always split and
+/// built with `source_key_separator`.
+pub trait TableSource: SonicDbTable {
+    fn source_db_name() -> String {
+        TABLE_SOURCES
+            .lock()
+            .unwrap()
+            .get(Self::table_name())
+            .cloned()
+            .unwrap_or_else(|| Self::db_name().to_string())
+    }
+
+    fn source_key_separator() -> char {
+        match TABLE_SOURCES.lock().unwrap().get(Self::table_name()) {
+            Some(db) if db != Self::db_name() => {
+                db_structs::db_key_separator(db).expect("--table-source only accepts dbs of the schema")
+            }
+            _ => Self::key_separator(),
+        }
+    }
+}
+
+impl<T: SonicDbTable> TableSource for T {}
+
 async fn db_for_table<T>() -> anyhow::Result<DbConnector>
 where
     T: SonicDbTable + 'static,
 {
-    let name = T::db_name();
-    db_named(name, T::is_dpu()).await
+    let name = T::source_db_name();
+    db_named(&name, T::is_dpu()).await
 }
 
 // producer bridges are responsible for updating sonic-db optionally sending the update out via zmq
@@ -127,7 +596,11 @@ where
 // all actors in the process.
 async fn spawn_producer_bridges(edge_runtime: Arc<SwbusEdgeRuntime>, dpu: &Dpu) -> Result<Vec<JoinHandle<()>>> {
     let mut handles = Vec::new();
-    let zmq_endpoint = format!("tcp://{}:{}", dpu.midplane_ipv4, dpu.orchagent_zmq_port);
+    let midplane_ip = dpu
+        .midplane_ip()
+        .ok_or_else(|| anyhow!("DPU {} has neither midplane_ipv4 nor midplane_ipv6", dpu.dpu_id))?;
+    // IPv6 addresses are bracketed in the endpoint
+    let zmq_endpoint = format!("tcp://{}", SocketAddr::new(midplane_ip, dpu.orchagent_zmq_port));
 
     // Spawn BFD_SESSION_TABLE zmq producer bridge for DPU actor
     // has service path swss-common-bridge/BFD_SESSION_TABLE.
@@ -158,36 +631,15 @@ async fn start_actor_creators(edge_runtime: &Arc<SwbusEdgeRuntime>) -> Result<Ve
     Ok(bridges)
 }
 
-pub fn get_slot_id(swbus_edge: &Arc<SwbusEdgeRuntime>) -> u32 {
-    let runtime_env = swbus_edge.get_runtime_env();
-    //let raw_ptr = guard.as_any() as *const dyn Any;
-    let inner = runtime_env.as_ref().unwrap().as_ref();
-    let runtime_env = inner.as_any().downcast_ref::<RuntimeData>().unwrap();
-    runtime_env.dpu_id
-}
-
-pub fn get_npu_ipv4(swbus_edge: &Arc<SwbusEdgeRuntime>) -> Option<Ipv4Addr> {
-    let runtime_env = swbus_edge.get_runtime_env();
-    //let raw_ptr = guard.as_any() as *const dyn Any;
-    let inner = runtime_env.as_ref().unwrap().as_ref();
-    let runtime_env = inner.as_any().downcast_ref::<RuntimeData>().unwrap();
-    runtime_env.npu_ipv4
-}
-
-pub fn get_npu_ipv6(swbus_edge: &Arc<SwbusEdgeRuntime>) -> Option<Ipv6Addr> {
-    let runtime_env = swbus_edge.get_runtime_env();
-    //let raw_ptr = guard.as_any() as *const dyn Any;
-    let inner = runtime_env.as_ref().unwrap().as_ref();
-    let runtime_env = inner.as_any().downcast_ref::<RuntimeData>().unwrap();
-    runtime_env.npu_ipv6
-}
-pub struct RuntimeData {
+/// What hamgrd knows about where it runs, attached to the edge runtime so that actors can get it
+/// from their context.
+pub struct HamgrdContext {
     dpu_id: u32,
     npu_ipv4: Option<Ipv4Addr>,
     npu_ipv6: Option<Ipv6Addr>,
 }
 
-impl RuntimeEnv for RuntimeData {
+impl RuntimeEnv for HamgrdContext {
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -197,7 +649,14 @@ impl RuntimeEnv for RuntimeData {
     }
 }
 
-impl RuntimeData {
+impl HamgrdContext {
+    /// The context attached to `edge_runtime` at startup.
+    pub fn of(edge_runtime: &SwbusEdgeRuntime) -> &HamgrdContext {
+        edge_runtime
+            .env::<HamgrdContext>()
+            .expect("hamgrd context is attached to the edge runtime at startup")
+    }
+
     pub fn new(dpu_id: u32, npu_ipv4: Option<Ipv4Addr>, npu_ipv6: Option<Ipv6Addr>) -> Self {
         Self {
             dpu_id,
@@ -225,7 +684,7 @@ where
 {
     let mut new_sp = runtime.get_base_sp();
     new_sp.resource_type = "swss-common-bridge".into();
-    new_sp.resource_id = format!("{}|{}", T::db_name(), T::table_name());
+    new_sp.resource_id = format!("{}|{}", T::source_db_name(), T::table_name());
     new_sp
 }
 
@@ -233,13 +692,159 @@ where
 mod test {
     use super::*;
     use db_structs::*;
+    use sonicdb_derive::SonicDb;
     use swss_common_testing::Redis;
 
     #[tokio::test]
     async fn test_db_for_table() {
         let _ = Redis::start_config_db();
-        set_dpu_slot_id(0);
         crate::db_for_table::<Dpu>().await.unwrap();
         crate::db_for_table::<DashHaScopeTable>().await.unwrap();
     }
+
+    #[derive(SonicDb)]
+    #[sonicdb(table_name = "TEST_SOURCE_TABLE", key_separator = ":", db_name = "APPL_DB")]
+    struct TestSourceTable {}
+
+    #[test]
+    fn test_table_source() {
+        assert_eq!(
+            parse_table_source("TEST_SOURCE_TABLE=CONFIG_DB"),
+            Result::Ok(("TEST_SOURCE_TABLE".to_string(), "CONFIG_DB".to_string()))
+        );
+        assert!(parse_table_source("TEST_SOURCE_TABLE").is_err());
+        assert!(parse_table_source("TEST_SOURCE_TABLE=STATE_DB").is_err());
+
+        assert_eq!(TestSourceTable::source_db_name(), "APPL_DB");
+        assert_eq!(TestSourceTable::source_key_separator(), ':');
+        set_table_source("TEST_SOURCE_TABLE", "CONFIG_DB");
+        assert_eq!(TestSourceTable::source_db_name(), "CONFIG_DB");
+        assert_eq!(TestSourceTable::source_key_separator(), '|');
+    }
+
+    #[test]
+    fn test_actor_mailbox() {
+        assert_eq!(
+            parse_actor_mailbox("dpu=1000:block"),
+            Result::Ok((
+                "dpu".to_string(),
+                MailboxConfig {
+                    size: 1000,
+                    overflow: OverflowPolicy::Block
+                }
+            ))
+        );
+        assert_eq!(
+            parse_actor_mailbox("ha-scope=100").unwrap().1.overflow,
+            OverflowPolicy::Block
+        );
+        assert_eq!(
+            parse_actor_mailbox("ha-scope=100:reject").unwrap().1.overflow,
+            OverflowPolicy::Reject
+        );
+        assert!(parse_actor_mailbox("dpu").is_err());
+        assert!(parse_actor_mailbox("dpu=0").is_err());
+        assert!(parse_actor_mailbox("dpu=1000:drop-all").is_err());
+        assert!(parse_actor_mailbox("dpu=1000:drop-new").is_err());
+        assert!(parse_actor_mailbox("dpu=1000:drop-oldest").is_err());
+    }
+
+    /// A CA, and a certificate for 127.0.0.1 signed by it, written to `dir` in PEM.
+    fn write_certificates(dir: &std::path::Path) -> swbus_config::TlsConfig {
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let params = rcgen::CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let tls = swbus_config::TlsConfig {
+            ca_cert: dir.join("ca.pem"),
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            domain_name: None,
+        };
+        std::fs::write(&tls.ca_cert, ca.pem()).unwrap();
+        std::fs::write(&tls.cert, cert.pem()).unwrap();
+        std::fs::write(&tls.key, key.serialize_pem()).unwrap();
+        tls
+    }
+
+    async fn connected(runtime: &SwbusEdgeRuntime) -> bool {
+        for _ in 0..50 {
+            if runtime.swbusd_connected().await {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn edge_runtime_connects_to_swbusd_with_client_auth() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = write_certificates(dir.path());
+        let token_file = dir.path().join("hamgrd.token");
+        std::fs::write(&token_file, "secret\n").unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let swbus_config: SwbusConfig = serde_yaml::from_str(&format!(
+            r#"
+            endpoint: "127.0.0.1:{port}"
+            routes:
+              - key: "region-a.cluster-a.10.0.1.0-dpu0"
+                scope: "Cluster"
+            peers: []
+            tls:
+              ca_cert: "{}"
+              cert: "{}"
+              key: "{}"
+            client_auth:
+              clients:
+                - token_file: "{}"
+                  service_paths: ["region-a.cluster-a.10.0.1.0-dpu0/hamgrd"]
+            "#,
+            tls.ca_cert.display(),
+            tls.cert.display(),
+            tls.key.display(),
+            token_file.display()
+        ))
+        .unwrap();
+        let service_host = swbus_core::mux::service::SwbusServiceHost::new(&swbus_config.endpoint);
+        tokio::spawn(service_host.start(swbus_config.clone()));
+
+        let mut sp = swbus_config.get_swbusd_service_path().unwrap();
+        sp.service_type = "hamgrd".into();
+        sp.service_id = "0".into();
+
+        // the token doesn't allow other services
+        *SWBUS_AUTH_TOKEN.lock().unwrap() = Some("secret".to_string());
+        let mut other_sp = sp.clone();
+        other_sp.service_type = "swbus-cli".into();
+        let other = swbus_edge_builder(&swbus_config, other_sp)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        // hamgrd connects over TLS, with the certificates of the swbusd config, and the token
+        let runtime = Arc::new(swbus_edge_builder(&swbus_config, sp).unwrap().build().await.unwrap());
+        *SWBUS_AUTH_TOKEN.lock().unwrap() = None;
+        assert!(connected(&runtime).await);
+        assert!(!other.swbusd_connected().await);
+
+        let mut client = mgmt_client::ManagementClient::new(runtime.clone(), "test");
+        let request = client.new_request(
+            runtime.get_base_sp().to_swbusd_service_path(),
+            swbus_edge::swbus_proto::swbus::ManagementRequestType::SwbusdGetRoutes,
+            &[],
+        );
+        let response = client.request(request, std::time::Duration::from_secs(5)).await;
+        assert!(response.is_some());
+    }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_edge::swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_edge::swbus_proto::swbus::{
+    swbus_message::Body, ManagementRequest, ManagementRequestArg, ManagementRequestType, RequestResponse, ServicePath,
+    SwbusMessage, SwbusMessageHeader,
+};
+use swbus_edge::SwbusEdgeRuntime;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::time::{timeout, Instant};
+use tracing::warn;
+
+/// Client for sending management requests to actors, swbusd or other hamgrd services, and collecting
+/// their responses. Requests to actors in this process are delivered by the edge runtime directly, so
+/// they work even if swbusd is not reachable.
+pub struct ManagementClient {
+    sp: ServicePath,
+    rt: Arc<SwbusEdgeRuntime>,
+    handler_rx: Receiver<SwbusMessage>,
+    id_generator: MessageIdGenerator,
+}
+
+impl ManagementClient {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, resource_type: &str) -> Self {
+        let sp = rt.new_sp(resource_type, "0");
+        let (handler_tx, handler_rx) = channel::<SwbusMessage>(100);
+        rt.add_handler(sp.clone(), handler_tx);
+
+        Self {
+            sp,
+            rt,
+            handler_rx,
+            id_generator: MessageIdGenerator::new(),
+        }
+    }
+
+    pub fn new_request(
+        &self,
+        destination: ServicePath,
+        request: ManagementRequestType,
+        args: &[(&str, &str)],
+    ) -> SwbusMessage {
+        let mut mgmt_request = ManagementRequest::new(request);
+        mgmt_request.arguments = args
+            .iter()
+            .map(|(name, value)| ManagementRequestArg {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect();
+        let header = SwbusMessageHeader::new(self.sp.clone(), destination, self.id_generator.generate());
+        SwbusMessage::new(header, Body::ManagementRequest(mgmt_request))
+    }
+
+    /// Send a single request and wait for its response.
+    pub async fn request(&mut self, request: SwbusMessage, wait: Duration) -> Option<RequestResponse> {
+        self.query(vec![(String::new(), request)], wait).await.remove("")
+    }
+
+    /// Send `requests` and wait for their responses, keyed by the name each request is paired with.
+    /// Responses that don't arrive within `wait` are left out of the result.
+    pub async fn query(
+        &mut self,
+        requests: Vec<(String, SwbusMessage)>,
+        wait: Duration,
+    ) -> HashMap<String, RequestResponse> {
+        let mut pending = HashMap::new();
+        for (name, msg) in requests {
+            let id = msg.header.as_ref().unwrap().id;
+            if self.rt.send(msg).await.is_err() {
+                warn!("Failed to send management request to {name}");
+                continue;
+            }
+            pending.insert(id, name);
+        }
+
+        let mut responses = HashMap::new();
+        let deadline = Instant::now() + wait;
+        while !pending.is_empty() {
+            let msg = match timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                self.handler_rx.recv(),
+            )
+            .await
+            {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(_) => {
+                    warn!("Timed out waiting for response from {:?}", pending.values());
+                    break;
+                }
+            };
+            if let Some(Body::Response(response)) = msg.body {
+                if let Some(name) = pending.remove(&response.request_id) {
+                    responses.insert(name, response);
+                }
+            }
+        }
+        responses
+    }
+}
@@ -0,0 +1,332 @@
+//! Orchagent consumption lag
+//!
+//! Writes to the ZMQ-programmed DPU tables are fire and forget, so a stuck orchagent would let the
+//! producer bridges queue updates without bound. Orchagent acknowledges every operation it applied in
+//! the table of the same name in DPU_APPL_STATE_DB: a set leaves an entry with the field values it
+//! applied, a delete removes the entry. Each write is pending until its ack shows up. Once too many
+//! writes are pending, or one is pending for too long, orchagent is considered stuck: an HA event is
+//! raised and writes are rejected, so the bridge backs off through its retry policy and the actors
+//! resend their latest state later, until the acks catch up.
+use crate::ha_events::{self, HaEvent, HaEventSeverity, HaEventType};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use swss_common::{FieldValues, KeyOpFieldValues, KeyOperation, Table};
+use swss_common_bridge::producer::ProducerTable;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+pub const ACK_DB: &str = "DPU_APPL_STATE_DB";
+
+static LAGS: Mutex<BTreeMap<&'static str, TableLag>> = Mutex::new(BTreeMap::new());
+
+/// The lag of a ZMQ-programmed table, as last seen by its producer bridge.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TableLag {
+    pub produced: u64,
+    pub consumed: u64,
+    pub pending: usize,
+    pub oldest_pending_ms: u64,
+    pub stuck: bool,
+}
+
+/// The lag of every monitored table, by table name.
+pub fn lags() -> BTreeMap<&'static str, TableLag> {
+    LAGS.lock().unwrap().clone()
+}
+
+#[derive(Clone, Debug)]
+pub struct LagLimits {
+    /// Writes pending at most
+    pub max_pending: usize,
+    /// Time a write is pending at most
+    pub stall_timeout: Duration,
+    /// Acks are polled at most this often, except while orchagent is stuck
+    pub ack_poll_interval: Duration,
+}
+
+impl Default for LagLimits {
+    fn default() -> Self {
+        LagLimits {
+            max_pending: 256,
+            stall_timeout: Duration::from_secs(10),
+            ack_poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Where orchagent acknowledges the writes to a table.
+pub trait AckSource: Send + 'static {
+    /// The field values orchagent applied to `key`, or None if there is no entry.
+    fn ack(&mut self, key: &str) -> impl Future<Output = Result<Option<FieldValues>>> + Send;
+}
+
+/// The acks in DPU_APPL_STATE_DB.
+pub struct ApplStateAcks {
+    table: Table,
+}
+
+impl ApplStateAcks {
+    pub async fn new(table_name: &str) -> Result<Self> {
+        let db = crate::db_named(ACK_DB, true).await?;
+        let table = Table::new_async(db, table_name).await?;
+        Ok(ApplStateAcks { table })
+    }
+}
+
+impl AckSource for ApplStateAcks {
+    async fn ack(&mut self, key: &str) -> Result<Option<FieldValues>> {
+        Ok(self.table.get_async(key).await?)
+    }
+}
+
+struct Pending {
+    /// The field values of a set, None for a delete
+    expected: Option<FieldValues>,
+    /// When the first unacknowledged write to the key was produced
+    since: Instant,
+}
+
+impl Pending {
+    fn acked_by(&self, ack: Option<&FieldValues>) -> bool {
+        match (&self.expected, ack) {
+            (None, ack) => ack.is_none(),
+            (Some(_), None) => false,
+            (Some(expected), Some(ack)) => expected.iter().all(|(field, value)| ack.get(field) == Some(value)),
+        }
+    }
+}
+
+/// A producer table that tracks the writes orchagent has not acknowledged yet, and rejects writes
+/// while orchagent is stuck.
+pub struct LagMonitoredTable<T, A> {
+    table: T,
+    table_name: &'static str,
+    acks: A,
+    limits: LagLimits,
+    pending: HashMap<String, Pending>,
+    last_poll: Option<Instant>,
+    lag: TableLag,
+}
+
+impl<T: ProducerTable, A: AckSource> LagMonitoredTable<T, A> {
+    pub fn new(table: T, table_name: &'static str, acks: A, limits: LagLimits) -> Self {
+        LagMonitoredTable {
+            table,
+            table_name,
+            acks,
+            limits,
+            pending: HashMap::new(),
+            last_poll: None,
+            lag: TableLag::default(),
+        }
+    }
+
+    /// Drop the pending writes orchagent has acknowledged since the last poll.
+    async fn poll_acks(&mut self) {
+        if !self.lag.stuck
+            && self
+                .last_poll
+                .is_some_and(|last| last.elapsed() < self.limits.ack_poll_interval)
+        {
+            return;
+        }
+        self.last_poll = Some(Instant::now());
+
+        let keys: Vec<String> = self.pending.keys().cloned().collect();
+        for key in keys {
+            let ack = match self.acks.ack(&key).await {
+                Ok(ack) => ack,
+                Err(e) => {
+                    warn!("Failed to read the ack of {}:{key}: {e:#}", self.table_name);
+                    return;
+                }
+            };
+            if self.pending[&key].acked_by(ack.as_ref()) {
+                self.pending.remove(&key);
+                self.lag.consumed += 1;
+            }
+        }
+    }
+
+    /// Poll the acks and update whether orchagent is stuck. Returns the reason if it is.
+    async fn check_lag(&mut self) -> Option<String> {
+        self.poll_acks().await;
+
+        let oldest = self
+            .pending
+            .values()
+            .map(|pending| pending.since.elapsed())
+            .max()
+            .unwrap_or_default();
+        let reason = if self.pending.len() >= self.limits.max_pending {
+            Some(format!(
+                "{} writes not consumed by orchagent, oldest for {oldest:?}",
+                self.pending.len()
+            ))
+        } else if oldest >= self.limits.stall_timeout {
+            Some(format!(
+                "a write not consumed by orchagent for {oldest:?}, {} pending",
+                self.pending.len()
+            ))
+        } else {
+            None
+        };
+
+        match (&reason, self.lag.stuck) {
+            (Some(reason), false) => {
+                ha_events::emit(HaEvent::new(
+                    HaEventType::HaOrchagentStuck,
+                    HaEventSeverity::Major,
+                    self.table_name,
+                    reason,
+                ));
+            }
+            (None, true) => {
+                info!("Orchagent consumes {} again", self.table_name);
+                ha_events::emit(HaEvent::new(
+                    HaEventType::HaOrchagentStuck,
+                    HaEventSeverity::Info,
+                    self.table_name,
+                    "orchagent consumes writes again",
+                ));
+            }
+            _ => {}
+        }
+        self.lag.stuck = reason.is_some();
+        self.publish();
+        reason
+    }
+
+    /// Record a write as pending. A key written again while pending stays pending since its first
+    /// write, so that rewriting it does not hide the lag.
+    fn produced(&mut self, key: &str, expected: Option<FieldValues>) {
+        self.lag.produced += 1;
+        self.pending
+            .entry(key.to_string())
+            .and_modify(|pending| pending.expected = expected.clone())
+            .or_insert(Pending {
+                expected,
+                since: Instant::now(),
+            });
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        self.lag.pending = self.pending.len();
+        self.lag.oldest_pending_ms = self
+            .pending
+            .values()
+            .map(|pending| pending.since.elapsed().as_millis() as u64)
+            .max()
+            .unwrap_or_default();
+        LAGS.lock().unwrap().insert(self.table_name, self.lag.clone());
+    }
+}
+
+impl<T: ProducerTable, A: AckSource> ProducerTable for LagMonitoredTable<T, A> {
+    async fn set(&mut self, key: &str, fvs: FieldValues) -> swbus_actor::Result<()> {
+        if let Some(reason) = self.check_lag().await {
+            return Err(swbus_actor::Error::msg(format!("orchagent is stuck: {reason}")));
+        }
+        self.table.set(key, fvs.clone()).await?;
+        self.produced(key, Some(fvs));
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> swbus_actor::Result<()> {
+        if let Some(reason) = self.check_lag().await {
+            return Err(swbus_actor::Error::msg(format!("orchagent is stuck: {reason}")));
+        }
+        self.table.del(key).await?;
+        self.produced(key, None);
+        Ok(())
+    }
+
+    async fn apply_batch(&mut self, kfvs: Vec<KeyOpFieldValues>) -> swbus_actor::Result<()> {
+        if let Some(reason) = self.check_lag().await {
+            return Err(swbus_actor::Error::msg(format!("orchagent is stuck: {reason}")));
+        }
+        self.table.apply_batch(kfvs.clone()).await?;
+        for kfv in kfvs {
+            let expected = matches!(kfv.operation, KeyOperation::Set).then_some(kfv.field_values);
+            self.produced(&kfv.key, expected);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::CxxString;
+
+    /// Acks whatever the test puts in it.
+    #[derive(Clone, Default)]
+    struct FakeAcks(std::sync::Arc<Mutex<HashMap<String, FieldValues>>>);
+
+    impl AckSource for FakeAcks {
+        async fn ack(&mut self, key: &str) -> Result<Option<FieldValues>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+    }
+
+    struct NullTable;
+
+    impl ProducerTable for NullTable {
+        async fn set(&mut self, _key: &str, _fvs: FieldValues) -> swbus_actor::Result<()> {
+            Ok(())
+        }
+
+        async fn del(&mut self, _key: &str) -> swbus_actor::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fvs(version: &str) -> FieldValues {
+        FieldValues::from([("version".to_string(), CxxString::new(version))])
+    }
+
+    #[tokio::test]
+    async fn backs_off_while_orchagent_is_stuck() {
+        let acks = FakeAcks::default();
+        let limits = LagLimits {
+            max_pending: 3,
+            stall_timeout: Duration::from_millis(200),
+            ack_poll_interval: Duration::from_millis(50),
+        };
+        let mut table = LagMonitoredTable::new(NullTable, "LAG_TEST_TABLE", acks.clone(), limits);
+
+        // Acked writes are not pending
+        table.set("a", fvs("1")).await.unwrap();
+        let mut ack = fvs("1");
+        ack.insert("err_str".to_string(), CxxString::new("SWSS_RC_SUCCESS"));
+        acks.0.lock().unwrap().insert("a".to_string(), ack);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        table.set("b", fvs("1")).await.unwrap();
+        assert_eq!(table.pending.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        // Too many writes pending, rewriting a key does not count twice
+        table.set("b", fvs("2")).await.unwrap();
+        table.set("c", fvs("1")).await.unwrap();
+        table.del("a").await.unwrap();
+        assert!(table.set("d", fvs("1")).await.is_err());
+        assert!(lags()["LAG_TEST_TABLE"].stuck);
+
+        // Orchagent catches up with all but the first write to b, which was superseded
+        acks.0.lock().unwrap().remove("a");
+        acks.0.lock().unwrap().insert("b".to_string(), fvs("2"));
+        acks.0.lock().unwrap().insert("c".to_string(), fvs("1"));
+        table.set("d", fvs("1")).await.unwrap();
+        let lag = &lags()["LAG_TEST_TABLE"];
+        assert!(!lag.stuck);
+        assert_eq!((lag.produced, lag.consumed, lag.pending), (6, 4, 1));
+
+        // A write that is never acked stalls the table
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(table.del("b").await.is_err());
+    }
+}
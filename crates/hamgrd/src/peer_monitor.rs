@@ -0,0 +1,305 @@
+//! Peer liveness
+//!
+//! BFD tells whether the data plane of the peer DPU is up, not whether the hamgrd managing it is. The
+//! peer monitor at `<hamgrd>/peer-monitor/0` pings the hamgrd of the peer of every HA set actor that
+//! registers with [`PeerMonitorRegistration`], every [`PeerMonitorPolicy::interval`]. A ping that is
+//! not answered by the time of the next one, or answered with an error, is a failure. Once
+//! [`PeerMonitorPolicy::failure_threshold`] pings in a row failed, the peer hamgrd is unreachable until
+//! it answers again. The HA set actor is sent [`PeerHamgrdState`] on every change, and passes it on to
+//! its HA scope actors, which don't start or keep a planned switchover with an unreachable peer.
+use crate::audit::{self, AuditEntry, AuditEvent};
+use crate::ha_actor_messages::{HaSetPeer, PeerHamgrdState, PeerMonitorRegistration};
+use crate::ha_events::{self, HaEvent, HaEventSeverity, HaEventType};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_actor::ActorMessage;
+use swbus_edge::swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_edge::swbus_proto::swbus::{
+    swbus_message::Body, DataRequest, PingRequest, ServicePath, SwbusErrorCode, SwbusMessage, SwbusMessageHeader,
+};
+use swbus_edge::SwbusEdgeRuntime;
+use tokio::sync::mpsc::{channel, Receiver};
+use tracing::{debug, error, info, warn};
+
+/// The resource type of the peer monitor.
+pub const NAME: &str = "peer-monitor";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerMonitorPolicy {
+    /// How often the peers are pinged, and how long a ping has to be answered
+    pub interval: Duration,
+    /// Pings failed in a row before a peer is unreachable
+    pub failure_threshold: u32,
+}
+
+impl Default for PeerMonitorPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            failure_threshold: 3,
+        }
+    }
+}
+
+/// The liveness of the hamgrd of one peer.
+struct Peer {
+    peer: HaSetPeer,
+    hamgrd_sp: ServicePath,
+    // The last ping sent and whether it was answered
+    ping: Option<(u64, bool)>,
+    failures: u32,
+    reachable: bool,
+}
+
+impl Peer {
+    fn new(peer: HaSetPeer, hamgrd_sp: ServicePath) -> Self {
+        Self {
+            peer,
+            hamgrd_sp,
+            ping: None,
+            failures: 0,
+            // until proven otherwise
+            reachable: true,
+        }
+    }
+
+    /// Count the last ping, before the next one is sent. Returns whether the peer is reachable if that
+    /// changed.
+    fn count_last_ping(&mut self, failure_threshold: u32) -> Option<bool> {
+        match self.ping.take() {
+            Some((_, true)) => self.failures = 0,
+            Some((_, false)) => self.failures += 1,
+            None => return None,
+        }
+        let reachable = self.failures < failure_threshold;
+        if reachable == self.reachable {
+            return None;
+        }
+        self.reachable = reachable;
+        Some(reachable)
+    }
+
+    fn ping_answered(&mut self, request_id: u64, error_code: SwbusErrorCode) {
+        if let Some((id, answered)) = self.ping.as_mut() {
+            if *id == request_id {
+                *answered = error_code == SwbusErrorCode::Ok;
+            }
+        }
+    }
+}
+
+pub struct PeerMonitor {
+    sp: ServicePath,
+    rt: Arc<SwbusEdgeRuntime>,
+    handler_rx: Receiver<SwbusMessage>,
+    id_generator: MessageIdGenerator,
+    policy: PeerMonitorPolicy,
+    // Peers by the HA set actor they are monitored for
+    peers: HashMap<ServicePath, Peer>,
+}
+
+impl PeerMonitor {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, policy: PeerMonitorPolicy) -> Self {
+        let sp = rt.new_sp(NAME, "0");
+        let (handler_tx, handler_rx) = channel::<SwbusMessage>(100);
+        rt.add_handler(sp.clone(), handler_tx);
+
+        Self {
+            sp,
+            rt,
+            handler_rx,
+            id_generator: MessageIdGenerator::new(),
+            policy,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(self.policy.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut timers = crate::ha_timers::subscribe();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.ping_peers().await,
+                Ok(()) = timers.changed() => {
+                    let policy = timers.borrow_and_update().peer_monitor;
+                    if policy != self.policy {
+                        info!("Peer monitor policy changed to {policy:?}");
+                        self.policy = policy;
+                        interval = tokio::time::interval(policy.interval);
+                        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                    }
+                }
+                msg = self.handler_rx.recv() => match msg {
+                    Some(msg) => self.handle_message(msg).await,
+                    None => break,
+                },
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, msg: SwbusMessage) {
+        let Some(header) = msg.header.as_ref() else {
+            return;
+        };
+        let source = header.source.clone().unwrap_or_default();
+        match msg.body {
+            Some(Body::Response(ref response)) => {
+                for peer in self.peers.values_mut() {
+                    peer.ping_answered(response.request_id, response.error_code());
+                }
+            }
+            Some(Body::DataRequest(DataRequest { ref payload })) => {
+                let result = self.handle_registration(source, payload).await;
+                let (code, error_message) = match result {
+                    Ok(()) => (SwbusErrorCode::Ok, String::new()),
+                    Err(ref e) => {
+                        error!("Failed to handle peer monitor registration: {e:#}");
+                        (SwbusErrorCode::Fail, format!("{e:#}"))
+                    }
+                };
+                let response = SwbusMessage::new_response(
+                    &msg,
+                    Some(&self.sp),
+                    code,
+                    &error_message,
+                    self.id_generator.generate(),
+                    None,
+                );
+                if self.rt.send(response).await.is_err() {
+                    error!("Failed to send peer monitor response to swbus");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Start or stop monitoring the peer of the HA set actor at `source`. A new peer is reachable until
+    /// its pings fail, and the actor is told so.
+    async fn handle_registration(&mut self, source: ServicePath, payload: &[u8]) -> Result<()> {
+        let msg = ActorMessage::deserialize(payload)?;
+        if !PeerMonitorRegistration::is_my_msg(&msg.key) {
+            debug!("Ignoring {} from {}", msg.key, source.to_longest_path());
+            return Ok(());
+        }
+        let PeerMonitorRegistration { peer } = msg.deserialize_data()?;
+        let Some(peer) = peer else {
+            if self.peers.remove(&source).is_some() {
+                info!("Stopped monitoring the peer hamgrd of {}", source.to_longest_path());
+            }
+            return Ok(());
+        };
+
+        let same_peer = self.peers.get(&source).is_some_and(|monitored| monitored.peer == peer);
+        if !same_peer {
+            let hamgrd_sp = peer.hamgrd_sp(&self.sp);
+            info!(
+                "Monitoring the peer hamgrd {} of {}",
+                hamgrd_sp.to_longest_path(),
+                source.to_longest_path()
+            );
+            self.peers.insert(source.clone(), Peer::new(peer, hamgrd_sp));
+        }
+        let peer = &self.peers[&source];
+        let state = PeerHamgrdState::new_actor_msg(peer.reachable, peer.failures)?;
+        self.send_request(source, state.serialize()).await;
+        Ok(())
+    }
+
+    async fn ping_peers(&mut self) {
+        let mut changes = Vec::new();
+        for (ha_set, peer) in self.peers.iter_mut() {
+            if let Some(reachable) = peer.count_last_ping(self.policy.failure_threshold) {
+                changes.push((ha_set.clone(), reachable, peer.failures, peer.hamgrd_sp.clone()));
+            }
+            let id = self.id_generator.generate();
+            peer.ping = Some((id, false));
+            let header = SwbusMessageHeader::new(self.sp.clone(), peer.hamgrd_sp.clone(), id);
+            let ping = SwbusMessage::new(header, Body::PingRequest(PingRequest::new()));
+            if self.rt.send(ping).await.is_err() {
+                error!("Failed to send ping to {}", peer.hamgrd_sp.to_longest_path());
+            }
+        }
+
+        for (ha_set, reachable, failures, hamgrd_sp) in changes {
+            let (event_type, severity, reason) = if reachable {
+                (
+                    HaEventType::HaPeerReachable,
+                    HaEventSeverity::Info,
+                    format!("peer hamgrd {} answers pings again", hamgrd_sp.to_longest_path()),
+                )
+            } else {
+                (
+                    HaEventType::HaPeerUnreachable,
+                    HaEventSeverity::Major,
+                    format!(
+                        "peer hamgrd {} didn't answer {failures} pings in a row",
+                        hamgrd_sp.to_longest_path()
+                    ),
+                )
+            };
+            ha_events::emit(HaEvent::new(event_type, severity, &ha_set.resource_id, &reason));
+            let (old_state, new_state) = match reachable {
+                true => ("unreachable", "reachable"),
+                false => ("reachable", "unreachable"),
+            };
+            audit::record(AuditEntry::new(
+                AuditEvent::PeerReachability,
+                &format!("{NAME}/0"),
+                &ha_set.resource_id,
+                old_state,
+                new_state,
+                &reason,
+            ));
+            match PeerHamgrdState::new_actor_msg(reachable, failures) {
+                Ok(state) => self.send_request(ha_set, state.serialize()).await,
+                Err(e) => warn!("Failed to create peer hamgrd state: {e:#}"),
+            }
+        }
+    }
+
+    async fn send_request(&self, destination: ServicePath, payload: Vec<u8>) {
+        let header = SwbusMessageHeader::new(self.sp.clone(), destination, self.id_generator.generate());
+        let msg = SwbusMessage::new(header, Body::DataRequest(DataRequest::new(payload)));
+        if self.rt.send(msg).await.is_err() {
+            error!("Failed to send peer hamgrd state to swbus");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn peer_is_unreachable_after_consecutive_failures() {
+        let hamgrd_sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0").unwrap();
+        let peer = HaSetPeer {
+            vdpu_id: "vdpu1-0".to_string(),
+            npu_ip: "10.0.1.0".to_string(),
+            dpu_id: 0,
+        };
+        let mut peer = Peer::new(peer, hamgrd_sp);
+        // Nothing to count before the first ping
+        assert_eq!(peer.count_last_ping(3), None);
+
+        let mut ping = |id: u64, answer: Option<SwbusErrorCode>| {
+            peer.ping = Some((id, false));
+            if let Some(error_code) = answer {
+                peer.ping_answered(id, error_code);
+            }
+            // a late answer to an older ping doesn't count
+            peer.ping_answered(id - 1, SwbusErrorCode::Ok);
+            peer.count_last_ping(3)
+        };
+        assert_eq!(ping(1, Some(SwbusErrorCode::Ok)), None);
+        assert_eq!(ping(2, None), None);
+        assert_eq!(ping(3, Some(SwbusErrorCode::NoRoute)), None);
+        assert_eq!(ping(4, None), Some(false));
+        assert_eq!(ping(5, None), None);
+        assert_eq!(ping(6, Some(SwbusErrorCode::Ok)), Some(true));
+        assert_eq!(ping(7, Some(SwbusErrorCode::Ok)), None);
+    }
+}
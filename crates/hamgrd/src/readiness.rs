@@ -0,0 +1,268 @@
+//! Startup readiness
+//!
+//! hamgrd is started together with the databases and swbusd, so the things it depends on may not be
+//! there yet when it comes up. Each dependency is probed until it is ready, backing off between
+//! attempts up to a bound, instead of failing the whole process on the first try. Once the database
+//! config is loaded, what hamgrd is waiting for is also recorded in
+//! STATE_DB/HAMGRD_STARTUP_STATUS_TABLE, so a hamgrd held up by e.g. a missing DPU entry shows why.
+use crate::db_structs::{now_in_millis, HamgrdStartupStatusTable};
+use crate::tables::DbTable;
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use swbus_config::{swbus_config_from_db, SwbusConfig};
+use swss_common::sonic_db_config_initialize_global;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Instant};
+use tracing::{error, info, warn};
+
+pub const DATABASE_GLOBAL_CONFIG: &str = "/var/run/redis/sonic-db/database_global.json";
+
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Call `probe` until it succeeds, waiting `backoff.initial` after the first failure and doubling
+/// the wait after every failure, up to `backoff.max`.
+pub async fn wait_until_ready<T, F, Fut>(what: &str, backoff: &Backoff, probe: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    wait_until_ready_recording(what, backoff, &mut StartupStatus::disabled(), probe).await
+}
+
+/// [`wait_until_ready`], recording every failed attempt in `status`.
+pub async fn wait_until_ready_recording<T, F, Fut>(
+    what: &str,
+    backoff: &Backoff,
+    status: &mut StartupStatus,
+    mut probe: F,
+) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let mut delay = backoff.initial;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match probe().await {
+            Ok(value) => {
+                if attempts > 1 {
+                    info!("{what} is ready after {attempts} attempts in {:?}", start.elapsed());
+                }
+                return value;
+            }
+            Err(e) => {
+                warn!("Waiting for {what} (attempt {attempts}), retrying in {delay:?}: {e:#}");
+                status.waiting(what, attempts, &e).await;
+            }
+        }
+        sleep(delay).await;
+        delay = (delay * 2).min(backoff.max);
+    }
+}
+
+/// The startup status of hamgrd in STATE_DB/HAMGRD_STARTUP_STATUS_TABLE. Recording is best effort:
+/// STATE_DB may not be up yet either.
+pub struct StartupStatus {
+    // None if nothing is recorded
+    key: Option<String>,
+    table: Option<DbTable>,
+}
+
+impl StartupStatus {
+    /// Status of the hamgrd of DPU `slot_id`.
+    pub fn new(slot_id: u32) -> Self {
+        StartupStatus {
+            key: Some(format!("dpu{slot_id}")),
+            table: None,
+        }
+    }
+
+    fn disabled() -> Self {
+        StartupStatus { key: None, table: None }
+    }
+
+    async fn waiting(&mut self, what: &str, attempts: u32, error: &anyhow::Error) {
+        self.record(HamgrdStartupStatusTable {
+            state: "waiting".to_string(),
+            waiting_for: what.to_string(),
+            attempts,
+            error: format!("{error:#}"),
+            last_update_time_in_ms: now_in_millis(),
+        })
+        .await
+    }
+
+    /// Record that hamgrd is no longer waiting for anything.
+    pub async fn ready(&mut self) {
+        self.record(HamgrdStartupStatusTable {
+            state: "ready".to_string(),
+            last_update_time_in_ms: now_in_millis(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn record(&mut self, entry: HamgrdStartupStatusTable) {
+        let Some(key) = &self.key else {
+            return;
+        };
+        if self.table.is_none() {
+            match Self::open_table().await {
+                Ok(table) => self.table = Some(table),
+                Err(e) => {
+                    warn!("Failed to record startup status: {e:#}");
+                    return;
+                }
+            }
+        }
+        let fvs = swss_serde::to_field_values(&entry).unwrap();
+        if let Err(e) = self.table.as_mut().unwrap().set_async(key, fvs).await {
+            error!("Failed to record startup status: {e:#}");
+            // Reconnect on the next attempt
+            self.table = None;
+        }
+    }
+
+    async fn open_table() -> Result<DbTable> {
+        crate::tables::open_table::<HamgrdStartupStatusTable>().await
+    }
+}
+
+/// Wait for the database config at `path` and load it.
+pub async fn wait_for_database_config(path: &str, backoff: &Backoff) {
+    wait_until_ready(path, backoff, || async {
+        if !Path::new(path).exists() {
+            return Err(anyhow!("{path} does not exist"));
+        }
+        sonic_db_config_initialize_global(path)?;
+        Ok(())
+    })
+    .await
+}
+
+/// Wait for the DPU table entry of `slot_id` and the swbusd config derived from it.
+pub async fn wait_for_dpu_config(
+    slot_id: u32,
+    backoff: &Backoff,
+    status: &mut StartupStatus,
+) -> (crate::db_structs::Dpu, SwbusConfig) {
+    let what = format!("CONFIG_DB/DPU entry of slot {slot_id}");
+    wait_until_ready_recording(&what, backoff, status, || async {
+        let dpu = crate::db_structs::DPU_CONFIG_CACHE.get(slot_id)?;
+        #[cfg(feature = "dev-sim")]
+        if let Some(path) = crate::sim::swbus_config_path() {
+            return Ok((dpu, swbus_config::swbus_config_from_yaml(path)?));
+        }
+        let swbus_config = swbus_config_from_db(slot_id)?;
+        Ok((dpu, swbus_config))
+    })
+    .await
+}
+
+/// Wait for swbusd to accept connections at `endpoint`.
+pub async fn wait_for_swbusd(endpoint: SocketAddr, backoff: &Backoff, status: &mut StartupStatus) {
+    wait_until_ready_recording(&format!("swbusd at {endpoint}"), backoff, status, || async {
+        timeout(Duration::from_secs(5), TcpStream::connect(endpoint))
+            .await
+            .map_err(|_| anyhow!("connecting timed out"))??;
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::{DbConnector, SonicDbTable, Table};
+    use swss_common_testing::Redis;
+    use tokio::net::TcpListener;
+
+    fn fast_backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_until_ready_retries_until_probe_succeeds() {
+        let mut attempts = 0;
+        let value = wait_until_ready("test", &fast_backoff(), || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    3 => Ok(attempt),
+                    _ => Err(anyhow!("not ready")),
+                }
+            }
+        })
+        .await;
+        assert_eq!(value, 3);
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn wait_for_swbusd_waits_for_listener() {
+        // Find a free port, then only listen on it after the wait has started
+        let endpoint = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let wait =
+            tokio::spawn(
+                async move { wait_for_swbusd(endpoint, &fast_backoff(), &mut StartupStatus::disabled()).await },
+            );
+        sleep(Duration::from_millis(20)).await;
+        assert!(!wait.is_finished());
+
+        let _listener = TcpListener::bind(endpoint).await.unwrap();
+        timeout(Duration::from_secs(5), wait).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn startup_status_records_what_is_waited_for() {
+        let _redis = Redis::start_config_db();
+        let mut status = StartupStatus::new(3);
+        let mut attempts = 0;
+        wait_until_ready_recording("CONFIG_DB/DPU entry of slot 3", &fast_backoff(), &mut status, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    3 => Ok(()),
+                    _ => Err(anyhow!("DPU entry of slot 3 not found")),
+                }
+            }
+        })
+        .await;
+
+        let db = DbConnector::new_named("STATE_DB", false, 0).unwrap();
+        let table = Table::new(db, HamgrdStartupStatusTable::table_name()).unwrap();
+        let entry: HamgrdStartupStatusTable = swss_serde::from_table(&table, "dpu3").unwrap();
+        assert_eq!(entry.state, "waiting");
+        assert_eq!(entry.waiting_for, "CONFIG_DB/DPU entry of slot 3");
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(entry.error, "DPU entry of slot 3 not found");
+
+        status.ready().await;
+        let entry: HamgrdStartupStatusTable = swss_serde::from_table(&table, "dpu3").unwrap();
+        assert_eq!(entry.state, "ready");
+        assert_eq!(entry.waiting_for, "");
+    }
+}
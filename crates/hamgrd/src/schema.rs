@@ -0,0 +1,102 @@
+//! JSON Schema of the sonic-db tables used by hamgrd
+//!
+//! `hamgrd --dump-schema <dir>` writes one `<db_name>.<table_name>.json` file per table in db_structs,
+//! generated from the same structs hamgrd parses, so external config validators can't drift from it.
+//! Besides the field definitions, each schema carries the table location in `x-sonic-db`.
+use crate::db_structs::*;
+use anyhow::{Context, Result};
+use schemars::{schema_for, JsonSchema};
+use serde_json::{json, Value};
+use std::path::Path;
+use swss_common::SonicDbTable;
+
+fn table_schema<T>() -> Value
+where
+    T: SonicDbTable + JsonSchema,
+{
+    let mut schema = schema_for!(T);
+    schema.schema.extensions.insert(
+        "x-sonic-db".to_string(),
+        json!({
+            "db_name": T::db_name(),
+            "table_name": T::table_name(),
+            "key_separator": T::key_separator().to_string(),
+            "is_dpu": T::is_dpu(),
+        }),
+    );
+    serde_json::to_value(schema).expect("schema is serializable")
+}
+
+/// Schemas of all tables, keyed by `<db_name>.<table_name>`.
+pub fn all_table_schemas() -> Vec<(String, Value)> {
+    fn add<T: SonicDbTable + JsonSchema>(schemas: &mut Vec<(String, Value)>) {
+        schemas.push((format!("{}.{}", T::db_name(), T::table_name()), table_schema::<T>()));
+    }
+
+    let mut schemas = Vec::new();
+    add::<Dpu>(&mut schemas);
+    add::<RemoteDpu>(&mut schemas);
+    add::<VDpu>(&mut schemas);
+    add::<DashHaGlobalConfig>(&mut schemas);
+    add::<DashHaSetConfigTable>(&mut schemas);
+    add::<DashHaScopeConfigTable>(&mut schemas);
+    add::<DashEniPlacementTable>(&mut schemas);
+    add::<VnetRouteTunnelTable>(&mut schemas);
+    add::<NpuDashHaScopeState>(&mut schemas);
+    add::<DashHaDpuStateTable>(&mut schemas);
+    add::<DashHaScopeSummaryTable>(&mut schemas);
+    add::<DashHaAlarmTable>(&mut schemas);
+    add::<DashHaConfigValidationTable>(&mut schemas);
+    add::<DashHaBridgeDeadLetterTable>(&mut schemas);
+    add::<DashHaAuditTable>(&mut schemas);
+    add::<HamgrdActorSnapshotTable>(&mut schemas);
+    add::<HamgrdGenerationTable>(&mut schemas);
+    add::<HamgrdStartupStatusTable>(&mut schemas);
+    add::<HamgrdExitTable>(&mut schemas);
+    add::<DpuState>(&mut schemas);
+    add::<ChassisModuleTable>(&mut schemas);
+    add::<PcieDetachInfo>(&mut schemas);
+    add::<DashHaSetTable>(&mut schemas);
+    add::<DashHaScopeTable>(&mut schemas);
+    add::<BfdSessionTable>(&mut schemas);
+    add::<DpuDashHaScopeState>(&mut schemas);
+    add::<DpuDashHaFlowSyncState>(&mut schemas);
+    add::<DashBfdProbeState>(&mut schemas);
+    schemas
+}
+
+pub fn dump_schemas(dir: &str) -> Result<()> {
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir).context(format!("creating {}", dir.display()))?;
+    for (name, schema) in all_table_schemas() {
+        let path = dir.join(format!("{name}.json"));
+        std::fs::write(&path, serde_json::to_string_pretty(&schema)?).context(format!("writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table_schema() {
+        let schema = table_schema::<VDpu>();
+        assert_eq!(schema["title"], "VDpu");
+        // comma separated lists are stored as a single string
+        assert_eq!(schema["properties"]["main_dpu_ids"]["type"], "string");
+        assert_eq!(schema["required"], json!(["main_dpu_ids"]));
+        assert_eq!(
+            schema["x-sonic-db"],
+            json!({ "db_name": "CONFIG_DB", "table_name": "VDPU", "key_separator": "|", "is_dpu": false })
+        );
+
+        let schema = table_schema::<BfdSessionTable>();
+        assert!(schema["properties"]["type"].is_object());
+        assert!(!schema["required"].as_array().unwrap().contains(&json!("tx_interval")));
+
+        let names: Vec<String> = all_table_schemas().into_iter().map(|(name, _)| name).collect();
+        assert!(names.contains(&"DPU_STATE_DB.DASH_HA_SCOPE_STATE".to_string()));
+        assert!(names.contains(&"STATE_DB.DASH_HA_SCOPE_STATE".to_string()));
+    }
+}
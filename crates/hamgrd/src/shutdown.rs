@@ -0,0 +1,182 @@
+//! Graceful shutdown
+//!
+//! On SIGTERM or SIGINT hamgrd drains before it exits:
+//! 1. the actor creators stop, so no actor is created for config that arrives while draining,
+//! 2. every actor is sent [`HamgrdShutdown`]. HA scope actors tell the HA scope actor of their peer,
+//!    in the hamgrd managing the peer vDPU, that this is a planned exit,
+//! 3. every actor is queried for its state until the writes it sent to the producer bridges are all
+//!    acknowledged. An actor answers the query after it handled the messages before, so its internal
+//!    state is committed to STATE_DB by then.
+//!
+//! Draining takes at most [`DRAIN_TIMEOUT`], so a stuck actor or bridge doesn't hold up the exit.
+use crate::ha_actor_messages::{HamgrdShutdown, PeerPlannedExit};
+use crate::mgmt_client::ManagementClient;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_actor::state::ActorStateDump;
+use swbus_edge::simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient};
+use swbus_edge::swbus_proto::swbus::{request_response::ResponseBody, ManagementRequestType, ServicePath};
+use swbus_edge::SwbusEdgeRuntime;
+use swss_common_bridge::consumer::ConsumerBridge;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// Time to drain the actors before exiting anyway.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the actors are queried while their writes are not acknowledged.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Whether hamgrd is draining before it exits.
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Wait for SIGTERM or SIGINT. Returns the name of the signal.
+pub async fn wait_for_signal() -> Result<&'static str> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT"),
+    }
+}
+
+/// Drain hamgrd after `signal`. `actor_creators` are the bridges that feed the actor creators.
+pub async fn drain(edge_runtime: Arc<SwbusEdgeRuntime>, actor_creators: Vec<ConsumerBridge>, signal: &str) {
+    info!("Received {signal}, draining for up to {DRAIN_TIMEOUT:?}");
+    DRAINING.store(true, Ordering::Relaxed);
+    drop(actor_creators);
+
+    let actors = match crate::slot::current().actor_runtime() {
+        Some(runtime) => runtime.actors(),
+        None => Vec::new(),
+    };
+    let count = actors.len();
+    let not_drained = drain_actors(edge_runtime, actors, signal, Instant::now() + DRAIN_TIMEOUT).await;
+    if not_drained.is_empty() {
+        info!("Drained {count} actors");
+    } else {
+        error!(
+            "Exiting with {} of {count} actors not drained: {}",
+            not_drained.len(),
+            not_drained.join(", ")
+        );
+    }
+}
+
+/// Send [`HamgrdShutdown`] to `actors` and wait until they handled it and their writes are
+/// acknowledged, or `deadline`. Returns the actors that are not drained by then.
+async fn drain_actors(
+    edge_runtime: Arc<SwbusEdgeRuntime>,
+    actors: Vec<ServicePath>,
+    signal: &str,
+    deadline: Instant,
+) -> Vec<String> {
+    let sp = edge_runtime.new_sp("shutdown", "0");
+    let swbus = SimpleSwbusEdgeClient::new(edge_runtime.clone(), sp, false, false);
+    let mut client = ManagementClient::new(edge_runtime, "shutdown-client");
+
+    let mut pending = HashMap::new();
+    for actor in actors {
+        match HamgrdShutdown::new_actor_msg(signal) {
+            Ok(msg) => {
+                let outgoing = OutgoingMessage {
+                    destination: actor.clone(),
+                    body: MessageBody::Request {
+                        payload: msg.serialize().into(),
+                    },
+                };
+                if let Err(e) = swbus.send(outgoing).await {
+                    warn!("Failed to send shutdown to {}: {e}", actor.to_longest_path());
+                }
+            }
+            Err(e) => warn!("Failed to create shutdown message: {e:#}"),
+        }
+        pending.insert(actor.to_longest_path(), actor);
+    }
+
+    loop {
+        let requests = pending
+            .iter()
+            .map(|(name, actor)| {
+                let msg = client.new_request(actor.clone(), ManagementRequestType::HamgrdGetActorState, &[]);
+                (name.clone(), msg)
+            })
+            .collect();
+        let wait = deadline.saturating_duration_since(Instant::now());
+        for (name, response) in client.query(requests, wait).await {
+            let Some(ResponseBody::ManagementQueryResult(result)) = response.response_body else {
+                continue;
+            };
+            match serde_json::from_str::<ActorStateDump>(&result.value) {
+                Ok(state) if all_acked(&state) => {
+                    pending.remove(&name);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Invalid state of {name}: {e}"),
+            }
+        }
+
+        if pending.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    let mut not_drained: Vec<String> = pending.into_keys().collect();
+    not_drained.sort();
+    not_drained
+}
+
+/// Whether all messages sent by an actor are acknowledged. The planned exit notice to the peer
+/// doesn't count, the peer may be down.
+fn all_acked(state: &ActorStateDump) -> bool {
+    state
+        .outgoing
+        .outgoing_sent
+        .iter()
+        .all(|(key, entry)| entry.acked || PeerPlannedExit::is_my_msg(key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use swbus_actor::{Actor, Context, State};
+
+    /// Records the keys of the messages it handles.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Actor for Recorder {
+        async fn handle_message(&mut self, _state: &mut State, key: &str, _context: &mut Context) -> Result<()> {
+            self.0.lock().unwrap().push(key.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_sends_shutdown_to_actors() {
+        let runtime = crate::actors::test::create_actor_runtime(0, "10.0.0.0", "10::").await;
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        runtime.spawn(Recorder(handled.clone()), "recorder", "0");
+        let actor = runtime.sp("recorder", "0");
+        let missing = runtime.sp("recorder", "missing");
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let not_drained = drain_actors(
+            runtime.get_swbus_edge(),
+            vec![actor, missing.clone()],
+            "SIGTERM",
+            deadline,
+        )
+        .await;
+        assert_eq!(not_drained, vec![missing.to_longest_path()]);
+        assert_eq!(*handled.lock().unwrap(), vec![HamgrdShutdown::msg_key().to_string()]);
+    }
+}
@@ -0,0 +1,384 @@
+//! In-memory sonic-db for development
+//!
+//! With `--dev-sim <seed.json>`, every table hamgrd reads or writes lives in an in-memory store
+//! instead of redis, so the actor pipeline can be run and debugged on a laptop with only swbusd.
+//! The store is seeded from a file in the format of config_db.json, nested one level deeper by db:
+//!
+//! ```json
+//! { "CONFIG_DB": { "DPU": { "dpu0": { "dpu_id": "0", ... } } } }
+//! ```
+//!
+//! The seed file is watched, and the entries changed in it are applied to the store as they would
+//! come from redis. The swbusd config is loaded from `--dev-sim-swbus-config`, and the content of the
+//! store, outputs included, is written to `--dev-sim-dump` when hamgrd exits.
+use anyhow::{Context, Result};
+use clap::Args;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use swss_common::{CxxString, FieldValues, KeyOpFieldValues, KeyOperation};
+use swss_common_bridge::consumer::ConsumerTable;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info};
+
+const SEED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Args, Debug, Default)]
+pub struct SimArgs {
+    // Keep all tables in memory instead of redis, seeded from this JSON file. For development only.
+    #[arg(long)]
+    pub dev_sim: Option<PathBuf>,
+    // swbusd config to use with --dev-sim, instead of deriving it from CONFIG_DB.
+    #[arg(long, requires = "dev_sim")]
+    pub dev_sim_swbus_config: Option<String>,
+    // Write the content of the in-memory tables to this JSON file on exit.
+    #[arg(long, requires = "dev_sim")]
+    pub dev_sim_dump: Option<PathBuf>,
+}
+
+type Entries = BTreeMap<String, FieldValues>;
+
+/// Tables by `(db, table)`
+#[derive(Default)]
+struct Store {
+    tables: BTreeMap<(String, String), Entries>,
+    subscribers: HashMap<(String, String), Vec<UnboundedSender<KeyOpFieldValues>>>,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static SWBUS_CONFIG: OnceLock<String> = OnceLock::new();
+
+/// Whether the tables are kept in memory.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+fn store() -> std::sync::MutexGuard<'static, Store> {
+    STORE.get_or_init(Default::default).lock().unwrap()
+}
+
+/// Keep the tables in memory if `--dev-sim` is given, seeding and watching them.
+pub fn start(args: &SimArgs) -> Result<()> {
+    let Some(seed) = &args.dev_sim else {
+        return Ok(());
+    };
+    let entries = read_seed(seed)?;
+    info!("Running on in-memory tables seeded from {}", seed.display());
+    ACTIVE.store(true, Ordering::Relaxed);
+    apply_seed(&BTreeMap::new(), &entries);
+    if let Some(path) = &args.dev_sim_swbus_config {
+        SWBUS_CONFIG.set(path.clone()).unwrap();
+    }
+    tokio::task::spawn(watch_seed(seed.clone(), entries));
+    Ok(())
+}
+
+/// Path of the swbusd config given with `--dev-sim-swbus-config`.
+pub fn swbus_config_path() -> Option<&'static str> {
+    SWBUS_CONFIG.get().map(String::as_str)
+}
+
+/// Content of all tables, in the format of the seed file.
+pub fn dump() -> Value {
+    let store = store();
+    let mut dbs = Map::new();
+    for ((db, table), entries) in &store.tables {
+        let entries = entries
+            .iter()
+            .map(|(key, fvs)| {
+                let fvs: Map<String, Value> = fvs
+                    .iter()
+                    .map(|(field, value)| (field.clone(), Value::String(value.to_string_lossy().into_owned())))
+                    .collect();
+                (key.clone(), Value::Object(fvs))
+            })
+            .collect();
+        let tables = dbs.entry(db.clone()).or_insert_with(|| Value::Object(Map::new()));
+        tables
+            .as_object_mut()
+            .unwrap()
+            .insert(table.clone(), Value::Object(entries));
+    }
+    Value::Object(dbs)
+}
+
+/// Write the content of all tables to `--dev-sim-dump`, if given.
+pub fn write_dump(args: &SimArgs) {
+    let Some(path) = &args.dev_sim_dump else {
+        return;
+    };
+    match std::fs::write(path, serde_json::to_string_pretty(&dump()).unwrap()) {
+        Ok(()) => info!("Wrote the in-memory tables to {}", path.display()),
+        Err(e) => error!("Failed to write the in-memory tables to {}: {e}", path.display()),
+    }
+}
+
+type SeedEntries = BTreeMap<(String, String, String), FieldValues>;
+
+fn read_seed(path: &Path) -> Result<SeedEntries> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let dbs: BTreeMap<String, BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>> =
+        serde_json::from_str(&content).with_context(|| format!("parsing {}", path.display()))?;
+    let mut entries = SeedEntries::new();
+    for (db, tables) in dbs {
+        for (table, keys) in tables {
+            for (key, fvs) in keys {
+                let fvs = fvs
+                    .into_iter()
+                    .map(|(field, value)| (field, CxxString::new(value)))
+                    .collect();
+                entries.insert((db.clone(), table.clone(), key), fvs);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Apply the difference between two versions of the seed.
+fn apply_seed(old: &SeedEntries, new: &SeedEntries) {
+    for ((db, table, key), fvs) in new {
+        if old.get(&(db.clone(), table.clone(), key.clone())) != Some(fvs) {
+            SimTable::new(db, table).set(key, fvs.clone());
+        }
+    }
+    for (db, table, key) in old.keys() {
+        if !new.contains_key(&(db.clone(), table.clone(), key.clone())) {
+            SimTable::new(db, table).del(key);
+        }
+    }
+}
+
+async fn watch_seed(path: PathBuf, mut entries: SeedEntries) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified: Option<SystemTime> = modified(&path);
+    loop {
+        tokio::time::sleep(SEED_POLL_INTERVAL).await;
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match read_seed(&path) {
+            Ok(new) => {
+                info!("Applying the changes of {}", path.display());
+                apply_seed(&entries, &new);
+                entries = new;
+            }
+            Err(e) => error!("Ignoring the changes of {}: {e:#}", path.display()),
+        }
+    }
+}
+
+/// A table of the in-memory store. Mirrors the `Table` API for writes and reads, and subscribes to
+/// the updates of the table as a [`ConsumerTable`].
+#[derive(Debug)]
+pub struct SimTable {
+    db: String,
+    table: String,
+    updates: Option<UnboundedReceiver<KeyOpFieldValues>>,
+    pending: Vec<KeyOpFieldValues>,
+}
+
+impl SimTable {
+    pub fn new(db: &str, table: &str) -> Self {
+        SimTable {
+            db: db.to_string(),
+            table: table.to_string(),
+            updates: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The table, receiving the updates made to it from now on.
+    pub fn subscribe(db: &str, table: &str) -> Self {
+        let (tx, rx) = unbounded_channel();
+        store()
+            .subscribers
+            .entry((db.to_string(), table.to_string()))
+            .or_default()
+            .push(tx);
+        SimTable {
+            updates: Some(rx),
+            ..SimTable::new(db, table)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.table
+    }
+
+    fn id(&self) -> (String, String) {
+        (self.db.clone(), self.table.clone())
+    }
+
+    pub fn get(&self, key: &str) -> Option<FieldValues> {
+        store().tables.get(&self.id())?.get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        store()
+            .tables
+            .get(&self.id())
+            .map(|entries| entries.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Merge `fvs` into the entry of `key`, like `Table::set`.
+    pub fn set(&self, key: &str, fvs: FieldValues) {
+        debug!("{}/{}|{key}: set {fvs:?}", self.db, self.table);
+        let mut store = store();
+        store
+            .tables
+            .entry(self.id())
+            .or_default()
+            .entry(key.to_string())
+            .or_default()
+            .extend(fvs.clone());
+        store.notify(
+            &self.id(),
+            KeyOpFieldValues {
+                key: key.to_string(),
+                operation: KeyOperation::Set,
+                field_values: fvs,
+            },
+        );
+    }
+
+    pub fn del(&self, key: &str) {
+        debug!("{}/{}|{key}: del", self.db, self.table);
+        let mut store = store();
+        let removed = store
+            .tables
+            .get_mut(&self.id())
+            .and_then(|entries| entries.remove(key))
+            .is_some();
+        if removed {
+            store.notify(
+                &self.id(),
+                KeyOpFieldValues {
+                    key: key.to_string(),
+                    operation: KeyOperation::Del,
+                    field_values: FieldValues::new(),
+                },
+            );
+        }
+    }
+}
+
+impl Store {
+    fn notify(&mut self, id: &(String, String), kfv: KeyOpFieldValues) {
+        if let Some(subscribers) = self.subscribers.get_mut(id) {
+            subscribers.retain(|tx| tx.send(kfv.clone()).is_ok());
+        }
+    }
+}
+
+impl ConsumerTable for SimTable {
+    async fn read_data(&mut self) -> swbus_actor::Result<()> {
+        let updates = self
+            .updates
+            .as_mut()
+            .ok_or_else(|| swbus_actor::Error::msg(format!("{} is not subscribed", self.table)))?;
+        match updates.recv().await {
+            Some(kfv) => {
+                self.pending.push(kfv);
+                Ok(())
+            }
+            None => Err(swbus_actor::Error::msg("the store is gone")),
+        }
+    }
+
+    async fn pops(&mut self) -> swbus_actor::Result<Vec<KeyOpFieldValues>> {
+        if let Some(updates) = self.updates.as_mut() {
+            while let Ok(kfv) = updates.try_recv() {
+                self.pending.push(kfv);
+            }
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    async fn rehydrate(&mut self) -> swbus_actor::Result<Vec<KeyOpFieldValues>> {
+        Ok(self
+            .keys()
+            .into_iter()
+            .filter_map(|key| {
+                let field_values = self.get(&key)?;
+                Some(KeyOpFieldValues {
+                    key,
+                    operation: KeyOperation::Set,
+                    field_values,
+                })
+            })
+            .collect())
+    }
+
+    async fn reconnect(&mut self) -> swbus_actor::Result<()> {
+        Ok(())
+    }
+}
+
+impl swss_common_bridge::producer::ProducerTable for SimTable {
+    async fn set(&mut self, key: &str, fvs: FieldValues) -> swbus_actor::Result<()> {
+        SimTable::set(self, key, fvs);
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> swbus_actor::Result<()> {
+        SimTable::del(self, key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fvs(pairs: &[(&str, &str)]) -> FieldValues {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), CxxString::new(*value)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn subscriber_receives_seed_changes() {
+        let mut subscriber = SimTable::subscribe("CONFIG_DB", "SIM_TEST_TABLE");
+        let seed = |version: &str| -> SeedEntries {
+            [(
+                ("CONFIG_DB".to_string(), "SIM_TEST_TABLE".to_string(), "a".to_string()),
+                fvs(&[("version", version)]),
+            )]
+            .into()
+        };
+        apply_seed(&SeedEntries::new(), &seed("1"));
+        // An unchanged entry is not applied again
+        apply_seed(&seed("1"), &seed("1"));
+        apply_seed(&seed("1"), &SeedEntries::new());
+
+        subscriber.read_data().await.unwrap();
+        let kfvs = subscriber.pops().await.unwrap();
+        assert_eq!(kfvs.len(), 2);
+        assert_eq!(kfvs[0].operation, KeyOperation::Set);
+        assert_eq!(kfvs[0].field_values, fvs(&[("version", "1")]));
+        assert_eq!(kfvs[1].operation, KeyOperation::Del);
+        assert!(subscriber.rehydrate().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_merges_and_dump_has_all_tables() {
+        let table = SimTable::new("APPL_DB", "SIM_DUMP_TABLE");
+        table.set("a", fvs(&[("x", "1"), ("y", "2")]));
+        table.set("a", fvs(&[("y", "3")]));
+        assert_eq!(table.get("a"), Some(fvs(&[("x", "1"), ("y", "3")])));
+        assert_eq!(dump()["APPL_DB"]["SIM_DUMP_TABLE"]["a"]["y"], "3");
+
+        let mut rehydrating = SimTable::subscribe("APPL_DB", "SIM_DUMP_TABLE");
+        assert_eq!(rehydrating.rehydrate().await.unwrap().len(), 1);
+        table.del("a");
+        assert_eq!(table.keys(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,187 @@
+//! DPU slots
+//!
+//! hamgrd manages the DPU of one slot, or the DPUs of several slots in multi-slot mode. Every slot runs
+//! independently in a tokio runtime of its own, with its own swbus edge runtime, actors and producer
+//! bridges. The edge runtime connects to the swbusd of the DPU, which only routes the service paths of
+//! that DPU. The CPUs are divided among the runtimes of the slots, see [`default_worker_threads`].
+//!
+//! What hamgrd keeps for the slot it manages, i.e. the slot id, the vDPU it serves, the write generation
+//! and the actor runtime, is a [`Slot`] that the threads of the runtime enter when they start, so code
+//! running in a slot gets it with [`current`]. Running outside of a slot is a bug. Everything else, e.g.
+//! the db options, the table sources and the event exporters, is shared by the slots.
+use crate::generation::Generation;
+use std::cell::Cell;
+use std::num::NonZeroUsize;
+#[cfg(test)]
+use std::sync::LazyLock;
+use std::sync::{Mutex, OnceLock};
+use swbus_actor::ActorRuntime;
+use tokio::runtime::Runtime;
+
+pub struct Slot {
+    id: u32,
+    vdpu_id: Option<String>,
+    generation: Generation,
+    actor_runtime: OnceLock<ActorRuntime>,
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<&'static Slot>> = const { Cell::new(None) };
+}
+
+/// The slots of this process, in the order they were started
+static SLOTS: Mutex<Vec<&'static Slot>> = Mutex::new(Vec::new());
+
+/// The slot of tests, which don't run in one
+#[cfg(test)]
+static DEFAULT: LazyLock<Slot> = LazyLock::new(|| Slot::new(0, None));
+
+/// The slot the calling thread runs in. Panics if it doesn't run in one, since guessing the slot would
+/// read and write the tables of another DPU.
+pub fn current() -> &'static Slot {
+    match try_current() {
+        Some(slot) => slot,
+        #[cfg(test)]
+        None => &DEFAULT,
+        #[cfg(not(test))]
+        None => panic!(
+            "thread {} doesn't run in a slot",
+            std::thread::current().name().unwrap_or("<unnamed>")
+        ),
+    }
+}
+
+/// The slot the calling thread runs in, if it runs in one.
+pub fn try_current() -> Option<&'static Slot> {
+    CURRENT.get()
+}
+
+/// The slots started in this process, or the slot the calling thread runs in if none was started.
+pub fn all() -> Vec<&'static Slot> {
+    let slots = SLOTS.lock().unwrap().clone();
+    if !slots.is_empty() {
+        return slots;
+    }
+    #[cfg(test)]
+    let current = Some(current());
+    #[cfg(not(test))]
+    let current = try_current();
+    current.into_iter().collect()
+}
+
+/// Worker threads of the runtime of every slot when `slots` slots run in this process: the CPUs are
+/// divided among them, with at least one thread per slot.
+pub fn default_worker_threads(slots: usize) -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    (cpus / slots.max(1)).max(1)
+}
+
+impl Slot {
+    fn new(id: u32, vdpu_id: Option<String>) -> Self {
+        Slot {
+            id,
+            vdpu_id,
+            generation: Generation::new(),
+            actor_runtime: OnceLock::new(),
+        }
+    }
+
+    /// Create the slot of DPU `id`, serving `vdpu_id` if known, and the runtime it runs in with
+    /// `worker_threads` worker threads. The slot lives as long as the process.
+    pub fn start(id: u32, vdpu_id: Option<String>, worker_threads: usize) -> std::io::Result<(&'static Slot, Runtime)> {
+        let slot: &'static Slot = Box::leak(Box::new(Slot::new(id, vdpu_id)));
+        let runtime = slot.runtime(worker_threads)?;
+        SLOTS.lock().unwrap().push(slot);
+        Ok((slot, runtime))
+    }
+
+    /// A runtime whose threads run in this slot.
+    fn runtime(&'static self, worker_threads: usize) -> std::io::Result<Runtime> {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads.max(1))
+            .enable_all()
+            .thread_name(format!("hamgrd-{}", self.name()))
+            .on_thread_start(move || self.enter())
+            .build()
+    }
+
+    /// Run the calling thread in this slot, e.g. before it blocks on the runtime of the slot.
+    pub fn enter(&'static self) {
+        CURRENT.set(Some(self));
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The name of the slot in keys and container names, e.g. dpu0.
+    pub fn name(&self) -> String {
+        format!("dpu{}", self.id)
+    }
+
+    /// The vDPU the DPU of this slot serves, if it was configured.
+    pub fn vdpu_id(&self) -> Option<&str> {
+        self.vdpu_id.as_deref()
+    }
+
+    /// The generation of the writes of this slot, see [`crate::generation`].
+    pub fn generation(&'static self) -> &'static Generation {
+        &self.generation
+    }
+
+    /// Set the actor runtime of this slot, once on startup.
+    pub fn set_actor_runtime(&self, actor_runtime: ActorRuntime) {
+        if self.actor_runtime.set(actor_runtime).is_err() {
+            panic!("the actor runtime of {} is set already", self.name());
+        }
+    }
+
+    /// The actor runtime of this slot, if it is set.
+    pub fn actor_runtime(&self) -> Option<&ActorRuntime> {
+        self.actor_runtime.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn threads_of_a_slot_runtime_run_in_the_slot() {
+        assert_eq!(current().id(), 0);
+        // Not started, so the slots of the process stay as they are for the other tests
+        let slot: &'static Slot = Box::leak(Box::new(Slot::new(3, None)));
+        let runtime = slot.runtime(2).unwrap();
+        let id = runtime.block_on(async { tokio::task::spawn(async { current().id() }).await.unwrap() });
+        assert_eq!(id, 3);
+        assert!(std::ptr::eq(
+            runtime.block_on(async { tokio::task::spawn(async { current().generation() }).await.unwrap() }),
+            slot.generation()
+        ));
+        // The calling thread is not in the slot until it enters it
+        assert_eq!(current().id(), 0);
+        slot.enter();
+        assert_eq!(current().id(), 3);
+    }
+
+    #[test]
+    fn threads_outside_of_a_slot_have_none() {
+        assert!(std::thread::spawn(|| try_current().is_none()).join().unwrap());
+        let slot: &'static Slot = Box::leak(Box::new(Slot::new(5, None)));
+        let runtime = slot.runtime(1).unwrap();
+        let in_slot = runtime.block_on(async {
+            tokio::task::spawn_blocking(|| try_current().map(Slot::id))
+                .await
+                .unwrap()
+        });
+        assert_eq!(in_slot, Some(5));
+    }
+
+    #[test]
+    fn cpus_are_divided_among_the_slots() {
+        let cpus = std::thread::available_parallelism().unwrap().get();
+        assert_eq!(default_worker_threads(1), cpus);
+        assert_eq!(default_worker_threads(cpus * 2), 1);
+        assert_eq!(default_worker_threads(0), cpus);
+    }
+}
@@ -0,0 +1,170 @@
+//! Tables hamgrd reads and writes
+//!
+//! The tables are in redis, or with the `dev-sim` feature and `--dev-sim`, in the in-memory store of
+//! [`crate::sim`]. Code that opens a table goes through [`open_table`] and [`subscribe`] so that it
+//! runs on either.
+#[cfg(feature = "dev-sim")]
+use crate::TableSource;
+use anyhow::Result;
+use swss_common::{FieldValues, KeyOpFieldValues, SonicDbTable, SubscriberStateTable, Table};
+use swss_common_bridge::consumer::ConsumerTable;
+
+/// Whether the tables are in memory instead of redis.
+#[cfg(feature = "dev-sim")]
+pub fn in_memory() -> bool {
+    crate::sim::is_active()
+}
+
+#[cfg(not(feature = "dev-sim"))]
+pub fn in_memory() -> bool {
+    false
+}
+
+/// A table to read and write entries of, with the async API of `Table`.
+#[derive(Debug)]
+pub enum DbTable {
+    Swss(Table),
+    #[cfg(feature = "dev-sim")]
+    Sim(crate::sim::SimTable),
+}
+
+/// The table of `T`, in the db it is read from.
+pub async fn open_table<T: SonicDbTable + 'static>() -> Result<DbTable> {
+    #[cfg(feature = "dev-sim")]
+    if in_memory() {
+        return Ok(DbTable::Sim(crate::sim::SimTable::new(
+            &T::source_db_name(),
+            T::table_name(),
+        )));
+    }
+    let db = crate::db_for_table::<T>().await?;
+    Ok(DbTable::Swss(Table::new_async(db, T::table_name()).await?))
+}
+
+impl DbTable {
+    pub async fn get_async(&mut self, key: &str) -> Result<Option<FieldValues>> {
+        match self {
+            DbTable::Swss(table) => Ok(table.get_async(key).await?),
+            #[cfg(feature = "dev-sim")]
+            DbTable::Sim(table) => Ok(table.get(key)),
+        }
+    }
+
+    pub async fn get_keys_async(&mut self) -> Result<Vec<String>> {
+        match self {
+            DbTable::Swss(table) => Ok(table.get_keys_async().await?),
+            #[cfg(feature = "dev-sim")]
+            DbTable::Sim(table) => Ok(table.keys()),
+        }
+    }
+
+    pub async fn set_async(&mut self, key: &str, fvs: FieldValues) -> Result<()> {
+        match self {
+            DbTable::Swss(table) => Ok(table.set_async(key, fvs).await?),
+            #[cfg(feature = "dev-sim")]
+            DbTable::Sim(table) => {
+                table.set(key, fvs);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn del_async(&mut self, key: &str) -> Result<()> {
+        match self {
+            DbTable::Swss(table) => Ok(table.del_async(key).await?),
+            #[cfg(feature = "dev-sim")]
+            DbTable::Sim(table) => {
+                table.del(key);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl swbus_actor::state::internal::InternalTable for DbTable {
+    fn table_name(&self) -> String {
+        match self {
+            DbTable::Swss(table) => table.get_name().to_string(),
+            #[cfg(feature = "dev-sim")]
+            DbTable::Sim(table) => table.name().to_string(),
+        }
+    }
+
+    fn get<'a>(
+        &'a mut self,
+        key: &'a str,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Option<FieldValues>>> + Send + 'a>> {
+        Box::pin(self.get_async(key))
+    }
+
+    fn set<'a>(
+        &'a mut self,
+        key: &'a str,
+        fvs: FieldValues,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(self.set_async(key, fvs))
+    }
+
+    fn del<'a>(
+        &'a mut self,
+        key: &'a str,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(self.del_async(key))
+    }
+}
+
+/// A table to consume the updates of.
+pub enum SubscribedTable {
+    Swss(SubscriberStateTable),
+    #[cfg(feature = "dev-sim")]
+    Sim(crate::sim::SimTable),
+}
+
+/// Subscribe to the table of `T`, in the db it is read from.
+pub async fn subscribe<T: SonicDbTable + 'static>() -> Result<SubscribedTable> {
+    #[cfg(feature = "dev-sim")]
+    if in_memory() {
+        return Ok(SubscribedTable::Sim(crate::sim::SimTable::subscribe(
+            &T::source_db_name(),
+            T::table_name(),
+        )));
+    }
+    let db = crate::db_for_table::<T>().await?;
+    Ok(SubscribedTable::Swss(
+        SubscriberStateTable::new_async(db, T::table_name(), None, None).await?,
+    ))
+}
+
+impl ConsumerTable for SubscribedTable {
+    async fn read_data(&mut self) -> swbus_actor::Result<()> {
+        match self {
+            SubscribedTable::Swss(table) => ConsumerTable::read_data(table).await,
+            #[cfg(feature = "dev-sim")]
+            SubscribedTable::Sim(table) => ConsumerTable::read_data(table).await,
+        }
+    }
+
+    async fn pops(&mut self) -> swbus_actor::Result<Vec<KeyOpFieldValues>> {
+        match self {
+            SubscribedTable::Swss(table) => ConsumerTable::pops(table).await,
+            #[cfg(feature = "dev-sim")]
+            SubscribedTable::Sim(table) => ConsumerTable::pops(table).await,
+        }
+    }
+
+    async fn rehydrate(&mut self) -> swbus_actor::Result<Vec<KeyOpFieldValues>> {
+        match self {
+            SubscribedTable::Swss(table) => ConsumerTable::rehydrate(table).await,
+            #[cfg(feature = "dev-sim")]
+            SubscribedTable::Sim(table) => ConsumerTable::rehydrate(table).await,
+        }
+    }
+
+    async fn reconnect(&mut self) -> swbus_actor::Result<()> {
+        match self {
+            SubscribedTable::Swss(table) => ConsumerTable::reconnect(table).await,
+            #[cfg(feature = "dev-sim")]
+            SubscribedTable::Sim(table) => ConsumerTable::reconnect(table).await,
+        }
+    }
+}
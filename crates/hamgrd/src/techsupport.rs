@@ -0,0 +1,382 @@
+//! Techsupport dump
+//!
+//! Handles `HAMGRD_TECHSUPPORT_DUMP` management requests sent to `<hamgrd>/techsupport/0`. The dump
+//! collects the state of every running actor, the swbusd route table, the sonic-db tables used by
+//! hamgrd, the recent HA events and the audit log files into a single tarball, and returns the
+//! tarball path so `show techsupport` can pick it up.
+//!
+//! The request may carry a `path` argument naming a subdirectory of [`DEFAULT_DUMP_DIR`] to write the
+//! tarball to. Absolute paths, `..` and symlinks are refused, so a sender can't make hamgrd write
+//! anywhere else.
+use crate::db_structs::*;
+use crate::mgmt_client::ManagementClient;
+use crate::TableSource;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_edge::swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_edge::swbus_proto::swbus::{
+    request_response::ResponseBody, swbus_message::Body, ManagementQueryResult, ManagementRequestType,
+    RouteQueryResult, ServicePath, SwbusErrorCode, SwbusMessage,
+};
+use swbus_edge::SwbusEdgeRuntime;
+use swss_common::SonicDbTable;
+use tokio::sync::mpsc::{channel, Receiver};
+use tracing::{error, info};
+
+/// Directory the tarballs are written under.
+pub const DEFAULT_DUMP_DIR: &str = "/var/dump";
+
+/// Time to wait for all actors and swbusd to respond to state queries.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TechsupportDumper {
+    sp: ServicePath,
+    rt: Arc<SwbusEdgeRuntime>,
+    handler_rx: Receiver<SwbusMessage>,
+    id_generator: MessageIdGenerator,
+    client: ManagementClient,
+}
+
+impl TechsupportDumper {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>) -> Self {
+        let sp = rt.new_sp("techsupport", "0");
+        let (handler_tx, handler_rx) = channel::<SwbusMessage>(100);
+        rt.add_handler(sp.clone(), handler_tx);
+        let client = ManagementClient::new(rt.clone(), "techsupport-client");
+
+        Self {
+            sp,
+            rt,
+            handler_rx,
+            id_generator: MessageIdGenerator::new(),
+            client,
+        }
+    }
+
+    pub async fn run(mut self) {
+        while let Some(msg) = self.handler_rx.recv().await {
+            let Some(Body::ManagementRequest(ref mgmt_request)) = msg.body else {
+                continue;
+            };
+
+            let result = match ManagementRequestType::try_from(mgmt_request.request) {
+                Ok(ManagementRequestType::HamgrdTechsupportDump) => {
+                    let path = mgmt_request
+                        .arguments
+                        .iter()
+                        .find(|arg| arg.name == "path")
+                        .map(|arg| arg.value.as_str());
+                    self.dump(path).await
+                }
+                _ => Err(anyhow!("Unsupported request type: {}", mgmt_request.request)),
+            };
+
+            let (code, error_message, body) = match result {
+                Ok(tarball) => {
+                    info!("Techsupport dump is saved to {tarball}");
+                    let body = ResponseBody::ManagementQueryResult(ManagementQueryResult { value: tarball });
+                    (SwbusErrorCode::Ok, String::new(), Some(body))
+                }
+                Err(e) => {
+                    error!("Failed to collect techsupport dump: {e:#}");
+                    (SwbusErrorCode::Fail, format!("{e:#}"), None)
+                }
+            };
+            let response = SwbusMessage::new_response(
+                &msg,
+                Some(&self.sp),
+                code,
+                &error_message,
+                self.id_generator.generate(),
+                body,
+            );
+            if self.rt.send(response).await.is_err() {
+                error!("Failed to send techsupport response to swbus");
+            }
+        }
+    }
+
+    /// Collect the dump into `<dump_dir>/hamgrd_dump_<timestamp>.tar.gz` and return the tarball path,
+    /// `dump_dir` being `path` resolved under [`DEFAULT_DUMP_DIR`].
+    async fn dump(&mut self, path: Option<&str>) -> Result<String> {
+        let dump_dir = resolve_dump_dir(Path::new(DEFAULT_DUMP_DIR), path).await?;
+        let name = format!("hamgrd_dump_{}", chrono::Utc::now().format("%Y%m%d_%H%M%S"));
+        let work_dir = dump_dir.join(&name);
+        // create_dir fails on an existing entry, so nothing is written through a planted symlink
+        tokio::fs::create_dir(&work_dir)
+            .await
+            .context(format!("creating {}", work_dir.display()))?;
+
+        let actors = self.collect_actor_states().await;
+        write_json(&work_dir.join("actors.json"), &actors).await?;
+
+        let routes = self.collect_routes().await;
+        write_json(&work_dir.join("routes.json"), &routes).await?;
+
+        let tables = collect_tables().await;
+        write_json(&work_dir.join("tables.json"), &tables).await?;
+
+        let ha_events = serde_json::to_value(crate::ha_events::history())?;
+        write_json(&work_dir.join("ha_events.json"), &ha_events).await?;
+
+        let parse_warnings = serde_json::to_value(crate::db_structs::parse_warnings())?;
+        write_json(&work_dir.join("parse_warnings.json"), &parse_warnings).await?;
+
+        let bridge_resyncs = serde_json::to_value(swss_common_bridge::consumer::resync_counts())?;
+        write_json(&work_dir.join("bridge_resyncs.json"), &bridge_resyncs).await?;
+
+        let bridge_coalesced = serde_json::to_value(swss_common_bridge::consumer::coalesced_counts())?;
+        write_json(&work_dir.join("bridge_coalesced.json"), &bridge_coalesced).await?;
+
+        #[cfg(feature = "dpu")]
+        {
+            let orchagent_lag = serde_json::to_value(crate::orchagent_lag::lags())?;
+            write_json(&work_dir.join("orchagent_lag.json"), &orchagent_lag).await?;
+        }
+
+        copy_audit_files(&work_dir).await;
+
+        let result = pack(&dump_dir, &name).await;
+        tokio::fs::remove_dir_all(&work_dir).await.ok();
+        result
+    }
+
+    async fn collect_actor_states(&mut self) -> Value {
+        let actors = match crate::slot::current().actor_runtime() {
+            Some(runtime) => runtime.actors(),
+            None => Vec::new(),
+        };
+        let requests = actors
+            .into_iter()
+            .map(|sp| {
+                let msg = self
+                    .client
+                    .new_request(sp.clone(), ManagementRequestType::HamgrdGetActorState, &[]);
+                (sp.to_longest_path(), msg)
+            })
+            .collect();
+
+        let states: Map<String, Value> = self
+            .client
+            .query(requests, QUERY_TIMEOUT)
+            .await
+            .into_iter()
+            .map(|(name, response)| {
+                let state = match response.response_body {
+                    Some(ResponseBody::ManagementQueryResult(result)) => {
+                        serde_json::from_str(&result.value).unwrap_or(Value::String(result.value))
+                    }
+                    _ => json!({ "error": response.error_message }),
+                };
+                (name, state)
+            })
+            .collect();
+        Value::Object(states)
+    }
+
+    /// All the routes of swbusd, queried a page at a time.
+    async fn collect_routes(&mut self) -> Value {
+        let swbusd_sp = self.sp.to_swbusd_service_path();
+        let mut routes = RouteQueryResult::default();
+        loop {
+            let cursor = std::mem::take(&mut routes.next_cursor);
+            // an empty cursor asks for the first page
+            let msg = self.client.new_request(
+                swbusd_sp.clone(),
+                ManagementRequestType::SwbusdGetRoutes,
+                &[("cursor", cursor.as_str())],
+            );
+            match self
+                .client
+                .request(msg, QUERY_TIMEOUT)
+                .await
+                .and_then(|r| r.response_body)
+            {
+                Some(ResponseBody::RouteQueryResult(page)) => {
+                    routes.entries.extend(page.entries);
+                    routes.next_cursor = page.next_cursor;
+                    routes.total_entries = page.total_entries;
+                }
+                _ => return json!({ "error": "failed to get routes from swbusd" }),
+            }
+            if routes.next_cursor.is_empty() {
+                return serde_json::to_value(routes).unwrap_or_default();
+            }
+        }
+    }
+}
+
+async fn dump_table<T>() -> Result<Value>
+where
+    T: SonicDbTable + 'static,
+{
+    let mut table = crate::tables::open_table::<T>().await?;
+    let mut entries = Map::new();
+    for key in table.get_keys_async().await? {
+        if let Some(fvs) = table.get_async(&key).await? {
+            entries.insert(key, serde_json::to_value(fvs)?);
+        }
+    }
+    Ok(Value::Object(entries))
+}
+
+/// Snapshot of the sonic-db tables read or written by hamgrd, keyed by `<db>|<table>`.
+async fn collect_tables() -> Value {
+    async fn add<T: SonicDbTable + 'static>(tables: &mut Map<String, Value>) {
+        let value = match dump_table::<T>().await {
+            Ok(value) => value,
+            Err(e) => json!({ "error": format!("{e:#}") }),
+        };
+        tables.insert(format!("{}|{}", T::source_db_name(), T::table_name()), value);
+    }
+
+    let mut tables = Map::new();
+    add::<Dpu>(&mut tables).await;
+    add::<RemoteDpu>(&mut tables).await;
+    add::<VDpu>(&mut tables).await;
+    add::<DashHaGlobalConfig>(&mut tables).await;
+    add::<DashHaSetConfigTable>(&mut tables).await;
+    add::<DashHaScopeConfigTable>(&mut tables).await;
+    add::<DashEniPlacementTable>(&mut tables).await;
+    add::<VnetRouteTunnelTable>(&mut tables).await;
+    add::<NpuDashHaScopeState>(&mut tables).await;
+    add::<DashHaDpuStateTable>(&mut tables).await;
+    add::<DashHaScopeSummaryTable>(&mut tables).await;
+    add::<DashHaAlarmTable>(&mut tables).await;
+    add::<DashHaConfigValidationTable>(&mut tables).await;
+    add::<DashHaBridgeDeadLetterTable>(&mut tables).await;
+    add::<DashHaAuditTable>(&mut tables).await;
+    add::<HamgrdActorSnapshotTable>(&mut tables).await;
+    add::<HamgrdGenerationTable>(&mut tables).await;
+    add::<HamgrdStartupStatusTable>(&mut tables).await;
+    add::<HamgrdExitTable>(&mut tables).await;
+    add::<DpuState>(&mut tables).await;
+    add::<ChassisModuleTable>(&mut tables).await;
+    add::<PcieDetachInfo>(&mut tables).await;
+    add::<DashHaSetTable>(&mut tables).await;
+    add::<DashHaScopeTable>(&mut tables).await;
+    add::<BfdSessionTable>(&mut tables).await;
+    add::<DpuDashHaScopeState>(&mut tables).await;
+    add::<DpuDashHaFlowSyncState>(&mut tables).await;
+    add::<DashBfdProbeState>(&mut tables).await;
+    Value::Object(tables)
+}
+
+/// Resolve the `path` argument of a dump request to a directory under `base`, creating it if needed.
+/// `path` must be relative and must not go through `..` or a symlink.
+async fn resolve_dump_dir(base: &Path, path: Option<&str>) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(base)
+        .await
+        .context(format!("creating {}", base.display()))?;
+
+    let mut dir = base.to_path_buf();
+    for component in Path::new(path.unwrap_or_default()).components() {
+        match component {
+            Component::Normal(name) => dir.push(name),
+            Component::CurDir => continue,
+            _ => return Err(anyhow!("dump path must be a relative path under {}", base.display())),
+        }
+        match tokio::fs::symlink_metadata(&dir).await {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(anyhow!("{} is not a directory", dir.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => tokio::fs::create_dir(&dir)
+                .await
+                .context(format!("creating {}", dir.display()))?,
+            Err(e) => return Err(e).context(format!("checking {}", dir.display())),
+        }
+    }
+    Ok(dir)
+}
+
+/// Pack `<dump_dir>/<name>` into `<dump_dir>/<name>.tar.gz` and return the tarball path.
+async fn pack(dump_dir: &Path, name: &str) -> Result<String> {
+    let tarball = dump_dir.join(format!("{name}.tar.gz"));
+    // tar writes to a file created here, as create_new doesn't follow a symlink planted at its path
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tarball)
+        .context(format!("creating {}", tarball.display()))?;
+    let status = tokio::process::Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(dump_dir)
+        .arg(name)
+        .stdout(file)
+        .status()
+        .await
+        .context("running tar")?;
+    if !status.success() {
+        tokio::fs::remove_file(&tarball).await.ok();
+        return Err(anyhow!("tar exited with {status}"));
+    }
+    Ok(tarball.to_string_lossy().into_owned())
+}
+
+async fn write_json(path: &Path, value: &Value) -> Result<()> {
+    let content = serde_json::to_string_pretty(value)?;
+    tokio::fs::write(path, content)
+        .await
+        .context(format!("writing {}", path.display()))
+}
+
+/// Copy the audit log files to `work_dir`. A file that can't be copied is left out of the dump.
+async fn copy_audit_files(work_dir: &Path) {
+    for path in crate::audit::files() {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        match tokio::fs::copy(&path, work_dir.join(name)).await {
+            Ok(_) => {}
+            // the file is not rotated yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Failed to copy the audit log {} to the dump: {e}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use swss_common::{DbConnector, Table};
+    use swss_common_testing::Redis;
+
+    #[tokio::test]
+    async fn test_dump_table() {
+        let _redis = Redis::start_config_db();
+        let db = DbConnector::new_named("CONFIG_DB", false, 0).unwrap();
+        let table = Table::new(db, VDpu::table_name()).unwrap();
+        table
+            .set("vdpu0", vec![("main_dpu_ids".to_string(), "dpu0".to_string())])
+            .unwrap();
+
+        let value = dump_table::<VDpu>().await.unwrap();
+        assert_eq!(value, json!({ "vdpu0": { "main_dpu_ids": "dpu0" } }));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dump_dir() {
+        let base = std::env::temp_dir().join(format!("hamgrd-dump-{}", uuid::Uuid::new_v4()));
+
+        assert_eq!(resolve_dump_dir(&base, None).await.unwrap(), base);
+        assert_eq!(
+            resolve_dump_dir(&base, Some("a/./b")).await.unwrap(),
+            base.join("a").join("b")
+        );
+        assert!(base.join("a").join("b").is_dir());
+
+        for path in ["/etc", "../x", "a/../../x"] {
+            assert!(resolve_dump_dir(&base, Some(path)).await.is_err(), "{path}");
+        }
+
+        std::os::unix::fs::symlink(std::env::temp_dir(), base.join("link")).unwrap();
+        assert!(resolve_dump_dir(&base, Some("link")).await.is_err());
+        assert!(resolve_dump_dir(&base, Some("link/x")).await.is_err());
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -16,6 +16,7 @@ use tracing_subscriber::{
 
 lazy_static! {
     static ref LOG_FOR_TEST_INIT: Mutex<bool> = Mutex::new(false);
+    static ref LEVEL_RELOAD_HANDLE: Mutex<Option<reload::Handle<filter::LevelFilter, Registry>>> = Mutex::new(None);
 }
 
 #[cfg(debug_assertions)]
@@ -81,6 +82,7 @@ pub fn init(program_name: &'static str, link_swsscommon_logger: bool) -> Result<
     if link_swsscommon_logger && !log_env_set {
         let filter = filter::LevelFilter::INFO;
         let (level_layer, level_reload_handle) = reload::Layer::new(filter);
+        *LEVEL_RELOAD_HANDLE.lock().unwrap() = Some(level_reload_handle.clone());
 
         let handler = LoggerConfigHandler { level_reload_handle };
         // connect to swsscommon logger
@@ -111,14 +113,41 @@ pub fn init(program_name: &'static str, link_swsscommon_logger: bool) -> Result<
         .with_ansi(false)
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE);
 
+    // The env filter can't be reloaded. Put a pass-through level filter in front of it so that the level
+    // can still be lowered at runtime with set_log_level.
+    let (level_layer, level_reload_handle) = reload::Layer::new(filter::LevelFilter::TRACE);
+    *LEVEL_RELOAD_HANDLE.lock().unwrap() = Some(level_reload_handle);
+
     tracing_subscriber::registry()
-        .with(filter.and_then(file_subscriber))
+        .with(level_layer.and_then(filter.and_then(file_subscriber)))
         .with(ErrorLayer::default())
         .init();
 
     Ok(())
 }
 
+/// Change the log level at runtime. Accepts the tracing level names (error, warn, info, debug, trace, off),
+/// case insensitive. Fails if the logger is not initialized with [`init`].
+pub fn set_log_level(level: &str) -> Result<()> {
+    let level: filter::LevelFilter = level
+        .parse()
+        .map_err(|_| color_eyre::eyre::eyre!("Invalid log level: {level}"))?;
+    let guard = LEVEL_RELOAD_HANDLE.lock().unwrap();
+    let handle = guard
+        .as_ref()
+        .ok_or_else(|| color_eyre::eyre::eyre!("Logger is not initialized"))?;
+    handle.modify(|f| *f = level).wrap_err("Unable to change log level")?;
+    info!("Log level changed to {}", level);
+    Ok(())
+}
+
+/// Get the current runtime log level, or None if the logger is not initialized with [`init`].
+pub fn get_log_level() -> Option<String> {
+    let guard = LEVEL_RELOAD_HANDLE.lock().unwrap();
+    let level = guard.as_ref()?.clone_current()?;
+    Some(level.to_string().to_lowercase())
+}
+
 #[cfg(target_os = "windows")]
 fn new_file_subscriber(
     program_name: &str,
@@ -207,5 +236,9 @@ mod test {
     fn log_can_be_initialized() {
         let result = super::init("test", true);
         assert!(result.is_ok());
+
+        assert!(super::set_log_level("debug").is_ok());
+        assert_eq!(super::get_log_level(), Some("debug".to_string()));
+        assert!(super::set_log_level("verbose").is_err());
     }
 }
@@ -0,0 +1,282 @@
+//! Prometheus metrics
+//!
+//! Metrics are kept in a process-wide registry and served in the Prometheus text format at `/metrics` by
+//! [`serve`]. A metric family is registered once, usually in a `LazyLock` static next to the code that
+//! updates it, and has one counter or gauge per set of label values. Family names must be unique.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info};
+
+/// Largest request head read from a client.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A counter or a gauge.
+pub trait Metric: Default + Send + Sync + 'static {
+    /// Prometheus type of the metric
+    const TYPE: &'static str;
+    fn value(&self) -> String;
+}
+
+impl Metric for Counter {
+    const TYPE: &'static str = "counter";
+    fn value(&self) -> String {
+        self.get().to_string()
+    }
+}
+
+impl Metric for Gauge {
+    const TYPE: &'static str = "gauge";
+    fn value(&self) -> String {
+        self.get().to_string()
+    }
+}
+
+/// A metric with one instance per set of label values.
+pub struct Family<M> {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    metrics: RwLock<BTreeMap<Vec<String>, Arc<M>>>,
+}
+
+pub type CounterVec = Family<Counter>;
+pub type GaugeVec = Family<Gauge>;
+
+impl<M: Metric> Family<M> {
+    /// Register a metric family named `name`, with the label names `labels`.
+    pub fn register(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Arc<Self> {
+        let family = Arc::new(Family {
+            name,
+            help,
+            labels,
+            metrics: RwLock::new(BTreeMap::new()),
+        });
+        REGISTRY.lock().unwrap().push(family.clone());
+        family
+    }
+
+    /// The metric of `label_values`, given in the order of the label names. Callers updating a metric
+    /// often should keep it, instead of looking it up every time.
+    pub fn with_label_values(&self, label_values: &[&str]) -> Arc<M> {
+        assert_eq!(
+            label_values.len(),
+            self.labels.len(),
+            "metric {} has labels {:?}",
+            self.name,
+            self.labels
+        );
+        let key: Vec<String> = label_values.iter().map(|value| value.to_string()).collect();
+        if let Some(metric) = self.metrics.read().unwrap().get(&key) {
+            return metric.clone();
+        }
+        self.metrics.write().unwrap().entry(key).or_default().clone()
+    }
+
+    /// Stop reporting the metric of `label_values`, e.g. when what it counts for is gone.
+    pub fn remove(&self, label_values: &[&str]) {
+        let key: Vec<String> = label_values.iter().map(|value| value.to_string()).collect();
+        self.metrics.write().unwrap().remove(&key);
+    }
+}
+
+trait Render: Send + Sync {
+    fn render(&self, out: &mut String);
+}
+
+impl<M: Metric> Render for Family<M> {
+    fn render(&self, out: &mut String) {
+        let metrics = self.metrics.read().unwrap();
+        if metrics.is_empty() {
+            return;
+        }
+        writeln!(out, "# HELP {} {}", self.name, self.help).unwrap();
+        writeln!(out, "# TYPE {} {}", self.name, M::TYPE).unwrap();
+        for (label_values, metric) in metrics.iter() {
+            out.push_str(self.name);
+            if !label_values.is_empty() {
+                let labels: Vec<String> = self
+                    .labels
+                    .iter()
+                    .zip(label_values)
+                    .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+                    .collect();
+                write!(out, "{{{}}}", labels.join(",")).unwrap();
+            }
+            writeln!(out, " {}", metric.value()).unwrap();
+        }
+    }
+}
+
+fn escape(label_value: &str) -> String {
+    label_value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+static REGISTRY: Mutex<Vec<Arc<dyn Render>>> = Mutex::new(Vec::new());
+
+/// All registered metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for family in REGISTRY.lock().unwrap().iter() {
+        family.render(&mut out);
+    }
+    out
+}
+
+/// Serve the metrics at `http://<addr>/metrics`. Only fails if `addr` can't be bound.
+pub async fn serve(addr: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving metrics at http://{addr}/metrics");
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept metrics connection: {e}");
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                debug!("Failed to serve metrics to {remote_addr}: {e}");
+            }
+        });
+    }
+}
+
+/// Answer one HTTP request and close the connection.
+async fn respond(mut stream: TcpStream) -> io::Result<()> {
+    let mut request = vec![0; MAX_REQUEST_SIZE];
+    let mut len = 0;
+    while len < request.len() && !request[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut request[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+
+    let request = String::from_utf8_lossy(&request[..len]);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render()),
+        ("GET", _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let counter = CounterVec::register("test_render_total", "Test counter.", &["peer"]);
+        counter.with_label_values(&["a"]).inc_by(2);
+        counter.with_label_values(&["b\"c"]).inc();
+        let gauge = GaugeVec::register("test_render_size", "Test gauge.", &[]);
+        gauge.with_label_values(&[]).set(-3);
+        GaugeVec::register("test_render_unused", "Not reported until used.", &[]);
+
+        let out = render();
+        let counter_text = "# HELP test_render_total Test counter.\n\
+                            # TYPE test_render_total counter\n\
+                            test_render_total{peer=\"a\"} 2\n\
+                            test_render_total{peer=\"b\\\"c\"} 1\n";
+        assert!(out.contains(counter_text), "{out}");
+        assert!(
+            out.contains("# TYPE test_render_size gauge\ntest_render_size -3\n"),
+            "{out}"
+        );
+        assert!(!out.contains("test_render_unused"), "{out}");
+
+        counter.remove(&["a"]);
+        assert!(!render().contains("peer=\"a\""));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let counter = CounterVec::register("test_serve_total", "Test counter.", &[]);
+        counter.with_label_values(&[]).inc();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        tokio::spawn(serve(addr));
+
+        let get = |path: &'static str| async move {
+            let mut stream = loop {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+                }
+            };
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("\r\n\r\n# HELP"), "{response}");
+        assert!(response.contains("test_serve_total 1\n"), "{response}");
+        assert!(get("/other").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
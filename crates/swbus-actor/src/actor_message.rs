@@ -1,7 +1,10 @@
 use crate::Result;
 use anyhow::Context;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::any::type_name;
+use std::{
+    any::type_name,
+    time::{Duration, SystemTime},
+};
 use swbus_edge::{
     simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient},
     swbus_proto::swbus::{ServicePath, SwbusMessage},
@@ -13,12 +16,77 @@ pub use serde_json::Value;
 pub struct ActorMessage {
     pub key: String,
     pub data: Value,
+    /// Set when the data is re-sent from a fresh snapshot of its source, e.g. by a bridge that
+    /// reconnected to the database. Updates may have been missed before it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resync: bool,
+    /// Version of the data, for senders that number the updates they send to a key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<SequenceNumber>,
+    /// Time after which the message is stale, in unix milliseconds. A message that is not acked by
+    /// then times out at its sender, and is dropped by its receiver if it arrives later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+}
+
+/// Number of an update to a key, assigned by its sender.
+///
+/// `epoch` identifies the incarnation of the sender and `seq` counts the updates it sent since, so
+/// that the updates of a restarted sender order after the ones from before the restart. The
+/// incoming table drops updates older than the one it has, and counts the ones that never arrived.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SequenceNumber {
+    pub epoch: u64,
+    pub seq: u64,
 }
 
 impl ActorMessage {
     pub fn new<K: Into<String>, T: Serialize>(key: K, data: &T) -> Result<Self> {
         let data = serde_json::to_value(data).context("serializing actor message data")?;
-        Ok(Self { key: key.into(), data })
+        Ok(Self {
+            key: key.into(),
+            data,
+            resync: false,
+            seq: None,
+            deadline: None,
+        })
+    }
+
+    /// Mark the message as a resync.
+    pub fn as_resync(mut self) -> Self {
+        self.resync = true;
+        self
+    }
+
+    /// Number the message as update `seq` of its key.
+    pub fn with_seq(mut self, seq: SequenceNumber) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    /// Give the message a deadline, in unix milliseconds. The sender's [`Actor::handle_timeout`]
+    /// runs if it is not acked by then.
+    ///
+    /// [`Actor::handle_timeout`]: crate::Actor::handle_timeout
+    pub fn with_deadline(mut self, deadline: u64) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Give the message a deadline `timeout` from now, see [`Self::with_deadline`].
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(now_in_millis().saturating_add(timeout.as_millis() as u64))
+    }
+
+    /// Whether the deadline of the message has passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now_in_millis())
+    }
+
+    /// Time left until the deadline of the message, `None` if it has none.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| Duration::from_millis(deadline.saturating_sub(now_in_millis())))
     }
 
     /// Deserialize the JSON value of `self.data` into a rust type.
@@ -50,6 +118,12 @@ impl ActorMessage {
     }
 }
 
+fn now_in_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
 pub(crate) fn actor_msg_to_swbus_msg(
     actor_message: &ActorMessage,
     destination: ServicePath,
@@ -59,7 +133,7 @@ pub(crate) fn actor_msg_to_swbus_msg(
         .outgoing_message_to_swbus_message(OutgoingMessage {
             destination,
             body: MessageBody::Request {
-                payload: actor_message.serialize(),
+                payload: actor_message.serialize().into(),
             },
         })
         .1
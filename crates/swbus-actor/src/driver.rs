@@ -1,11 +1,12 @@
-use crate::{state::ActorStateDump, Actor, ActorMessage, Context, State};
+use crate::supervisor::{is_fatal, LastState};
+use crate::{metrics, snapshot, state::ActorStateDump, watchdog::CheckIn, Actor, ActorMessage, Context, Result, State};
 use std::collections::HashMap;
 use std::sync::Arc;
 use swbus_edge::{
     simple_client::{IncomingMessage, MessageBody, MessageResponseBody, OutgoingMessage, SimpleSwbusEdgeClient},
     swbus_proto::swbus::{ManagementRequestType, ServicePath, SwbusErrorCode},
 };
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 /// An actor and the support structures needed to run it.
 pub(crate) struct ActorDriver<A> {
@@ -13,10 +14,15 @@ pub(crate) struct ActorDriver<A> {
     state: State,
     swbus_edge: Arc<SimpleSwbusEdgeClient>,
     context: Context,
+    check_in: CheckIn,
+    /// Times the actor was restarted by its supervisor
+    restarts: u32,
+    /// Where the incoming state table is kept for the next instance, if it replays the last state
+    last_state: Option<LastState>,
 }
 
 impl<A: Actor> ActorDriver<A> {
-    pub(crate) fn new(actor: A, swbus_edge: SimpleSwbusEdgeClient) -> Self {
+    pub(crate) fn new(actor: A, swbus_edge: SimpleSwbusEdgeClient, check_in: CheckIn) -> Self {
         let swbus_edge = Arc::new(swbus_edge);
         let edge_runtime = swbus_edge.get_edge_runtime().clone();
         ActorDriver {
@@ -24,21 +30,66 @@ impl<A: Actor> ActorDriver<A> {
             state: State::new(swbus_edge.clone()),
             swbus_edge,
             context: Context::new(edge_runtime),
+            check_in,
+            restarts: 0,
+            last_state: None,
         }
     }
 
+    pub(crate) fn with_restarts(mut self, restarts: u32) -> Self {
+        self.restarts = restarts;
+        self
+    }
+
+    pub(crate) fn with_last_state(mut self, last_state: Option<LastState>) -> Self {
+        self.last_state = last_state;
+        self
+    }
+
     /// Run the actor's main loop
     pub(crate) async fn run(mut self) {
+        // The state of the previous instance goes in before init, so a snapshot doesn't replace it
+        let replayed = match &self.last_state {
+            Some(last_state) => last_state.lock().unwrap().clone(),
+            None => HashMap::new(),
+        };
+        let mut replayed_keys: Vec<String> = replayed.keys().cloned().collect();
+        replayed_keys.sort();
+        self.state.incoming.restore(replayed);
+
+        self.check_in.busy("init");
         self.actor.init(&mut self.state).await.unwrap();
         self.state.internal.commit_changes().await;
         self.state.outgoing.send_queued_messages().await;
+        self.check_in.check_in();
+
+        if !replayed_keys.is_empty() {
+            info!(
+                "actor {} replaying {} incoming state entries",
+                self.swbus_edge.get_service_path().to_longest_path(),
+                replayed_keys.len()
+            );
+        }
+        for key in replayed_keys {
+            self.handle_actor_message(&key).await;
+            self.check_in.check_in();
+        }
 
         loop {
             tokio::select! {
-                _ = self.state.outgoing.drive_resend_loop() => unreachable!("drive_resend_loop never returns"),
+                expired = self.state.outgoing.drive_resend_loop() => {
+                    for msg in expired {
+                        self.check_in.busy(format!("timeout of {}", msg.key));
+                        self.handle_timeout(&msg).await;
+                        self.check_in.check_in();
+                    }
+                }
+                _ = snapshot::next_due(self.state.snapshots.as_ref()) => self.save_snapshot().await,
                 maybe_msg = self.swbus_edge.recv() => {
                     if let Some(maybe_msg) = maybe_msg {
+                        self.check_in.busy(format!("swbus message {}", maybe_msg.id));
                         self.handle_swbus_message(maybe_msg).await;
+                        self.check_in.check_in();
                     } else {
                         //recv returns None when the message is not for actors
                         continue;
@@ -46,6 +97,9 @@ impl<A: Actor> ActorDriver<A> {
                 }
             }
             if self.context.stopped {
+                if let Some(snapshots) = &mut self.state.snapshots {
+                    snapshots.delete().await;
+                }
                 info!(
                     "actor {} terminated",
                     self.swbus_edge.get_service_path().to_longest_path()
@@ -54,8 +108,15 @@ impl<A: Actor> ActorDriver<A> {
             }
         }
     }
-    #[instrument(name="handle_swbus_message", level="debug", skip_all, fields(actor=self.swbus_edge.get_service_path().to_longest_path(), id=%msg.id))]
+    /// Handles `msg` in the scope of its trace id, which the responses and the messages the actor sends
+    /// while handling it inherit.
+    #[instrument(name="handle_swbus_message", level="debug", skip_all, fields(actor=self.swbus_edge.get_service_path().to_longest_path(), id=%msg.id, trace_id=%msg.trace_id))]
     async fn handle_swbus_message(&mut self, msg: IncomingMessage) {
+        let trace_id = msg.trace_id.clone();
+        swbus_edge::trace::scope(trace_id, self.handle_traced_swbus_message(msg)).await
+    }
+
+    async fn handle_traced_swbus_message(&mut self, msg: IncomingMessage) {
         debug!("received message: {msg:?}");
         let IncomingMessage { id, source, body, .. } = msg;
         match body {
@@ -87,8 +148,10 @@ impl<A: Actor> ActorDriver<A> {
                     .await
                     .expect("failed to send swbus message");
 
-                if let Ok(key) = res {
-                    self.handle_actor_message(&key).await;
+                match res {
+                    Ok(Some(key)) => self.handle_actor_message(&key).await,
+                    Ok(None) => debug!("dropped a redelivered quarantined message, a stale update or a late one"),
+                    Err(_) => {}
                 }
             }
             MessageBody::Response {
@@ -106,24 +169,63 @@ impl<A: Actor> ActorDriver<A> {
         }
     }
 
+    async fn save_snapshot(&mut self) {
+        if let Some(snapshots) = &mut self.state.snapshots {
+            snapshots
+                .save(&self.state.incoming.dump_state(), self.actor.snapshot())
+                .await;
+        }
+    }
+
     /// Handle an actor message in the incoming state table, triggering `Actor::handle_message`.
     async fn handle_actor_message(&mut self, key: &str) {
+        self.check_in.busy(format!("message {key}"));
         let res = self.actor.handle_message(&mut self.state, key, &mut self.context).await;
-        let (error_code, error_message) = match res {
+        let (error_code, error_message) = self.finish_callback(res).await;
+        let result = if error_code == SwbusErrorCode::Ok {
+            "ok"
+        } else {
+            "failed"
+        };
+        metrics::MESSAGES_HANDLED
+            .with_label_values(&[&self.swbus_edge.get_service_path().to_longest_path(), result])
+            .inc();
+        info!("message handled by actor: {error_code:?} {error_message}");
+        self.state.incoming.request_handled(key, error_code, &error_message);
+        if let Some(last_state) = &self.last_state {
+            *last_state.lock().unwrap() = self.state.incoming.dump_state();
+        }
+    }
+
+    /// Handle a message the actor sent that was not acked before its deadline, triggering
+    /// `Actor::handle_timeout`.
+    async fn handle_timeout(&mut self, msg: &ActorMessage) {
+        let actor = self.swbus_edge.get_service_path().to_longest_path();
+        warn!("actor {actor} got no ack of '{}' before its deadline", msg.key);
+        metrics::REQUEST_TIMEOUTS.with_label_values(&[&actor]).inc();
+        let res = self.actor.handle_timeout(&mut self.state, msg, &mut self.context).await;
+        self.finish_callback(res).await;
+    }
+
+    /// Commit the state changes of an actor callback that succeeded, or drop them if it failed.
+    async fn finish_callback(&mut self, res: Result<()>) -> (SwbusErrorCode, String) {
+        match res {
             Ok(()) => {
                 self.state.internal.commit_changes().await;
                 self.state.outgoing.send_queued_messages().await;
                 (SwbusErrorCode::Ok, String::new())
             }
             Err(e) => {
-                error!("Actor failed to handle message: {e:#}");
+                error!("Actor callback failed: {e:#}");
                 self.state.internal.drop_changes();
                 self.state.outgoing.drop_queued_messages();
+                if is_fatal(&e) {
+                    // Crash the actor for its supervisor to handle
+                    panic!("{e:#}");
+                }
                 (SwbusErrorCode::Fail, format!("{e:#}"))
             }
-        };
-        info!("message handled by actor: {error_code:?} {error_message}");
-        self.state.incoming.request_handled(key, error_code, &error_message);
+        }
     }
 
     async fn handle_management_request(
@@ -170,6 +272,9 @@ impl<A: Actor> ActorDriver<A> {
     }
 
     fn dump_state(&self) -> ActorStateDump {
-        self.state.dump_state()
+        ActorStateDump {
+            restarts: self.restarts,
+            ..self.state.dump_state()
+        }
     }
 }
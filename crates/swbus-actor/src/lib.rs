@@ -1,14 +1,21 @@
 mod driver;
+mod metrics;
 
 pub mod actor_message;
 pub mod runtime;
+pub mod snapshot;
 pub mod state;
+pub mod supervisor;
+pub mod watchdog;
 
 use std::future::Future;
 
 pub use actor_message::ActorMessage;
 pub use anyhow::{Error, Result};
-pub use runtime::{get_global_runtime, set_global_runtime, set_global_runtime_if_unset, spawn, ActorRuntime};
+pub use runtime::{
+    get_global_runtime, set_global_runtime, set_global_runtime_if_unset, spawn, spawn_supervised, spawn_with_policy,
+    ActorRuntime,
+};
 pub use serde_json as json;
 pub use state::State;
 use std::sync::Arc;
@@ -25,16 +32,41 @@ pub trait Actor: Send + 'static {
         async { Ok(()) }
     }
 
+    /// State of the actor to keep in its snapshots besides the incoming state table, see
+    /// [`State::enable_snapshots`]. It is handed back by `enable_snapshots` when the actor restarts.
+    ///
+    /// The default implementation keeps nothing.
+    fn snapshot(&self) -> Option<json::Value> {
+        None
+    }
+
     /// Callback run upon receipt of an [`ActorMessage`].
     ///
     /// If this returns `Err(..)`, state changes are not committed.
     /// Outgoing state messages are not sent, and internal state changes are rolled back.
+    /// If the error is a [`supervisor::FatalError`], the actor crashes, see [`supervisor`].
     fn handle_message(
         &mut self,
         state: &mut State,
         key: &str,
         context: &mut Context,
     ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Callback run when a message the actor sent with a deadline, see [`ActorMessage::with_deadline`],
+    /// was not acked by then. The message is no longer resent, and a response arriving later is dropped.
+    ///
+    /// Errors are handled as for [`Actor::handle_message`].
+    ///
+    /// The default implementation does nothing.
+    fn handle_timeout(
+        &mut self,
+        state: &mut State,
+        msg: &ActorMessage,
+        context: &mut Context,
+    ) -> impl Future<Output = Result<()>> + Send {
+        _ = (state, msg, context);
+        async { Ok(()) }
+    }
 }
 
 pub struct Context {
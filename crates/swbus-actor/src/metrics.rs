@@ -0,0 +1,30 @@
+//! Metrics of the actors, see [`sonic_common::metrics`].
+use sonic_common::metrics::{CounterVec, Gauge, GaugeVec};
+use std::sync::{Arc, LazyLock};
+
+pub(crate) static MESSAGES_HANDLED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_actor_messages_handled_total",
+        "Messages handled by an actor, by its service path and whether it handled them successfully.",
+        &["actor", "result"],
+    )
+});
+
+pub(crate) static REQUEST_TIMEOUTS: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_actor_request_timeouts_total",
+        "Messages an actor sent with a deadline that were not acked in time, by its service path.",
+        &["actor"],
+    )
+});
+
+pub(crate) static RESTARTS: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_actor_restarts_total",
+        "Times an actor was restarted after crashing, by its service path.",
+        &["actor"],
+    )
+});
+
+pub(crate) static ACTORS: LazyLock<Arc<Gauge>> =
+    LazyLock::new(|| GaugeVec::register("swbus_actors", "Actors running.", &[]).with_label_values(&[]));
@@ -1,28 +1,153 @@
-use crate::{driver::ActorDriver, Actor};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use swbus_edge::{simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::ServicePath, SwbusEdgeRuntime};
+use crate::supervisor::{ActorHealthEvent, Escalations, HealthHandler, RestartFn, RestartPolicy, Supervisor};
+use crate::watchdog::WatchdogPolicy;
+use crate::{metrics, Actor, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use swbus_edge::{
+    mailbox::MailboxConfig, simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::ServicePath, SwbusEdgeRuntime,
+};
 use tokio::task::JoinHandle;
 use tracing::info;
 
 /// Global structures shared by all actors.
 pub struct ActorRuntime {
     swbus_edge: Arc<SwbusEdgeRuntime>,
+    /// Service paths of the actors that are currently running on this runtime.
+    actors: Arc<Mutex<BTreeSet<ServicePath>>>,
+    health_handler: Option<HealthHandler>,
+    watchdog: Option<WatchdogPolicy>,
+    escalations: Escalations,
+    /// Mailboxes of the actors by resource type, for the types that don't use the default one
+    mailboxes: HashMap<String, MailboxConfig>,
 }
 
 impl ActorRuntime {
     pub fn new(swbus_edge: Arc<SwbusEdgeRuntime>) -> Self {
-        Self { swbus_edge }
+        Self {
+            swbus_edge,
+            actors: Arc::new(Mutex::new(BTreeSet::new())),
+            health_handler: None,
+            watchdog: None,
+            escalations: Escalations::default(),
+            mailboxes: HashMap::new(),
+        }
+    }
+
+    /// Call `handler` when an actor spawned after this call crashes.
+    pub fn set_health_handler(&mut self, handler: impl Fn(&ActorHealthEvent) + Send + Sync + 'static) {
+        self.health_handler = Some(Arc::new(handler));
+    }
+
+    /// Watch the actors spawned after this call with `policy`, reporting the stuck ones to the health
+    /// handler.
+    pub fn set_watchdog(&mut self, policy: WatchdogPolicy) {
+        self.watchdog = Some(policy);
+    }
+
+    /// Receive the messages to the actors of `resource_type` spawned after this call in a mailbox of the
+    /// given size and overflow policy, see [`swbus_edge::mailbox`]. Actors fed by bridges or
+    /// [`ActorMessage`](crate::ActorMessage)s need [`OverflowPolicy::Block`](swbus_edge::mailbox::OverflowPolicy::Block)
+    /// or [`OverflowPolicy::Reject`](swbus_edge::mailbox::OverflowPolicy::Reject), since a dropped update
+    /// is never resent, while a rejected one is.
+    pub fn set_mailbox(&mut self, resource_type: &str, mailbox: MailboxConfig) {
+        self.mailboxes.insert(resource_type.to_string(), mailbox);
     }
 
     /// Spawn an actor on this runtime, reachable by sending Swbus requests to `addr`.
+    ///
+    /// If the actor panics, it stays down and the crash is reported to the health handler.
     pub fn spawn<A: Actor>(&self, actor: A, resource_type: &str, resource_id: &str) -> JoinHandle<()> {
+        self.spawn_with_restart(actor, None, RestartPolicy::default(), None, resource_type, resource_id)
+    }
+
+    /// Spawn an actor like [`ActorRuntime::spawn`], and replace it with a new instance from `restart`
+    /// whenever it panics.
+    pub fn spawn_supervised<A, F>(&self, actor: A, restart: F, resource_type: &str, resource_id: &str) -> JoinHandle<()>
+    where
+        A: Actor,
+        F: FnMut() -> Result<A> + Send + 'static,
+    {
+        self.spawn_with_restart(
+            actor,
+            Some(Box::new(restart)),
+            RestartPolicy::default(),
+            None,
+            resource_type,
+            resource_id,
+        )
+    }
+
+    /// Spawn a supervised actor like [`ActorRuntime::spawn_supervised`], handling its crashes as `policy`
+    /// says. With a `parent`, the service path of another supervised actor, the actor is a child of the
+    /// parent that [`RestartPolicy::Escalate`] crashes in turn.
+    pub fn spawn_with_policy<A, F>(
+        &self,
+        actor: A,
+        restart: F,
+        policy: RestartPolicy,
+        parent: Option<ServicePath>,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> JoinHandle<()>
+    where
+        A: Actor,
+        F: FnMut() -> Result<A> + Send + 'static,
+    {
+        self.spawn_with_restart(
+            actor,
+            Some(Box::new(restart)),
+            policy,
+            parent,
+            resource_type,
+            resource_id,
+        )
+    }
+
+    fn spawn_with_restart<A: Actor>(
+        &self,
+        actor: A,
+        restart: Option<RestartFn<A>>,
+        policy: RestartPolicy,
+        parent: Option<ServicePath>,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> JoinHandle<()> {
         // TODO: Add privacy option
         let sp = self.sp(resource_type, resource_id);
         info!("Spawning actor at {}", sp.to_longest_path());
-        let swbus_client = SimpleSwbusEdgeClient::new(self.swbus_edge.clone(), sp, true, false);
-        let actor_driver = ActorDriver::new(actor, swbus_client);
+        let mailbox = self.mailboxes.get(resource_type).copied().unwrap_or_default();
+        let swbus_client =
+            SimpleSwbusEdgeClient::with_mailbox(self.swbus_edge.clone(), sp.clone(), true, false, mailbox);
+        let (escalated_tx, escalated_rx) = tokio::sync::mpsc::unbounded_channel();
+        let supervisor = Supervisor {
+            swbus_edge: self.swbus_edge.clone(),
+            sp: sp.clone(),
+            mailbox,
+            restart,
+            policy,
+            parent,
+            escalations: self.escalations.clone(),
+            escalated_rx,
+            health_handler: self.health_handler.clone(),
+            watchdog: self.watchdog.clone(),
+        };
 
-        tokio::task::spawn(actor_driver.run())
+        self.actors.lock().unwrap().insert(sp.clone());
+        self.escalations.register(&sp, escalated_tx);
+        metrics::ACTORS.inc();
+        let actors = self.actors.clone();
+        let escalations = self.escalations.clone();
+        tokio::task::spawn(async move {
+            supervisor.run(actor, swbus_client).await;
+            actors.lock().unwrap().remove(&sp);
+            escalations.unregister(&sp);
+            metrics::ACTORS.dec();
+        })
+    }
+
+    /// Service paths of all running actors, in sorted order.
+    pub fn actors(&self) -> Vec<ServicePath> {
+        self.actors.lock().unwrap().iter().cloned().collect()
     }
 
     pub fn get_swbus_edge(&self) -> Arc<SwbusEdgeRuntime> {
@@ -68,3 +193,43 @@ pub fn spawn<A: Actor>(actor: A, resource_type: &str, resource_id: &str) -> Join
         .expect("You must call actor::set_global_runtime() before calling actor::spawn()")
         .spawn(actor, resource_type, resource_id)
 }
+
+/// Spawn a supervised actor on the global runtime. See [`ActorRuntime::spawn_supervised`].
+///
+/// Panics if called before [`set_global_runtime`] is called.
+pub fn spawn_supervised<A, F>(actor: A, restart: F, resource_type: &str, resource_id: &str) -> JoinHandle<()>
+where
+    A: Actor,
+    F: FnMut() -> Result<A> + Send + 'static,
+{
+    GLOBAL_RUNTIME
+        .read()
+        .unwrap()
+        .as_ref()
+        .expect("You must call actor::set_global_runtime() before calling actor::spawn_supervised()")
+        .spawn_supervised(actor, restart, resource_type, resource_id)
+}
+
+/// Spawn a supervised actor with a restart policy on the global runtime. See
+/// [`ActorRuntime::spawn_with_policy`].
+///
+/// Panics if called before [`set_global_runtime`] is called.
+pub fn spawn_with_policy<A, F>(
+    actor: A,
+    restart: F,
+    policy: RestartPolicy,
+    parent: Option<ServicePath>,
+    resource_type: &str,
+    resource_id: &str,
+) -> JoinHandle<()>
+where
+    A: Actor,
+    F: FnMut() -> Result<A> + Send + 'static,
+{
+    GLOBAL_RUNTIME
+        .read()
+        .unwrap()
+        .as_ref()
+        .expect("You must call actor::set_global_runtime() before calling actor::spawn_with_policy()")
+        .spawn_with_policy(actor, restart, policy, parent, resource_type, resource_id)
+}
@@ -0,0 +1,218 @@
+//! Snapshots of actor state
+//!
+//! An actor that restarts, with its daemon or after a crash, starts with an empty incoming state table
+//! and decides on partial state until the other actors and bridges have sent theirs again. An actor that
+//! calls [`State::enable_snapshots`](crate::State::enable_snapshots) in `init` has its incoming state
+//! table, and what [`Actor::snapshot`](crate::Actor::snapshot) returns, saved to a table every
+//! [`SNAPSHOT_INTERVAL`] if they changed. The next instance restores the incoming state table from the
+//! snapshot, and the messages it receives replace the restored ones as they arrive. Snapshots older than
+//! [`MAX_SNAPSHOT_AGE`] are ignored, and the snapshot is deleted when the actor stops.
+use crate::state::incoming::IncomingTableEntry;
+use crate::state::internal::InternalTable;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use swss_common::{CxxString, FieldValues};
+use tokio::time::Instant;
+use tracing::{debug, error, warn};
+
+/// How often snapshots are saved.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Age after which a snapshot is too old to restore.
+pub const MAX_SNAPSHOT_AGE: Duration = Duration::from_secs(600);
+
+const INCOMING_FIELD: &str = "incoming";
+const ACTOR_FIELD: &str = "actor";
+const SAVED_TIME_FIELD: &str = "saved_time_in_ms";
+
+/// What a snapshot restores.
+pub(crate) struct Restored {
+    pub(crate) incoming: HashMap<String, IncomingTableEntry>,
+    pub(crate) actor: Option<Value>,
+}
+
+/// Where the snapshots of an actor are saved.
+pub(crate) struct Snapshots {
+    table: Box<dyn InternalTable>,
+    key: String,
+    next_save: Instant,
+    /// The incoming state table and actor state of the last snapshot saved, to skip unchanged ones
+    last_saved: Option<(String, String)>,
+}
+
+impl Snapshots {
+    pub(crate) fn new(table: Box<dyn InternalTable>, key: String) -> Self {
+        Snapshots {
+            table,
+            key,
+            next_save: Instant::now() + SNAPSHOT_INTERVAL,
+            last_saved: None,
+        }
+    }
+
+    /// Read the snapshot saved by a previous instance, if there is a recent enough one.
+    pub(crate) async fn restore(&mut self) -> Result<Option<Restored>> {
+        let Some(fvs) = self.table.get(&self.key).await? else {
+            return Ok(None);
+        };
+        let field = |name: &str| -> Result<String> {
+            Ok(fvs
+                .get(name)
+                .with_context(|| format!("snapshot has no field {name}"))?
+                .to_str()?
+                .to_string())
+        };
+        let saved_time: u64 = field(SAVED_TIME_FIELD)?.parse()?;
+        let age = Duration::from_millis(now_in_millis().saturating_sub(saved_time));
+        if age > MAX_SNAPSHOT_AGE {
+            warn!("Ignoring snapshot {} saved {age:?} ago", self.key);
+            return Ok(None);
+        }
+
+        let incoming = field(INCOMING_FIELD)?;
+        let actor = field(ACTOR_FIELD)?;
+        let restored = Restored {
+            incoming: serde_json::from_str(&incoming)?,
+            actor: serde_json::from_str(&actor)?,
+        };
+        self.last_saved = Some((incoming, actor));
+        Ok(Some(restored))
+    }
+
+    pub(crate) async fn save(&mut self, incoming: &HashMap<String, IncomingTableEntry>, actor: Option<Value>) {
+        self.next_save = Instant::now() + SNAPSHOT_INTERVAL;
+        let incoming = serde_json::to_string(incoming).expect("incoming state table is serializable");
+        let actor = serde_json::to_string(&actor).expect("actor state is serializable");
+        if self
+            .last_saved
+            .as_ref()
+            .is_some_and(|(last_incoming, last_actor)| *last_incoming == incoming && *last_actor == actor)
+        {
+            return;
+        }
+
+        let fvs = FieldValues::from([
+            (INCOMING_FIELD.to_string(), CxxString::new(&incoming)),
+            (ACTOR_FIELD.to_string(), CxxString::new(&actor)),
+            (
+                SAVED_TIME_FIELD.to_string(),
+                CxxString::new(now_in_millis().to_string()),
+            ),
+        ]);
+        match self.table.set(&self.key, fvs).await {
+            Ok(()) => {
+                debug!("Saved snapshot {}", self.key);
+                self.last_saved = Some((incoming, actor));
+            }
+            Err(e) => error!("Failed to save snapshot {}: {e:#}", self.key),
+        }
+    }
+
+    /// Delete the snapshot, so an actor started later with the same key starts over.
+    pub(crate) async fn delete(&mut self) {
+        if let Err(e) = self.table.del(&self.key).await {
+            error!("Failed to delete snapshot {}: {e:#}", self.key);
+        }
+    }
+}
+
+/// Wait until the next snapshot is due, forever if snapshots are not enabled.
+pub(crate) async fn next_due(snapshots: Option<&Snapshots>) {
+    match snapshots {
+        Some(snapshots) => tokio::time::sleep_until(snapshots.next_save).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn now_in_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::internal::BoxFuture;
+    use crate::ActorMessage;
+    use std::sync::{Arc, Mutex};
+    use swbus_edge::swbus_proto::swbus::ServicePath;
+
+    #[derive(Debug, Default, Clone)]
+    struct MemoryTable(Arc<Mutex<HashMap<String, FieldValues>>>);
+
+    impl InternalTable for MemoryTable {
+        fn table_name(&self) -> String {
+            "SNAPSHOT".to_string()
+        }
+
+        fn get<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, Option<FieldValues>> {
+            Box::pin(async move { Ok(self.0.lock().unwrap().get(key).cloned()) })
+        }
+
+        fn set<'a>(&'a mut self, key: &'a str, fvs: FieldValues) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                self.0.lock().unwrap().insert(key.to_string(), fvs);
+                Ok(())
+            })
+        }
+
+        fn del<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                self.0.lock().unwrap().remove(key);
+                Ok(())
+            })
+        }
+    }
+
+    fn entry(data: &str) -> IncomingTableEntry {
+        IncomingTableEntry {
+            msg: ActorMessage::new("state", &data).unwrap(),
+            source: ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/source/0").unwrap(),
+            request_id: 1,
+            version: 1,
+            created_time: 0,
+            last_updated_time: 0,
+            response: "Ok".to_string(),
+            acked: true,
+            failures: 0,
+            missed: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_save_restore_and_delete() {
+        let table = MemoryTable::default();
+        let incoming = HashMap::from([("state".to_string(), entry("up"))]);
+        let mut snapshots = Snapshots::new(Box::new(table.clone()), "actor|0".to_string());
+        assert!(snapshots.restore().await.unwrap().is_none());
+        snapshots.save(&incoming, Some(Value::from("active"))).await;
+
+        let mut restarted = Snapshots::new(Box::new(table.clone()), "actor|0".to_string());
+        let restored = restarted.restore().await.unwrap().unwrap();
+        assert_eq!(restored.incoming, incoming);
+        assert_eq!(restored.actor, Some(Value::from("active")));
+
+        restarted.delete().await;
+        assert!(table.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn old_snapshots_are_not_restored() {
+        let table = MemoryTable::default();
+        let mut snapshots = Snapshots::new(Box::new(table.clone()), "actor|0".to_string());
+        snapshots.save(&HashMap::new(), None).await;
+        let saved_time = now_in_millis() - MAX_SNAPSHOT_AGE.as_millis() as u64 - 1;
+        table
+            .0
+            .lock()
+            .unwrap()
+            .get_mut("actor|0")
+            .unwrap()
+            .insert(SAVED_TIME_FIELD.to_string(), CxxString::new(saved_time.to_string()));
+
+        assert!(snapshots.restore().await.unwrap().is_none());
+    }
+}
@@ -2,19 +2,23 @@ pub mod incoming;
 pub mod internal;
 pub mod outgoing;
 
-use incoming::{Incoming, IncomingTableEntry};
-use internal::{Internal, InternalTableData};
+use crate::snapshot::Snapshots;
+use incoming::{DeadLetter, Incoming, IncomingTableEntry};
+use internal::{Internal, InternalTable, InternalTableData};
 use outgoing::{Outgoing, OutgoingStateData};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 use swbus_edge::simple_client::SimpleSwbusEdgeClient;
+use tracing::{info, warn};
 
 /// Actor state tables.
 pub struct State {
     pub(crate) internal: Internal,
     pub(crate) incoming: Incoming,
     pub(crate) outgoing: Outgoing,
+    pub(crate) snapshots: Option<Snapshots>,
 }
 
 impl State {
@@ -23,6 +27,7 @@ impl State {
             internal: Internal::new(),
             incoming: Incoming::new(swbus_edge.clone()),
             outgoing: Outgoing::new(swbus_edge),
+            snapshots: None,
         }
     }
 
@@ -63,11 +68,42 @@ impl State {
         &mut self.outgoing
     }
 
+    /// Save snapshots of the incoming state table and [`Actor::snapshot`](crate::Actor::snapshot) to
+    /// `swss_key` of `swss_table`, see [`crate::snapshot`]. The incoming state table is restored from the
+    /// snapshot a previous instance of the actor saved there, and the actor state of that snapshot is
+    /// returned for the actor to restore. A snapshot that can't be read is ignored.
+    pub async fn enable_snapshots(
+        &mut self,
+        swss_table: impl InternalTable,
+        swss_key: impl Into<String>,
+    ) -> Option<Value> {
+        let mut snapshots = Snapshots::new(Box::new(swss_table), swss_key.into());
+        let restored = snapshots.restore().await;
+        self.snapshots = Some(snapshots);
+        match restored {
+            Ok(Some(restored)) => {
+                info!(
+                    "Restored {} incoming state entries from snapshot",
+                    restored.incoming.len()
+                );
+                self.incoming.restore(restored.incoming);
+                restored.actor
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Ignoring snapshot that can't be restored: {e:#}");
+                None
+            }
+        }
+    }
+
     pub fn dump_state(&self) -> ActorStateDump {
         ActorStateDump {
             incoming: self.incoming.dump_state(),
             internal: self.internal.dump_state(),
             outgoing: self.outgoing.dump_state(),
+            dead_letters: self.incoming.dump_dead_letters(),
+            restarts: 0,
         }
     }
 }
@@ -85,4 +121,9 @@ pub struct ActorStateDump {
     pub incoming: HashMap<String, IncomingTableEntry>,
     pub internal: HashMap<String, InternalTableData>,
     pub outgoing: OutgoingStateData,
+    #[serde(default)]
+    pub dead_letters: HashMap<String, DeadLetter>,
+    /// Times the actor was restarted by its supervisor
+    #[serde(default)]
+    pub restarts: u32,
 }
@@ -7,11 +7,17 @@ use swbus_edge::{
     simple_client::{MessageBody, MessageId, OutgoingMessage, SimpleSwbusEdgeClient},
     swbus_proto::swbus::{ServicePath, SwbusErrorCode},
 };
+use tracing::{debug, error};
+
+/// How many times in a row the actor may fail to handle the same message before it is quarantined.
+pub const MAX_HANDLE_FAILURES: u32 = 3;
 
 /// Incoming state table - messages from other actors identified by a string key.
 pub struct Incoming {
     swbus_edge: Arc<SimpleSwbusEdgeClient>,
     table: HashMap<String, IncomingTableEntry>,
+    /// Messages taken out of the table because the actor kept failing to handle them.
+    dead_letters: HashMap<String, DeadLetter>,
 }
 
 impl Incoming {
@@ -29,9 +35,14 @@ impl Incoming {
         Self {
             swbus_edge,
             table: HashMap::new(),
+            dead_letters: HashMap::new(),
         }
     }
 
+    pub fn get_dead_letter(&self, key: &str) -> Option<&DeadLetter> {
+        self.dead_letters.get(key)
+    }
+
     pub fn get_by_prefix(&self, prefix: &str) -> Vec<&IncomingTableEntry> {
         // ideally we should use a radix trie here, but didn't find a suitable and stable
         // implementation in Rust. This is a simple and inefficient implementation but we don't
@@ -55,17 +66,47 @@ impl Incoming {
 
     /// Extracts the ActorMessage from a request and inserts it into the table,
     /// and returns a clone of the key to pass to the actor callback.
+    ///
+    /// Returns `None` if the message is a redelivery of a quarantined message, which the actor
+    /// must not handle again. A different message to the same key releases the quarantine.
+    /// Also returns `None` for a numbered update older than the one in the table, which arrived
+    /// out of order and must not replace it, and for a message that arrived after its deadline, which
+    /// its sender has given up on.
     pub(crate) async fn handle_request(
         &mut self,
         id: MessageId,
         source: ServicePath,
         payload: &[u8],
-    ) -> Result<String> {
+    ) -> Result<Option<String>> {
         match ActorMessage::deserialize(payload) {
             Ok(actor_msg) => {
                 let key = actor_msg.key.clone();
+                if actor_msg.is_expired() {
+                    debug!(
+                        "dropped '{key}' from {} that arrived after its deadline",
+                        source.to_longest_path()
+                    );
+                    return Ok(None);
+                }
+                if let Some(dead_letter) = self.dead_letters.get_mut(&key) {
+                    if dead_letter.entry.msg == actor_msg {
+                        dead_letter.redeliveries += 1;
+                        return Ok(None);
+                    }
+                    self.dead_letters.remove(&key);
+                }
+                if let (Some(seq), Some(entry)) = (actor_msg.seq, self.table.get(&key)) {
+                    if entry.msg.seq.is_some_and(|latest| seq < latest) {
+                        debug!(
+                            "dropped stale update {seq:?} of '{key}' from {}, already have {:?}",
+                            source.to_longest_path(),
+                            entry.msg.seq
+                        );
+                        return Ok(None);
+                    }
+                }
                 self.insert(actor_msg, source.clone(), id);
-                Ok(key)
+                Ok(Some(key))
             }
             Err(e) => {
                 self.swbus_edge
@@ -89,6 +130,9 @@ impl Incoming {
     /// Updates the incoming table with data about the response to the most recent request.
     /// Called by `ActorDriver` after the actor has handled the message.
     /// Returns data for the actor driver to route the response.
+    ///
+    /// A message the actor failed to handle [`MAX_HANDLE_FAILURES`] times in a row is moved to the
+    /// dead letters, so it no longer shows up in the table.
     pub(crate) fn request_handled(
         &mut self,
         key: &str,
@@ -97,12 +141,42 @@ impl Incoming {
     ) -> (MessageId, ServicePath) {
         let entry = self.table.get_mut(key).unwrap();
         entry.update_handled(error_code, error_message);
-        (entry.request_id, entry.source.clone())
+        let ret = (entry.request_id, entry.source.clone());
+
+        if entry.failures >= MAX_HANDLE_FAILURES {
+            let entry = self.table.remove(key).unwrap();
+            error!(
+                "Quarantined message '{key}' from {} after {} failures: {error_message}",
+                entry.source.to_longest_path(),
+                entry.failures
+            );
+            self.dead_letters.insert(
+                key.to_string(),
+                DeadLetter {
+                    entry,
+                    error: error_message.to_string(),
+                    quarantined_time: get_unix_time(),
+                    redeliveries: 0,
+                },
+            );
+        }
+        ret
+    }
+
+    /// Puts back entries restored from a snapshot, unless the key received a message already.
+    pub(crate) fn restore(&mut self, entries: HashMap<String, IncomingTableEntry>) {
+        for (key, entry) in entries {
+            self.table.entry(key).or_insert(entry);
+        }
     }
 
     pub(crate) fn dump_state(&self) -> HashMap<String, IncomingTableEntry> {
         self.table.clone()
     }
+
+    pub(crate) fn dump_dead_letters(&self) -> HashMap<String, DeadLetter> {
+        self.dead_letters.clone()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -123,6 +197,26 @@ pub struct IncomingTableEntry {
     pub response: String,
     /// Whether the latest request was successful or not.
     pub acked: bool,
+    /// How many times in a row the actor failed to handle the latest message.
+    #[serde(default)]
+    pub failures: u32,
+    /// How many numbered updates the sender sent between the previous message and the latest one
+    /// that never arrived.
+    #[serde(default)]
+    pub missed: u64,
+}
+
+/// A message the actor kept failing to handle, with what is needed to diagnose it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeadLetter {
+    /// The entry of the message in the incoming table when it was quarantined.
+    pub entry: IncomingTableEntry,
+    /// The error of the last failure.
+    pub error: String,
+    /// Time the message was quarantined, in unix seconds.
+    pub quarantined_time: u64,
+    /// How many times the same message was received again since, and dropped.
+    pub redeliveries: u64,
 }
 
 impl IncomingTableEntry {
@@ -137,11 +231,20 @@ impl IncomingTableEntry {
             last_updated_time: get_unix_time(),
             response: String::new(),
             acked: false,
+            failures: 0,
+            missed: 0,
         }
     }
 
     /// Update this entry with a newly received request.
     fn update_received(&mut self, msg: ActorMessage, source: ServicePath, request_id: MessageId) {
+        if msg != self.msg {
+            self.failures = 0;
+        }
+        self.missed = match (self.msg.seq, msg.seq) {
+            (Some(prev), Some(seq)) if prev.epoch == seq.epoch => seq.seq.saturating_sub(prev.seq + 1),
+            _ => 0,
+        };
         self.msg = msg;
         self.source = source;
         self.request_id = request_id;
@@ -154,9 +257,11 @@ impl IncomingTableEntry {
         if error_code == SwbusErrorCode::Ok {
             self.acked = true;
             self.response = String::from("Ok");
+            self.failures = 0;
         } else {
             self.acked = false;
             self.response = format!("{error_code:?} ({error_message})");
+            self.failures += 1;
         }
     }
 }
@@ -169,13 +274,16 @@ impl PartialEq for IncomingTableEntry {
             && self.msg == other.msg
             && self.response == other.response
             && self.acked == other.acked
+            && self.failures == other.failures
+            && self.missed == other.missed
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::actor_message::ActorMessage;
+    use crate::actor_message::{ActorMessage, SequenceNumber};
+    use std::time::Duration;
     use swbus_edge::swbus_proto::swbus::ServicePath;
     use swbus_edge::SwbusEdgeRuntime;
 
@@ -212,4 +320,156 @@ mod test {
         let regs = incoming.get_by_prefix("actor_registration-");
         assert_eq!(regs.len(), 2);
     }
+
+    #[tokio::test]
+    async fn test_poison_message_quarantine() {
+        let swbus_edge = Arc::new(SwbusEdgeRuntime::new(
+            "none".to_string(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0").unwrap(),
+        ));
+
+        let swbus_edge = Arc::new(SimpleSwbusEdgeClient::new(
+            swbus_edge.clone(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/0").unwrap(),
+            true,
+            false,
+        ));
+        let mut incoming = Incoming::new(swbus_edge.clone());
+        let source = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/source/0").unwrap();
+        let poison = ActorMessage::new("poison", &1).unwrap().serialize();
+
+        // A new message resets the count, so only failures on the same message add up
+        for (id, payload) in [(0, &poison), (1, &ActorMessage::new("poison", &2).unwrap().serialize())] {
+            let key = incoming.handle_request(id, source.clone(), payload).await.unwrap();
+            assert_eq!(key.as_deref(), Some("poison"));
+            incoming.request_handled("poison", SwbusErrorCode::Fail, "bad data");
+        }
+        assert_eq!(incoming.get_entry("poison").unwrap().failures, 1);
+
+        for id in 2..2 + MAX_HANDLE_FAILURES as u64 {
+            let key = incoming.handle_request(id, source.clone(), &poison).await.unwrap();
+            assert_eq!(key.as_deref(), Some("poison"));
+            incoming.request_handled("poison", SwbusErrorCode::Fail, "bad data");
+        }
+        assert!(incoming.get("poison").is_err());
+        let dead_letter = incoming.get_dead_letter("poison").unwrap();
+        assert_eq!(dead_letter.entry.failures, MAX_HANDLE_FAILURES);
+        assert_eq!(dead_letter.error, "bad data");
+
+        // Redeliveries are dropped, a different message releases the quarantine
+        let key = incoming.handle_request(10, source.clone(), &poison).await.unwrap();
+        assert_eq!(key, None);
+        assert_eq!(incoming.get_dead_letter("poison").unwrap().redeliveries, 1);
+
+        let fixed = ActorMessage::new("poison", &3).unwrap().serialize();
+        let key = incoming.handle_request(11, source.clone(), &fixed).await.unwrap();
+        assert_eq!(key.as_deref(), Some("poison"));
+        assert!(incoming.get_dead_letter("poison").is_none());
+        incoming.request_handled("poison", SwbusErrorCode::Ok, "");
+        assert_eq!(incoming.get_entry("poison").unwrap().failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sequenced_updates() {
+        let swbus_edge = Arc::new(SwbusEdgeRuntime::new(
+            "none".to_string(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0").unwrap(),
+        ));
+
+        let swbus_edge = Arc::new(SimpleSwbusEdgeClient::new(
+            swbus_edge.clone(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/0").unwrap(),
+            true,
+            false,
+        ));
+        let mut incoming = Incoming::new(swbus_edge.clone());
+        let source = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/source/0").unwrap();
+        let mut receive = async |id, epoch, seq| {
+            let payload = ActorMessage::new("state", &seq)
+                .unwrap()
+                .with_seq(SequenceNumber { epoch, seq })
+                .serialize();
+            incoming.handle_request(id, source.clone(), &payload).await.unwrap()
+        };
+        assert_eq!(receive(0, 1, 1).await.as_deref(), Some("state"));
+        assert_eq!(receive(1, 1, 2).await.as_deref(), Some("state"));
+
+        // Updates 3 and 4 are lost, 5 overtakes 3
+        assert_eq!(receive(2, 1, 5).await.as_deref(), Some("state"));
+        assert_eq!(receive(3, 1, 3).await, None);
+
+        // The same update again is not stale
+        assert_eq!(receive(4, 1, 5).await.as_deref(), Some("state"));
+        let entry = incoming.get_entry("state").unwrap();
+        assert_eq!(entry.msg.seq, Some(SequenceNumber { epoch: 1, seq: 5 }));
+        assert_eq!(entry.missed, 0);
+        assert_eq!(entry.version, 4);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_gap_and_restart() {
+        let swbus_edge = Arc::new(SwbusEdgeRuntime::new(
+            "none".to_string(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0").unwrap(),
+        ));
+
+        let swbus_edge = Arc::new(SimpleSwbusEdgeClient::new(
+            swbus_edge.clone(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/0").unwrap(),
+            true,
+            false,
+        ));
+        let mut incoming = Incoming::new(swbus_edge.clone());
+        let source = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/source/0").unwrap();
+        for (id, epoch, seq) in [(0, 1, 1), (1, 1, 4)] {
+            let payload = ActorMessage::new("state", &seq)
+                .unwrap()
+                .with_seq(SequenceNumber { epoch, seq })
+                .serialize();
+            incoming.handle_request(id, source.clone(), &payload).await.unwrap();
+        }
+        assert_eq!(incoming.get_entry("state").unwrap().missed, 2);
+
+        // The first update of a restarted sender is not a gap, and updates from before are stale
+        let mut receive = async |id, epoch, seq| {
+            let payload = ActorMessage::new("state", &seq)
+                .unwrap()
+                .with_seq(SequenceNumber { epoch, seq })
+                .serialize();
+            incoming.handle_request(id, source.clone(), &payload).await.unwrap()
+        };
+        assert_eq!(receive(2, 2, 1).await.as_deref(), Some("state"));
+        assert_eq!(receive(3, 1, 5).await, None);
+        assert_eq!(incoming.get_entry("state").unwrap().missed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_message() {
+        let swbus_edge = Arc::new(SwbusEdgeRuntime::new(
+            "none".to_string(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0").unwrap(),
+        ));
+
+        let swbus_edge = Arc::new(SimpleSwbusEdgeClient::new(
+            swbus_edge.clone(),
+            ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/test/0").unwrap(),
+            true,
+            false,
+        ));
+        let mut incoming = Incoming::new(swbus_edge.clone());
+        let source = ServicePath::from_string("unknown.unknown.unknown/hamgrd/0/source/0").unwrap();
+
+        let payload = ActorMessage::new("confirm", &1)
+            .unwrap()
+            .with_timeout(Duration::from_secs(60))
+            .serialize();
+        let key = incoming.handle_request(0, source.clone(), &payload).await.unwrap();
+        assert_eq!(key.as_deref(), Some("confirm"));
+
+        // A message that arrives too late doesn't replace the one in the table
+        let payload = ActorMessage::new("confirm", &2).unwrap().with_deadline(1).serialize();
+        let key = incoming.handle_request(1, source.clone(), &payload).await.unwrap();
+        assert_eq!(key, None);
+        assert_eq!(incoming.get("confirm").unwrap().deserialize_data::<u32>().unwrap(), 1);
+    }
 }
@@ -1,8 +1,40 @@
 use super::get_unix_time;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
 use swss_common::{FieldValues, Table};
 
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'a>>;
+
+/// Where an internal table entry is stored. Implemented for swss `Table`, and by other stores, e.g.
+/// an in-memory one to run actors without redis.
+pub trait InternalTable: Debug + Send + 'static {
+    fn table_name(&self) -> String;
+    fn get<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, Option<FieldValues>>;
+    fn set<'a>(&'a mut self, key: &'a str, fvs: FieldValues) -> BoxFuture<'a, ()>;
+    fn del<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, ()>;
+}
+
+impl InternalTable for Table {
+    fn table_name(&self) -> String {
+        self.get_name().to_string()
+    }
+
+    fn get<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, Option<FieldValues>> {
+        Box::pin(async move { Ok(self.get_async(key).await?) })
+    }
+
+    fn set<'a>(&'a mut self, key: &'a str, fvs: FieldValues) -> BoxFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_async(key, fvs).await?) })
+    }
+
+    fn del<'a>(&'a mut self, key: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move { Ok(self.del_async(key).await?) })
+    }
+}
+
 /// Internal state table - SWSS `Table`s.
 #[derive(Default, Debug)]
 pub struct Internal {
@@ -24,8 +56,8 @@ impl Internal {
             .fvs_mut()
     }
 
-    pub async fn add(&mut self, key: impl Into<String>, swss_table: Table, swss_key: impl Into<String>) {
-        let entry = InternalTableEntry::new(swss_table, swss_key.into()).await;
+    pub async fn add(&mut self, key: impl Into<String>, swss_table: impl InternalTable, swss_key: impl Into<String>) {
+        let entry = InternalTableEntry::new(Box::new(swss_table), swss_key.into()).await;
         self.table.insert(key.into(), entry);
     }
 
@@ -63,7 +95,7 @@ impl Internal {
 
 #[derive(Debug)]
 struct InternalTableEntry {
-    swss_table: Table,
+    swss_table: Box<dyn InternalTable>,
     data: InternalTableData,
 }
 
@@ -95,10 +127,10 @@ impl PartialEq for InternalTableData {
 }
 
 impl InternalTableEntry {
-    async fn new(mut swss_table: Table, swss_key: String) -> Self {
+    async fn new(mut swss_table: Box<dyn InternalTable>, swss_key: String) -> Self {
         // (re)hydrate from the table
         let fvs = swss_table
-            .get_async(&swss_key)
+            .get(&swss_key)
             .await
             .expect("Table::get threw an exception")
             .unwrap_or_default();
@@ -106,7 +138,7 @@ impl InternalTableEntry {
 
         Self {
             data: InternalTableData {
-                swss_table_name: swss_table.get_name().to_string(),
+                swss_table_name: swss_table.table_name(),
                 swss_key,
                 fvs,
                 mutated: false,
@@ -132,7 +164,7 @@ impl InternalTableEntry {
     async fn commit_changes(&mut self) {
         self.data.mutated = false;
         self.swss_table
-            .set_async(&self.data.swss_key, self.data.fvs.clone())
+            .set(&self.data.swss_key, self.data.fvs.clone())
             .await
             .expect("Table::set threw an exception");
         self.data.last_updated_time = Some(get_unix_time());
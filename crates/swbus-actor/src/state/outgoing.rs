@@ -10,6 +10,7 @@ use swbus_edge::{
     swbus_proto::swbus::{ServicePath, SwbusErrorCode, SwbusMessage},
 };
 use tokio::time::{interval, Interval};
+use tracing::debug;
 
 use super::get_unix_time;
 
@@ -24,6 +25,9 @@ pub struct Outgoing {
     /// Messages that will be sent if the actor logic succeeds, or dropped if it fails.
     queued_messages: Vec<UnackedMessage>,
 
+    /// Messages queued by [`Outgoing::send_after`] and their delay.
+    delayed_messages: Vec<(Duration, SwbusMessage)>,
+
     /// Record of sent messages, purely for GetActorState
     sent_messages: HashMap<String, SentMessageEntry>,
 }
@@ -44,6 +48,15 @@ impl Outgoing {
         });
     }
 
+    /// Enqueue a message to send after `delay`, if the actor callback succeeds, e.g. a timeout an actor
+    /// sends to itself.
+    ///
+    /// Unlike [`Outgoing::send`], the message is sent once and never resent.
+    pub fn send_after(&mut self, dest: ServicePath, msg: ActorMessage, delay: Duration) {
+        let swbus_message = actor_msg_to_swbus_msg(&msg, dest, &self.swbus_client);
+        self.delayed_messages.push((delay, swbus_message));
+    }
+
     pub(crate) fn new(swbus_client: Arc<SimpleSwbusEdgeClient>) -> Self {
         let mut resend_interval = interval(RESEND_TIME);
         resend_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
@@ -52,6 +65,7 @@ impl Outgoing {
             resend_interval,
             unacked_messages: HashMap::new(),
             queued_messages: Vec::new(),
+            delayed_messages: Vec::new(),
             sent_messages: HashMap::new(),
         }
     }
@@ -79,11 +93,21 @@ impl Outgoing {
             // Add to unacked messages/resend queue
             self.unacked_messages.insert(id, msg);
         }
+
+        for (delay, swbus_message) in self.delayed_messages.drain(..) {
+            let swbus_client = self.swbus_client.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                // the receiver may be gone by then, e.g. a timeout of an actor that has stopped
+                let _ = swbus_client.send_raw(swbus_message).await;
+            });
+        }
     }
 
     /// Actor logic failed, so don't send any messages.
     pub(crate) fn drop_queued_messages(&mut self) {
         self.queued_messages.clear();
+        self.delayed_messages.clear();
     }
 
     /// Handle a response to a sent message.
@@ -95,9 +119,18 @@ impl Outgoing {
         source: ServicePath,
     ) {
         let Some(unacked_message) = self.unacked_messages.get(&id) else {
-            // Response for message that was already acked. Ignore it.
+            // Response for message that was already acked or timed out. Ignore it.
             return;
         };
+        if unacked_message.actor_message.is_expired() {
+            // The message times out instead, see drive_resend_loop
+            debug!(
+                "dropped response to '{}' from {} after its deadline",
+                unacked_message.key(),
+                source.to_longest_path()
+            );
+            return;
+        }
 
         // Update the table for GetActorState
         self.sent_messages
@@ -112,29 +145,64 @@ impl Outgoing {
     }
 
     /// Run the resend/maintenence loop. Returned future must be polled to run it.
-    pub(crate) async fn drive_resend_loop(&mut self) {
+    ///
+    /// Returns the messages whose deadline passed before they were acked, which are no longer resent.
+    pub(crate) async fn drive_resend_loop(&mut self) -> Vec<ActorMessage> {
         loop {
-            self.resend_interval.tick().await;
-
-            // Drop messages that have been unacked for over an hour, as a memory leak failsafe
-            self.unacked_messages
-                .retain(|_, msg| Duration::from_secs(get_elapsed_time(&msg.time_sent)) < Duration::from_secs(3600));
-
-            // Resend unacked messages
-            for msg in self.unacked_messages.values() {
-                if Duration::from_secs(get_elapsed_time(&msg.time_sent)) >= self.resend_interval.period() {
-                    self.swbus_client
-                        .send_raw(msg.swbus_message.clone())
-                        .await
-                        .expect("Sending swbus message failed");
-
-                    // Update the table for GetActorState
-                    self.sent_messages.get_mut(msg.key()).unwrap().message_resent();
+            let next_deadline = self
+                .unacked_messages
+                .values()
+                .filter_map(|msg| msg.actor_message.time_left())
+                .min();
+            tokio::select! {
+                _ = self.resend_interval.tick() => self.resend_unacked_messages().await,
+                _ = sleep_or_pending(next_deadline) => {
+                    let expired = self.take_expired_messages();
+                    if !expired.is_empty() {
+                        return expired;
+                    }
                 }
             }
         }
     }
 
+    async fn resend_unacked_messages(&mut self) {
+        // Drop messages that have been unacked for over an hour, as a memory leak failsafe
+        self.unacked_messages
+            .retain(|_, msg| Duration::from_secs(get_elapsed_time(&msg.time_sent)) < Duration::from_secs(3600));
+
+        // Resend unacked messages
+        for msg in self.unacked_messages.values() {
+            if Duration::from_secs(get_elapsed_time(&msg.time_sent)) >= self.resend_interval.period() {
+                self.swbus_client
+                    .send_raw(msg.swbus_message.clone())
+                    .await
+                    .expect("Sending swbus message failed");
+
+                // Update the table for GetActorState
+                self.sent_messages.get_mut(msg.key()).unwrap().message_resent();
+            }
+        }
+    }
+
+    /// Remove the unacked messages whose deadline passed.
+    fn take_expired_messages(&mut self) -> Vec<ActorMessage> {
+        let expired_ids: Vec<MessageId> = self
+            .unacked_messages
+            .iter()
+            .filter(|(_, msg)| msg.actor_message.is_expired())
+            .map(|(id, _)| *id)
+            .collect();
+        let mut expired = Vec::new();
+        for id in expired_ids {
+            let msg = self.unacked_messages.remove(&id).unwrap();
+            // Update the table for GetActorState
+            self.sent_messages.get_mut(msg.key()).unwrap().timed_out(id);
+            expired.push(msg.actor_message);
+        }
+        expired
+    }
+
     pub fn from_my_sp(&self, resource_type: &str, resource_id: &str) -> ServicePath {
         let mut sp = self.swbus_client.get_service_path().clone();
         sp.resource_type = resource_type.into();
@@ -163,6 +231,13 @@ impl Outgoing {
     }
 }
 
+async fn sleep_or_pending(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
 pub fn get_elapsed_time(systime: &SystemTime) -> u64 {
     match systime.elapsed() {
         Ok(elapsed) => elapsed.as_secs(),
@@ -242,6 +317,13 @@ impl SentMessageEntry {
         self.last_sent_time = get_unix_time();
     }
 
+    fn timed_out(&mut self, request_id: MessageId) {
+        if request_id == self.id {
+            self.acked = false;
+            self.response = Some(String::from("Timeout (deadline passed)"));
+        }
+    }
+
     fn response_received(
         &mut self,
         request_id: MessageId,
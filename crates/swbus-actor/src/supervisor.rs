@@ -0,0 +1,382 @@
+//! Supervision of actor tasks
+//!
+//! Every actor runs in a task of its own, watched by a supervisor task, so a panic in an actor is
+//! caught at the task boundary and never reaches the runtime, the bridges or the other actors. A
+//! [`FatalError`] returned by the actor crashes it the same way. The supervisor reports the crash as an
+//! [`ActorHealthEvent`], and handles it as the [`RestartPolicy`] of the actor says: actors spawned with
+//! a restart function are restarted, backing off between restarts and giving up after
+//! [`MAX_RESTARTS`] crashes in a row by default, or the crash is escalated to the parent actor.
+//! With a [`WatchdogPolicy`], it also reports actors that are stuck, see [`crate::watchdog`].
+use crate::state::incoming::IncomingTableEntry;
+use crate::watchdog::{CheckIn, WatchdogPolicy};
+use crate::{driver::ActorDriver, metrics, Actor, Result};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use swbus_edge::{
+    mailbox::MailboxConfig, simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::ServicePath, SwbusEdgeRuntime,
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{error, info};
+
+/// Crashes in a row after which an actor is not restarted any more.
+pub const MAX_RESTARTS: u32 = 5;
+
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+
+/// An actor that ran this long before crashing starts over with a fresh restart count.
+const STABLE_RUN_TIME: Duration = Duration::from_secs(60);
+
+/// An error the actor can't recover from by itself. Returned by `Actor::handle_message`, it crashes the
+/// actor like a panic instead of failing only the message.
+#[derive(Debug)]
+pub struct FatalError(pub String);
+
+impl std::fmt::Display for FatalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fatal error: {}", self.0)
+    }
+}
+
+impl std::error::Error for FatalError {}
+
+/// A [`FatalError`] with `message`.
+pub fn fatal(message: impl Into<String>) -> crate::Error {
+    FatalError(message.into()).into()
+}
+
+/// How a supervised actor is restarted after a crash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Crashes in a row after which the actor is not restarted any more
+    pub max_restarts: u32,
+    /// Wait before the first restart. It doubles after every crash in a row, up to `max_delay`.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            max_restarts: MAX_RESTARTS,
+            initial_delay: RESTART_BACKOFF,
+            max_delay: MAX_RESTART_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, restarts: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(1 << (restarts - 1).min(16))
+            .min(self.max_delay)
+    }
+}
+
+/// What the supervisor does when a supervised actor crashes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Start a new instance of the actor from `init`.
+    RestartWithBackoff(Backoff),
+    /// Start a new instance with the incoming state table of the crashed one, and have it handle every
+    /// entry again after `init`. The message the crashed instance was handling is left out.
+    RestartAndReplayLastState(Backoff),
+    /// Leave the actor down and crash its parent instead, which is handled as the policy of the parent
+    /// says. An actor without a parent stays down.
+    Escalate,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::RestartWithBackoff(Backoff::default())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ActorHealthEvent {
+    /// The actor panicked and is being restarted. `restarts` counts the crashes in a row.
+    Restarting {
+        actor: ServicePath,
+        panic: String,
+        restarts: u32,
+    },
+    /// The actor panicked and stays down.
+    Down { actor: ServicePath, reason: String },
+    /// The actor crashed and stays down, and its parent is crashed in turn.
+    Escalated {
+        actor: ServicePath,
+        parent: ServicePath,
+        reason: String,
+    },
+    /// The actor has been busy with `activity` for longer than the watchdog deadline. `aborted` tells
+    /// whether the watchdog aborted it.
+    Stuck {
+        actor: ServicePath,
+        activity: String,
+        busy_for: Duration,
+        aborted: bool,
+    },
+}
+
+pub type HealthHandler = Arc<dyn Fn(&ActorHealthEvent) + Send + Sync>;
+
+pub(crate) type RestartFn<A> = Box<dyn FnMut() -> Result<A> + Send>;
+
+/// The incoming state table of an actor as of the last message it handled, for its next instance.
+pub(crate) type LastState = Arc<Mutex<HashMap<String, IncomingTableEntry>>>;
+
+/// Where the supervisors of the running actors take the crashes escalated by their children.
+#[derive(Clone, Default)]
+pub(crate) struct Escalations(Arc<Mutex<HashMap<ServicePath, UnboundedSender<String>>>>);
+
+impl Escalations {
+    pub(crate) fn register(&self, sp: &ServicePath, tx: UnboundedSender<String>) {
+        self.0.lock().unwrap().insert(sp.clone(), tx);
+    }
+
+    pub(crate) fn unregister(&self, sp: &ServicePath) {
+        self.0.lock().unwrap().remove(sp);
+    }
+
+    /// Crash `parent` because of `reason`. Returns whether the parent is running.
+    fn escalate(&self, parent: &ServicePath, reason: String) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(parent)
+            .is_some_and(|tx| tx.send(reason).is_ok())
+    }
+}
+
+pub(crate) struct Supervisor<A> {
+    pub(crate) swbus_edge: Arc<SwbusEdgeRuntime>,
+    pub(crate) sp: ServicePath,
+    /// The mailbox of the actor, which every new instance gets too
+    pub(crate) mailbox: MailboxConfig,
+    pub(crate) restart: Option<RestartFn<A>>,
+    pub(crate) policy: RestartPolicy,
+    pub(crate) parent: Option<ServicePath>,
+    pub(crate) escalations: Escalations,
+    /// Crashes escalated by the children of the actor
+    pub(crate) escalated_rx: UnboundedReceiver<String>,
+    pub(crate) health_handler: Option<HealthHandler>,
+    pub(crate) watchdog: Option<WatchdogPolicy>,
+}
+
+impl<A: Actor> Supervisor<A> {
+    /// Run `actor` on `swbus_client` until it terminates, or until it crashed and can't be restarted.
+    pub(crate) async fn run(mut self, mut actor: A, mut swbus_client: SimpleSwbusEdgeClient) {
+        let mut restarts = 0;
+        let mut total_restarts = 0;
+        let last_state = matches!(self.policy, RestartPolicy::RestartAndReplayLastState(_)).then(LastState::default);
+        loop {
+            let check_in = CheckIn::default();
+            let actor_driver = ActorDriver::new(actor, swbus_client, check_in.clone())
+                .with_restarts(total_restarts)
+                .with_last_state(last_state.clone());
+            let started = Instant::now();
+            let mut task = tokio::task::spawn(actor_driver.run());
+            let (result, aborted) = self.watch(&mut task, &check_in).await;
+            let panic = match result {
+                Ok(()) => return,
+                Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                Err(_) => match aborted {
+                    Some(reason) => reason,
+                    // The runtime is shutting down
+                    None => return,
+                },
+            };
+            error!("actor {} panicked: {panic}", self.sp.to_longest_path());
+
+            if started.elapsed() >= STABLE_RUN_TIME {
+                restarts = 0;
+            }
+            restarts += 1;
+
+            let backoff = match &self.policy {
+                RestartPolicy::RestartWithBackoff(backoff) | RestartPolicy::RestartAndReplayLastState(backoff) => {
+                    backoff.clone()
+                }
+                RestartPolicy::Escalate => {
+                    self.escalate(panic);
+                    return;
+                }
+            };
+            let Some(restart) = self.restart.as_mut() else {
+                self.report(ActorHealthEvent::Down {
+                    actor: self.sp.clone(),
+                    reason: format!("panicked: {panic}"),
+                });
+                return;
+            };
+            if restarts > backoff.max_restarts {
+                self.report(ActorHealthEvent::Down {
+                    actor: self.sp.clone(),
+                    reason: format!("crashed {} times in a row, last panic: {panic}", backoff.max_restarts),
+                });
+                return;
+            }
+
+            actor = match restart() {
+                Ok(actor) => actor,
+                Err(e) => {
+                    self.report(ActorHealthEvent::Down {
+                        actor: self.sp.clone(),
+                        reason: format!("panicked: {panic}, and failed to restart: {e:#}"),
+                    });
+                    return;
+                }
+            };
+            total_restarts += 1;
+            metrics::RESTARTS.with_label_values(&[&self.sp.to_longest_path()]).inc();
+            self.report(ActorHealthEvent::Restarting {
+                actor: self.sp.clone(),
+                panic,
+                restarts,
+            });
+            sleep(backoff.delay(restarts)).await;
+            info!("restarting actor {}", self.sp.to_longest_path());
+            swbus_client = SimpleSwbusEdgeClient::with_mailbox(
+                self.swbus_edge.clone(),
+                self.sp.clone(),
+                true,
+                false,
+                self.mailbox,
+            );
+        }
+    }
+
+    /// Leave the crashed actor down, and crash its parent.
+    fn escalate(&self, panic: String) {
+        let Some(parent) = self.parent.clone() else {
+            self.report(ActorHealthEvent::Down {
+                actor: self.sp.clone(),
+                reason: format!("panicked: {panic}, and has no parent to escalate to"),
+            });
+            return;
+        };
+        let reason = format!("child {} crashed: {panic}", self.sp.to_longest_path());
+        if !self.escalations.escalate(&parent, reason) {
+            self.report(ActorHealthEvent::Down {
+                actor: self.sp.clone(),
+                reason: format!(
+                    "panicked: {panic}, and parent {} is not running",
+                    parent.to_longest_path()
+                ),
+            });
+            return;
+        }
+        self.report(ActorHealthEvent::Escalated {
+            actor: self.sp.clone(),
+            parent,
+            reason: panic,
+        });
+    }
+
+    /// Wait for the actor task to end, checking on it if there is a watchdog. Returns why the actor
+    /// was aborted, if the watchdog aborted it or a child escalated a crash.
+    async fn watch(
+        &mut self,
+        task: &mut JoinHandle<()>,
+        check_in: &CheckIn,
+    ) -> (std::result::Result<(), tokio::task::JoinError>, Option<String>) {
+        let mut reported_since = None;
+        let mut aborted = None;
+        let mut interval = self
+            .watchdog
+            .as_ref()
+            .map(|policy| tokio::time::interval(policy.check_interval));
+        loop {
+            tokio::select! {
+                result = &mut *task => return (result, aborted),
+                Some(reason) = self.escalated_rx.recv(), if aborted.is_none() => {
+                    task.abort();
+                    aborted = Some(reason);
+                    continue;
+                }
+                _ = tick(interval.as_mut()) => {}
+            }
+            let Some(policy) = &self.watchdog else {
+                continue;
+            };
+            let Some(busy) = check_in.overdue(policy.deadline) else {
+                continue;
+            };
+            // Report every stuck activity once
+            if reported_since == Some(busy.since) || aborted.is_some() {
+                continue;
+            }
+            reported_since = Some(busy.since);
+            error!(
+                "actor {} is stuck on {} for {:?}",
+                self.sp.to_longest_path(),
+                busy.activity,
+                busy.since.elapsed()
+            );
+            self.report(ActorHealthEvent::Stuck {
+                actor: self.sp.clone(),
+                activity: busy.activity.clone(),
+                busy_for: busy.since.elapsed(),
+                aborted: policy.abort,
+            });
+            if policy.abort {
+                task.abort();
+                aborted = Some(format!("stuck on {}, aborted by the watchdog", busy.activity));
+            }
+        }
+    }
+
+    fn report(&self, event: ActorHealthEvent) {
+        if let Some(handler) = &self.health_handler {
+            handler(&event);
+        }
+    }
+}
+
+/// The next tick of the watchdog `interval`, never without a watchdog.
+async fn tick(interval: Option<&mut tokio::time::Interval>) {
+    match interval {
+        Some(interval) => _ = interval.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Whether `error` is, or was caused by, a [`FatalError`].
+pub(crate) fn is_fatal(error: &crate::Error) -> bool {
+    error.chain().any(|cause| cause.is::<FatalError>())
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn restart_backoff_is_bounded() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(5), Duration::from_secs(16));
+        assert_eq!(backoff.delay(6), MAX_RESTART_BACKOFF);
+        assert_eq!(backoff.delay(100), MAX_RESTART_BACKOFF);
+    }
+
+    #[test]
+    fn fatal_errors_are_recognized_through_context() {
+        let e = fatal("table is corrupt").context("handling message x");
+        assert!(is_fatal(&e));
+        assert!(!is_fatal(&crate::Error::msg("try again")));
+    }
+}
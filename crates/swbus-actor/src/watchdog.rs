@@ -0,0 +1,91 @@
+//! Watchdog of actor tasks
+//!
+//! A panic ends an actor task, but an actor that deadlocks on an await or loops forever keeps its
+//! task alive and silently stops handling messages. The driver marks its actor busy while it runs
+//! `init` or handles a message, and checks in when done. The supervisor looks at the mark every
+//! [`WatchdogPolicy::check_interval`]: an actor busy with the same activity for longer than
+//! [`WatchdogPolicy::deadline`] is reported as stuck, and aborted if the policy says so, in which case
+//! it is restarted like an actor that panicked.
+//!
+//! Aborting only takes effect at an await point, so an actor looping without awaiting is reported
+//! but keeps its worker thread.
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct WatchdogPolicy {
+    /// Time an actor may be busy with one activity
+    pub deadline: Duration,
+    pub check_interval: Duration,
+    /// Abort stuck actors, so that supervised ones are restarted
+    pub abort: bool,
+}
+
+impl Default for WatchdogPolicy {
+    fn default() -> Self {
+        WatchdogPolicy {
+            deadline: Duration::from_secs(30),
+            check_interval: Duration::from_secs(5),
+            abort: false,
+        }
+    }
+}
+
+/// What an actor is busy with, shared between its driver and its supervisor.
+#[derive(Clone, Default)]
+pub(crate) struct CheckIn(Arc<Mutex<Option<Busy>>>);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Busy {
+    pub(crate) activity: String,
+    pub(crate) since: Instant,
+}
+
+impl CheckIn {
+    /// Mark the actor busy with `activity`.
+    pub(crate) fn busy(&self, activity: impl Into<String>) {
+        *self.0.lock().unwrap() = Some(Busy {
+            activity: activity.into(),
+            since: Instant::now(),
+        });
+    }
+
+    /// Mark the actor idle.
+    pub(crate) fn check_in(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// The activity the actor is busy with for longer than `deadline`, if any.
+    pub(crate) fn overdue(&self, deadline: Duration) -> Option<Busy> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|busy| busy.since.elapsed() > deadline)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn overdue_only_while_busy_past_deadline() {
+        let check_in = CheckIn::default();
+        let deadline = Duration::from_millis(20);
+        assert!(check_in.overdue(deadline).is_none());
+
+        check_in.busy("message a");
+        assert!(check_in.overdue(deadline).is_none());
+        tokio::time::sleep(deadline * 2).await;
+        assert_eq!(check_in.overdue(deadline).unwrap().activity, "message a");
+
+        // A new activity starts a new deadline
+        check_in.busy("message b");
+        assert!(check_in.overdue(deadline).is_none());
+        check_in.check_in();
+        tokio::time::sleep(deadline * 2).await;
+        assert!(check_in.overdue(deadline).is_none());
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::bail;
+use std::{mem, time::Duration};
+use swbus_actor::{Actor, ActorMessage, ActorRuntime, Context, Result, State};
+use swbus_edge::{swbus_proto::swbus::ServicePath, SwbusEdgeRuntime};
+use tokio::{
+    sync::oneshot::{channel, Sender},
+    time::timeout,
+};
+
+fn sp(name: &str) -> ServicePath {
+    ServicePath::from_string(&format!("test.test.test/test/test/test/{name}")).unwrap()
+}
+
+#[tokio::test]
+async fn deadline() {
+    let mut swbus_edge = SwbusEdgeRuntime::new("none".to_string(), sp("none"));
+    swbus_edge.start().await.unwrap();
+    let actor_runtime = ActorRuntime::new(swbus_edge.into());
+    swbus_actor::set_global_runtime(actor_runtime);
+
+    let (notify_timeout, timed_out) = channel();
+
+    swbus_actor::spawn(Rejecter, "test", "rejecter");
+    swbus_actor::spawn(Requester(notify_timeout), "test", "requester");
+
+    let msg = timeout(Duration::from_secs(3), timed_out)
+        .await
+        .expect("timeout")
+        .unwrap();
+    assert_eq!(msg.key, "request");
+    assert!(msg.is_expired());
+}
+
+/// Sends a request with a deadline and reports when it times out.
+struct Requester(Sender<ActorMessage>);
+
+impl Actor for Requester {
+    async fn init(&mut self, state: &mut State) -> Result<()> {
+        let msg = ActorMessage::new("request", &0)?.with_timeout(Duration::from_millis(200));
+        state.outgoing().send(sp("rejecter"), msg);
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, _state: &mut State, _key: &str, _context: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    async fn handle_timeout(&mut self, _state: &mut State, msg: &ActorMessage, _context: &mut Context) -> Result<()> {
+        mem::replace(&mut self.0, channel().0).send(msg.clone()).unwrap();
+        Ok(())
+    }
+}
+
+/// Never acks a request.
+struct Rejecter;
+
+impl Actor for Rejecter {
+    async fn handle_message(&mut self, _state: &mut State, key: &str, _context: &mut Context) -> Result<()> {
+        bail!("rejecting {key}")
+    }
+}
@@ -26,6 +26,9 @@ async fn echo() {
         .await
         .expect("timeout")
         .unwrap();
+
+    let actors = swbus_actor::get_global_runtime().as_ref().unwrap().actors();
+    assert_eq!(actors, vec![sp("client"), sp("echo")]);
 }
 
 struct EchoClient(Sender<()>);
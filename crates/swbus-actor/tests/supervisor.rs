@@ -0,0 +1,305 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_actor::supervisor::{fatal, ActorHealthEvent, Backoff, RestartPolicy};
+use swbus_actor::watchdog::WatchdogPolicy;
+use swbus_actor::{Actor, ActorMessage, ActorRuntime, Context, Result, State};
+use swbus_edge::{
+    mailbox::{MailboxConfig, OverflowPolicy},
+    simple_client::{MessageBody, MessageId, OutgoingMessage, SimpleSwbusEdgeClient},
+    swbus_proto::swbus::{ServicePath, SwbusErrorCode},
+    SwbusEdgeRuntime,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+fn sp(name: &str) -> ServicePath {
+    ServicePath::from_string(&format!("test.test.test/test/test/test/{name}")).unwrap()
+}
+
+/// Panics on the message with key "boom", and never finishes handling the one with key "hang".
+struct Fragile {
+    inits: Arc<AtomicU32>,
+}
+
+impl Actor for Fragile {
+    async fn init(&mut self, _state: &mut State) -> Result<()> {
+        self.inits.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, _state: &mut State, key: &str, _context: &mut Context) -> Result<()> {
+        match key {
+            "boom" => panic!("boom received"),
+            "hang" => std::future::pending().await,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn panicking_actor_is_restarted() {
+    let mut swbus_edge = SwbusEdgeRuntime::new("none".to_string(), sp("none"));
+    swbus_edge.start().await.unwrap();
+    let swbus_edge = Arc::new(swbus_edge);
+    let mut actor_runtime = ActorRuntime::new(swbus_edge.clone());
+    let (health_tx, mut health_rx) = unbounded_channel();
+    actor_runtime.set_health_handler(move |event| health_tx.send(event.clone()).unwrap());
+
+    let inits = Arc::new(AtomicU32::new(0));
+    let restart_inits = inits.clone();
+    actor_runtime.spawn_supervised(
+        Fragile { inits: inits.clone() },
+        move || {
+            Ok(Fragile {
+                inits: restart_inits.clone(),
+            })
+        },
+        "test",
+        "fragile",
+    );
+    let unsupervised = actor_runtime.spawn(Fragile { inits: inits.clone() }, "test", "unsupervised");
+
+    let client = SimpleSwbusEdgeClient::new(swbus_edge, sp("client"), true, false);
+    for name in ["fragile", "unsupervised"] {
+        client
+            .send(OutgoingMessage {
+                destination: sp(name),
+                body: MessageBody::Request {
+                    payload: ActorMessage::new("boom", &0).unwrap().serialize().into(),
+                },
+            })
+            .await
+            .unwrap();
+    }
+
+    let mut events = Vec::new();
+    while events.len() < 2 {
+        events.push(
+            timeout(Duration::from_secs(5), health_rx.recv())
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+    }
+    events.sort_by_key(|event| matches!(event, ActorHealthEvent::Down { .. }));
+    assert_eq!(
+        events,
+        vec![
+            ActorHealthEvent::Restarting {
+                actor: sp("fragile"),
+                panic: "boom received".to_string(),
+                restarts: 1,
+            },
+            ActorHealthEvent::Down {
+                actor: sp("unsupervised"),
+                reason: "panicked: boom received".to_string(),
+            },
+        ]
+    );
+
+    // The unsupervised actor is gone, the supervised one is back after the backoff
+    timeout(Duration::from_secs(1), unsupervised).await.unwrap().unwrap();
+    timeout(Duration::from_secs(5), async {
+        while inits.load(Ordering::SeqCst) < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(actor_runtime.actors(), vec![sp("fragile")]);
+}
+
+async fn next_event(health_rx: &mut UnboundedReceiver<ActorHealthEvent>) -> ActorHealthEvent {
+    timeout(Duration::from_secs(5), health_rx.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn stuck_actor_is_aborted_and_restarted() {
+    let mut swbus_edge = SwbusEdgeRuntime::new("none".to_string(), sp("none"));
+    swbus_edge.start().await.unwrap();
+    let swbus_edge = Arc::new(swbus_edge);
+    let mut actor_runtime = ActorRuntime::new(swbus_edge.clone());
+    let (health_tx, mut health_rx) = unbounded_channel();
+    actor_runtime.set_health_handler(move |event| health_tx.send(event.clone()).unwrap());
+    actor_runtime.set_watchdog(WatchdogPolicy {
+        deadline: Duration::from_millis(100),
+        check_interval: Duration::from_millis(10),
+        abort: true,
+    });
+
+    let inits = Arc::new(AtomicU32::new(0));
+    let restart_inits = inits.clone();
+    actor_runtime.spawn_supervised(
+        Fragile { inits: inits.clone() },
+        move || {
+            Ok(Fragile {
+                inits: restart_inits.clone(),
+            })
+        },
+        "test",
+        "stuck",
+    );
+
+    let client = SimpleSwbusEdgeClient::new(swbus_edge, sp("client"), true, false);
+    client
+        .send(OutgoingMessage {
+            destination: sp("stuck"),
+            body: MessageBody::Request {
+                payload: ActorMessage::new("hang", &0).unwrap().serialize().into(),
+            },
+        })
+        .await
+        .unwrap();
+
+    let ActorHealthEvent::Stuck {
+        actor,
+        activity,
+        busy_for,
+        aborted,
+    } = next_event(&mut health_rx).await
+    else {
+        panic!("expected the actor to be reported stuck");
+    };
+    assert_eq!((actor, activity.as_str(), aborted), (sp("stuck"), "message hang", true));
+    assert!(busy_for > Duration::from_millis(100));
+    assert_eq!(
+        next_event(&mut health_rx).await,
+        ActorHealthEvent::Restarting {
+            actor: sp("stuck"),
+            panic: "stuck on message hang, aborted by the watchdog".to_string(),
+            restarts: 1,
+        }
+    );
+
+    timeout(Duration::from_secs(5), async {
+        while inits.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn restarted_actor_keeps_its_mailbox() {
+    let mut swbus_edge = SwbusEdgeRuntime::new("none".to_string(), sp("none"));
+    swbus_edge.start().await.unwrap();
+    let swbus_edge = Arc::new(swbus_edge);
+    let mut actor_runtime = ActorRuntime::new(swbus_edge.clone());
+    let (health_tx, mut health_rx) = unbounded_channel();
+    actor_runtime.set_health_handler(move |event| health_tx.send(event.clone()).unwrap());
+    actor_runtime.set_mailbox(
+        "test",
+        MailboxConfig {
+            size: 1,
+            overflow: OverflowPolicy::Reject,
+        },
+    );
+
+    let (events_tx, mut events) = unbounded_channel();
+    let gate = Arc::new(Notify::new());
+    let restart_events_tx = events_tx.clone();
+    let restart_gate = gate.clone();
+    actor_runtime.spawn_with_policy(
+        Recorder {
+            events: events_tx,
+            gate: gate.clone(),
+        },
+        move || {
+            Ok(Recorder {
+                events: restart_events_tx.clone(),
+                gate: restart_gate.clone(),
+            })
+        },
+        RestartPolicy::RestartWithBackoff(Backoff {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        }),
+        None,
+        "test",
+        "mailbox",
+    );
+    assert_eq!(next_key(&mut events).await, "init");
+
+    let client = SimpleSwbusEdgeClient::new(swbus_edge, sp("client"), true, false);
+    send(&client, "mailbox", "fatal").await;
+    assert_eq!(next_key(&mut events).await, "fatal");
+    assert!(matches!(
+        next_event(&mut health_rx).await,
+        ActorHealthEvent::Restarting { restarts: 1, .. }
+    ));
+    // The new instance is initialized once its mailbox is in place
+    assert_eq!(next_key(&mut events).await, "init");
+
+    // The new instance holds one message while it handles "slow", and rejects the others
+    send(&client, "mailbox", "slow").await;
+    assert_eq!(next_key(&mut events).await, "slow");
+    let mut ids = Vec::new();
+    for key in ["a", "b", "c"] {
+        ids.push(send(&client, "mailbox", key).await);
+    }
+    let mut rejected = Vec::new();
+    while rejected.len() < 2 {
+        let msg = timeout(Duration::from_secs(5), client.recv()).await.unwrap().unwrap();
+        if let MessageBody::Response {
+            request_id,
+            error_code: SwbusErrorCode::QueueFull,
+            ..
+        } = msg.body
+        {
+            rejected.push(request_id);
+        }
+    }
+    assert_eq!(rejected, ids[1..]);
+
+    gate.notify_one();
+    assert_eq!(next_key(&mut events).await, "a");
+    send(&client, "mailbox", "d").await;
+    assert_eq!(next_key(&mut events).await, "d");
+}
+
+async fn next_key(events: &mut UnboundedReceiver<String>) -> String {
+    timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+}
+
+/// Reports its init and the keys of the messages it handles to `events`, fails fatally on the message
+/// with key "fatal", and doesn't finish handling the one with key "slow" until `gate` is opened.
+struct Recorder {
+    events: UnboundedSender<String>,
+    gate: Arc<Notify>,
+}
+
+impl Actor for Recorder {
+    async fn init(&mut self, _state: &mut State) -> Result<()> {
+        self.events.send("init".to_string()).unwrap();
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, _state: &mut State, key: &str, _context: &mut Context) -> Result<()> {
+        self.events.send(key.to_string()).unwrap();
+        match key {
+            "fatal" => return Err(fatal("state is corrupt")),
+            "slow" => self.gate.notified().await,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+async fn send(client: &SimpleSwbusEdgeClient, name: &str, key: &str) -> MessageId {
+    client
+        .send(OutgoingMessage {
+            destination: sp(name),
+            body: MessageBody::Request {
+                payload: ActorMessage::new(key, &0).unwrap().serialize().into(),
+            },
+        })
+        .await
+        .unwrap()
+}
@@ -0,0 +1,96 @@
+use super::CmdHandler;
+use crate::wait_for_response;
+use clap::Parser;
+use prost::Message;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use swbus_proto::recording::{write_message, RecordedMessage};
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration, Instant};
+use tracing::{error, info};
+
+const CAPTURE_QUEUE_SIZE: usize = 1024;
+
+#[derive(Parser, Debug)]
+pub struct CaptureCmd {
+    /// File to write the captured messages to, one JSON message per line
+    #[arg(short = 'o', long)]
+    output: String,
+
+    /// How long to capture in seconds
+    #[arg(short = 't', long, default_value_t = 60)]
+    duration: u64,
+}
+
+impl CmdHandler for CaptureCmd {
+    async fn handle(&self, ctx: &super::CommandContext) {
+        let file = match File::create(&self.output) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Failed to create {}: {}", self.output, e);
+                return;
+            }
+        };
+        let mut writer = BufWriter::new(file);
+
+        // Create a channel to receive the captured messages
+        let (recv_queue_tx, mut recv_queue_rx) = mpsc::channel::<SwbusMessage>(CAPTURE_QUEUE_SIZE);
+        let mut src_sp = ctx.sp.clone();
+        src_sp.resource_type = "capture".to_string();
+        src_sp.resource_id = "0".to_string();
+        ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
+
+        let mut mgmt_req = ManagementRequest::new(ManagementRequestType::SwbusdCapture);
+        mgmt_req.arguments.push(ManagementRequestArg {
+            name: "duration_secs".to_string(),
+            value: self.duration.to_string(),
+        });
+        let header = SwbusMessageHeader::new(
+            src_sp.clone(),
+            ctx.sp.to_swbusd_service_path(),
+            ctx.id_generator.generate(),
+        );
+        let request_id = header.id;
+        let request_msg = SwbusMessage::new(header, swbus_message::Body::ManagementRequest(mgmt_req));
+        ctx.runtime.send(request_msg).await.unwrap();
+
+        let result = wait_for_response(&mut recv_queue_rx, request_id, 10).await;
+        if result.error_code != SwbusErrorCode::Ok {
+            error!("Failed to start capture: {}", result.error_message);
+            return;
+        }
+
+        info!("Capturing to {} for {}s", self.output, self.duration);
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(self.duration);
+        let mut count = 0;
+        while let Ok(Some(msg)) = time::timeout_at(deadline, recv_queue_rx.recv()).await {
+            let Some(swbus_message::Body::DataRequest(data)) = msg.body else {
+                continue;
+            };
+            let message = match SwbusMessage::decode(&data.payload[..]) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Failed to decode captured message: {}", e);
+                    continue;
+                }
+            };
+            let recorded = RecordedMessage {
+                time_ms: start.elapsed().as_millis() as u64,
+                direction: None,
+                message,
+            };
+            if let Err(e) = write_message(&mut writer, &recorded) {
+                error!("Failed to write to {}: {}", self.output, e);
+                return;
+            }
+            count += 1;
+        }
+        if let Err(e) = writer.flush() {
+            error!("Failed to write to {}: {}", self.output, e);
+            return;
+        }
+        info!("{} messages captured", count);
+    }
+}
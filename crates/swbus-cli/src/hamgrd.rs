@@ -0,0 +1,136 @@
+use super::CmdHandler;
+use crate::{wait_for_response, CommandContext};
+use clap::Parser;
+use swbus_proto::swbus::*;
+use tabled::{Table, Tabled};
+use tokio::sync::mpsc;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct HamgrdCmd {
+    #[command(subcommand)]
+    subcommand: HamgrdSubCmd,
+}
+
+#[derive(Parser, Debug)]
+enum HamgrdSubCmd {
+    Health(HealthCmd),
+}
+
+/// Check the health of hamgrd. Exits with 1 if it is not ready, or not live with --live, so it can be
+/// used as a systemd watchdog or container probe.
+#[derive(Parser, Debug)]
+pub struct HealthCmd {
+    /// The slot of the DPU whose hamgrd is checked. Default is the DPU of this swbusd.
+    slot: Option<u32>,
+
+    /// Only check liveness, not readiness
+    #[arg(long)]
+    live: bool,
+
+    /// Timeout in seconds
+    #[arg(short = 't', long, default_value_t = 10)]
+    timeout: u32,
+}
+
+#[derive(Tabled)]
+struct HealthCheckDisplay {
+    check: String,
+    kind: String,
+    status: String,
+    detail: String,
+}
+
+impl HealthCheckDisplay {
+    fn from_check(check: &HealthCheck) -> Self {
+        HealthCheckDisplay {
+            check: check.name.clone(),
+            kind: if check.liveness { "liveness" } else { "readiness" }.to_string(),
+            status: if check.ok { "ok" } else { "FAILED" }.to_string(),
+            detail: check.detail.clone(),
+        }
+    }
+}
+
+impl HamgrdCmd {
+    /// Run the command. Returns whether it succeeded.
+    pub async fn run(&self, ctx: &CommandContext) -> bool {
+        match &self.subcommand {
+            HamgrdSubCmd::Health(health_cmd) => health_cmd.run(ctx).await,
+        }
+    }
+}
+
+impl CmdHandler for HamgrdCmd {
+    async fn handle(&self, ctx: &CommandContext) {
+        self.run(ctx).await;
+    }
+}
+
+impl HealthCmd {
+    /// The health checker of the hamgrd of `slot`, or of the hamgrd of this swbusd.
+    fn destination(&self, ctx: &CommandContext) -> ServicePath {
+        let mut dest_sp = ctx.sp.to_swbusd_service_path();
+        if let Some(slot) = self.slot {
+            // swbusd of DPUs are named <npu address>-dpu<slot>
+            let npu = dest_sp
+                .node_id
+                .rsplit_once("-dpu")
+                .map_or(dest_sp.node_id.as_str(), |(npu, _)| npu);
+            dest_sp.node_id = format!("{npu}-dpu{slot}");
+        }
+        dest_sp.join(&ServicePath::from_string("/hamgrd/0/health/0").unwrap());
+        dest_sp
+    }
+
+    /// Query the health and print it. Returns whether hamgrd is healthy.
+    async fn run(&self, ctx: &CommandContext) -> bool {
+        let (recv_queue_tx, mut recv_queue_rx) = mpsc::channel::<SwbusMessage>(1);
+        let mut src_sp = ctx.sp.clone();
+        src_sp.resource_type = "health".to_string();
+        src_sp.resource_id = "0".to_string();
+        ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
+
+        let header = SwbusMessageHeader::new(src_sp, self.destination(ctx), ctx.id_generator.generate());
+        let request_id = header.id;
+        let request = SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(ManagementRequest::new(
+                ManagementRequestType::HamgrdHealthCheck,
+            ))),
+        };
+        ctx.runtime.send(request).await.unwrap();
+
+        let result = wait_for_response(&mut recv_queue_rx, request_id, self.timeout).await;
+        if result.error_code != SwbusErrorCode::Ok {
+            match ctx.output.is_table() {
+                true => info!("not live: {}:{}", result.error_code.as_str_name(), result.error_message),
+                false => ctx.output.print_error(result.error_code, &result.error_message),
+            }
+            return false;
+        }
+        let report = match result.msg.and_then(|msg| msg.body) {
+            Some(swbus_message::Body::Response(RequestResponse {
+                response_body: Some(request_response::ResponseBody::HealthReport(report)),
+                ..
+            })) => report,
+            body => {
+                info!("Expecting HealthReport but got something else: {:?}", body);
+                return false;
+            }
+        };
+
+        if ctx.output.is_table() {
+            let checks: Vec<HealthCheckDisplay> = report.checks.iter().map(HealthCheckDisplay::from_check).collect();
+            info!("live: {}, ready: {}", report.live, report.ready);
+            info!("{}", Table::new(checks));
+        } else {
+            ctx.output.print_value(&report);
+        }
+        if self.live {
+            report.live
+        } else {
+            report.ready
+        }
+    }
+}
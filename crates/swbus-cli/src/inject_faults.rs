@@ -0,0 +1,137 @@
+use super::CmdHandler;
+use crate::output::StatusDisplay;
+use crate::{wait_for_response, CommandContext};
+use clap::Parser;
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Inject faults into the messages the local swbusd receives from a peer, for testing. swbusd must be
+/// built with the fault-injection feature.
+#[derive(Parser, Debug)]
+pub struct InjectFaultsCmd {
+    /// Service path of the peer, e.g. region-a.cluster-a.10.0.0.1-dpu0
+    peer: String,
+
+    /// Probability that a message is dropped, between 0 and 1
+    #[arg(long, default_value_t = 0.0)]
+    drop: f64,
+
+    /// Time every message is delayed by, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+
+    /// Probability that a message is held back so the ones after it overtake it, between 0 and 1
+    #[arg(long, default_value_t = 0.0)]
+    reorder: f64,
+
+    /// Stop injecting faults
+    #[arg(long, conflicts_with_all = ["drop", "latency_ms", "reorder", "reset"])]
+    clear: bool,
+
+    /// Reset the connections to the peer once
+    #[arg(long, conflicts_with_all = ["drop", "latency_ms", "reorder"])]
+    reset: bool,
+
+    /// Timeout in seconds
+    #[arg(short = 't', long, default_value_t = 10)]
+    timeout: u32,
+}
+
+impl InjectFaultsCmd {
+    fn request(&self) -> ManagementRequest {
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdInjectFaults);
+        let mut arg = |name: &str, value: String| {
+            mgmt_request.arguments.push(ManagementRequestArg {
+                name: name.to_string(),
+                value,
+            })
+        };
+        arg("peer", self.peer.clone());
+        if self.clear {
+            arg("clear", String::new());
+        } else if self.reset {
+            arg("reset", String::new());
+        } else {
+            arg("drop_probability", self.drop.to_string());
+            arg("latency_ms", self.latency_ms.to_string());
+            arg("reorder_probability", self.reorder.to_string());
+        }
+        mgmt_request
+    }
+}
+
+impl CmdHandler for InjectFaultsCmd {
+    async fn handle(&self, ctx: &CommandContext) {
+        let (recv_queue_tx, mut recv_queue_rx) = mpsc::channel::<SwbusMessage>(1);
+        let mut src_sp = ctx.sp.clone();
+        src_sp.resource_type = "inject-faults".to_string();
+        src_sp.resource_id = "0".to_string();
+        ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
+
+        let header = SwbusMessageHeader::new(src_sp, ctx.sp.to_swbusd_service_path(), ctx.id_generator.generate());
+        let request_id = header.id;
+        let request = SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(self.request())),
+        };
+        ctx.runtime.send(request).await.unwrap();
+
+        let result = wait_for_response(&mut recv_queue_rx, request_id, self.timeout).await;
+        if !ctx.output.is_table() {
+            ctx.output
+                .print_value(&StatusDisplay::new(result.error_code, &result.error_message));
+            return;
+        }
+        match result.error_code {
+            SwbusErrorCode::Ok => info!("Faults of {} are set", self.peer),
+            error_code => info!(
+                "Failed to inject faults: {}:{}",
+                error_code.as_str_name(),
+                result.error_message
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(request: &ManagementRequest) -> Vec<(&str, &str)> {
+        request
+            .arguments
+            .iter()
+            .map(|arg| (arg.name.as_str(), arg.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_request() {
+        let cmd = InjectFaultsCmd::parse_from(["inject-faults", "region-a.cluster-a.10.0.0.1-dpu0", "--drop", "0.1"]);
+        assert_eq!(
+            args(&cmd.request()),
+            vec![
+                ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+                ("drop_probability", "0.1"),
+                ("latency_ms", "0"),
+                ("reorder_probability", "0"),
+            ]
+        );
+
+        let cmd = InjectFaultsCmd::parse_from(["inject-faults", "region-a.cluster-a.10.0.0.1-dpu0", "--reset"]);
+        assert_eq!(
+            args(&cmd.request()),
+            vec![("peer", "region-a.cluster-a.10.0.0.1-dpu0"), ("reset", "")]
+        );
+
+        assert!(InjectFaultsCmd::try_parse_from([
+            "inject-faults",
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            "--clear",
+            "--drop",
+            "1"
+        ])
+        .is_err());
+    }
+}
@@ -1,10 +1,19 @@
+mod capture;
+mod hamgrd;
+mod inject_faults;
+mod output;
 mod ping;
+mod replay;
+mod rotate_tls;
+mod shell;
 mod show;
 mod trace_route;
 use anyhow::{Context, Result};
 use clap::Parser;
+use output::OutputFormat;
+use std::path::PathBuf;
 use std::sync::Arc;
-use swbus_config::{swbus_config_from_db, swbus_config_from_yaml, SwbusConfig};
+use swbus_config::{swbus_config_from_db, swbus_config_from_yaml, SwbusConfig, TlsConfig};
 use swbus_edge::edge_runtime::SwbusEdgeRuntime;
 use swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_proto::swbus::*;
@@ -23,6 +32,25 @@ struct Command {
     /// Path to swbusd config file. Only used for local testing.
     #[arg(short, long)]
     config_file: Option<String>,
+    /// File with the token to present to swbusd, if it authenticates its clients
+    #[arg(long)]
+    auth_token_file: Option<String>,
+    /// CA certificate in PEM to verify swbusd with, to connect over mutual TLS. If not set, the TLS files
+    /// of the swbusd config are used when swbusd has TLS set.
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    tls_ca_cert: Option<PathBuf>,
+    /// Certificate in PEM to present to swbusd
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_cert: Option<PathBuf>,
+    /// Key of the certificate in PEM
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_key: Option<PathBuf>,
+    /// Name the certificate of swbusd is verified against, instead of its IP address
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_domain_name: Option<String>,
+    /// Output format of the results
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
     #[command(subcommand)]
     subcommand: CliSubCmd,
 }
@@ -30,16 +58,41 @@ struct Command {
 #[derive(Parser, Debug)]
 enum CliSubCmd {
     Ping(ping::PingCmd),
+    #[command(name = "trace", visible_alias = "trace-route")]
     TraceRoute(trace_route::TraceRouteCmd),
     Show(show::ShowCmd),
+    Capture(capture::CaptureCmd),
+    Replay(replay::ReplayCmd),
+    Hamgrd(hamgrd::HamgrdCmd),
+    RotateTls(rotate_tls::RotateTlsCmd),
+    InjectFaults(inject_faults::InjectFaultsCmd),
+    /// Run commands interactively over a single connection to swbusd
+    Shell(shell::ShellCmd),
 }
 
 trait CmdHandler {
     async fn handle(&self, ctx: &CommandContext);
 }
 
+impl CmdHandler for CliSubCmd {
+    async fn handle(&self, ctx: &CommandContext) {
+        match self {
+            CliSubCmd::Ping(ping_args) => ping_args.handle(ctx).await,
+            CliSubCmd::Show(show_args) => show_args.handle(ctx).await,
+            CliSubCmd::TraceRoute(trace_route_args) => trace_route_args.handle(ctx).await,
+            CliSubCmd::Capture(capture_args) => capture_args.handle(ctx).await,
+            CliSubCmd::Replay(replay_args) => replay_args.handle(ctx).await,
+            CliSubCmd::Hamgrd(hamgrd_args) => hamgrd_args.handle(ctx).await,
+            CliSubCmd::RotateTls(rotate_tls_args) => rotate_tls_args.handle(ctx).await,
+            CliSubCmd::InjectFaults(inject_faults_args) => inject_faults_args.handle(ctx).await,
+            CliSubCmd::Shell(_) => error!("Already in the shell"),
+        }
+    }
+}
+
 struct CommandContext {
     debug: bool,
+    output: OutputFormat,
     // The source servicepath of swbus-cli
     sp: ServicePath,
     runtime: Arc<SwbusEdgeRuntime>,
@@ -158,9 +211,34 @@ async fn main() {
 
     sp.service_type = "swbus-cli".to_string();
     sp.service_id = Uuid::new_v4().to_string();
-    let mut runtime = SwbusEdgeRuntime::new(format!("http://{}", swbus_config.endpoint), sp.clone());
-    runtime.start().await.unwrap();
-    let runtime = Arc::new(runtime);
+    let tls = match (&args.tls_ca_cert, &args.tls_cert, &args.tls_key) {
+        (Some(ca_cert), Some(cert), Some(key)) => Some(TlsConfig {
+            ca_cert: ca_cert.clone(),
+            cert: cert.clone(),
+            key: key.clone(),
+            domain_name: args.tls_domain_name.clone(),
+        }),
+        _ => swbus_config.tls.clone(),
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let mut builder = SwbusEdgeRuntime::builder(format!("{scheme}://{}", swbus_config.endpoint), sp.clone());
+    if let Some(tls) = tls {
+        let tls = swbus_edge::core_client::TlsConfig::from_files(
+            &tls.ca_cert,
+            Some((&tls.cert, &tls.key)),
+            tls.domain_name.clone(),
+        )
+        .context("Failed to read the TLS certificates")
+        .unwrap();
+        builder = builder.tls(tls);
+    }
+    if let Some(path) = &args.auth_token_file {
+        let token = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {path}"))
+            .unwrap();
+        builder = builder.auth_token(token.trim().to_string());
+    }
+    let runtime = Arc::new(builder.build().await.unwrap());
 
     let start = Instant::now();
     // wait until swbusd is connected
@@ -177,6 +255,7 @@ async fn main() {
 
     let ctx = CommandContext {
         debug: args.debug,
+        output: args.output,
         sp,
         runtime: runtime.clone(),
         id_generator: MessageIdGenerator::new(),
@@ -187,9 +266,14 @@ async fn main() {
     }
 
     match args.subcommand {
-        CliSubCmd::Ping(ping_args) => ping_args.handle(&ctx).await,
-        CliSubCmd::Show(show_args) => show_args.handle(&ctx).await,
-        CliSubCmd::TraceRoute(trace_route_args) => trace_route_args.handle(&ctx).await,
+        CliSubCmd::Shell(shell_args) => shell_args.run(&ctx).await,
+        // the exit code tells probes whether hamgrd is healthy
+        CliSubCmd::Hamgrd(hamgrd_args) => {
+            if !hamgrd_args.run(&ctx).await {
+                std::process::exit(1);
+            }
+        }
+        subcommand => subcommand.handle(&ctx).await,
     };
 }
 
@@ -0,0 +1,152 @@
+//! Output formats of the commands
+//!
+//! Commands print tables by default. With `--output json` or `--output yaml` they print the same data
+//! as a single document instead, so that scripts and test frameworks can parse it. Commands that
+//! report results as they come, like ping, print one document per result: a line of JSON, or a YAML
+//! document starting with `---`. Failures are reported as documents too, see [`StatusDisplay`].
+use clap::ValueEnum;
+use serde::Serialize;
+use swbus_proto::swbus::SwbusErrorCode;
+use tabled::{Table, Tabled};
+use tracing::{error, info};
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+/// The error code and message of a response in the structured formats.
+#[derive(Serialize, Debug)]
+pub struct StatusDisplay {
+    pub error_code: String,
+    pub error_message: String,
+}
+
+impl StatusDisplay {
+    pub fn new(error_code: SwbusErrorCode, error_message: &str) -> Self {
+        StatusDisplay {
+            error_code: error_code
+                .as_str_name()
+                .strip_prefix("SWBUS_ERROR_CODE_")
+                .unwrap_or(error_code.as_str_name())
+                .to_string(),
+            error_message: error_message.to_string(),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn is_table(self) -> bool {
+        self == OutputFormat::Table
+    }
+
+    /// `rows` as a table, or as a list in the structured formats.
+    pub fn render<T: Serialize + Tabled>(self, rows: &[T]) -> String {
+        match self {
+            OutputFormat::Table => Table::new(rows).to_string(),
+            _ => self.render_value(&rows),
+        }
+    }
+
+    /// `value` as a document of the structured format. Values without a table form are printed as
+    /// JSON in table format.
+    pub fn render_value<T: Serialize + ?Sized>(self, value: &T) -> String {
+        let rendered = match self {
+            OutputFormat::Table | OutputFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            OutputFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+        };
+        rendered.unwrap_or_else(|e| {
+            error!("Failed to serialize the output: {e}");
+            String::new()
+        })
+    }
+
+    /// `record` as one of a stream of documents: a line of JSON or a YAML document.
+    pub fn render_record<T: Serialize>(self, record: &T) -> String {
+        match self {
+            OutputFormat::Yaml => format!("---\n{}", self.render_value(record).trim_end()),
+            _ => serde_json::to_string(record).unwrap_or_else(|e| {
+                error!("Failed to serialize the output: {e}");
+                String::new()
+            }),
+        }
+    }
+
+    /// Print `rows`, see [`OutputFormat::render`].
+    pub fn print<T: Serialize + Tabled>(self, rows: &[T]) {
+        info!("{}", self.render(rows));
+    }
+
+    /// Print `value`, see [`OutputFormat::render_value`].
+    pub fn print_value<T: Serialize + ?Sized>(self, value: &T) {
+        info!("{}", self.render_value(value).trim_end());
+    }
+
+    /// Report a failed request: as text in table format, as a [`StatusDisplay`] document otherwise.
+    pub fn print_error(self, error_code: SwbusErrorCode, error_message: &str) {
+        match self {
+            OutputFormat::Table => info!("{}:{}", error_code.as_str_name(), error_message),
+            _ => self.print_value(&StatusDisplay::new(error_code, error_message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Tabled)]
+    struct Row {
+        name: String,
+        count: u32,
+    }
+
+    fn rows() -> Vec<Row> {
+        vec![
+            Row {
+                name: "a".to_string(),
+                count: 1,
+            },
+            Row {
+                name: "b".to_string(),
+                count: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render_rows() {
+        let table = OutputFormat::Table.render(&rows());
+        assert!(table.contains("name") && table.contains("count"));
+
+        let json: serde_json::Value = serde_json::from_str(&OutputFormat::Json.render(&rows())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{"name": "a", "count": 1}, {"name": "b", "count": 2}])
+        );
+
+        let yaml: serde_json::Value = serde_yaml::from_str(&OutputFormat::Yaml.render(&rows())).unwrap();
+        assert_eq!(yaml, json);
+    }
+
+    #[test]
+    fn test_render_record() {
+        let row = &rows()[0];
+        assert_eq!(OutputFormat::Json.render_record(row), r#"{"name":"a","count":1}"#);
+        assert_eq!(OutputFormat::Yaml.render_record(row), "---\nname: a\ncount: 1");
+    }
+
+    #[test]
+    fn test_status_display() {
+        let status = StatusDisplay::new(SwbusErrorCode::Timeout, "request timeout");
+        assert_eq!(status.error_code, "TIMEOUT");
+        let json: serde_json::Value = serde_json::from_str(&OutputFormat::Json.render_value(&status)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"error_code": "TIMEOUT", "error_message": "request timeout"})
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use super::CmdHandler;
-use crate::wait_for_response;
+use crate::output::StatusDisplay;
+use crate::{wait_for_response, ResponseResult};
 use clap::Parser;
+use serde::Serialize;
 use std::time::Instant;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc;
@@ -25,6 +27,35 @@ pub struct PingCmd {
     dest: ServicePath,
 }
 
+/// The result of a ping in the structured output formats
+#[derive(Serialize)]
+struct PingResult {
+    seq: u32,
+    ttl: Option<u32>,
+    rtt_ms: Option<f64>,
+    /// Where the error came from, if not the destination
+    source: Option<String>,
+    #[serde(flatten)]
+    error: Option<StatusDisplay>,
+}
+
+impl PingResult {
+    fn new(seq: u32, result: ResponseResult, start: Instant) -> Self {
+        let ok = result.error_code == SwbusErrorCode::Ok;
+        let header = result.msg.and_then(|msg| msg.header);
+        PingResult {
+            seq,
+            ttl: header.as_ref().filter(|_| ok).map(|header| header.ttl),
+            rtt_ms: ok.then(|| start.elapsed().as_secs_f64() * 1000.0),
+            source: header
+                .and_then(|header| header.source)
+                .filter(|_| !ok)
+                .map(|sp| sp.to_longest_path()),
+            error: (!ok).then(|| StatusDisplay::new(result.error_code, &result.error_message)),
+        }
+    }
+}
+
 impl CmdHandler for PingCmd {
     async fn handle(&self, ctx: &super::CommandContext) {
         // Create a channel to receive response
@@ -36,7 +67,9 @@ impl CmdHandler for PingCmd {
         ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
 
         // Send ping messages
-        info!("PING {}", self.dest.to_longest_path());
+        if ctx.output.is_table() {
+            info!("PING {}", self.dest.to_longest_path());
+        }
         for i in 0..self.count {
             let header = SwbusMessageHeader::new(src_sp.clone(), self.dest.clone(), ctx.id_generator.generate());
             let header_id = header.id;
@@ -49,44 +82,53 @@ impl CmdHandler for PingCmd {
 
             // wait on the channel to receive response or timeout
             let result = wait_for_response(&mut recv_queue_rx, header_id, self.timeout).await;
-            match result.error_code {
-                SwbusErrorCode::Ok => {
-                    let elapsed = start.elapsed();
-                    info!(
-                        "Response received: ping_seq={}, ttl={}, time={:.3}ms",
-                        i,
-                        result
-                            .msg
-                            .expect("SwbusMessage shouldn't be None in success case")
-                            .header
-                            .unwrap()
-                            .ttl,
-                        elapsed.as_secs_f64() * 1000.0
-                    );
-                }
-                SwbusErrorCode::Timeout => {
-                    info!("ping_seq {}: request timeout", i);
-                }
-                _ => {
-                    let src_sp = match result.msg {
-                        Some(msg) => format!("{} => ", msg.header.unwrap().source.unwrap().to_longest_path()),
-                        None => "".to_string(),
-                    };
-                    info!(
-                        "ping_seq {}: {}{}:{}",
-                        i,
-                        src_sp,
-                        result
-                            .error_code
-                            .as_str_name()
-                            .strip_prefix("SWBUS_ERROR_CODE_")
-                            .unwrap_or(result.error_code.as_str_name()),
-                        result.error_message
-                    );
-                }
+            if ctx.output.is_table() {
+                print_result(i, result, start);
+            } else {
+                info!("{}", ctx.output.render_record(&PingResult::new(i, result, start)));
             }
 
             tokio::time::sleep(tokio::time::Duration::from_secs(self.interval as u64)).await;
         }
     }
 }
+
+/// Print the result of ping `seq` sent at `start` as a line of text.
+fn print_result(seq: u32, result: ResponseResult, start: Instant) {
+    match result.error_code {
+        SwbusErrorCode::Ok => {
+            let elapsed = start.elapsed();
+            info!(
+                "Response received: ping_seq={}, ttl={}, time={:.3}ms",
+                seq,
+                result
+                    .msg
+                    .expect("SwbusMessage shouldn't be None in success case")
+                    .header
+                    .unwrap()
+                    .ttl,
+                elapsed.as_secs_f64() * 1000.0
+            );
+        }
+        SwbusErrorCode::Timeout => {
+            info!("ping_seq {}: request timeout", seq);
+        }
+        _ => {
+            let src_sp = match result.msg {
+                Some(msg) => format!("{} => ", msg.header.unwrap().source.unwrap().to_longest_path()),
+                None => "".to_string(),
+            };
+            info!(
+                "ping_seq {}: {}{}:{}",
+                seq,
+                src_sp,
+                result
+                    .error_code
+                    .as_str_name()
+                    .strip_prefix("SWBUS_ERROR_CODE_")
+                    .unwrap_or(result.error_code.as_str_name()),
+                result.error_message
+            );
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use super::CmdHandler;
+use clap::Parser;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use swbus_edge::trace::new_trace_id;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::recording::{read_recording, Direction};
+use swbus_proto::swbus::*;
+use tokio::time::{self, Duration, Instant};
+use tracing::{error, info};
+
+#[derive(Parser, Debug)]
+pub struct ReplayCmd {
+    /// File recorded by the capture command, or by an edge runtime
+    #[arg(short = 'i', long)]
+    input: String,
+
+    /// Replay speed, e.g. 2.0 to send the messages twice as fast as they were captured
+    #[arg(short = 's', long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Only replay the messages recorded by an edge runtime in this direction: sent or received
+    #[arg(short = 'd', long)]
+    direction: Option<Direction>,
+
+    /// Send the messages with the ids and trace ids they were recorded with. By default they get new
+    /// ones, so that the receivers don't take them for retries of the recorded messages.
+    #[arg(long)]
+    keep_ids: bool,
+}
+
+/// New ids of the replayed messages, consistent across the recording: a response answers the replayed
+/// request, and the messages of a trace stay in one trace.
+#[derive(Default)]
+struct IdRewriter {
+    id_generator: MessageIdGenerator,
+    ids: HashMap<u64, u64>,
+    trace_ids: HashMap<String, String>,
+}
+
+impl IdRewriter {
+    fn id(&mut self, id: u64) -> u64 {
+        *self.ids.entry(id).or_insert_with(|| self.id_generator.generate())
+    }
+
+    fn rewrite(&mut self, message: &mut SwbusMessage) {
+        if let Some(header) = message.header.as_mut() {
+            header.id = self.id(header.id);
+            if !header.trace_id.is_empty() {
+                header.trace_id = self
+                    .trace_ids
+                    .entry(header.trace_id.clone())
+                    .or_insert_with(new_trace_id)
+                    .clone();
+            }
+        }
+        if let Some(swbus_message::Body::Response(response)) = message.body.as_mut() {
+            response.request_id = self.id(response.request_id);
+        }
+    }
+}
+
+impl CmdHandler for ReplayCmd {
+    async fn handle(&self, ctx: &super::CommandContext) {
+        if self.speed <= 0.0 {
+            error!("Replay speed must be positive");
+            return;
+        }
+        let mut recording = match File::open(&self.input).and_then(|file| read_recording(BufReader::new(file))) {
+            Ok(recording) => recording,
+            Err(e) => {
+                error!("Failed to read {}: {}", self.input, e);
+                return;
+            }
+        };
+        if let Some(direction) = self.direction {
+            recording.retain(|recorded| recorded.direction == Some(direction));
+        }
+
+        info!("Replaying {} messages from {}", recording.len(), self.input);
+        // the timing is kept from the first message replayed
+        let first_ms = recording.first().map_or(0, |recorded| recorded.time_ms);
+        let mut id_rewriter = IdRewriter::default();
+        let start = Instant::now();
+        for mut recorded in recording {
+            let offset = Duration::from_millis(recorded.time_ms.saturating_sub(first_ms));
+            time::sleep_until(start + offset.div_f64(self.speed)).await;
+            if !self.keep_ids {
+                id_rewriter.rewrite(&mut recorded.message);
+            }
+            if let Err(e) = ctx.runtime.send(recorded.message).await {
+                error!("Failed to send message: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replayed_response_answers_replayed_request() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/ha-scope/vdpu0:haset0").unwrap();
+        let mut header = SwbusMessageHeader::new(sp.clone(), sp, 10);
+        header.trace_id = "trace".to_string();
+        let mut request = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+        let mut response = SwbusMessage::new_response(&request, None, SwbusErrorCode::Ok, "", 11, None);
+
+        let mut id_rewriter = IdRewriter::default();
+        id_rewriter.rewrite(&mut request);
+        id_rewriter.rewrite(&mut response);
+
+        let request_header = request.header.unwrap();
+        let response_header = response.header.unwrap();
+        assert_ne!(request_header.id, 10);
+        assert_ne!(response_header.id, request_header.id);
+        let Some(swbus_message::Body::Response(response)) = response.body else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.request_id, request_header.id);
+        assert_ne!(request_header.trace_id, "trace");
+        assert_eq!(response_header.trace_id, request_header.trace_id);
+    }
+}
@@ -0,0 +1,51 @@
+use super::CmdHandler;
+use crate::output::StatusDisplay;
+use crate::{wait_for_response, CommandContext};
+use clap::Parser;
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Reload the TLS certificates of the local swbusd and reconnect its peers with them, one at a time.
+#[derive(Parser, Debug)]
+pub struct RotateTlsCmd {
+    /// Timeout in seconds
+    #[arg(short = 't', long, default_value_t = 60)]
+    timeout: u32,
+}
+
+impl CmdHandler for RotateTlsCmd {
+    async fn handle(&self, ctx: &CommandContext) {
+        let (recv_queue_tx, mut recv_queue_rx) = mpsc::channel::<SwbusMessage>(1);
+        let mut src_sp = ctx.sp.clone();
+        src_sp.resource_type = "rotate-tls".to_string();
+        src_sp.resource_id = "0".to_string();
+        ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
+
+        let header = SwbusMessageHeader::new(src_sp, ctx.sp.to_swbusd_service_path(), ctx.id_generator.generate());
+        let request_id = header.id;
+        let request = SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(ManagementRequest::new(
+                ManagementRequestType::SwbusdRotateTls,
+            ))),
+        };
+        ctx.runtime.send(request).await.unwrap();
+
+        // swbusd responds once every peer is reconnected
+        let result = wait_for_response(&mut recv_queue_rx, request_id, self.timeout).await;
+        if !ctx.output.is_table() {
+            ctx.output
+                .print_value(&StatusDisplay::new(result.error_code, &result.error_message));
+            return;
+        }
+        match result.error_code {
+            SwbusErrorCode::Ok => info!("TLS certificates rotated: {}", result.error_message),
+            error_code => info!(
+                "Failed to rotate TLS certificates: {}:{}",
+                error_code.as_str_name(),
+                result.error_message
+            ),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use super::{CliSubCmd, CmdHandler, CommandContext};
+use crate::wait_for_response;
+use clap::{CommandFactory, Parser};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, Helper, Highlighter, Hinter, Validator};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+const PROMPT: &str = "swbus> ";
+
+/// Time to wait for the route table of swbusd in seconds
+const ROUTE_QUERY_TIMEOUT: u32 = 2;
+
+#[derive(Parser, Debug)]
+pub struct ShellCmd {
+    /// File to keep the command history in. Default is ~/.swbus_cli_history.
+    #[arg(long)]
+    history_file: Option<PathBuf>,
+}
+
+/// A command line typed in the shell
+#[derive(Parser, Debug)]
+#[command(name = "", no_binary_name = true)]
+struct ShellLine {
+    #[command(subcommand)]
+    subcommand: CliSubCmd,
+}
+
+/// Completes the commands of the shell, and the service paths in swbusd's route table as their
+/// arguments.
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ShellHelper {
+    service_paths: Arc<Mutex<Vec<String>>>,
+}
+
+impl ShellHelper {
+    fn candidates(&self, words: &[&str]) -> Vec<String> {
+        let mut cmd = ShellLine::command();
+        for word in words {
+            let Some(sub_cmd) = cmd.find_subcommand(word).cloned() else {
+                break;
+            };
+            cmd = sub_cmd;
+        }
+
+        let mut candidates: Vec<String> = cmd.get_subcommands().map(|c| c.get_name().to_string()).collect();
+        if words.is_empty() {
+            candidates.extend(["exit", "quit"].map(String::from));
+        }
+        if cmd.get_positionals().next().is_some() {
+            candidates.extend(self.service_paths.lock().unwrap().iter().cloned());
+        }
+        candidates
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let prefix = &line[start..];
+        let mut candidates: Vec<String> = self
+            .candidates(&words)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl ShellCmd {
+    /// Read commands and run them over the connection to swbusd until exit or EOF.
+    pub async fn run(&self, ctx: &CommandContext) {
+        let service_paths = Arc::new(Mutex::new(Vec::new()));
+        let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                error!("Failed to start the shell: {e}");
+                return;
+            }
+        };
+        editor.set_helper(Some(ShellHelper {
+            service_paths: service_paths.clone(),
+        }));
+        let history_file = self.history_file.clone().or_else(default_history_file);
+        if let Some(history_file) = &history_file {
+            // there is no history on the first run
+            let _ = editor.load_history(history_file);
+        }
+
+        loop {
+            // routes come and go, refresh them for completion before every command
+            *service_paths.lock().unwrap() = query_service_paths(ctx).await;
+
+            let line = match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    error!("Failed to read command: {e}");
+                    break;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(line);
+            if line == "exit" || line == "quit" {
+                break;
+            }
+
+            let shell_line = match ShellLine::try_parse_from(line.split_whitespace()) {
+                Ok(shell_line) => shell_line,
+                Err(e) => {
+                    // also prints the help
+                    let _ = e.print();
+                    continue;
+                }
+            };
+            tokio::select! {
+                _ = shell_line.subcommand.handle(ctx) => {}
+                _ = tokio::signal::ctrl_c() => info!("Interrupted"),
+            }
+        }
+
+        if let Some(history_file) = &history_file {
+            if let Err(e) = editor.save_history(history_file) {
+                error!("Failed to save the history to {}: {e}", history_file.display());
+            }
+        }
+    }
+}
+
+fn default_history_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".swbus_cli_history"))
+}
+
+/// Service paths in the route table of swbusd, as many of them as could be queried.
+async fn query_service_paths(ctx: &CommandContext) -> Vec<String> {
+    let (recv_queue_tx, mut recv_queue_rx) = mpsc::channel::<SwbusMessage>(1);
+    let mut src_sp = ctx.sp.clone();
+    src_sp.resource_type = "shell".to_string();
+    src_sp.resource_id = "0".to_string();
+    ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
+
+    // a page at a time, until the last one
+    let mut service_paths = Vec::new();
+    let mut cursor = String::new();
+    loop {
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdGetRoutes);
+        if !cursor.is_empty() {
+            mgmt_request.arguments.push(ManagementRequestArg {
+                name: "cursor".to_string(),
+                value: cursor,
+            });
+        }
+        let header = SwbusMessageHeader::new(
+            src_sp.clone(),
+            ctx.sp.to_swbusd_service_path(),
+            ctx.id_generator.generate(),
+        );
+        let request_id = header.id;
+        let request = SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(mgmt_request)),
+        };
+        if ctx.runtime.send(request).await.is_err() {
+            return service_paths;
+        }
+
+        let result = wait_for_response(&mut recv_queue_rx, request_id, ROUTE_QUERY_TIMEOUT).await;
+        let Some(swbus_message::Body::Response(RequestResponse {
+            response_body: Some(request_response::ResponseBody::RouteQueryResult(routes)),
+            ..
+        })) = result.msg.and_then(|msg| msg.body)
+        else {
+            return service_paths;
+        };
+        service_paths.extend(
+            routes
+                .entries
+                .iter()
+                .filter_map(|entry| entry.service_path.as_ref())
+                .map(|sp| sp.to_longest_path()),
+        );
+        if routes.next_cursor.is_empty() {
+            return service_paths;
+        }
+        cursor = routes.next_cursor;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(line: &str, service_paths: &[&str]) -> (usize, Vec<String>) {
+        let helper = ShellHelper {
+            service_paths: Arc::new(Mutex::new(service_paths.iter().map(|sp| sp.to_string()).collect())),
+        };
+        let history = DefaultHistory::new();
+        let ctx = rustyline::Context::new(&history);
+        helper.complete(line, line.len(), &ctx).unwrap()
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        let (start, candidates) = complete("sh", &[]);
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec!["shell", "show"]);
+
+        let (start, candidates) = complete("show sw", &[]);
+        assert_eq!(start, 5);
+        assert_eq!(candidates, vec!["swbusd"]);
+
+        let (_, candidates) = complete("e", &[]);
+        assert_eq!(candidates, vec!["exit"]);
+    }
+
+    #[test]
+    fn test_complete_service_paths() {
+        let service_paths = ["region-a.cluster-a.10.0.0.1-dpu0", "region-a.cluster-a.10.0.0.2-dpu0"];
+        let (start, candidates) = complete("ping -c 3 region-a.cluster-a.10.0.0.2", &service_paths);
+        assert_eq!(start, 10);
+        assert_eq!(candidates, vec!["region-a.cluster-a.10.0.0.2-dpu0"]);
+
+        // show takes no service path
+        let (_, candidates) = complete("show ", &service_paths);
+        assert!(candidates.iter().all(|candidate| !candidate.starts_with("region-a")));
+    }
+
+    #[test]
+    fn test_parse_shell_line() {
+        let line = ShellLine::try_parse_from("ping -c 1 region-a.cluster-a.10.0.0.1-dpu0".split_whitespace()).unwrap();
+        assert!(matches!(line.subcommand, CliSubCmd::Ping(_)));
+        assert!(ShellLine::try_parse_from("bogus".split_whitespace()).is_err());
+    }
+}
@@ -1,11 +1,12 @@
+use crate::output::OutputFormat;
 use crate::show::ShowCmdHandler;
 use crate::CommandContext;
 use chrono::{DateTime, Local};
 use clap::Parser;
 use serde_json::to_string_pretty;
 use swbus_actor::state::{
-    incoming::IncomingTableEntry, internal::InternalTableData, outgoing::get_elapsed_time, outgoing::SentMessageEntry,
-    outgoing::UnackedMessage, ActorStateDump,
+    incoming::DeadLetter, incoming::IncomingTableEntry, internal::InternalTableData, outgoing::get_elapsed_time,
+    outgoing::SentMessageEntry, outgoing::UnackedMessage, ActorStateDump,
 };
 use swbus_proto::swbus::*;
 use tabled::settings::{object::Rows, style::Style, Alignment, Modify, Panel};
@@ -90,6 +91,52 @@ impl IncomingStateDisplay {
     }
 }
 
+#[derive(Tabled)]
+struct DeadLetterDisplay {
+    key: String,
+    details: String,
+}
+
+impl DeadLetterDisplay {
+    fn from_dead_letter((key, dead_letter): (&String, &DeadLetter)) -> Self {
+        let details = vec![
+            KeyValue {
+                attribute: "source".to_string(),
+                value: dead_letter.entry.source.to_longest_path(),
+            },
+            KeyValue {
+                attribute: "request-id".to_string(),
+                value: dead_letter.entry.request_id.to_string(),
+            },
+            KeyValue {
+                attribute: "message/value".to_string(),
+                value: to_string_pretty(&dead_letter.entry.msg.data).unwrap_or("INV".to_string()),
+            },
+            KeyValue {
+                attribute: "failures".to_string(),
+                value: dead_letter.entry.failures.to_string(),
+            },
+            KeyValue {
+                attribute: "error".to_string(),
+                value: dead_letter.error.clone(),
+            },
+            KeyValue {
+                attribute: "quarantined-time".to_string(),
+                value: unix_secs_to_string(dead_letter.quarantined_time),
+            },
+            KeyValue {
+                attribute: "redeliveries".to_string(),
+                value: dead_letter.redeliveries.to_string(),
+            },
+        ];
+        let table = Table::new(details).with(Style::ascii().remove_frame()).to_string();
+        DeadLetterDisplay {
+            key: key.clone(),
+            details: table,
+        }
+    }
+}
+
 #[derive(Tabled)]
 struct OutgoingUnackedMessageDisplay {
     message: String,
@@ -260,7 +307,7 @@ impl ShowCmdHandler for ShowActorCmd {
         }
     }
 
-    fn process_response(&self, response: &RequestResponse) {
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
         let result = match &response.response_body {
             Some(request_response::ResponseBody::ManagementQueryResult(ref result)) => &result.value,
             _ => {
@@ -270,6 +317,14 @@ impl ShowCmdHandler for ShowActorCmd {
         };
 
         let state: ActorStateDump = serde_json::from_str(result).unwrap();
+        if !output.is_table() {
+            output.print_value(&state);
+            return;
+        }
+
+        if state.restarts > 0 {
+            info!("Restarted {} times by its supervisor", state.restarts);
+        }
 
         // convert to table for display
         let incoming_state_display = state
@@ -285,6 +340,22 @@ impl ShowCmdHandler for ShowActorCmd {
 
         info!("{}", incoming_state_table);
 
+        if !state.dead_letters.is_empty() {
+            // convert to table for display
+            let dead_letter_display = state
+                .dead_letters
+                .iter()
+                .map(DeadLetterDisplay::from_dead_letter)
+                .collect::<Vec<DeadLetterDisplay>>();
+            let dead_letter_table = Table::new(dead_letter_display)
+                .with(Panel::header("Quarantined Messages"))
+                .with(Modify::list(Rows::first(), Alignment::center()))
+                .with(Style::modern())
+                .to_string();
+
+            info!("{}", dead_letter_table);
+        }
+
         // convert to table for display
         let internal_state_display = state
             .internal
@@ -1,7 +1,9 @@
 mod actor;
+mod techsupport;
 use clap::Parser;
 use swbus_proto::swbus::*;
 
+use crate::output::OutputFormat;
 use crate::show::ShowCmdHandler;
 use crate::CommandContext;
 
@@ -14,16 +16,28 @@ pub struct ShowHamgrdCmd {
 #[derive(Parser, Debug)]
 enum HamgrdCmd {
     Actor(actor::ShowActorCmd),
+    Techsupport(techsupport::ShowTechsupportCmd),
 }
 
 impl ShowCmdHandler for ShowHamgrdCmd {
     fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
-        let HamgrdCmd::Actor(sub_cmd) = &self.subcommand;
-        sub_cmd.create_request(ctx, src_sp)
+        match &self.subcommand {
+            HamgrdCmd::Actor(sub_cmd) => sub_cmd.create_request(ctx, src_sp),
+            HamgrdCmd::Techsupport(sub_cmd) => sub_cmd.create_request(ctx, src_sp),
+        }
     }
 
-    fn process_response(&self, response: &RequestResponse) {
-        let HamgrdCmd::Actor(sub_cmd) = &self.subcommand;
-        sub_cmd.process_response(response);
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
+        match &self.subcommand {
+            HamgrdCmd::Actor(sub_cmd) => sub_cmd.process_response(response, output),
+            HamgrdCmd::Techsupport(sub_cmd) => sub_cmd.process_response(response, output),
+        }
+    }
+
+    fn timeout(&self) -> u32 {
+        match &self.subcommand {
+            HamgrdCmd::Actor(sub_cmd) => sub_cmd.timeout(),
+            HamgrdCmd::Techsupport(sub_cmd) => sub_cmd.timeout(),
+        }
     }
 }
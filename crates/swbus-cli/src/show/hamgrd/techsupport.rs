@@ -0,0 +1,48 @@
+use crate::output::OutputFormat;
+use crate::show::ShowCmdHandler;
+use crate::CommandContext;
+use clap::Parser;
+use swbus_proto::swbus::*;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct ShowTechsupportCmd {
+    /// The subdirectory of /var/dump on the hamgrd host where the dump tarball is written
+    #[arg(short, long)]
+    path: Option<String>,
+}
+
+impl ShowCmdHandler for ShowTechsupportCmd {
+    fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
+        let mut mgmt_req = ManagementRequest::new(ManagementRequestType::HamgrdTechsupportDump);
+        if let Some(path) = &self.path {
+            mgmt_req.arguments.push(ManagementRequestArg {
+                name: "path".to_string(),
+                value: path.clone(),
+            });
+        }
+        let mut dest_sp = ctx.sp.to_swbusd_service_path();
+        dest_sp.join(&ServicePath::from_string("/hamgrd/0/techsupport/0").unwrap());
+        let header = SwbusMessageHeader::new(src_sp.clone(), dest_sp, ctx.id_generator.generate());
+
+        SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(mgmt_req)),
+        }
+    }
+
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
+        match &response.response_body {
+            Some(request_response::ResponseBody::ManagementQueryResult(ref result)) if output.is_table() => {
+                info!("{}", result.value)
+            }
+            Some(request_response::ResponseBody::ManagementQueryResult(ref result)) => output.print_value(result),
+            _ => info!("Expecting ManagementQueryResult but got something else: {:?}", response),
+        }
+    }
+
+    fn timeout(&self) -> u32 {
+        // collecting the dump queries every actor and swbusd before packing the tarball
+        60
+    }
+}
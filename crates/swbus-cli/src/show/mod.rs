@@ -1,5 +1,6 @@
 pub mod hamgrd;
 pub mod swbusd;
+use crate::output::OutputFormat;
 use crate::wait_for_response;
 use clap::Parser;
 use swbus_proto::swbus::*;
@@ -22,7 +23,12 @@ enum ShowSubCmd {
 
 trait ShowCmdHandler {
     fn create_request(&self, ctx: &super::CommandContext, src_sp: &ServicePath) -> SwbusMessage;
-    fn process_response(&self, response: &RequestResponse);
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat);
+
+    /// Time to wait for the response in seconds
+    fn timeout(&self) -> u32 {
+        CMD_TIMEOUT
+    }
 }
 
 impl super::CmdHandler for ShowCmd {
@@ -49,25 +55,23 @@ impl super::CmdHandler for ShowCmd {
         ctx.runtime.send(request_msg).await.unwrap();
 
         // wait on the channel to receive response
-        let result = wait_for_response(&mut recv_queue_rx, request_id, CMD_TIMEOUT).await;
+        let result = wait_for_response(&mut recv_queue_rx, request_id, sub_cmd.timeout()).await;
         match result.error_code {
             SwbusErrorCode::Ok => {
                 let body = result.msg.unwrap().body.unwrap();
                 match body {
                     swbus_message::Body::Response(response) => {
-                        sub_cmd.process_response(&response);
+                        sub_cmd.process_response(&response, ctx.output);
                     }
                     _ => {
                         info!("Invalid response");
                     }
                 }
             }
-            SwbusErrorCode::Timeout => {
+            SwbusErrorCode::Timeout if ctx.output.is_table() => {
                 info!("Request timeout");
             }
-            _ => {
-                info!("{}:{}", result.error_code.as_str_name(), result.error_message);
-            }
+            _ => ctx.output.print_error(result.error_code, &result.error_message),
         }
     }
 }
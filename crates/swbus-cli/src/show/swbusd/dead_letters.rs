@@ -0,0 +1,97 @@
+use crate::output::OutputFormat;
+use crate::show::ShowCmdHandler;
+use crate::CommandContext;
+use chrono::{DateTime, Local};
+use clap::Parser;
+use prost::Message;
+use serde::Serialize;
+use swbus_proto::swbus::*;
+use tabled::Tabled;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct ShowDeadLettersCmd {
+    /// Show the payloads of the messages too, which swbusd redacts otherwise
+    #[arg(long)]
+    payloads: bool,
+}
+
+#[derive(Tabled, Serialize)]
+struct DeadLetterDisplay {
+    time: String,
+    reason: String,
+    source: String,
+    destination: String,
+    message: String,
+}
+
+fn unix_millis_to_string(unix_millis: u64) -> String {
+    match DateTime::from_timestamp_millis(unix_millis as i64) {
+        Some(naive) => {
+            let datetime: DateTime<Local> = naive.with_timezone(&Local);
+            datetime.format("%Y:%m:%d %H:%M:%S%.3f").to_string()
+        }
+        None => "INV".to_string(),
+    }
+}
+
+impl DeadLetterDisplay {
+    fn from_dead_letter(dead_letter: &DeadLetter) -> Self {
+        let time = unix_millis_to_string(dead_letter.timestamp_in_ms);
+        let reason = dead_letter.reason.clone();
+        let Ok(message) = SwbusMessage::decode(dead_letter.message.as_slice()) else {
+            return DeadLetterDisplay {
+                time,
+                reason,
+                source: String::new(),
+                destination: String::new(),
+                message: "<invalid message>".to_string(),
+            };
+        };
+        let sp = |sp: Option<&ServicePath>| sp.map(|sp| sp.to_longest_path()).unwrap_or_default();
+        let header = message.header.as_ref();
+        DeadLetterDisplay {
+            time,
+            reason,
+            source: sp(header.and_then(|h| h.source.as_ref())),
+            destination: sp(header.and_then(|h| h.destination.as_ref())),
+            message: serde_json::to_string(&message.body).unwrap_or_default(),
+        }
+    }
+}
+
+impl ShowCmdHandler for ShowDeadLettersCmd {
+    fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
+        let mut mgmt_req = ManagementRequest::new(ManagementRequestType::SwbusdGetDeadLetters);
+        if self.payloads {
+            mgmt_req.arguments.push(ManagementRequestArg {
+                name: "payloads".to_string(),
+                value: String::new(),
+            });
+        }
+
+        let swbusd_sp = ctx.sp.to_swbusd_service_path();
+        let header = SwbusMessageHeader::new(src_sp.clone(), swbusd_sp, ctx.id_generator.generate());
+        SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(mgmt_req)),
+        }
+    }
+
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
+        let dead_letters = match &response.response_body {
+            Some(request_response::ResponseBody::DeadLetterQueryResult(result)) => result,
+            _ => {
+                info!("Expecting DeadLetterQueryResult but got something else: {:?}", response);
+                return;
+            }
+        };
+
+        let dead_letters: Vec<DeadLetterDisplay> = dead_letters
+            .entries
+            .iter()
+            .map(DeadLetterDisplay::from_dead_letter)
+            .collect();
+        output.print(&dead_letters);
+    }
+}
@@ -1,8 +1,11 @@
+mod dead_letters;
+mod reachability;
 mod route;
 
 use clap::Parser;
 use swbus_proto::swbus::*;
 
+use crate::output::OutputFormat;
 use crate::show::ShowCmdHandler;
 use crate::CommandContext;
 
@@ -15,16 +18,32 @@ pub struct ShowSwbusdCmd {
 #[derive(Parser, Debug)]
 enum SwbusdCmd {
     Route(route::ShowRouteCmd),
+    DeadLetters(dead_letters::ShowDeadLettersCmd),
+    Reachability(reachability::ShowReachabilityCmd),
 }
 
 impl ShowCmdHandler for ShowSwbusdCmd {
     fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
-        let SwbusdCmd::Route(sub_cmd) = &self.subcommand;
-        sub_cmd.create_request(ctx, src_sp)
+        match &self.subcommand {
+            SwbusdCmd::Route(sub_cmd) => sub_cmd.create_request(ctx, src_sp),
+            SwbusdCmd::DeadLetters(sub_cmd) => sub_cmd.create_request(ctx, src_sp),
+            SwbusdCmd::Reachability(sub_cmd) => sub_cmd.create_request(ctx, src_sp),
+        }
     }
 
-    fn process_response(&self, response: &RequestResponse) {
-        let SwbusdCmd::Route(sub_cmd) = &self.subcommand;
-        sub_cmd.process_response(response);
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
+        match &self.subcommand {
+            SwbusdCmd::Route(sub_cmd) => sub_cmd.process_response(response, output),
+            SwbusdCmd::DeadLetters(sub_cmd) => sub_cmd.process_response(response, output),
+            SwbusdCmd::Reachability(sub_cmd) => sub_cmd.process_response(response, output),
+        }
+    }
+
+    fn timeout(&self) -> u32 {
+        match &self.subcommand {
+            SwbusdCmd::Route(sub_cmd) => sub_cmd.timeout(),
+            SwbusdCmd::DeadLetters(sub_cmd) => sub_cmd.timeout(),
+            SwbusdCmd::Reachability(sub_cmd) => sub_cmd.timeout(),
+        }
     }
 }
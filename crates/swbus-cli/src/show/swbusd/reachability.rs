@@ -0,0 +1,89 @@
+use crate::output::OutputFormat;
+use crate::show::{ShowCmdHandler, CMD_TIMEOUT};
+use crate::CommandContext;
+use clap::Parser;
+use serde::Serialize;
+use swbus_proto::swbus::*;
+use tabled::Tabled;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+pub struct ShowReachabilityCmd {
+    /// How long swbusd waits for the response to each ping, in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    timeout_ms: u64,
+}
+
+#[derive(Tabled, Serialize)]
+struct ReachabilityDisplay {
+    destination: String,
+    #[tabled(rename = "rtt", display_with = "display_rtt")]
+    rtt_ms: Option<f64>,
+    error: String,
+}
+
+fn display_rtt(rtt_ms: &Option<f64>) -> String {
+    match rtt_ms {
+        Some(rtt_ms) => format!("{rtt_ms:.3}ms"),
+        None => "-".to_string(),
+    }
+}
+
+impl ReachabilityDisplay {
+    fn from_entry(entry: &ReachabilityEntry) -> Self {
+        let destination = entry
+            .destination
+            .as_ref()
+            .map(|sp| sp.to_longest_path())
+            .unwrap_or_default();
+        match entry.error_code() {
+            SwbusErrorCode::Ok => ReachabilityDisplay {
+                destination,
+                rtt_ms: Some(entry.rtt_in_us as f64 / 1000.0),
+                error: String::new(),
+            },
+            error_code => ReachabilityDisplay {
+                destination,
+                rtt_ms: None,
+                error: format!("{}: {}", error_code.as_str_name(), entry.error_message),
+            },
+        }
+    }
+}
+
+impl ShowCmdHandler for ShowReachabilityCmd {
+    fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
+        let mut mgmt_req = ManagementRequest::new(ManagementRequestType::SwbusdPingAll);
+        mgmt_req.arguments.push(ManagementRequestArg {
+            name: "timeout_ms".to_string(),
+            value: self.timeout_ms.to_string(),
+        });
+
+        let swbusd_sp = ctx.sp.to_swbusd_service_path();
+        let header = SwbusMessageHeader::new(src_sp.clone(), swbusd_sp, ctx.id_generator.generate());
+        SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::ManagementRequest(mgmt_req)),
+        }
+    }
+
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
+        let report = match &response.response_body {
+            Some(request_response::ResponseBody::ReachabilityReport(report)) => report,
+            _ => {
+                info!("Expecting ReachabilityReport but got something else: {:?}", response);
+                return;
+            }
+        };
+
+        let mut entries: Vec<ReachabilityDisplay> =
+            report.entries.iter().map(ReachabilityDisplay::from_entry).collect();
+        entries.sort_by(|a, b| a.destination.cmp(&b.destination));
+        output.print(&entries);
+    }
+
+    fn timeout(&self) -> u32 {
+        // swbusd responds once every ping is answered or timed out
+        CMD_TIMEOUT + self.timeout_ms.div_ceil(1000) as u32
+    }
+}
@@ -1,25 +1,71 @@
+use crate::output::OutputFormat;
 use crate::show::ShowCmdHandler;
 use crate::CommandContext;
 use clap::Parser;
+use serde::Serialize;
 use swbus_proto::swbus::*;
-use tabled::{Table, Tabled};
+use tabled::Tabled;
 use tracing::info;
 
 #[derive(Parser, Debug)]
-pub struct ShowRouteCmd {}
+pub struct ShowRouteCmd {
+    /// Only the routes of this scope or wider, e.g. cluster, region or global
+    #[arg(long)]
+    scope: Option<String>,
 
-#[derive(Tabled)]
+    /// Only the routes whose service path starts with this, e.g. region-a.cluster-a
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Only the routes through this connection, by its id or the service path of its peer
+    #[arg(long)]
+    connection: Option<String>,
+
+    /// Show the page after this one, as printed at the end of the previous page
+    #[arg(long)]
+    cursor: Option<String>,
+
+    /// Max rows in the page, at most 1000
+    #[arg(short, long)]
+    limit: Option<u32>,
+}
+
+#[derive(Tabled, Serialize)]
 struct RouteDisplay {
     service_path: String,
     hop_count: u32,
+    preference: u32,
     nh_id: String,
     nh_scope: String,
     nh_service_path: String,
 }
 
+impl ShowRouteCmd {
+    fn request(&self) -> ManagementRequest {
+        let mut mgmt_req = ManagementRequest::new(ManagementRequestType::SwbusdGetRoutes);
+        let limit = self.limit.map(|limit| limit.to_string());
+        let args = [
+            ("scope", &self.scope),
+            ("prefix", &self.prefix),
+            ("connection", &self.connection),
+            ("cursor", &self.cursor),
+            ("limit", &limit),
+        ];
+        for (name, value) in args {
+            if let Some(value) = value {
+                mgmt_req.arguments.push(ManagementRequestArg {
+                    name: name.to_string(),
+                    value: value.clone(),
+                });
+            }
+        }
+        mgmt_req
+    }
+}
+
 impl ShowCmdHandler for ShowRouteCmd {
     fn create_request(&self, ctx: &CommandContext, src_sp: &ServicePath) -> SwbusMessage {
-        let mgmt_req = ManagementRequest::new(ManagementRequestType::SwbusdGetRoutes);
+        let mgmt_req = self.request();
         let swbusd_sp = ctx.sp.to_swbusd_service_path();
         let header = SwbusMessageHeader::new(src_sp.clone(), swbusd_sp, ctx.id_generator.generate());
 
@@ -29,7 +75,7 @@ impl ShowCmdHandler for ShowRouteCmd {
         }
     }
 
-    fn process_response(&self, response: &RequestResponse) {
+    fn process_response(&self, response: &RequestResponse, output: OutputFormat) {
         let routes = match &response.response_body {
             Some(request_response::ResponseBody::RouteQueryResult(route_result)) => route_result,
             _ => {
@@ -38,7 +84,7 @@ impl ShowCmdHandler for ShowRouteCmd {
             }
         };
 
-        let routes: Vec<RouteDisplay> = routes
+        let page: Vec<RouteDisplay> = routes
             .entries
             .iter()
             .map(|entry| RouteDisplay {
@@ -48,6 +94,7 @@ impl ShowCmdHandler for ShowRouteCmd {
                     .expect("service_path in RouteQueryResult cannot be None")
                     .to_longest_path(),
                 hop_count: entry.hop_count,
+                preference: entry.preference,
                 nh_id: entry.nh_id.clone(),
                 nh_scope: RouteScope::try_from(entry.nh_scope).unwrap().as_str_name().to_string(),
                 nh_service_path: entry
@@ -57,7 +104,54 @@ impl ShowCmdHandler for ShowRouteCmd {
                     .to_longest_path(),
             })
             .collect();
-        let table = Table::new(routes);
-        info!("{}", table)
+        output.print(&page);
+        if !routes.next_cursor.is_empty() {
+            info!(
+                "Showing {} of {} rows, see the next ones with --cursor {}",
+                page.len(),
+                routes.total_entries,
+                routes.next_cursor
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(request: &ManagementRequest) -> Vec<(&str, &str)> {
+        request
+            .arguments
+            .iter()
+            .map(|arg| (arg.name.as_str(), arg.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_request() {
+        let cmd = ShowRouteCmd::parse_from(["route"]);
+        assert!(cmd.request().arguments.is_empty());
+
+        let cmd = ShowRouteCmd::parse_from([
+            "route",
+            "--scope",
+            "region",
+            "--prefix",
+            "region-a",
+            "--cursor",
+            "region-a.cluster-a",
+            "--limit",
+            "50",
+        ]);
+        assert_eq!(
+            args(&cmd.request()),
+            vec![
+                ("scope", "region"),
+                ("prefix", "region-a"),
+                ("cursor", "region-a.cluster-a"),
+                ("limit", "50"),
+            ]
+        );
     }
 }
@@ -1,7 +1,11 @@
 use super::CmdHandler;
+use crate::output::StatusDisplay;
 use crate::wait_for_response;
 use clap::Parser;
-use std::time::Instant;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use swbus_proto::swbus::request_response::ResponseBody;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc;
 use tracing::info;
@@ -21,6 +25,26 @@ pub struct TraceRouteCmd {
     dest: ServicePath,
 }
 
+/// A hop of the route in the structured output formats
+#[derive(Serialize)]
+struct TraceHop {
+    hop: usize,
+    service_path: String,
+    rtt_ms: Option<f64>,
+    /// Time the request took from the previous hop by the hop timestamps
+    latency_ms: Option<f64>,
+}
+
+/// The route to the destination in the structured output formats
+#[derive(Serialize)]
+struct TraceRouteResult {
+    hops: Vec<TraceHop>,
+    /// Where the trace stopped if it did not reach the destination
+    source: Option<String>,
+    #[serde(flatten)]
+    error: Option<StatusDisplay>,
+}
+
 impl CmdHandler for TraceRouteCmd {
     async fn handle(&self, ctx: &super::CommandContext) {
         // Create a channel to receive response
@@ -31,12 +55,13 @@ impl CmdHandler for TraceRouteCmd {
         // Register the channel to the runtime to receive response
         ctx.runtime.add_handler(src_sp.clone(), recv_queue_tx);
 
-        // Send ping messages
-        info!(
-            "traceroute to {}, {} hops max",
-            self.dest.to_longest_path(),
-            self.max_hop
-        );
+        if ctx.output.is_table() {
+            info!(
+                "traceroute to {}, {} hops max",
+                self.dest.to_longest_path(),
+                self.max_hop
+            );
+        }
 
         let mut header = SwbusMessageHeader::new(src_sp.clone(), self.dest.clone(), ctx.id_generator.generate());
         header.ttl = self.max_hop;
@@ -48,36 +73,54 @@ impl CmdHandler for TraceRouteCmd {
         let start = Instant::now();
         ctx.runtime.send(trace_route_msg).await.unwrap();
 
-        for i in 0..self.max_hop {
-            // wait on the channel to receive response or timeout
+        // Every hop responds with the hops the request went through so far, so the longest hop list
+        // is the path to the last hop reached. The RTT of a hop is when its response arrived.
+        let mut hops = Vec::new();
+        let mut rtts = HashMap::<ServicePath, Duration>::new();
+        let mut failure = None;
+        for _ in 0..self.max_hop {
             let result = wait_for_response(&mut recv_queue_rx, header_id, self.timeout).await;
-            match result.error_code {
-                SwbusErrorCode::Ok => {
-                    let elapsed = start.elapsed();
-                    let source_sp = result
-                        .msg
-                        .as_ref()
-                        .unwrap()
-                        .header
-                        .as_ref()
-                        .unwrap()
-                        .source
-                        .as_ref()
-                        .unwrap();
-                    info!(
-                        "{}  {}  {:.3}ms  {} hops",
-                        i + 1,
-                        source_sp.to_longest_path(),
-                        elapsed.as_secs_f64() * 1000.0,
-                        (64 - result.msg.as_ref().unwrap().header.as_ref().unwrap().ttl) as u8
-                    );
-                    if source_sp == &self.dest {
-                        break;
-                    }
-                }
-                SwbusErrorCode::Timeout => {
-                    info!("{}  *  *  *", i + 1);
+            if result.error_code != SwbusErrorCode::Ok {
+                failure = Some(result);
+                break;
+            }
+            let msg = result.msg.unwrap();
+            let source_sp = msg.header.unwrap().source.unwrap();
+            rtts.insert(source_sp.clone(), start.elapsed());
+            if let Some(swbus_message::Body::Response(RequestResponse {
+                response_body: Some(ResponseBody::TraceRouteResult(result)),
+                ..
+            })) = msg.body
+            {
+                if result.hops.len() > hops.len() {
+                    hops = result.hops;
                 }
+            }
+            if source_sp == self.dest {
+                break;
+            }
+        }
+
+        let trace = trace_hops(&hops, &rtts);
+        if !ctx.output.is_table() {
+            let result = TraceRouteResult {
+                hops: trace,
+                source: failure
+                    .as_ref()
+                    .and_then(|result| result.msg.as_ref())
+                    .and_then(|msg| msg.header.as_ref())
+                    .and_then(|header| header.source.as_ref())
+                    .map(|sp| sp.to_longest_path()),
+                error: failure.map(|result| StatusDisplay::new(result.error_code, &result.error_message)),
+            };
+            ctx.output.print_value(&result);
+            return;
+        }
+        print_hops(&trace);
+
+        if let Some(result) = failure {
+            match result.error_code {
+                SwbusErrorCode::Timeout => info!("{}  *  *  *", hops.len() + 1),
                 _ => {
                     let src_sp = match result.msg {
                         Some(msg) => msg.header.unwrap().source.unwrap().to_longest_path().to_string(),
@@ -85,7 +128,7 @@ impl CmdHandler for TraceRouteCmd {
                     };
                     info!(
                         "{}  {}  {}({})",
-                        i + 1,
+                        hops.len() + 1,
                         src_sp,
                         result
                             .error_code
@@ -99,3 +142,38 @@ impl CmdHandler for TraceRouteCmd {
         }
     }
 }
+
+/// The hops with their RTT, and the time the request took from the previous hop to reach them by
+/// the hop timestamps, which is only accurate if the clocks of the hops are in sync.
+fn trace_hops(hops: &[TraceRouteHop], rtts: &HashMap<ServicePath, Duration>) -> Vec<TraceHop> {
+    let mut trace_hops = Vec::new();
+    let mut previous_timestamp = None;
+    for (i, hop) in hops.iter().enumerate() {
+        let Some(sp) = hop.service_path.as_ref() else {
+            continue;
+        };
+        trace_hops.push(TraceHop {
+            hop: i + 1,
+            service_path: sp.to_longest_path(),
+            rtt_ms: rtts.get(sp).map(|rtt| rtt.as_secs_f64() * 1000.0),
+            latency_ms: previous_timestamp.map(|previous| hop.timestamp_in_us.saturating_sub(previous) as f64 / 1000.0),
+        });
+        previous_timestamp = Some(hop.timestamp_in_us);
+    }
+    trace_hops
+}
+
+/// Print a line per hop with its RTT and latency from the previous hop.
+fn print_hops(hops: &[TraceHop]) {
+    for hop in hops {
+        let rtt = match hop.rtt_ms {
+            Some(rtt_ms) => format!("{rtt_ms:.3}ms"),
+            None => "*".to_string(),
+        };
+        let latency = match hop.latency_ms {
+            Some(latency_ms) => format!("  +{latency_ms:.3}ms"),
+            None => "".to_string(),
+        };
+        info!("{}  {}  {}{}", hop.hop, hop.service_path, rtt, latency);
+    }
+}
@@ -0,0 +1,330 @@
+//! Embeddable swbus client
+//!
+//! A daemon that wants to talk on swbus without the rest of hamgrd needs three calls:
+//!
+//! ```ignore
+//! // 1. Load the swbusd config, start an edge runtime and wait until swbusd is connected
+//! let client = SwbusClient::connect(SwbusClientOptions::new("dash-telemetry", "0").dpu_slot(0)).await?;
+//! // 2. Get an endpoint at <node>/dash-telemetry/0/<resource_type>/<resource_id>
+//! let endpoint = client.endpoint("counters", "eni");
+//! // 3. Send and receive serde values
+//! endpoint.send(&destination, &counters).await?;
+//! let request: Typed<Query> = endpoint.recv_typed().await?;
+//! ```
+//!
+//! The [`SimpleSwbusEdgeClient`] and [`SwbusEdgeRuntime`] underneath are available for anything the
+//! facade doesn't cover.
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use swbus_config::{swbus_config_from_db, swbus_config_from_yaml, SwbusConfig, SwbusConfigError};
+use swbus_edge::simple_client::{IncomingMessage, MessageBody, MessageId, OutgoingMessage, SimpleSwbusEdgeClient};
+use swbus_edge::swbus_proto::result::SwbusError;
+use swbus_edge::swbus_proto::swbus::{ServicePath, SwbusErrorCode};
+use swbus_edge::SwbusEdgeRuntime;
+use thiserror::Error;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::info;
+
+pub use swbus_edge::{simple_client, swbus_proto};
+
+#[derive(Error, Debug)]
+pub enum SwbusClientError {
+    #[error("Failed to load the swbusd config: {0}")]
+    Config(#[from] SwbusConfigError),
+
+    #[error("No cluster route in the swbusd config")]
+    NoRoute,
+
+    #[error(transparent)]
+    Swbus(#[from] SwbusError),
+
+    #[error("swbusd at {0} is not connected after {1:?}")]
+    ConnectTimeout(String, Duration),
+
+    #[error("Invalid payload: {0}")]
+    Payload(#[from] serde_json::Error),
+
+    #[error("Unexpected message {0}: {1}")]
+    Unexpected(MessageId, &'static str),
+
+    #[error("No more messages will be received")]
+    Closed,
+}
+
+pub type Result<T, E = SwbusClientError> = core::result::Result<T, E>;
+
+/// Where the swbusd config comes from.
+#[derive(Clone, Debug)]
+pub enum ConfigSource {
+    /// The config of the swbusd of a DPU slot, from CONFIG_DB
+    Dpu(u32),
+    /// A YAML file
+    Yaml(String),
+    Config(SwbusConfig),
+}
+
+#[derive(Clone, Debug)]
+pub struct SwbusClientOptions {
+    pub service_type: String,
+    pub service_id: String,
+    pub config: ConfigSource,
+    /// Give up if swbusd is not connected after this long
+    pub connect_timeout: Duration,
+}
+
+impl SwbusClientOptions {
+    /// Options of a client at `<node>/<service_type>/<service_id>`, connecting to the swbusd of DPU
+    /// slot 0 within 10 seconds.
+    pub fn new(service_type: impl Into<String>, service_id: impl Into<String>) -> Self {
+        SwbusClientOptions {
+            service_type: service_type.into(),
+            service_id: service_id.into(),
+            config: ConfigSource::Dpu(0),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn dpu_slot(mut self, slot: u32) -> Self {
+        self.config = ConfigSource::Dpu(slot);
+        self
+    }
+
+    pub fn config_file(mut self, path: impl Into<String>) -> Self {
+        self.config = ConfigSource::Yaml(path.into());
+        self
+    }
+
+    pub fn config(mut self, config: SwbusConfig) -> Self {
+        self.config = ConfigSource::Config(config);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+}
+
+/// A connection to swbusd, shared by the endpoints of a daemon.
+pub struct SwbusClient {
+    runtime: Arc<SwbusEdgeRuntime>,
+}
+
+impl SwbusClient {
+    /// Load the swbusd config, start an edge runtime and wait until swbusd is connected.
+    pub async fn connect(options: SwbusClientOptions) -> Result<Self> {
+        let config = match options.config {
+            ConfigSource::Dpu(slot) => swbus_config_from_db(slot)?,
+            ConfigSource::Yaml(path) => swbus_config_from_yaml(&path)?,
+            ConfigSource::Config(config) => config,
+        };
+        let mut sp = config.get_swbusd_service_path().ok_or(SwbusClientError::NoRoute)?;
+        sp.service_type = options.service_type;
+        sp.service_id = options.service_id;
+
+        let uri = format!("http://{}", config.endpoint);
+        let runtime = SwbusEdgeRuntime::builder(uri.clone(), sp).build().await?;
+        let start = Instant::now();
+        while !runtime.swbusd_connected().await {
+            if start.elapsed() > options.connect_timeout {
+                return Err(SwbusClientError::ConnectTimeout(uri, options.connect_timeout));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        info!("Connected to swbusd at {uri}");
+        Ok(SwbusClient {
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// A public endpoint at `<node>/<service_type>/<service_id>/<resource_type>/<resource_id>`.
+    pub fn endpoint(&self, resource_type: &str, resource_id: &str) -> SwbusEndpoint {
+        let sp = self.runtime.new_sp(resource_type, resource_id);
+        SwbusEndpoint {
+            client: SimpleSwbusEdgeClient::new(self.runtime.clone(), sp.clone(), true, false),
+            sp,
+        }
+    }
+
+    pub fn runtime(&self) -> &Arc<SwbusEdgeRuntime> {
+        &self.runtime
+    }
+}
+
+/// A request received with its payload decoded.
+#[derive(Clone, Debug)]
+pub struct Typed<T> {
+    pub id: MessageId,
+    pub source: ServicePath,
+    pub payload: T,
+}
+
+/// Sends and receives JSON payloads at one service path.
+pub struct SwbusEndpoint {
+    client: SimpleSwbusEdgeClient,
+    sp: ServicePath,
+}
+
+impl SwbusEndpoint {
+    pub fn service_path(&self) -> &ServicePath {
+        &self.sp
+    }
+
+    /// Send `payload` as a JSON request to `destination`.
+    pub async fn send<T: Serialize>(&self, destination: &ServicePath, payload: &T) -> Result<MessageId> {
+        let payload = serde_json::to_vec(payload)?;
+        Ok(self
+            .client
+            .send(OutgoingMessage {
+                destination: destination.clone(),
+                body: MessageBody::Request {
+                    payload: payload.into(),
+                },
+            })
+            .await?)
+    }
+
+    /// Answer the request `request_id` from `destination`.
+    pub async fn respond(
+        &self,
+        destination: &ServicePath,
+        request_id: MessageId,
+        error_code: SwbusErrorCode,
+        error_message: &str,
+    ) -> Result<()> {
+        self.client
+            .send(OutgoingMessage {
+                destination: destination.clone(),
+                body: MessageBody::Response {
+                    request_id,
+                    error_code,
+                    error_message: error_message.to_string(),
+                    response_body: None,
+                },
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Receive the next message of any kind.
+    pub async fn recv(&self) -> Result<IncomingMessage> {
+        self.client.recv().await.ok_or(SwbusClientError::Closed)
+    }
+
+    /// Receive the next request and decode its JSON payload. The request is answered with Ok if it
+    /// decodes, and with InvalidPayload otherwise. Responses to the requests sent from this endpoint
+    /// are skipped.
+    pub async fn recv_typed<T: DeserializeOwned>(&self) -> Result<Typed<T>> {
+        loop {
+            let msg = self.recv().await?;
+            let payload = match msg.body {
+                MessageBody::Request { payload } => payload,
+                MessageBody::Response { .. } => continue,
+                MessageBody::ManagementRequest { .. } => {
+                    return Err(SwbusClientError::Unexpected(msg.id, "management request"))
+                }
+            };
+            match serde_json::from_slice(&payload) {
+                Ok(payload) => {
+                    self.respond(&msg.source, msg.id, SwbusErrorCode::Ok, "").await?;
+                    return Ok(Typed {
+                        id: msg.id,
+                        source: msg.source,
+                        payload,
+                    });
+                }
+                Err(e) => {
+                    self.respond(&msg.source, msg.id, SwbusErrorCode::InvalidPayload, &e.to_string())
+                        .await?;
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// The client underneath, for what the endpoint doesn't cover.
+    pub fn simple_client(&self) -> &SimpleSwbusEdgeClient {
+        &self.client
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use sonic_common::log::init_logger_for_test;
+    use swbus_mock::MockSwbusd;
+    use tokio::time::timeout;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Counters {
+        eni: String,
+        packets: u64,
+    }
+
+    fn config(mock: &MockSwbusd) -> SwbusConfig {
+        let endpoint = mock.uri().trim_start_matches("http://").to_string();
+        serde_yaml::from_str(&format!(
+            r#"
+        endpoint: "{endpoint}"
+        routes:
+          - key: "region-a.cluster-a.10.0.0.1-dpu0"
+            scope: "Cluster"
+        peers:
+        "#
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn typed_messages_between_clients() {
+        init_logger_for_test();
+        let mock = MockSwbusd::start(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap())
+            .await
+            .unwrap();
+        let connect = |service_id: &str| {
+            SwbusClient::connect(SwbusClientOptions::new("telemetry", service_id).config(config(&mock)))
+        };
+        let a = connect("0").await.unwrap();
+        let b = connect("1").await.unwrap();
+        let sender = a.endpoint("counters", "out");
+        let receiver = b.endpoint("counters", "in");
+
+        let counters = Counters {
+            eni: "eni0".to_string(),
+            packets: 42,
+        };
+        let id = sender.send(receiver.service_path(), &counters).await.unwrap();
+        let received: Typed<Counters> = timeout(Duration::from_secs(5), receiver.recv_typed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (received.id, &received.source, received.payload),
+            (id, sender.service_path(), counters)
+        );
+
+        // The request was acknowledged
+        let ack = timeout(Duration::from_secs(5), sender.recv()).await.unwrap().unwrap();
+        assert!(matches!(
+            ack.body,
+            MessageBody::Response { request_id, error_code: SwbusErrorCode::Ok, .. } if request_id == id
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_times_out_without_swbusd() {
+        let mock = MockSwbusd::start(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap())
+            .await
+            .unwrap();
+        let config = config(&mock);
+        drop(mock);
+        let options = SwbusClientOptions::new("telemetry", "0")
+            .config(config)
+            .connect_timeout(Duration::from_millis(300));
+        assert!(matches!(
+            SwbusClient::connect(options).await,
+            Err(SwbusClientError::ConnectTimeout(..))
+        ));
+    }
+}
@@ -4,6 +4,7 @@ use std::io;
 use std::io::BufReader;
 use std::net::SocketAddr;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use swbus_proto::swbus::*;
 use swss_common::{DbConnector, Table};
@@ -13,6 +14,9 @@ use tracing::*;
 
 const CONFIG_DB: &str = "CONFIG_DB";
 
+/// CONFIG_DB tables that [`swbus_config_from_db`] reads.
+pub const CONFIG_DB_TABLES: [&str; 4] = ["DEVICE_METADATA", "LOOPBACK_INTERFACE", "DPU", "REMOTE_DPU"];
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct SwbusConfig {
     pub endpoint: SocketAddr,
@@ -20,6 +24,198 @@ pub struct SwbusConfig {
     pub peers: Vec<PeerConfig>,
     pub npu_ipv4: Option<Ipv4Addr>,
     pub npu_ipv6: Option<Ipv6Addr>,
+    /// Connections to and from peers are plain if not set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub ecmp_hash: EcmpHash,
+    #[serde(default)]
+    pub send_queue: SendQueueConfig,
+    /// Connections to and from peers are not compressed if not set.
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    /// Connections from clients are not rate limited if not set.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Connect to the DPUs in the DPU and REMOTE_DPU tables of CONFIG_DB too, in addition to `peers`,
+    /// and follow the changes of the tables. A config read from CONFIG_DB always does.
+    #[serde(default)]
+    pub peer_discovery: bool,
+    /// The routes learned from peers and clients are lost when swbusd restarts if not set.
+    #[serde(default)]
+    pub graceful_restart: Option<GracefulRestartConfig>,
+    /// Routes through the peers without a preference here have preference 0.
+    #[serde(default)]
+    pub route_preferences: Vec<RoutePreferenceConfig>,
+    /// Clients connect without authentication if not set.
+    #[serde(default)]
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+/// What picks the connection a message takes when a route has several of equal cost. Messages that
+/// hash the same take the same connection as long as it is up, so they stay in order.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum EcmpHash {
+    /// The whole source service path
+    #[default]
+    ServicePath,
+    /// The source service, up to the service id
+    Service,
+    /// The source node
+    Node,
+}
+
+/// Outgoing message queue of each connection. Infra messages, i.e. pings, traceroutes, registration
+/// and management requests, responses, and data requests flagged high priority, have a lane of
+/// their own that is always sent ahead of the lane of the other data requests, so they get through
+/// when the connection is busy with bulk data.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SendQueueConfig {
+    /// Messages the high priority lane holds
+    pub high_priority_depth: usize,
+    /// Messages the low priority lane holds
+    pub low_priority_depth: usize,
+    /// What happens to a message when its lane is full
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        SendQueueConfig {
+            high_priority_depth: 64,
+            low_priority_depth: 16,
+            drop_policy: DropPolicy::default(),
+        }
+    }
+}
+
+/// Compression of the data requests sent to peers. A connection is compressed when both ends have it
+/// set, and then data requests with a payload larger than `threshold` bytes are sent compressed with
+/// zstd. Other messages and smaller payloads are not worth it.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub threshold: usize,
+    /// zstd compression level
+    pub level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            threshold: 4096,
+            level: 3,
+        }
+    }
+}
+
+/// Rate limit of the messages each client connection sends to swbusd, so that one client can't starve
+/// the others. Each limit is a token bucket refilled at the given rate that holds one second worth of
+/// tokens, so a client can send a burst of that size after being idle. A limit of 0 is no limit.
+/// Connections from other swbusd carry the messages of many clients and are not limited.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub messages_per_sec: u32,
+    pub bytes_per_sec: u64,
+    /// What happens to a message over the limit
+    pub policy: RateLimitPolicy,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            messages_per_sec: 1000,
+            bytes_per_sec: 0,
+            policy: RateLimitPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Drop the message, and send the sender a RateLimited error response
+    #[default]
+    Reject,
+    /// Stop reading from the connection until the message is within the limit, which slows the sender
+    /// down through flow control
+    Backpressure,
+}
+
+/// Graceful restart of swbusd. The routes learned from peers and clients are saved to `state_file`
+/// when swbusd shuts down, and announced again as stale routes when it starts, so its route
+/// subscribers don't see them withdrawn. A stale route is refreshed when its peer or client connects
+/// again, the ones that aren't within `hold_time_in_secs` are withdrawn then. Messages to a stale
+/// route get a NoRoute response until it is refreshed.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct GracefulRestartConfig {
+    pub state_file: PathBuf,
+    #[serde(default = "default_hold_time_in_secs")]
+    pub hold_time_in_secs: u64,
+}
+
+fn default_hold_time_in_secs() -> u64 {
+    120
+}
+
+/// Authentication of the connections to swbusd, e.g. of hamgrd, so that a rogue container can't connect
+/// with the service path of another client and take its messages. A connection is admitted if one of
+/// `clients` lets it connect with its service path, before a route is installed for it, and rejected
+/// otherwise. The connection type is declared by the connecting side, so peers are authenticated like
+/// clients, usually by the SPIFFE ID of their certificate. [`TlsConfig`] must be set too, so that tokens
+/// are not sent in plaintext.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ClientAuthConfig {
+    pub clients: Vec<ClientCredentialConfig>,
+    /// The service paths of the clients allowed to make privileged management requests, i.e. capture
+    /// the messages routed through swbusd and read its dead letters, and the ones below them. No client
+    /// is allowed if empty.
+    #[serde(default)]
+    pub management_service_paths: Vec<String>,
+}
+
+/// A credential of clients or peers, and the service paths it lets them connect with: the ones in
+/// `service_paths` and below them, e.g. `region-a.cluster-a.10.0.0.1-dpu0/hamgrd` lets a client
+/// connect as `region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ClientCredentialConfig {
+    #[serde(flatten)]
+    pub credential: ClientCredential,
+    pub service_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientCredential {
+    /// A secret token in this file, which the client presents when it connects. The file is read again
+    /// when the config is reloaded.
+    TokenFile(PathBuf),
+    /// The SPIFFE ID in the URI SAN of the certificate the client presents over mutual TLS, e.g.
+    /// `spiffe://sonic/hamgrd`
+    SpiffeId(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Refuse the message, so the sender gets a QueueFull error response
+    #[default]
+    Reject,
+    /// Drop the message without telling the sender, which is left to time out and retry
+    Drop,
+}
+
+/// Mutual TLS of the connections between swbusd instances. All files are in PEM. swbusd presents
+/// `cert` to its peers, both when it connects and when it accepts, and verifies the certificate of
+/// the peer with `ca_cert`. The files are read again when they change.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub ca_cert: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Name the certificates of peers are verified against, instead of their IP address
+    #[serde(default)]
+    pub domain_name: Option<String>,
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Deserialize)]
@@ -29,6 +225,19 @@ pub struct RouteConfig {
     pub scope: RouteScope,
 }
 
+/// Administrative preference of the route to `key` through the connection to peer `via`. A route
+/// takes the next hops with the highest preference, and falls back to the ones with a lower
+/// preference only when all of those are down. Among the next hops of the same preference, the ones
+/// with the smallest hop count win and share the traffic by ECMP.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct RoutePreferenceConfig {
+    #[serde(deserialize_with = "deserialize_service_path")]
+    pub key: ServicePath,
+    #[serde(deserialize_with = "deserialize_service_path")]
+    pub via: ServicePath,
+    pub preference: u32,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct PeerConfig {
     #[serde(deserialize_with = "deserialize_service_path")]
@@ -46,6 +255,48 @@ impl SwbusConfig {
         }
         None
     }
+
+    /// Changes from this config to `new`.
+    pub fn diff(&self, new: &SwbusConfig) -> SwbusConfigDiff {
+        SwbusConfigDiff {
+            added_routes: new
+                .routes
+                .iter()
+                .filter(|r| !self.routes.contains(r))
+                .cloned()
+                .collect(),
+            removed_routes: self
+                .routes
+                .iter()
+                .filter(|r| !new.routes.contains(r))
+                .cloned()
+                .collect(),
+            added_peers: new.peers.iter().filter(|p| !self.peers.contains(p)).cloned().collect(),
+            removed_peers: self.peers.iter().filter(|p| !new.peers.contains(p)).cloned().collect(),
+            endpoint_changed: self.endpoint != new.endpoint,
+        }
+    }
+}
+
+/// Route and peer changes between two [`SwbusConfig`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwbusConfigDiff {
+    pub added_routes: Vec<RouteConfig>,
+    pub removed_routes: Vec<RouteConfig>,
+    pub added_peers: Vec<PeerConfig>,
+    pub removed_peers: Vec<PeerConfig>,
+    /// The endpoint swbusd listens on changed. This is only applied by restarting swbusd.
+    pub endpoint_changed: bool,
+}
+
+impl SwbusConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_routes.is_empty()
+            && self.removed_routes.is_empty()
+            && self.added_peers.is_empty()
+            && self.removed_peers.is_empty()
+            && !self.endpoint_changed
+    }
 }
 
 #[derive(Error, Debug)]
@@ -152,7 +403,9 @@ fn route_config_from_dpu_entry(dpu_entry: &ConfigDBDPUEntry, region: &str, clust
     }
 
     if routes.is_empty() {
-        SwbusConfigError::InvalidConfig(format!("No valid routes found in local dpu{dpu_id}"));
+        return Err(SwbusConfigError::InvalidConfig(format!(
+            "No valid routes found in local dpu{dpu_id}"
+        )));
     }
 
     debug!("Routes collected: {:?}", &routes);
@@ -319,11 +572,12 @@ pub fn swbus_config_from_db(dpu_id: u32) -> Result<SwbusConfig> {
                 e
             })?);
 
-            if let Some(npu_ipv4) = dpu.npu_ipv4 {
-                myendpoint = Some(SocketAddr::new(std::net::IpAddr::V4(npu_ipv4), swbusd_port));
-            } else if let Some(npu_ipv6) = dpu.npu_ipv6 {
-                myendpoint = Some(SocketAddr::new(std::net::IpAddr::V6(npu_ipv6), swbusd_port));
-            }
+            // IPv4 is preferred, IPv6-only NPUs listen on their IPv6 address
+            myendpoint = dpu
+                .npu_ipv4
+                .map(IpAddr::V4)
+                .or(dpu.npu_ipv6.map(IpAddr::V6))
+                .map(|ip| SocketAddr::new(ip, swbusd_port));
             continue;
         }
         let remote_dpu = dpu.to_remote_dpu();
@@ -333,11 +587,11 @@ pub fn swbus_config_from_db(dpu_id: u32) -> Result<SwbusConfig> {
         })?;
         peers.extend(peer);
     }
-    if myroutes.is_none() {
+    let (Some(myroutes), Some(myendpoint)) = (myroutes, myendpoint) else {
         return Err(SwbusConfigError::InvalidConfig(format!(
             "DPU at slot {dpu_id} is not found"
         )));
-    }
+    };
 
     let db = DbConnector::new_named(CONFIG_DB, false, 0).unwrap();
     let table = Table::new(db, "REMOTE_DPU").map_err(|e| ("opening REMOTE_DPU table".into(), e))?;
@@ -361,14 +615,81 @@ pub fn swbus_config_from_db(dpu_id: u32) -> Result<SwbusConfig> {
     info!("successfully load swbus config from configdb for dpu {}", dpu_id);
 
     Ok(SwbusConfig {
-        endpoint: myendpoint.unwrap(),
-        routes: myroutes.unwrap(),
+        endpoint: myendpoint,
+        routes: myroutes,
         peers,
         npu_ipv4: my_ipv4,
         npu_ipv6: my_ipv6,
+        tls: None,
+        ecmp_hash: EcmpHash::default(),
+        send_queue: SendQueueConfig::default(),
+        compression: None,
+        rate_limit: None,
+        peer_discovery: false,
+        graceful_restart: None,
+        route_preferences: Vec::new(),
+        client_auth: None,
     })
 }
 
+/// The peers of `config` with the DPUs found in the DPU and REMOTE_DPU tables of CONFIG_DB. The DPUs
+/// of the DPU table are on this NPU, and are reached at the NPU addresses of `config`. A DPU entry that
+/// can't be used is skipped rather than failing the others, and so are the DPUs `config` routes to
+/// itself or already has as peers.
+#[instrument(skip(config))]
+pub fn discover_peers(config: &SwbusConfig) -> Result<Vec<PeerConfig>> {
+    let (region, cluster) = get_device_info()?;
+    let mut dpus = Vec::new();
+
+    let db = DbConnector::new_named(CONFIG_DB, false, 0).map_err(|e| ("connecting config_db".into(), e))?;
+    let table = Table::new(db, "DPU").map_err(|e| ("opening DPU table".into(), e))?;
+    let keys = table
+        .get_keys()
+        .map_err(|e| ("Failed to get keys from DPU table".into(), e))?;
+    for key in keys {
+        match from_table::<ConfigDBDPUEntry>(&table, &key) {
+            Ok(mut dpu) => {
+                dpu.npu_ipv4 = config.npu_ipv4;
+                dpu.npu_ipv6 = config.npu_ipv6;
+                dpus.push((key, dpu.to_remote_dpu()));
+            }
+            Err(e) => warn!("Skipping DPU entry {key}: {e}"),
+        }
+    }
+
+    let db = DbConnector::new_named(CONFIG_DB, false, 0).map_err(|e| ("connecting config_db".into(), e))?;
+    let table = Table::new(db, "REMOTE_DPU").map_err(|e| ("opening REMOTE_DPU table".into(), e))?;
+    let keys = table
+        .get_keys()
+        .map_err(|e| ("Failed to get keys from REMOTE_DPU table".into(), e))?;
+    for key in keys {
+        match from_table::<ConfigDBRemoteDPUEntry>(&table, &key) {
+            Ok(dpu) => dpus.push((key, dpu)),
+            Err(e) => warn!("Skipping REMOTE_DPU entry {key}: {e}"),
+        }
+    }
+
+    let mut peers = config.peers.clone();
+    for (key, dpu) in dpus {
+        let found = match peer_config_from_dpu_entry(&key, dpu, &region, &cluster) {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Skipping DPU {key}: {e}");
+                continue;
+            }
+        };
+        for peer in found {
+            let known = peers.iter().any(|p| p.id == peer.id);
+            if !known && !config.routes.iter().any(|route| route.key == peer.id) {
+                peers.push(peer);
+            }
+        }
+    }
+
+    debug!("Peers with the discovered ones: {:?}", &peers);
+    Ok(peers)
+}
+
 pub fn swbus_config_from_yaml(yaml_file: &str) -> Result<SwbusConfig> {
     let file = File::open(yaml_file)?;
     let reader = BufReader::new(file);
@@ -551,6 +872,47 @@ mod tests {
         cleanup_configdb_for_test();
     }
 
+    #[test]
+    fn test_discover_peers() {
+        let _ = Redis::start_config_db();
+        populate_configdb_for_test();
+
+        let peer = |id: &str, endpoint: &str| PeerConfig {
+            id: ServicePath::from_string(id).unwrap(),
+            endpoint: endpoint.parse().unwrap(),
+            conn_type: ConnectionType::Cluster,
+        };
+        let config = SwbusConfig {
+            endpoint: "10.0.1.0:23606".parse().unwrap(),
+            routes: vec![RouteConfig {
+                key: ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0").unwrap(),
+                scope: RouteScope::Cluster,
+            }],
+            peers: vec![peer("region-a.cluster-a.10.0.1.1-dpu0", "10.0.1.1:23606")],
+            npu_ipv4: Some("10.0.1.0".parse().unwrap()),
+            npu_ipv6: None,
+            tls: None,
+            ecmp_hash: EcmpHash::default(),
+            send_queue: SendQueueConfig::default(),
+            compression: None,
+            rate_limit: None,
+            peer_discovery: true,
+            graceful_restart: None,
+            route_preferences: Vec::new(),
+            client_auth: None,
+        };
+
+        // the static peer comes first and isn't repeated, and the local dpu0 is not a peer
+        let peers = discover_peers(&config).unwrap();
+        assert_eq!(peers.len(), 9);
+        assert_eq!(peers[0], config.peers[0]);
+        assert!(peers.contains(&peer("region-a.cluster-a.10.0.1.0-dpu1", "10.0.1.0:23607")));
+        assert!(peers.contains(&peer("region-a.cluster-a.2001:db8:1::2-dpu1", "[2001:db8:1::2]:23607")));
+        assert!(!peers.iter().any(|p| p.id == config.routes[0].key));
+
+        cleanup_configdb_for_test();
+    }
+
     #[test]
     fn test_load_from_yaml() {
         let yaml_content = r#"
@@ -608,5 +970,206 @@ mod tests {
             "10.0.0.3:8000".parse().expect("not expecting error")
         );
         assert_eq!(config.peers[1].conn_type, ConnectionType::Cluster);
+        assert_eq!(config.tls, None);
+        assert_eq!(config.ecmp_hash, EcmpHash::ServicePath);
+        assert_eq!(config.send_queue, SendQueueConfig::default());
+        assert_eq!(config.compression, None);
+        assert_eq!(config.rate_limit, None);
+        assert_eq!(config.graceful_restart, None);
+        assert_eq!(config.client_auth, None);
+    }
+
+    #[test]
+    fn test_load_send_queue_from_yaml() {
+        let yaml_content = r#"
+        endpoint: 10.0.0.1:8000
+        routes:
+          - key: "region-a.cluster-a.10.0.0.1-dpu0"
+            scope: "Cluster"
+        peers: []
+        send_queue:
+          low_priority_depth: 256
+          drop_policy: Drop
+        compression:
+          threshold: 1024
+        rate_limit:
+          bytes_per_sec: 1048576
+          policy: Backpressure
+        "#;
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test_config.yaml");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all(yaml_content.as_bytes()).unwrap();
+
+        let config = swbus_config_from_yaml(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            config.send_queue,
+            SendQueueConfig {
+                high_priority_depth: 64,
+                low_priority_depth: 256,
+                drop_policy: DropPolicy::Drop,
+            }
+        );
+        assert_eq!(
+            config.compression,
+            Some(CompressionConfig {
+                threshold: 1024,
+                level: 3,
+            })
+        );
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimitConfig {
+                messages_per_sec: 1000,
+                bytes_per_sec: 1048576,
+                policy: RateLimitPolicy::Backpressure,
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_tls_and_ecmp_hash_from_yaml() {
+        let yaml_content = r#"
+        endpoint: 10.0.0.1:8000
+        routes:
+          - key: "region-a.cluster-a.10.0.0.1-dpu0"
+            scope: "Cluster"
+        peers: []
+        tls:
+          ca_cert: /etc/swbus/ca.pem
+          cert: /etc/swbus/cert.pem
+          key: /etc/swbus/key.pem
+        ecmp_hash: Node
+        graceful_restart:
+          state_file: /var/lib/swbus/routes.json
+        route_preferences:
+          - key: "region-b"
+            via: "region-a.cluster-a.10.0.0.2-dpu0"
+            preference: 100
+        client_auth:
+          clients:
+            - token_file: /etc/swbus/hamgrd.token
+              service_paths: ["region-a.cluster-a.10.0.0.1-dpu0/hamgrd"]
+            - spiffe_id: spiffe://sonic/swbus-cli
+              service_paths: ["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli"]
+          management_service_paths: ["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli"]
+        "#;
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test_config.yaml");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all(yaml_content.as_bytes()).unwrap();
+
+        let config = swbus_config_from_yaml(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            config.client_auth,
+            Some(ClientAuthConfig {
+                clients: vec![
+                    ClientCredentialConfig {
+                        credential: ClientCredential::TokenFile("/etc/swbus/hamgrd.token".into()),
+                        service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/hamgrd".to_string()],
+                    },
+                    ClientCredentialConfig {
+                        credential: ClientCredential::SpiffeId("spiffe://sonic/swbus-cli".to_string()),
+                        service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli".to_string()],
+                    },
+                ],
+                management_service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli".to_string()],
+            })
+        );
+        assert_eq!(
+            config.tls,
+            Some(TlsConfig {
+                ca_cert: "/etc/swbus/ca.pem".into(),
+                cert: "/etc/swbus/cert.pem".into(),
+                key: "/etc/swbus/key.pem".into(),
+                domain_name: None,
+            })
+        );
+        assert_eq!(config.ecmp_hash, EcmpHash::Node);
+        assert_eq!(
+            config.graceful_restart,
+            Some(GracefulRestartConfig {
+                state_file: "/var/lib/swbus/routes.json".into(),
+                hold_time_in_secs: 120,
+            })
+        );
+        assert_eq!(
+            config.route_preferences,
+            vec![RoutePreferenceConfig {
+                key: ServicePath::from_string("region-b").unwrap(),
+                via: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                preference: 100,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let route = |key: &str| RouteConfig {
+            key: ServicePath::from_string(key).unwrap(),
+            scope: RouteScope::Cluster,
+        };
+        let peer = |id: &str, endpoint: &str| PeerConfig {
+            id: ServicePath::from_string(id).unwrap(),
+            endpoint: endpoint.parse().unwrap(),
+            conn_type: ConnectionType::Cluster,
+        };
+        let old = SwbusConfig {
+            endpoint: "10.0.0.1:8000".parse().unwrap(),
+            routes: vec![route("region-a.cluster-a.10.0.0.1-dpu0")],
+            peers: vec![
+                peer("region-a.cluster-a.10.0.0.2-dpu0", "10.0.0.2:8000"),
+                peer("region-a.cluster-a.10.0.0.3-dpu0", "10.0.0.3:8000"),
+            ],
+            npu_ipv4: None,
+            npu_ipv6: None,
+            tls: None,
+            ecmp_hash: EcmpHash::default(),
+            send_queue: SendQueueConfig::default(),
+            compression: None,
+            rate_limit: None,
+            peer_discovery: false,
+            graceful_restart: None,
+            route_preferences: Vec::new(),
+            client_auth: None,
+        };
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.routes.push(route("region-a.cluster-a.2001:db8::1-dpu0"));
+        new.peers[1] = peer("region-a.cluster-a.10.0.0.3-dpu0", "10.0.0.3:8001");
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            SwbusConfigDiff {
+                added_routes: vec![route("region-a.cluster-a.2001:db8::1-dpu0")],
+                removed_routes: vec![],
+                added_peers: vec![peer("region-a.cluster-a.10.0.0.3-dpu0", "10.0.0.3:8001")],
+                removed_peers: vec![peer("region-a.cluster-a.10.0.0.3-dpu0", "10.0.0.3:8000")],
+                endpoint_changed: false,
+            }
+        );
+
+        new.endpoint = "10.0.0.1:8001".parse().unwrap();
+        assert!(old.diff(&new).endpoint_changed);
+    }
+
+    #[test]
+    fn test_routes_of_ipv6_only_dpu() {
+        let mut dpu = ConfigDBDPUEntry {
+            state: None,
+            swbus_port: Some(23606),
+            dpu_id: 1,
+            npu_ipv4: None,
+            npu_ipv6: Some("2001:db8:1::".parse().unwrap()),
+        };
+        let routes = route_config_from_dpu_entry(&dpu, "region-a", "cluster-a").unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].key.to_longest_path(), "region-a.cluster-a.2001:db8:1::-dpu1");
+
+        dpu.npu_ipv6 = None;
+        assert!(route_config_from_dpu_entry(&dpu, "region-a", "cluster-a").is_err());
     }
 }
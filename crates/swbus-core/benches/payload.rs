@@ -0,0 +1,47 @@
+//! Forwarding of data requests through swbusd.
+//!
+//! A forwarded message is decoded from the buffer of the incoming connection, routed by the
+//! multiplexer to the send queue of the outgoing connection, and encoded to the outgoing buffer. The
+//! payload is shared from the incoming buffer rather than copied, so forwarding a 64KB data request
+//! should cost about as much as forwarding a 64B one.
+//! Run with `cargo bench -p swbus-core --features bench --bench payload`.
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prost::Message;
+use swbus_core::mux::bench::PeerRoute;
+use swbus_proto::swbus::{swbus_message::Body, DataRequest, ServicePath, SwbusMessage, SwbusMessageHeader};
+
+const PAYLOAD_SIZES: [usize; 2] = [64, 64 * 1024];
+
+fn bench_route(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let my_sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap();
+    let peer_sp = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap();
+    let source = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/dpu/dpu0").unwrap();
+    let destination = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/dpu/dpu0").unwrap();
+    let mut route = PeerRoute::new(&my_sp, &peer_sp);
+
+    let mut group = c.benchmark_group("route_data_request");
+    for size in PAYLOAD_SIZES {
+        let message = SwbusMessage::new(
+            SwbusMessageHeader::new(source.clone(), destination.clone(), 1),
+            Body::DataRequest(DataRequest::new(vec![0x5a; size])),
+        );
+        let wire = Bytes::from(message.encode_to_vec());
+        let mut out = BytesMut::with_capacity(2 * wire.len());
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &wire, |b, wire| {
+            b.iter(|| {
+                let message = SwbusMessage::decode(wire.clone()).unwrap();
+                let routed = runtime.block_on(route.route(message));
+                out.clear();
+                routed.encode(&mut out).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_route);
+criterion_main!(benches);
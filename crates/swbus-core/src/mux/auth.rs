@@ -0,0 +1,354 @@
+//! Authentication of clients
+//!
+//! swbusd authenticates a client when it connects, before a route is installed for the service path it
+//! connects with, so that a rogue container can't impersonate another client, e.g. hamgrd. The
+//! credentials the client presents are checked by the authenticators of the connection store: the one
+//! built from [`ClientAuthConfig`], which knows static tokens and SPIFFE IDs, and the ones added by the
+//! program running swbusd, e.g. to check an identity derived from the platform. A client is admitted if
+//! one of them admits it, and without authentication if there are none. Once admitted, a client can only
+//! send messages from the service path it connected with or from below it.
+//!
+//! Every connection is authenticated, whatever connection type it declares, since a client could
+//! otherwise skip authentication by connecting as a peer. Peers connect over mutual TLS, and present the
+//! SPIFFE ID of their certificate.
+//!
+//! Privileged management requests, e.g. to capture the messages routed through swbusd or to read the
+//! messages it couldn't deliver, are only
+//! accepted from the clients in [`ClientAuthConfig::management_service_paths`], and only over their own
+//! connection to swbusd, not forwarded by a peer. Without a config, any client of swbusd can make them.
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use swbus_config::{ClientAuthConfig, ClientCredential};
+use swbus_proto::result::*;
+use swbus_proto::swbus::{
+    swbus_message, ConnectionType, ManagementRequestType, ServicePath, SwbusErrorCode, SwbusMessage,
+};
+use tonic::metadata::MetadataMap;
+
+/// What a client, or a peer, presents when it connects.
+pub struct ClientCredentials<'a> {
+    /// The service path the client connects with
+    pub service_path: &'a ServicePath,
+    pub remote_addr: SocketAddr,
+    /// The token in [`swbus_proto::swbus::SWBUS_AUTH_TOKEN`]
+    pub token: Option<&'a str>,
+    /// The SPIFFE ID of the certificate the client presented over mutual TLS
+    pub spiffe_id: Option<String>,
+    /// The meta data of the connection request, for credentials of other kinds
+    pub metadata: &'a MetadataMap,
+}
+
+/// Checks the credentials of the clients of swbusd, see the module docs.
+pub trait ClientAuthenticator: Send + Sync {
+    /// Admit the client with `credentials`, or say why not.
+    fn authenticate(&self, credentials: &ClientCredentials) -> std::result::Result<(), String>;
+}
+
+enum Credential {
+    Token(String),
+    SpiffeId(String),
+}
+
+/// The management requests only authorized clients can make, see the module docs.
+const PRIVILEGED_REQUESTS: &[ManagementRequestType] = &[
+    ManagementRequestType::SwbusdCapture,
+    ManagementRequestType::SwbusdGetDeadLetters,
+];
+
+/// Whether `message` is a privileged management request, see the module docs.
+pub(crate) fn is_privileged(message: &SwbusMessage) -> bool {
+    let Some(swbus_message::Body::ManagementRequest(request)) = &message.body else {
+        return false;
+    };
+    PRIVILEGED_REQUESTS
+        .iter()
+        .any(|privileged| request.request == *privileged as i32)
+}
+
+/// The authenticator of a [`ClientAuthConfig`].
+struct ConfigAuthenticator {
+    clients: Vec<(Credential, Vec<String>)>,
+    management_service_paths: Vec<String>,
+}
+
+impl ConfigAuthenticator {
+    /// Build the authenticator of `config`, reading the token files.
+    fn load(config: &ClientAuthConfig) -> Result<Self> {
+        let clients = config
+            .clients
+            .iter()
+            .map(|client| {
+                let credential = match &client.credential {
+                    ClientCredential::TokenFile(path) => {
+                        let token = std::fs::read_to_string(path).map_err(|e| {
+                            SwbusError::input(
+                                SwbusErrorCode::InvalidArgs,
+                                format!("Failed to read {}: {e}", path.display()),
+                            )
+                        })?;
+                        let token = token.trim();
+                        if token.is_empty() {
+                            return Err(SwbusError::input(
+                                SwbusErrorCode::InvalidArgs,
+                                format!("No token in {}", path.display()),
+                            ));
+                        }
+                        Credential::Token(token.to_string())
+                    }
+                    ClientCredential::SpiffeId(spiffe_id) => Credential::SpiffeId(spiffe_id.clone()),
+                };
+                Ok((credential, client.service_paths.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ConfigAuthenticator {
+            clients,
+            management_service_paths: config.management_service_paths.clone(),
+        })
+    }
+}
+
+impl ClientAuthenticator for ConfigAuthenticator {
+    fn authenticate(&self, credentials: &ClientCredentials) -> std::result::Result<(), String> {
+        let service_path = credentials.service_path.to_longest_path();
+        let mut valid = false;
+        for (credential, service_paths) in &self.clients {
+            let presented = match credential {
+                Credential::Token(token) => credentials
+                    .token
+                    .is_some_and(|client_token| constant_time_eq(client_token.as_bytes(), token.as_bytes())),
+                Credential::SpiffeId(spiffe_id) => credentials.spiffe_id.as_ref() == Some(spiffe_id),
+            };
+            if !presented {
+                continue;
+            }
+            if service_paths.iter().any(|allowed| covers(allowed, &service_path)) {
+                return Ok(());
+            }
+            valid = true;
+        }
+        match valid {
+            true => Err(format!("the credentials don't allow {service_path}")),
+            false => Err("no valid credentials".to_string()),
+        }
+    }
+}
+
+/// Whether `service_path` is `allowed` or below it.
+pub(crate) fn covers(allowed: &str, service_path: &str) -> bool {
+    service_path
+        .strip_prefix(allowed)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Compare tokens in a time that doesn't depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The authenticators of the clients of swbusd, see the module docs.
+#[derive(Default)]
+pub(crate) struct ClientAuth {
+    config: RwLock<Option<Arc<ConfigAuthenticator>>>,
+    authenticators: RwLock<Vec<Arc<dyn ClientAuthenticator>>>,
+}
+
+impl ClientAuth {
+    /// Authenticate the clients with `config` from now on, or without it if None. The current config is
+    /// kept if a token file can't be read.
+    pub(crate) fn set_config(&self, config: Option<&ClientAuthConfig>) -> Result<()> {
+        let config = config.map(ConfigAuthenticator::load).transpose()?;
+        *self.config.write().unwrap() = config.map(Arc::new);
+        Ok(())
+    }
+
+    pub(crate) fn add(&self, authenticator: Arc<dyn ClientAuthenticator>) {
+        self.authenticators.write().unwrap().push(authenticator);
+    }
+
+    /// Admit the client with `credentials` if one of the authenticators does, or if there are none.
+    pub(crate) fn authenticate(&self, credentials: &ClientCredentials) -> std::result::Result<(), String> {
+        let config = self.config.read().unwrap().clone();
+        let authenticators: Vec<Arc<dyn ClientAuthenticator>> = config
+            .into_iter()
+            .map(|config| config as Arc<dyn ClientAuthenticator>)
+            .chain(self.authenticators.read().unwrap().iter().cloned())
+            .collect();
+        if authenticators.is_empty() {
+            return Ok(());
+        }
+        let mut errors = Vec::new();
+        for authenticator in authenticators {
+            match authenticator.authenticate(credentials) {
+                Ok(()) => return Ok(()),
+                Err(e) => errors.push(e),
+            }
+        }
+        Err(errors.join(", "))
+    }
+
+    /// Allow the client of a connection of `connection_type` with `service_path` to make privileged
+    /// management requests, or say why not, see the module docs.
+    pub(crate) fn authorize_management(
+        &self,
+        connection_type: ConnectionType,
+        service_path: &ServicePath,
+    ) -> std::result::Result<(), String> {
+        if !matches!(connection_type, ConnectionType::Client | ConnectionType::Local) {
+            return Err("privileged requests are only accepted from clients of this swbusd".to_string());
+        }
+        let Some(config) = self.config.read().unwrap().clone() else {
+            return Ok(());
+        };
+        let service_path = service_path.to_longest_path();
+        match config
+            .management_service_paths
+            .iter()
+            .any(|allowed| covers(allowed, &service_path))
+        {
+            true => Ok(()),
+            false => Err(format!("{service_path} is not allowed to make privileged requests")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use swbus_config::ClientCredentialConfig;
+
+    fn credentials<'a>(
+        service_path: &'a ServicePath,
+        token: Option<&'a str>,
+        spiffe_id: Option<&str>,
+        metadata: &'a MetadataMap,
+    ) -> ClientCredentials<'a> {
+        ClientCredentials {
+            service_path,
+            remote_addr: "127.0.0.1:50000".parse().unwrap(),
+            token,
+            spiffe_id: spiffe_id.map(str::to_string),
+            metadata,
+        }
+    }
+
+    #[test]
+    fn test_config_authenticator() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_file = dir.path().join("hamgrd.token");
+        std::fs::File::create(&token_file)
+            .unwrap()
+            .write_all(b"secret\n")
+            .unwrap();
+        let config = ClientAuthConfig {
+            clients: vec![
+                ClientCredentialConfig {
+                    credential: ClientCredential::TokenFile(token_file),
+                    service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/hamgrd".to_string()],
+                },
+                ClientCredentialConfig {
+                    credential: ClientCredential::SpiffeId("spiffe://sonic/swbus-cli".to_string()),
+                    service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli".to_string()],
+                },
+            ],
+            management_service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli".to_string()],
+        };
+        let auth = ClientAuth::default();
+        let metadata = MetadataMap::new();
+        let hamgrd = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        let cli = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0").unwrap();
+
+        // anyone is admitted without authenticators
+        assert!(auth.authenticate(&credentials(&hamgrd, None, None, &metadata)).is_ok());
+
+        auth.set_config(Some(&config)).unwrap();
+        assert!(auth
+            .authenticate(&credentials(&hamgrd, Some("secret"), None, &metadata))
+            .is_ok());
+        assert!(auth
+            .authenticate(&credentials(&cli, None, Some("spiffe://sonic/swbus-cli"), &metadata))
+            .is_ok());
+        assert_eq!(
+            auth.authenticate(&credentials(&hamgrd, Some("guess"), None, &metadata)),
+            Err("no valid credentials".to_string())
+        );
+        assert!(auth.authenticate(&credentials(&hamgrd, None, None, &metadata)).is_err());
+
+        // a credential only lets a client connect with its own service paths
+        assert_eq!(
+            auth.authenticate(&credentials(&cli, Some("secret"), None, &metadata)),
+            Err("the credentials don't allow region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0".to_string())
+        );
+        let impostor = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd2/0").unwrap();
+        assert!(auth
+            .authenticate(&credentials(&impostor, Some("secret"), None, &metadata))
+            .is_err());
+
+        // a missing token file keeps the current config
+        let mut missing = config.clone();
+        missing.clients[0].credential = ClientCredential::TokenFile(dir.path().join("missing.token"));
+        assert!(auth.set_config(Some(&missing)).is_err());
+        assert!(auth
+            .authenticate(&credentials(&hamgrd, Some("secret"), None, &metadata))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_authorize_management() {
+        let auth = ClientAuth::default();
+        let hamgrd = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        let cli = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0").unwrap();
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap();
+
+        // any client of swbusd without a config, but never through a peer
+        assert!(auth.authorize_management(ConnectionType::Local, &hamgrd).is_ok());
+        assert!(auth.authorize_management(ConnectionType::Cluster, &peer).is_err());
+
+        auth.set_config(Some(&ClientAuthConfig {
+            clients: Vec::new(),
+            management_service_paths: vec!["region-a.cluster-a.10.0.0.1-dpu0/swbus-cli".to_string()],
+        }))
+        .unwrap();
+        assert!(auth.authorize_management(ConnectionType::Client, &cli).is_ok());
+        assert!(auth.authorize_management(ConnectionType::Local, &cli).is_ok());
+        assert_eq!(
+            auth.authorize_management(ConnectionType::Local, &hamgrd),
+            Err("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0 is not allowed to make privileged requests".to_string())
+        );
+        assert!(auth.authorize_management(ConnectionType::Cluster, &cli).is_err());
+    }
+
+    #[test]
+    fn test_added_authenticator() {
+        /// Admits the clients connecting from the loopback address.
+        struct Loopback;
+
+        impl ClientAuthenticator for Loopback {
+            fn authenticate(&self, credentials: &ClientCredentials) -> std::result::Result<(), String> {
+                match credentials.remote_addr.ip().is_loopback() {
+                    true => Ok(()),
+                    false => Err("not from the loopback address".to_string()),
+                }
+            }
+        }
+
+        let auth = ClientAuth::default();
+        auth.set_config(Some(&ClientAuthConfig {
+            clients: Vec::new(),
+            management_service_paths: Vec::new(),
+        }))
+        .unwrap();
+        let metadata = MetadataMap::new();
+        let hamgrd = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        assert!(auth.authenticate(&credentials(&hamgrd, None, None, &metadata)).is_err());
+
+        auth.add(Arc::new(Loopback));
+        assert!(auth.authenticate(&credentials(&hamgrd, None, None, &metadata)).is_ok());
+        let mut remote = credentials(&hamgrd, None, None, &metadata);
+        remote.remote_addr = "10.0.0.2:50000".parse().unwrap();
+        assert_eq!(
+            auth.authenticate(&remote),
+            Err("no valid credentials, not from the loopback address".to_string())
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Routing through a real multiplexer for the benchmarks in `benches/`, which can't reach the
+//! multiplexer otherwise. Enabled with the `bench` feature.
+use super::{send_queue, SendQueueRx, SwbusConnInfo, SwbusConnProxy, SwbusMultiplexer};
+use std::sync::Arc;
+use swbus_config::{RouteConfig, SendQueueConfig};
+use swbus_proto::swbus::{ConnectionType, RouteScope, ServicePath, SwbusMessage};
+
+/// A multiplexer with a route to the node of a single peer. The connection to the peer is only its
+/// send queue, the routed messages are taken from it instead of being streamed to the peer.
+pub struct PeerRoute {
+    mux: SwbusMultiplexer,
+    peer_queue: SendQueueRx,
+}
+
+impl PeerRoute {
+    pub fn new(my_sp: &ServicePath, peer_sp: &ServicePath) -> Self {
+        let mux = SwbusMultiplexer::new();
+        mux.set_my_routes(vec![RouteConfig {
+            key: my_sp.clone(),
+            scope: RouteScope::Cluster,
+        }]);
+        let conn_info = Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Cluster,
+            "127.0.0.1:8080".parse().unwrap(),
+            peer_sp.clone(),
+            my_sp.clone(),
+        ));
+        let (send_queue_tx, peer_queue) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+        mux.register(&conn_info, SwbusConnProxy::new(send_queue_tx));
+        PeerRoute { mux, peer_queue }
+    }
+
+    /// Route `message` with `SwbusMultiplexer::route_message` and take it from the send queue of the
+    /// peer, as the connection worker does before encoding it.
+    pub async fn route(&mut self, message: SwbusMessage) -> SwbusMessage {
+        self.mux.route_message(message).await.expect("failed to route message");
+        self.peer_queue
+            .try_recv()
+            .expect("message is not queued to the peer")
+            .expect("message is queued as an error")
+    }
+}
@@ -0,0 +1,179 @@
+//! Compression of the data requests on a connection
+//!
+//! A client offers zstd in the [`SWBUS_COMPRESSION`] meta data of its connection when it has a
+//! [`CompressionConfig`], and the server accepts it in the meta data of its response when it has one too.
+//! Each end of a compressed connection then compresses the payload of the data requests it sends when it
+//! is larger than the threshold, and flags them with [`SWBUS_FLAG_COMPRESSED`]. Flagged data requests are
+//! decompressed on receipt whatever the connection negotiated, so the messages swbusd routes are never
+//! compressed.
+use super::metrics;
+use sonic_common::metrics::Counter;
+use std::sync::Arc;
+use swbus_config::CompressionConfig;
+use swbus_proto::result::*;
+use swbus_proto::swbus::*;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tracing::warn;
+
+/// The value of [`SWBUS_COMPRESSION`] for zstd, the only compression there is
+const ZSTD: &str = "zstd";
+
+/// Offer compression in the meta data of a connection request, if there is a config.
+pub(crate) fn offer(config: Option<&CompressionConfig>, meta: &mut MetadataMap) {
+    if config.is_some() {
+        meta.insert(SWBUS_COMPRESSION, MetadataValue::from_static(ZSTD));
+    }
+}
+
+/// Whether the meta data of a connection request offers, or of its response accepts, compression.
+pub(crate) fn negotiated(meta: &MetadataMap) -> bool {
+    meta.get(SWBUS_COMPRESSION).is_some_and(|value| value == ZSTD)
+}
+
+/// Accept compression in the meta data of a connection response.
+pub(crate) fn accept(meta: &mut MetadataMap) {
+    meta.insert(SWBUS_COMPRESSION, MetadataValue::from_static(ZSTD));
+}
+
+/// Compresses the data requests sent on a connection.
+pub(crate) struct Compressor {
+    conn_id: String,
+    config: CompressionConfig,
+    original_bytes: Arc<Counter>,
+    compressed_bytes: Arc<Counter>,
+}
+
+impl Compressor {
+    /// Create a compressor of the connection `conn_id`, which labels its metrics.
+    pub(crate) fn new(conn_id: &str, config: CompressionConfig) -> Self {
+        Compressor {
+            conn_id: conn_id.to_string(),
+            config,
+            original_bytes: metrics::COMPRESSION_BYTES.with_label_values(&[conn_id, "original"]),
+            compressed_bytes: metrics::COMPRESSION_BYTES.with_label_values(&[conn_id, "compressed"]),
+        }
+    }
+
+    /// Compress the payload of `message` if it is a data request larger than the threshold, and if it
+    /// compresses to less.
+    pub(crate) fn compress(&self, mut message: SwbusMessage) -> SwbusMessage {
+        if let (Some(header), Some(swbus_message::Body::DataRequest(data))) = (&mut message.header, &mut message.body) {
+            if let Some(compressed) = self.compressed(header, &data.payload) {
+                data.payload = compressed.into();
+                header.flag |= SWBUS_FLAG_COMPRESSED;
+            }
+        }
+        message
+    }
+
+    fn compressed(&self, header: &SwbusMessageHeader, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.len() <= self.config.threshold || header.flag & SWBUS_FLAG_COMPRESSED != 0 {
+            return None;
+        }
+        let compressed = match zstd::bulk::compress(payload, self.config.level) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                warn!("Failed to compress message {}: {}", header.id, e);
+                return None;
+            }
+        };
+        if compressed.len() >= payload.len() {
+            return None;
+        }
+        self.original_bytes.inc_by(payload.len() as u64);
+        self.compressed_bytes.inc_by(compressed.len() as u64);
+        Some(compressed)
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        for size in ["original", "compressed"] {
+            metrics::COMPRESSION_BYTES.remove(&[self.conn_id.as_str(), size]);
+        }
+    }
+}
+
+/// Decompress the payload of `message` if it is flagged compressed.
+pub(crate) fn decompress(message: &mut SwbusMessage) -> Result<()> {
+    let Some(header) = &mut message.header else {
+        return Ok(());
+    };
+    if header.flag & SWBUS_FLAG_COMPRESSED == 0 {
+        return Ok(());
+    }
+    let Some(swbus_message::Body::DataRequest(data)) = &mut message.body else {
+        return Err(SwbusError::input(
+            SwbusErrorCode::InvalidHeader,
+            "Only data requests can be compressed".to_string(),
+        ));
+    };
+    let decompressed = zstd::stream::decode_all(&data.payload[..]).map_err(|e| {
+        SwbusError::input(
+            SwbusErrorCode::InvalidPayload,
+            format!("Failed to decompress payload: {e}"),
+        )
+    })?;
+    data.payload = decompressed.into();
+    header.flag &= !SWBUS_FLAG_COMPRESSED;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn data_request(payload: Vec<u8>) -> SwbusMessage {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp, 1),
+            swbus_message::Body::DataRequest(DataRequest::new(payload)),
+        )
+    }
+
+    #[test]
+    fn test_compress_and_decompress() {
+        let compressor = Compressor::new("test_compress_and_decompress", CompressionConfig::default());
+        let original = data_request(b"HA_SET_TABLE|ha-set-0".repeat(1000));
+        let mut compressed = compressor.compress(original.clone());
+        assert_ne!(compressed.header.as_ref().unwrap().flag & SWBUS_FLAG_COMPRESSED, 0);
+        let Some(swbus_message::Body::DataRequest(data)) = &compressed.body else {
+            panic!("expected a data request");
+        };
+        assert!(data.payload.len() < 1000);
+        assert_eq!(compressor.original_bytes.get(), 21000);
+
+        decompress(&mut compressed).unwrap();
+        assert_eq!(compressed, original);
+    }
+
+    #[test]
+    fn test_small_payloads_are_not_compressed() {
+        let compressor = Compressor::new("test_small_payloads_are_not_compressed", CompressionConfig::default());
+        let small = data_request(vec![0; 100]);
+        assert_eq!(compressor.compress(small.clone()), small);
+
+        // Neither are the payloads that don't get smaller
+        let mut state = 0x2545f4914f6cdd1du64;
+        let random = (0..8192)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let incompressible = data_request(random);
+        assert_eq!(compressor.compress(incompressible.clone()), incompressible);
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let mut meta = MetadataMap::new();
+        offer(None, &mut meta);
+        assert!(!negotiated(&meta));
+        offer(Some(&CompressionConfig::default()), &mut meta);
+        assert!(negotiated(&meta));
+    }
+}
@@ -1,21 +1,22 @@
+use super::compression::{self, Compressor};
 use super::conn_store::SwbusConnStore;
+use super::send_queue;
 use super::SwbusConnInfo;
 use super::SwbusConnProxy;
 use super::SwbusConnWorker;
 use super::SwbusMultiplexer;
+use super::{SendQueueRx, SendQueueTx};
 use std::io;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_client::SwbusServiceClient;
 use swbus_proto::swbus::*;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
 use tonic::metadata::MetadataValue;
 use tonic::transport::{Channel, Endpoint};
-use tonic::{Request, Status, Streaming};
+use tonic::{Request, Streaming};
 use tracing::*;
 
 #[derive(Debug)]
@@ -28,15 +29,12 @@ pub struct SwbusConn {
     shutdown_ct: CancellationToken,
 
     // Outgoing message queue
-    send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
+    send_queue_tx: SendQueueTx,
 }
 
 // Connection operations
 impl SwbusConn {
-    pub(crate) fn new(
-        conn_info: &Arc<SwbusConnInfo>,
-        send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
-    ) -> SwbusConn {
+    pub(crate) fn new(conn_info: &Arc<SwbusConnInfo>, send_queue_tx: SendQueueTx) -> SwbusConn {
         SwbusConn {
             info: conn_info.clone(),
             worker_task: None,
@@ -66,8 +64,7 @@ impl SwbusConn {
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
     ) -> Result<SwbusConn> {
-        let endpoint = Endpoint::from_str(&format!("http://{}", conn_info.remote_addr()))
-            .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Failed to create endpoint: {e}.")))?;
+        let endpoint = Self::endpoint(&conn_info, &conn_store)?;
 
         let channel = match endpoint.connect().await {
             Ok(c) => c,
@@ -84,17 +81,41 @@ impl SwbusConn {
         Self::start_client_worker_task(conn_info, client, mux, conn_store).await
     }
 
+    /// Endpoint of the peer, over TLS if the connection store has TLS set.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    fn endpoint(conn_info: &SwbusConnInfo, conn_store: &SwbusConnStore) -> Result<Endpoint> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = conn_store.tls() {
+            return Endpoint::from_str(&format!("https://{}", conn_info.remote_addr()))
+                .map_err(|e| {
+                    SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Failed to create endpoint: {e}."))
+                })?
+                .tls_config(tls.client_tls_config()?)
+                .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Invalid TLS config: {e}.")));
+        }
+        Endpoint::from_str(&format!("http://{}", conn_info.remote_addr()))
+            .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Failed to create endpoint: {e}.")))
+    }
+
     async fn start_client_worker_task(
         conn_info: Arc<SwbusConnInfo>,
         mut client: SwbusServiceClient<Channel>,
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
     ) -> Result<SwbusConn> {
-        let (send_queue_tx, send_queue_rx) = mpsc::channel(16);
+        let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &conn_store.send_queue_config());
         let mut conn = SwbusConn::new(&conn_info, send_queue_tx);
 
-        let request_stream = ReceiverStream::new(send_queue_rx)
-            .map(|result| result.expect("Not expecting grpc client adding messages with error status"));
+        // Messages are compressed once the server accepts, see `compression`
+        let compressor = Arc::new(OnceLock::<Compressor>::new());
+        let stream_compressor = compressor.clone();
+        let request_stream = send_queue_rx.map(move |result| {
+            let message = result.expect("Not expecting grpc client adding messages with error status");
+            match stream_compressor.get() {
+                Some(compressor) => compressor.compress(message),
+                None => message,
+            }
+        });
 
         let mut stream_message_request = Request::new(request_stream);
 
@@ -113,9 +134,17 @@ impl SwbusConn {
             SWBUS_CONNECTION_TYPE,
             MetadataValue::from_str(conn_info.connection_type().as_str_name()).unwrap(),
         );
+        let compression_config = conn_store.compression_config();
+        compression::offer(compression_config.as_ref(), meta);
 
         let incoming_stream = match client.stream_messages(stream_message_request).await {
-            Ok(response) => response.into_inner(),
+            Ok(response) => {
+                if let Some(config) = compression_config.filter(|_| compression::negotiated(response.metadata())) {
+                    info!("Compressing data requests to {}", conn_info.remote_addr());
+                    _ = compressor.set(Compressor::new(conn_info.id(), config));
+                }
+                response.into_inner()
+            }
             Err(e) => {
                 error!("Failed to establish message streaming: {}.", e);
                 return Err(SwbusError::connection(
@@ -172,22 +201,24 @@ impl SwbusConn {
     /// - conn_type: The connection type.
     /// - client_addr: The client address.
     /// - incoming_stream: The incoming message stream.
-    /// - send_queue_tx: The tx end of outgoing message queue
     /// - mux: The SwbusMultiplexer
-    pub async fn from_incoming_stream(
+    ///
+    /// Returns the connection and the stream of its outgoing messages.
+    pub(crate) async fn from_incoming_stream(
         conn_info: Arc<SwbusConnInfo>,
         incoming_stream: Streaming<SwbusMessage>,
-        send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
-    ) -> SwbusConn {
-        Self::start_server_worker_task(conn_info, incoming_stream, send_queue_tx, mux, conn_store).await
+    ) -> (SwbusConn, SendQueueRx) {
+        let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &conn_store.send_queue_config());
+        let conn = Self::start_server_worker_task(conn_info, incoming_stream, send_queue_tx, mux, conn_store).await;
+        (conn, send_queue_rx)
     }
 
     async fn start_server_worker_task(
         conn_info: Arc<SwbusConnInfo>,
         incoming_stream: Streaming<SwbusMessage>,
-        send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
+        send_queue_tx: SendQueueTx,
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
     ) -> SwbusConn {
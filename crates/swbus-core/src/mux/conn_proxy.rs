@@ -1,41 +1,35 @@
+use super::SendQueueTx;
 use swbus_proto::result::*;
 use swbus_proto::swbus::SwbusMessage;
-use swbus_proto::swbus::*;
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::error::TrySendError;
 use tonic::Status;
 
 #[derive(Debug, Clone)]
 pub(crate) struct SwbusConnProxy {
-    pub send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
+    pub send_queue_tx: SendQueueTx,
 }
 
 impl SwbusConnProxy {
-    pub fn new(send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>) -> Self {
+    pub fn new(send_queue_tx: SendQueueTx) -> Self {
         SwbusConnProxy { send_queue_tx }
     }
 
+    /// Queue a message to the connection, in the send queue lane of its priority.
     pub async fn try_queue(&self, message: Result<SwbusMessage, Status>) -> Result<()> {
-        let tx = &self.send_queue_tx;
-
-        match tx.try_send(message) {
-            Ok(_) => Ok(()),
-            Err(e) => match e {
-                TrySendError::Full(_) => Err(SwbusError::route(SwbusErrorCode::QueueFull, e.to_string())),
-                _ => Err(SwbusError::route(SwbusErrorCode::NoRoute, e.to_string())),
-            },
-        }
+        self.send_queue_tx.try_send(message)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mux::send_queue;
     use pretty_assertions::assert_eq;
+    use swbus_config::SendQueueConfig;
+    use swbus_proto::swbus::*;
 
     #[tokio::test]
     async fn conn_proxy_can_queue_message() {
-        let (tx, mut rx) = mpsc::channel(1);
+        let (tx, mut rx) = send_queue::channel("conn_proxy_can_queue_message", &SendQueueConfig::default());
         let proxy = SwbusConnProxy::new(tx);
 
         let message = SwbusMessage::default();
@@ -47,7 +41,11 @@ mod tests {
 
     #[tokio::test]
     async fn conn_proxy_should_fail_when_queue_full() {
-        let (tx, _rx) = mpsc::channel(1);
+        let config = SendQueueConfig {
+            high_priority_depth: 1,
+            ..Default::default()
+        };
+        let (tx, _rx) = send_queue::channel("conn_proxy_should_fail_when_queue_full", &config);
         let proxy = SwbusConnProxy::new(tx);
 
         let message = SwbusMessage::default();
@@ -1,10 +1,14 @@
+use crate::mux::auth::{ClientAuth, ClientAuthenticator, ClientCredentials};
 use crate::mux::conn::SwbusConn;
+use crate::mux::metrics;
 use crate::mux::SwbusConnInfo;
 use crate::mux::SwbusConnMode;
 use crate::mux::SwbusMultiplexer;
 use dashmap::{DashMap, DashSet};
-use std::sync::Arc;
-use swbus_config::{PeerConfig, RouteConfig};
+use std::sync::{Arc, RwLock};
+use swbus_config::{ClientAuthConfig, CompressionConfig, PeerConfig, RateLimitConfig, RouteConfig, SendQueueConfig};
+use swbus_proto::result::*;
+use swbus_proto::swbus::{ConnectionType, ServicePath};
 use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 use tracing::*;
@@ -19,6 +23,12 @@ pub struct SwbusConnStore {
     mux: Arc<SwbusMultiplexer>,
     connections: DashMap<Arc<SwbusConnInfo>, ConnTracker>,
     my_routes: DashSet<RouteConfig>,
+    send_queue_config: RwLock<SendQueueConfig>,
+    compression_config: RwLock<Option<CompressionConfig>>,
+    rate_limit_config: RwLock<Option<RateLimitConfig>>,
+    client_auth: ClientAuth,
+    #[cfg(feature = "tls")]
+    tls: std::sync::OnceLock<Arc<crate::mux::tls::SwbusTls>>,
 }
 
 impl SwbusConnStore {
@@ -27,9 +37,78 @@ impl SwbusConnStore {
             mux,
             connections: DashMap::new(),
             my_routes: DashSet::new(),
+            send_queue_config: RwLock::new(SendQueueConfig::default()),
+            compression_config: RwLock::new(None),
+            rate_limit_config: RwLock::new(None),
+            client_auth: ClientAuth::default(),
+            #[cfg(feature = "tls")]
+            tls: std::sync::OnceLock::new(),
         }
     }
 
+    /// Set the send queue of the connections established from now on.
+    pub fn set_send_queue_config(&self, config: SendQueueConfig) {
+        *self.send_queue_config.write().unwrap() = config;
+    }
+
+    pub(crate) fn send_queue_config(&self) -> SendQueueConfig {
+        *self.send_queue_config.read().unwrap()
+    }
+
+    /// Set the compression of the connections established from now on. None turns it off.
+    pub fn set_compression_config(&self, config: Option<CompressionConfig>) {
+        *self.compression_config.write().unwrap() = config;
+    }
+
+    pub(crate) fn compression_config(&self) -> Option<CompressionConfig> {
+        *self.compression_config.read().unwrap()
+    }
+
+    /// Set the rate limit of the client connections established from now on. None turns it off.
+    pub fn set_rate_limit_config(&self, config: Option<RateLimitConfig>) {
+        *self.rate_limit_config.write().unwrap() = config;
+    }
+
+    pub(crate) fn rate_limit_config(&self) -> Option<RateLimitConfig> {
+        *self.rate_limit_config.read().unwrap()
+    }
+
+    /// Authenticate the clients that connect from now on with `config`, see [`crate::mux::auth`]. None
+    /// turns it off. The current config is kept if it can't be loaded.
+    pub fn set_client_auth_config(&self, config: Option<&ClientAuthConfig>) -> Result<()> {
+        self.client_auth.set_config(config)
+    }
+
+    /// Authenticate the clients with `authenticator` too, see [`crate::mux::auth`].
+    pub fn add_client_authenticator(&self, authenticator: Arc<dyn ClientAuthenticator>) {
+        self.client_auth.add(authenticator);
+    }
+
+    pub(crate) fn authenticate_client(&self, credentials: &ClientCredentials) -> std::result::Result<(), String> {
+        self.client_auth.authenticate(credentials)
+    }
+
+    pub(crate) fn authorize_management(
+        &self,
+        connection_type: ConnectionType,
+        service_path: &ServicePath,
+    ) -> std::result::Result<(), String> {
+        self.client_auth.authorize_management(connection_type, service_path)
+    }
+
+    /// Connect to peers over TLS. Must be set before any peer is added.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&self, tls: Arc<crate::mux::tls::SwbusTls>) {
+        if self.tls.set(tls).is_err() {
+            warn!("TLS of the connections to peers is already set");
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(&self) -> Option<&Arc<crate::mux::tls::SwbusTls>> {
+        self.tls.get()
+    }
+
     #[instrument(skip(self, conn_info), fields(conn_id=conn_info.id()))]
     fn start_connect_task(self: &Arc<SwbusConnStore>, conn_info: Arc<SwbusConnInfo>, reconnect: bool) {
         let conn_info_clone = conn_info.clone();
@@ -52,6 +131,11 @@ impl SwbusConnStore {
                     }
                     match SwbusConn::connect(conn_info.clone(), mux_clone.clone(), conn_store.clone()).await {
                         Ok(conn) => {
+                            if child_token.is_cancelled() {
+                                // the peer is removed while connecting
+                                let _ = conn.shutdown().await;
+                                return;
+                            }
                             info!("Successfully connect to the peer");
                             // register the new connection and update the route table
                             conn_store.conn_established(conn);
@@ -72,6 +156,10 @@ impl SwbusConnStore {
         self.my_routes.insert(my_route);
     }
 
+    pub fn remove_my_route(&self, my_route: &RouteConfig) {
+        self.my_routes.remove(my_route);
+    }
+
     pub fn add_peer(self: &Arc<SwbusConnStore>, peer: PeerConfig) {
         // todo: assuming only one route for now. Will be improved to send routes in route update message and remove this
         let my_route = self.my_routes.iter().next().expect("My service path is not set");
@@ -84,12 +172,49 @@ impl SwbusConnStore {
         self.start_connect_task(conn_info, false);
     }
 
+    /// Stop connecting to a peer added by [`Self::add_peer`] and close the connection to it.
+    pub async fn remove_peer(&self, peer: &PeerConfig) {
+        let conn_infos: Vec<Arc<SwbusConnInfo>> = self
+            .connections
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|conn_info| {
+                conn_info.mode() == SwbusConnMode::Client
+                    && conn_info.remote_addr() == peer.endpoint
+                    && conn_info.remote_service_path() == &peer.id
+                    && conn_info.connection_type() == peer.conn_type
+            })
+            .collect();
+
+        for conn_info in conn_infos {
+            match self.connections.remove(&conn_info) {
+                Some((_, ConnTracker::SwbusConn(conn))) => {
+                    info!("Closing connection to removed peer: {}", conn_info.id());
+                    if let Err(swbus_err) = conn.shutdown().await {
+                        error!("Failed to shutdown connection: {:?}", swbus_err);
+                    }
+                }
+                Some((_, ConnTracker::Task(task))) => {
+                    info!("Stopping connection task to removed peer: {}", conn_info.id());
+                    task.cancel();
+                }
+                None => {}
+            }
+        }
+    }
+
     pub fn conn_lost(self: &Arc<SwbusConnStore>, conn_info: Arc<SwbusConnInfo>) {
+        // The peer is connected with the connection that replaced this one
+        if self.is_replaced(&conn_info) {
+            return;
+        }
+
         // First, we remove the connection from the connection table.
         self.connections.remove(&conn_info);
 
         // If connection is client mode, we start a new connection task.
         if conn_info.mode() == SwbusConnMode::Client {
+            metrics::RECONNECTS.with_label_values(&[conn_info.id().as_str()]).inc();
             self.start_connect_task(conn_info, true /*reconnect from connection loss*/);
         }
     }
@@ -100,6 +225,58 @@ impl SwbusConnStore {
             .insert(conn.info().clone(), ConnTracker::SwbusConn(conn));
     }
 
+    /// Whether the connection of `conn_info` was replaced by another connection to the same peer, see
+    /// [`Self::reconnect_peers`]. The connection infos of the two are equal, but not the same.
+    pub(crate) fn is_replaced(&self, conn_info: &Arc<SwbusConnInfo>) -> bool {
+        match self.connections.get(conn_info).as_deref() {
+            Some(ConnTracker::SwbusConn(conn)) => !Arc::ptr_eq(conn.info(), conn_info),
+            _ => false,
+        }
+    }
+
+    /// Connect to the connected peers again and replace the connections to them with the new ones, one
+    /// peer at a time, e.g. to use rotated TLS certificates. The new connection takes over the route to
+    /// the peer before the old one is closed, so the route table is kept intact. Stops at the first peer
+    /// that can't be connected, its connection and the ones after it are left as they are. Returns the
+    /// number of connections replaced.
+    pub async fn reconnect_peers(self: &Arc<SwbusConnStore>) -> Result<usize> {
+        let conn_infos: Vec<Arc<SwbusConnInfo>> = self
+            .connections
+            .iter()
+            .filter(|entry| {
+                entry.key().mode() == SwbusConnMode::Client && matches!(entry.value(), ConnTracker::SwbusConn(_))
+            })
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut replaced = 0;
+        for conn_info in conn_infos {
+            let new_conn_info = Arc::new(SwbusConnInfo::clone(&conn_info));
+            let conn = SwbusConn::connect(new_conn_info, self.mux.clone(), self.clone()).await?;
+            if !matches!(
+                self.connections.get(&conn_info).as_deref(),
+                Some(ConnTracker::SwbusConn(_))
+            ) {
+                // the peer is removed or lost while connecting
+                let _ = conn.shutdown().await;
+                continue;
+            }
+
+            info!("Replacing connection to the peer: {}", conn_info.id());
+            self.mux.register(conn.info(), conn.new_proxy());
+            let old = self
+                .connections
+                .insert(conn.info().clone(), ConnTracker::SwbusConn(conn));
+            if let Some(ConnTracker::SwbusConn(old)) = old {
+                if let Err(swbus_err) = old.shutdown().await {
+                    error!("Failed to shutdown connection: {:?}", swbus_err);
+                }
+            }
+            replaced += 1;
+        }
+        Ok(replaced)
+    }
+
     pub async fn shutdown(&self) {
         for entry in self.connections.iter() {
             match entry.value() {
@@ -123,10 +300,11 @@ impl SwbusConnStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mux::send_queue;
     use swbus_proto::swbus::ConnectionType;
     use swbus_proto::swbus::RouteScope;
     use swbus_proto::swbus::ServicePath;
-    use tokio::sync::mpsc;
+
     #[tokio::test]
     async fn test_add_peer() {
         let mux = Arc::new(SwbusMultiplexer::new());
@@ -149,6 +327,33 @@ mod tests {
         }));
     }
 
+    #[tokio::test]
+    async fn test_remove_peer() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        let route_config = RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        };
+        conn_store.add_my_route(route_config);
+        let peer_config = |endpoint: &str| PeerConfig {
+            conn_type: ConnectionType::Cluster,
+            endpoint: endpoint.parse().unwrap(),
+            id: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+        };
+        conn_store.add_peer(peer_config("127.0.0.1:8080"));
+        conn_store.add_peer(peer_config("127.0.0.1:8081"));
+
+        conn_store.remove_peer(&peer_config("127.0.0.1:8080")).await;
+
+        let ids: Vec<String> = conn_store
+            .connections
+            .iter()
+            .map(|entry| entry.key().id().clone())
+            .collect();
+        assert_eq!(ids, vec!["swbs-to://127.0.0.1:8081".to_string()]);
+    }
+
     #[tokio::test]
     async fn test_add_my_route() {
         let mux = Arc::new(SwbusMultiplexer::new());
@@ -202,7 +407,7 @@ mod tests {
             ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
             ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
         ));
-        let (send_queue_tx, _) = mpsc::channel(16);
+        let (send_queue_tx, _) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
         let conn = SwbusConn::new(&conn_info, send_queue_tx);
         conn_store.conn_established(conn);
 
@@ -211,4 +416,33 @@ mod tests {
             .iter()
             .any(|entry| entry.key().id() == conn_info.id() && matches!(entry.value(), ConnTracker::SwbusConn(_))));
     }
+
+    #[tokio::test]
+    async fn test_replaced_conn_lost() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        let old_conn_info = Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Cluster,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
+            ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
+        ));
+        let (send_queue_tx, _) = send_queue::channel(old_conn_info.id(), &SendQueueConfig::default());
+        conn_store.conn_established(SwbusConn::new(&old_conn_info, send_queue_tx));
+        assert!(!conn_store.is_replaced(&old_conn_info));
+
+        // replace the connection, as reconnect_peers does
+        let new_conn_info = Arc::new(SwbusConnInfo::clone(&old_conn_info));
+        let (send_queue_tx, _) = send_queue::channel(new_conn_info.id(), &SendQueueConfig::default());
+        conn_store.conn_established(SwbusConn::new(&new_conn_info, send_queue_tx));
+        assert!(conn_store.is_replaced(&old_conn_info));
+        assert!(!conn_store.is_replaced(&new_conn_info));
+
+        // losing the old connection leaves the new one alone
+        conn_store.conn_lost(old_conn_info);
+        assert!(matches!(
+            conn_store.connections.get(&new_conn_info).as_deref(),
+            Some(ConnTracker::SwbusConn(conn)) if Arc::ptr_eq(conn.info(), &new_conn_info)
+        ));
+    }
 }
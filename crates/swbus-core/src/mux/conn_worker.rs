@@ -1,12 +1,18 @@
+use super::compression;
+use super::metrics;
+use super::rate_limit::RateLimiter;
 use super::SwbusConnInfo;
 use super::SwbusMultiplexer;
 use crate::mux::conn_store::SwbusConnStore;
 use futures_core::stream::Stream;
+use prost::Message as _;
 use std::io;
 use std::sync::Arc;
+use swbus_config::RateLimitPolicy;
 use swbus_proto::result::*;
 use swbus_proto::swbus::SwbusMessage;
 use swbus_proto::swbus::*;
+use tokio::time::Instant;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
 use tonic::Status;
@@ -22,6 +28,9 @@ where
     message_stream: T,
     mux: Arc<SwbusMultiplexer>,
     conn_store: Arc<SwbusConnStore>,
+    rate_limiter: Option<RateLimiter>,
+    #[cfg(feature = "fault-injection")]
+    faults: Arc<super::fault_injection::PeerFaults>,
 }
 
 // Connection worker facade
@@ -36,12 +45,18 @@ where
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
     ) -> Self {
+        let rate_limiter = RateLimiter::new(conn_store.rate_limit_config(), info.connection_type(), Instant::now());
+        #[cfg(feature = "fault-injection")]
+        let faults = mux.peer_faults(info.remote_service_path());
         Self {
             info,
             shutdown_ct,
             message_stream,
             mux,
             conn_store,
+            rate_limiter,
+            #[cfg(feature = "fault-injection")]
+            faults,
         }
     }
 
@@ -75,11 +90,23 @@ where
     }
 
     fn unregister_from_mux(&self) -> Result<()> {
+        // a replaced connection leaves the route to the peer to the connection replacing it
+        if self.conn_store.is_replaced(&self.info) {
+            info!("Connection is replaced, keeping the route to the peer.");
+            return Ok(());
+        }
         self.mux.unregister(self.info.clone());
         Ok(())
     }
 
     async fn run_worker_loop(&mut self) -> Result<()> {
+        #[cfg(feature = "fault-injection")]
+        let faults = self.faults.clone();
+        #[cfg(feature = "fault-injection")]
+        let reset_requested = faults.reset_requested();
+        #[cfg(not(feature = "fault-injection"))]
+        let reset_requested = std::future::pending::<()>();
+        tokio::pin!(reset_requested);
         loop {
             tokio::select! {
                 _ = self.shutdown_ct.cancelled() => {
@@ -87,6 +114,14 @@ where
                     break;
                 }
 
+                _ = &mut reset_requested => {
+                    warn!("Resetting the connection, as requested by fault injection.");
+                    return Err(SwbusError::connection(
+                        SwbusErrorCode::ConnectionError,
+                        io::Error::new(io::ErrorKind::ConnectionReset, "Reset by fault injection.".to_string()),
+                    ));
+                }
+
                 data_message = self.message_stream.next() => {
                     match data_message {
                         Some(Ok(message)) => {
@@ -119,29 +154,108 @@ where
         Ok(())
     }
 
-    #[instrument(name="receive_msg", level="debug", skip_all, fields(message.id=message.header.as_ref().unwrap().id))]
-    async fn process_data_message(&mut self, message: SwbusMessage) -> Result<()> {
+    #[instrument(name="receive_msg", level="debug", skip_all, fields(message.id=message.header.as_ref().unwrap().id, trace_id=%message.header.as_ref().unwrap().trace_id))]
+    async fn process_data_message(&mut self, mut message: SwbusMessage) -> Result<()> {
         debug!("{:?}", &message);
         self.validate_message_common(&message)?;
-        match message.body {
-            Some(swbus_message::Body::TraceRouteRequest(_)) => {
-                info!("Received traceroute request: {:?}", message);
-
-                let id = self.mux.generate_message_id();
-                let my_sp = self.mux.get_my_service_path();
-                let response = SwbusMessage::new_response(&message, Some(&my_sp), SwbusErrorCode::Ok, "", id, None);
-
-                self.mux.route_message(response).await?;
+        if !self.apply_rate_limit(&message).await? {
+            return Ok(());
+        }
+        if !self.authorize_management(&message).await? {
+            return Ok(());
+        }
+        compression::decompress(&mut message)?;
+        #[cfg(feature = "fault-injection")]
+        match self.faults.next_fault() {
+            super::fault_injection::Fault::None => {}
+            super::fault_injection::Fault::Drop => {
+                debug!("Dropping message by fault injection");
+                self.mux.drop_undeliverable(&message, "fault_injected");
+                return Ok(());
+            }
+            super::fault_injection::Fault::Delay(latency) => tokio::time::sleep(latency).await,
+            super::fault_injection::Fault::Reorder(delay) => {
+                debug!("Holding message back for {:?} by fault injection", delay);
+                let mux = self.mux.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(e) = route_incoming(&mux, message).await {
+                        error!("Failed to process the incoming message: {}", e);
+                    }
+                });
+                return Ok(());
+            }
+        }
+        route_incoming(&self.mux, message).await
+    }
 
-                if message.header.as_ref().unwrap().destination.as_ref().unwrap() != &my_sp {
-                    self.mux.route_message(message).await?;
+    /// Apply the rate limit of the connection to a message, as it came over the wire. Returns whether the
+    /// message goes on. One over the limit is delayed until it is within it, or rejected with a
+    /// RateLimited error response, depending on the policy.
+    async fn apply_rate_limit(&mut self, message: &SwbusMessage) -> Result<bool> {
+        let Some(limiter) = self.rate_limiter.as_mut() else {
+            return Ok(true);
+        };
+        let size = message.encoded_len();
+        loop {
+            let wait = match limiter.try_acquire(size, Instant::now()) {
+                Ok(()) => return Ok(true),
+                Err(wait) => wait,
+            };
+            match limiter.policy() {
+                RateLimitPolicy::Backpressure => {
+                    debug!("Rate limit exceeded, holding the connection for {:?}", wait);
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = self.shutdown_ct.cancelled() => return Ok(false),
+                    }
                 }
-            }
-            _ => {
-                self.mux.route_message(message).await?;
+                RateLimitPolicy::Reject => break,
             }
         }
-        Ok(())
+
+        debug!("Rate limit exceeded, rejecting the message");
+        self.mux.drop_undeliverable(message, "rate_limited");
+        // There is nobody to tell about a response
+        if matches!(message.body, Some(swbus_message::Body::Response(_))) {
+            return Ok(false);
+        }
+        let response = SwbusMessage::new_response(
+            message,
+            Some(&self.mux.get_my_service_path()),
+            SwbusErrorCode::RateLimited,
+            "Rate limit exceeded",
+            self.mux.generate_message_id(),
+            None,
+        );
+        self.mux.route_message(response).await?;
+        Ok(false)
+    }
+
+    /// Refuse a privileged management request the client of the connection is not allowed to make, see
+    /// [`super::auth`], with a PermissionDenied error response. Returns whether the message goes on.
+    async fn authorize_management(&self, message: &SwbusMessage) -> Result<bool> {
+        if !super::auth::is_privileged(message) {
+            return Ok(true);
+        }
+        let Err(e) = self
+            .conn_store
+            .authorize_management(self.info.connection_type(), self.info.remote_service_path())
+        else {
+            return Ok(true);
+        };
+        info!("Refusing management request: {}", e);
+        metrics::message_dropped("permission_denied");
+        let response = SwbusMessage::new_response(
+            message,
+            Some(&self.mux.get_my_service_path()),
+            SwbusErrorCode::PermissionDenied,
+            &e,
+            self.mux.generate_message_id(),
+            None,
+        );
+        self.mux.route_message(response).await?;
+        Ok(false)
     }
 
     fn validate_message_common(&mut self, message: &SwbusMessage) -> Result<()> {
@@ -181,14 +295,60 @@ where
             ));
         }
 
-        Ok(())
+        self.validate_source(message_header.source.as_ref().unwrap())
+    }
+
+    /// A client, including a swbus-edge runtime connecting as local, only sends messages from the
+    /// service path it was authenticated with or from below it, e.g. from the actors of hamgrd. It would
+    /// otherwise pass itself off as another service, and get the responses meant for it. Peers forward
+    /// messages from anywhere.
+    fn validate_source(&self, source: &ServicePath) -> Result<()> {
+        let identity = match self.info.connection_type() {
+            ConnectionType::Client => self.info.remote_service_path().to_longest_path(),
+            ConnectionType::Local => self.info.remote_service_path().to_service_prefix(),
+            _ => return Ok(()),
+        };
+        let source = source.to_longest_path();
+        if super::auth::covers(&identity, &source) {
+            return Ok(());
+        }
+        Err(SwbusError::input(
+            SwbusErrorCode::InvalidSource,
+            format!("Message source {source} is not under the service path {identity} of the connection"),
+        ))
+    }
+}
+
+/// Route a message received on the connection.
+async fn route_incoming(mux: &SwbusMultiplexer, mut message: SwbusMessage) -> Result<()> {
+    match message.body {
+        Some(swbus_message::Body::TraceRouteRequest(ref mut request)) => {
+            info!("Received traceroute request: {:?}", request);
+
+            let id = mux.generate_message_id();
+            let my_sp = mux.get_my_service_path();
+            // Record this hop in the request it forwards, so later hops report the whole path
+            let hops = request.add_hop(my_sp.clone());
+            let response = SwbusMessage::new_response(&message, Some(&my_sp), SwbusErrorCode::Ok, "", id, Some(hops));
+
+            mux.route_message(response).await?;
+
+            if message.header.as_ref().unwrap().destination.as_ref().unwrap() != &my_sp {
+                mux.route_message(message).await?;
+            }
+        }
+        _ => {
+            mux.route_message(message).await?;
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use swbus_config::RouteConfig;
+    use prost::Message;
+    use swbus_config::{RateLimitConfig, RouteConfig};
     use tokio_stream::{self as stream};
 
     #[tokio::test]
@@ -251,6 +411,128 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn conn_worker_rejects_messages_over_the_rate_limit() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        conn_store.set_rate_limit_config(Some(RateLimitConfig {
+            messages_per_sec: 1,
+            bytes_per_sec: 0,
+            policy: RateLimitPolicy::Reject,
+        }));
+        let conn_info = Arc::new(SwbusConnInfo::new_server(
+            ConnectionType::Local,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+        ));
+        let mut worker = SwbusConnWorker::new(
+            conn_info,
+            CancellationToken::new(),
+            stream::iter(vec![]),
+            mux.clone(),
+            conn_store,
+        );
+
+        let ping = |id| {
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+                id,
+            );
+            SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()))
+        };
+        assert!(worker.apply_rate_limit(&ping(1)).await.unwrap());
+        assert!(!worker.apply_rate_limit(&ping(2)).await.unwrap());
+        let dead_letters = mux.export_dead_letters(true).entries;
+        assert_eq!(dead_letters[0].reason, "rate_limited");
+        let message = SwbusMessage::decode(dead_letters[0].message.as_slice()).unwrap();
+        assert_eq!(message.header.unwrap().id, 2);
+    }
+
+    #[tokio::test]
+    async fn conn_worker_rejects_messages_from_other_services() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        let conn_info = Arc::new(SwbusConnInfo::new_server(
+            ConnectionType::Local,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+        ));
+        let mut worker = SwbusConnWorker::new(
+            conn_info,
+            CancellationToken::new(),
+            stream::iter(vec![]),
+            mux,
+            conn_store,
+        );
+
+        let ping = |source| {
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string(source).unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+                1,
+            );
+            SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()))
+        };
+        assert!(worker
+            .validate_message_common(&ping("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"))
+            .is_ok());
+        assert!(worker
+            .validate_message_common(&ping("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/vdpu/vdpu0"))
+            .is_ok());
+        for source in [
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1",
+            "region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0",
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+        ] {
+            let Err(SwbusError::InputError { code, .. }) = worker.validate_message_common(&ping(source)) else {
+                panic!("message from {source} accepted");
+            };
+            assert_eq!(code, SwbusErrorCode::InvalidSource);
+        }
+    }
+
+    #[tokio::test]
+    async fn conn_worker_refuses_privileged_requests_through_peers() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        let worker = |connection_type, remote_sp| {
+            let conn_info = Arc::new(SwbusConnInfo::new_server(
+                connection_type,
+                "127.0.0.1:8080".parse().unwrap(),
+                ServicePath::from_string(remote_sp).unwrap(),
+            ));
+            SwbusConnWorker::new(
+                conn_info,
+                CancellationToken::new(),
+                stream::iter(vec![]),
+                mux.clone(),
+                conn_store.clone(),
+            )
+        };
+
+        let capture = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+                1,
+            ),
+            swbus_message::Body::ManagementRequest(ManagementRequest::new(ManagementRequestType::SwbusdCapture)),
+        );
+        let client = worker(ConnectionType::Local, "region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0");
+        assert!(client.authorize_management(&capture).await.unwrap());
+        let peer = worker(ConnectionType::Cluster, "region-a.cluster-a.10.0.0.2-dpu0");
+        assert!(!peer.authorize_management(&capture).await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_worker_invalid_message() {
         let shutdown_ct = CancellationToken::new();
@@ -280,6 +562,7 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            trace_id: String::new(),
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap()),
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -295,6 +578,7 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            trace_id: String::new(),
             source: None,
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -310,6 +594,7 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            trace_id: String::new(),
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
             destination: None,
         };
@@ -325,6 +610,7 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            trace_id: String::new(),
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap()),
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -0,0 +1,269 @@
+//! Fault injection
+//!
+//! Built with the `fault-injection` feature only, so HA convergence can be tested under network faults.
+//! Faults are set per peer with `MANAGEMENT_REQUEST_TYPE_SWBUSD_INJECT_FAULTS` and injected by the
+//! workers of the connections to the peer into the messages they receive: a message can be dropped,
+//! delayed, or held back so the ones after it overtake it. The connections can also be reset, which
+//! makes the client side reconnect as if the connection was lost.
+use dashmap::DashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{ManagementRequest, ServicePath, SwbusErrorCode};
+use tokio::sync::Notify;
+use tracing::*;
+
+/// Extra time a reordered message is held back, on top of the latency.
+const REORDER_DELAY: Duration = Duration::from_millis(100);
+
+/// Faults injected into the messages from a peer.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct FaultConfig {
+    /// Probability that a message is dropped
+    pub drop_probability: f64,
+    /// Time every message is delayed by
+    pub latency: Duration,
+    /// Probability that a message is held back for [`REORDER_DELAY`], so the ones after it overtake it
+    pub reorder_probability: f64,
+}
+
+/// The fault injected into a message.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Fault {
+    None,
+    Drop,
+    /// Deliver the message after this time, holding up the ones after it
+    Delay(Duration),
+    /// Deliver the message after this time, letting the ones after it go first
+    Reorder(Duration),
+}
+
+/// What a fault injection request asks for.
+#[derive(Debug, PartialEq)]
+pub(crate) enum FaultRequest {
+    Set(FaultConfig),
+    Clear,
+    Reset,
+}
+
+/// Faults of the connections to one peer.
+#[derive(Debug, Default)]
+pub(crate) struct PeerFaults {
+    config: RwLock<FaultConfig>,
+    reset: Notify,
+}
+
+impl PeerFaults {
+    /// The fault to inject into the next message from the peer.
+    pub(crate) fn next_fault(&self) -> Fault {
+        let config = *self.config.read().unwrap();
+        if config.drop_probability > 0.0 && rand::random::<f64>() < config.drop_probability {
+            return Fault::Drop;
+        }
+        if config.reorder_probability > 0.0 && rand::random::<f64>() < config.reorder_probability {
+            return Fault::Reorder(config.latency + REORDER_DELAY);
+        }
+        match config.latency.is_zero() {
+            true => Fault::None,
+            false => Fault::Delay(config.latency),
+        }
+    }
+
+    /// Wait until the connections to the peer are to be reset.
+    pub(crate) async fn reset_requested(&self) {
+        self.reset.notified().await
+    }
+}
+
+/// Faults injected into the connections to each peer, by the service path of the peer.
+#[derive(Debug, Default)]
+pub(crate) struct FaultInjector {
+    peers: DashMap<String, Arc<PeerFaults>>,
+}
+
+impl FaultInjector {
+    /// The faults of the connections to `peer`, none until they are set.
+    pub(crate) fn peer(&self, peer: &ServicePath) -> Arc<PeerFaults> {
+        self.peers.entry(peer.to_longest_path()).or_default().clone()
+    }
+
+    /// Set or clear the faults of a peer, or reset the connections to it, as `mgmt_request` asks.
+    pub(crate) fn apply(&self, mgmt_request: &ManagementRequest) -> Result<()> {
+        let (peer, request) = parse_request(mgmt_request)?;
+        let faults = self.peer(&peer);
+        match request {
+            FaultRequest::Set(config) => {
+                warn!(
+                    "Injecting faults into messages from {}: {:?}",
+                    peer.to_longest_path(),
+                    config
+                );
+                *faults.config.write().unwrap() = config;
+            }
+            FaultRequest::Clear => {
+                warn!("Stop injecting faults into messages from {}", peer.to_longest_path());
+                *faults.config.write().unwrap() = FaultConfig::default();
+            }
+            FaultRequest::Reset => {
+                warn!("Resetting the connections to {}", peer.to_longest_path());
+                faults.reset.notify_waiters();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_request(mgmt_request: &ManagementRequest) -> Result<(ServicePath, FaultRequest)> {
+    let arg = |name: &str| {
+        mgmt_request
+            .arguments
+            .iter()
+            .find(|arg| arg.name == name)
+            .map(|arg| arg.value.as_str())
+    };
+    let invalid = |detail: String| SwbusError::input(SwbusErrorCode::InvalidArgs, detail);
+
+    let peer = arg("peer").ok_or_else(|| invalid("missing peer".to_string()))?;
+    let peer = ServicePath::from_string(peer).map_err(|_| invalid(format!("Invalid peer: {peer}")))?;
+    if arg("reset").is_some() {
+        return Ok((peer, FaultRequest::Reset));
+    }
+    if arg("clear").is_some() {
+        return Ok((peer, FaultRequest::Clear));
+    }
+
+    let probability = |name: &str| match arg(name) {
+        Some(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|p| (0.0..=1.0).contains(p))
+            .ok_or_else(|| invalid(format!("Invalid {name}: {value}"))),
+        None => Ok(0.0),
+    };
+    let latency_ms = match arg("latency_ms") {
+        Some(value) => value
+            .parse()
+            .map_err(|_| invalid(format!("Invalid latency_ms: {value}")))?,
+        None => 0,
+    };
+    let config = FaultConfig {
+        drop_probability: probability("drop_probability")?,
+        latency: Duration::from_millis(latency_ms),
+        reorder_probability: probability("reorder_probability")?,
+    };
+    Ok((peer, FaultRequest::Set(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::{ManagementRequestArg, ManagementRequestType};
+
+    fn request(arguments: &[(&str, &str)]) -> ManagementRequest {
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdInjectFaults);
+        mgmt_request.arguments = arguments
+            .iter()
+            .map(|(name, value)| ManagementRequestArg {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect();
+        mgmt_request
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap();
+        let (sp, faults) = parse_request(&request(&[
+            ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+            ("drop_probability", "0.5"),
+            ("latency_ms", "20"),
+        ]))
+        .unwrap();
+        assert_eq!(sp, peer);
+        assert_eq!(
+            faults,
+            FaultRequest::Set(FaultConfig {
+                drop_probability: 0.5,
+                latency: Duration::from_millis(20),
+                reorder_probability: 0.0,
+            })
+        );
+
+        let (_, faults) =
+            parse_request(&request(&[("peer", "region-a.cluster-a.10.0.0.1-dpu0"), ("reset", "")])).unwrap();
+        assert_eq!(faults, FaultRequest::Reset);
+        let (_, faults) =
+            parse_request(&request(&[("peer", "region-a.cluster-a.10.0.0.1-dpu0"), ("clear", "")])).unwrap();
+        assert_eq!(faults, FaultRequest::Clear);
+
+        assert!(parse_request(&request(&[("drop_probability", "0.5")])).is_err());
+        assert!(parse_request(&request(&[
+            ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+            ("drop_probability", "1.5")
+        ]))
+        .is_err());
+        assert!(parse_request(&request(&[
+            ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+            ("latency_ms", "-1")
+        ]))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_faults_of_peer() {
+        let injector = FaultInjector::default();
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap();
+        let faults = injector.peer(&peer);
+        assert_eq!(faults.next_fault(), Fault::None);
+
+        injector
+            .apply(&request(&[
+                ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+                ("latency_ms", "20"),
+            ]))
+            .unwrap();
+        assert_eq!(faults.next_fault(), Fault::Delay(Duration::from_millis(20)));
+
+        injector
+            .apply(&request(&[
+                ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+                ("latency_ms", "20"),
+                ("reorder_probability", "1"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            faults.next_fault(),
+            Fault::Reorder(Duration::from_millis(20) + REORDER_DELAY)
+        );
+
+        injector
+            .apply(&request(&[
+                ("peer", "region-a.cluster-a.10.0.0.1-dpu0"),
+                ("drop_probability", "1"),
+            ]))
+            .unwrap();
+        assert_eq!(faults.next_fault(), Fault::Drop);
+        // other peers are not affected
+        let other = injector.peer(&ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap());
+        assert_eq!(other.next_fault(), Fault::None);
+
+        injector
+            .apply(&request(&[("peer", "region-a.cluster-a.10.0.0.1-dpu0"), ("clear", "")]))
+            .unwrap();
+        assert_eq!(faults.next_fault(), Fault::None);
+
+        let reset = tokio::spawn({
+            let faults = faults.clone();
+            async move { faults.reset_requested().await }
+        });
+        tokio::task::yield_now().await;
+        injector
+            .apply(&request(&[("peer", "region-a.cluster-a.10.0.0.1-dpu0"), ("reset", "")]))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), reset)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+//! Metrics of the multiplexer and the peer connections, see [`sonic_common::metrics`].
+use sonic_common::metrics::{Counter, CounterVec, Gauge, GaugeVec};
+use std::sync::{Arc, LazyLock};
+
+pub(crate) static MESSAGES_ROUTED: LazyLock<Arc<Counter>> = LazyLock::new(|| {
+    CounterVec::register("swbus_messages_routed_total", "Messages routed by swbusd.", &[]).with_label_values(&[])
+});
+
+pub(crate) static MESSAGES_DROPPED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_messages_dropped_total",
+        "Messages swbusd could not deliver, by reason.",
+        &["reason"],
+    )
+});
+
+pub(crate) static ROUTES: LazyLock<Arc<Gauge>> = LazyLock::new(|| {
+    GaugeVec::register("swbus_routes", "Entries in the swbusd route table.", &[]).with_label_values(&[])
+});
+
+pub(crate) static RECONNECTS: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_reconnects_total",
+        "Times swbusd reconnected to a peer after losing the connection, by connection.",
+        &["peer"],
+    )
+});
+
+pub(crate) static CLIENT_AUTH_FAILURES: LazyLock<Arc<Counter>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_client_auth_failures_total",
+        "Connections refused because they failed authentication, of clients and peers alike.",
+        &[],
+    )
+    .with_label_values(&[])
+});
+
+pub(crate) fn message_dropped(reason: &str) {
+    MESSAGES_DROPPED.with_label_values(&[reason]).inc();
+}
+
+pub(crate) static COMPRESSION_BYTES: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_compression_bytes_total",
+        "Payload bytes of the data requests sent compressed, original and compressed, by connection.",
+        &["conn", "size"],
+    )
+});
+
+pub(crate) static SEND_QUEUE_DEPTH: LazyLock<Arc<GaugeVec>> = LazyLock::new(|| {
+    GaugeVec::register(
+        "swbus_send_queue_depth",
+        "Messages waiting in the send queue of a connection, by connection and priority lane.",
+        &["conn", "lane"],
+    )
+});
+
+pub(crate) static SEND_QUEUE_DROPPED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_send_queue_dropped_total",
+        "Messages refused or dropped because their lane of the send queue was full, by connection and priority lane.",
+        &["conn", "lane"],
+    )
+});
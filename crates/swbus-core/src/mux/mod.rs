@@ -1,12 +1,25 @@
+pub mod auth;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod compression;
 mod conn;
 mod conn_info;
 mod conn_proxy;
 mod conn_store;
 mod conn_worker;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
 mod message_handler;
+mod metrics;
 mod multiplexer;
 pub mod nexthop;
+mod rate_limit;
+mod route_query;
+mod route_table;
+mod send_queue;
 pub mod service;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 pub use conn::*;
 pub use conn_info::*;
@@ -15,3 +28,6 @@ pub use conn_worker::*;
 pub use message_handler::*;
 pub(crate) use multiplexer::*;
 pub(crate) use nexthop::*;
+pub(crate) use route_query::*;
+pub(crate) use route_table::*;
+pub(crate) use send_queue::{SendQueueRx, SendQueueTx};
@@ -1,40 +1,89 @@
-use super::{NextHopType, SwbusConnInfo, SwbusConnProxy, SwbusNextHop};
-use dashmap::mapref::entry::*;
+use super::metrics;
+use super::{
+    aggregate_routes, error_code_and_message, NextHopType, RouteQuery, RouteTable, Routes, SwbusConnInfo,
+    SwbusConnProxy, SwbusNextHop,
+};
+use bytes::Bytes;
 use dashmap::{DashMap, DashSet};
-use std::sync::Arc;
-use swbus_config::RouteConfig;
+use prost::Message;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use swbus_config::{EcmpHash, RouteConfig, RoutePreferenceConfig};
 use swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
+use tokio::sync::oneshot;
 use tracing::*;
 
-enum RouteStage {
-    Global,
-    Region,
-    Cluster,
-    Local,
+/// Number of undeliverable messages kept for debugging
+const DEAD_LETTER_CAPACITY: usize = 100;
+
+/// Subscribers to route changes, and the routes they know about, aggregated.
+#[derive(Default)]
+struct RouteSubscriptions {
+    subscribers: Vec<ServicePath>,
+    routes: BTreeSet<String>,
 }
-const ROUTE_STAGES: [RouteStage; 4] = [
-    RouteStage::Local,
-    RouteStage::Cluster,
-    RouteStage::Region,
-    RouteStage::Global,
-];
 
 #[derive(Default)]
 pub struct SwbusMultiplexer {
-    /// Route table. Each entry is a registered prefix to a next hop, which points to a connection.
-    routes: DashMap<String, SwbusNextHop>,
+    /// Route table. Each entry is a registered prefix to its next hops, which point to connections.
+    routes: RouteTable,
+    ecmp_hash: RwLock<EcmpHash>,
+    route_preferences: RwLock<Vec<RoutePreferenceConfig>>,
     id_generator: MessageIdGenerator,
     my_routes: DashSet<RouteConfig>,
+    /// Service paths that routed messages are copied to, until when, and the connection they requested
+    /// the capture on.
+    captures: DashMap<ServicePath, (Instant, SwbusConnProxy)>,
+    /// The last undeliverable messages, oldest first.
+    dead_letters: Mutex<VecDeque<DeadLetter>>,
+    route_subscriptions: Mutex<RouteSubscriptions>,
+    /// Routes restored by [`Self::restore_routes`] that are not learned again yet. They are announced
+    /// to the route subscribers, but have no next hop.
+    stale_routes: Mutex<BTreeSet<String>>,
+    /// Pings sent by [`Self::ping_all`] waiting for their response, by message id.
+    pending_pings: Arc<DashMap<u64, oneshot::Sender<(RequestResponse, Instant)>>>,
+    /// The connections to reconnect when the TLS certificates are rotated, set if peers are
+    /// connected over TLS.
+    #[cfg(feature = "tls")]
+    tls_conn_store: std::sync::OnceLock<std::sync::Weak<super::conn_store::SwbusConnStore>>,
+    /// Faults injected into the connections to peers, on request.
+    #[cfg(feature = "fault-injection")]
+    faults: super::fault_injection::FaultInjector,
 }
 
 impl SwbusMultiplexer {
     pub fn new() -> Self {
         SwbusMultiplexer {
-            routes: DashMap::new(),
+            routes: RouteTable::default(),
+            ecmp_hash: RwLock::new(EcmpHash::default()),
+            route_preferences: RwLock::new(Vec::new()),
             id_generator: MessageIdGenerator::new(),
             my_routes: DashSet::new(),
+            captures: DashMap::new(),
+            dead_letters: Mutex::new(VecDeque::new()),
+            route_subscriptions: Mutex::new(RouteSubscriptions::default()),
+            stale_routes: Mutex::new(BTreeSet::new()),
+            pending_pings: Arc::new(DashMap::new()),
+            #[cfg(feature = "tls")]
+            tls_conn_store: std::sync::OnceLock::new(),
+            #[cfg(feature = "fault-injection")]
+            faults: super::fault_injection::FaultInjector::default(),
+        }
+    }
+
+    /// Rotate the TLS certificates of the connections of `conn_store` on
+    /// `MANAGEMENT_REQUEST_TYPE_SWBUSD_ROTATE_TLS`, see [`Self::rotate_tls`].
+    #[cfg(feature = "tls")]
+    pub(crate) fn set_tls_conn_store(&self, conn_store: &Arc<super::conn_store::SwbusConnStore>) {
+        if self.tls_conn_store.set(Arc::downgrade(conn_store)).is_err() {
+            warn!("TLS connection store is already set");
         }
     }
 
@@ -66,32 +115,373 @@ impl SwbusMultiplexer {
             ConnectionType::Local => path.to_service_prefix(),
             ConnectionType::Client => path.to_string(),
         };
-        self.routes.remove(&route_key);
+        self.routes.remove_nexthop(&route_key, &conn_info);
+        self.routes_changed();
     }
 
     #[instrument(name = "update_route", level = "info", skip(self, nexthop), fields(nh_type=?nexthop.nh_type(), hop_count=nexthop.hop_count(), conn_info=nexthop.conn_info().as_ref().map(|x| x.id()).unwrap_or(&"None".to_string())))]
-    pub(crate) fn update_route(&self, route_key: String, nexthop: SwbusNextHop) {
-        // If route entry doesn't exist, we insert the next hop as a new one.
+    pub(crate) fn update_route(&self, route_key: String, mut nexthop: SwbusNextHop) {
+        // If route entry doesn't exist, we insert the next hop as a new one. If we already have one,
+        // then we update the entry only when we have a higher preference or a smaller hop count.
         info!("Update route entry");
-        match self.routes.entry(route_key) {
-            Entry::Occupied(mut existing) => {
-                let route_entry = existing.get();
-                if route_entry.hop_count() > nexthop.hop_count() {
-                    existing.insert(nexthop);
-                } else {
-                    info!("Route entry already exists with smaller hop count");
+        nexthop.set_preference(self.route_preference(&route_key, &nexthop));
+        if matches!(nexthop.nh_type(), NextHopType::Remote) && self.stale_routes.lock().unwrap().remove(&route_key) {
+            info!("Stale route {} is refreshed", route_key);
+        }
+        self.routes.update(route_key, nexthop);
+        self.routes_changed();
+    }
+
+    /// Save the routes learned from peers and clients, and the stale ones, to `path`, for
+    /// [`Self::restore_routes`] after swbusd restarts.
+    pub fn save_routes(&self, path: &Path) -> io::Result<()> {
+        let mut route_keys = learned_routes(&self.routes.snapshot());
+        route_keys.extend(self.stale_routes.lock().unwrap().iter().cloned());
+
+        // write a temporary file and rename it, so a crash while saving doesn't leave half a file
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&mut file, &route_keys)?;
+        file.persist(path)?;
+        info!("Saved {} routes to {}", route_keys.len(), path.display());
+        Ok(())
+    }
+
+    /// Restore the routes saved by [`Self::save_routes`] at `path` as stale routes, except the ones
+    /// learned already. Returns the number of stale routes. There are none if `path` doesn't exist.
+    pub fn restore_routes(&self, path: &Path) -> io::Result<usize> {
+        let route_keys: BTreeSet<String> = match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let learned = learned_routes(&self.routes.snapshot());
+        let count = {
+            let mut stale_routes = self.stale_routes.lock().unwrap();
+            stale_routes.extend(
+                route_keys
+                    .into_iter()
+                    .filter(|route_key| !learned.contains(route_key) && ServicePath::from_string(route_key).is_ok()),
+            );
+            stale_routes.len()
+        };
+        self.routes_changed();
+        Ok(count)
+    }
+
+    /// Withdraw the stale routes that are not learned again by now.
+    pub fn withdraw_stale_routes(&self) {
+        let stale_routes = std::mem::take(&mut *self.stale_routes.lock().unwrap());
+        if stale_routes.is_empty() {
+            return;
+        }
+        info!(
+            "Withdrawing {} stale routes: {}",
+            stale_routes.len(),
+            stale_routes.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+        self.routes_changed();
+    }
+
+    fn routes_changed(&self) {
+        // The snapshot is taken under the lock, so subscribers are told about the changes in order.
+        let mut subscriptions = self.route_subscriptions.lock().unwrap();
+        let routes = self.routes.snapshot();
+        metrics::ROUTES.set(routes.len() as i64);
+        if subscriptions.subscribers.is_empty() {
+            return;
+        }
+
+        let current = announced_routes(&routes, &self.stale_routes.lock().unwrap());
+        let removed = subscriptions
+            .routes
+            .difference(&current)
+            .map(|route_key| (RouteChangeType::Removed, route_key));
+        let added = current
+            .difference(&subscriptions.routes)
+            .map(|route_key| (RouteChangeType::Added, route_key));
+        let changes: Vec<RouteChange> = removed
+            .chain(added)
+            .map(|(change_type, route_key)| RouteChange::new(change_type, route_key_to_service_path(route_key)))
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        subscriptions.subscribers.retain(|subscriber| {
+            self.notify_route_changes(&routes, subscriber, &changes)
+                .inspect_err(|e| {
+                    info!(
+                        "Failed to notify {} of route changes, unsubscribing it: {}",
+                        subscriber.to_longest_path(),
+                        e
+                    )
+                })
+                .is_ok()
+        });
+        subscriptions.routes = current;
+    }
+
+    /// Notify `subscriber` of the route changes from now on, starting with the routes there are now.
+    pub(crate) fn subscribe_routes(&self, subscriber: ServicePath) -> Result<()> {
+        let mut subscriptions = self.route_subscriptions.lock().unwrap();
+        let routes = self.routes.snapshot();
+        if subscriptions.subscribers.is_empty() {
+            subscriptions.routes = announced_routes(&routes, &self.stale_routes.lock().unwrap());
+        }
+        let changes: Vec<RouteChange> = subscriptions
+            .routes
+            .iter()
+            .map(|route_key| RouteChange::new(RouteChangeType::Added, route_key_to_service_path(route_key)))
+            .collect();
+        self.notify_route_changes(&routes, &subscriber, &changes)?;
+        if !subscriptions.subscribers.contains(&subscriber) {
+            info!("Notifying {} of route changes", subscriber.to_longest_path());
+            subscriptions.subscribers.push(subscriber);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unsubscribe_routes(&self, subscriber: &ServicePath) {
+        info!("Stop notifying {} of route changes", subscriber.to_longest_path());
+        self.route_subscriptions
+            .lock()
+            .unwrap()
+            .subscribers
+            .retain(|s| s != subscriber);
+    }
+
+    /// Queue route changes to a subscriber, each as the payload of a data request. Route changes are sent
+    /// while the route table is being updated, so they go straight to the connection of the subscriber,
+    /// which must be a client of this swbusd.
+    fn notify_route_changes(&self, routes: &Routes, subscriber: &ServicePath, changes: &[RouteChange]) -> Result<()> {
+        let proxy = routes
+            .get(&subscriber.to_service_prefix())
+            .and_then(|nexthops| nexthops.iter().find_map(|nexthop| nexthop.conn_proxy().clone()))
+            .ok_or_else(|| {
+                SwbusError::route(
+                    SwbusErrorCode::NoRoute,
+                    format!("{} is not a client of swbusd", subscriber.to_longest_path()),
+                )
+            })?;
+        let my_sp = self.get_my_service_path();
+        for change in changes {
+            let mut header = SwbusMessageHeader::new(my_sp.clone(), subscriber.clone(), self.generate_message_id());
+            header.flag |= SWBUS_FLAG_HIGH_PRIORITY;
+            let message = SwbusMessage::new(
+                header,
+                swbus_message::Body::DataRequest(DataRequest::new(change.encode_to_vec())),
+            );
+            proxy.send_queue_tx.try_send(Ok(message))?;
+        }
+        Ok(())
+    }
+
+    /// Ping every swbusd there is a route to, in parallel, and respond to `request` with how long each of
+    /// them took to respond, or why it didn't, once all of them responded or `timeout` passed. Like route
+    /// changes, the report goes straight to the connection of the requester, which must be a client of
+    /// this swbusd.
+    pub(crate) fn ping_all(&self, request: &SwbusMessage, timeout: Duration) -> Result<()> {
+        let routes = self.routes.snapshot();
+        let (requester, proxy) = requester_proxy(&routes, request)?;
+
+        let my_sp = self.get_my_service_path();
+        let mut pings = Vec::new();
+        for (route_key, nexthops) in routes.iter() {
+            // Only the routes to a swbusd, not to its services or to a whole cluster or region.
+            let destination = route_key_to_service_path(route_key);
+            if destination.node_id.is_empty() || !destination.service_type.is_empty() {
+                continue;
+            }
+            let Some(nh_proxy) = nexthops.iter().find_map(|nexthop| nexthop.conn_proxy().clone()) else {
+                continue;
+            };
+            let id = self.generate_message_id();
+            let header = SwbusMessageHeader::new(my_sp.clone(), destination.clone(), id);
+            let ping = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+            let (response_tx, response_rx) = oneshot::channel();
+            self.pending_pings.insert(id, response_tx);
+            let sent_at = Instant::now();
+            let ping = match nh_proxy.send_queue_tx.try_send(Ok(ping)) {
+                Ok(()) => Ok((id, sent_at, response_rx)),
+                Err(e) => {
+                    self.pending_pings.remove(&id);
+                    Err(error_code_and_message(e))
                 }
+            };
+            pings.push((destination, ping));
+        }
+
+        info!("Pinging {} swbusd for {}", pings.len(), requester.to_longest_path());
+        let deadline = Instant::now() + timeout;
+        let pending_pings = self.pending_pings.clone();
+        let request = request.clone();
+        let response_id = self.generate_message_id();
+        tokio::spawn(async move {
+            let mut entries = Vec::new();
+            for (destination, ping) in pings {
+                let mut entry = ReachabilityEntry {
+                    destination: Some(destination),
+                    ..Default::default()
+                };
+                let result = match ping {
+                    Err(e) => Err(e),
+                    Ok((id, sent_at, response_rx)) => {
+                        let result = tokio::time::timeout_at(deadline.into(), response_rx).await;
+                        pending_pings.remove(&id);
+                        match result {
+                            Ok(Ok((response, received_at))) => {
+                                entry.rtt_in_us = received_at.duration_since(sent_at).as_micros() as u64;
+                                Ok(response)
+                            }
+                            _ => Err((SwbusErrorCode::Timeout, "No response".to_string())),
+                        }
+                    }
+                };
+                match result {
+                    Ok(response) => {
+                        entry.error_code = response.error_code;
+                        entry.error_message = response.error_message;
+                    }
+                    Err((error_code, error_message)) => {
+                        entry.error_code = error_code as i32;
+                        entry.error_message = error_message;
+                    }
+                }
+                entries.push(entry);
             }
-            Entry::Vacant(entry) => {
-                entry.insert(nexthop);
+
+            let response = SwbusMessage::new_response(
+                &request,
+                None,
+                SwbusErrorCode::Ok,
+                "",
+                response_id,
+                Some(request_response::ResponseBody::ReachabilityReport(ReachabilityReport {
+                    entries,
+                })),
+            );
+            if let Err(e) = proxy.send_queue_tx.try_send(Ok(response)) {
+                info!("Failed to send reachability report: {}", e);
             }
+        });
+        Ok(())
+    }
+
+    /// Reload the TLS certificates and reconnect the peers with them, one at a time and keeping their
+    /// routes, then respond to `request` with how many connections were replaced, or why the rotation
+    /// stopped. Like [`Self::ping_all`], the response goes straight to the connection of the requester.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    pub(crate) fn rotate_tls(&self, request: &SwbusMessage) -> Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(conn_store) = self.tls_conn_store.get().and_then(std::sync::Weak::upgrade) {
+            let (requester, proxy) = requester_proxy(&self.routes.snapshot(), request)?;
+            let tls = conn_store.tls().cloned().expect("TLS connection store without TLS");
+            // new server side handshakes use the new certificates right away
+            tls.reload()?;
+
+            info!("Rotating TLS certificates for {}", requester.to_longest_path());
+            let request = request.clone();
+            let response_id = self.generate_message_id();
+            tokio::spawn(async move {
+                let (error_code, error_message) = match conn_store.reconnect_peers().await {
+                    Ok(replaced) => {
+                        info!("TLS certificates rotated, {} connections replaced", replaced);
+                        (SwbusErrorCode::Ok, format!("{replaced} connections replaced"))
+                    }
+                    Err(e) => {
+                        error!("Failed to rotate TLS certificates: {}", e);
+                        error_code_and_message(e)
+                    }
+                };
+                let response =
+                    SwbusMessage::new_response(&request, None, error_code, &error_message, response_id, None);
+                if let Err(e) = proxy.send_queue_tx.try_send(Ok(response)) {
+                    info!("Failed to send TLS rotation result: {}", e);
+                }
+            });
+            return Ok(());
         }
+        Err(SwbusError::input(
+            SwbusErrorCode::InvalidArgs,
+            "Peers are not connected over TLS".to_string(),
+        ))
+    }
+
+    /// The faults injected into the messages from `peer`.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fn peer_faults(&self, peer: &ServicePath) -> Arc<super::fault_injection::PeerFaults> {
+        self.faults.peer(peer)
+    }
 
-        // // If we already have one, then we update the entry only when we have a smaller hop count.
-        // // The dashmap RefMut reference will hold a lock to the entry, which makes this function atomic.
-        // if route_entry.hop_count > nexthop.hop_count {
-        //     *route_entry.value_mut() = nexthop;
-        // }
+    /// Inject faults into the connections to a peer as `mgmt_request` asks, see `fault_injection`.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fn inject_faults(&self, mgmt_request: &ManagementRequest) -> Result<()> {
+        self.faults.apply(mgmt_request)
+    }
+
+    #[cfg(not(feature = "fault-injection"))]
+    pub(crate) fn inject_faults(&self, _mgmt_request: &ManagementRequest) -> Result<()> {
+        Err(SwbusError::input(
+            SwbusErrorCode::InvalidArgs,
+            "swbusd is built without fault injection".to_string(),
+        ))
+    }
+
+    /// Hand a response to swbusd itself to the ping it answers. Returns false if it doesn't answer a ping
+    /// of [`Self::ping_all`], or the ping already timed out.
+    pub(crate) fn complete_ping(&self, response: &RequestResponse) -> bool {
+        match self.pending_pings.remove(&response.request_id) {
+            Some((_, response_tx)) => response_tx.send((response.clone(), Instant::now())).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn set_ecmp_hash(&self, ecmp_hash: EcmpHash) {
+        *self.ecmp_hash.write().unwrap() = ecmp_hash;
+    }
+
+    /// Set the administrative preferences of the routes through peers, and apply them to the routes
+    /// there are.
+    pub fn set_route_preferences(&self, route_preferences: Vec<RoutePreferenceConfig>) {
+        *self.route_preferences.write().unwrap() = route_preferences;
+        if self
+            .routes
+            .set_preferences(|route_key, nexthop| self.route_preference(route_key, nexthop))
+        {
+            self.routes_changed();
+        }
+    }
+
+    /// Preference of the route to `route_key` through `nexthop`, 0 if none is set.
+    fn route_preference(&self, route_key: &str, nexthop: &SwbusNextHop) -> u32 {
+        let Some(conn_info) = nexthop.conn_info() else {
+            return 0;
+        };
+        let peer = conn_info.remote_service_path().to_node_prefix();
+        self.route_preferences
+            .read()
+            .unwrap()
+            .iter()
+            .find(|p| p.key.to_longest_path() == route_key && p.via.to_node_prefix() == peer)
+            .map_or(0, |p| p.preference)
+    }
+
+    /// Index of the next hop, out of `count` equal-cost ones, for a message from `source`.
+    fn ecmp_index(&self, source: Option<&ServicePath>, count: usize) -> usize {
+        let Some(source) = source.filter(|_| count > 1) else {
+            return 0;
+        };
+        let key = match *self.ecmp_hash.read().unwrap() {
+            EcmpHash::ServicePath => source.to_longest_path(),
+            EcmpHash::Service => source.to_service_prefix(),
+            EcmpHash::Node => source.to_node_prefix(),
+        };
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % count as u64) as usize
     }
 
     // Riff: The my route part is very confusing. Looks to be made for local service, but not really sure how it works.
@@ -108,6 +498,14 @@ impl SwbusMultiplexer {
         }
     }
 
+    /// Remove a route set by [`Self::set_my_routes`] along with its local service route.
+    pub fn remove_my_route(&self, route: &RouteConfig) {
+        if self.my_routes.remove(route).is_some() && route.scope == RouteScope::Cluster {
+            self.routes.remove(&route.key.to_node_prefix());
+            self.routes_changed();
+        }
+    }
+
     pub fn get_my_service_path(&self) -> ServicePath {
         self.my_routes
             .iter()
@@ -117,7 +515,7 @@ impl SwbusMultiplexer {
             .key
             .clone()
     }
-    #[instrument(name="route_message", parent=None, level="debug", skip_all, fields(message_id=?message.header.as_ref().unwrap().id))]
+    #[instrument(name="route_message", parent=None, level="debug", skip_all, fields(message_id=?message.header.as_ref().unwrap().id, trace_id=%message.header.as_ref().unwrap().trace_id))]
     pub async fn route_message(&self, message: SwbusMessage) -> Result<()> {
         debug!(
             destination = message
@@ -149,32 +547,50 @@ impl SwbusMultiplexer {
             }
         };
 
-        for stage in &ROUTE_STAGES {
-            let route_key = match stage {
-                RouteStage::Local => destination.to_service_prefix(),
-                RouteStage::Cluster => destination.to_node_prefix(),
-                RouteStage::Region => destination.to_cluster_prefix(),
-                RouteStage::Global => destination.to_regional_prefix(),
-            };
-            // If the route entry doesn't exist, we drop the message.
-            let nexthop = match self.routes.get(&route_key) {
-                Some(entry) => entry,
-                None => {
-                    continue;
-                }
-            };
+        metrics::MESSAGES_ROUTED.inc();
+        self.capture(&message, destination);
+
+        // Messages to a wildcard node id of this cluster are copied to the nodes it matches. The ones to
+        // a wildcard node id of another cluster are routed to it like the others, and copied there.
+        if destination.has_node_wildcard() {
+            let my_sp = self.get_my_service_path();
+            if destination.region_id == my_sp.region_id && destination.cluster_id == my_sp.cluster_id {
+                self.fan_out(message).await;
+                return Ok(());
+            }
+        }
 
-            // If the route entry is resolved, we forward the message to the next hop.
-            let response = nexthop.queue_message(self, message).await.unwrap();
+        // The route to the longest prefix of the destination wins. If there is none, we drop the message.
+        if let Some((_, nexthops)) = self.routes.longest_match(destination) {
+            // If the route entry is resolved, we forward the message to the next hop picked by the source.
+            // The connection of the next hop can be torn down meanwhile, in which case queueing fails and
+            // the other equal-cost next hops are tried in turn.
+            let first = self.ecmp_index(header.source.as_ref(), nexthops.len());
+            let mut candidates = nexthops[first..].iter().chain(&nexthops[..first]);
+            let mut last = candidates.next().expect("route without next hops");
+            for nexthop in candidates {
+                match last.queue_message(self, message.clone()).await {
+                    Ok(response) => {
+                        if let Some(response) = response {
+                            Box::pin(self.route_message(response)).await.unwrap();
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => debug!("Failed to queue message, trying the next equal-cost next hop: {}", e),
+                }
+                last = nexthop;
+            }
+            let response = last.queue_message(self, message).await.inspect_err(|_| {
+                metrics::message_dropped("queue_failed");
+            })?;
             if let Some(response) = response {
                 Box::pin(self.route_message(response)).await.unwrap();
-            } else {
-                // todo: try another nexthop if there is one
             }
             return Ok(());
         }
 
         info!("No route found for destination: {}", destination.to_longest_path());
+        self.drop_undeliverable(&message, "no_route");
         let response = SwbusMessage::new_response(
             &message,
             Some(&self.get_my_service_path()),
@@ -194,52 +610,262 @@ impl SwbusMultiplexer {
         Ok(())
     }
 
-    pub fn export_routes(&self, scope: Option<RouteScope>) -> RouteQueryResult {
-        let entries: Vec<RouteQueryResultEntry> = self
+    /// Copy a message to a wildcard node id of this cluster to every node it matches that has a route,
+    /// this one included, and respond to the source with a [`FanOutReport`] of the destinations of the
+    /// copies if it asks for it with [`SWBUS_FLAG_FAN_OUT_REPORT`].
+    async fn fan_out(&self, message: SwbusMessage) {
+        let header = message.header.as_ref().expect("message without header");
+        let destination = header.destination.as_ref().expect("message without destination");
+        let nodes: BTreeSet<String> = self
             .routes
+            .snapshot()
+            .keys()
+            .map(|route_key| route_key_to_service_path(route_key))
+            .filter(|sp| {
+                sp.region_id == destination.region_id
+                    && sp.cluster_id == destination.cluster_id
+                    && destination.matches_node(&sp.node_id)
+            })
+            .map(|sp| sp.node_id)
+            .collect();
+        debug!(
+            "Copying message to {} to {} nodes",
+            destination.to_longest_path(),
+            nodes.len()
+        );
+
+        let mut destinations = Vec::new();
+        for node_id in nodes {
+            let mut copy = message.clone();
+            let copy_header = copy.header.as_mut().unwrap();
+            copy_header.flag &= !SWBUS_FLAG_FAN_OUT_REPORT;
+            let copy_destination = copy_header.destination.as_mut().unwrap();
+            copy_destination.node_id = node_id;
+            let copy_destination = copy_destination.clone();
+            match Box::pin(self.route_message(copy)).await {
+                Ok(()) => destinations.push(copy_destination),
+                Err(e) => info!(
+                    "Failed to copy message to {}: {}",
+                    copy_destination.to_longest_path(),
+                    e
+                ),
+            }
+        }
+
+        if header.flag & SWBUS_FLAG_FAN_OUT_REPORT == 0
+            || matches!(message.body, Some(swbus_message::Body::Response(_)))
+        {
+            return;
+        }
+        let report = SwbusMessage::new_response(
+            &message,
+            Some(&self.get_my_service_path()),
+            SwbusErrorCode::Ok,
+            "",
+            self.generate_message_id(),
+            Some(request_response::ResponseBody::FanOutReport(FanOutReport {
+                destinations,
+            })),
+        );
+        if let Err(e) = Box::pin(self.route_message(report)).await {
+            info!("Failed to send fan-out report: {}", e);
+        }
+    }
+
+    /// Copy the messages routed from now on to the source of `request` for `duration`. Like
+    /// [`Self::ping_all`], the copies go straight to the connection of the requester, which must be a
+    /// client of this swbusd.
+    pub(crate) fn start_capture(&self, request: &SwbusMessage, duration: Duration) -> Result<()> {
+        let (subscriber, proxy) = requester_proxy(&self.routes.snapshot(), request)?;
+        info!(
+            "Capturing messages to {} for {:?}",
+            subscriber.to_longest_path(),
+            duration
+        );
+        self.captures
+            .insert(subscriber.clone(), (Instant::now() + duration, proxy));
+        Ok(())
+    }
+
+    pub(crate) fn stop_capture(&self, subscriber: &ServicePath) {
+        info!("Stop capturing messages to {}", subscriber.to_longest_path());
+        self.captures.remove(subscriber);
+    }
+
+    /// Copy a message being routed to the capture subscribers, as the payload of a data request.
+    fn capture(&self, message: &SwbusMessage, destination: &ServicePath) {
+        if self.captures.is_empty() || self.captures.contains_key(destination) {
+            return;
+        }
+
+        let now = Instant::now();
+        self.captures.retain(|_, (until, _)| *until > now);
+        let subscribers: Vec<(ServicePath, SwbusConnProxy)> = self
+            .captures
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().1.clone()))
+            .collect();
+        // the copies share the encoded message
+        let payload = Bytes::from(message.encode_to_vec());
+        let my_sp = self.get_my_service_path();
+        for (subscriber, proxy) in subscribers {
+            let header = SwbusMessageHeader::new(my_sp.clone(), subscriber.clone(), self.generate_message_id());
+            let copy = SwbusMessage::new(
+                header,
+                swbus_message::Body::DataRequest(DataRequest::new(payload.clone())),
+            );
+            if let Err(e) = proxy.send_queue_tx.try_send(Ok(copy)) {
+                info!("Failed to copy message to {}: {}", subscriber.to_longest_path(), e);
+                self.stop_capture(&subscriber);
+            }
+        }
+    }
+
+    /// Count a message that can't be delivered as dropped for `reason`, and keep it as a dead letter.
+    pub(crate) fn drop_undeliverable(&self, message: &SwbusMessage, reason: &str) {
+        metrics::message_dropped(reason);
+        let timestamp_in_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        if dead_letters.len() == DEAD_LETTER_CAPACITY {
+            dead_letters.pop_front();
+        }
+        dead_letters.push_back(DeadLetter {
+            message: message.encode_to_vec(),
+            reason: reason.to_string(),
+            timestamp_in_ms,
+        });
+    }
+
+    /// The dead letters, oldest first. The payloads of the messages are redacted unless `payloads` is
+    /// set, see [`redact_payload`].
+    pub fn export_dead_letters(&self, payloads: bool) -> DeadLetterQueryResult {
+        let dead_letters = self.dead_letters.lock().unwrap();
+        let entries = dead_letters
             .iter()
-            .filter(|entry| {
-                if !matches!(entry.value().nh_type(), NextHopType::Remote) {
-                    return false;
+            .map(|dead_letter| match payloads {
+                true => dead_letter.clone(),
+                false => DeadLetter {
+                    message: redact_payload(&dead_letter.message),
+                    ..dead_letter.clone()
+                },
+            })
+            .collect();
+        DeadLetterQueryResult { entries }
+    }
+
+    /// The page of the routes through connections that matches `query`, see [`RouteQuery`].
+    pub fn export_routes(&self, query: &RouteQuery) -> RouteQueryResult {
+        let snapshot = self.routes.snapshot();
+        let mut routes: Vec<(String, Vec<RouteQueryResultEntry>)> = snapshot
+            .iter()
+            .filter_map(|(route_key, nexthops)| {
+                let service_path = ServicePath::from_string(route_key)
+                    .expect("Not expecting service_path in route table to be invalid");
+                if !query.matches_route(route_key, service_path.route_scope()) {
+                    return None;
                 }
-                let route_scope = ServicePath::from_string(entry.key()).unwrap().route_scope();
-                match scope {
-                    Some(s) => route_scope >= s && route_scope >= RouteScope::Cluster,
-                    None => true,
+                let mut entries: Vec<RouteQueryResultEntry> = nexthops
+                    .iter()
+                    .filter(|nexthop| matches!(nexthop.nh_type(), NextHopType::Remote))
+                    .filter_map(|nexthop| {
+                        let conn_info = nexthop.conn_info().as_ref().unwrap();
+                        let nh_service_path = conn_info.remote_service_path();
+                        if !query.matches_connection(conn_info.id(), &nh_service_path.to_longest_path()) {
+                            return None;
+                        }
+                        Some(RouteQueryResultEntry {
+                            service_path: Some(service_path.clone()),
+                            hop_count: nexthop.hop_count(),
+                            preference: nexthop.preference(),
+                            nh_id: conn_info.id().to_string(),
+                            nh_service_path: Some(nh_service_path.clone()),
+                            nh_scope: conn_info.connection_type() as i32,
+                        })
+                    })
+                    .collect();
+                if entries.is_empty() {
+                    return None;
                 }
-            })
-            .map(|entry| RouteQueryResultEntry {
-                service_path: Some(
-                    ServicePath::from_string(entry.key())
-                        .expect("Not expecting service_path in route table to be invalid"),
-                ),
-                hop_count: entry.value().hop_count(),
-                nh_id: entry.value().conn_info().as_ref().unwrap().id().to_string(),
-                nh_service_path: Some(
-                    entry
-                        .value()
-                        .conn_info()
-                        .as_ref()
-                        .unwrap()
-                        .remote_service_path()
-                        .clone(),
-                ),
-                nh_scope: entry.value().conn_info().as_ref().unwrap().connection_type() as i32,
+                entries.sort_by(|a, b| a.nh_id.cmp(&b.nh_id));
+                Some((route_key.clone(), entries))
             })
             .collect();
+        routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        query.page(routes)
+    }
+}
+
+/// The source of `request` and the connection to it, which must be a client of this swbusd.
+fn requester_proxy<'a>(routes: &Routes, request: &'a SwbusMessage) -> Result<(&'a ServicePath, SwbusConnProxy)> {
+    let requester = request
+        .header
+        .as_ref()
+        .and_then(|h| h.source.as_ref())
+        .ok_or_else(|| SwbusError::input(SwbusErrorCode::InvalidSource, "missing source".to_string()))?;
+    let proxy = routes
+        .get(&requester.to_service_prefix())
+        .and_then(|nexthops| nexthops.iter().find_map(|nexthop| nexthop.conn_proxy().clone()))
+        .ok_or_else(|| {
+            SwbusError::route(
+                SwbusErrorCode::NoRoute,
+                format!("{} is not a client of swbusd", requester.to_longest_path()),
+            )
+        })?;
+    Ok((requester, proxy))
+}
 
-        RouteQueryResult { entries }
+/// Drop the application data from an encoded message: the payload of a data request and the body of a
+/// response. The header and the other bodies, which swbus makes up itself, are kept.
+fn redact_payload(encoded: &[u8]) -> Vec<u8> {
+    let Ok(mut message) = SwbusMessage::decode(encoded) else {
+        return Vec::new();
+    };
+    match message.body.as_mut() {
+        Some(swbus_message::Body::DataRequest(data)) => data.payload = Bytes::new(),
+        Some(swbus_message::Body::Response(response)) => response.response_body = None,
+        _ => {}
     }
+    message.encode_to_vec()
+}
+
+/// The routes to other swbusd and clients, which are the ones reachable through a connection.
+fn learned_routes(routes: &Routes) -> BTreeSet<String> {
+    routes
+        .iter()
+        .filter(|(_, nexthops)| {
+            nexthops
+                .iter()
+                .any(|nexthop| matches!(nexthop.nh_type(), NextHopType::Remote))
+        })
+        .map(|(route_key, _)| route_key.clone())
+        .collect()
+}
+
+/// Routes announced to the subscribers: the learned routes and the stale ones, without the ones
+/// covered by a shorter prefix among them.
+fn announced_routes(routes: &Routes, stale_routes: &BTreeSet<String>) -> BTreeSet<String> {
+    let mut reachable = learned_routes(routes);
+    reachable.extend(stale_routes.iter().cloned());
+    aggregate_routes(&reachable)
+}
+
+fn route_key_to_service_path(route_key: &str) -> ServicePath {
+    ServicePath::from_string(route_key).expect("Not expecting service_path in route table to be invalid")
 }
 
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
-    use tokio::{sync::mpsc, time};
-    use tonic::Status;
+    use tokio::time;
 
     use super::*;
-    use crate::mux::SwbusConn;
+    use crate::mux::{route_prefixes, send_queue, SendQueueRx, SwbusConn};
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+    use swbus_config::SendQueueConfig;
+    use swbus_proto::arbitrary;
     use tokio::time::Duration;
 
     #[test]
@@ -252,24 +878,37 @@ mod tests {
         mux.set_my_routes(vec![route_config.clone()]);
         assert!(mux.my_routes.contains(&route_config));
 
-        let nh = mux.routes.get(&route_config.key.to_node_prefix()).unwrap();
+        let nh = &mux.routes.get(&route_config.key.to_node_prefix()).unwrap()[0];
         assert_eq!(nh.nh_type(), NextHopType::Local);
     }
 
+    #[test]
+    fn test_remove_my_route() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        let route_config = RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        };
+        mux.set_my_routes(vec![route_config.clone()]);
+        mux.remove_my_route(&route_config);
+        assert!(!mux.my_routes.contains(&route_config));
+        assert!(mux.routes.get(&route_config.key.to_node_prefix()).is_none());
+    }
+
     fn add_route(
         mux: &SwbusMultiplexer,
         route_key: &str,
         hop_count: u32,
         nh_sp: &str,
         nh_conn_type: ConnectionType,
-    ) -> mpsc::Receiver<Result<SwbusMessage, Status>> {
+    ) -> SendQueueRx {
         let conn_info = Arc::new(SwbusConnInfo::new_client(
             nh_conn_type,
             "127.0.0.1:8080".parse().unwrap(),
             ServicePath::from_string(nh_sp).unwrap(),
             ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
         ));
-        let (send_queue_tx, send_queue_rx) = mpsc::channel(16);
+        let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
         let conn = SwbusConn::new(&conn_info, send_queue_tx);
 
         let nexthop_nh1 = SwbusNextHop::new_remote(conn_info.clone(), conn.new_proxy(), hop_count);
@@ -279,7 +918,7 @@ mod tests {
 
     async fn route_message_and_compare(
         mux: &SwbusMultiplexer,
-        send_queue_rx: &mut mpsc::Receiver<Result<SwbusMessage, Status>>,
+        send_queue_rx: &mut SendQueueRx,
         request: &str,
         expected: &str,
     ) {
@@ -359,6 +998,453 @@ mod tests {
         route_message_and_compare(&mux, &mut send_queue_rx3, request, expected).await;
     }
 
+    #[tokio::test]
+    async fn test_capture() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut capture_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0",
+            ConnectionType::Local,
+        );
+        let mut send_queue_rx3 = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        );
+        // only clients of this swbusd can capture
+        let capture_request = |source: &str| {
+            SwbusMessage::new(
+                SwbusMessageHeader::new(
+                    ServicePath::from_string(source).unwrap(),
+                    ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                    1,
+                ),
+                swbus_message::Body::ManagementRequest(ManagementRequest::new(ManagementRequestType::SwbusdCapture)),
+            )
+        };
+        assert!(mux
+            .start_capture(
+                &capture_request("region-a.cluster-a.10.0.0.1-dpu0/swbus-cli/0/capture/0"),
+                Duration::from_secs(60)
+            )
+            .is_err());
+        let subscriber = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0/capture/0").unwrap();
+        mux.start_capture(&capture_request(&subscriber.to_longest_path()), Duration::from_secs(60))
+            .unwrap();
+
+        let request = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.3-dpu0/testsvc/0/ping/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.3-dpu0/local-mgmt/0").unwrap(),
+                1,
+            ),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        );
+        mux.route_message(request.clone()).await.unwrap();
+        assert!(send_queue_rx3.recv().await.unwrap().is_ok());
+
+        let copy = capture_rx.recv().await.unwrap().unwrap();
+        assert_eq!(copy.header.as_ref().unwrap().destination, Some(subscriber.clone()));
+        let Some(swbus_message::Body::DataRequest(data)) = copy.body else {
+            panic!("expected a data request, got {:?}", copy.body);
+        };
+        assert_eq!(SwbusMessage::decode(&data.payload[..]).unwrap(), request);
+
+        mux.stop_capture(&subscriber);
+        mux.route_message(request).await.unwrap();
+        assert!(send_queue_rx3.recv().await.unwrap().is_ok());
+        assert!(capture_rx.try_recv().is_err());
+    }
+
+    fn recv_route_change(send_queue_rx: &mut SendQueueRx) -> (RouteChangeType, String) {
+        let message = send_queue_rx.try_recv().unwrap().unwrap();
+        assert_ne!(message.header.as_ref().unwrap().flag & SWBUS_FLAG_HIGH_PRIORITY, 0);
+        let Some(swbus_message::Body::DataRequest(data)) = message.body else {
+            panic!("expected a data request, got {:?}", message.body);
+        };
+        let change = RouteChange::decode(&data.payload[..]).unwrap();
+        (change.change_type(), change.service_path.unwrap().to_longest_path())
+    }
+
+    #[test]
+    fn test_subscribe_routes() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut client_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+
+        // Only clients of swbusd can subscribe
+        let stranger = ServicePath::from_string("region-a.cluster-a.10.0.0.3-dpu0/hamgrd/0/route-changes/0").unwrap();
+        assert!(mux.subscribe_routes(stranger).is_err());
+
+        // A new subscriber learns the routes there are now
+        let subscriber = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/route-changes/0").unwrap();
+        mux.subscribe_routes(subscriber.clone()).unwrap();
+        assert_eq!(
+            recv_route_change(&mut client_rx),
+            (
+                RouteChangeType::Added,
+                "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0".to_string()
+            )
+        );
+        assert!(client_rx.try_recv().is_err());
+
+        let _peer_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        assert_eq!(
+            recv_route_change(&mut client_rx),
+            (RouteChangeType::Added, "region-a.cluster-a.10.0.0.1-dpu0".to_string())
+        );
+
+        mux.routes.remove("region-a.cluster-a.10.0.0.1-dpu0");
+        mux.routes_changed();
+        assert_eq!(
+            recv_route_change(&mut client_rx),
+            (RouteChangeType::Removed, "region-a.cluster-a.10.0.0.1-dpu0".to_string())
+        );
+
+        mux.unsubscribe_routes(&subscriber);
+        let _peer_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        assert!(client_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_graceful_restart_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("routes.json");
+        let my_route = RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        };
+
+        // Before the restart
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![my_route.clone()]);
+        let _peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        let _peer3_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        );
+        mux.save_routes(&state_file).unwrap();
+
+        // After the restart, the saved routes are announced as stale to a subscriber
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![my_route]);
+        assert_eq!(mux.restore_routes(&dir.path().join("missing.json")).unwrap(), 0);
+        let mut client_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+        assert_eq!(mux.restore_routes(&state_file).unwrap(), 2);
+        let subscriber = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/route-changes/0").unwrap();
+        mux.subscribe_routes(subscriber).unwrap();
+        let mut announced: Vec<_> = (0..3).map(|_| recv_route_change(&mut client_rx).1).collect();
+        announced.sort();
+        assert_eq!(
+            announced,
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0",
+                "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+                "region-a.cluster-a.10.0.0.3-dpu0"
+            ]
+        );
+
+        // A stale route learned again is not announced again, and is not withdrawn with the others
+        let _peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        assert!(client_rx.try_recv().is_err());
+        mux.withdraw_stale_routes();
+        assert_eq!(
+            recv_route_change(&mut client_rx),
+            (RouteChangeType::Removed, "region-a.cluster-a.10.0.0.3-dpu0".to_string())
+        );
+        assert!(client_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_subscribe_routes_aggregated() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut client_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+        let _node_rx = add_route(
+            &mux,
+            "region-a.cluster-b.10.0.1.1-dpu0",
+            1,
+            "region-a.cluster-b.10.0.1.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        let _cluster_rx = add_route(
+            &mux,
+            "region-a.cluster-b",
+            1,
+            "region-a.cluster-b.10.0.1.2-dpu0",
+            ConnectionType::Region,
+        );
+
+        // The route to the node in cluster-b is covered by the route to the cluster
+        let subscriber = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/route-changes/0").unwrap();
+        mux.subscribe_routes(subscriber).unwrap();
+        let mut added = vec![recv_route_change(&mut client_rx), recv_route_change(&mut client_rx)];
+        added.sort();
+        assert_eq!(
+            added,
+            vec![
+                (
+                    RouteChangeType::Added,
+                    "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0".to_string()
+                ),
+                (RouteChangeType::Added, "region-a.cluster-b".to_string()),
+            ]
+        );
+        assert!(client_rx.try_recv().is_err());
+
+        // Without the cluster route, the node route is announced on its own
+        mux.routes.remove("region-a.cluster-b");
+        mux.routes_changed();
+        let mut changes = vec![recv_route_change(&mut client_rx), recv_route_change(&mut client_rx)];
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (RouteChangeType::Added, "region-a.cluster-b.10.0.1.1-dpu0".to_string()),
+                (RouteChangeType::Removed, "region-a.cluster-b".to_string()),
+            ]
+        );
+        assert!(client_rx.try_recv().is_err());
+    }
+
+    fn recv_response(send_queue_rx: &mut SendQueueRx) -> (String, RequestResponse) {
+        let message = send_queue_rx.try_recv().unwrap().unwrap();
+        let source = message.header.unwrap().source.unwrap().to_longest_path();
+        let Some(swbus_message::Body::Response(response)) = message.body else {
+            panic!("expected a response, got {:?}", message.body);
+        };
+        (source, response)
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut client_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+        let mut peer_rxs = ["10.0.0.1-dpu0", "10.0.0.3-dpu0"].map(|node| {
+            let route_key = format!("region-a.cluster-a.{node}");
+            add_route(&mux, &route_key, 1, &route_key, ConnectionType::Cluster)
+        });
+        let mut cluster_b_rx = add_route(
+            &mux,
+            "region-a.cluster-b",
+            1,
+            "region-a.cluster-b.10.0.1.1-dpu0",
+            ConnectionType::Region,
+        );
+
+        let mut header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/ha-set/0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.*/hamgrd/0/ha-set/0").unwrap(),
+            1,
+        );
+        header.flag |= SWBUS_FLAG_FAN_OUT_REPORT;
+        let request = SwbusMessage::new(header, swbus_message::Body::DataRequest(DataRequest::new(vec![1])));
+        mux.route_message(request.clone()).await.unwrap();
+
+        // Each peer gets a copy to its own node id
+        for (peer_rx, node) in peer_rxs.iter_mut().zip(["10.0.0.1-dpu0", "10.0.0.3-dpu0"]) {
+            let copy = peer_rx.try_recv().unwrap().unwrap();
+            let header = copy.header.as_ref().unwrap();
+            assert_eq!(
+                header.destination.as_ref().unwrap().to_longest_path(),
+                format!("region-a.cluster-a.{node}/hamgrd/0/ha-set/0")
+            );
+            assert_eq!(header.id, 1);
+            assert_eq!(header.flag & SWBUS_FLAG_FAN_OUT_REPORT, 0);
+            assert_eq!(copy.body, request.body);
+        }
+
+        // This node has no such service, so the copy to it is answered with no route, then the report
+        // of all the destinations follows
+        let (source, response) = recv_response(&mut client_rx);
+        assert_eq!(source, "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/ha-set/0");
+        assert_eq!(response.error_code(), SwbusErrorCode::NoRoute);
+        let (source, response) = recv_response(&mut client_rx);
+        assert_eq!(source, "region-a.cluster-a.10.0.0.2-dpu0");
+        assert_eq!(response.request_id, 1);
+        let Some(request_response::ResponseBody::FanOutReport(report)) = response.response_body else {
+            panic!("expected a fan-out report, got {:?}", response.response_body);
+        };
+        let destinations: Vec<String> = report.destinations.iter().map(|sp| sp.to_longest_path()).collect();
+        assert_eq!(
+            destinations,
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/ha-set/0",
+                "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/ha-set/0",
+                "region-a.cluster-a.10.0.0.3-dpu0/hamgrd/0/ha-set/0",
+            ]
+        );
+        assert!(client_rx.try_recv().is_err());
+
+        // A wildcard of another cluster is routed to it as is
+        let destination = ServicePath::from_string("region-a.cluster-b.*/hamgrd/0").unwrap();
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap(),
+            destination.clone(),
+            2,
+        );
+        let request = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+        mux.route_message(request).await.unwrap();
+        let forwarded = cluster_b_rx.try_recv().unwrap().unwrap();
+        assert_eq!(forwarded.header.unwrap().destination, Some(destination));
+        assert!(peer_rxs.iter_mut().all(|peer_rx| peer_rx.try_recv().is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_ping_all() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut cli_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0",
+            ConnectionType::Local,
+        );
+        let mut peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        let mut peer3_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        );
+
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdPingAll);
+        mgmt_request.arguments.push(ManagementRequestArg {
+            name: "timeout_ms".to_string(),
+            value: "200".to_string(),
+        });
+        let request = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                1,
+            ),
+            swbus_message::Body::ManagementRequest(mgmt_request),
+        );
+        mux.route_message(request).await.unwrap();
+
+        // 10.0.0.1 responds, 10.0.0.3 doesn't
+        let ping = peer1_rx.recv().await.unwrap().unwrap();
+        assert!(matches!(ping.body, Some(swbus_message::Body::PingRequest(_))));
+        let pong = SwbusMessage::new_response(&ping, None, SwbusErrorCode::Ok, "", 2, None);
+        mux.route_message(pong).await.unwrap();
+        let ping = peer3_rx.recv().await.unwrap().unwrap();
+        assert!(matches!(ping.body, Some(swbus_message::Body::PingRequest(_))));
+
+        let report = time::timeout(Duration::from_secs(1), cli_rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Some(swbus_message::Body::Response(response)) = report.body else {
+            panic!("expected a response, got {:?}", report.body);
+        };
+        let Some(request_response::ResponseBody::ReachabilityReport(mut report)) = response.response_body else {
+            panic!("expected a reachability report, got {:?}", response.response_body);
+        };
+        report
+            .entries
+            .sort_by_key(|entry| entry.destination.as_ref().unwrap().to_longest_path());
+        let results: Vec<(String, SwbusErrorCode)> = report
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.destination.as_ref().unwrap().to_longest_path(),
+                    entry.error_code(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("region-a.cluster-a.10.0.0.1-dpu0".to_string(), SwbusErrorCode::Ok),
+                ("region-a.cluster-a.10.0.0.3-dpu0".to_string(), SwbusErrorCode::Timeout),
+            ]
+        );
+        assert!(mux.pending_pings.is_empty());
+    }
+
     #[tokio::test]
     async fn test_route_message_unreachable() {
         let mux = Arc::new(SwbusMultiplexer::new());
@@ -421,6 +1507,10 @@ mod tests {
             }
             "#;
         route_message_and_compare(&mux, &mut send_queue_rx1, request, expected).await;
+
+        let dead_letters = mux.export_dead_letters(false).entries;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].reason, "ttl_expired");
     }
 
     #[tokio::test]
@@ -478,6 +1568,63 @@ mod tests {
             }
             "#;
         route_message_and_compare(&mux, &mut send_queue_rx1, request, expected).await;
+
+        let dead_letters = mux.export_dead_letters(true).entries;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].reason, "no_route");
+        let dead_letter = SwbusMessage::decode(dead_letters[0].message.as_slice()).unwrap();
+        let request_msg: SwbusMessage = serde_json::from_str(request).unwrap();
+        assert_eq!(normalize_msg(&dead_letter), request_msg);
+    }
+
+    #[test]
+    fn test_dead_letters_keep_the_last_messages() {
+        let mux = SwbusMultiplexer::new();
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap();
+        for id in 0..DEAD_LETTER_CAPACITY as u64 + 2 {
+            let message = SwbusMessage::new(
+                SwbusMessageHeader::new(sp.clone(), sp.clone(), id),
+                swbus_message::Body::PingRequest(PingRequest::new()),
+            );
+            mux.drop_undeliverable(&message, "no_route");
+        }
+
+        let ids: Vec<u64> = mux
+            .export_dead_letters(false)
+            .entries
+            .iter()
+            .map(|entry| {
+                SwbusMessage::decode(entry.message.as_slice())
+                    .unwrap()
+                    .header
+                    .unwrap()
+                    .id
+            })
+            .collect();
+        assert_eq!(ids, (2..DEAD_LETTER_CAPACITY as u64 + 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_dead_letters_redact_payloads() {
+        let mux = SwbusMultiplexer::new();
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap();
+        let message = SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp.clone(), 1),
+            swbus_message::Body::DataRequest(DataRequest::new(b"secret".to_vec())),
+        );
+        mux.drop_undeliverable(&message, "no_route");
+
+        let payload = |payloads| {
+            let entries = mux.export_dead_letters(payloads).entries;
+            let dead_letter = SwbusMessage::decode(entries[0].message.as_slice()).unwrap();
+            assert_eq!(dead_letter.header, message.header);
+            let Some(swbus_message::Body::DataRequest(data)) = dead_letter.body else {
+                panic!("expected a data request, got {:?}", dead_letter.body);
+            };
+            data.payload
+        };
+        assert!(payload(false).is_empty());
+        assert_eq!(&payload(true)[..], b"secret");
     }
 
     #[tokio::test]
@@ -540,7 +1687,11 @@ mod tests {
             ConnectionType::Region,
         );
 
-        let routes = mux.export_routes(Some(RouteScope::Cluster));
+        let by_scope = |scope| RouteQuery {
+            scope: Some(scope),
+            ..Default::default()
+        };
+        let routes = mux.export_routes(&by_scope(RouteScope::Cluster));
         let json_string = serde_json::to_string(&routes).unwrap();
         let normalized_routes: RouteQueryResult = serde_json::from_str(&json_string).unwrap();
 
@@ -550,6 +1701,7 @@ mod tests {
             nh_id: "".to_string(),
             nh_service_path: Some(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap()),
             nh_scope: RouteScope::Cluster as i32,
+            preference: 0,
         };
         let entry2 = RouteQueryResultEntry {
             service_path: Some(ServicePath::from_string("region-a.cluster-b").unwrap()),
@@ -557,17 +1709,308 @@ mod tests {
             nh_id: "".to_string(),
             nh_service_path: Some(ServicePath::from_string("region-a.cluster-b.10.0.0.1-dpu0").unwrap()),
             nh_scope: RouteScope::Region as i32,
+            preference: 0,
         };
 
         let expected = RouteQueryResult {
             entries: vec![entry1.clone(), entry2.clone()],
+            next_cursor: String::new(),
+            total_entries: 2,
         };
         assert_eq!(normalized_routes, expected);
 
-        let routes = mux.export_routes(Some(RouteScope::Region));
+        let routes = mux.export_routes(&by_scope(RouteScope::Region));
         let json_string = serde_json::to_string(&routes).unwrap();
         let normalized_routes: RouteQueryResult = serde_json::from_str(&json_string).unwrap();
-        let expected = RouteQueryResult { entries: vec![entry2] };
+        let expected = RouteQueryResult {
+            entries: vec![entry2.clone()],
+            next_cursor: String::new(),
+            total_entries: 1,
+        };
         assert_eq!(normalized_routes, expected);
+
+        // by prefix of the route and by the peer of the connection
+        let routes = mux.export_routes(&RouteQuery {
+            prefix: Some("region-a.cluster-a".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(routes.total_entries, 1);
+        assert_eq!(routes.entries[0].service_path, entry1.service_path);
+        let routes = mux.export_routes(&RouteQuery {
+            connection: Some("region-a.cluster-b.10.0.0.1-dpu0".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(routes.total_entries, 1);
+        assert_eq!(routes.entries[0].service_path, entry2.service_path);
+
+        // a page at a time, in the order of the routes
+        let mut query = RouteQuery {
+            limit: 1,
+            ..Default::default()
+        };
+        let routes = mux.export_routes(&query);
+        assert_eq!(routes.entries[0].service_path, entry1.service_path);
+        assert_eq!(routes.next_cursor, "region-a.cluster-a.10.0.0.1-dpu0");
+        assert_eq!(routes.total_entries, 2);
+        query.cursor = Some(routes.next_cursor);
+        let routes = mux.export_routes(&query);
+        assert_eq!(routes.entries[0].service_path, entry2.service_path);
+        assert_eq!(routes.next_cursor, "");
+    }
+
+    #[tokio::test]
+    async fn test_route_message_ecmp() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        mux.set_ecmp_hash(EcmpHash::Node);
+
+        // a client and a server connection to the same peer
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap();
+        let mut queues: Vec<_> = [
+            SwbusConnInfo::new_client(
+                ConnectionType::Cluster,
+                "127.0.0.1:8080".parse().unwrap(),
+                peer.clone(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            ),
+            SwbusConnInfo::new_server(ConnectionType::Cluster, "127.0.0.1:50000".parse().unwrap(), peer),
+        ]
+        .into_iter()
+        .map(|conn_info| {
+            let conn_info = Arc::new(conn_info);
+            let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+            mux.register(&conn_info, SwbusConnProxy::new(send_queue_tx));
+            (conn_info, send_queue_rx)
+        })
+        .collect();
+        assert_eq!(mux.export_routes(&RouteQuery::default()).entries.len(), 2);
+
+        let ping = |source: &str| {
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string(source).unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap(),
+                mux.generate_message_id(),
+            );
+            SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()))
+        };
+
+        // messages from one node take the same connection
+        for service_id in 0..4 {
+            mux.route_message(ping(&format!("region-a.cluster-a.10.0.0.2-dpu0/testsvc/{service_id}")))
+                .await
+                .unwrap();
+        }
+        let counts: Vec<usize> = queues
+            .iter_mut()
+            .map(|(_, rx)| std::iter::from_fn(|| rx.try_recv().ok()).count())
+            .collect();
+        assert!(counts == vec![4, 0] || counts == vec![0, 4], "{counts:?}");
+
+        // the other connection takes over when the one in use is gone, before and after it is unregistered
+        let used = counts.iter().position(|count| *count == 4).unwrap();
+        let (conn_info, mut rx) = queues.remove(used);
+        rx.close();
+        mux.route_message(ping("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0"))
+            .await
+            .unwrap();
+        mux.unregister(conn_info);
+        mux.route_message(ping("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0"))
+            .await
+            .unwrap();
+        let (_, other_rx) = &mut queues[0];
+        assert!(other_rx.try_recv().is_ok());
+        assert!(other_rx.try_recv().is_ok());
+        assert_eq!(mux.export_routes(&RouteQuery::default()).entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_route_preference() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        mux.set_route_preferences(vec![RoutePreferenceConfig {
+            key: ServicePath::from_string("region-b").unwrap(),
+            via: ServicePath::from_string("region-b.cluster-a.10.1.0.2-dpu0").unwrap(),
+            preference: 100,
+        }]);
+
+        // two connections to region-b, through different peers
+        let mut queues: Vec<_> = [("10.1.0.1", 8081), ("10.1.0.2", 8082)]
+            .into_iter()
+            .map(|(peer, port)| {
+                let conn_info = Arc::new(SwbusConnInfo::new_client(
+                    ConnectionType::Global,
+                    format!("127.0.0.1:{port}").parse().unwrap(),
+                    ServicePath::from_string(&format!("region-b.cluster-a.{peer}-dpu0")).unwrap(),
+                    ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                ));
+                let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+                mux.register(&conn_info, SwbusConnProxy::new(send_queue_tx));
+                (conn_info, send_queue_rx)
+            })
+            .collect();
+        let routes = mux.export_routes(&RouteQuery::default());
+        assert_eq!(routes.entries.len(), 1);
+        assert_eq!(routes.entries[0].preference, 100);
+
+        let ping = || {
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0").unwrap(),
+                ServicePath::from_string("region-b.cluster-b.10.2.0.1-dpu0/testsvc/0").unwrap(),
+                mux.generate_message_id(),
+            );
+            SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()))
+        };
+        let received = |queues: &mut Vec<(Arc<SwbusConnInfo>, SendQueueRx)>| -> Vec<usize> {
+            queues
+                .iter_mut()
+                .map(|(_, rx)| std::iter::from_fn(|| rx.try_recv().ok()).count())
+                .collect()
+        };
+
+        // traffic is forced through the preferred peer
+        mux.route_message(ping()).await.unwrap();
+        assert_eq!(received(&mut queues), vec![0, 1]);
+
+        // and fails over to the other one when the preferred connection is down
+        mux.unregister(queues[1].0.clone());
+        mux.route_message(ping()).await.unwrap();
+        assert_eq!(received(&mut queues), vec![1, 0]);
+
+        // until the preferred connection is back
+        let conn_info = queues[1].0.clone();
+        let (send_queue_tx, send_queue_rx) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+        mux.register(&conn_info, SwbusConnProxy::new(send_queue_tx));
+        queues[1].1 = send_queue_rx;
+        mux.route_message(ping()).await.unwrap();
+        assert_eq!(received(&mut queues), vec![0, 1]);
+
+        // without preferences, the equal-cost peers share the traffic again
+        mux.set_route_preferences(Vec::new());
+        assert_eq!(mux.export_routes(&RouteQuery::default()).entries.len(), 2);
+    }
+
+    proptest! {
+        #[test]
+        fn test_route_message_longest_match(destination in arbitrary::full_service_path(), registered in 1u8..16) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let mux = SwbusMultiplexer::new();
+            let prefixes = route_prefixes(&destination);
+            // bit i of `registered` adds a route to prefixes[i]
+            let mut queues: Vec<_> = (0..prefixes.len())
+                .filter(|i| registered & (1 << i) != 0)
+                .map(|i| {
+                    let rx = add_route(&mux, &prefixes[i], 1, "region-a.cluster-a.10.0.0.1-dpu0", ConnectionType::Cluster);
+                    (i, rx)
+                })
+                .collect();
+            // a route to another node in the same cluster must not match
+            let mut sibling = destination.clone();
+            sibling.node_id.push_str("-peer");
+            let mut sibling_rx = add_route(&mux, &sibling.to_node_prefix(), 1, "region-a.cluster-a.10.0.0.1-dpu0", ConnectionType::Cluster);
+
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap(),
+                destination.clone(),
+                mux.generate_message_id(),
+            );
+            let message = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+            runtime.block_on(mux.route_message(message)).unwrap();
+
+            let longest = registered.trailing_zeros() as usize;
+            for (i, rx) in &mut queues {
+                prop_assert_eq!(rx.try_recv().is_ok(), *i == longest, "route {}", prefixes[*i]);
+            }
+            prop_assert!(sibling_rx.try_recv().is_err());
+        }
+
+        #[test]
+        fn test_export_routes_by_scope(
+            routes in prop::collection::vec((arbitrary::full_service_path(), 0..5usize), 1..8),
+            scope in prop::option::of(arbitrary::route_scope()),
+        ) {
+            let mux = SwbusMultiplexer::new();
+            let mut keys = HashSet::new();
+            for (sp, len) in &routes {
+                // the client route of a path is the full path, the others are its prefixes
+                let key = match len {
+                    4 => sp.to_string(),
+                    len => route_prefixes(sp)[*len].clone(),
+                };
+                add_route(&mux, &key, 1, "region-a.cluster-a.10.0.0.1-dpu0", ConnectionType::Cluster);
+                keys.insert(key);
+            }
+
+            let query = RouteQuery {
+                scope,
+                ..Default::default()
+            };
+            let exported: HashSet<String> = mux
+                .export_routes(&query)
+                .entries
+                .into_iter()
+                .map(|entry| entry.service_path.unwrap().to_string())
+                .collect();
+            let expected: HashSet<String> = keys
+                .into_iter()
+                .filter(|key| {
+                    let route_scope = ServicePath::from_string(key).unwrap().route_scope();
+                    scope.is_none_or(|s| route_scope >= s && route_scope >= RouteScope::Cluster)
+                })
+                .map(|key| ServicePath::from_string(&key).unwrap().to_string())
+                .collect();
+            prop_assert_eq!(exported, expected);
+        }
+    }
+
+    // Run with: RUSTFLAGS="--cfg loom" cargo test --release -p swbus-core --lib loom
+    #[cfg(loom)]
+    #[test]
+    fn loom_route_message_vs_connection_teardown() {
+        loom::model(|| {
+            let mux = Arc::new(SwbusMultiplexer::new());
+            mux.set_my_routes(vec![RouteConfig {
+                key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                scope: RouteScope::Cluster,
+            }]);
+            let conn_info = Arc::new(SwbusConnInfo::new_client(
+                ConnectionType::Cluster,
+                "127.0.0.1:8080".parse().unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            ));
+            let (send_queue_tx, mut send_queue_rx) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+            mux.register(&conn_info, SwbusConnProxy::new(send_queue_tx));
+
+            let header = SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/local-mgmt/0").unwrap(),
+                mux.generate_message_id(),
+            );
+            let message = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+
+            // the connection worker goes away: it unregisters from the mux and closes its queue
+            let teardown = {
+                let mux = mux.clone();
+                loom::thread::spawn(move || {
+                    mux.unregister(conn_info);
+                    send_queue_rx.close();
+                    send_queue_rx
+                })
+            };
+            let result = loom::future::block_on(mux.route_message(message));
+            let mut send_queue_rx = teardown.join().unwrap();
+
+            // routing must not panic. The message is queued before the connection is closed, or not
+            // queued at all, and only the latter can fail.
+            let delivered = send_queue_rx.try_recv().is_ok();
+            assert!(result.is_ok() || !delivered);
+            assert!(mux.routes.get("region-a.cluster-a.10.0.0.1-dpu0").is_none());
+        });
     }
 }
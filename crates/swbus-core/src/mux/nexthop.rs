@@ -1,21 +1,31 @@
+use super::metrics;
+use super::RouteQuery;
 use super::SwbusConnInfo;
 use super::SwbusConnProxy;
 use super::SwbusMultiplexer;
 use getset::CopyGetters;
 use getset::Getters;
+use getset::Setters;
 use std::sync::Arc;
+use std::time::Duration;
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use swbus_proto::swbus::{swbus_message, ManagementRequestType, SwbusMessage};
 use tracing::*;
 
+/// How long a capture lasts if the request doesn't say
+const DEFAULT_CAPTURE_SECS: u64 = 60;
+
+/// How long to wait for the responses to a ping_all request if it doesn't say
+const DEFAULT_PING_ALL_TIMEOUT_MS: u64 = 1000;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(crate) enum NextHopType {
     Local,
     Remote,
 }
 
-#[derive(Clone, Getters, CopyGetters)]
+#[derive(Clone, Getters, CopyGetters, Setters)]
 pub(crate) struct SwbusNextHop {
     #[getset(get_copy = "pub")]
     nh_type: NextHopType,
@@ -28,6 +38,10 @@ pub(crate) struct SwbusNextHop {
 
     #[getset(get_copy = "pub")]
     hop_count: u32,
+
+    /// Administrative preference of the route through this next hop, see [`swbus_config::RoutePreferenceConfig`]
+    #[getset(get_copy = "pub", set = "pub")]
+    preference: u32,
 }
 
 impl SwbusNextHop {
@@ -37,6 +51,7 @@ impl SwbusNextHop {
             conn_info: Some(conn_info),
             conn_proxy: Some(conn_proxy),
             hop_count,
+            preference: 0,
         }
     }
 
@@ -46,10 +61,11 @@ impl SwbusNextHop {
             conn_info: None,
             conn_proxy: None,
             hop_count: 0,
+            preference: 0,
         }
     }
 
-    #[instrument(name="queue_message", parent=None, level="debug", skip_all, fields(nh_type=?self.nh_type, conn_info=self.conn_info.as_ref().map(|x| x.id()).unwrap_or(&"None".to_string()), message.id=?message.header.as_ref().unwrap().id))]
+    #[instrument(name="queue_message", parent=None, level="debug", skip_all, fields(nh_type=?self.nh_type, conn_info=self.conn_info.as_ref().map(|x| x.id()).unwrap_or(&"None".to_string()), message.id=?message.header.as_ref().unwrap().id, trace_id=%message.header.as_ref().unwrap().trace_id))]
     pub async fn queue_message(
         &self,
         mux: &SwbusMultiplexer,
@@ -68,6 +84,7 @@ impl SwbusNextHop {
                 header.ttl -= 1;
                 if header.ttl == 0 {
                     debug!("TTL expired");
+                    mux.drop_undeliverable(&message, "ttl_expired");
                     let response = SwbusMessage::new_response(
                         &message,
                         Some(&mux.get_my_service_path()),
@@ -100,6 +117,7 @@ impl SwbusNextHop {
             // local nexthop uses swbusd service path. If the dest sp is to a local service and
             // there is no route to the service, the packet will be routed to here. We need to
             // return no route error in this case.
+            mux.drop_undeliverable(&message, "no_route");
             let response = SwbusMessage::new_response(
                 &message,
                 None,
@@ -112,12 +130,56 @@ impl SwbusNextHop {
         }
         let response = match message.body.as_ref() {
             Some(swbus_message::Body::PingRequest(_)) => self.process_ping_request(mux, message).unwrap(),
+            Some(swbus_message::Body::ManagementRequest(mgmt_request))
+                if mgmt_request.request == ManagementRequestType::SwbusdPingAll as i32
+                    || mgmt_request.request == ManagementRequestType::SwbusdRotateTls as i32 =>
+            {
+                // The response is sent once the pings are answered or the connections are replaced,
+                // so there is nothing to respond now unless they can't be started.
+                let result = if mgmt_request.request == ManagementRequestType::SwbusdPingAll as i32 {
+                    debug!("Received ping_all request");
+                    self.process_ping_all_request(mux, &message, mgmt_request)
+                } else {
+                    debug!("Received rotate_tls request");
+                    mux.rotate_tls(&message)
+                };
+                let Err(e) = result else {
+                    return Ok(None);
+                };
+                let (error_code, error_message) = error_code_and_message(e);
+                SwbusMessage::new_response(
+                    &message,
+                    None,
+                    error_code,
+                    &error_message,
+                    mux.generate_message_id(),
+                    None,
+                )
+            }
             Some(swbus_message::Body::ManagementRequest(mgmt_request)) => {
-                self.process_mgmt_request(mux, &message, mgmt_request).unwrap()
+                match self.process_mgmt_request(mux, &message, mgmt_request) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let (error_code, error_message) = error_code_and_message(e);
+                        SwbusMessage::new_response(
+                            &message,
+                            None,
+                            error_code,
+                            &error_message,
+                            mux.generate_message_id(),
+                            None,
+                        )
+                    }
+                }
+            }
+            Some(swbus_message::Body::Response(response)) if mux.complete_ping(response) => {
+                debug!("Received ping response");
+                return Ok(None);
             }
             _ => {
                 // drop all other messages. This could happen due to message loop or other invaid messages to swbusd.
                 debug!("Drop unknown message to a local endpoint");
+                metrics::message_dropped("unexpected");
                 return Ok(None);
             }
         };
@@ -153,16 +215,89 @@ impl SwbusNextHop {
         match request_type {
             ManagementRequestType::SwbusdGetRoutes => {
                 debug!("Received show_route request");
-                let routes = mux.export_routes(None);
-                let response_msg = SwbusMessage::new_response(
+                let response_msg = match RouteQuery::from_request(mgmt_request) {
+                    Ok(query) => SwbusMessage::new_response(
+                        message,
+                        None,
+                        SwbusErrorCode::Ok,
+                        "",
+                        mux.generate_message_id(),
+                        Some(request_response::ResponseBody::RouteQueryResult(
+                            mux.export_routes(&query),
+                        )),
+                    ),
+                    Err(e) => {
+                        let (error_code, error_message) = error_code_and_message(e);
+                        SwbusMessage::new_response(
+                            message,
+                            None,
+                            error_code,
+                            &error_message,
+                            mux.generate_message_id(),
+                            None,
+                        )
+                    }
+                };
+                Ok(response_msg)
+            }
+            ManagementRequestType::SwbusdGetDeadLetters => {
+                debug!("Received get_dead_letters request");
+                let payloads = mgmt_request.arguments.iter().any(|arg| arg.name == "payloads");
+                let dead_letters = mux.export_dead_letters(payloads);
+                Ok(SwbusMessage::new_response(
                     message,
                     None,
                     SwbusErrorCode::Ok,
                     "",
                     mux.generate_message_id(),
-                    Some(request_response::ResponseBody::RouteQueryResult(routes)),
-                );
-                Ok(response_msg)
+                    Some(request_response::ResponseBody::DeadLetterQueryResult(dead_letters)),
+                ))
+            }
+            ManagementRequestType::SwbusdCapture => {
+                debug!("Received capture request");
+                let (error_code, error_message) = match self.process_capture_request(mux, message, mgmt_request) {
+                    Ok(()) => (SwbusErrorCode::Ok, String::new()),
+                    Err(e) => error_code_and_message(e),
+                };
+                Ok(SwbusMessage::new_response(
+                    message,
+                    None,
+                    error_code,
+                    &error_message,
+                    mux.generate_message_id(),
+                    None,
+                ))
+            }
+            ManagementRequestType::SwbusdSubscribeRoutes => {
+                debug!("Received subscribe_routes request");
+                let (error_code, error_message) =
+                    match self.process_subscribe_routes_request(mux, message, mgmt_request) {
+                        Ok(()) => (SwbusErrorCode::Ok, String::new()),
+                        Err(e) => error_code_and_message(e),
+                    };
+                Ok(SwbusMessage::new_response(
+                    message,
+                    None,
+                    error_code,
+                    &error_message,
+                    mux.generate_message_id(),
+                    None,
+                ))
+            }
+            ManagementRequestType::SwbusdInjectFaults => {
+                debug!("Received inject_faults request");
+                let (error_code, error_message) = match mux.inject_faults(mgmt_request) {
+                    Ok(()) => (SwbusErrorCode::Ok, String::new()),
+                    Err(e) => error_code_and_message(e),
+                };
+                Ok(SwbusMessage::new_response(
+                    message,
+                    None,
+                    error_code,
+                    &error_message,
+                    mux.generate_message_id(),
+                    None,
+                ))
             }
             _ => Err(SwbusError::input(
                 SwbusErrorCode::InvalidArgs,
@@ -170,16 +305,97 @@ impl SwbusNextHop {
             )),
         }
     }
+
+    /// Start or stop copying routed messages to the source of the request.
+    fn process_capture_request(
+        &self,
+        mux: &SwbusMultiplexer,
+        message: &SwbusMessage,
+        mgmt_request: &ManagementRequest,
+    ) -> Result<()> {
+        let arg = |name: &str| {
+            mgmt_request
+                .arguments
+                .iter()
+                .find(|arg| arg.name == name)
+                .map(|arg| arg.value.as_str())
+        };
+        if arg("stop").is_some() {
+            let Some(subscriber) = message.header.as_ref().and_then(|h| h.source.as_ref()) else {
+                return Err(SwbusError::input(
+                    SwbusErrorCode::InvalidArgs,
+                    "missing source of capture request".to_string(),
+                ));
+            };
+            mux.stop_capture(subscriber);
+            return Ok(());
+        }
+        let duration_secs = match arg("duration_secs") {
+            Some(value) => value.parse().map_err(|_| {
+                SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Invalid duration_secs: {value}"))
+            })?,
+            None => DEFAULT_CAPTURE_SECS,
+        };
+        mux.start_capture(message, Duration::from_secs(duration_secs))
+    }
+
+    /// Subscribe the source of the request to route changes, or unsubscribe it.
+    fn process_subscribe_routes_request(
+        &self,
+        mux: &SwbusMultiplexer,
+        message: &SwbusMessage,
+        mgmt_request: &ManagementRequest,
+    ) -> Result<()> {
+        let Some(subscriber) = message.header.as_ref().and_then(|h| h.source.clone()) else {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                "missing source of subscribe_routes request".to_string(),
+            ));
+        };
+        if mgmt_request.arguments.iter().any(|arg| arg.name == "stop") {
+            mux.unsubscribe_routes(&subscriber);
+            return Ok(());
+        }
+        mux.subscribe_routes(subscriber)
+    }
+
+    /// Ping every swbusd there is a route to, and report back to the source of the request.
+    fn process_ping_all_request(
+        &self,
+        mux: &SwbusMultiplexer,
+        message: &SwbusMessage,
+        mgmt_request: &ManagementRequest,
+    ) -> Result<()> {
+        let timeout_ms = match mgmt_request.arguments.iter().find(|arg| arg.name == "timeout_ms") {
+            Some(arg) => arg.value.parse().map_err(|_| {
+                SwbusError::input(
+                    SwbusErrorCode::InvalidArgs,
+                    format!("Invalid timeout_ms: {}", arg.value),
+                )
+            })?,
+            None => DEFAULT_PING_ALL_TIMEOUT_MS,
+        };
+        mux.ping_all(message, Duration::from_millis(timeout_ms))
+    }
+}
+
+/// The error code and message to respond with for `error`.
+pub(crate) fn error_code_and_message(error: SwbusError) -> (SwbusErrorCode, String) {
+    match error {
+        SwbusError::ConnectionError { code, detail } => (code, detail.to_string()),
+        SwbusError::InputError { code, detail }
+        | SwbusError::RouteError { code, detail }
+        | SwbusError::InternalError { code, detail } => (code, detail),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mux::SwbusConn;
+    use crate::mux::{send_queue, SwbusConn};
     use std::sync::Arc;
-    use swbus_config::RouteConfig;
+    use swbus_config::{RouteConfig, SendQueueConfig};
     use swbus_proto::swbus::SwbusMessage;
-    use tokio::sync::mpsc;
 
     #[tokio::test]
     async fn test_new_remote() {
@@ -189,7 +405,7 @@ mod tests {
             ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
             ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
         ));
-        let (send_queue_tx, _) = mpsc::channel(16);
+        let (send_queue_tx, _) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
         let conn = SwbusConn::new(&conn_info, send_queue_tx);
         let hop_count = 5;
         let nexthop = SwbusNextHop::new_remote(conn_info.clone(), conn.new_proxy(), hop_count);
@@ -262,6 +478,31 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_queue_message_unsupported_mgmt_request() {
+        let nexthop = SwbusNextHop::new_local();
+        let mux = Arc::new(SwbusMultiplexer::default());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+
+        // a request for hamgrd sent to swbusd is answered with an error
+        let request = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/swbus-cli/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                1,
+            ),
+            swbus_message::Body::ManagementRequest(ManagementRequest::new(ManagementRequestType::HamgrdGetActorState)),
+        );
+        let response = nexthop.queue_message(&mux, request).await.unwrap().unwrap();
+        let Some(swbus_message::Body::Response(response)) = response.body else {
+            panic!("expected a response, got {:?}", response.body);
+        };
+        assert_eq!(response.error_code(), SwbusErrorCode::InvalidArgs);
+    }
+
     #[tokio::test]
     async fn test_queue_message_remote_ttl_expired() {
         let conn_info = Arc::new(SwbusConnInfo::new_client(
@@ -270,7 +511,7 @@ mod tests {
             ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
             ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
         ));
-        let (send_queue_tx, _) = mpsc::channel(16);
+        let (send_queue_tx, _) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
         let conn = SwbusConn::new(&conn_info, send_queue_tx);
         let hop_count = 5;
         let nexthop = SwbusNextHop::new_remote(conn_info.clone(), conn.new_proxy(), hop_count);
@@ -0,0 +1,143 @@
+use swbus_config::{RateLimitConfig, RateLimitPolicy};
+use swbus_proto::swbus::ConnectionType;
+use tokio::time::{Duration, Instant};
+
+/// A token bucket refilled at `rate` tokens per second, holding up to one second worth of them.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        Some(TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled_at: now,
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+    }
+
+    /// How long until the bucket holds `amount` tokens. Amounts larger than the bucket only wait for it
+    /// to be full, so they get through eventually.
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.rate) - self.tokens;
+        match missing > 0.0 {
+            true => Duration::from_secs_f64(missing / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens = (self.tokens - amount).max(0.0);
+    }
+}
+
+/// Rate limit of the messages received on one connection, see [`RateLimitConfig`].
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    policy: RateLimitPolicy,
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// The rate limiter of a connection of `conn_type`, if it is limited. Only clients are, which
+    /// includes the swbus-edge runtimes connecting as local.
+    pub(crate) fn new(config: Option<RateLimitConfig>, conn_type: ConnectionType, now: Instant) -> Option<Self> {
+        let config = config?;
+        if !matches!(conn_type, ConnectionType::Client | ConnectionType::Local) {
+            return None;
+        }
+        let messages = TokenBucket::new(config.messages_per_sec as u64, now);
+        let bytes = TokenBucket::new(config.bytes_per_sec, now);
+        if messages.is_none() && bytes.is_none() {
+            return None;
+        }
+        Some(RateLimiter {
+            policy: config.policy,
+            messages,
+            bytes,
+        })
+    }
+
+    pub(crate) fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    /// Take the tokens of a message of `size` bytes if both limits allow it. Otherwise nothing is taken,
+    /// and the error is how long until they do.
+    pub(crate) fn try_acquire(&mut self, size: usize, now: Instant) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        for (bucket, amount) in [(&mut self.messages, 1.0), (&mut self.bytes, size as f64)] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                wait = wait.max(bucket.wait_for(amount));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (bucket, amount) in [(&mut self.messages, 1.0), (&mut self.bytes, size as f64)] {
+            if let Some(bucket) = bucket {
+                bucket.take(amount);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(messages_per_sec: u32, bytes_per_sec: u64) -> Option<RateLimitConfig> {
+        Some(RateLimitConfig {
+            messages_per_sec,
+            bytes_per_sec,
+            policy: RateLimitPolicy::Reject,
+        })
+    }
+
+    #[test]
+    fn only_client_connections_are_limited() {
+        let now = Instant::now();
+        assert!(RateLimiter::new(config(10, 0), ConnectionType::Local, now).is_some());
+        assert!(RateLimiter::new(config(10, 0), ConnectionType::Client, now).is_some());
+        assert!(RateLimiter::new(config(10, 0), ConnectionType::Cluster, now).is_none());
+        assert!(RateLimiter::new(config(0, 0), ConnectionType::Local, now).is_none());
+        assert!(RateLimiter::new(None, ConnectionType::Local, now).is_none());
+    }
+
+    #[test]
+    fn messages_over_the_limit_wait_for_the_bucket_to_refill() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(config(2, 100), ConnectionType::Local, now).unwrap();
+
+        // a burst of one second worth of messages gets through
+        assert_eq!(limiter.try_acquire(10, now), Ok(()));
+        assert_eq!(limiter.try_acquire(10, now), Ok(()));
+        assert_eq!(limiter.try_acquire(10, now), Err(Duration::from_millis(500)));
+
+        // half a second later, there is room for one message but not for 90 more bytes
+        let now = now + Duration::from_millis(500);
+        assert_eq!(limiter.try_acquire(90, now), Err(Duration::from_millis(100)));
+        assert_eq!(limiter.try_acquire(40, now), Ok(()));
+        assert_eq!(limiter.try_acquire(1, now), Err(Duration::from_millis(500)));
+
+        // a message larger than the bucket gets through once it is full
+        let now = now + Duration::from_secs(1);
+        assert_eq!(limiter.try_acquire(1000, now), Ok(()));
+        assert!(limiter.try_acquire(1, now).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Route queries
+//!
+//! `MANAGEMENT_REQUEST_TYPE_SWBUSD_GET_ROUTES` exports the routes a page at a time, so the response stays
+//! small in clusters with thousands of routes. The routes are ordered by service path, and a page ends
+//! after a whole route, so the `next_cursor` of a page is the service path of its last route, and the
+//! next page starts after it. Routes added or removed between two pages don't shift the later ones.
+use swbus_proto::result::*;
+use swbus_proto::swbus::{ManagementRequest, RouteQueryResult, RouteQueryResultEntry, RouteScope, SwbusErrorCode};
+
+/// Max entries in a page, and the size of a page if the request doesn't set a limit.
+pub const ROUTE_PAGE_LIMIT: usize = 1000;
+
+/// Which routes to export, and which page of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteQuery {
+    /// Only the routes of this scope or wider, down to cluster scope.
+    pub scope: Option<RouteScope>,
+    /// Only the routes whose service path starts with this.
+    pub prefix: Option<String>,
+    /// Only the next hops through this connection, by its id or the service path of its peer.
+    pub connection: Option<String>,
+    /// Start after the route with this service path, the `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Max entries in the page. A route with more next hops than this is still exported whole.
+    pub limit: usize,
+}
+
+impl Default for RouteQuery {
+    fn default() -> Self {
+        RouteQuery {
+            scope: None,
+            prefix: None,
+            connection: None,
+            cursor: None,
+            limit: ROUTE_PAGE_LIMIT,
+        }
+    }
+}
+
+impl RouteQuery {
+    /// Query of a get_routes request, with the arguments `scope`, `prefix`, `connection`, `cursor` and
+    /// `limit`, all optional.
+    pub fn from_request(mgmt_request: &ManagementRequest) -> Result<Self> {
+        let arg = |name: &str| {
+            mgmt_request
+                .arguments
+                .iter()
+                .find(|arg| arg.name == name)
+                .map(|arg| arg.value.clone())
+        };
+        let invalid = |detail: String| SwbusError::input(SwbusErrorCode::InvalidArgs, detail);
+
+        let scope = match arg("scope") {
+            Some(value) => Some(parse_route_scope(&value).ok_or_else(|| invalid(format!("Invalid scope: {value}")))?),
+            None => None,
+        };
+        let limit = match arg("limit") {
+            Some(value) => match value.parse() {
+                Ok(limit) if limit > 0 => usize::min(limit, ROUTE_PAGE_LIMIT),
+                _ => return Err(invalid(format!("Invalid limit: {value}"))),
+            },
+            None => ROUTE_PAGE_LIMIT,
+        };
+        Ok(RouteQuery {
+            scope,
+            prefix: arg("prefix"),
+            connection: arg("connection"),
+            cursor: arg("cursor").filter(|cursor| !cursor.is_empty()),
+            limit,
+        })
+    }
+
+    pub(crate) fn matches_route(&self, route_key: &str, route_scope: RouteScope) -> bool {
+        let in_scope = self
+            .scope
+            .is_none_or(|s| route_scope >= s && route_scope >= RouteScope::Cluster);
+        in_scope && self.prefix.as_ref().is_none_or(|prefix| route_key.starts_with(prefix))
+    }
+
+    pub(crate) fn matches_connection(&self, nh_id: &str, nh_service_path: &str) -> bool {
+        self.connection
+            .as_ref()
+            .is_none_or(|connection| connection == nh_id || connection == nh_service_path)
+    }
+
+    /// The page of `routes`, which are the matching routes with their entries, sorted by service path.
+    pub(crate) fn page(&self, routes: Vec<(String, Vec<RouteQueryResultEntry>)>) -> RouteQueryResult {
+        let total_entries = routes.iter().map(|(_, entries)| entries.len()).sum::<usize>() as u32;
+        let start = match &self.cursor {
+            Some(cursor) => routes.partition_point(|(route_key, _)| route_key <= cursor),
+            None => 0,
+        };
+        let mut page = Vec::new();
+        let mut next_cursor = String::new();
+        let mut last_key = None;
+        for (route_key, entries) in routes.into_iter().skip(start) {
+            if !page.is_empty() && page.len() + entries.len() > self.limit {
+                next_cursor = last_key.unwrap_or_default();
+                break;
+            }
+            page.extend(entries);
+            last_key = Some(route_key);
+        }
+        RouteQueryResult {
+            entries: page,
+            next_cursor,
+            total_entries,
+        }
+    }
+}
+
+/// Route scope by its name, e.g. `cluster` or `ROUTE_SCOPE_CLUSTER`.
+fn parse_route_scope(name: &str) -> Option<RouteScope> {
+    let name = name.to_uppercase();
+    RouteScope::from_str_name(&name).or_else(|| RouteScope::from_str_name(&format!("ROUTE_SCOPE_{name}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::{ManagementRequestArg, ManagementRequestType, ServicePath};
+
+    fn request(args: &[(&str, &str)]) -> ManagementRequest {
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdGetRoutes);
+        mgmt_request.arguments = args
+            .iter()
+            .map(|(name, value)| ManagementRequestArg {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect();
+        mgmt_request
+    }
+
+    fn routes(nexthops: &[(&str, usize)]) -> Vec<(String, Vec<RouteQueryResultEntry>)> {
+        nexthops
+            .iter()
+            .map(|(route_key, count)| {
+                let entry = RouteQueryResultEntry {
+                    service_path: Some(ServicePath::from_string(route_key).unwrap()),
+                    ..Default::default()
+                };
+                (route_key.to_string(), vec![entry; *count])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_from_request() {
+        assert_eq!(RouteQuery::from_request(&request(&[])).unwrap(), RouteQuery::default());
+
+        let query = RouteQuery::from_request(&request(&[
+            ("scope", "region"),
+            ("prefix", "region-a"),
+            ("connection", "swbs-to://10.0.0.1:8000"),
+            ("cursor", "region-a.cluster-a"),
+            ("limit", "10"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            query,
+            RouteQuery {
+                scope: Some(RouteScope::Region),
+                prefix: Some("region-a".to_string()),
+                connection: Some("swbs-to://10.0.0.1:8000".to_string()),
+                cursor: Some("region-a.cluster-a".to_string()),
+                limit: 10,
+            }
+        );
+
+        let query =
+            RouteQuery::from_request(&request(&[("scope", "ROUTE_SCOPE_GLOBAL"), ("limit", "100000")])).unwrap();
+        assert_eq!(query.scope, Some(RouteScope::Global));
+        assert_eq!(query.limit, ROUTE_PAGE_LIMIT);
+
+        assert!(RouteQuery::from_request(&request(&[("scope", "planet")])).is_err());
+        assert!(RouteQuery::from_request(&request(&[("limit", "0")])).is_err());
+        assert!(RouteQuery::from_request(&request(&[("limit", "many")])).is_err());
+    }
+
+    #[test]
+    fn test_page() {
+        let routes = routes(&[("region-a", 1), ("region-b", 2), ("region-c", 1), ("region-d", 3)]);
+        let mut query = RouteQuery {
+            limit: 3,
+            ..Default::default()
+        };
+        let keys = |result: &RouteQueryResult| -> Vec<String> {
+            result
+                .entries
+                .iter()
+                .map(|entry| entry.service_path.as_ref().unwrap().to_longest_path())
+                .collect()
+        };
+
+        // pages end after a whole route
+        let page = query.page(routes.clone());
+        assert_eq!(keys(&page), vec!["region-a", "region-b", "region-b"]);
+        assert_eq!(page.next_cursor, "region-b");
+        assert_eq!(page.total_entries, 7);
+
+        query.cursor = Some(page.next_cursor);
+        let page = query.page(routes.clone());
+        assert_eq!(keys(&page), vec!["region-c"]);
+        assert_eq!(page.next_cursor, "region-c");
+
+        // a route with more next hops than the limit still makes a page
+        query.cursor = Some(page.next_cursor);
+        let page = query.page(routes.clone());
+        assert_eq!(keys(&page), vec!["region-d"; 3]);
+        assert_eq!(page.next_cursor, "");
+
+        // a cursor that is gone from the routes continues after where it was
+        query.cursor = Some("region-bb".to_string());
+        assert_eq!(keys(&query.page(routes)), vec!["region-c"]);
+    }
+}
@@ -0,0 +1,413 @@
+use super::{SwbusConnInfo, SwbusNextHop};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use swbus_proto::swbus::ServicePath;
+use tracing::*;
+
+#[cfg(loom)]
+use loom::sync::{Arc, Mutex, RwLock};
+#[cfg(not(loom))]
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Equal-cost next hops of a route, ordered by their connection id.
+pub(crate) type NextHops = Vec<SwbusNextHop>;
+
+pub(crate) type Routes = HashMap<String, NextHops>;
+
+/// Route table of the multiplexer, from a registered prefix to its next hops.
+///
+/// Lookups take a snapshot of the table, so no lock is held while a message is queued to the next
+/// hop. Updates copy the table and swap the copy in, one writer at a time.
+///
+/// A route takes the next hops with the highest preference, and among them the ones with the smallest
+/// hop count. The other next hops are kept as candidates and take over when those are removed.
+pub(crate) struct RouteTable {
+    routes: RwLock<Arc<Routes>>,
+    /// All the next hops of each route, the ones in `routes` and the ones that lost to them. Writers hold
+    /// this lock while they update `routes`.
+    candidates: Mutex<Routes>,
+}
+
+impl Default for RouteTable {
+    fn default() -> Self {
+        RouteTable {
+            routes: RwLock::new(Arc::new(Routes::new())),
+            candidates: Mutex::new(Routes::new()),
+        }
+    }
+}
+
+impl RouteTable {
+    pub fn snapshot(&self) -> Arc<Routes> {
+        self.routes.read().unwrap().clone()
+    }
+
+    pub fn get(&self, route_key: &str) -> Option<NextHops> {
+        self.snapshot().get(route_key).cloned()
+    }
+
+    /// The route to the longest prefix of `destination`, with its key. A route to a cluster covers all
+    /// the nodes of the cluster that have no route of their own, and so on.
+    pub fn longest_match(&self, destination: &ServicePath) -> Option<(String, NextHops)> {
+        let routes = self.snapshot();
+        route_prefixes(destination)
+            .into_iter()
+            .find_map(|route_key| Some((route_key.clone(), routes.get(&route_key)?.clone())))
+    }
+
+    /// Add a next hop to a route. It replaces the next hops of the route if it ranks higher, i.e. it has
+    /// a higher preference, or the same preference and a smaller hop count, and is added to them if it
+    /// ranks the same. A next hop over the same connection as one of the route replaces that one.
+    /// Returns whether the table changed.
+    pub fn update(&self, route_key: String, nexthop: SwbusNextHop) -> bool {
+        let mut candidates = self.candidates.lock().unwrap();
+        let nexthops = candidates.entry(route_key.clone()).or_default();
+        nexthops.retain(|nh| nh.conn_info() != nexthop.conn_info());
+        let new_rank = rank(&nexthop);
+        nexthops.push(nexthop);
+        // a next hop that is selected goes in even if it only replaces one over the same connection
+        let force = nexthops.iter().map(rank).min() == Some(new_rank);
+        let changed = self.select(&candidates, &route_key, force);
+        if !changed {
+            info!("Route entry already exists with a higher preference or smaller hop count");
+        }
+        changed
+    }
+
+    pub fn remove(&self, route_key: &str) -> Option<NextHops> {
+        let mut candidates = self.candidates.lock().unwrap();
+        candidates.remove(route_key);
+        let mut routes = self.routes.write().unwrap();
+        if !routes.contains_key(route_key) {
+            return None;
+        }
+        let mut new_routes = Routes::clone(&routes);
+        let removed = new_routes.remove(route_key);
+        *routes = Arc::new(new_routes);
+        removed
+    }
+
+    /// Remove the next hop over `conn_info` from a route. The next hops that rank highest among the
+    /// remaining ones take over, and the route is removed if it was the last next hop. Returns whether
+    /// the table changed.
+    pub fn remove_nexthop(&self, route_key: &str, conn_info: &SwbusConnInfo) -> bool {
+        let mut candidates = self.candidates.lock().unwrap();
+        let Some(nexthops) = candidates.get_mut(route_key) else {
+            return false;
+        };
+        nexthops.retain(|nh| nh.conn_info().as_deref() != Some(conn_info));
+        if nexthops.is_empty() {
+            candidates.remove(route_key);
+        }
+        self.select(&candidates, route_key, false)
+    }
+
+    /// Set the preference of every next hop to `preference(route_key, nexthop)`, and select the next
+    /// hops of the routes again. Returns whether the table changed.
+    pub fn set_preferences(&self, preference: impl Fn(&str, &SwbusNextHop) -> u32) -> bool {
+        let mut candidates = self.candidates.lock().unwrap();
+        for (route_key, nexthops) in candidates.iter_mut() {
+            for nexthop in nexthops.iter_mut() {
+                let value = preference(route_key, nexthop);
+                nexthop.set_preference(value);
+            }
+        }
+        let route_keys: Vec<String> = candidates.keys().cloned().collect();
+        route_keys.iter().fold(false, |changed, route_key| {
+            self.select(&candidates, route_key, false) || changed
+        })
+    }
+
+    /// Update a route to the next hops among its candidates that rank highest, unless they are the
+    /// ones it has and `force` is not set. Returns whether the route changed.
+    fn select(&self, candidates: &Routes, route_key: &str, force: bool) -> bool {
+        let mut selected: NextHops = match candidates.get(route_key) {
+            Some(nexthops) => {
+                let best = nexthops.iter().map(rank).min().expect("route without candidates");
+                nexthops.iter().filter(|nh| rank(nh) == best).cloned().collect()
+            }
+            None => NextHops::new(),
+        };
+        selected.sort_by(|a, b| nexthop_id(a).cmp(nexthop_id(b)));
+
+        let mut routes = self.routes.write().unwrap();
+        let same = match routes.get(route_key) {
+            Some(existing) => {
+                existing.len() == selected.len()
+                    && existing.iter().zip(&selected).all(|(a, b)| {
+                        a.conn_info() == b.conn_info() && a.nh_type() == b.nh_type() && rank(a) == rank(b)
+                    })
+            }
+            None => selected.is_empty(),
+        };
+        if same && !force {
+            return false;
+        }
+
+        let mut new_routes = Routes::clone(&routes);
+        if selected.is_empty() {
+            new_routes.remove(route_key);
+        } else {
+            new_routes.insert(route_key.to_string(), selected);
+        }
+        *routes = Arc::new(new_routes);
+        true
+    }
+}
+
+/// Rank of a next hop, the lowest one wins: higher preferences first, then smaller hop counts.
+fn rank(nexthop: &SwbusNextHop) -> (Reverse<u32>, u32) {
+    (Reverse(nexthop.preference()), nexthop.hop_count())
+}
+
+/// Route keys of `destination` from the longest prefix to the shortest: its service, node, cluster and
+/// region.
+pub(crate) fn route_prefixes(destination: &ServicePath) -> [String; 4] {
+    [
+        destination.to_service_prefix(),
+        destination.to_node_prefix(),
+        destination.to_cluster_prefix(),
+        destination.to_regional_prefix(),
+    ]
+}
+
+/// Summarize routes by leaving out the ones covered by a route to a shorter prefix in `routes`. Messages
+/// routed over a route that is left out would be routed over the covering route without it, so the
+/// summary reaches the same destinations.
+pub(crate) fn aggregate_routes(routes: &BTreeSet<String>) -> BTreeSet<String> {
+    routes
+        .iter()
+        .filter(|route_key| {
+            let Ok(sp) = ServicePath::from_string(route_key) else {
+                return true;
+            };
+            !route_prefixes(&sp)
+                .iter()
+                .any(|prefix| prefix != *route_key && route_key.starts_with(prefix.as_str()) && routes.contains(prefix))
+        })
+        .cloned()
+        .collect()
+}
+
+fn nexthop_id(nexthop: &SwbusNextHop) -> &str {
+    nexthop.conn_info().as_ref().map_or("", |conn_info| conn_info.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mux::{send_queue, SwbusConnInfo, SwbusConnProxy};
+    use swbus_config::SendQueueConfig;
+    use swbus_proto::swbus::{ConnectionType, ServicePath};
+
+    fn remote_nexthop(hop_count: u32) -> SwbusNextHop {
+        remote_nexthop_at(hop_count, 8080)
+    }
+
+    fn remote_nexthop_at(hop_count: u32, port: u16) -> SwbusNextHop {
+        let conn_info = std::sync::Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Cluster,
+            format!("127.0.0.1:{port}").parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+        ));
+        let (tx, _) = send_queue::channel(conn_info.id(), &SendQueueConfig::default());
+        SwbusNextHop::new_remote(conn_info, SwbusConnProxy::new(tx), hop_count)
+    }
+
+    #[test]
+    fn update_keeps_the_smallest_hop_count() {
+        let table = RouteTable::default();
+        assert!(table.update("region-a.cluster-a".to_string(), remote_nexthop(2)));
+        assert!(!table.update("region-a.cluster-a".to_string(), remote_nexthop(3)));
+        assert!(table.update("region-a.cluster-a".to_string(), remote_nexthop(1)));
+        assert_eq!(table.get("region-a.cluster-a").unwrap()[0].hop_count(), 1);
+    }
+
+    #[test]
+    fn equal_cost_nexthops_are_kept_until_their_connection_is_gone() {
+        let table = RouteTable::default();
+        let a = remote_nexthop_at(1, 8081);
+        let b = remote_nexthop_at(1, 8080);
+        assert!(table.update("region-a.cluster-a".to_string(), a.clone()));
+        assert!(table.update("region-a.cluster-a".to_string(), b.clone()));
+        // the same connection again replaces its next hop
+        assert!(table.update("region-a.cluster-a".to_string(), a.clone()));
+        let ids: Vec<String> = table
+            .get("region-a.cluster-a")
+            .unwrap()
+            .iter()
+            .map(|nh| nexthop_id(nh).to_string())
+            .collect();
+        assert_eq!(ids, vec!["swbs-to://127.0.0.1:8080", "swbs-to://127.0.0.1:8081"]);
+
+        assert!(table.remove_nexthop("region-a.cluster-a", b.conn_info().as_ref().unwrap()));
+        assert!(!table.remove_nexthop("region-a.cluster-a", b.conn_info().as_ref().unwrap()));
+        assert_eq!(table.get("region-a.cluster-a").unwrap().len(), 1);
+        assert!(table.remove_nexthop("region-a.cluster-a", a.conn_info().as_ref().unwrap()));
+        assert!(table.get("region-a.cluster-a").is_none());
+    }
+
+    #[test]
+    fn less_preferred_nexthops_take_over_when_the_preferred_ones_are_gone() {
+        let table = RouteTable::default();
+        let nexthop_ids = |table: &RouteTable| -> Vec<String> {
+            table
+                .get("region-b")
+                .unwrap()
+                .iter()
+                .map(|nh| nexthop_id(nh).to_string())
+                .collect()
+        };
+        let mut preferred = remote_nexthop_at(2, 8081);
+        preferred.set_preference(100);
+        let backup = remote_nexthop_at(1, 8080);
+        assert!(table.update("region-b".to_string(), backup.clone()));
+        // a higher preference wins over a smaller hop count
+        assert!(table.update("region-b".to_string(), preferred.clone()));
+        assert_eq!(nexthop_ids(&table), vec!["swbs-to://127.0.0.1:8081"]);
+
+        assert!(table.remove_nexthop("region-b", preferred.conn_info().as_ref().unwrap()));
+        assert_eq!(nexthop_ids(&table), vec!["swbs-to://127.0.0.1:8080"]);
+        assert!(table.update("region-b".to_string(), preferred.clone()));
+        assert_eq!(nexthop_ids(&table), vec!["swbs-to://127.0.0.1:8081"]);
+
+        // changed preferences apply to the next hops there are
+        let prefer_backup = |_: &str, nh: &SwbusNextHop| if nexthop_id(nh).ends_with(":8080") { 200 } else { 0 };
+        assert!(table.set_preferences(prefer_backup));
+        assert_eq!(nexthop_ids(&table), vec!["swbs-to://127.0.0.1:8080"]);
+        assert!(!table.set_preferences(prefer_backup));
+
+        assert!(table.remove_nexthop("region-b", backup.conn_info().as_ref().unwrap()));
+        assert!(table.remove_nexthop("region-b", preferred.conn_info().as_ref().unwrap()));
+        assert!(table.get("region-b").is_none());
+    }
+
+    #[test]
+    fn longest_match_falls_back_to_shorter_prefixes() {
+        let table = RouteTable::default();
+        let destination = ServicePath::from_string("region-a.cluster-b.10.0.1.1-dpu0/hamgrd/0").unwrap();
+        assert!(table.longest_match(&destination).is_none());
+
+        table.update("region-a".to_string(), remote_nexthop(3));
+        table.update("region-a.cluster-b".to_string(), remote_nexthop(2));
+        assert_eq!(table.longest_match(&destination).unwrap().0, "region-a.cluster-b");
+        table.update("region-a.cluster-b.10.0.1.1-dpu0".to_string(), remote_nexthop(1));
+        assert_eq!(
+            table.longest_match(&destination).unwrap().0,
+            "region-a.cluster-b.10.0.1.1-dpu0"
+        );
+
+        // other nodes of the cluster are covered by the cluster route
+        let sibling = ServicePath::from_string("region-a.cluster-b.10.0.1.2-dpu0/hamgrd/0").unwrap();
+        assert_eq!(table.longest_match(&sibling).unwrap().0, "region-a.cluster-b");
+        let other_cluster = ServicePath::from_string("region-a.cluster-c.10.0.2.1-dpu0/hamgrd/0").unwrap();
+        assert_eq!(table.longest_match(&other_cluster).unwrap().0, "region-a");
+    }
+
+    #[test]
+    fn aggregate_routes_leaves_out_covered_routes() {
+        let routes: BTreeSet<String> = [
+            "region-a.cluster-b",
+            "region-a.cluster-b.10.0.1.1-dpu0",
+            "region-a.cluster-b.10.0.1.2-dpu0/hamgrd/0",
+            "region-a.cluster-c.10.0.2.1-dpu0",
+            "region-a.cluster-c.10.0.2.1-dpu0/hamgrd/0",
+            // a string prefix but not a route prefix
+            "region-a.cluster-bb.10.0.3.1-dpu0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let expected: BTreeSet<String> = [
+            "region-a.cluster-b",
+            "region-a.cluster-c.10.0.2.1-dpu0",
+            "region-a.cluster-bb.10.0.3.1-dpu0",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(aggregate_routes(&routes), expected);
+    }
+
+    #[test]
+    fn snapshot_is_not_affected_by_updates() {
+        let table = RouteTable::default();
+        table.update("region-a.cluster-a".to_string(), remote_nexthop(1));
+        let snapshot = table.snapshot();
+
+        table.remove("region-a.cluster-a");
+        table.update("region-a.cluster-b".to_string(), remote_nexthop(1));
+        assert!(snapshot.contains_key("region-a.cluster-a"));
+        assert!(!snapshot.contains_key("region-a.cluster-b"));
+        assert!(table.get("region-a.cluster-a").is_none());
+        assert!(table.remove("region-a.cluster-a").is_none());
+    }
+
+    // Run with: RUSTFLAGS="--cfg loom" cargo test --release -p swbus-core --lib loom
+    #[cfg(loom)]
+    #[test]
+    fn loom_concurrent_updates_are_not_lost() {
+        loom::model(|| {
+            let table = Arc::new(RouteTable::default());
+            let writers: Vec<_> = ["region-a.cluster-a", "region-a.cluster-b"]
+                .into_iter()
+                .map(|key| {
+                    let table = table.clone();
+                    loom::thread::spawn(move || table.update(key.to_string(), remote_nexthop(1)))
+                })
+                .collect();
+
+            // a later snapshot never misses a route an earlier one had
+            let first = table.snapshot();
+            let second = table.snapshot();
+            assert!(first.keys().all(|key| second.contains_key(key)));
+
+            for writer in writers {
+                assert!(writer.join().unwrap());
+            }
+            assert_eq!(table.snapshot().len(), 2);
+        });
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn loom_smallest_hop_count_wins_in_any_order() {
+        loom::model(|| {
+            let table = Arc::new(RouteTable::default());
+            let writers: Vec<_> = [3, 1, 2]
+                .into_iter()
+                .map(|hop_count| {
+                    let table = table.clone();
+                    loom::thread::spawn(move || {
+                        table.update("region-a.cluster-a".to_string(), remote_nexthop(hop_count));
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            assert_eq!(table.get("region-a.cluster-a").unwrap()[0].hop_count(), 1);
+        });
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn loom_remove_during_update() {
+        loom::model(|| {
+            let table = Arc::new(RouteTable::default());
+            table.update("region-a.cluster-a".to_string(), remote_nexthop(2));
+
+            let remover = {
+                let table = table.clone();
+                loom::thread::spawn(move || table.remove("region-a.cluster-a"))
+            };
+            // the closer next hop always gets in, whether it replaces the route or re-adds it
+            assert!(table.update("region-a.cluster-a".to_string(), remote_nexthop(1)));
+            assert!(remover.join().unwrap().is_some());
+            if let Some(nexthops) = table.get("region-a.cluster-a") {
+                assert_eq!(nexthops[0].hop_count(), 1);
+            }
+        });
+    }
+}
@@ -0,0 +1,281 @@
+//! Outgoing message queue of a connection
+//!
+//! The queue has a high priority lane for infra messages and a low priority lane for data requests,
+//! each a bounded channel of the depth in [`SendQueueConfig`]. The receiving end, the stream of
+//! messages sent on the connection, takes from the high priority lane whenever it has a message, so
+//! pings, responses and the like are not stuck behind bulk data. A message for a full lane is
+//! refused or dropped as the [`DropPolicy`] says.
+use super::metrics;
+use futures_core::Stream;
+use sonic_common::metrics::{Counter, Gauge};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use swbus_config::{DropPolicy, SendQueueConfig};
+use swbus_proto::result::*;
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tonic::Status;
+
+type Message = std::result::Result<SwbusMessage, Status>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Priority {
+    High,
+    Low,
+}
+
+impl Priority {
+    /// Data requests are low priority unless flagged high priority, everything else is infra.
+    pub(crate) fn of(message: &SwbusMessage) -> Self {
+        match message.body {
+            Some(swbus_message::Body::DataRequest(_))
+                if message
+                    .header
+                    .as_ref()
+                    .is_none_or(|header| header.flag & SWBUS_FLAG_HIGH_PRIORITY == 0) =>
+            {
+                Priority::Low
+            }
+            _ => Priority::High,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Low => "low",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LaneTx {
+    tx: mpsc::Sender<Message>,
+    depth: Arc<Gauge>,
+    dropped: Arc<Counter>,
+}
+
+struct LaneRx {
+    rx: mpsc::Receiver<Message>,
+    depth: Arc<Gauge>,
+    closed: bool,
+}
+
+/// Create a send queue of the connection `conn_id`, which labels its metrics.
+pub(crate) fn channel(conn_id: &str, config: &SendQueueConfig) -> (SendQueueTx, SendQueueRx) {
+    let (high_tx, high_rx) = lane(conn_id, Priority::High, config.high_priority_depth);
+    let (low_tx, low_rx) = lane(conn_id, Priority::Low, config.low_priority_depth);
+    let tx = SendQueueTx {
+        high: high_tx,
+        low: low_tx,
+        drop_policy: config.drop_policy,
+    };
+    let rx = SendQueueRx {
+        conn_id: conn_id.to_string(),
+        high: high_rx,
+        low: low_rx,
+    };
+    (tx, rx)
+}
+
+fn lane(conn_id: &str, priority: Priority, depth: usize) -> (LaneTx, LaneRx) {
+    let (tx, rx) = mpsc::channel(depth.max(1));
+    let labels = [conn_id, priority.as_str()];
+    let depth = metrics::SEND_QUEUE_DEPTH.with_label_values(&labels);
+    depth.set(0);
+    let lane_tx = LaneTx {
+        tx,
+        depth: depth.clone(),
+        dropped: metrics::SEND_QUEUE_DROPPED.with_label_values(&labels),
+    };
+    let lane_rx = LaneRx {
+        rx,
+        depth,
+        closed: false,
+    };
+    (lane_tx, lane_rx)
+}
+
+/// The sending end of a send queue
+#[derive(Debug, Clone)]
+pub(crate) struct SendQueueTx {
+    high: LaneTx,
+    low: LaneTx,
+    drop_policy: DropPolicy,
+}
+
+impl SendQueueTx {
+    /// Queue `message` in the lane of its priority, without waiting for room.
+    pub(crate) fn try_send(&self, message: Message) -> Result<()> {
+        let priority = match &message {
+            Ok(message) => Priority::of(message),
+            Err(_) => Priority::High,
+        };
+        let lane = match priority {
+            Priority::High => &self.high,
+            Priority::Low => &self.low,
+        };
+
+        match lane.tx.try_send(message) {
+            Ok(()) => {
+                lane.depth.set((lane.tx.max_capacity() - lane.tx.capacity()) as i64);
+                Ok(())
+            }
+            Err(e @ TrySendError::Full(_)) => {
+                lane.dropped.inc();
+                match self.drop_policy {
+                    DropPolicy::Reject => Err(SwbusError::route(SwbusErrorCode::QueueFull, e.to_string())),
+                    DropPolicy::Drop => Ok(()),
+                }
+            }
+            Err(e @ TrySendError::Closed(_)) => Err(SwbusError::route(SwbusErrorCode::NoRoute, e.to_string())),
+        }
+    }
+}
+
+/// The receiving end of a send queue, a stream of messages in the order they are sent on the
+/// connection. It ends when all sending ends are dropped.
+pub(crate) struct SendQueueRx {
+    conn_id: String,
+    high: LaneRx,
+    low: LaneRx,
+}
+
+impl SendQueueRx {
+    pub(crate) async fn recv(&mut self) -> Option<Message> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub(crate) fn try_recv(&mut self) -> std::result::Result<Message, TryRecvError> {
+        self.high.try_recv().or_else(|_| self.low.try_recv())
+    }
+
+    /// Close both lanes, refusing messages from now on, while the queued ones can still be received.
+    pub(crate) fn close(&mut self) {
+        self.high.rx.close();
+        self.low.rx.close();
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        if let Poll::Ready(Some(message)) = self.high.poll_recv(cx) {
+            return Poll::Ready(Some(message));
+        }
+        if let Poll::Ready(Some(message)) = self.low.poll_recv(cx) {
+            return Poll::Ready(Some(message));
+        }
+        if self.high.closed && self.low.closed {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl Stream for SendQueueRx {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl Drop for SendQueueRx {
+    fn drop(&mut self) {
+        for priority in [Priority::High, Priority::Low] {
+            let labels = [self.conn_id.as_str(), priority.as_str()];
+            metrics::SEND_QUEUE_DEPTH.remove(&labels);
+            metrics::SEND_QUEUE_DROPPED.remove(&labels);
+        }
+    }
+}
+
+impl LaneRx {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        if self.closed {
+            return Poll::Ready(None);
+        }
+        let poll = self.rx.poll_recv(cx);
+        match &poll {
+            Poll::Ready(Some(_)) => self.depth.set(self.rx.len() as i64),
+            Poll::Ready(None) => self.closed = true,
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn try_recv(&mut self) -> std::result::Result<Message, TryRecvError> {
+        let message = self.rx.try_recv()?;
+        self.depth.set(self.rx.len() as i64);
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn message(id: u64, body: swbus_message::Body) -> SwbusMessage {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap();
+        SwbusMessage::new(SwbusMessageHeader::new(sp.clone(), sp, id), body)
+    }
+
+    fn data_request(id: u64) -> SwbusMessage {
+        message(id, swbus_message::Body::DataRequest(DataRequest::new(Vec::new())))
+    }
+
+    fn ping(id: u64) -> SwbusMessage {
+        message(id, swbus_message::Body::PingRequest(PingRequest::new()))
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(Priority::of(&data_request(1)), Priority::Low);
+        assert_eq!(Priority::of(&ping(1)), Priority::High);
+        let mut flagged = data_request(1);
+        flagged.header.as_mut().unwrap().flag |= SWBUS_FLAG_HIGH_PRIORITY;
+        assert_eq!(Priority::of(&flagged), Priority::High);
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_is_sent_first() {
+        let (tx, mut rx) = channel("test_high_priority_is_sent_first", &SendQueueConfig::default());
+        tx.try_send(Ok(data_request(1))).unwrap();
+        tx.try_send(Ok(data_request(2))).unwrap();
+        tx.try_send(Ok(ping(3))).unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(rx.recv().await.unwrap().unwrap().header.unwrap().id);
+        }
+        assert_eq!(ids, vec![3, 1, 2]);
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drop_policy() {
+        for (drop_policy, accepted) in [(DropPolicy::Reject, false), (DropPolicy::Drop, true)] {
+            let config = SendQueueConfig {
+                low_priority_depth: 1,
+                drop_policy,
+                ..Default::default()
+            };
+            let (tx, mut rx) = channel("test_drop_policy", &config);
+            tx.try_send(Ok(data_request(1))).unwrap();
+            let result = tx.try_send(Ok(data_request(2)));
+            assert_eq!(result.is_ok(), accepted);
+            if let Err(SwbusError::RouteError { code, .. }) = result {
+                assert_eq!(code, SwbusErrorCode::QueueFull);
+            }
+
+            // the full low priority lane doesn't hold up infra messages
+            tx.try_send(Ok(ping(3))).unwrap();
+            assert_eq!(rx.recv().await.unwrap().unwrap().header.unwrap().id, 3);
+            assert_eq!(rx.recv().await.unwrap().unwrap().header.unwrap().id, 1);
+            assert!(rx.try_recv().is_err());
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use super::auth::{ClientAuthenticator, ClientCredentials};
+use super::compression::{self, Compressor};
+use super::metrics;
 use super::SwbusConn;
 use super::SwbusMultiplexer;
 use crate::mux::conn_store::SwbusConnStore;
@@ -6,16 +9,13 @@ use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use swbus_config::SwbusConfig;
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_server::{SwbusService, SwbusServiceServer};
 use swbus_proto::swbus::*;
-use tokio::sync::{
-    mpsc,
-    oneshot::{self, Receiver, Sender},
-};
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_stream::Stream;
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio_stream::{Stream, StreamExt};
 use tonic::{transport::Server, Request, Response, Status, Streaming};
 use tracing::*;
 
@@ -27,6 +27,13 @@ pub struct SwbusServiceHost {
     shutdown_rx: Option<Receiver<()>>,
 }
 
+/// Applies config changes to a running [`SwbusServiceHost`].
+#[derive(Clone)]
+pub struct SwbusConfigHandle {
+    mux: Arc<SwbusMultiplexer>,
+    conn_store: Arc<SwbusConnStore>,
+}
+
 type SwbusMessageResult<T> = Result<Response<T>, Status>;
 type SwbusMessageStream = Pin<Box<dyn Stream<Item = Result<SwbusMessage, Status>> + Send>>;
 
@@ -44,10 +51,23 @@ impl SwbusServiceHost {
         }
     }
 
+    pub fn config_handle(&self) -> SwbusConfigHandle {
+        SwbusConfigHandle {
+            mux: self.mux.clone(),
+            conn_store: self.conn_store.clone(),
+        }
+    }
+
     pub fn take_shutdown_sender(&mut self) -> Option<Sender<()>> {
         self.shutdown_tx.take()
     }
 
+    /// Authenticate the clients with `authenticator` too, e.g. to check an identity derived from the
+    /// platform, see [`super::auth`].
+    pub fn add_client_authenticator(&self, authenticator: Arc<dyn ClientAuthenticator>) {
+        self.conn_store.add_client_authenticator(authenticator);
+    }
+
     pub async fn shutdown(&mut self) {
         if let Some(shutdown_tx) = self.shutdown_tx.take() {
             let _ = shutdown_tx.send(());
@@ -65,38 +85,174 @@ impl SwbusServiceHost {
             ));
         }
 
+        check_client_auth(&config)?;
+        self.conn_store.set_client_auth_config(config.client_auth.as_ref())?;
+
+        // peers are connected over TLS if it is set
+        #[cfg(feature = "tls")]
+        let tls = match config.tls {
+            Some(tls) => {
+                let tls = Arc::new(super::tls::SwbusTls::load(tls)?);
+                self.conn_store.set_tls(tls.clone());
+                self.mux.set_tls_conn_store(&self.conn_store);
+                Some(tls)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "tls"))]
+        if config.tls.is_some() {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                "TLS is configured, but swbus-core is built without the tls feature.".to_string(),
+            ));
+        }
+
         // register local nexthops for local services
+        self.mux.set_ecmp_hash(config.ecmp_hash);
+        self.mux.set_route_preferences(config.route_preferences.clone());
+        self.conn_store.set_send_queue_config(config.send_queue);
+        self.conn_store.set_compression_config(config.compression);
+        self.conn_store.set_rate_limit_config(config.rate_limit);
         self.mux.set_my_routes(config.routes.clone());
         for route in config.routes {
             self.conn_store.add_my_route(route);
         }
 
+        // announce the routes saved before the restart as stale until peers and clients connect again
+        let graceful_restart = config.graceful_restart;
+        if let Some(graceful_restart) = &graceful_restart {
+            match self.mux.restore_routes(&graceful_restart.state_file) {
+                Ok(count) => info!(
+                    "Restored {} stale routes from {}",
+                    count,
+                    graceful_restart.state_file.display()
+                ),
+                Err(e) => error!(
+                    "Failed to restore routes from {}: {}",
+                    graceful_restart.state_file.display(),
+                    e
+                ),
+            }
+            let mux = self.mux.clone();
+            let hold_time = Duration::from_secs(graceful_restart.hold_time_in_secs);
+            tokio::spawn(async move {
+                tokio::time::sleep(hold_time).await;
+                mux.withdraw_stale_routes();
+            });
+        }
+
         // add peers to the connection store
         for peer in config.peers {
             self.conn_store.add_peer(peer);
         }
 
+        let mux = self.mux.clone();
         let conn_store = self.conn_store.clone();
         let shutdown_rx = self.shutdown_rx.take().unwrap();
-        Server::builder()
-            .add_service(SwbusServiceServer::new(self))
-            .serve_with_shutdown(addr, async {
-                shutdown_rx.await.ok();
-                info!("SwbusServiceServer received shutdown signal");
-                conn_store.shutdown().await;
-            })
-            .await
-            .map_err(|e| {
-                SwbusError::connection(
-                    SwbusErrorCode::ConnectionError,
-                    io::Error::other(format!("Failed to listen at {addr}: {e}")),
-                )
-            })?;
+        let router = Server::builder().add_service(SwbusServiceServer::new(self));
+        let signal = async {
+            shutdown_rx.await.ok();
+            info!("SwbusServiceServer received shutdown signal");
+            // the routes are saved before the connections are torn down and their routes removed
+            if let Some(graceful_restart) = &graceful_restart {
+                if let Err(e) = mux.save_routes(&graceful_restart.state_file) {
+                    error!(
+                        "Failed to save routes to {}: {}",
+                        graceful_restart.state_file.display(),
+                        e
+                    );
+                }
+            }
+            conn_store.shutdown().await;
+        };
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = tls {
+            super::tls::serve(router, addr, tls, signal).await?;
+            debug!("SwbusServiceServer terminated");
+            return Ok(());
+        }
+
+        router.serve_with_shutdown(addr, signal).await.map_err(|e| {
+            SwbusError::connection(
+                SwbusErrorCode::ConnectionError,
+                io::Error::other(format!("Failed to listen at {addr}: {e}")),
+            )
+        })?;
         debug!("SwbusServiceServer terminated");
         Ok(())
     }
 }
 
+impl SwbusConfigHandle {
+    /// Change the routes and peers from `old`, the config the host runs with, to `new`. Peers are
+    /// reconnected when the routes change, since a client connection tells the peer my route when it
+    /// connects. A change of the endpoint is not applied, it requires restarting the host.
+    pub async fn reload(&self, old: &SwbusConfig, new: &SwbusConfig) -> Result<()> {
+        if new.routes.is_empty() {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                "No routes found in the configuration.".to_string(),
+            ));
+        }
+
+        // token files are read again, nothing is applied if one can't be
+        check_client_auth(new)?;
+        self.conn_store.set_client_auth_config(new.client_auth.as_ref())?;
+
+        let mut diff = old.diff(new);
+        if diff.endpoint_changed {
+            warn!(
+                "Endpoint changed from {} to {}, restart to listen at the new endpoint",
+                old.endpoint, new.endpoint
+            );
+        }
+
+        self.mux.set_ecmp_hash(new.ecmp_hash);
+        self.mux.set_route_preferences(new.route_preferences.clone());
+        self.conn_store.set_send_queue_config(new.send_queue);
+        self.conn_store.set_compression_config(new.compression);
+        self.conn_store.set_rate_limit_config(new.rate_limit);
+
+        // add routes before removing any, so there is always a route to connect peers from
+        self.mux.set_my_routes(diff.added_routes.clone());
+        for route in &diff.added_routes {
+            info!("Adding route {}", route.key.to_longest_path());
+            self.conn_store.add_my_route(route.clone());
+        }
+        for route in &diff.removed_routes {
+            info!("Removing route {}", route.key.to_longest_path());
+            self.mux.remove_my_route(route);
+            self.conn_store.remove_my_route(route);
+        }
+
+        if !diff.added_routes.is_empty() || !diff.removed_routes.is_empty() {
+            diff.removed_peers = old.peers.clone();
+            diff.added_peers = new.peers.clone();
+        }
+        for peer in &diff.removed_peers {
+            info!("Removing peer {} at {}", peer.id.to_longest_path(), peer.endpoint);
+            self.conn_store.remove_peer(peer).await;
+        }
+        for peer in diff.added_peers {
+            info!("Adding peer {} at {}", peer.id.to_longest_path(), peer.endpoint);
+            self.conn_store.add_peer(peer);
+        }
+        Ok(())
+    }
+}
+
+/// Fail if `config` authenticates clients without TLS, which would send their tokens in plaintext.
+fn check_client_auth(config: &SwbusConfig) -> Result<()> {
+    if config.client_auth.is_some() && config.tls.is_none() {
+        return Err(SwbusError::input(
+            SwbusErrorCode::InvalidArgs,
+            "client_auth is configured without tls, the tokens would be sent in plaintext.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 #[tonic::async_trait]
 impl SwbusService for SwbusServiceHost {
     type StreamMessagesStream = SwbusMessageStream;
@@ -139,21 +295,57 @@ impl SwbusService for SwbusServiceHost {
             }
         };
 
+        // connections are authenticated before a route is installed for them, whatever type they
+        // declare, so a client can't skip authentication by connecting as a peer
+        #[cfg(feature = "tls")]
+        let spiffe_id = request
+            .peer_certs()
+            .and_then(|certs| certs.first().and_then(|cert| super::tls::spiffe_id(cert.as_ref())));
+        #[cfg(not(feature = "tls"))]
+        let spiffe_id = None;
+        let credentials = ClientCredentials {
+            service_path: &service_path,
+            remote_addr: client_addr,
+            token: request
+                .metadata()
+                .get(SWBUS_AUTH_TOKEN)
+                .and_then(|token| token.to_str().ok()),
+            spiffe_id,
+            metadata: request.metadata(),
+        };
+        if let Err(e) = self.conn_store.authenticate_client(&credentials) {
+            warn!(
+                "Refusing {} connection {} from {}: {}",
+                conn_type,
+                service_path.to_longest_path(),
+                client_addr,
+                e
+            );
+            metrics::CLIENT_AUTH_FAILURES.inc();
+            return Err(Status::unauthenticated(e));
+        }
+
+        let compression = self
+            .conn_store
+            .compression_config()
+            .filter(|_| compression::negotiated(request.metadata()));
         let in_stream = request.into_inner();
         info!(
             conn_type = conn_type as i32,
             service_path = service_path.to_longest_path(),
             "Creating SwbusConn"
         );
-        // outgoing message queue
-        let (out_tx, out_rx) = mpsc::channel(16);
-
         let conn_info = Arc::new(SwbusConnInfo::new_server(conn_type, client_addr, service_path));
-        let conn =
-            SwbusConn::from_incoming_stream(conn_info, in_stream, out_tx, self.mux.clone(), self.conn_store.clone())
-                .await;
+        let compressor = compression.map(|config| Compressor::new(conn_info.id(), config));
+        let (conn, out_stream) =
+            SwbusConn::from_incoming_stream(conn_info, in_stream, self.mux.clone(), self.conn_store.clone()).await;
         self.conn_store.conn_established(conn);
-        let out_stream = ReceiverStream::new(out_rx);
-        Ok(Response::new(Box::pin(out_stream) as Self::StreamMessagesStream))
+        let Some(compressor) = compressor else {
+            return Ok(Response::new(Box::pin(out_stream) as Self::StreamMessagesStream));
+        };
+        let out_stream = out_stream.map(move |message| message.map(|message| compressor.compress(message)));
+        let mut response = Response::new(Box::pin(out_stream) as Self::StreamMessagesStream);
+        compression::accept(response.metadata_mut());
+        Ok(response)
     }
 }
@@ -0,0 +1,234 @@
+//! Mutual TLS between swbusd instances.
+//!
+//! Peers are connected with a client config that is built from the certificate files on every
+//! connection attempt. Incoming connections are accepted with a server config that is rebuilt when
+//! the files change, so renewed certificates are used without restarting swbusd. Connections that
+//! are already up keep the certificates they were set up with, until they are rotated with
+//! `MANAGEMENT_REQUEST_TYPE_SWBUSD_ROTATE_TLS`, which reconnects the peers one at a time.
+//!
+//! Clients connect over TLS too, with certificates of the same CA. The SPIFFE ID of the certificate of a
+//! client can authenticate it, see [`super::auth`].
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use swbus_config::TlsConfig;
+use swbus_proto::result::*;
+use swbus_proto::swbus::SwbusErrorCode;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Router;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+use tracing::*;
+use x509_parser::extensions::GeneralName;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Certificates of swbusd, reloaded when their files change.
+pub struct SwbusTls {
+    config: TlsConfig,
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl SwbusTls {
+    pub fn load(config: TlsConfig) -> Result<Self> {
+        let server_config = server_config(&config)?;
+        Ok(SwbusTls {
+            config,
+            server_config: RwLock::new(Arc::new(server_config)),
+        })
+    }
+
+    /// TLS config to connect to a peer with.
+    pub fn client_tls_config(&self) -> Result<ClientTlsConfig> {
+        let ca_cert = read(&self.config.ca_cert)?;
+        let identity = Identity::from_pem(read(&self.config.cert)?, read(&self.config.key)?);
+        let mut tls_config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca_cert))
+            .identity(identity);
+        if let Some(domain_name) = &self.config.domain_name {
+            tls_config = tls_config.domain_name(domain_name.clone());
+        }
+        Ok(tls_config)
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.server_config.read().unwrap().clone())
+    }
+
+    /// Rebuild the server config whenever the certificate files change.
+    async fn watch(&self) {
+        let mut modified = self.modified();
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+            let current = self.modified();
+            if current == modified {
+                continue;
+            }
+            modified = current;
+            if let Err(e) = self.reload() {
+                error!("Failed to reload TLS certificates, keeping the current ones: {}", e);
+            }
+        }
+    }
+
+    /// Rebuild the server config from the certificate files now. The current one is kept if they are
+    /// not valid.
+    pub fn reload(&self) -> Result<()> {
+        let server_config = server_config(&self.config)?;
+        *self.server_config.write().unwrap() = Arc::new(server_config);
+        info!("TLS certificates reloaded");
+        Ok(())
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [&self.config.ca_cert, &self.config.cert, &self.config.key]
+            .into_iter()
+            .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect()
+    }
+}
+
+/// Serve `router` at `addr` over TLS until `signal` completes. Clients must present a certificate
+/// signed by the CA.
+pub(crate) async fn serve(
+    router: Router,
+    addr: SocketAddr,
+    tls: Arc<SwbusTls>,
+    signal: impl Future<Output = ()>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| SwbusError::connection(SwbusErrorCode::ConnectionError, e))?;
+
+    let watch_task = tokio::spawn({
+        let tls = tls.clone();
+        async move { tls.watch().await }
+    });
+
+    // handshakes run in their own tasks, so a slow client doesn't hold up the others
+    let (incoming_tx, incoming_rx) = mpsc::channel(16);
+    let accept_task = tokio::spawn(async move {
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+            let acceptor = tls.acceptor();
+            let incoming_tx = incoming_tx.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let _ = incoming_tx.send(Ok::<_, io::Error>(stream)).await;
+                    }
+                    Err(e) => warn!("TLS handshake with {} failed: {}", remote_addr, e),
+                }
+            });
+        }
+    });
+
+    let res = router
+        .serve_with_incoming_shutdown(ReceiverStream::new(incoming_rx), signal)
+        .await;
+    accept_task.abort();
+    watch_task.abort();
+    res.map_err(|e| {
+        SwbusError::connection(
+            SwbusErrorCode::ConnectionError,
+            io::Error::other(format!("Failed to serve at {addr}: {e}")),
+        )
+    })
+}
+
+/// The SPIFFE ID in the URI SAN of the DER certificate `cert`, if it has one.
+pub(crate) fn spiffe_id(cert: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let san = cert.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::URI(uri) if uri.starts_with("spiffe://") => Some(uri.to_string()),
+        _ => None,
+    })
+}
+
+fn server_config(config: &TlsConfig) -> Result<ServerConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    for ca_cert in certs(&config.ca_cert)? {
+        roots.add(ca_cert).map_err(invalid_config)?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(invalid_config)?;
+
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_config)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs(&config.cert)?, private_key(&config.key)?)
+        .map_err(invalid_config)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(server_config)
+}
+
+fn invalid_config(e: impl std::fmt::Display) -> SwbusError {
+    SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Invalid TLS config: {e}"))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        SwbusError::input(
+            SwbusErrorCode::InvalidArgs,
+            format!("Failed to read {}: {e}", path.display()),
+        )
+    })
+}
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    rustls_pemfile::certs(&mut read(path)?.as_slice())
+        .collect::<Result<_, _>>()
+        .map_err(|e| invalid_config(format!("{}: {e}", path.display())))
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut read(path)?.as_slice())
+        .map_err(|e| invalid_config(format!("{}: {e}", path.display())))?
+        .ok_or_else(|| invalid_config(format!("no private key in {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_load_fails_without_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_cert = dir.path().join("ca.pem");
+        let mut file = std::fs::File::create(&ca_cert).unwrap();
+        file.write_all(b"not a certificate").unwrap();
+
+        let config = TlsConfig {
+            ca_cert,
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+            domain_name: None,
+        };
+        let Err(SwbusError::InputError { code, .. }) = SwbusTls::load(config) else {
+            panic!("expected an input error");
+        };
+        assert_eq!(code, SwbusErrorCode::InvalidArgs);
+    }
+}
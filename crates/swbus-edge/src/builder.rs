@@ -0,0 +1,118 @@
+use crate::core_client::{ConnectionConfig, ReconnectPolicy};
+use crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE;
+use crate::{RuntimeEnv, SwbusEdgeRuntime};
+use std::path::PathBuf;
+use swbus_proto::result::*;
+use swbus_proto::swbus::ServicePath;
+use tokio::time::Duration;
+
+/// Configures and starts a [`SwbusEdgeRuntime`].
+///
+/// ```ignore
+/// let runtime = SwbusEdgeRuntimeBuilder::new("http://127.0.0.1:23606", sp)
+///     .connect_timeout(Duration::from_secs(5))
+///     .runtime_env(Box::new(env))
+///     .build()
+///     .await?;
+/// ```
+pub struct SwbusEdgeRuntimeBuilder {
+    swbus_uri: String,
+    sp: ServicePath,
+    recv_queue_size: usize,
+    connection: ConnectionConfig,
+    runtime_env: Option<Box<dyn RuntimeEnv>>,
+    record_path: Option<PathBuf>,
+}
+
+impl SwbusEdgeRuntimeBuilder {
+    pub fn new(swbus_uri: impl Into<String>, sp: ServicePath) -> Self {
+        SwbusEdgeRuntimeBuilder {
+            swbus_uri: swbus_uri.into(),
+            sp,
+            recv_queue_size: SWBUS_RECV_QUEUE_SIZE,
+            connection: ConnectionConfig::default(),
+            runtime_env: None,
+            record_path: None,
+        }
+    }
+
+    /// Size of the queues of messages to route, from local handlers and from swbusd each.
+    pub fn recv_queue_size(mut self, size: usize) -> Self {
+        self.recv_queue_size = size;
+        self
+    }
+
+    /// Size of the queue of messages to send to swbusd.
+    pub fn send_queue_size(mut self, size: usize) -> Self {
+        self.connection.send_queue_size = size;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connection.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Ping swbusd every `interval` while connected, and reconnect if a ping is not answered within
+    /// `timeout`.
+    pub fn keep_alive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.connection.keep_alive_interval = Some(interval);
+        self.connection.keep_alive_timeout = timeout;
+        self
+    }
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.connection.reconnect = policy;
+        self
+    }
+
+    /// Hold up to `size` messages sent while swbusd is not connected, and send them once it is. Once the
+    /// buffer is full, sending fails with a NotConnected error.
+    pub fn outgoing_buffer(mut self, size: usize) -> Self {
+        self.connection.outgoing_buffer_size = size;
+        self
+    }
+
+    /// Present `token` to swbusd when connecting, for swbusd to authenticate the runtime with.
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.connection.auth_token = Some(token.into());
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: crate::core_client::TlsConfig) -> Self {
+        self.connection.tls = Some(tls);
+        self
+    }
+
+    /// Record every message sent and received by the clients of the runtime to `path`, see
+    /// [`SwbusEdgeRuntime::record`].
+    pub fn record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_path = Some(path.into());
+        self
+    }
+
+    pub fn runtime_env(mut self, runtime_env: Box<dyn RuntimeEnv>) -> Self {
+        self.runtime_env = Some(runtime_env);
+        self
+    }
+
+    /// Create the runtime without starting it.
+    pub fn create(self) -> SwbusEdgeRuntime {
+        let mut runtime = SwbusEdgeRuntime::with_config(self.swbus_uri, self.sp, self.recv_queue_size, self.connection);
+        if let Some(runtime_env) = self.runtime_env {
+            runtime.set_runtime_env(runtime_env);
+        }
+        if let Some(path) = self.record_path {
+            runtime.record(path);
+        }
+        runtime
+    }
+
+    /// Create and start the runtime.
+    pub async fn build(self) -> Result<SwbusEdgeRuntime> {
+        let mut runtime = self.create();
+        runtime.start().await?;
+        Ok(runtime)
+    }
+}
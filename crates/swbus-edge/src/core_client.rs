@@ -1,7 +1,8 @@
 use contracts::requires;
+use std::collections::VecDeque;
 use std::io;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_client::SwbusServiceClient;
 use swbus_proto::swbus::*;
@@ -15,12 +16,109 @@ use tonic::Request;
 use tonic::Streaming;
 use tracing::{debug, error, info};
 
+/// How the connection to swbusd is made and kept.
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    /// Messages queued for swbusd at most
+    pub send_queue_size: usize,
+    /// Give up a connection attempt after this long. Attempts are not bounded if not set.
+    pub connect_timeout: Option<Duration>,
+    /// Ping swbusd this often while connected, and drop the connection if a ping is not answered
+    /// within `keep_alive_timeout`
+    pub keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Duration,
+    pub reconnect: ReconnectPolicy,
+    /// Messages held while swbusd is not connected at most, and sent once it is. Sending fails while
+    /// swbusd is not connected if 0.
+    pub outgoing_buffer_size: usize,
+    /// Token presented to swbusd, when it authenticates its clients
+    pub auth_token: Option<String>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            send_queue_size: 100,
+            connect_timeout: None,
+            keep_alive_interval: None,
+            keep_alive_timeout: Duration::from_secs(20),
+            reconnect: ReconnectPolicy::default(),
+            outgoing_buffer_size: 0,
+            auth_token: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// How long to wait before connecting to swbusd again. The wait doubles after every failed attempt,
+/// up to `max_backoff`, and starts over once connected.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    fn backoff(&self, failures: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << failures.saturating_sub(1).min(16))
+            .min(self.max_backoff)
+    }
+}
+
+/// TLS settings of the connection to swbusd, all in PEM.
+#[cfg(feature = "tls")]
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// CA certificate the certificate of swbusd is verified with
+    pub ca_cert: Vec<u8>,
+    /// Client certificate and key, for mutual TLS
+    pub identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// Name the certificate of swbusd is verified against, instead of the host in the URI
+    pub domain_name: Option<String>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    /// Read the CA certificate, and the client certificate and key if any, from PEM files.
+    pub fn from_files(
+        ca_cert: &std::path::Path,
+        identity: Option<(&std::path::Path, &std::path::Path)>,
+        domain_name: Option<String>,
+    ) -> io::Result<Self> {
+        let identity = match identity {
+            Some((cert, key)) => Some((std::fs::read(cert)?, std::fs::read(key)?)),
+            None => None,
+        };
+        Ok(TlsConfig {
+            ca_cert: std::fs::read(ca_cert)?,
+            identity,
+            domain_name,
+        })
+    }
+}
+
 pub struct SwbusCoreClient {
     uri: String,
     sp: ServicePath,
+    config: ConnectionConfig,
 
     // tx queue to send messages to swbusd
     pub(crate) send_queue_tx: Arc<RwLock<Option<mpsc::Sender<SwbusMessage>>>>,
+    // messages sent while swbusd is not connected, see ConnectionConfig::outgoing_buffer_size
+    outgoing_buffer: Arc<Mutex<VecDeque<SwbusMessage>>>,
     // tx queue to send messages to message router
     message_processor_tx: mpsc::Sender<SwbusMessage>,
 
@@ -30,10 +128,21 @@ pub struct SwbusCoreClient {
 // Factory functions
 impl SwbusCoreClient {
     pub fn new(uri: String, sp: ServicePath, message_processor_tx: mpsc::Sender<SwbusMessage>) -> Self {
+        Self::with_config(uri, sp, message_processor_tx, ConnectionConfig::default())
+    }
+
+    pub fn with_config(
+        uri: String,
+        sp: ServicePath,
+        message_processor_tx: mpsc::Sender<SwbusMessage>,
+        config: ConnectionConfig,
+    ) -> Self {
         Self {
             uri,
             sp,
+            config,
             send_queue_tx: Arc::new(RwLock::new(None)),
+            outgoing_buffer: Arc::new(Mutex::new(VecDeque::new())),
             message_processor_tx,
             swbusd_connect_task: None,
         }
@@ -51,10 +160,18 @@ impl SwbusCoreClient {
         sp: ServicePath,
         receive_queue_tx: mpsc::Sender<SwbusMessage>,
     ) -> Result<(tokio::task::JoinHandle<Result<()>>, mpsc::Sender<SwbusMessage>)> {
-        let (send_queue_tx, send_queue_rx) = mpsc::channel::<SwbusMessage>(100);
+        Self::connect_with_config(uri, sp, receive_queue_tx, &ConnectionConfig::default()).await
+    }
 
-        let endpoint = Endpoint::from_str(&uri)
-            .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Failed to create endpoint: {e}.")))?;
+    pub async fn connect_with_config(
+        uri: String,
+        sp: ServicePath,
+        receive_queue_tx: mpsc::Sender<SwbusMessage>,
+        config: &ConnectionConfig,
+    ) -> Result<(tokio::task::JoinHandle<Result<()>>, mpsc::Sender<SwbusMessage>)> {
+        let (send_queue_tx, send_queue_rx) = mpsc::channel::<SwbusMessage>(config.send_queue_size);
+
+        let endpoint = Self::endpoint(&uri, config)?;
 
         let channel = match endpoint.connect().await {
             Ok(c) => c,
@@ -83,6 +200,12 @@ impl SwbusCoreClient {
             MetadataValue::from_str(ConnectionType::Local.as_str_name()).unwrap(),
         );
 
+        if let Some(token) = &config.auth_token {
+            let token = MetadataValue::from_str(token)
+                .map_err(|_| SwbusError::input(SwbusErrorCode::InvalidArgs, "Invalid auth token".to_string()))?;
+            meta.insert(SWBUS_AUTH_TOKEN, token);
+        }
+
         let recv_stream = match client.stream_messages(send_stream_request).await {
             Ok(response) => response.into_inner(),
             Err(e) => {
@@ -100,18 +223,54 @@ impl SwbusCoreClient {
         Ok((recv_stream_task, send_queue_tx))
     }
 
+    fn endpoint(uri: &str, config: &ConnectionConfig) -> Result<Endpoint> {
+        let mut endpoint = Endpoint::from_str(uri)
+            .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Failed to create endpoint: {e}.")))?;
+        if let Some(connect_timeout) = config.connect_timeout {
+            endpoint = endpoint.connect_timeout(connect_timeout);
+        }
+        if let Some(interval) = config.keep_alive_interval {
+            endpoint = endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_timeout(config.keep_alive_timeout);
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &config.tls {
+            use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+            let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&tls.ca_cert));
+            if let Some((cert, key)) = &tls.identity {
+                tls_config = tls_config.identity(Identity::from_pem(cert, key));
+            }
+            if let Some(domain_name) = &tls.domain_name {
+                tls_config = tls_config.domain_name(domain_name.clone());
+            }
+            endpoint = endpoint
+                .tls_config(tls_config)
+                .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidArgs, format!("Invalid TLS config: {e}.")))?;
+        }
+        Ok(endpoint)
+    }
+
     fn spawn_connect_task(&mut self) {
+        let config = self.config.clone();
         let uri = self.uri.clone();
         let sp = self.sp.clone();
         let message_processor_tx = self.message_processor_tx.clone();
         let send_queue_tx_arc = self.send_queue_tx.clone();
+        let outgoing_buffer = self.outgoing_buffer.clone();
 
         let handle = tokio::spawn(async move {
+            let mut failures = 0;
             loop {
-                match Self::connect(uri.clone(), sp.clone(), message_processor_tx.clone()).await {
+                match Self::connect_with_config(uri.clone(), sp.clone(), message_processor_tx.clone(), &config).await {
                     Ok((recv_stream_task, send_queue_tx)) => {
                         info!("Successfully connected to swbusd at {}", uri);
-                        send_queue_tx_arc.write().await.replace(send_queue_tx);
+                        failures = 0;
+                        // sends wait for the buffered messages to go out first, so that the order is kept
+                        let mut send_queue_tx_slot = send_queue_tx_arc.write().await;
+                        Self::replay_buffered_messages(&outgoing_buffer, &send_queue_tx).await;
+                        send_queue_tx_slot.replace(send_queue_tx);
+                        drop(send_queue_tx_slot);
                         // wait for the recv_stream_task to finish
                         let _ = recv_stream_task.await;
                         // clear the send_queue_tx and retry
@@ -120,7 +279,8 @@ impl SwbusCoreClient {
                     }
                     Err(e) => {
                         debug!("Failed to reconnect: {}.", e);
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        failures += 1;
+                        tokio::time::sleep(config.reconnect.backoff(failures)).await;
                     }
                 }
             }
@@ -133,15 +293,13 @@ impl SwbusCoreClient {
         self.spawn_connect_task();
     }
 
+    /// Send a message to swbusd. While swbusd is not connected, the message is buffered if the
+    /// outgoing buffer has room, and fails with [`io::ErrorKind::NotConnected`] if it is full.
     pub async fn send(&self, message: SwbusMessage) -> Result<()> {
         let tx = self.send_queue_tx.read().await;
-        if tx.is_none() {
-            return Err(SwbusError::connection(
-                SwbusErrorCode::ConnectionError,
-                io::Error::new(io::ErrorKind::ConnectionReset, "Not connected to swbusd"),
-            ));
-        }
-        let tx = tx.as_ref().unwrap();
+        let Some(tx) = tx.as_ref() else {
+            return self.buffer_message(message);
+        };
         match tx.send(message).await {
             Ok(_) => {}
             Err(e) => {
@@ -156,6 +314,44 @@ impl SwbusCoreClient {
         Ok(())
     }
 
+    fn buffer_message(&self, message: SwbusMessage) -> Result<()> {
+        if self.config.outgoing_buffer_size == 0 {
+            return Err(SwbusError::connection(
+                SwbusErrorCode::ConnectionError,
+                io::Error::new(io::ErrorKind::ConnectionReset, "Not connected to swbusd"),
+            ));
+        }
+        let mut buffer = self.outgoing_buffer.lock().unwrap();
+        if buffer.len() >= self.config.outgoing_buffer_size {
+            return Err(SwbusError::connection(
+                SwbusErrorCode::ConnectionError,
+                io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Not connected to swbusd and the outgoing buffer is full",
+                ),
+            ));
+        }
+        buffer.push_back(message);
+        Ok(())
+    }
+
+    /// Send the messages buffered while swbusd was not connected as they are, keeping their ids. The ones
+    /// that can't be sent stay buffered for the next connection.
+    async fn replay_buffered_messages(buffer: &Mutex<VecDeque<SwbusMessage>>, tx: &mpsc::Sender<SwbusMessage>) {
+        let mut messages = std::mem::take(&mut *buffer.lock().unwrap());
+        if !messages.is_empty() {
+            info!("Sending {} messages buffered while disconnected", messages.len());
+        }
+        while let Some(message) = messages.pop_front() {
+            if let Err(e) = tx.send(message).await {
+                error!("Failed to send buffered message: {}.", e);
+                messages.push_front(e.0);
+                *buffer.lock().unwrap() = messages;
+                return;
+            }
+        }
+    }
+
     async fn run_recv_stream_task(
         mut recv_stream: Streaming<SwbusMessage>,
         message_processor_tx: mpsc::Sender<SwbusMessage>,
@@ -196,3 +392,71 @@ impl SwbusCoreClient {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_max() {
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        let backoffs: Vec<_> = (1..=5).map(|failures| policy.backoff(failures).as_millis()).collect();
+        assert_eq!(backoffs, vec![100, 200, 400, 500, 500]);
+        assert_eq!(ReconnectPolicy::default().backoff(10), Duration::from_secs(1));
+    }
+
+    fn message(id: u64) -> SwbusMessage {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp, id),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        )
+    }
+
+    fn client(outgoing_buffer_size: usize) -> SwbusCoreClient {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        let (message_processor_tx, _) = mpsc::channel(1);
+        let config = ConnectionConfig {
+            outgoing_buffer_size,
+            ..Default::default()
+        };
+        SwbusCoreClient::with_config("http://127.0.0.1:8080".to_string(), sp, message_processor_tx, config)
+    }
+
+    fn error_kind(result: Result<()>) -> io::ErrorKind {
+        match result {
+            Err(SwbusError::ConnectionError { detail, .. }) => detail.kind(),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_while_disconnected_is_buffered_and_replayed() {
+        assert_eq!(
+            error_kind(client(0).send(message(1)).await),
+            io::ErrorKind::ConnectionReset
+        );
+
+        let client = client(2);
+        client.send(message(1)).await.unwrap();
+        client.send(message(2)).await.unwrap();
+        assert_eq!(error_kind(client.send(message(3)).await), io::ErrorKind::NotConnected);
+
+        // the buffered messages go out in order with their ids once connected
+        let (tx, mut rx) = mpsc::channel(2);
+        SwbusCoreClient::replay_buffered_messages(&client.outgoing_buffer, &tx).await;
+        for id in [1, 2] {
+            assert_eq!(rx.recv().await.unwrap().header.unwrap().id, id);
+        }
+        assert!(client.outgoing_buffer.lock().unwrap().is_empty());
+        client.send(message(3)).await.unwrap();
+
+        // a connection that is lost again leaves them buffered
+        drop(rx);
+        SwbusCoreClient::replay_buffered_messages(&client.outgoing_buffer, &tx).await;
+        assert_eq!(client.outgoing_buffer.lock().unwrap().len(), 1);
+    }
+}
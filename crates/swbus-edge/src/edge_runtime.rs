@@ -1,10 +1,15 @@
-use crate::core_client::SwbusCoreClient;
+use crate::builder::SwbusEdgeRuntimeBuilder;
+use crate::core_client::{ConnectionConfig, SwbusCoreClient};
+use crate::mailbox::MailboxTx;
 use crate::message_handler_proxy::SwbusMessageHandlerProxy;
 use crate::message_router::SwbusMessageRouter;
+use crate::route_changes::{RouteChangeEvent, RouteSubscription};
 use crate::RuntimeEnv;
 use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::OnceLock;
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc;
@@ -20,16 +25,31 @@ pub struct SwbusEdgeRuntime {
     sender_to_message_router: Sender<SwbusMessage>,
     //base service path with service type and service id
     base_sp: ServicePath,
-    runtime_env: RwLock<Option<Box<dyn RuntimeEnv>>>,
+    runtime_env: OnceLock<Box<dyn RuntimeEnv>>,
     tx_to_swbusd: Arc<AsyncRwLock<Option<mpsc::Sender<SwbusMessage>>>>,
+    next_route_subscription_id: AtomicU32,
 }
 
 impl SwbusEdgeRuntime {
+    /// Create a runtime with the default settings. See [`SwbusEdgeRuntimeBuilder`] for the others.
     pub fn new(swbus_uri: String, sp: ServicePath) -> Self {
-        let (local_msg_tx, local_msg_rx) = channel(SWBUS_RECV_QUEUE_SIZE);
-        let (remote_msg_tx, remote_msg_rx) = channel(SWBUS_RECV_QUEUE_SIZE);
+        SwbusEdgeRuntimeBuilder::new(swbus_uri, sp).create()
+    }
+
+    pub fn builder(swbus_uri: impl Into<String>, sp: ServicePath) -> SwbusEdgeRuntimeBuilder {
+        SwbusEdgeRuntimeBuilder::new(swbus_uri, sp)
+    }
+
+    pub(crate) fn with_config(
+        swbus_uri: String,
+        sp: ServicePath,
+        recv_queue_size: usize,
+        connection: ConnectionConfig,
+    ) -> Self {
+        let (local_msg_tx, local_msg_rx) = channel(recv_queue_size);
+        let (remote_msg_tx, remote_msg_rx) = channel(recv_queue_size);
         let base_sp = sp.clone();
-        let swbus_client = SwbusCoreClient::new(swbus_uri.clone(), sp, remote_msg_tx);
+        let swbus_client = SwbusCoreClient::with_config(swbus_uri.clone(), sp, remote_msg_tx, connection);
         let tx_to_swbusd = swbus_client.send_queue_tx.clone();
         let message_router = SwbusMessageRouter::new(swbus_client, local_msg_rx, remote_msg_rx);
 
@@ -38,8 +58,9 @@ impl SwbusEdgeRuntime {
             message_router,
             sender_to_message_router: local_msg_tx,
             base_sp,
-            runtime_env: RwLock::new(None),
+            runtime_env: OnceLock::new(),
             tx_to_swbusd,
+            next_route_subscription_id: AtomicU32::new(0),
         }
     }
 
@@ -48,6 +69,13 @@ impl SwbusEdgeRuntime {
         self.message_router.start().await
     }
 
+    /// Record every message sent and received by the clients of the runtime to a recording at `path`, see
+    /// [`swbus_proto::recording`]. `swbus-cli replay` replays it. Must be called before [`start`](Self::start),
+    /// which fails if the recording can't be created.
+    pub fn record(&mut self, path: impl Into<PathBuf>) {
+        self.message_router.record_to(path.into());
+    }
+
     pub fn new_sp(&self, resource_type: &str, resource_id: &str) -> ServicePath {
         let mut new_sp = self.base_sp.clone();
         new_sp.resource_type = resource_type.to_string();
@@ -73,7 +101,20 @@ impl SwbusEdgeRuntime {
         self.message_router.add_private_route(svc_path, proxy);
     }
 
-    pub async fn send(&self, message: SwbusMessage) -> Result<()> {
+    /// Add a handler receiving in a mailbox, see [`crate::mailbox`].
+    pub(crate) fn add_mailbox(&self, svc_path: ServicePath, mailbox_tx: MailboxTx, public: bool) {
+        let proxy = SwbusMessageHandlerProxy::new_mailbox(mailbox_tx);
+        if public {
+            info!("Added handler for service path: {}", svc_path.to_longest_path());
+            self.message_router.add_route(svc_path, proxy);
+        } else {
+            info!("Added private handler for service path: {}", svc_path.to_longest_path());
+            self.message_router.add_private_route(svc_path, proxy);
+        }
+    }
+
+    pub async fn send(&self, mut message: SwbusMessage) -> Result<()> {
+        crate::trace::stamp(&mut message);
         // Send message to the message router
         match self.sender_to_message_router.send(message).await {
             Ok(_) => Ok(()),
@@ -87,32 +128,60 @@ impl SwbusEdgeRuntime {
         }
     }
 
-    pub fn get_runtime_env(&self) -> std::sync::RwLockReadGuard<'_, Option<Box<dyn RuntimeEnv>>> {
-        self.runtime_env.read().unwrap()
+    pub fn get_runtime_env(&self) -> Option<&dyn RuntimeEnv> {
+        self.runtime_env.get().map(|env| env.as_ref())
+    }
+
+    /// The runtime environment, if it was set and is a `T`.
+    pub fn env<T: RuntimeEnv>(&self) -> Option<&T> {
+        self.get_runtime_env()?.as_any().downcast_ref::<T>()
     }
 
+    /// Set the runtime environment. It can only be set once, later calls are ignored.
     pub fn set_runtime_env(&mut self, runtime_env: Box<dyn RuntimeEnv>) {
-        let mut guard = self.runtime_env.write().unwrap();
-        if guard.is_none() {
-            *guard = Some(runtime_env);
-        }
+        _ = self.runtime_env.set(runtime_env);
     }
 
     pub async fn swbusd_connected(&self) -> bool {
         self.tx_to_swbusd.read().await.is_some()
     }
+
+    /// Subscribe to the routes added to and removed from swbusd, starting with the routes it has now.
+    /// Every route is removed when the connection to swbusd is lost, and added again once it is back.
+    /// The subscription ends when the receiver is dropped.
+    pub fn subscribe_route_changes(&self) -> mpsc::Receiver<RouteChangeEvent> {
+        let id = self.next_route_subscription_id.fetch_add(1, Ordering::Relaxed);
+        let sp = self.new_sp("route-changes", &id.to_string());
+        let (recv_queue_tx, recv_queue_rx) = channel(SWBUS_RECV_QUEUE_SIZE);
+        self.add_handler(sp.clone(), recv_queue_tx);
+
+        let (event_tx, event_rx) = channel(SWBUS_RECV_QUEUE_SIZE);
+        let subscription = RouteSubscription {
+            sp,
+            message_router_tx: self.sender_to_message_router.clone(),
+            tx_to_swbusd: self.tx_to_swbusd.clone(),
+            recv_queue_rx,
+            event_tx,
+        };
+        tokio::spawn(subscription.run());
+        event_rx
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::SwbusEdgeRuntime;
+    use crate::{RouteChangeEvent, SwbusEdgeRuntime};
     use rand::Rng;
     use serde_yaml;
     use sonic_common::log::init_logger_for_test;
+    use std::fs::File;
     use std::future::Future;
+    use std::io::BufReader;
     use std::sync::Arc;
-    use swbus_config::SwbusConfig;
+    use swbus_config::{ClientAuthConfig, ClientCredential, ClientCredentialConfig, SwbusConfig};
     use swbus_core::mux::service::SwbusServiceHost;
+    use swbus_proto::recording::{read_recording, Direction};
+    use swbus_proto::result::SwbusError;
     use swbus_proto::swbus::*;
     use tokio::sync::mpsc::{self, Receiver, Sender};
     use tokio::sync::oneshot;
@@ -211,6 +280,33 @@ mod tests {
         shut_hdl.send(()).expect("Failed to send shutdown signal");
     }
 
+    #[tokio::test]
+    async fn test_client_auth_needs_tls() {
+        init_logger_for_test();
+
+        let token_file = std::env::temp_dir().join(format!("swbus-edge-auth-{}.token", std::process::id()));
+        std::fs::write(&token_file, "secret\n").unwrap();
+        let mut swbus_config = make_swbusd_config();
+        let mut sp = swbus_config.routes[0].key.clone();
+        sp.service_type = "swbus-edge".to_string();
+        sp.service_id = "test".to_string();
+        swbus_config.client_auth = Some(ClientAuthConfig {
+            clients: vec![ClientCredentialConfig {
+                credential: ClientCredential::TokenFile(token_file.clone()),
+                service_paths: vec![sp.to_longest_path()],
+            }],
+            management_service_paths: Vec::new(),
+        });
+
+        // the tokens would be sent in plaintext
+        let service_host = SwbusServiceHost::new(&swbus_config.endpoint);
+        let Err(SwbusError::InputError { code, .. }) = service_host.start(swbus_config).await else {
+            panic!("swbusd started with client_auth and without tls");
+        };
+        assert_eq!(code, SwbusErrorCode::InvalidArgs);
+        std::fs::remove_file(token_file).unwrap();
+    }
+
     #[tokio::test]
     async fn test_routing_to_handler() {
         // enable trace logging if ENABLE_TRACE env is set
@@ -265,4 +361,130 @@ mod tests {
             assert_eq!(recv_msg.header.unwrap().destination.unwrap(), sp);
         }
     }
+
+    #[tokio::test]
+    async fn test_record() {
+        let swbus_config = make_swbusd_config();
+        let mut sp = swbus_config.routes[0].key.clone();
+        sp.service_type = "swbus-edge".to_string();
+        sp.service_id = "test".to_string();
+        let path = std::env::temp_dir().join(format!("swbus-edge-record-{}.jsonl", std::process::id()));
+        let mut runtime = SwbusEdgeRuntime::builder(format!("http://{}", swbus_config.endpoint), sp.clone())
+            .record(&path)
+            .create();
+        runtime.start().await.unwrap();
+
+        let (handler_sp, mut rx, tx) = make_a_handler(&format!("{}/r/1", sp.to_longest_path()));
+        runtime.add_handler(handler_sp.clone(), tx);
+        let msg = SwbusMessage::new(
+            SwbusMessageHeader::new(sp, handler_sp.clone(), 1),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        );
+        runtime.send(msg).await.unwrap();
+        timeout(Duration::from_secs(1), rx.recv()).await.unwrap().unwrap();
+
+        let recording = read_recording(BufReader::new(File::open(&path).unwrap())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.len(), 1);
+        assert_eq!(recording[0].direction, Some(Direction::Sent));
+        let header = recording[0].message.header.as_ref().unwrap();
+        assert_eq!((header.id, header.destination.as_ref()), (1, Some(&handler_sp)));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_route_changes() {
+        init_logger_for_test();
+        let swbus_config = make_swbusd_config();
+        let shut_hdl = start_standalone_swbusd(swbus_config.clone());
+
+        let mut sp = swbus_config.routes[0].key.clone();
+        sp.service_type = "swbus-edge".to_string();
+        sp.service_id = "test".to_string();
+        let mut runtime = SwbusEdgeRuntime::new(format!("http://{}", swbus_config.endpoint), sp.clone());
+        runtime.start().await.unwrap();
+        let mut route_changes = runtime.subscribe_route_changes();
+
+        // the route to the runtime itself is there from the start
+        let event = timeout(Duration::from_secs(10), route_changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            RouteChangeEvent::RouteAdded(ServicePath::from_string(&sp.to_service_prefix()).unwrap())
+        );
+
+        let mut peer_sp = sp.clone();
+        peer_sp.service_id = "peer".to_string();
+        let mut peer = SwbusEdgeRuntime::new(format!("http://{}", swbus_config.endpoint), peer_sp.clone());
+        peer.start().await.unwrap();
+        let event = timeout(Duration::from_secs(10), route_changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            RouteChangeEvent::RouteAdded(ServicePath::from_string(&peer_sp.to_service_prefix()).unwrap())
+        );
+
+        // every route is gone with swbusd
+        shut_hdl.send(()).expect("Failed to send shutdown signal");
+        let mut removed = Vec::new();
+        for _ in 0..2 {
+            match timeout(Duration::from_secs(10), route_changes.recv())
+                .await
+                .unwrap()
+                .unwrap()
+            {
+                RouteChangeEvent::RouteRemoved(route) => removed.push(route.to_longest_path()),
+                event => panic!("Unexpected route change {event:?}"),
+            }
+        }
+        removed.sort();
+        assert_eq!(removed, vec![peer_sp.to_service_prefix(), sp.to_service_prefix()]);
+    }
+
+    struct TestEnv(u32);
+
+    impl crate::RuntimeEnv for TestEnv {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_starts_configured_runtime() {
+        init_logger_for_test();
+        let swbus_config = make_swbusd_config();
+        let mut sp = swbus_config.routes[0].key.clone();
+        sp.service_type = "swbus-edge".to_string();
+        sp.service_id = "test".to_string();
+
+        // swbusd comes up after the runtime, which keeps retrying quickly
+        let runtime = SwbusEdgeRuntime::builder(format!("http://{}", swbus_config.endpoint), sp)
+            .recv_queue_size(64)
+            .send_queue_size(16)
+            .connect_timeout(Duration::from_secs(1))
+            .keep_alive(Duration::from_secs(5), Duration::from_secs(5))
+            .reconnect(crate::core_client::ReconnectPolicy {
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(100),
+            })
+            .runtime_env(Box::new(TestEnv(7)))
+            .build()
+            .await
+            .unwrap();
+        let runtime = Arc::new(runtime);
+        assert_eq!(runtime.env::<TestEnv>().unwrap().0, 7);
+
+        let shut_hdl = start_standalone_swbusd(swbus_config.clone());
+        wait_runtime_until(runtime.clone(), |x| async move { x.swbusd_connected().await }, 10)
+            .await
+            .expect("swbusd is not connected");
+        shut_hdl.send(()).expect("Failed to send shutdown signal");
+    }
 }
@@ -1,10 +1,21 @@
+pub mod builder;
 pub mod core_client;
 pub mod edge_runtime;
+pub mod mailbox;
 mod message_handler_proxy;
 mod message_router;
+mod metrics;
+mod recorder;
+pub mod reliable;
+pub mod route_changes;
+pub mod rpc;
 pub mod simple_client;
+pub mod stream;
+pub mod trace;
 
+pub use builder::SwbusEdgeRuntimeBuilder;
 pub use edge_runtime::SwbusEdgeRuntime;
+pub use route_changes::RouteChangeEvent;
 
 use std::any::Any;
 pub use swbus_proto;
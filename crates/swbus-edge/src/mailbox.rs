@@ -0,0 +1,273 @@
+//! Bounded receive queues of clients, see [`SimpleSwbusEdgeClient::with_mailbox`](crate::simple_client::SimpleSwbusEdgeClient::with_mailbox).
+use sonic_common::metrics::Counter;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use swbus_proto::swbus::SwbusMessage;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// What a mailbox does with a message received while it is full.
+///
+/// [`Block`](Self::Block) holds up every other client of the edge runtime while one mailbox is full.
+/// [`Reject`](Self::Reject) doesn't: the router answers a refused request with
+/// [`SwbusErrorCode::QueueFull`](swbus_proto::swbus::SwbusErrorCode::QueueFull), and consumer bridges
+/// and actors resend it later. The drop policies lose messages for good: nothing tells the sender, so
+/// nothing resends them. They are only safe for traffic that is idempotent and periodic, where the next
+/// message supersedes a dropped one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Wait for the client to take a message, holding up the messages to the other clients meanwhile.
+    #[default]
+    Block,
+    /// Refuse the message, so that the sender of a request gets a QueueFull error response.
+    Reject,
+    /// Drop the message.
+    DropNew,
+    /// Drop the oldest message in the mailbox to make room.
+    DropOldest,
+}
+
+/// Size and overflow policy of the receive queue of a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MailboxConfig {
+    /// Messages the mailbox holds
+    pub size: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        MailboxConfig {
+            size: crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE,
+            overflow: OverflowPolicy::Block,
+        }
+    }
+}
+
+struct Queue {
+    messages: VecDeque<SwbusMessage>,
+    /// Whether a message was dropped since the mailbox last had room, so that a storm is logged once
+    overflowing: bool,
+    tx_closed: bool,
+    rx_closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    config: MailboxConfig,
+    owner: String,
+    overflows: Arc<Counter>,
+    received: Notify,
+    taken: Notify,
+}
+
+/// Create a mailbox for the client at `owner`, counting the messages that overflow it in `overflows`.
+pub(crate) fn channel(config: MailboxConfig, owner: String, overflows: Arc<Counter>) -> (MailboxTx, MailboxRx) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            overflowing: false,
+            tx_closed: false,
+            rx_closed: false,
+        }),
+        config: MailboxConfig {
+            size: config.size.max(1),
+            ..config
+        },
+        owner,
+        overflows,
+        received: Notify::new(),
+        taken: Notify::new(),
+    });
+    (MailboxTx { shared: shared.clone() }, MailboxRx { shared })
+}
+
+pub(crate) struct MailboxTx {
+    shared: Arc<Shared>,
+}
+
+/// Why a mailbox didn't take a message
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SendError {
+    /// The mailbox is full and rejects the messages it has no room for
+    Full,
+    /// The mailbox is no longer read
+    Closed,
+}
+
+impl MailboxTx {
+    /// Put `message` in the mailbox, as its overflow policy says if it is full. Fails if the mailbox is
+    /// no longer read, or rejected the message.
+    pub(crate) async fn send(&self, message: SwbusMessage) -> Result<(), SendError> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                if queue.rx_closed {
+                    return Err(SendError::Closed);
+                }
+                if queue.messages.len() < shared.config.size {
+                    queue.messages.push_back(message);
+                    queue.overflowing = false;
+                    shared.received.notify_one();
+                    return Ok(());
+                }
+                match shared.config.overflow {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::Reject => {
+                        self.overflowed(&mut queue);
+                        return Err(SendError::Full);
+                    }
+                    OverflowPolicy::DropNew => {
+                        self.overflowed(&mut queue);
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropOldest => {
+                        queue.messages.pop_front();
+                        queue.messages.push_back(message);
+                        self.overflowed(&mut queue);
+                        shared.received.notify_one();
+                        return Ok(());
+                    }
+                }
+            }
+            // notify_one keeps a permit, so a message taken before this waits isn't missed
+            shared.taken.notified().await;
+        }
+    }
+
+    fn overflowed(&self, queue: &mut Queue) {
+        let shared = &self.shared;
+        shared.overflows.inc();
+        if !queue.overflowing {
+            queue.overflowing = true;
+            warn!(
+                "Mailbox of {} is full with {} messages, {} ones",
+                shared.owner,
+                shared.config.size,
+                match shared.config.overflow {
+                    OverflowPolicy::Reject => "rejecting the new",
+                    OverflowPolicy::DropOldest => "dropping the oldest",
+                    _ => "dropping the new",
+                }
+            );
+        }
+    }
+}
+
+impl Drop for MailboxTx {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().tx_closed = true;
+        self.shared.received.notify_one();
+    }
+}
+
+pub(crate) struct MailboxRx {
+    shared: Arc<Shared>,
+}
+
+impl MailboxRx {
+    /// Take the oldest message. Returns `None` once the mailbox is empty and no longer written to.
+    pub(crate) async fn recv(&mut self) -> Option<SwbusMessage> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                if let Some(message) = queue.messages.pop_front() {
+                    shared.taken.notify_one();
+                    return Some(message);
+                }
+                if queue.tx_closed {
+                    return None;
+                }
+            }
+            shared.received.notified().await;
+        }
+    }
+
+    /// Messages in the mailbox
+    pub(crate) fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().messages.len()
+    }
+}
+
+impl Drop for MailboxRx {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().rx_closed = true;
+        self.shared.taken.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::{swbus_message::Body, DataRequest, ServicePath, SwbusMessageHeader};
+    use tokio::time::{timeout, Duration};
+
+    fn message(id: u64) -> SwbusMessage {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/test/0").unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp, id),
+            Body::DataRequest(DataRequest::default()),
+        )
+    }
+
+    fn id(message: Option<SwbusMessage>) -> u64 {
+        message.unwrap().header.unwrap().id
+    }
+
+    fn mailbox(overflow: OverflowPolicy) -> (MailboxTx, MailboxRx, Arc<Counter>) {
+        let overflows = Arc::new(Counter::default());
+        let (tx, rx) = channel(
+            MailboxConfig { size: 2, overflow },
+            "test".to_string(),
+            overflows.clone(),
+        );
+        (tx, rx, overflows)
+    }
+
+    #[tokio::test]
+    async fn overflow_drops_as_the_policy_says() {
+        let (tx, mut rx, overflows) = mailbox(OverflowPolicy::DropNew);
+        for i in 1..=3 {
+            tx.send(message(i)).await.unwrap();
+        }
+        assert_eq!((id(rx.recv().await), id(rx.recv().await)), (1, 2));
+        assert_eq!(overflows.get(), 1);
+
+        let (tx, mut rx, overflows) = mailbox(OverflowPolicy::DropOldest);
+        for i in 1..=3 {
+            tx.send(message(i)).await.unwrap();
+        }
+        assert_eq!((id(rx.recv().await), id(rx.recv().await)), (2, 3));
+        assert_eq!(overflows.get(), 1);
+        assert_eq!(rx.len(), 0);
+
+        let (tx, mut rx, overflows) = mailbox(OverflowPolicy::Reject);
+        tx.send(message(1)).await.unwrap();
+        tx.send(message(2)).await.unwrap();
+        assert_eq!(tx.send(message(3)).await, Err(SendError::Full));
+        assert_eq!(id(rx.recv().await), 1);
+        tx.send(message(4)).await.unwrap();
+        assert_eq!((id(rx.recv().await), id(rx.recv().await)), (2, 4));
+        assert_eq!(overflows.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn overflow_blocks_until_a_message_is_taken() {
+        let (tx, mut rx, overflows) = mailbox(OverflowPolicy::Block);
+        tx.send(message(1)).await.unwrap();
+        tx.send(message(2)).await.unwrap();
+        assert!(timeout(Duration::from_millis(50), tx.send(message(3))).await.is_err());
+
+        let sender = tokio::spawn(async move { tx.send(message(3)).await.is_ok() });
+        assert_eq!(id(rx.recv().await), 1);
+        assert!(sender.await.unwrap());
+        assert_eq!((id(rx.recv().await), id(rx.recv().await)), (2, 3));
+        assert_eq!(overflows.get(), 0);
+
+        // the sender is gone
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -1,23 +1,54 @@
 use std::io;
+use std::sync::Arc;
 
+use crate::mailbox::{MailboxTx, SendError};
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc::Sender;
 
 #[derive(Clone)]
 pub struct SwbusMessageHandlerProxy {
-    tx: Sender<SwbusMessage>,
+    tx: HandlerTx,
+}
+
+#[derive(Clone)]
+enum HandlerTx {
+    Channel(Sender<SwbusMessage>),
+    Mailbox(Arc<MailboxTx>),
 }
 
 impl SwbusMessageHandlerProxy {
     pub fn new(tx: Sender<SwbusMessage>) -> Self {
-        Self { tx }
+        Self {
+            tx: HandlerTx::Channel(tx),
+        }
+    }
+
+    pub(crate) fn new_mailbox(tx: MailboxTx) -> Self {
+        Self {
+            tx: HandlerTx::Mailbox(Arc::new(tx)),
+        }
     }
 
+    /// Hand `message` to the handler. Fails with [`SwbusErrorCode::QueueFull`] if its mailbox rejected the
+    /// message, see [`OverflowPolicy::Reject`](crate::mailbox::OverflowPolicy::Reject).
     pub async fn send(&self, message: SwbusMessage) -> Result<()> {
-        match self.tx.send(message).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(SwbusError::connection(
+        let broken = match &self.tx {
+            HandlerTx::Channel(tx) => tx.send(message).await.err().map(|e| e.to_string()),
+            HandlerTx::Mailbox(tx) => match tx.send(message).await {
+                Ok(()) => None,
+                Err(SendError::Full) => {
+                    return Err(SwbusError::route(
+                        SwbusErrorCode::QueueFull,
+                        "Mailbox of the handler is full".to_string(),
+                    ))
+                }
+                Err(SendError::Closed) => Some("mailbox is closed".to_string()),
+            },
+        };
+        match broken {
+            None => Ok(()),
+            Some(e) => Err(SwbusError::connection(
                 SwbusErrorCode::ConnectionError,
                 io::Error::new(
                     io::ErrorKind::ConnectionAborted,
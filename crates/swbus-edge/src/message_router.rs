@@ -1,14 +1,23 @@
+mod circuit_breaker;
 mod route_map;
 
 use crate::core_client::SwbusCoreClient;
 use crate::message_handler_proxy::SwbusMessageHandlerProxy;
+use crate::metrics;
+use crate::recorder::Recorder;
+use circuit_breaker::CircuitBreaker;
 use route_map::RouteMap;
+use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::recording::Direction;
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc::Receiver;
 use tokio::task;
-use tracing::error;
+use tokio::time::Instant;
+use tracing::{debug, error};
 
 /// How private a route is.
 ///
@@ -30,6 +39,7 @@ pub struct SwbusMessageRouter {
     swbus_client: Option<SwbusCoreClient>,
     local_msg_rx: Option<Receiver<SwbusMessage>>,
     remote_msg_rx: Option<Receiver<SwbusMessage>>,
+    record_path: Option<PathBuf>,
 }
 
 impl SwbusMessageRouter {
@@ -44,15 +54,32 @@ impl SwbusMessageRouter {
             swbus_client: Some(swbus_client),
             local_msg_rx: Some(local_msg_rx),
             remote_msg_rx: Some(remote_msg_rx),
+            record_path: None,
         }
     }
 
+    /// Record the messages routed from now on to `path`, see [`Recorder`].
+    pub fn record_to(&mut self, path: PathBuf) {
+        self.record_path = Some(path);
+    }
+
     pub async fn start(&mut self) -> Result<()> {
         let routes = self.routes.clone();
         let mut local_msg_rx = self.local_msg_rx.take().unwrap();
         let mut remote_msg_rx = self.remote_msg_rx.take().unwrap();
         let mut swbus_client = self.swbus_client.take().unwrap();
         swbus_client.start();
+        let mut circuit_breaker = CircuitBreaker::default();
+        let id_generator = MessageIdGenerator::new();
+        let mut recorder = match &self.record_path {
+            Some(path) => Some(Recorder::create(path).map_err(|e| {
+                SwbusError::internal(
+                    SwbusErrorCode::Fail,
+                    format!("Failed to create recording {}: {e}", path.display()),
+                )
+            })?),
+            None => None,
+        };
 
         let swbusd_route_task = task::spawn(async move {
             loop {
@@ -60,8 +87,27 @@ impl SwbusMessageRouter {
                     msg = local_msg_rx.recv() => (msg.unwrap(), Privacy::Private),
                     msg = remote_msg_rx.recv() => (msg.unwrap(), Privacy::Public),
                 };
+                if let Some(recorder) = &mut recorder {
+                    // messages from the local clients are the ones they send, the others are from swbusd
+                    let direction = match privacy {
+                        Privacy::Private => Direction::Sent,
+                        Privacy::Public => Direction::Received,
+                    };
+                    recorder.record(direction, &msg);
+                }
 
-                Self::route_message(&mut swbus_client, &routes, msg, privacy).await;
+                if privacy == Privacy::Public {
+                    circuit_breaker.on_received(&msg, Instant::now());
+                }
+                Self::route_message(
+                    &mut swbus_client,
+                    &routes,
+                    &mut circuit_breaker,
+                    &id_generator,
+                    msg,
+                    privacy,
+                )
+                .await;
             }
         });
         self.route_task = Some(swbusd_route_task);
@@ -80,6 +126,8 @@ impl SwbusMessageRouter {
     async fn route_message(
         swbus_client: &mut SwbusCoreClient,
         routes: &RouteMap,
+        circuit_breaker: &mut CircuitBreaker,
+        id_generator: &MessageIdGenerator,
         message: SwbusMessage,
         privacy: Privacy,
     ) {
@@ -93,38 +141,140 @@ impl SwbusMessageRouter {
             return;
         };
 
-        // Try the full route/address
-        if try_route(routes, destination, privacy, &message).await {
-            return;
+        match route_locally(routes, destination, privacy, &message).await {
+            Some(Err(SwbusError::RouteError {
+                code: SwbusErrorCode::QueueFull,
+                detail,
+            })) => {
+                Self::reject(swbus_client, routes, id_generator, &message, &detail).await;
+                return;
+            }
+            Some(_) => return,
+            None => {}
         }
 
-        // Try stripping the resource id
-        let mut partial_dest = destination.clone();
-        partial_dest.resource_id.clear();
-        if try_route(routes, &partial_dest, privacy, &message).await {
+        // Fail fast instead of sending out to a destination that keeps failing
+        if !circuit_breaker.allow(&message, Instant::now()) {
+            debug!(
+                "Circuit to {} is open, rejecting message",
+                destination.to_longest_path()
+            );
+            let response = SwbusMessage::new_response(
+                &message,
+                None,
+                SwbusErrorCode::CircuitOpen,
+                "Destination keeps failing",
+                id_generator.generate(),
+                None,
+            );
+            metrics::router_dropped("circuit_open");
+            let source = response.header.as_ref().unwrap().destination.as_ref().unwrap();
+            if route_locally(routes, source, Privacy::Private, &response)
+                .await
+                .is_none()
+            {
+                debug!("No local route to {}, dropping response", source.to_longest_path());
+            }
             return;
         }
 
-        // Try stripping the resource type
-        partial_dest.resource_type.clear();
-        if try_route(routes, &partial_dest, privacy, &message).await {
-            return;
+        // Give up at this point and send out to swbus
+        match swbus_client.send(message).await {
+            Ok(()) => {}
+            Err(SwbusError::ConnectionError { detail, .. }) if detail.kind() == io::ErrorKind::NotConnected => {
+                error!("Failed to send message to swbusd: {detail}");
+                metrics::router_dropped("outgoing_buffer_full");
+            }
+            Err(e) => {
+                error!("Failed to send message to swbusd: {e}");
+                metrics::router_dropped("swbusd_unreachable");
+            }
         }
+    }
 
-        // Give up at this point and send out to swbus
-        if let Err(e) = swbus_client.send(message).await {
-            error!("Failed to send message to swbusd: {e}");
+    /// Answer a request that the mailbox of its destination rejected with QueueFull, so that the sender
+    /// resends it later. Rejected responses are dropped, nobody resends them.
+    async fn reject(
+        swbus_client: &mut SwbusCoreClient,
+        routes: &RouteMap,
+        id_generator: &MessageIdGenerator,
+        message: &SwbusMessage,
+        detail: &str,
+    ) {
+        if matches!(message.body, Some(swbus_message::Body::Response(_)) | None) {
+            return;
+        }
+        let response = SwbusMessage::new_response(
+            message,
+            None,
+            SwbusErrorCode::QueueFull,
+            detail,
+            id_generator.generate(),
+            None,
+        );
+        let source = response.header.as_ref().unwrap().destination.as_ref().unwrap();
+        if route_locally(routes, source, Privacy::Private, &response)
+            .await
+            .is_some()
+        {
+            return;
+        }
+        if let Err(e) = swbus_client.send(response).await {
+            debug!("Failed to send QueueFull response to swbusd: {e}");
         }
     }
 }
 
-async fn try_route(routes: &RouteMap, destination: &ServicePath, privacy: Privacy, message: &SwbusMessage) -> bool {
-    if let Some(handler) = routes.get(destination, privacy) {
-        if let Err(e) = handler.send(message.clone()).await {
+/// Route the message to a local handler, trying the full destination first, then with the resource
+/// id and the resource type stripped. Returns `None` if there is no local handler, else how handing
+/// the message to it went.
+async fn route_locally(
+    routes: &RouteMap,
+    destination: &ServicePath,
+    privacy: Privacy,
+    message: &SwbusMessage,
+) -> Option<Result<()>> {
+    // Try the full route/address
+    if let Some(result) = try_route(routes, destination, privacy, message).await {
+        return Some(result);
+    }
+
+    // Try stripping the resource id
+    let mut partial_dest = destination.clone();
+    partial_dest.resource_id.clear();
+    if let Some(result) = try_route(routes, &partial_dest, privacy, message).await {
+        return Some(result);
+    }
+
+    // Try stripping the resource type
+    partial_dest.resource_type.clear();
+    try_route(routes, &partial_dest, privacy, message).await
+}
+
+async fn try_route(
+    routes: &RouteMap,
+    destination: &ServicePath,
+    privacy: Privacy,
+    message: &SwbusMessage,
+) -> Option<Result<()>> {
+    let handler = routes.get(destination, privacy)?;
+    let result = handler.send(message.clone()).await;
+    match &result {
+        Ok(()) => {}
+        Err(SwbusError::RouteError {
+            code: SwbusErrorCode::QueueFull,
+            ..
+        }) => {
+            debug!(
+                "Mailbox of {} is full, rejecting message",
+                destination.to_longest_path()
+            );
+            metrics::router_dropped("mailbox_full");
+        }
+        Err(e) => {
             error!("Failed to send message to local handler: {e}");
+            metrics::router_dropped("handler_failed");
         }
-        true
-    } else {
-        false
     }
+    Some(result)
 }
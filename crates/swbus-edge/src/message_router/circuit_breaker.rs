@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use swbus_proto::swbus::*;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Consecutive routing failures after which the circuit of a destination opens
+pub(super) const FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open circuit fails messages locally before letting one through as a probe
+pub(super) const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Requests waiting for a response. Past this, the oldest ones are forgotten.
+const MAX_PENDING_REQUESTS: usize = 10000;
+
+#[derive(Default)]
+struct Circuit {
+    failures: u32,
+    // Set while the circuit is open, to when it opened or when the last probe was let through
+    open_since: Option<Instant>,
+}
+
+/// Stops sending to destinations that keep failing, so that retries don't pile up on swbusd while
+/// a peer is down.
+///
+/// The requests sent to swbusd are tracked until their response comes back. After
+/// `failure_threshold` NoRoute or Unreachable responses in a row from the same destination, its
+/// circuit opens and requests to it are failed locally with `CircuitOpen`. One request every
+/// `probe_interval` is still let through as a probe, and the first response that isn't a routing
+/// failure closes the circuit again.
+pub(super) struct CircuitBreaker {
+    failure_threshold: u32,
+    probe_interval: Duration,
+    circuits: HashMap<ServicePath, Circuit>,
+    pending: HashMap<u64, ServicePath>,
+    pending_order: VecDeque<u64>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(FAILURE_THRESHOLD, PROBE_INTERVAL)
+    }
+}
+
+impl CircuitBreaker {
+    pub(super) fn new(failure_threshold: u32, probe_interval: Duration) -> Self {
+        Self {
+            failure_threshold,
+            probe_interval,
+            circuits: HashMap::new(),
+            pending: HashMap::new(),
+            pending_order: VecDeque::new(),
+        }
+    }
+
+    /// Whether `message` can be sent out to swbusd.
+    pub(super) fn allow(&mut self, message: &SwbusMessage, now: Instant) -> bool {
+        // Responses get no response back, so there is nothing to learn from them
+        if matches!(message.body, Some(swbus_message::Body::Response(_))) {
+            return true;
+        }
+        let Some(header) = &message.header else {
+            return true;
+        };
+        let Some(destination) = &header.destination else {
+            return true;
+        };
+
+        if let Some(circuit) = self.circuits.get_mut(destination) {
+            if let Some(open_since) = circuit.open_since {
+                if now < open_since + self.probe_interval {
+                    return false;
+                }
+                circuit.open_since = Some(now);
+            }
+        }
+
+        self.pending.insert(header.id, destination.clone());
+        self.pending_order.push_back(header.id);
+        if self.pending_order.len() > MAX_PENDING_REQUESTS {
+            let oldest = self.pending_order.pop_front().unwrap();
+            self.pending.remove(&oldest);
+        }
+        true
+    }
+
+    /// Account for a message received from swbusd.
+    pub(super) fn on_received(&mut self, message: &SwbusMessage, now: Instant) {
+        let Some(swbus_message::Body::Response(response)) = &message.body else {
+            return;
+        };
+        let Some(destination) = self.pending.remove(&response.request_id) else {
+            return;
+        };
+
+        let routing_failure = response.error_code == SwbusErrorCode::NoRoute as i32
+            || response.error_code == SwbusErrorCode::Unreachable as i32;
+        if routing_failure {
+            let circuit = self.circuits.entry(destination.clone()).or_default();
+            circuit.failures += 1;
+            if circuit.failures >= self.failure_threshold && circuit.open_since.is_none() {
+                warn!(
+                    "Opening circuit to {} after {} routing failures",
+                    destination.to_longest_path(),
+                    circuit.failures
+                );
+                circuit.open_since = Some(now);
+            }
+        } else if let Some(circuit) = self.circuits.remove(&destination) {
+            if circuit.open_since.is_some() {
+                info!("Closing circuit to {}", destination.to_longest_path());
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn is_open(&self, destination: &ServicePath) -> bool {
+        self.circuits
+            .get(destination)
+            .is_some_and(|circuit| circuit.open_since.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, destination: &ServicePath) -> SwbusMessage {
+        let source = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/test/0").unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(source, destination.clone(), id),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        )
+    }
+
+    fn response(request_id: u64, error_code: SwbusErrorCode) -> SwbusMessage {
+        let source = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap();
+        let destination = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/test/0").unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(source, destination, 0),
+            swbus_message::Body::Response(RequestResponse::infra_error(request_id, error_code, "")),
+        )
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_routing_failures() {
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/test/0").unwrap();
+        let other = ServicePath::from_string("region-a.cluster-a.10.0.0.3-dpu0/hamgrd/0/test/0").unwrap();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(5));
+        let now = Instant::now();
+
+        // A success in between resets the count
+        for (id, error_code) in [
+            (1, SwbusErrorCode::NoRoute),
+            (2, SwbusErrorCode::Unreachable),
+            (3, SwbusErrorCode::Ok),
+            (4, SwbusErrorCode::NoRoute),
+            (5, SwbusErrorCode::NoRoute),
+        ] {
+            assert!(breaker.allow(&request(id, &peer), now));
+            breaker.on_received(&response(id, error_code), now);
+        }
+        assert!(!breaker.is_open(&peer));
+
+        assert!(breaker.allow(&request(6, &peer), now));
+        breaker.on_received(&response(6, SwbusErrorCode::Unreachable), now);
+        assert!(breaker.is_open(&peer));
+        assert!(!breaker.allow(&request(7, &peer), now));
+        assert!(breaker.allow(&request(8, &other), now));
+
+        // Responses to unknown requests are ignored
+        breaker.on_received(&response(100, SwbusErrorCode::Ok), now);
+        assert!(breaker.is_open(&peer));
+    }
+
+    #[test]
+    fn open_circuit_probes_until_destination_recovers() {
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/test/0").unwrap();
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(5));
+        let start = Instant::now();
+
+        assert!(breaker.allow(&request(1, &peer), start));
+        breaker.on_received(&response(1, SwbusErrorCode::NoRoute), start);
+        assert!(!breaker.allow(&request(2, &peer), start + Duration::from_secs(4)));
+
+        // The probe fails, so the circuit stays open for another interval
+        let probe = start + Duration::from_secs(5);
+        assert!(breaker.allow(&request(3, &peer), probe));
+        assert!(!breaker.allow(&request(4, &peer), probe + Duration::from_secs(1)));
+        breaker.on_received(&response(3, SwbusErrorCode::NoRoute), probe);
+        assert!(!breaker.allow(&request(5, &peer), probe + Duration::from_secs(4)));
+
+        let probe = probe + Duration::from_secs(5);
+        assert!(breaker.allow(&request(6, &peer), probe));
+        breaker.on_received(&response(6, SwbusErrorCode::Ok), probe);
+        assert!(!breaker.is_open(&peer));
+        assert!(breaker.allow(&request(7, &peer), probe));
+    }
+
+    #[test]
+    fn responses_are_never_blocked() {
+        let peer = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/test/0").unwrap();
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(5));
+        let now = Instant::now();
+
+        assert!(breaker.allow(&request(1, &peer), now));
+        breaker.on_received(&response(1, SwbusErrorCode::NoRoute), now);
+
+        let mut reply = response(2, SwbusErrorCode::Ok);
+        reply.header.as_mut().unwrap().destination = Some(peer.clone());
+        assert!(breaker.allow(&reply, now));
+    }
+}
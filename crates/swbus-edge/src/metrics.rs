@@ -0,0 +1,92 @@
+//! Metrics of the edge runtime and its clients, see [`sonic_common::metrics`].
+use sonic_common::metrics::{Counter, CounterVec, Gauge, GaugeVec};
+use std::sync::{Arc, LazyLock};
+use swbus_proto::swbus::ServicePath;
+
+static MESSAGES_SENT: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_edge_messages_sent_total",
+        "Messages sent by a swbus client, by its service path.",
+        &["service_path"],
+    )
+});
+
+static MESSAGES_RECEIVED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_edge_messages_received_total",
+        "Messages received by a swbus client, by its service path.",
+        &["service_path"],
+    )
+});
+
+static MESSAGES_DROPPED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_edge_messages_dropped_total",
+        "Invalid or unsupported messages dropped by a swbus client, by its service path.",
+        &["service_path"],
+    )
+});
+
+static QUEUE_DEPTH: LazyLock<Arc<GaugeVec>> = LazyLock::new(|| {
+    GaugeVec::register(
+        "swbus_edge_queue_depth",
+        "Messages waiting in the receive queue of a swbus client, e.g. the mailbox of an actor, by its service path.",
+        &["service_path"],
+    )
+});
+
+static MAILBOX_OVERFLOWS: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_edge_mailbox_overflows_total",
+        "Messages dropped or rejected because the mailbox of a swbus client was full, by its service path.",
+        &["service_path"],
+    )
+});
+
+static ROUTER_DROPPED: LazyLock<Arc<CounterVec>> = LazyLock::new(|| {
+    CounterVec::register(
+        "swbus_edge_router_dropped_total",
+        "Messages the edge runtime could not deliver, by reason.",
+        &["reason"],
+    )
+});
+
+pub(crate) fn router_dropped(reason: &str) {
+    ROUTER_DROPPED.with_label_values(&[reason]).inc();
+}
+
+/// Metrics of one client. They are no longer reported once it is dropped.
+pub(crate) struct ClientMetrics {
+    service_path: String,
+    pub(crate) sent: Arc<Counter>,
+    pub(crate) received: Arc<Counter>,
+    pub(crate) dropped: Arc<Counter>,
+    pub(crate) queue_depth: Arc<Gauge>,
+    pub(crate) overflows: Arc<Counter>,
+}
+
+impl ClientMetrics {
+    pub(crate) fn new(source: &ServicePath) -> Self {
+        let service_path = source.to_longest_path();
+        let labels = [service_path.as_str()];
+        ClientMetrics {
+            sent: MESSAGES_SENT.with_label_values(&labels),
+            received: MESSAGES_RECEIVED.with_label_values(&labels),
+            dropped: MESSAGES_DROPPED.with_label_values(&labels),
+            queue_depth: QUEUE_DEPTH.with_label_values(&labels),
+            overflows: MAILBOX_OVERFLOWS.with_label_values(&labels),
+            service_path,
+        }
+    }
+}
+
+impl Drop for ClientMetrics {
+    fn drop(&mut self) {
+        let labels = [self.service_path.as_str()];
+        MESSAGES_SENT.remove(&labels);
+        MESSAGES_RECEIVED.remove(&labels);
+        MESSAGES_DROPPED.remove(&labels);
+        QUEUE_DEPTH.remove(&labels);
+        MAILBOX_OVERFLOWS.remove(&labels);
+    }
+}
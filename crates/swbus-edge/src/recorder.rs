@@ -0,0 +1,36 @@
+//! Recording of the messages sent and received by the clients of an edge runtime, see
+//! [`SwbusEdgeRuntime::record`](crate::SwbusEdgeRuntime::record).
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use swbus_proto::recording::{write_message, Direction, RecordedMessage};
+use swbus_proto::swbus::SwbusMessage;
+use tokio::time::Instant;
+use tracing::error;
+
+pub(crate) struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    /// Append `message` to the recording. It is flushed right away, so that the recording of a process
+    /// that crashes is complete.
+    pub(crate) fn record(&mut self, direction: Direction, message: &SwbusMessage) {
+        let recorded = RecordedMessage {
+            time_ms: self.start.elapsed().as_millis() as u64,
+            direction: Some(direction),
+            message: message.clone(),
+        };
+        if let Err(e) = write_message(&mut self.writer, &recorded).and_then(|_| self.writer.flush()) {
+            error!("Failed to record message: {e}");
+        }
+    }
+}
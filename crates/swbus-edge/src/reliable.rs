@@ -0,0 +1,202 @@
+//! Reliable delivery of requests, see [`SimpleSwbusEdgeClient::new_reliable`](crate::simple_client::SimpleSwbusEdgeClient::new_reliable).
+use crate::simple_client::MessageId;
+use crate::SwbusEdgeRuntime;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{swbus_message::Body, ServicePath, SwbusErrorCode, SwbusMessage};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration, Instant};
+use tracing::debug;
+
+/// How requests are resent and retried requests are recognized.
+#[derive(Clone, Debug)]
+pub struct ReliableDelivery {
+    /// Time to wait for the response to each attempt
+    pub timeout: Duration,
+    /// Attempts after the first one. Requests are retried if they time out or fail to be routed.
+    pub retries: u32,
+    /// Wait before the first retry. The wait doubles after every retry.
+    pub backoff: Duration,
+    /// How long received requests are remembered, to answer their retries without handling them again
+    pub dedup_window: Duration,
+}
+
+impl Default for ReliableDelivery {
+    fn default() -> Self {
+        ReliableDelivery {
+            timeout: Duration::from_secs(1),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            dedup_window: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReliableDelivery {
+    fn backoff(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retry.min(16))
+    }
+}
+
+/// What to do with a received message.
+pub(crate) enum Filtered {
+    Pass,
+    Drop,
+    /// The message is a retried request that was answered already, send the answer again
+    Resend(SwbusMessage),
+}
+
+enum Received {
+    Handling { at: Instant },
+    Answered { at: Instant, response: SwbusMessage },
+}
+
+impl Received {
+    fn at(&self) -> Instant {
+        match self {
+            Received::Handling { at } | Received::Answered { at, .. } => *at,
+        }
+    }
+}
+
+/// Requests waiting for their response, and requests received recently.
+pub(crate) struct Reliability {
+    config: ReliableDelivery,
+    pending: Mutex<HashMap<MessageId, oneshot::Sender<(SwbusErrorCode, String)>>>,
+    received: Mutex<HashMap<(ServicePath, MessageId), Received>>,
+}
+
+impl Reliability {
+    pub(crate) fn new(config: ReliableDelivery) -> Self {
+        Reliability {
+            config,
+            pending: Mutex::new(HashMap::new()),
+            received: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send `msg` with id `id`. Requests are resent with the same id until they are answered with Ok,
+    /// and the error of the last attempt is returned if they are not.
+    pub(crate) async fn send(&self, rt: &SwbusEdgeRuntime, id: MessageId, msg: SwbusMessage) -> Result<()> {
+        match &msg.body {
+            Some(Body::DataRequest(_)) => {}
+            Some(Body::Response(response)) => {
+                if let Some(destination) = msg.header.as_ref().and_then(|header| header.destination.as_ref()) {
+                    self.answered(destination, response.request_id, &msg);
+                }
+                return rt.send(msg).await;
+            }
+            _ => return rt.send(msg).await,
+        }
+
+        let mut retry = 0;
+        loop {
+            let response_rx = self.expect_response(id);
+            let res = match rt.send(msg.clone()).await {
+                Ok(()) => self.wait_for_response(id, response_rx).await,
+                Err(e) => Err(e),
+            };
+            self.pending.lock().unwrap().remove(&id);
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) if retry < self.config.retries && is_retriable(&e) => {
+                    debug!("Retrying request {id}: {e}");
+                    tokio::time::sleep(self.config.backoff(retry)).await;
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn expect_response(&self, id: MessageId) -> oneshot::Receiver<(SwbusErrorCode, String)> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, response_tx);
+        response_rx
+    }
+
+    async fn wait_for_response(
+        &self,
+        id: MessageId,
+        response_rx: oneshot::Receiver<(SwbusErrorCode, String)>,
+    ) -> Result<()> {
+        match timeout(self.config.timeout, response_rx).await {
+            Ok(Ok((SwbusErrorCode::Ok, _))) => Ok(()),
+            Ok(Ok((error_code, error_message))) => Err(response_error(error_code, error_message)),
+            _ => Err(SwbusError::connection(
+                SwbusErrorCode::Timeout,
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("No response to request {id} within {:?}", self.config.timeout),
+                ),
+            )),
+        }
+    }
+
+    /// Remember that `msg` answers the request `request_id` from `destination`.
+    fn answered(&self, destination: &ServicePath, request_id: MessageId, msg: &SwbusMessage) {
+        let mut received = self.received.lock().unwrap();
+        if let Some(entry) = received.get_mut(&(destination.clone(), request_id)) {
+            *entry = Received::Answered {
+                at: entry.at(),
+                response: msg.clone(),
+            };
+        }
+    }
+
+    /// Take the responses to pending requests, and drop retried requests.
+    pub(crate) fn filter_received(&self, msg: &SwbusMessage) -> Filtered {
+        let (Some(header), Some(body)) = (&msg.header, &msg.body) else {
+            return Filtered::Pass;
+        };
+        match body {
+            Body::Response(response) => match self.pending.lock().unwrap().remove(&response.request_id) {
+                Some(response_tx) => {
+                    let error_code =
+                        SwbusErrorCode::try_from(response.error_code).unwrap_or(SwbusErrorCode::UnknownError);
+                    let _ = response_tx.send((error_code, response.error_message.clone()));
+                    Filtered::Drop
+                }
+                None => Filtered::Pass,
+            },
+            Body::DataRequest(_) => {
+                let Some(source) = &header.source else {
+                    return Filtered::Pass;
+                };
+                let now = Instant::now();
+                let mut received = self.received.lock().unwrap();
+                received.retain(|_, entry| now.duration_since(entry.at()) < self.config.dedup_window);
+                match received.get(&(source.clone(), header.id)) {
+                    Some(Received::Handling { .. }) => Filtered::Drop,
+                    Some(Received::Answered { response, .. }) => Filtered::Resend(response.clone()),
+                    None => {
+                        received.insert((source.clone(), header.id), Received::Handling { at: now });
+                        Filtered::Pass
+                    }
+                }
+            }
+            _ => Filtered::Pass,
+        }
+    }
+}
+
+fn is_retriable(e: &SwbusError) -> bool {
+    matches!(e, SwbusError::ConnectionError { .. } | SwbusError::RouteError { .. })
+}
+
+/// The error of a response with `error_code`.
+pub(crate) fn response_error(error_code: SwbusErrorCode, error_message: String) -> SwbusError {
+    if error_code > SwbusErrorCode::ConnectionErrorMin && error_code < SwbusErrorCode::ConnectionErrorMax {
+        SwbusError::connection(error_code, io::Error::other(error_message))
+    } else if error_code > SwbusErrorCode::InputErrorMin && error_code < SwbusErrorCode::InputErrorMax {
+        SwbusError::input(error_code, error_message)
+    } else if error_code > SwbusErrorCode::RouteErrorMin && error_code < SwbusErrorCode::RouteErrorMax {
+        SwbusError::route(error_code, error_message)
+    } else if error_code > SwbusErrorCode::InternalErrorMin && error_code < SwbusErrorCode::InternalErrorMax {
+        SwbusError::internal(error_code, error_message)
+    } else {
+        SwbusError::internal(SwbusErrorCode::Fail, format!("{error_code:?}: {error_message}"))
+    }
+}
@@ -0,0 +1,184 @@
+//! Route changes of swbusd, see
+//! [`SwbusEdgeRuntime::subscribe_route_changes`](crate::SwbusEdgeRuntime::subscribe_route_changes).
+use prost::Message;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc;
+use tokio::sync::RwLock as AsyncRwLock;
+use tokio::time::{self, Duration};
+use tracing::{debug, info, warn};
+
+/// How often the connection to swbusd is checked, to subscribe again after it is reestablished
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A route added to or removed from swbusd
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteChangeEvent {
+    RouteAdded(ServicePath),
+    RouteRemoved(ServicePath),
+}
+
+impl RouteChangeEvent {
+    pub fn route(&self) -> &ServicePath {
+        match self {
+            RouteChangeEvent::RouteAdded(route) | RouteChangeEvent::RouteRemoved(route) => route,
+        }
+    }
+}
+
+/// Whether messages to `destination` can be routed over `route`.
+pub fn route_leads_to(route: &ServicePath, destination: &ServicePath) -> bool {
+    let route = route.to_longest_path();
+    [
+        destination.to_service_prefix(),
+        destination.to_node_prefix(),
+        destination.to_cluster_prefix(),
+        destination.to_regional_prefix(),
+    ]
+    .contains(&route)
+}
+
+/// The event of a route change notified by swbusd, if it changes the known `routes`. Notifications can
+/// repeat what is known already, e.g. after subscribing again.
+fn route_change_event(routes: &mut BTreeSet<ServicePath>, data: &DataRequest) -> Option<RouteChangeEvent> {
+    let change = match RouteChange::decode(&data.payload[..]) {
+        Ok(change) => change,
+        Err(e) => {
+            warn!("Failed to decode route change: {}", e);
+            return None;
+        }
+    };
+    let route = change.service_path.clone()?;
+    match change.change_type() {
+        RouteChangeType::Added if routes.insert(route.clone()) => Some(RouteChangeEvent::RouteAdded(route)),
+        RouteChangeType::Removed if routes.remove(&route) => Some(RouteChangeEvent::RouteRemoved(route)),
+        _ => None,
+    }
+}
+
+pub(crate) struct RouteSubscription {
+    pub(crate) sp: ServicePath,
+    pub(crate) message_router_tx: mpsc::Sender<SwbusMessage>,
+    pub(crate) tx_to_swbusd: Arc<AsyncRwLock<Option<mpsc::Sender<SwbusMessage>>>>,
+    pub(crate) recv_queue_rx: mpsc::Receiver<SwbusMessage>,
+    pub(crate) event_tx: mpsc::Sender<RouteChangeEvent>,
+}
+
+impl RouteSubscription {
+    /// Subscribe to swbusd whenever it is (re)connected, and turn its notifications into events until the
+    /// receiver of the events is dropped.
+    pub(crate) async fn run(mut self) {
+        let id_generator = MessageIdGenerator::new();
+        let mut routes = BTreeSet::new();
+        // The connection to swbusd the subscription was made over
+        let mut subscribed_over: Option<mpsc::Sender<SwbusMessage>> = None;
+        let mut interval = time::interval(RESUBSCRIBE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = self.event_tx.closed() => break,
+                _ = interval.tick() => {
+                    let connection = self.tx_to_swbusd.read().await.clone();
+                    let same_connection = match (&connection, &subscribed_over) {
+                        (Some(connection), Some(subscribed_over)) => connection.same_channel(subscribed_over),
+                        _ => false,
+                    };
+                    if same_connection {
+                        continue;
+                    }
+                    // Whatever was reachable over the previous connection may be gone now
+                    for route in std::mem::take(&mut routes) {
+                        if !self.emit(RouteChangeEvent::RouteRemoved(route)).await {
+                            return;
+                        }
+                    }
+                    subscribed_over = None;
+                    if connection.is_some() && self.request(&id_generator, false).await {
+                        subscribed_over = connection;
+                    }
+                }
+                message = self.recv_queue_rx.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    match message.body {
+                        Some(swbus_message::Body::Response(response)) => {
+                            if response.error_code != SwbusErrorCode::Ok as i32 {
+                                warn!("Failed to subscribe to route changes: {}", response.error_message);
+                                subscribed_over = None;
+                            }
+                        }
+                        Some(swbus_message::Body::DataRequest(data)) => {
+                            let Some(event) = route_change_event(&mut routes, &data) else {
+                                continue;
+                            };
+                            if !self.emit(event).await {
+                                break;
+                            }
+                        }
+                        _ => debug!("Ignoring unexpected message to route subscription: {:?}", message.body),
+                    }
+                }
+            }
+        }
+
+        info!("Route subscription {} ended", self.sp.to_longest_path());
+        self.request(&id_generator, true).await;
+    }
+
+    async fn emit(&self, event: RouteChangeEvent) -> bool {
+        debug!("Route change: {:?}", event);
+        self.event_tx.send(event).await.is_ok()
+    }
+
+    /// Send a request to subscribe, or to unsubscribe if `stop`. Returns whether it was sent.
+    async fn request(&self, id_generator: &MessageIdGenerator, stop: bool) -> bool {
+        let mut mgmt_request = ManagementRequest::new(ManagementRequestType::SwbusdSubscribeRoutes);
+        if stop {
+            mgmt_request.arguments.push(ManagementRequestArg {
+                name: "stop".to_string(),
+                value: String::new(),
+            });
+        }
+        let header = SwbusMessageHeader::new(
+            self.sp.clone(),
+            self.sp.to_swbusd_service_path(),
+            id_generator.generate(),
+        );
+        let message = SwbusMessage::new(header, swbus_message::Body::ManagementRequest(mgmt_request));
+        self.message_router_tx.send(message).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_leads_to() {
+        let destination = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/ha-set/0").unwrap();
+        for route in [
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            "region-a.cluster-a.10.0.0.2-dpu0",
+            "region-a.cluster-a",
+            "region-a",
+        ] {
+            assert!(
+                route_leads_to(&ServicePath::from_string(route).unwrap(), &destination),
+                "{route}"
+            );
+        }
+        for route in [
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            "region-a.cluster-b",
+            "region-a.cluster-a.10.0.0.2-dpu0/swbusd/0",
+        ] {
+            assert!(
+                !route_leads_to(&ServicePath::from_string(route).unwrap(), &destination),
+                "{route}"
+            );
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Typed request/response calls between swbus clients.
+//!
+//! A method is a type implementing [`RpcMethod`], which names it and gives its request and response
+//! types. An [`RpcServer`] at a service path serves the methods registered with
+//! [`register`](RpcServer::register), and an [`RpcClient`] calls them with [`call`](RpcClient::call):
+//!
+//! ```ignore
+//! struct GetHaState;
+//! impl RpcMethod for GetHaState {
+//!     const NAME: &'static str = "get_ha_state";
+//!     type Request = String;
+//!     type Response = HaState;
+//! }
+//!
+//! let mut server = RpcServer::new(rt.clone(), rt.new_sp("ha-set", "rpc"));
+//! server.register::<GetHaState, _, _>(|_caller, ha_set_id| async move { Ok(lookup(&ha_set_id)) });
+//! tokio::spawn(server.run());
+//!
+//! let client = RpcClient::new(rt.clone(), rt.new_sp("ha-scope", "rpc"));
+//! let state = client.call::<GetHaState>(&server_sp, &"haset0".to_string(), Duration::from_secs(1)).await?;
+//! ```
+//!
+//! A call is an [`RpcRequest`] of the method name and the request, serialized in JSON, sent as the
+//! payload of a data request. It is answered with a response carrying the serialized response as an
+//! [`RpcResult`], or the error of the method. Calls that are not answered within their timeout fail
+//! with [`SwbusErrorCode::Timeout`].
+//!
+//! [`call_all`](RpcClient::call_all) calls the servers at every node of a wildcard node id at once, like
+//! `region.cluster.*/hamgrd/0/rpc/0`. swbusd copies the call to each node and reports which ones, and the
+//! responses are gathered by node.
+use crate::reliable::response_error;
+use crate::simple_client::MessageId;
+use crate::SwbusEdgeRuntime;
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{
+    request_response::ResponseBody, swbus_message::Body, DataRequest, RpcRequest, RpcResult, ServicePath,
+    SwbusErrorCode, SwbusMessage, SwbusMessageHeader, SWBUS_FLAG_FAN_OUT_REPORT,
+};
+use tokio::sync::mpsc::{self, channel, Receiver};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, timeout_at, Duration, Instant};
+use tracing::{debug, error};
+
+const RPC_QUEUE_SIZE: usize = 100;
+
+/// A method that can be called over swbus.
+pub trait RpcMethod {
+    /// The name the method is registered and called by
+    const NAME: &'static str;
+    type Request: Serialize + DeserializeOwned + Send;
+    type Response: Serialize + DeserializeOwned + Send;
+}
+
+type Handling = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send>>;
+type BoxedHandler = Arc<dyn Fn(ServicePath, &[u8]) -> Handling + Send + Sync>;
+
+/// Serves the methods registered with it at a service path.
+pub struct RpcServer {
+    rt: Arc<SwbusEdgeRuntime>,
+    sp: ServicePath,
+    handler_rx: Receiver<SwbusMessage>,
+    methods: HashMap<&'static str, BoxedHandler>,
+    id_generator: Arc<MessageIdGenerator>,
+}
+
+impl RpcServer {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, sp: ServicePath) -> Self {
+        let (handler_tx, handler_rx) = channel::<SwbusMessage>(RPC_QUEUE_SIZE);
+        rt.add_handler(sp.clone(), handler_tx);
+        RpcServer {
+            rt,
+            sp,
+            handler_rx,
+            methods: HashMap::new(),
+            id_generator: Arc::new(MessageIdGenerator::new()),
+        }
+    }
+
+    /// Serve method `M` with `handler`, which is given the service path of the caller and the request.
+    /// An error of the handler is the error of the call.
+    pub fn register<M, F, Fut>(&mut self, handler: F)
+    where
+        M: RpcMethod,
+        F: Fn(ServicePath, M::Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<M::Response>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let boxed: BoxedHandler = Arc::new(move |caller: ServicePath, payload: &[u8]| -> Handling {
+            let request = serde_json::from_slice::<M::Request>(payload);
+            let handler = handler.clone();
+            Box::pin(async move {
+                let request = request.map_err(|e| {
+                    SwbusError::input(
+                        SwbusErrorCode::InvalidPayload,
+                        format!("invalid request of {}: {e}", M::NAME),
+                    )
+                })?;
+                let response = handler(caller, request).await?;
+                serde_json::to_vec(&response).map_err(|e| {
+                    SwbusError::internal(
+                        SwbusErrorCode::Fail,
+                        format!("failed to serialize the response of {}: {e}", M::NAME),
+                    )
+                })
+            })
+        });
+        self.methods.insert(M::NAME, boxed);
+    }
+
+    /// Answer calls until the edge runtime shuts down. Every call is handled in a task of its own, so a
+    /// slow method doesn't hold up the others.
+    pub async fn run(mut self) {
+        while let Some(msg) = self.handler_rx.recv().await {
+            let (Some(header), Some(Body::DataRequest(DataRequest { payload }))) = (&msg.header, &msg.body) else {
+                continue;
+            };
+            let caller = header.source.clone().unwrap_or_default();
+            let call = RpcRequest::decode(&payload[..])
+                .map_err(|e| SwbusError::input(SwbusErrorCode::InvalidPayload, format!("invalid RPC request: {e}")))
+                .and_then(|call| match self.methods.get(call.method.as_str()) {
+                    Some(handler) => Ok(handler(caller, &call.payload)),
+                    None => Err(SwbusError::input(
+                        SwbusErrorCode::InvalidArgs,
+                        format!("unknown method {} of {}", call.method, self.sp.to_longest_path()),
+                    )),
+                });
+
+            let rt = self.rt.clone();
+            let sp = self.sp.clone();
+            let id_generator = self.id_generator.clone();
+            tokio::spawn(async move {
+                let result = match call {
+                    Ok(handling) => handling.await,
+                    Err(e) => Err(e),
+                };
+                let response = match result {
+                    Ok(payload) => SwbusMessage::new_response(
+                        &msg,
+                        Some(&sp),
+                        SwbusErrorCode::Ok,
+                        "",
+                        id_generator.generate(),
+                        Some(ResponseBody::RpcResult(RpcResult { payload })),
+                    ),
+                    Err(e) => {
+                        debug!("RPC call failed: {e}");
+                        let (code, message) = error_code_and_message(e);
+                        SwbusMessage::new_response(&msg, Some(&sp), code, &message, id_generator.generate(), None)
+                    }
+                };
+                if rt.send(response).await.is_err() {
+                    error!("Failed to send RPC response to swbus");
+                }
+            });
+        }
+    }
+}
+
+/// The error code, error message and body of a response.
+type Reply = (SwbusErrorCode, String, Option<ResponseBody>);
+type PendingCalls = Mutex<HashMap<MessageId, oneshot::Sender<Reply>>>;
+/// Calls to all the nodes of a wildcard, which take every response by its source.
+type PendingFanOuts = Mutex<HashMap<MessageId, mpsc::UnboundedSender<(ServicePath, Reply)>>>;
+
+/// Calls methods of [`RpcServer`]s, from a service path of its own.
+pub struct RpcClient {
+    rt: Arc<SwbusEdgeRuntime>,
+    sp: ServicePath,
+    pending: Arc<PendingCalls>,
+    pending_fan_outs: Arc<PendingFanOuts>,
+    id_generator: MessageIdGenerator,
+    response_task: tokio::task::JoinHandle<()>,
+}
+
+impl RpcClient {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, sp: ServicePath) -> Self {
+        let (handler_tx, mut handler_rx) = channel::<SwbusMessage>(RPC_QUEUE_SIZE);
+        rt.add_handler(sp.clone(), handler_tx);
+        let pending = Arc::new(PendingCalls::default());
+        let pending_fan_outs = Arc::new(PendingFanOuts::default());
+
+        // Responses are taken by the call they answer, late ones are dropped
+        let response_task = tokio::spawn({
+            let pending = pending.clone();
+            let pending_fan_outs = pending_fan_outs.clone();
+            async move {
+                while let Some(msg) = handler_rx.recv().await {
+                    let SwbusMessage {
+                        header,
+                        body: Some(Body::Response(response)),
+                    } = msg
+                    else {
+                        continue;
+                    };
+                    let request_id = response.request_id;
+                    let reply = (response.error_code(), response.error_message, response.response_body);
+                    if let Some(response_tx) = pending.lock().unwrap().remove(&request_id) {
+                        let _ = response_tx.send(reply);
+                    } else if let Some(replies_tx) = pending_fan_outs.lock().unwrap().get(&request_id) {
+                        let source = header.and_then(|header| header.source).unwrap_or_default();
+                        let _ = replies_tx.send((source, reply));
+                    }
+                }
+            }
+        });
+
+        RpcClient {
+            rt,
+            sp,
+            pending,
+            pending_fan_outs,
+            id_generator: MessageIdGenerator::new(),
+            response_task,
+        }
+    }
+
+    /// Call method `M` of the server at `destination`, waiting at most `timeout` for the response.
+    pub async fn call<M: RpcMethod>(
+        &self,
+        destination: &ServicePath,
+        request: &M::Request,
+        timeout: Duration,
+    ) -> Result<M::Response> {
+        let payload = encode_call::<M>(request)?;
+        let id = self.id_generator.generate();
+        let header = SwbusMessageHeader::new(self.sp.clone(), destination.clone(), id);
+        let msg = SwbusMessage::new(header, Body::DataRequest(DataRequest::new(payload)));
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, response_tx);
+        let response = match self.rt.send(msg).await {
+            Ok(()) => self.wait_for_response::<M>(id, response_rx, timeout).await,
+            Err(e) => Err(e),
+        };
+        self.pending.lock().unwrap().remove(&id);
+        response
+    }
+
+    /// Call method `M` of the servers at every node matched by the wildcard node id of `destination`,
+    /// like `region.cluster.*/hamgrd/0/rpc/0`, with one request. swbusd copies the request to each of
+    /// them and reports where to, so the responses are waited for until all of them responded or
+    /// `timeout` passed. Returns the result of each server, by its service path, which is a timeout
+    /// error for the ones that did not respond in time.
+    pub async fn call_all<M: RpcMethod>(
+        &self,
+        destination: &ServicePath,
+        request: &M::Request,
+        timeout: Duration,
+    ) -> Result<Vec<(ServicePath, Result<M::Response>)>> {
+        if !destination.has_node_wildcard() {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidDestination,
+                format!("{} has no wildcard node id", destination.to_longest_path()),
+            ));
+        }
+        let payload = encode_call::<M>(request)?;
+        let id = self.id_generator.generate();
+        let mut header = SwbusMessageHeader::new(self.sp.clone(), destination.clone(), id);
+        header.flag |= SWBUS_FLAG_FAN_OUT_REPORT;
+        let msg = SwbusMessage::new(header, Body::DataRequest(DataRequest::new(payload)));
+
+        let (replies_tx, replies_rx) = mpsc::unbounded_channel();
+        self.pending_fan_outs.lock().unwrap().insert(id, replies_tx);
+        let results = match self.rt.send(msg).await {
+            Ok(()) => self.wait_for_responses::<M>(id, replies_rx, timeout).await,
+            Err(e) => Err(e),
+        };
+        self.pending_fan_outs.lock().unwrap().remove(&id);
+        results
+    }
+
+    async fn wait_for_response<M: RpcMethod>(
+        &self,
+        id: MessageId,
+        response_rx: oneshot::Receiver<Reply>,
+        wait: Duration,
+    ) -> Result<M::Response> {
+        match timeout(wait, response_rx).await {
+            Ok(Ok((error_code, error_message, response_body))) => {
+                decode_result::<M>(error_code, error_message, response_body)
+            }
+            _ => Err(timeout_error(format!(
+                "No response to call {id} of {} within {wait:?}",
+                M::NAME
+            ))),
+        }
+    }
+
+    /// Collect the responses to call `id` to a wildcard, by the node they come from, until the nodes in
+    /// the fan-out report all responded or `wait` passed.
+    async fn wait_for_responses<M: RpcMethod>(
+        &self,
+        id: MessageId,
+        mut replies_rx: mpsc::UnboundedReceiver<(ServicePath, Reply)>,
+        wait: Duration,
+    ) -> Result<Vec<(ServicePath, Result<M::Response>)>> {
+        let deadline = Instant::now() + wait;
+        let mut destinations: Option<Vec<ServicePath>> = None;
+        let mut replies: BTreeMap<String, (ServicePath, Reply)> = BTreeMap::new();
+        loop {
+            if let Some(destinations) = &destinations {
+                if destinations.iter().all(|d| replies.contains_key(&d.node_id)) {
+                    break;
+                }
+            }
+            let Ok(Some((source, reply))) = timeout_at(deadline, replies_rx.recv()).await else {
+                break;
+            };
+            match reply {
+                (SwbusErrorCode::Ok, _, Some(ResponseBody::FanOutReport(report))) => {
+                    destinations = Some(report.destinations)
+                }
+                reply => {
+                    replies.entry(source.node_id.clone()).or_insert((source, reply));
+                }
+            }
+        }
+
+        let decode = |(source, (error_code, error_message, response_body)): (ServicePath, Reply)| {
+            (source, decode_result::<M>(error_code, error_message, response_body))
+        };
+        match destinations {
+            Some(destinations) => Ok(destinations
+                .into_iter()
+                .map(|destination| match replies.remove(&destination.node_id) {
+                    Some(reply) => decode(reply),
+                    None => {
+                        let error = timeout_error(format!(
+                            "No response to call {id} of {} from {} within {wait:?}",
+                            M::NAME,
+                            destination.to_longest_path()
+                        ));
+                        (destination, Err(error))
+                    }
+                })
+                .collect()),
+            // Without the report, it is only known who responded
+            None if !replies.is_empty() => Ok(replies.into_values().map(decode).collect()),
+            None => Err(timeout_error(format!(
+                "No fan-out report for call {id} of {} within {wait:?}",
+                M::NAME
+            ))),
+        }
+    }
+}
+
+/// The payload of a data request calling method `M` with `request`.
+fn encode_call<M: RpcMethod>(request: &M::Request) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(request).map_err(|e| {
+        SwbusError::input(
+            SwbusErrorCode::InvalidPayload,
+            format!("failed to serialize the request of {}: {e}", M::NAME),
+        )
+    })?;
+    let call = RpcRequest {
+        method: M::NAME.to_string(),
+        payload,
+    };
+    Ok(call.encode_to_vec())
+}
+
+/// The response of method `M` in a response, or its error.
+fn decode_result<M: RpcMethod>(
+    error_code: SwbusErrorCode,
+    error_message: String,
+    response_body: Option<ResponseBody>,
+) -> Result<M::Response> {
+    if error_code != SwbusErrorCode::Ok {
+        return Err(response_error(error_code, error_message));
+    }
+    let Some(ResponseBody::RpcResult(RpcResult { payload })) = response_body else {
+        return Err(SwbusError::input(
+            SwbusErrorCode::InvalidPayload,
+            format!("response to {} without a result", M::NAME),
+        ));
+    };
+    serde_json::from_slice(&payload).map_err(|e| {
+        SwbusError::input(
+            SwbusErrorCode::InvalidPayload,
+            format!("invalid response of {}: {e}", M::NAME),
+        )
+    })
+}
+
+fn timeout_error(detail: String) -> SwbusError {
+    SwbusError::connection(SwbusErrorCode::Timeout, io::Error::new(io::ErrorKind::TimedOut, detail))
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        self.response_task.abort();
+    }
+}
+
+fn error_code_and_message(e: SwbusError) -> (SwbusErrorCode, String) {
+    match e {
+        SwbusError::ConnectionError { code, detail } => (code, detail.to_string()),
+        SwbusError::InputError { code, detail }
+        | SwbusError::RouteError { code, detail }
+        | SwbusError::InternalError { code, detail } => (code, detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use swbus_proto::swbus::FanOutReport;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Operands {
+        a: i64,
+        b: i64,
+    }
+
+    struct Divide;
+
+    impl RpcMethod for Divide {
+        const NAME: &'static str = "divide";
+        type Request = Operands;
+        type Response = i64;
+    }
+
+    struct Sleep;
+
+    impl RpcMethod for Sleep {
+        const NAME: &'static str = "sleep";
+        type Request = u64;
+        type Response = ();
+    }
+
+    struct Multiply;
+
+    impl RpcMethod for Multiply {
+        const NAME: &'static str = "multiply";
+        type Request = Operands;
+        type Response = i64;
+    }
+
+    #[tokio::test]
+    async fn typed_calls_are_answered() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0").unwrap();
+        let mut rt = SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp);
+        rt.start().await.unwrap();
+        let rt = Arc::new(rt);
+
+        let server_sp = rt.new_sp("test", "server");
+        let mut server = RpcServer::new(rt.clone(), server_sp.clone());
+        server.register::<Divide, _, _>(|_, Operands { a, b }| async move {
+            a.checked_div(b)
+                .ok_or_else(|| SwbusError::input(SwbusErrorCode::InvalidArgs, "division by zero".to_string()))
+        });
+        server.register::<Sleep, _, _>(|_, millis| async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            Ok(())
+        });
+        tokio::spawn(server.run());
+
+        let client = RpcClient::new(rt.clone(), rt.new_sp("test", "client"));
+        let wait = Duration::from_secs(1);
+        let quotient = client.call::<Divide>(&server_sp, &Operands { a: 7, b: 2 }, wait).await;
+        assert_eq!(quotient.unwrap(), 3);
+
+        let res = client.call::<Divide>(&server_sp, &Operands { a: 7, b: 0 }, wait).await;
+        let Err(SwbusError::InputError { code, detail }) = res else {
+            panic!("expected an input error");
+        };
+        assert_eq!(code, SwbusErrorCode::InvalidArgs);
+        assert_eq!(detail, "division by zero");
+
+        // a method the server doesn't have
+        let res = client
+            .call::<Multiply>(&server_sp, &Operands { a: 7, b: 2 }, wait)
+            .await;
+        assert!(matches!(
+            res,
+            Err(SwbusError::InputError {
+                code: SwbusErrorCode::InvalidArgs,
+                ..
+            })
+        ));
+
+        // a slow call times out, and doesn't hold up the others
+        let slow = client.call::<Sleep>(&server_sp, &500, Duration::from_millis(50));
+        let fast = client.call::<Divide>(&server_sp, &Operands { a: 9, b: 3 }, wait);
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert!(matches!(
+            slow,
+            Err(SwbusError::ConnectionError {
+                code: SwbusErrorCode::Timeout,
+                ..
+            })
+        ));
+        assert_eq!(fast.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn fan_out_responses_are_collected_by_node() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0/hamgrd/0").unwrap();
+        let mut rt = SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp);
+        rt.start().await.unwrap();
+        let rt = Arc::new(rt);
+        let client = RpcClient::new(rt.clone(), rt.new_sp("test", "client"));
+
+        let dpu =
+            |slot: u32| ServicePath::from_string(&format!("region-a.cluster-a.10.0.1.0-dpu{slot}/hamgrd/0")).unwrap();
+        let quotient = |value: i64| {
+            let payload = serde_json::to_vec(&value).unwrap();
+            (
+                SwbusErrorCode::Ok,
+                String::new(),
+                Some(ResponseBody::RpcResult(RpcResult { payload })),
+            )
+        };
+        let (replies_tx, replies_rx) = mpsc::unbounded_channel();
+        replies_tx.send((dpu(0), quotient(3))).unwrap();
+        let report = FanOutReport {
+            destinations: vec![dpu(0), dpu(1), dpu(2)],
+        };
+        let swbusd = ServicePath::from_string("region-a.cluster-a.10.0.1.0").unwrap();
+        replies_tx
+            .send((
+                swbusd,
+                (
+                    SwbusErrorCode::Ok,
+                    String::new(),
+                    Some(ResponseBody::FanOutReport(report)),
+                ),
+            ))
+            .unwrap();
+        replies_tx
+            .send((dpu(2), (SwbusErrorCode::NoRoute, "Route not found".to_string(), None)))
+            .unwrap();
+        // only the first response of a node counts
+        replies_tx.send((dpu(0), quotient(4))).unwrap();
+
+        let results = client
+            .wait_for_responses::<Divide>(1, replies_rx, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, dpu(0));
+        assert_eq!(*results[0].1.as_ref().unwrap(), 3);
+        assert_eq!(results[1].0, dpu(1));
+        assert!(matches!(
+            results[1].1,
+            Err(SwbusError::ConnectionError {
+                code: SwbusErrorCode::Timeout,
+                ..
+            })
+        ));
+        assert!(matches!(
+            results[2].1,
+            Err(SwbusError::RouteError {
+                code: SwbusErrorCode::NoRoute,
+                ..
+            })
+        ));
+
+        // a wildcard is needed to call all
+        let res = client
+            .call_all::<Divide>(&dpu(0), &Operands { a: 1, b: 1 }, Duration::from_millis(50))
+            .await;
+        assert!(matches!(
+            res,
+            Err(SwbusError::InputError {
+                code: SwbusErrorCode::InvalidDestination,
+                ..
+            })
+        ));
+    }
+}
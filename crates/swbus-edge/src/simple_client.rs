@@ -1,4 +1,9 @@
+use crate::mailbox::{self, MailboxConfig, MailboxRx};
+use crate::metrics::ClientMetrics;
+use crate::reliable::{Filtered, Reliability, ReliableDelivery};
 use crate::SwbusEdgeRuntime;
+use bytes::Bytes;
+use futures_util::stream::{self, Stream};
 use std::collections::HashMap;
 use std::sync::Arc;
 use swbus_proto::{
@@ -19,12 +24,43 @@ pub type MessageId = u64;
 
 /// Simplified interface to [`SwbusEdgeRuntime`] that does not expose infra messages, message id
 /// generation, raw message construction, and other internal details to Swbus clients.
+///
+/// A client can be [`split`](Self::split) into a sender and a receiver half, to send from several tasks
+/// and to receive in a `select!` loop or from several worker tasks.
 pub struct SimpleSwbusEdgeClient {
+    sender: SimpleSwbusEdgeSender,
+    handler_rx: Mutex<MailboxRx>,
+    sink: bool,
+}
+
+/// The sending half of a [`SimpleSwbusEdgeClient`], see [`split`](SimpleSwbusEdgeClient::split).
+///
+/// Clones send as the same client, with message ids from the same generator.
+#[derive(Clone)]
+pub struct SimpleSwbusEdgeSender {
     rt: Arc<SwbusEdgeRuntime>,
-    handler_rx: Mutex<Receiver<SwbusMessage>>,
     source: ServicePath,
-    id_generator: MessageIdGenerator,
-    sink: bool,
+    id_generator: Arc<MessageIdGenerator>,
+    reliability: Option<Arc<Reliability>>,
+    // a reliable send waits for the response the filter task takes, so it runs as long as either half
+    filter_task: Option<Arc<FilterTask>>,
+    metrics: Arc<ClientMetrics>,
+}
+
+/// The receiving half of a [`SimpleSwbusEdgeClient`], see [`split`](SimpleSwbusEdgeClient::split).
+///
+/// Clones receive from the same mailbox, each message is received by one of them.
+#[derive(Clone)]
+pub struct SimpleSwbusEdgeReceiver {
+    client: Arc<SimpleSwbusEdgeClient>,
+}
+
+struct FilterTask(tokio::task::JoinHandle<()>);
+
+impl Drop for FilterTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl SimpleSwbusEdgeClient {
@@ -32,57 +68,180 @@ impl SimpleSwbusEdgeClient {
     ///
     /// `public` determines whether the client is registered using [`SwbusEdgeRuntime::add_handler`] or [`SwbusEdgeRuntime::add_private_handler`].
     pub fn new(rt: Arc<SwbusEdgeRuntime>, source: ServicePath, public: bool, sink: bool) -> Self {
+        Self::with_mailbox(rt, source, public, sink, MailboxConfig::default())
+    }
+
+    /// Create and connect a new client like [`new`](Self::new), receiving in a mailbox of the given size
+    /// and overflow policy. Overflows are logged and counted in the `swbus_edge_mailbox_overflows_total`
+    /// metric.
+    pub fn with_mailbox(
+        rt: Arc<SwbusEdgeRuntime>,
+        source: ServicePath,
+        public: bool,
+        sink: bool,
+        mailbox: MailboxConfig,
+    ) -> Self {
+        let metrics = ClientMetrics::new(&source);
+        let (mailbox_tx, mailbox_rx) = mailbox::channel(mailbox, source.to_longest_path(), metrics.overflows.clone());
+        rt.add_mailbox(source.clone(), mailbox_tx, public);
+        Self {
+            sender: SimpleSwbusEdgeSender {
+                rt,
+                source,
+                id_generator: Arc::new(MessageIdGenerator::new()),
+                reliability: None,
+                filter_task: None,
+                metrics: Arc::new(metrics),
+            },
+            handler_rx: Mutex::new(mailbox_rx),
+            sink,
+        }
+    }
+
+    /// Create and connect a new client that delivers requests reliably.
+    ///
+    /// [`send`](Self::send) of a request resolves when the request is answered. The request is resent with
+    /// the same id if it is not answered in time or fails to be routed, and the send fails with the error of
+    /// the last attempt, [`SwbusErrorCode::Timeout`] if it was not answered. Responses to these requests are
+    /// not passed to [`recv`](Self::recv).
+    ///
+    /// Requests resent to a reliable client are recognized by their source and id. They are not passed to
+    /// `recv` again, and the response sent to the first one is sent again.
+    pub fn new_reliable(
+        rt: Arc<SwbusEdgeRuntime>,
+        source: ServicePath,
+        public: bool,
+        config: ReliableDelivery,
+    ) -> Self {
+        let mut unfiltered_rx = Self::add_handler(&rt, &source, public);
+        let metrics = ClientMetrics::new(&source);
+        let (filtered_tx, handler_rx) = mailbox::channel(
+            MailboxConfig::default(),
+            source.to_longest_path(),
+            metrics.overflows.clone(),
+        );
+        let reliability = Arc::new(Reliability::new(config));
+
+        // responses have to be taken while the owner of the client waits in send, not in recv
+        let filter_task = tokio::spawn({
+            let rt = rt.clone();
+            let reliability = reliability.clone();
+            async move {
+                while let Some(msg) = unfiltered_rx.recv().await {
+                    match reliability.filter_received(&msg) {
+                        Filtered::Pass => {
+                            if filtered_tx.send(msg).await.is_err() {
+                                break;
+                            }
+                        }
+                        Filtered::Drop => {}
+                        Filtered::Resend(response) => {
+                            let _ = rt.send(response).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender: SimpleSwbusEdgeSender {
+                rt,
+                source,
+                id_generator: Arc::new(MessageIdGenerator::new()),
+                reliability: Some(reliability),
+                filter_task: Some(Arc::new(FilterTask(filter_task))),
+                metrics: Arc::new(metrics),
+            },
+            handler_rx: Mutex::new(handler_rx),
+            sink: false,
+        }
+    }
+
+    fn add_handler(rt: &SwbusEdgeRuntime, source: &ServicePath, public: bool) -> Receiver<SwbusMessage> {
         let (handler_tx, handler_rx) = channel::<SwbusMessage>(crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE);
         if public {
             rt.add_handler(source.clone(), handler_tx);
         } else {
             rt.add_private_handler(source.clone(), handler_tx);
         }
-
-        Self {
-            rt,
-            handler_rx: Mutex::new(handler_rx),
-            source,
-            id_generator: MessageIdGenerator::new(),
-            sink,
-        }
+        handler_rx
     }
 
     pub fn get_edge_runtime(&self) -> &Arc<SwbusEdgeRuntime> {
-        &self.rt
+        &self.sender.rt
+    }
+
+    /// A sender that sends as this client. It can be cloned and moved to other tasks.
+    pub fn sender(&self) -> SimpleSwbusEdgeSender {
+        self.sender.clone()
+    }
+
+    /// Split the client into a sender and a receiver half. Both can be cloned, the client stops
+    /// receiving when the last receiver is dropped.
+    pub fn split(self) -> (SimpleSwbusEdgeSender, SimpleSwbusEdgeReceiver) {
+        let sender = self.sender.clone();
+        (sender, SimpleSwbusEdgeReceiver { client: Arc::new(self) })
+    }
+
+    /// The messages received by the client as a stream, see [`SimpleSwbusEdgeReceiver::into_stream`].
+    pub fn into_stream(self) -> impl Stream<Item = IncomingMessage> + Send + 'static {
+        self.split().1.into_stream()
     }
 
     /// Receive a message.
     ///
     /// Returns `None` when no more messages will ever be received.
     pub async fn recv(&self) -> Option<IncomingMessage> {
+        let metrics = &self.sender.metrics;
         loop {
-            let msg = self.handler_rx.lock().await.recv().await?;
+            let msg = {
+                let mut handler_rx = self.handler_rx.lock().await;
+                let msg = handler_rx.recv().await?;
+                metrics.queue_depth.set(handler_rx.len() as i64);
+                msg
+            };
+            metrics.received.inc();
             match self.handle_received_message(msg) {
                 HandleReceivedMessage::PassToActor(msg) => break Some(msg),
-                HandleReceivedMessage::Respond(msg) => self.rt.send(msg).await.unwrap(),
-                HandleReceivedMessage::Ignore => {}
+                HandleReceivedMessage::Respond(msg) => self.sender.rt.send(msg).await.unwrap(),
+                HandleReceivedMessage::Ignore => metrics.dropped.inc(),
             }
         }
     }
 
     fn handle_received_message(&self, msg: SwbusMessage) -> HandleReceivedMessage {
-        let header = msg.header.unwrap();
-        let id = header.id;
-        let source = header.source.unwrap();
-        let destination = header.destination.unwrap();
-        let body = msg.body.unwrap();
+        // Messages can come from remote switches. Drop the ones missing a mandatory part.
+        let Some(SwbusMessageHeader {
+            id,
+            source: Some(source),
+            destination: Some(destination),
+            trace_id,
+            ..
+        }) = msg.header
+        else {
+            return HandleReceivedMessage::Ignore;
+        };
+        let Some(body) = msg.body else {
+            return HandleReceivedMessage::Ignore;
+        };
+        // responses are part of the trace of the request
+        let respond = |source: ServicePath, destination: ServicePath, body: Body| {
+            let mut header = SwbusMessageHeader::new(source, destination, self.sender.id_generator.generate());
+            header.trace_id = trace_id.clone();
+            HandleReceivedMessage::Respond(SwbusMessage::new(header, body))
+        };
 
-        if self.sink && destination != self.source {
+        if self.sink && destination != self.sender.source {
             // sink will drop all messages not to itself and reply with NoRoute
-            return HandleReceivedMessage::Respond(SwbusMessage::new(
-                SwbusMessageHeader::new(self.source.clone(), source, self.id_generator.generate()),
+            return respond(
+                self.sender.source.clone(),
+                source,
                 Body::Response(RequestResponse::infra_error(
                     id,
                     SwbusErrorCode::NoRoute,
                     "Route not found",
                 )),
-            ));
+            );
         }
 
         match body {
@@ -91,6 +250,7 @@ impl SimpleSwbusEdgeClient {
                 source,
                 destination,
                 body: MessageBody::Request { payload },
+                trace_id,
             }),
             Body::Response(RequestResponse {
                 request_id,
@@ -107,15 +267,14 @@ impl SimpleSwbusEdgeClient {
                     error_message,
                     response_body: None,
                 },
+                trace_id,
             }),
-            Body::PingRequest(_) => HandleReceivedMessage::Respond(SwbusMessage::new(
-                SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
-                Body::Response(RequestResponse::ok(id)),
-            )),
-            Body::TraceRouteRequest(_) => HandleReceivedMessage::Respond(SwbusMessage::new(
-                SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
-                Body::Response(RequestResponse::ok(id)),
-            )),
+            Body::PingRequest(_) => respond(destination, source, Body::Response(RequestResponse::ok(id))),
+            Body::TraceRouteRequest(mut request) => {
+                let mut response = RequestResponse::ok(id);
+                response.response_body = Some(request.add_hop(destination.clone()));
+                respond(destination, source, Body::Response(response))
+            }
             Body::ManagementRequest(ManagementRequest { request, arguments }) => {
                 let request_type = match ManagementRequestType::try_from(request) {
                     Ok(request_type) => request_type,
@@ -135,16 +294,50 @@ impl SimpleSwbusEdgeClient {
                             .map(|arg| (arg.name.clone(), arg.value.clone()))
                             .collect(),
                     },
+                    trace_id,
                 })
             }
             _ => HandleReceivedMessage::Ignore,
         }
     }
 
-    /// Send a message.
+    /// Run a received message through the client without a runtime, for the fuzz targets.
+    #[cfg(fuzzing)]
+    pub fn fuzz_handle_received_message(&self, msg: SwbusMessage) {
+        self.handle_received_message(msg);
+    }
+
+    /// Send a message. For a reliable client, this waits until a request is answered.
+    pub async fn send(&self, msg: OutgoingMessage) -> Result<MessageId> {
+        self.sender.send(msg).await
+    }
+
+    /// Send a raw [`SwbusMessage`], see [`SimpleSwbusEdgeSender::send_raw`].
+    pub async fn send_raw(&self, msg: SwbusMessage) -> Result<()> {
+        self.sender.send_raw(msg).await
+    }
+
+    /// Compile an [`OutgoingMessage`] into an [`SwbusMessage`] for use with [`send_raw`](Self::send_raw).
+    pub fn outgoing_message_to_swbus_message(&self, msg: OutgoingMessage) -> (MessageId, SwbusMessage) {
+        self.sender.outgoing_message_to_swbus_message(msg)
+    }
+
+    pub fn get_service_path(self: &Arc<Self>) -> &ServicePath {
+        &self.sender.source
+    }
+}
+
+impl SimpleSwbusEdgeSender {
+    /// Send a message. For a reliable client, this waits until a request is answered.
     pub async fn send(&self, msg: OutgoingMessage) -> Result<MessageId> {
         let (id, msg) = self.outgoing_message_to_swbus_message(msg);
-        self.send_raw(msg).await?;
+        match &self.reliability {
+            Some(reliability) => {
+                reliability.send(&self.rt, id, msg).await?;
+                self.metrics.sent.inc();
+            }
+            None => self.send_raw(msg).await?,
+        }
         Ok(id)
     }
 
@@ -155,13 +348,15 @@ impl SimpleSwbusEdgeClient {
     ///
     /// This method is intended to be used to implement message resending - repeating a message with the same id.
     pub async fn send_raw(&self, msg: SwbusMessage) -> Result<()> {
-        self.rt.send(msg).await
+        self.rt.send(msg).await?;
+        self.metrics.sent.inc();
+        Ok(())
     }
 
     /// Compile an [`OutgoingMessage`] into an [`SwbusMessage`] for use with [`send_raw`](Self::send_raw).
     pub fn outgoing_message_to_swbus_message(&self, msg: OutgoingMessage) -> (MessageId, SwbusMessage) {
         let id = self.id_generator.generate();
-        let msg = SwbusMessage {
+        let mut msg = SwbusMessage {
             header: Some(SwbusMessageHeader::new(self.source.clone(), msg.destination, id)),
             body: Some(match msg.body {
                 MessageBody::Request { payload } => Body::DataRequest(DataRequest { payload }),
@@ -185,14 +380,32 @@ impl SimpleSwbusEdgeClient {
                 MessageBody::ManagementRequest { .. } => unimplemented!(),
             }),
         };
+        // stamped now, so that resends keep the trace id
+        crate::trace::stamp(&mut msg);
         (id, msg)
     }
 
-    pub fn get_service_path(self: &Arc<Self>) -> &ServicePath {
+    pub fn get_service_path(&self) -> &ServicePath {
         &self.source
     }
 }
 
+impl SimpleSwbusEdgeReceiver {
+    /// Receive a message, see [`SimpleSwbusEdgeClient::recv`].
+    pub async fn recv(&self) -> Option<IncomingMessage> {
+        self.client.recv().await
+    }
+
+    /// The received messages as a stream, to receive in a `select!` loop. The stream ends when no more
+    /// messages will ever be received.
+    pub fn into_stream(self) -> impl Stream<Item = IncomingMessage> + Send + 'static {
+        stream::unfold(self, |receiver| async move {
+            let msg = receiver.recv().await?;
+            Some((msg, receiver))
+        })
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 enum HandleReceivedMessage {
     PassToActor(IncomingMessage),
@@ -204,7 +417,7 @@ enum HandleReceivedMessage {
 #[derive(Debug, Clone)]
 pub enum MessageBody {
     Request {
-        payload: Vec<u8>,
+        payload: Bytes,
     },
     Response {
         request_id: MessageId,
@@ -230,6 +443,8 @@ pub struct IncomingMessage {
     pub source: ServicePath,
     pub destination: ServicePath,
     pub body: MessageBody,
+    /// Trace id of the operation the message is part of, see [`crate::trace`]
+    pub trace_id: String,
 }
 
 /// A message to send to another Swbus client.
@@ -238,3 +453,223 @@ pub struct OutgoingMessage {
     pub destination: ServicePath,
     pub body: MessageBody,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::result::SwbusError;
+    use swbus_proto::swbus::TraceRouteRequest;
+    use tokio::time::Duration;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn malformed_message_is_ignored() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0/test/0").unwrap();
+        let rt = Arc::new(SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp.clone()));
+        let client = SimpleSwbusEdgeClient::new(rt, sp.clone(), false, false);
+
+        let message = SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp, 1),
+            Body::DataRequest(DataRequest::default()),
+        );
+        assert!(matches!(
+            client.handle_received_message(message.clone()),
+            HandleReceivedMessage::PassToActor(_)
+        ));
+
+        let mut no_header = message.clone();
+        no_header.header = None;
+        let mut no_source = message.clone();
+        no_source.header.as_mut().unwrap().source = None;
+        let mut no_destination = message.clone();
+        no_destination.header.as_mut().unwrap().destination = None;
+        let mut no_body = message;
+        no_body.body = None;
+        for message in [no_header, no_source, no_destination, no_body] {
+            assert!(matches!(
+                client.handle_received_message(message),
+                HandleReceivedMessage::Ignore
+            ));
+        }
+    }
+
+    #[test]
+    fn trace_route_is_answered_with_the_path() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0/test/0").unwrap();
+        let swbusd_sp = sp.to_swbusd_service_path();
+        let source = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/cli/0/traceroute/0").unwrap();
+        let rt = Arc::new(SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp.clone()));
+        let client = SimpleSwbusEdgeClient::new(rt, sp.clone(), false, false);
+
+        let mut request = TraceRouteRequest::new();
+        request.add_hop(swbusd_sp.clone());
+        let message = SwbusMessage::new(
+            SwbusMessageHeader::new(source, sp.clone(), 1),
+            Body::TraceRouteRequest(request),
+        );
+        let HandleReceivedMessage::Respond(response) = client.handle_received_message(message) else {
+            panic!("trace route request is not answered");
+        };
+        let Some(Body::Response(RequestResponse {
+            response_body: Some(ResponseBody::TraceRouteResult(result)),
+            ..
+        })) = response.body
+        else {
+            panic!("unexpected response {response:?}");
+        };
+        let hops: Vec<_> = result.hops.into_iter().map(|hop| hop.service_path.unwrap()).collect();
+        assert_eq!(hops, vec![swbusd_sp, sp]);
+    }
+
+    async fn started_runtime() -> Arc<SwbusEdgeRuntime> {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0").unwrap();
+        let mut rt = SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp);
+        rt.start().await.unwrap();
+        Arc::new(rt)
+    }
+
+    fn request(destination: &ServicePath) -> OutgoingMessage {
+        OutgoingMessage {
+            destination: destination.clone(),
+            body: MessageBody::Request {
+                payload: Bytes::from_static(b"hi"),
+            },
+        }
+    }
+
+    fn response(msg: &IncomingMessage) -> OutgoingMessage {
+        OutgoingMessage {
+            destination: msg.source.clone(),
+            body: MessageBody::Response {
+                request_id: msg.id,
+                error_code: SwbusErrorCode::Ok,
+                error_message: String::new(),
+                response_body: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn reliable_send_waits_for_response() {
+        let rt = started_runtime().await;
+        let a =
+            SimpleSwbusEdgeClient::new_reliable(rt.clone(), rt.new_sp("test", "a"), true, ReliableDelivery::default());
+        let b = Arc::new(SimpleSwbusEdgeClient::new_reliable(
+            rt.clone(),
+            rt.new_sp("test", "b"),
+            true,
+            ReliableDelivery::default(),
+        ));
+
+        let responder = tokio::spawn({
+            let b = b.clone();
+            async move {
+                let msg = b.recv().await.unwrap();
+                b.send(response(&msg)).await.unwrap();
+            }
+        });
+        a.send(request(&rt.new_sp("test", "b"))).await.unwrap();
+        responder.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reliable_send_times_out() {
+        let rt = started_runtime().await;
+        let config = ReliableDelivery {
+            timeout: Duration::from_millis(50),
+            retries: 2,
+            backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let a = SimpleSwbusEdgeClient::new_reliable(rt.clone(), rt.new_sp("test", "a"), true, config);
+        let silent = SimpleSwbusEdgeClient::new(rt.clone(), rt.new_sp("test", "silent"), true, false);
+
+        let res = a.send(request(&rt.new_sp("test", "silent"))).await;
+        assert!(matches!(
+            res,
+            Err(SwbusError::ConnectionError {
+                code: SwbusErrorCode::Timeout,
+                ..
+            })
+        ));
+
+        // the first attempt and 2 retries, all with the same id
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(silent.recv().await.unwrap().id);
+        }
+        assert!(ids.iter().all(|id| *id == ids[0]));
+    }
+
+    #[tokio::test]
+    async fn reliable_client_answers_retried_requests_once() {
+        let rt = started_runtime().await;
+        let sender = SimpleSwbusEdgeClient::new(rt.clone(), rt.new_sp("test", "sender"), true, false);
+        let b =
+            SimpleSwbusEdgeClient::new_reliable(rt.clone(), rt.new_sp("test", "b"), true, ReliableDelivery::default());
+        let (id, msg) = sender.outgoing_message_to_swbus_message(request(&rt.new_sp("test", "b")));
+
+        // a retry while the request is handled is dropped
+        sender.send_raw(msg.clone()).await.unwrap();
+        let received = b.recv().await.unwrap();
+        assert_eq!(received.id, id);
+        sender.send_raw(msg.clone()).await.unwrap();
+        b.send(response(&received)).await.unwrap();
+        let MessageBody::Response { request_id, .. } = sender.recv().await.unwrap().body else {
+            panic!("expected a response");
+        };
+        assert_eq!(request_id, id);
+
+        // a retry after it is answered gets the same answer
+        sender.send_raw(msg).await.unwrap();
+        let MessageBody::Response { request_id, .. } = sender.recv().await.unwrap().body else {
+            panic!("expected a response");
+        };
+        assert_eq!(request_id, id);
+        assert!(tokio::time::timeout(Duration::from_millis(100), b.recv())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn split_receivers_share_the_mailbox() {
+        let rt = started_runtime().await;
+        let sender = SimpleSwbusEdgeClient::new(rt.clone(), rt.new_sp("test", "sender"), true, false);
+        let b = SimpleSwbusEdgeClient::new(rt.clone(), rt.new_sp("test", "b"), true, false);
+        let (b_sender, b_receiver) = b.split();
+
+        // workers answer from clones of both halves
+        let (done_tx, mut done_rx) = tokio::sync::mpsc::channel(10);
+        for _ in 0..2 {
+            let mut requests = Box::pin(b_receiver.clone().into_stream());
+            let b_sender = b_sender.clone();
+            let done_tx = done_tx.clone();
+            tokio::spawn(async move {
+                while let Some(msg) = requests.next().await {
+                    b_sender.send(response(&msg)).await.unwrap();
+                    done_tx.send(msg.id).await.unwrap();
+                }
+            });
+        }
+        drop(b_receiver);
+
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(sender.send(request(&rt.new_sp("test", "b"))).await.unwrap());
+        }
+        let mut answered = Vec::new();
+        for _ in 0..4 {
+            answered.push(done_rx.recv().await.unwrap());
+            let MessageBody::Response { request_id, .. } = sender.recv().await.unwrap().body else {
+                panic!("expected a response");
+            };
+            assert!(ids.contains(&request_id));
+        }
+        answered.sort();
+        ids.sort();
+        assert_eq!(answered, ids);
+        assert!(tokio::time::timeout(Duration::from_millis(100), done_rx.recv())
+            .await
+            .is_err());
+    }
+}
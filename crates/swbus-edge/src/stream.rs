@@ -0,0 +1,683 @@
+//! Streams of data too large for a single message, e.g. the flow sync statistics or a dump of a large
+//! table.
+//!
+//! A [`DataStreams`] endpoint at a service path opens streams to other endpoints with
+//! [`open`](DataStreams::open), and takes the streams they open to it with
+//! [`accept`](DataStreams::accept), so two endpoints can transfer data both ways at once:
+//!
+//! ```ignore
+//! let streams = DataStreams::new(rt.clone(), rt.new_sp("flow-sync", "stream"), StreamConfig::default());
+//! let mut sender = streams.open(&peer_sp);
+//! sender.send(stats).await?;
+//! sender.finish().await?;
+//!
+//! // at the peer
+//! let mut receiver = peer_streams.accept().await.unwrap();
+//! let stats = receiver.read_to_end().await?;
+//! ```
+//!
+//! The data is split in [`StreamChunk`]s of at most [`StreamConfig::chunk_size`] bytes, each the payload
+//! of a data request of its own, so no message exceeds the gRPC limit. The receiver delivers the chunks
+//! in the order of their sequence number, and acks a chunk by responding to it once the chunk is taken
+//! with [`recv`](StreamReceiver::recv). The sender has at most [`StreamConfig::window`] chunks that are
+//! not acked, so a slow receiver holds up the sender instead of buffering the whole stream.
+//!
+//! A chunk that is not acked in time is sent again with the same message id. The receiver acks it again
+//! if it was taken already, and responds with [`SwbusErrorCode::QueueFull`] if it holds it, after which
+//! the sender waits for it to be taken without sending it again.
+use crate::reliable::response_error;
+use crate::simple_client::MessageId;
+use crate::SwbusEdgeRuntime;
+use bytes::{Bytes, BytesMut};
+use prost::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{
+    swbus_message::Body, DataRequest, ServicePath, StreamChunk, SwbusErrorCode, SwbusMessage, SwbusMessageHeader,
+};
+use tokio::sync::mpsc::{self, channel, Receiver, Sender};
+use tokio::sync::Notify;
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio::time::{timeout, Duration, Instant};
+use tracing::{debug, error};
+
+const STREAM_QUEUE_SIZE: usize = 100;
+
+/// How streams are chunked and paced.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    /// Max bytes of data in a chunk
+    pub chunk_size: usize,
+    /// Chunks sent and not acked at most. Streams with a larger window than the receiver's are refused.
+    pub window: u32,
+    /// Time to wait for the ack of a chunk before it is sent again
+    pub ack_timeout: Duration,
+    /// Times a chunk is sent again before the stream fails
+    pub retries: u32,
+    /// Time to wait for the next chunk, or for a chunk the receiver holds to be taken, before the stream
+    /// fails. Closed streams are remembered as long, to answer their chunks that are sent again.
+    pub idle_timeout: Duration,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            chunk_size: 256 * 1024,
+            window: 16,
+            ack_timeout: Duration::from_secs(1),
+            retries: 3,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A stream by its source and id.
+type StreamKey = (ServicePath, u64);
+/// The error code and error message of the response to a chunk.
+type Ack = (SwbusErrorCode, String);
+
+/// Opens and accepts streams at a service path, see the module docs. Its streams stop when it is dropped.
+pub struct DataStreams {
+    endpoint: Arc<Endpoint>,
+    accept_rx: Receiver<StreamReceiver>,
+    task: JoinHandle<()>,
+}
+
+impl DataStreams {
+    pub fn new(rt: Arc<SwbusEdgeRuntime>, sp: ServicePath, config: StreamConfig) -> Self {
+        let (handler_tx, mut handler_rx) = channel::<SwbusMessage>(STREAM_QUEUE_SIZE);
+        rt.add_handler(sp.clone(), handler_tx);
+        let (accept_tx, accept_rx) = channel(STREAM_QUEUE_SIZE);
+        let endpoint = Arc::new(Endpoint {
+            rt,
+            sp,
+            config,
+            id_generator: MessageIdGenerator::new(),
+            pending: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(HashMap::new()),
+            closed: Mutex::new(HashMap::new()),
+        });
+
+        let task = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move {
+                while let Some(msg) = handler_rx.recv().await {
+                    endpoint.handle(msg, &accept_tx).await;
+                }
+            }
+        });
+
+        DataStreams {
+            endpoint,
+            accept_rx,
+            task,
+        }
+    }
+
+    /// Open a stream to the endpoint at `destination`. Nothing is sent until the first chunk.
+    pub fn open(&self, destination: &ServicePath) -> StreamSender {
+        StreamSender {
+            endpoint: self.endpoint.clone(),
+            destination: destination.clone(),
+            stream_id: self.endpoint.id_generator.generate(),
+            next_seq: 0,
+            in_flight: JoinSet::new(),
+            failed: false,
+        }
+    }
+
+    /// The next stream opened to this endpoint, when its first chunk arrives.
+    pub async fn accept(&mut self) -> Option<StreamReceiver> {
+        self.accept_rx.recv().await
+    }
+}
+
+impl Drop for DataStreams {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A stream being received, the chunks not taken yet.
+struct Incoming {
+    state: Mutex<IncomingState>,
+    arrived: Notify,
+}
+
+struct IncomingState {
+    window: u64,
+    /// The chunks before it were taken
+    next_seq: u64,
+    /// The chunks received and not taken, with the request each came in
+    held: BTreeMap<u64, (StreamChunk, SwbusMessage)>,
+}
+
+struct Endpoint {
+    rt: Arc<SwbusEdgeRuntime>,
+    sp: ServicePath,
+    config: StreamConfig,
+    id_generator: MessageIdGenerator,
+    /// The chunks sent and not acked, by the id of their request
+    pending: Mutex<HashMap<MessageId, mpsc::UnboundedSender<Ack>>>,
+    incoming: Mutex<HashMap<StreamKey, Arc<Incoming>>>,
+    /// Streams received to the end, or closed before it by the receiver, and when
+    closed: Mutex<HashMap<StreamKey, (Instant, bool)>>,
+}
+
+impl Endpoint {
+    async fn handle(self: &Arc<Self>, msg: SwbusMessage, accept_tx: &Sender<StreamReceiver>) {
+        let (Some(header), Some(body)) = (&msg.header, &msg.body) else {
+            return;
+        };
+        let payload = match body {
+            Body::Response(response) => {
+                let mut pending = self.pending.lock().unwrap();
+                if let Some(ack_tx) = pending.get(&response.request_id) {
+                    // the chunk is gone if its sender was dropped
+                    if ack_tx
+                        .send((response.error_code(), response.error_message.clone()))
+                        .is_err()
+                    {
+                        pending.remove(&response.request_id);
+                    }
+                }
+                return;
+            }
+            Body::DataRequest(DataRequest { payload }) => payload.clone(),
+            _ => return,
+        };
+        let Some(source) = header.source.clone() else {
+            return;
+        };
+        let Ok(chunk) = StreamChunk::decode(payload) else {
+            self.respond(&msg, SwbusErrorCode::InvalidPayload, "invalid stream chunk")
+                .await;
+            return;
+        };
+
+        if let Some((error_code, error_message)) = self.receive((source, chunk.stream_id), chunk, &msg, accept_tx) {
+            self.respond(&msg, error_code, &error_message).await;
+        }
+    }
+
+    /// Hold `chunk` of stream `key` until it is taken, or the response to it now.
+    fn receive(
+        self: &Arc<Self>,
+        key: StreamKey,
+        chunk: StreamChunk,
+        msg: &SwbusMessage,
+        accept_tx: &Sender<StreamReceiver>,
+    ) -> Option<Ack> {
+        let now = Instant::now();
+        {
+            let mut closed = self.closed.lock().unwrap();
+            closed.retain(|_, (at, _)| now.duration_since(*at) < self.config.idle_timeout);
+            match closed.get(&key) {
+                Some((_, true)) => return Some((SwbusErrorCode::Ok, String::new())),
+                Some((_, false)) => {
+                    return Some((
+                        SwbusErrorCode::ResourceNotFound,
+                        format!("stream {} was closed by the receiver", key.1),
+                    ))
+                }
+                None => {}
+            }
+        }
+
+        let incoming = self.incoming.lock().unwrap().get(&key).cloned();
+        let incoming = match incoming {
+            Some(incoming) => incoming,
+            None if chunk.window == 0 || chunk.window > self.config.window => {
+                return Some((
+                    SwbusErrorCode::InvalidArgs,
+                    format!(
+                        "window {} of stream {} is not in 1..={}",
+                        chunk.window, key.1, self.config.window
+                    ),
+                ))
+            }
+            None if chunk.seq >= chunk.window as u64 => {
+                return Some((SwbusErrorCode::ResourceNotFound, format!("unknown stream {}", key.1)))
+            }
+            None => {
+                let incoming = Arc::new(Incoming {
+                    state: Mutex::new(IncomingState {
+                        window: chunk.window as u64,
+                        next_seq: 0,
+                        held: BTreeMap::new(),
+                    }),
+                    arrived: Notify::new(),
+                });
+                let receiver = StreamReceiver {
+                    endpoint: self.clone(),
+                    key: key.clone(),
+                    incoming: incoming.clone(),
+                    done: false,
+                };
+                if accept_tx.try_send(receiver).is_err() {
+                    return Some((
+                        SwbusErrorCode::Fail,
+                        format!(
+                            "too many streams waiting to be accepted by {}",
+                            self.sp.to_longest_path()
+                        ),
+                    ));
+                }
+                self.incoming.lock().unwrap().insert(key.clone(), incoming.clone());
+                incoming
+            }
+        };
+
+        let mut state = incoming.state.lock().unwrap();
+        if chunk.seq < state.next_seq {
+            // taken already, the ack was lost
+            return Some((SwbusErrorCode::Ok, String::new()));
+        }
+        if state.held.contains_key(&chunk.seq) {
+            return Some((SwbusErrorCode::QueueFull, format!("chunk {} is held", chunk.seq)));
+        }
+        if chunk.seq >= state.next_seq + state.window {
+            return Some((
+                SwbusErrorCode::InvalidArgs,
+                format!("chunk {} of stream {} is out of the window", chunk.seq, key.1),
+            ));
+        }
+        state.held.insert(chunk.seq, (chunk, msg.clone()));
+        incoming.arrived.notify_one();
+        None
+    }
+
+    async fn respond(&self, request: &SwbusMessage, error_code: SwbusErrorCode, error_message: &str) {
+        let response = SwbusMessage::new_response(
+            request,
+            Some(&self.sp),
+            error_code,
+            error_message,
+            self.id_generator.generate(),
+            None,
+        );
+        if self.rt.send(response).await.is_err() {
+            error!("Failed to send the response to a stream chunk to swbus");
+        }
+    }
+
+    /// Send chunk `msg` with id `id` until it is acked, see the module docs.
+    async fn deliver(&self, id: MessageId, msg: SwbusMessage) -> Result<()> {
+        let (ack_tx, mut ack_rx) = mpsc::unbounded_channel();
+        self.pending.lock().unwrap().insert(id, ack_tx);
+        let res = self.deliver_with_retries(id, msg, &mut ack_rx).await;
+        self.pending.lock().unwrap().remove(&id);
+        res
+    }
+
+    async fn deliver_with_retries(
+        &self,
+        id: MessageId,
+        msg: SwbusMessage,
+        ack_rx: &mut mpsc::UnboundedReceiver<Ack>,
+    ) -> Result<()> {
+        let mut retry = 0;
+        loop {
+            let ack = match self.rt.send(msg.clone()).await {
+                Ok(()) => timeout(self.config.ack_timeout, ack_rx.recv()).await.ok().flatten(),
+                Err(e) => {
+                    debug!("Failed to send stream chunk {id}: {e}");
+                    tokio::time::sleep(self.config.ack_timeout).await;
+                    None
+                }
+            };
+            match ack {
+                Some((SwbusErrorCode::Ok, _)) => return Ok(()),
+                Some((SwbusErrorCode::QueueFull, _)) => return self.wait_until_taken(id, ack_rx).await,
+                Some((error_code, error_message)) => return Err(response_error(error_code, error_message)),
+                None if retry < self.config.retries => {
+                    debug!("Resending stream chunk {id}");
+                    retry += 1;
+                }
+                None => {
+                    return Err(timeout_error(format!(
+                        "stream chunk {id} was not acked within {:?}",
+                        self.config.ack_timeout
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Wait for the ack of chunk `id`, which the receiver holds.
+    async fn wait_until_taken(&self, id: MessageId, ack_rx: &mut mpsc::UnboundedReceiver<Ack>) -> Result<()> {
+        loop {
+            match timeout(self.config.idle_timeout, ack_rx.recv()).await {
+                Ok(Some((SwbusErrorCode::Ok, _))) => return Ok(()),
+                Ok(Some((SwbusErrorCode::QueueFull, _))) => {}
+                Ok(Some((error_code, error_message))) => return Err(response_error(error_code, error_message)),
+                _ => {
+                    return Err(timeout_error(format!(
+                        "stream chunk {id} was not taken within {:?}",
+                        self.config.idle_timeout
+                    )))
+                }
+            }
+        }
+    }
+
+    fn close(&self, key: &StreamKey, done: bool) {
+        self.incoming.lock().unwrap().remove(key);
+        self.closed.lock().unwrap().insert(key.clone(), (Instant::now(), done));
+    }
+}
+
+/// Sends a stream, see [`DataStreams::open`].
+pub struct StreamSender {
+    endpoint: Arc<Endpoint>,
+    destination: ServicePath,
+    stream_id: u64,
+    next_seq: u64,
+    in_flight: JoinSet<Result<()>>,
+    failed: bool,
+}
+
+impl StreamSender {
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Send `data` in chunks, waiting for acks while the window is full. Returns once the last chunk is
+    /// sent, not acked. The stream fails if a chunk fails, and nothing more can be sent on it.
+    pub async fn send(&mut self, data: impl Into<Bytes>) -> Result<()> {
+        let mut data: Bytes = data.into();
+        while !data.is_empty() {
+            let payload = data.split_to(data.len().min(self.endpoint.config.chunk_size.max(1)));
+            self.send_chunk(payload, false).await?;
+        }
+        Ok(())
+    }
+
+    /// End the stream, and wait until the receiver took all of it.
+    pub async fn finish(mut self) -> Result<()> {
+        self.send_chunk(Bytes::new(), true).await?;
+        while let Some(res) = self.in_flight.join_next().await {
+            joined(res)?;
+        }
+        Ok(())
+    }
+
+    async fn send_chunk(&mut self, payload: Bytes, end: bool) -> Result<()> {
+        if self.failed {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!("stream {} failed", self.stream_id),
+            ));
+        }
+        let window = self.endpoint.config.window.max(1);
+        while self.in_flight.len() >= window as usize {
+            if let Some(res) = self.in_flight.join_next().await {
+                if let Err(e) = joined(res) {
+                    self.failed = true;
+                    return Err(e);
+                }
+            }
+        }
+
+        let chunk = StreamChunk {
+            stream_id: self.stream_id,
+            seq: self.next_seq,
+            payload,
+            end,
+            window,
+        };
+        self.next_seq += 1;
+        let id = self.endpoint.id_generator.generate();
+        let header = SwbusMessageHeader::new(self.endpoint.sp.clone(), self.destination.clone(), id);
+        let msg = SwbusMessage::new(header, Body::DataRequest(DataRequest::new(chunk.encode_to_vec())));
+        let endpoint = self.endpoint.clone();
+        self.in_flight.spawn(async move { endpoint.deliver(id, msg).await });
+        Ok(())
+    }
+}
+
+fn joined(res: std::result::Result<Result<()>, JoinError>) -> Result<()> {
+    res.map_err(|e| SwbusError::internal(SwbusErrorCode::Fail, format!("stream chunk task failed: {e}")))?
+}
+
+fn timeout_error(detail: String) -> SwbusError {
+    SwbusError::connection(SwbusErrorCode::Timeout, io::Error::new(io::ErrorKind::TimedOut, detail))
+}
+
+/// Receives a stream, see [`DataStreams::accept`]. The stream is closed when it is dropped, and the
+/// chunks the sender sends after that are refused.
+pub struct StreamReceiver {
+    endpoint: Arc<Endpoint>,
+    key: StreamKey,
+    incoming: Arc<Incoming>,
+    done: bool,
+}
+
+impl StreamReceiver {
+    /// The service path of the sender
+    pub fn source(&self) -> &ServicePath {
+        &self.key.0
+    }
+
+    pub fn stream_id(&self) -> u64 {
+        self.key.1
+    }
+
+    /// The data of the next chunk, or None at the end of the stream. Fails if the next chunk doesn't
+    /// arrive within [`StreamConfig::idle_timeout`], which closes the stream.
+    pub async fn recv(&mut self) -> Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+        loop {
+            let next = {
+                let mut state = self.incoming.state.lock().unwrap();
+                let next_seq = state.next_seq;
+                let next = state.held.remove(&next_seq);
+                if next.is_some() {
+                    state.next_seq += 1;
+                }
+                next
+            };
+            if let Some((chunk, request)) = next {
+                self.endpoint.respond(&request, SwbusErrorCode::Ok, "").await;
+                if chunk.end {
+                    self.done = true;
+                    self.endpoint.close(&self.key, true);
+                    return Ok(None);
+                }
+                return Ok(Some(chunk.payload));
+            }
+            if timeout(self.endpoint.config.idle_timeout, self.incoming.arrived.notified())
+                .await
+                .is_err()
+            {
+                self.done = true;
+                self.endpoint.close(&self.key, false);
+                return Err(timeout_error(format!(
+                    "no chunk of stream {} within {:?}",
+                    self.key.1, self.endpoint.config.idle_timeout
+                )));
+            }
+        }
+    }
+
+    /// The rest of the data of the stream.
+    pub async fn read_to_end(&mut self) -> Result<Bytes> {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = self.recv().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+}
+
+impl Drop for StreamReceiver {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.endpoint.close(&self.key, false);
+        // refuse the held chunks, so the sender doesn't wait for them to be taken
+        let held = std::mem::take(&mut self.incoming.state.lock().unwrap().held);
+        if held.is_empty() {
+            return;
+        }
+        let endpoint = self.endpoint.clone();
+        let message = format!("stream {} was closed by the receiver", self.key.1);
+        tokio::spawn(async move {
+            for (_, request) in held.into_values() {
+                endpoint
+                    .respond(&request, SwbusErrorCode::ResourceNotFound, &message)
+                    .await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn runtime() -> Arc<SwbusEdgeRuntime> {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.1.0-dpu0/hamgrd/0").unwrap();
+        let mut rt = SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp);
+        rt.start().await.unwrap();
+        Arc::new(rt)
+    }
+
+    /// The request id and error code of the next response in `responses`.
+    async fn next_response(responses: &mut Receiver<SwbusMessage>) -> (MessageId, SwbusErrorCode) {
+        let Some(Body::Response(response)) = responses.recv().await.unwrap().body else {
+            panic!("expected a response");
+        };
+        (response.request_id, response.error_code())
+    }
+
+    fn small_chunks(window: u32) -> StreamConfig {
+        StreamConfig {
+            chunk_size: 10,
+            window,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_are_transferred_both_ways() {
+        let rt = runtime().await;
+        let (a_sp, b_sp) = (rt.new_sp("test", "a"), rt.new_sp("test", "b"));
+        let mut a = DataStreams::new(rt.clone(), a_sp.clone(), small_chunks(4));
+        let mut b = DataStreams::new(rt.clone(), b_sp.clone(), small_chunks(4));
+        let to_b_data: Vec<u8> = (0..1007).map(|i| (i % 251) as u8).collect();
+        let to_a_data: Vec<u8> = (0..503).map(|i| (i % 13) as u8).collect();
+
+        let mut to_b = a.open(&b_sp);
+        let mut to_a = b.open(&a_sp);
+        let sending_to_b = tokio::spawn({
+            let data = to_b_data.clone();
+            async move {
+                to_b.send(data).await?;
+                to_b.finish().await
+            }
+        });
+        let sending_to_a = tokio::spawn({
+            let data = to_a_data.clone();
+            async move {
+                to_a.send(data).await?;
+                to_a.finish().await
+            }
+        });
+
+        let mut from_a = b.accept().await.unwrap();
+        let mut from_b = a.accept().await.unwrap();
+        assert_eq!(from_a.source(), &a_sp);
+        assert_eq!(from_b.source(), &b_sp);
+        let (received_by_b, received_by_a) = tokio::join!(from_a.read_to_end(), from_b.read_to_end());
+        assert_eq!(received_by_b.unwrap(), to_b_data);
+        assert_eq!(received_by_a.unwrap(), to_a_data);
+        sending_to_b.await.unwrap().unwrap();
+        sending_to_a.await.unwrap().unwrap();
+        assert_eq!(from_a.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn window_holds_up_the_sender() {
+        let rt = runtime().await;
+        let a = DataStreams::new(rt.clone(), rt.new_sp("test", "a"), small_chunks(4));
+        let b_sp = rt.new_sp("test", "b");
+        let mut b = DataStreams::new(rt.clone(), b_sp.clone(), small_chunks(4));
+
+        let mut sender = a.open(&b_sp);
+        let sending = tokio::spawn(async move {
+            sender.send(vec![7u8; 100]).await?;
+            sender.finish().await
+        });
+        let mut receiver = b.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(receiver.incoming.state.lock().unwrap().held.len(), 4);
+        assert!(!sending.is_finished());
+
+        assert_eq!(receiver.read_to_end().await.unwrap(), vec![7u8; 100]);
+        sending.await.unwrap().unwrap();
+
+        // a larger window than the receiver's is refused
+        let a = DataStreams::new(rt.clone(), rt.new_sp("test", "c"), small_chunks(8));
+        let mut sender = a.open(&b_sp);
+        sender.send(vec![7u8; 10]).await.unwrap();
+        assert!(matches!(
+            sender.finish().await,
+            Err(SwbusError::InputError {
+                code: SwbusErrorCode::InvalidArgs,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn chunks_are_taken_in_order() {
+        let rt = runtime().await;
+        let (source, b_sp) = (rt.new_sp("test", "raw"), rt.new_sp("test", "b"));
+        let (handler_tx, mut responses) = channel(STREAM_QUEUE_SIZE);
+        rt.add_handler(source.clone(), handler_tx);
+        let mut b = DataStreams::new(rt.clone(), b_sp.clone(), small_chunks(4));
+        let send_chunk = |id: MessageId, seq: u64, payload: &'static [u8], end: bool| {
+            let chunk = StreamChunk {
+                stream_id: 1,
+                seq,
+                payload: Bytes::from_static(payload),
+                end,
+                window: 4,
+            };
+            let header = SwbusMessageHeader::new(source.clone(), b_sp.clone(), id);
+            let msg = SwbusMessage::new(header, Body::DataRequest(DataRequest::new(chunk.encode_to_vec())));
+            let rt = rt.clone();
+            async move { rt.send(msg).await.unwrap() }
+        };
+        send_chunk(2, 1, b"world", false).await;
+        send_chunk(1, 0, b"hello ", false).await;
+        // sent again while it is held
+        send_chunk(2, 1, b"world", false).await;
+        assert_eq!(next_response(&mut responses).await, (2, SwbusErrorCode::QueueFull));
+
+        let mut receiver = b.accept().await.unwrap();
+        assert_eq!(receiver.stream_id(), 1);
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), &b"hello "[..]);
+        assert_eq!(next_response(&mut responses).await, (1, SwbusErrorCode::Ok));
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), &b"world"[..]);
+        assert_eq!(next_response(&mut responses).await, (2, SwbusErrorCode::Ok));
+
+        // sent again after it was taken
+        send_chunk(1, 0, b"hello ", false).await;
+        assert_eq!(next_response(&mut responses).await, (1, SwbusErrorCode::Ok));
+        // out of the window
+        send_chunk(3, 6, b"!", false).await;
+        assert_eq!(next_response(&mut responses).await, (3, SwbusErrorCode::InvalidArgs));
+
+        send_chunk(4, 2, b"", true).await;
+        assert_eq!(receiver.recv().await.unwrap(), None);
+        assert_eq!(next_response(&mut responses).await, (4, SwbusErrorCode::Ok));
+    }
+}
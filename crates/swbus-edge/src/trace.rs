@@ -0,0 +1,83 @@
+//! Trace ids, which correlate the messages of one operation across swbus-edge clients, swbusd hops and
+//! actors, see [`SwbusMessageHeader::trace_id`](swbus_proto::swbus::SwbusMessageHeader::trace_id).
+//!
+//! A message sent without a trace id gets the one of the [`scope`] it is sent from, i.e. the trace id of
+//! the message the task is handling, or a new one if it starts an operation.
+use std::future::Future;
+use swbus_proto::swbus::SwbusMessage;
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// The trace id of the current [`scope`], if any.
+pub fn current() -> Option<String> {
+    TRACE_ID
+        .try_with(|trace_id| trace_id.clone())
+        .ok()
+        .filter(|trace_id| !trace_id.is_empty())
+}
+
+/// Run `f` with `trace_id` as the trace id of the messages it sends.
+pub async fn scope<F: Future>(trace_id: String, f: F) -> F::Output {
+    TRACE_ID.scope(trace_id, f).await
+}
+
+/// A trace id for a new operation.
+pub fn new_trace_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Give `msg` the trace id of the current scope, or a new one, unless it has one already.
+pub(crate) fn stamp(msg: &mut SwbusMessage) {
+    let Some(header) = msg.header.as_mut() else {
+        return;
+    };
+    if header.trace_id.is_empty() {
+        header.trace_id = current().unwrap_or_else(new_trace_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::{ServicePath, SwbusMessageHeader};
+
+    fn message(trace_id: &str) -> SwbusMessage {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/actor/0").unwrap();
+        let mut header = SwbusMessageHeader::new(sp.clone(), sp, 1);
+        header.trace_id = trace_id.to_string();
+        SwbusMessage {
+            header: Some(header),
+            body: None,
+        }
+    }
+
+    fn trace_id(msg: &SwbusMessage) -> &str {
+        &msg.header.as_ref().unwrap().trace_id
+    }
+
+    #[tokio::test]
+    async fn messages_get_the_trace_id_of_their_scope() {
+        // outside of a scope, every message starts a trace of its own
+        let (mut first, mut second) = (message(""), message(""));
+        stamp(&mut first);
+        stamp(&mut second);
+        assert!(!trace_id(&first).is_empty());
+        assert_ne!(trace_id(&first), trace_id(&second));
+
+        scope("trace-0".to_string(), async {
+            assert_eq!(current().as_deref(), Some("trace-0"));
+            let mut msg = message("");
+            stamp(&mut msg);
+            assert_eq!(trace_id(&msg), "trace-0");
+
+            // a message keeps the trace id it has
+            let mut msg = message("trace-1");
+            stamp(&mut msg);
+            assert_eq!(trace_id(&msg), "trace-1");
+        })
+        .await;
+        assert_eq!(current(), None);
+    }
+}
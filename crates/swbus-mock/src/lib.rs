@@ -0,0 +1,486 @@
+//! A scriptable in-process swbusd for tests.
+//!
+//! [`MockSwbusd`] accepts connections from swbus-edge runtimes the way swbusd does and forwards
+//! messages between them by service prefix. Tests can program routes, delay messages and make the
+//! bus fail them, and can check every message that went through it.
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::recording::RecordedMessage;
+use swbus_proto::swbus::swbus_service_server::{SwbusService, SwbusServiceServer};
+use swbus_proto::swbus::*;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::*;
+
+type SwbusMessageStream = Pin<Box<dyn Stream<Item = Result<SwbusMessage, Status>> + Send>>;
+
+/// What the mock does with a message to a route.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteAction {
+    /// Forward to the client connected with this service prefix, e.g. "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0".
+    Forward(String),
+    /// Answer with an error response, the way swbusd answers a message it can't deliver.
+    Reject(SwbusErrorCode),
+    /// Drop without an answer, like a message lost on a broken link.
+    Drop,
+}
+
+struct Client {
+    conn_id: u64,
+    queue: mpsc::Sender<Result<SwbusMessage, Status>>,
+}
+
+#[derive(Default)]
+struct Bus {
+    next_conn_id: u64,
+    // connected clients by service prefix
+    clients: HashMap<String, Client>,
+    // programmed routes by prefix. They take precedence over the clients.
+    routes: HashMap<String, RouteAction>,
+    latency: Duration,
+    faults: VecDeque<SwbusErrorCode>,
+    messages: Vec<SwbusMessage>,
+}
+
+struct Inner {
+    sp: ServicePath,
+    bus: Mutex<Bus>,
+    id_generator: MessageIdGenerator,
+    // notified when a client connects or a message goes through the bus
+    changed: Notify,
+}
+
+impl Inner {
+    fn action(bus: &Bus, destination: &ServicePath) -> Option<RouteAction> {
+        let route_keys = [
+            destination.to_service_prefix(),
+            destination.to_node_prefix(),
+            destination.to_cluster_prefix(),
+            destination.to_regional_prefix(),
+        ];
+        route_keys.into_iter().find_map(|key| match bus.routes.get(&key) {
+            Some(action) => Some(action.clone()),
+            None => bus.clients.contains_key(&key).then_some(RouteAction::Forward(key)),
+        })
+    }
+
+    /// Forward a message from a client after the configured latency.
+    async fn forward(&self, message: SwbusMessage) {
+        let latency = self.bus.lock().unwrap().latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        self.route(message).await;
+    }
+
+    async fn route(&self, mut message: SwbusMessage) {
+        loop {
+            let Some(destination) = message.header.as_ref().and_then(|h| h.destination.clone()) else {
+                warn!("Dropping message without destination");
+                return;
+            };
+
+            let (queue, error) = {
+                let mut bus = self.bus.lock().unwrap();
+                bus.messages.push(message.clone());
+                let fault = bus.faults.pop_front();
+                match (fault, Self::action(&bus, &destination)) {
+                    (Some(code), _) => (None, Some(code)),
+                    (None, Some(RouteAction::Forward(client))) => match bus.clients.get(&client) {
+                        Some(client) => (Some(client.queue.clone()), None),
+                        None => (None, Some(SwbusErrorCode::NoRoute)),
+                    },
+                    (None, Some(RouteAction::Reject(code))) => (None, Some(code)),
+                    (None, Some(RouteAction::Drop)) => (None, None),
+                    (None, None) => (None, Some(SwbusErrorCode::NoRoute)),
+                }
+            };
+            self.changed.notify_waiters();
+
+            if let Some(queue) = queue {
+                if queue.send(Ok(message)).await.is_err() {
+                    debug!("Client of {} is gone, dropping message", destination.to_longest_path());
+                }
+                return;
+            }
+            // route the error response back to the sender
+            match error.and_then(|code| self.reject(&message, code)) {
+                Some(response) => message = response,
+                None => return,
+            }
+        }
+    }
+
+    fn reject(&self, message: &SwbusMessage, code: SwbusErrorCode) -> Option<SwbusMessage> {
+        // never answer a response, or responses could bounce forever
+        let has_source = message.header.as_ref().is_some_and(|h| h.source.is_some());
+        if !has_source || matches!(message.body, Some(swbus_message::Body::Response(_))) {
+            return None;
+        }
+        Some(SwbusMessage::new_response(
+            message,
+            Some(&self.sp),
+            code,
+            "Rejected by mock swbusd",
+            self.id_generator.generate(),
+            None,
+        ))
+    }
+}
+
+struct MockService {
+    inner: Arc<Inner>,
+}
+
+#[tonic::async_trait]
+impl SwbusService for MockService {
+    type StreamMessagesStream = SwbusMessageStream;
+
+    async fn stream_messages(
+        &self,
+        request: Request<Streaming<SwbusMessage>>,
+    ) -> Result<Response<SwbusMessageStream>, Status> {
+        let Some(client) = request
+            .metadata()
+            .get(SWBUS_CLIENT_SERVICE_PATH)
+            .and_then(|path| path.to_str().ok())
+            .map(String::from)
+        else {
+            return Err(Status::invalid_argument("Client service path not found"));
+        };
+        let mut in_stream = request.into_inner();
+        let (out_tx, out_rx) = mpsc::channel(16);
+
+        let conn_id = {
+            let mut bus = self.inner.bus.lock().unwrap();
+            bus.next_conn_id += 1;
+            let conn_id = bus.next_conn_id;
+            bus.clients.insert(client.clone(), Client { conn_id, queue: out_tx });
+            conn_id
+        };
+        info!("Client {client} connected");
+        self.inner.changed.notify_waiters();
+
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = in_stream.next().await {
+                inner.forward(message).await;
+            }
+            info!("Client {client} disconnected");
+            let mut bus = inner.bus.lock().unwrap();
+            // the client may have reconnected meanwhile
+            if bus.clients.get(&client).is_some_and(|c| c.conn_id == conn_id) {
+                bus.clients.remove(&client);
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(out_rx)) as SwbusMessageStream
+        ))
+    }
+}
+
+/// An in-process swbusd listening on a free local port. It stops when dropped.
+pub struct MockSwbusd {
+    inner: Arc<Inner>,
+    addr: SocketAddr,
+    server: JoinHandle<()>,
+}
+
+impl MockSwbusd {
+    /// Start a mock swbusd. `sp` is its own service path, the source of the error responses it sends.
+    pub async fn start(sp: ServicePath) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let inner = Arc::new(Inner {
+            sp,
+            bus: Mutex::new(Bus::default()),
+            id_generator: MessageIdGenerator::new(),
+            changed: Notify::new(),
+        });
+
+        let service = MockService { inner: inner.clone() };
+        let server = tokio::spawn(async move {
+            let res = Server::builder()
+                .add_service(SwbusServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await;
+            if let Err(e) = res {
+                error!("Mock swbusd failed: {e}");
+            }
+        });
+        info!("Mock swbusd listening at {addr}");
+        Ok(Self { inner, addr, server })
+    }
+
+    /// The URI to connect swbus-edge runtimes to.
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Program a route. `prefix` is matched the way swbusd matches routes: the service prefix of the
+    /// destination first, then its node, cluster and region prefixes.
+    pub fn set_route(&self, prefix: &str, action: RouteAction) {
+        self.inner.bus.lock().unwrap().routes.insert(prefix.to_string(), action);
+    }
+
+    pub fn remove_route(&self, prefix: &str) {
+        self.inner.bus.lock().unwrap().routes.remove(prefix);
+    }
+
+    /// Delay every message from a client by `latency`. Messages from a client stay in order.
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.bus.lock().unwrap().latency = latency;
+    }
+
+    /// Reject the next `count` messages with `code`, whatever their route.
+    pub fn fail_next(&self, count: usize, code: SwbusErrorCode) {
+        let mut bus = self.inner.bus.lock().unwrap();
+        bus.faults.extend(std::iter::repeat_n(code, count));
+    }
+
+    /// Close the connection of a client, which makes it reconnect.
+    pub fn disconnect(&self, client: &str) {
+        self.inner.bus.lock().unwrap().clients.remove(client);
+    }
+
+    /// Send a message into the bus as if it came from a client.
+    pub async fn inject(&self, message: SwbusMessage) {
+        self.inner.forward(message).await;
+    }
+
+    /// Inject the messages of a recording made by `swbus-cli capture`, keeping their timing.
+    /// `speed` scales the timing, e.g. 2.0 replays twice as fast as the messages were captured.
+    pub async fn replay(&self, recording: &[RecordedMessage], speed: f64) {
+        let start = tokio::time::Instant::now();
+        for recorded in recording {
+            tokio::time::sleep_until(start + Duration::from_millis(recorded.time_ms).div_f64(speed)).await;
+            self.inject(recorded.message.clone()).await;
+        }
+    }
+
+    /// All messages that went through the bus so far, including the responses of the mock.
+    pub fn messages(&self) -> Vec<SwbusMessage> {
+        self.inner.bus.lock().unwrap().messages.clone()
+    }
+
+    pub fn clear_messages(&self) {
+        self.inner.bus.lock().unwrap().messages.clear();
+    }
+
+    /// Wait until a client with service prefix `client` is connected.
+    pub async fn wait_for_client(&self, client: &str, timeout: Duration) -> bool {
+        self.wait_until(timeout, |bus| bus.clients.contains_key(client).then_some(()))
+            .await
+            .is_some()
+    }
+
+    /// Wait for a message that matches `pred` to go through the bus, or return one that already has.
+    pub async fn wait_for_message(
+        &self,
+        timeout: Duration,
+        pred: impl Fn(&SwbusMessage) -> bool,
+    ) -> Option<SwbusMessage> {
+        self.wait_until(timeout, |bus| bus.messages.iter().find(|m| pred(m)).cloned())
+            .await
+    }
+
+    async fn wait_until<T>(&self, timeout: Duration, check: impl Fn(&Bus) -> Option<T>) -> Option<T> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                let changed = self.inner.changed.notified();
+                if let Some(found) = check(&self.inner.bus.lock().unwrap()) {
+                    return found;
+                }
+                changed.await;
+            }
+        })
+        .await
+        .ok()
+    }
+}
+
+impl Drop for MockSwbusd {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonic_common::log::init_logger_for_test;
+    use swbus_edge::SwbusEdgeRuntime;
+    use tokio::time::{timeout, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct Edge {
+        runtime: SwbusEdgeRuntime,
+        sp: ServicePath,
+        rx: mpsc::Receiver<SwbusMessage>,
+    }
+
+    async fn start_mock() -> MockSwbusd {
+        init_logger_for_test();
+        MockSwbusd::start(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Connect an edge runtime with a handler at <node>/hamgrd/<service_id>/test/0
+    async fn connect_edge(mock: &MockSwbusd, service_id: &str) -> Edge {
+        let base_sp =
+            ServicePath::from_string(&format!("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/{service_id}")).unwrap();
+        let mut runtime = SwbusEdgeRuntime::new(mock.uri(), base_sp.clone());
+        runtime.start().await.unwrap();
+        let sp = runtime.new_sp("test", "0");
+        let (tx, rx) = mpsc::channel(16);
+        runtime.add_handler(sp.clone(), tx);
+        assert!(mock.wait_for_client(&base_sp.to_service_prefix(), TIMEOUT).await);
+        wait_connected(&runtime).await;
+        Edge { runtime, sp, rx }
+    }
+
+    async fn wait_connected(runtime: &SwbusEdgeRuntime) {
+        let start = Instant::now();
+        while !runtime.swbusd_connected().await {
+            assert!(start.elapsed() < TIMEOUT, "swbusd is not connected");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn send(from: &Edge, to: &Edge, id: u64) {
+        let header = SwbusMessageHeader::new(from.sp.clone(), to.sp.clone(), id);
+        let message = SwbusMessage::new(header, swbus_message::Body::DataRequest(DataRequest::default()));
+        from.runtime.send(message).await.unwrap();
+    }
+
+    async fn recv(edge: &mut Edge) -> SwbusMessage {
+        timeout(TIMEOUT, edge.rx.recv()).await.unwrap().unwrap()
+    }
+
+    fn error_code(message: &SwbusMessage) -> Option<SwbusErrorCode> {
+        match message.body {
+            Some(swbus_message::Body::Response(ref response)) => SwbusErrorCode::try_from(response.error_code).ok(),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_between_clients() {
+        let mock = start_mock().await;
+        let a = connect_edge(&mock, "0").await;
+        let mut b = connect_edge(&mock, "1").await;
+
+        send(&a, &b, 1).await;
+        let message = recv(&mut b).await;
+        assert_eq!(message.header.unwrap().id, 1);
+        assert!(mock
+            .wait_for_message(TIMEOUT, |m| m.header.as_ref().unwrap().id == 1)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn programmed_routes() {
+        let mock = start_mock().await;
+        let mut a = connect_edge(&mock, "0").await;
+        let mut b = connect_edge(&mock, "1").await;
+
+        mock.set_route(
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1",
+            RouteAction::Reject(SwbusErrorCode::Unreachable),
+        );
+        send(&a, &b, 1).await;
+        assert_eq!(error_code(&recv(&mut a).await), Some(SwbusErrorCode::Unreachable));
+
+        mock.set_route("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1", RouteAction::Drop);
+        send(&a, &b, 2).await;
+        assert!(mock
+            .wait_for_message(TIMEOUT, |m| m.header.as_ref().unwrap().id == 2)
+            .await
+            .is_some());
+        assert!(timeout(Duration::from_millis(200), b.rx.recv()).await.is_err());
+
+        // forward to a client that is not connected
+        mock.set_route(
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1",
+            RouteAction::Forward("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/9".to_string()),
+        );
+        send(&a, &b, 3).await;
+        assert_eq!(error_code(&recv(&mut a).await), Some(SwbusErrorCode::NoRoute));
+
+        mock.remove_route("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1");
+        send(&a, &b, 4).await;
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 4);
+    }
+
+    #[tokio::test]
+    async fn inject_faults_and_latency() {
+        let mock = start_mock().await;
+        let mut a = connect_edge(&mock, "0").await;
+        let mut b = connect_edge(&mock, "1").await;
+
+        mock.fail_next(1, SwbusErrorCode::QueueFull);
+        send(&a, &b, 1).await;
+        send(&a, &b, 2).await;
+        assert_eq!(error_code(&recv(&mut a).await), Some(SwbusErrorCode::QueueFull));
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 2);
+
+        mock.set_latency(Duration::from_millis(300));
+        let start = Instant::now();
+        send(&a, &b, 3).await;
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 3);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn replay_recording() {
+        let mock = start_mock().await;
+        let a = connect_edge(&mock, "0").await;
+        let mut b = connect_edge(&mock, "1").await;
+
+        let recording: Vec<RecordedMessage> = [(1, 0), (2, 400)]
+            .into_iter()
+            .map(|(id, time_ms)| RecordedMessage {
+                time_ms,
+                direction: None,
+                message: SwbusMessage::new(
+                    SwbusMessageHeader::new(a.sp.clone(), b.sp.clone(), id),
+                    swbus_message::Body::DataRequest(DataRequest::default()),
+                ),
+            })
+            .collect();
+
+        let start = Instant::now();
+        mock.replay(&recording, 2.0).await;
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 1);
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 2);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn client_reconnects_after_disconnect() {
+        let mock = start_mock().await;
+        let a = connect_edge(&mock, "0").await;
+        let mut b = connect_edge(&mock, "1").await;
+
+        mock.disconnect("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1");
+        assert!(
+            mock.wait_for_client("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1", TIMEOUT)
+                .await
+        );
+        wait_connected(&b.runtime).await;
+        send(&a, &b, 1).await;
+        assert_eq!(recv(&mut b).await.header.unwrap().id, 1);
+    }
+}
@@ -1,5 +1,5 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // skiping serializing epoch field in SwbusMessageHeader and nh_id in RouteQueryResultEntry for testing because they are not deterministic.
+    // skiping serializing epoch field in SwbusMessageHeader, nh_id in RouteQueryResultEntry and timestamp_in_us in TraceRouteHop for testing because they are not deterministic.
     let builder = tonic_build::configure()
         .enum_attribute("swbus.SwbusErrorCode", "#[derive(strum::Display)]")
         .enum_attribute("swbus.RouteScope", "#[derive(strum::Display)]")
@@ -7,11 +7,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .message_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .enum_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .message_attribute("swbus.ServicePath", "#[derive(Eq, Hash, Ord, PartialOrd)]")
+        // payloads are shared instead of copied when messages are cloned, and decoded without a copy
+        .bytes([".swbus.DataRequest.payload", ".swbus.StreamChunk.payload"])
         .field_attribute("swbus.SwbusMessageHeader.id", "#[serde(default, skip_serializing)]")
         .field_attribute(
             "swbus.RouteQueryResultEntry.nh_id",
             "#[serde(default, skip_serializing)]",
         )
+        .field_attribute("swbus.TraceRouteRequest.hops", "#[serde(default)]")
+        .field_attribute(
+            "swbus.TraceRouteHop.timestamp_in_us",
+            "#[serde(default, skip_serializing)]",
+        )
         .field_attribute(
             "swbus.RouteQueryResult.entries",
             "#[serde(serialize_with = \"sorted_vec_serializer\")]",
@@ -28,6 +35,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "swbus.RouteQueryResultEntry.service_path",
             "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
         )
+        .field_attribute(
+            "swbus.TraceRouteHop.service_path",
+            "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
+        )
         .field_attribute(
             "swbus.RouteQueryResultEntry.nh_service_path",
             "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
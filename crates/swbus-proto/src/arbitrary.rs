@@ -0,0 +1,77 @@
+//! proptest strategies for service paths and route scopes.
+//!
+//! Enabled in dependent crates' tests with the `proptest` feature.
+use crate::swbus::{RouteScope, ServicePath};
+use proptest::prelude::*;
+
+/// A region, cluster, service or resource id. Never empty.
+pub fn id() -> impl Strategy<Value = String> {
+    "[a-z0-9-]{1,8}"
+}
+
+/// A node id. Unlike the other ids it can contain dots, like "10.0.0.1-dpu0".
+pub fn node_id() -> impl Strategy<Value = String> {
+    "[a-z0-9-]{1,4}(\\.[a-z0-9-]{1,4}){0,3}"
+}
+
+/// A service path in the form [`ServicePath::from_string`] accepts: the locator and the service
+/// part are each filled from the left, so it prints back to the same string with
+/// [`ServicePath::to_longest_path`].
+pub fn service_path() -> impl Strategy<Value = ServicePath> {
+    (full_service_path(), 0..=3usize, 0..=4usize).prop_map(|(mut sp, loc_len, rsc_len)| {
+        let loc = [&mut sp.region_id, &mut sp.cluster_id, &mut sp.node_id];
+        loc.into_iter().skip(loc_len).for_each(String::clear);
+        let rsc = [
+            &mut sp.service_type,
+            &mut sp.service_id,
+            &mut sp.resource_type,
+            &mut sp.resource_id,
+        ];
+        rsc.into_iter().skip(rsc_len).for_each(String::clear);
+        sp
+    })
+}
+
+/// A service path with every field set.
+pub fn full_service_path() -> impl Strategy<Value = ServicePath> {
+    (id(), id(), node_id(), id(), id(), id(), id()).prop_map(
+        |(region_id, cluster_id, node_id, service_type, service_id, resource_type, resource_id)| ServicePath {
+            region_id,
+            cluster_id,
+            node_id,
+            service_type,
+            service_id,
+            resource_type,
+            resource_id,
+        },
+    )
+}
+
+/// Any service path that can be represented as a string, including ones with gaps such as an
+/// empty cluster id under a set node id. Fields don't contain '/', and region and cluster ids
+/// don't contain '.'.
+pub fn any_service_path() -> impl Strategy<Value = ServicePath> {
+    let loc = "[a-z0-9-]{0,8}";
+    let field = "[a-z0-9.-]{0,8}";
+    (loc, loc, field, field, field, field, field).prop_map(
+        |(region_id, cluster_id, node_id, service_type, service_id, resource_type, resource_id)| ServicePath {
+            region_id,
+            cluster_id,
+            node_id,
+            service_type,
+            service_id,
+            resource_type,
+            resource_id,
+        },
+    )
+}
+
+pub fn route_scope() -> impl Strategy<Value = RouteScope> {
+    prop_oneof![
+        Just(RouteScope::Client),
+        Just(RouteScope::Local),
+        Just(RouteScope::Cluster),
+        Just(RouteScope::Region),
+        Just(RouteScope::Global),
+    ]
+}
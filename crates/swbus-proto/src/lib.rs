@@ -1,3 +1,6 @@
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
 pub mod message_id_generator;
+pub mod recording;
 pub mod result;
 pub mod swbus;
@@ -0,0 +1,81 @@
+//! Recordings of swbus traffic captured from swbusd or recorded by an edge runtime, to replay in tests.
+//!
+//! A recording is a JSON lines file with one [`RecordedMessage`] per line, in the order the
+//! messages were captured.
+use crate::swbus::SwbusMessage;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedMessage {
+    /// Milliseconds from the start of the recording
+    pub time_ms: u64,
+    /// Whether the message was sent or received by the clients of the edge runtime that recorded it. Not
+    /// set for messages captured from swbusd.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    pub message: SwbusMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+pub fn write_message(writer: &mut impl Write, message: &RecordedMessage) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")
+}
+
+pub fn read_recording(reader: impl BufRead) -> io::Result<Vec<RecordedMessage>> {
+    let mut messages = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        messages.push(serde_json::from_str(&line)?);
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swbus::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn recording_round_trip() {
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/ha-scope/vdpu0:haset0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/ha-scope/vdpu1:haset0").unwrap(),
+            1,
+        );
+        let recording = vec![
+            RecordedMessage {
+                time_ms: 0,
+                direction: None,
+                message: SwbusMessage::new(
+                    header.clone(),
+                    swbus_message::Body::DataRequest(DataRequest::new(vec![1, 2])),
+                ),
+            },
+            RecordedMessage {
+                time_ms: 120,
+                direction: Some(Direction::Received),
+                message: SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new())),
+            },
+        ];
+
+        let mut file = Vec::new();
+        for message in &recording {
+            write_message(&mut file, message).unwrap();
+        }
+        assert_eq!(String::from_utf8_lossy(&file).lines().count(), 2);
+        assert_eq!(read_recording(file.as_slice()).unwrap(), recording);
+    }
+}
@@ -1,4 +1,5 @@
 use super::result::*;
+use bytes::Bytes;
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
@@ -9,6 +10,21 @@ use crate::swbus::request_response::ResponseBody;
 pub const SWBUS_CLIENT_SERVICE_PATH: &str = "x-swbus-service-path";
 /// Service path scope of the connection
 pub const SWBUS_CONNECTION_TYPE: &str = "x-swbus-connection-type";
+/// Secret token a client presents in gRPC request meta data, when swbusd authenticates its clients
+pub const SWBUS_AUTH_TOKEN: &str = "x-swbus-auth-token";
+/// Header flag of data requests that swbusd sends ahead of the others, like other infra messages
+pub const SWBUS_FLAG_HIGH_PRIORITY: u32 = 0x1;
+/// Compression of the connection, in gRPC request meta data offered by the client and in the response
+/// accepted by the server
+pub const SWBUS_COMPRESSION: &str = "x-swbus-compression";
+/// Header flag of data requests whose payload is compressed with zstd
+pub const SWBUS_FLAG_COMPRESSED: u32 = 0x2;
+/// Header flag of messages to a wildcard node id whose source wants to know the destinations they were
+/// copied to, see [`FanOutReport`]
+pub const SWBUS_FLAG_FAN_OUT_REPORT: u32 = 0x4;
+/// Node id matching every node of a cluster. It can also end a node id, like `10.0.0.1-dpu*`, to match
+/// the nodes starting with the rest.
+pub const SWBUS_NODE_WILDCARD: &str = "*";
 
 impl ServicePath {
     /// Create a new region level service path.
@@ -157,6 +173,20 @@ impl ServicePath {
         RouteScope::Client
     }
 
+    /// Whether the node id is a wildcard, so messages to the service path are copied to every node
+    /// of the cluster it matches.
+    pub fn has_node_wildcard(&self) -> bool {
+        self.node_id.ends_with(SWBUS_NODE_WILDCARD)
+    }
+
+    /// Whether the node id, wildcard or not, matches `node_id`.
+    pub fn matches_node(&self, node_id: &str) -> bool {
+        match self.node_id.strip_suffix(SWBUS_NODE_WILDCARD) {
+            Some(prefix) => !node_id.is_empty() && node_id.starts_with(prefix),
+            None => self.node_id == node_id,
+        }
+    }
+
     /// copy the fields from the other service path starting from the first non-empty one
     pub fn join(&mut self, other: &ServicePath) {
         vec![
@@ -294,6 +324,7 @@ where
 /// * SwbusMessageHeader.id
 /// * RouteQueryResultEntry.nh_id
 ///   nh_id includes nexthop IP and port, which is not deterministic.
+/// * TraceRouteHop.timestamp_in_us
 ///
 /// During serialization, vector is also sorted by sorted_vec_serializer to make sure the order is deterministic.
 /// This includes
@@ -314,6 +345,7 @@ impl SwbusMessageHeader {
             ttl: 64,
             source: Some(source),
             destination: Some(destination),
+            trace_id: String::new(),
         }
     }
 }
@@ -378,18 +410,16 @@ impl SwbusMessage {
                 .expect("missing dest service_path"),
         };
 
+        let request_header = request.header.as_ref().unwrap();
+        let mut header = SwbusMessageHeader::new(
+            src_sp,
+            request_header.source.clone().expect("missing source service_path"),
+            request_id,
+        );
+        // the response is part of the same operation as the request
+        header.trace_id = request_header.trace_id.clone();
         SwbusMessage {
-            header: Some(SwbusMessageHeader::new(
-                src_sp,
-                request
-                    .header
-                    .as_ref()
-                    .unwrap()
-                    .source
-                    .clone()
-                    .expect("missing source service_path"),
-                request_id,
-            )),
+            header: Some(header),
             body: Some(swbus_message::Body::Response(request_response)),
         }
     }
@@ -402,7 +432,28 @@ impl PingRequest {
 
 impl TraceRouteRequest {
     pub fn new() -> Self {
-        TraceRouteRequest {}
+        TraceRouteRequest { hops: Vec::new() }
+    }
+
+    /// Append `service_path` to the hops of the request, and return the response body it answers with.
+    pub fn add_hop(&mut self, service_path: ServicePath) -> ResponseBody {
+        self.hops.push(TraceRouteHop::new(service_path));
+        ResponseBody::TraceRouteResult(TraceRouteResult {
+            hops: self.hops.clone(),
+        })
+    }
+}
+
+impl TraceRouteHop {
+    /// A hop at `service_path`, reached now.
+    pub fn new(service_path: ServicePath) -> Self {
+        let timestamp_in_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        TraceRouteHop {
+            service_path: Some(service_path),
+            timestamp_in_us,
+        }
     }
 }
 
@@ -416,8 +467,19 @@ impl ManagementRequest {
 }
 
 impl DataRequest {
-    pub fn new(payload: Vec<u8>) -> Self {
-        DataRequest { payload }
+    pub fn new(payload: impl Into<Bytes>) -> Self {
+        DataRequest {
+            payload: payload.into(),
+        }
+    }
+}
+
+impl RouteChange {
+    pub fn new(change_type: RouteChangeType, service_path: ServicePath) -> Self {
+        RouteChange {
+            change_type: change_type.into(),
+            service_path: Some(service_path),
+        }
     }
 }
 
@@ -426,7 +488,10 @@ mod tests {
     use crate::message_id_generator::MessageIdGenerator;
 
     use super::*;
+    use crate::arbitrary;
     use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+    use prost::Message;
 
     #[test]
     fn service_path_can_be_converted_to_string() {
@@ -473,6 +538,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_node_wildcard() {
+        let all = ServicePath::from_string("region.cluster.*/hamgrd/0").unwrap();
+        assert!(all.has_node_wildcard());
+        assert!(all.matches_node("10.0.0.1-dpu0"));
+        assert!(!all.matches_node(""));
+
+        let dpus = ServicePath::from_string("region.cluster.10.0.0.1-dpu*/hamgrd/0").unwrap();
+        assert!(dpus.has_node_wildcard());
+        assert!(dpus.matches_node("10.0.0.1-dpu3"));
+        assert!(!dpus.matches_node("10.0.0.2-dpu3"));
+
+        let one = ServicePath::from_string("region.cluster.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        assert!(!one.has_node_wildcard());
+        assert!(one.matches_node("10.0.0.1-dpu0"));
+        assert!(!one.matches_node("10.0.0.1-dpu1"));
+    }
+
     #[test]
     fn service_path_join() {
         let mut service_path = ServicePath::from_string("region.cluster.node/stype/sid/rtype/rid").unwrap();
@@ -526,12 +609,50 @@ mod tests {
         test_packing_with_swbus_message(swbus_message::Body::TraceRouteRequest(request));
     }
 
+    #[test]
+    fn trace_route_request_adds_hops() {
+        let mut request = TraceRouteRequest::new();
+        request.add_hop(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap());
+        let ResponseBody::TraceRouteResult(result) =
+            request.add_hop(ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap())
+        else {
+            panic!("unexpected response body");
+        };
+        assert_eq!(result.hops, request.hops);
+        assert_eq!(
+            result.hops[1].service_path,
+            Some(ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap())
+        );
+        assert!(result.hops[0].timestamp_in_us <= result.hops[1].timestamp_in_us);
+
+        // timestamps are not compared after normalization
+        let json = serde_json::to_string(&request).unwrap();
+        let normalized: TraceRouteRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(normalized.hops[0].timestamp_in_us, 0);
+    }
+
     #[test]
     fn route_data_request_can_be_created() {
         let request = DataRequest::new("mock-payload".as_bytes().to_vec());
         test_packing_with_swbus_message(swbus_message::Body::DataRequest(request));
     }
 
+    #[test]
+    fn data_request_payload_is_shared() {
+        let request = DataRequest::new(vec![1, 2, 3]);
+        let copy = request.clone();
+        assert_eq!(copy.payload.as_ptr(), request.payload.as_ptr());
+
+        // decoding from a shared buffer doesn't copy the payload either
+        let encoded = Bytes::from(request.encode_to_vec());
+        let decoded = DataRequest::decode(encoded.clone()).unwrap();
+        assert_eq!(decoded, request);
+        assert!(encoded.as_ptr_range().contains(&decoded.payload.as_ptr()));
+
+        // the JSON form is unchanged
+        assert_eq!(serde_json::to_string(&request).unwrap(), r#"{"payload":[1,2,3]}"#);
+    }
+
     fn create_mock_service_path() -> ServicePath {
         ServicePath {
             region_id: "region".to_string(),
@@ -621,10 +742,9 @@ mod tests {
     }
     #[test]
     fn test_swbus_message_new_response() {
-        let request = SwbusMessage::new(
-            create_mock_swbus_message_header(),
-            swbus_message::Body::PingRequest(PingRequest::new()),
-        );
+        let mut header = create_mock_swbus_message_header();
+        header.trace_id = "trace-0".to_string();
+        let request = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
         let request_id = request.header.as_ref().unwrap().id;
         let src = request.header.as_ref().unwrap().source.as_ref().unwrap().clone();
         let dest = request.header.as_ref().unwrap().destination.as_ref().unwrap().clone();
@@ -633,6 +753,7 @@ mod tests {
         assert_eq!(response.header.as_ref().unwrap().version, 1);
         assert_eq!(response.header.as_ref().unwrap().flag, 0);
         assert_eq!(response.header.as_ref().unwrap().ttl, 64);
+        assert_eq!(response.header.as_ref().unwrap().trace_id, "trace-0");
         assert_eq!(response.header.as_ref().unwrap().source.as_ref().unwrap().clone(), dest);
         assert_eq!(
             response.header.as_ref().unwrap().destination.as_ref().unwrap().clone(),
@@ -646,4 +767,43 @@ mod tests {
 
         // assert_eq!(response.body.as_ref().unwrap().request_, true);
     }
+
+    proptest! {
+        #[test]
+        fn test_service_path_longest_path_round_trip(sp in arbitrary::service_path()) {
+            prop_assert_eq!(ServicePath::from_string(&sp.to_longest_path()).unwrap(), sp);
+        }
+
+        #[test]
+        fn test_service_path_display_round_trip(sp in arbitrary::any_service_path()) {
+            prop_assert_eq!(ServicePath::from_string(&sp.to_string()).unwrap(), sp);
+        }
+
+        #[test]
+        fn test_service_path_from_arbitrary_string(s in any::<String>()) {
+            // paths off the wire must parse to something that prints and parses back the same
+            let sp = ServicePath::from_string(&s).unwrap();
+            prop_assert_eq!(ServicePath::from_string(&sp.to_string()).unwrap(), sp.clone());
+            ServicePath::from_string(&sp.to_longest_path()).unwrap();
+            sp.route_scope();
+        }
+
+        #[test]
+        fn test_route_prefix_scopes(sp in arbitrary::full_service_path()) {
+            // each route prefix is in a narrower scope than the shorter ones
+            let scopes: Vec<RouteScope> = [
+                sp.to_regional_prefix(),
+                sp.to_cluster_prefix(),
+                sp.to_node_prefix(),
+                sp.to_service_prefix(),
+            ]
+            .iter()
+            .map(|prefix| ServicePath::from_string(prefix).unwrap().route_scope())
+            .collect();
+            prop_assert_eq!(
+                scopes,
+                vec![RouteScope::Global, RouteScope::Region, RouteScope::Cluster, RouteScope::Client]
+            );
+        }
+    }
 }
@@ -0,0 +1,78 @@
+//! Reload the swbusd config when the CONFIG_DB tables it is read from change.
+use std::time::Duration;
+use swbus_config::{SwbusConfig, CONFIG_DB_TABLES};
+use swbus_core::mux::service::SwbusConfigHandle;
+use swss_common::{DbConnector, SubscriberStateTable};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Time to wait for more changes after a change, so an update of several tables is applied at once.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Watch the CONFIG_DB tables the config is read from, load it again with `load` when they change, and
+/// apply the changes to the host behind `handle`. `config` is the config the host started with.
+pub async fn watch<F>(mut config: SwbusConfig, handle: SwbusConfigHandle, load: F)
+where
+    F: Fn() -> swbus_config::Result<SwbusConfig> + Clone + Send + 'static,
+{
+    let (changed_tx, mut changed_rx) = mpsc::channel(1);
+    for table_name in CONFIG_DB_TABLES {
+        let db = match DbConnector::new_named_async("CONFIG_DB", false, 0).await {
+            Ok(db) => db,
+            Err(e) => {
+                error!("Failed to connect to CONFIG_DB, config changes are not applied: {e}");
+                return;
+            }
+        };
+        let mut table = match SubscriberStateTable::new_async(db, table_name, None, None).await {
+            Ok(table) => table,
+            Err(e) => {
+                error!("Failed to subscribe to {table_name}, config changes are not applied: {e}");
+                return;
+            }
+        };
+        let changed_tx = changed_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = table.read_data_async().await {
+                    error!("Failed to read {table_name}: {e}");
+                    return;
+                }
+                match table.pops_async().await {
+                    Ok(kfvs) if !kfvs.is_empty() => {
+                        // a reload is pending if the channel is full
+                        let _ = changed_tx.try_send(());
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to pop {table_name}: {e}"),
+                }
+            }
+        });
+    }
+    drop(changed_tx);
+
+    while changed_rx.recv().await.is_some() {
+        tokio::time::sleep(SETTLE_TIME).await;
+        while changed_rx.try_recv().is_ok() {}
+
+        let new_config = match tokio::task::spawn_blocking(load.clone()).await {
+            Ok(Ok(new_config)) => new_config,
+            Ok(Err(e)) => {
+                error!("Failed to load the changed config, keeping the current one: {e}");
+                continue;
+            }
+            Err(e) => {
+                error!("Config loading task failed: {e}");
+                continue;
+            }
+        };
+        if config.diff(&new_config).is_empty() {
+            continue;
+        }
+        info!("swbusd config changed, reloading");
+        match handle.reload(&config, &new_config).await {
+            Ok(()) => config = new_config,
+            Err(e) => error!("Failed to reload the config, keeping the current one: {e}"),
+        }
+    }
+}
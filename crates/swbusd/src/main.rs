@@ -1,8 +1,13 @@
+mod config_watch;
+
 use clap::Parser;
 use sonic_common::log;
-use swbus_config::{swbus_config_from_db, swbus_config_from_yaml};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use swbus_config::{discover_peers, swbus_config_from_db, swbus_config_from_yaml, SwbusConfig, TlsConfig};
 use swbus_core::mux::service::SwbusServiceHost;
-use tracing::info;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
 
 #[derive(Parser, Debug)]
 #[command(name = "swbusd")]
@@ -14,6 +19,19 @@ struct Args {
     /// swbusd config in yaml file, including routes and peer information.
     #[arg(short = 'c', long)]
     config: Option<String>,
+    /// CA certificate in PEM to verify peers with. Connections to peers use mutual TLS if this is
+    /// set, overriding the TLS settings in the config.
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    tls_ca_cert: Option<PathBuf>,
+    /// Certificate in PEM to present to peers
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_cert: Option<PathBuf>,
+    /// Key of the certificate in PEM
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_key: Option<PathBuf>,
+    /// Serve Prometheus metrics at http://<address>/metrics
+    #[arg(long)]
+    metrics_address: Option<SocketAddr>,
 }
 
 #[tokio::main]
@@ -24,7 +42,7 @@ async fn main() {
         eprintln!("Failed to initialize logging: {e}");
     }
     info!("Starting swbusd");
-    let swbusd_config = match args.slot_id {
+    let mut swbusd_config = match args.slot_id {
         Some(slot_id) => swbus_config_from_db(slot_id).unwrap(),
         None => {
             let config_path = args.config.expect("route_config is required when slot_id is not set");
@@ -33,6 +51,65 @@ async fn main() {
         }
     };
 
-    let server = SwbusServiceHost::new(&swbusd_config.endpoint);
+    if let (Some(ca_cert), Some(cert), Some(key)) = (args.tls_ca_cert, args.tls_cert, args.tls_key) {
+        swbusd_config.tls = Some(TlsConfig {
+            ca_cert,
+            cert,
+            key,
+            domain_name: None,
+        });
+    }
+
+    if let Some(addr) = args.metrics_address {
+        tokio::spawn(async move {
+            if let Err(e) = sonic_common::metrics::serve(addr).await {
+                error!("Failed to serve metrics at {addr}: {e}");
+            }
+        });
+    }
+
+    // the peers listed in the yaml config are kept, the discovered ones follow CONFIG_DB
+    let static_config = swbusd_config.clone();
+    let with_discovered_peers = move || -> swbus_config::Result<SwbusConfig> {
+        Ok(SwbusConfig {
+            peers: discover_peers(&static_config)?,
+            ..static_config.clone()
+        })
+    };
+    if args.slot_id.is_none() && swbusd_config.peer_discovery {
+        match with_discovered_peers() {
+            Ok(config) => swbusd_config = config,
+            Err(e) => error!("Failed to discover peers, starting with the configured ones: {e}"),
+        }
+    }
+
+    let mut server = SwbusServiceHost::new(&swbusd_config.endpoint);
+    // shut down on SIGTERM or SIGINT, which saves the routes for a graceful restart if it is set
+    let shutdown_tx = server.take_shutdown_sender().unwrap();
+    tokio::spawn(async move {
+        let (Ok(mut sigterm), Ok(mut sigint)) = (signal(SignalKind::terminate()), signal(SignalKind::interrupt()))
+        else {
+            error!("Failed to listen for SIGTERM and SIGINT");
+            return;
+        };
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+            _ = sigint.recv() => info!("Received SIGINT, shutting down"),
+        }
+        let _ = shutdown_tx.send(());
+    });
+    if let Some(slot_id) = args.slot_id {
+        tokio::spawn(config_watch::watch(
+            swbusd_config.clone(),
+            server.config_handle(),
+            move || swbus_config_from_db(slot_id),
+        ));
+    } else if swbusd_config.peer_discovery {
+        tokio::spawn(config_watch::watch(
+            swbusd_config.clone(),
+            server.config_handle(),
+            with_discovered_peers,
+        ));
+    }
     server.start(swbusd_config).await.unwrap();
 }
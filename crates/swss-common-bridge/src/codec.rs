@@ -0,0 +1,290 @@
+//! Encoding of the table entries the bridges relay.
+//!
+//! A consumer bridge sends each entry as the data of an [`ActorMessage`], encoded by the codec of its
+//! table type, see [`set_table_codec`]. The JSON codec writes the plain `KeyOpFieldValues`, as the
+//! bridges did before codecs. Other codecs write a tagged payload with the name of the codec, the
+//! version of its encoding and the encoded bytes, so that the receiver picks the right codec and
+//! version regardless of its own table codec. [`decode_kfv`] decodes both, receivers that read the
+//! entries with [`ActorMessage::deserialize_data`] only accept JSON.
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock, RwLock},
+};
+use swbus_actor::{ActorMessage, Result};
+use swss_common::{CxxString, FieldValues, KeyOpFieldValues, KeyOperation};
+
+/// Encodes table entries into payload bytes and decodes them back.
+pub trait PayloadCodec: Send + Sync + 'static {
+    /// Name of the codec in the tag of its payloads.
+    fn name(&self) -> &'static str;
+
+    /// Version of the encoding the codec writes. It reads this version and the ones before.
+    fn version(&self) -> u32;
+
+    fn encode(&self, kfv: &KeyOpFieldValues) -> Result<Vec<u8>>;
+
+    /// Decode a payload written by version `version` of the codec.
+    fn decode(&self, version: u32, payload: &[u8]) -> Result<KeyOpFieldValues>;
+}
+
+/// `KeyOpFieldValues` as JSON, the data of the actor messages.
+pub struct JsonCodec;
+
+impl PayloadCodec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn encode(&self, kfv: &KeyOpFieldValues) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(kfv)?)
+    }
+
+    fn decode(&self, _version: u32, payload: &[u8]) -> Result<KeyOpFieldValues> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+/// `KeyOpFieldValues` as CBOR, more compact than JSON for entries with many fields.
+pub struct CborCodec;
+
+impl PayloadCodec for CborCodec {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn encode(&self, kfv: &KeyOpFieldValues) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        ciborium::into_writer(kfv, &mut payload)?;
+        Ok(payload)
+    }
+
+    fn decode(&self, _version: u32, payload: &[u8]) -> Result<KeyOpFieldValues> {
+        Ok(ciborium::from_reader(payload)?)
+    }
+}
+
+/// `KeyOpFieldValues` as a protobuf message, see [`ProtoKeyOpFieldValues`].
+pub struct ProtobufCodec;
+
+/// Protobuf encoding of a `KeyOpFieldValues`. The field values are bytes, they need not be UTF-8.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoKeyOpFieldValues {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(bool, tag = "2")]
+    pub del: bool,
+    #[prost(btree_map = "string, bytes", tag = "3")]
+    pub field_values: BTreeMap<String, Vec<u8>>,
+}
+
+impl PayloadCodec for ProtobufCodec {
+    fn name(&self) -> &'static str {
+        "protobuf"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn encode(&self, kfv: &KeyOpFieldValues) -> Result<Vec<u8>> {
+        let msg = ProtoKeyOpFieldValues {
+            key: kfv.key.clone(),
+            del: kfv.operation == KeyOperation::Del,
+            field_values: kfv
+                .field_values
+                .iter()
+                .map(|(field, value)| (field.clone(), value.as_bytes().to_vec()))
+                .collect(),
+        };
+        Ok(prost::Message::encode_to_vec(&msg))
+    }
+
+    fn decode(&self, _version: u32, payload: &[u8]) -> Result<KeyOpFieldValues> {
+        let msg: ProtoKeyOpFieldValues = prost::Message::decode(payload)?;
+        Ok(KeyOpFieldValues {
+            key: msg.key,
+            operation: if msg.del { KeyOperation::Del } else { KeyOperation::Set },
+            field_values: msg
+                .field_values
+                .into_iter()
+                .map(|(field, value)| (field, CxxString::new(value)))
+                .collect::<FieldValues>(),
+        })
+    }
+}
+
+/// A payload encoded by a codec other than JSON.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TaggedPayload {
+    codec: String,
+    version: u32,
+    /// Base64 of the encoded entry
+    payload: String,
+}
+
+struct Codecs {
+    /// Codecs by name
+    codecs: HashMap<&'static str, Arc<dyn PayloadCodec>>,
+    /// Codec names by table name
+    tables: HashMap<String, &'static str>,
+}
+
+static CODECS: LazyLock<RwLock<Codecs>> = LazyLock::new(|| {
+    let builtin: [Arc<dyn PayloadCodec>; 3] = [Arc::new(JsonCodec), Arc::new(CborCodec), Arc::new(ProtobufCodec)];
+    RwLock::new(Codecs {
+        codecs: builtin.into_iter().map(|codec| (codec.name(), codec)).collect(),
+        tables: HashMap::new(),
+    })
+});
+
+/// Add `codec` to the codecs the bridges can encode and decode with, replacing the one with the same
+/// name, e.g. with a newer version of it.
+pub fn register_codec(codec: Arc<dyn PayloadCodec>) {
+    CODECS.write().unwrap().codecs.insert(codec.name(), codec);
+}
+
+/// The codec named `name`.
+pub fn codec(name: &str) -> Option<Arc<dyn PayloadCodec>> {
+    CODECS.read().unwrap().codecs.get(name).cloned()
+}
+
+/// Encode the entries of table `table_name` with the codec named `codec_name`.
+pub fn set_table_codec(table_name: &str, codec_name: &str) -> Result<()> {
+    let mut codecs = CODECS.write().unwrap();
+    let Some(codec) = codecs.codecs.get(codec_name) else {
+        bail!("unknown payload codec {codec_name}");
+    };
+    let name = codec.name();
+    codecs.tables.insert(table_name.to_string(), name);
+    Ok(())
+}
+
+/// The codec the entries of table `table_name` are encoded with, JSON unless set with
+/// [`set_table_codec`].
+pub fn table_codec(table_name: &str) -> Arc<dyn PayloadCodec> {
+    let codecs = CODECS.read().unwrap();
+    let name = codecs.tables.get(table_name).copied().unwrap_or("json");
+    codecs.codecs[name].clone()
+}
+
+/// Encode `kfv` with `codec` into the data of an actor message with key `key`.
+pub fn encode_kfv(codec: &dyn PayloadCodec, key: String, kfv: &KeyOpFieldValues) -> Result<ActorMessage> {
+    if codec.name() == JsonCodec.name() {
+        return ActorMessage::new(key, kfv);
+    }
+    let payload = codec
+        .encode(kfv)
+        .with_context(|| format!("encoding {} with codec {}", kfv.key, codec.name()))?;
+    let tagged = TaggedPayload {
+        codec: codec.name().to_string(),
+        version: codec.version(),
+        payload: BASE64.encode(payload),
+    };
+    ActorMessage::new(key, &tagged)
+}
+
+/// Decode the `KeyOpFieldValues` in the data of `msg`, written by [`encode_kfv`] with any codec.
+pub fn decode_kfv(msg: &ActorMessage) -> Result<KeyOpFieldValues> {
+    if msg.data.get("codec").is_none() {
+        return msg.deserialize_data();
+    }
+    let tagged: TaggedPayload = msg.deserialize_data()?;
+    let Some(codec) = codec(&tagged.codec) else {
+        bail!("{}: unknown payload codec {}", msg.key, tagged.codec);
+    };
+    if tagged.version > codec.version() {
+        bail!(
+            "{}: payload is version {} of codec {}, only versions up to {} are supported",
+            msg.key,
+            tagged.version,
+            tagged.codec,
+            codec.version()
+        );
+    }
+    let payload = BASE64.decode(&tagged.payload).context("decoding base64 payload")?;
+    codec
+        .decode(tagged.version, &payload)
+        .with_context(|| format!("{}: decoding payload with codec {}", msg.key, tagged.codec))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kfv(operation: KeyOperation) -> KeyOpFieldValues {
+        KeyOpFieldValues {
+            key: "vdpu0:haset0".to_string(),
+            operation,
+            field_values: FieldValues::from([
+                ("version".to_string(), CxxString::new("1")),
+                ("desired_ha_state".to_string(), CxxString::new("active")),
+            ]),
+        }
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        for name in ["json", "cbor", "protobuf"] {
+            let codec = codec(name).unwrap();
+            for kfv in [kfv(KeyOperation::Set), kfv(KeyOperation::Del)] {
+                let msg = encode_kfv(codec.as_ref(), "key".to_string(), &kfv).unwrap();
+                let msg = ActorMessage::deserialize(&msg.serialize()).unwrap();
+                assert_eq!(decode_kfv(&msg).unwrap(), kfv, "codec {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn json_payload_is_untagged() {
+        let msg = encode_kfv(&JsonCodec, "key".to_string(), &kfv(KeyOperation::Set)).unwrap();
+        assert_eq!(
+            msg.deserialize_data::<KeyOpFieldValues>().unwrap(),
+            kfv(KeyOperation::Set)
+        );
+    }
+
+    #[test]
+    fn newer_payload_version_is_rejected() {
+        let msg = ActorMessage::new(
+            "key",
+            &TaggedPayload {
+                codec: "cbor".to_string(),
+                version: 2,
+                payload: String::new(),
+            },
+        )
+        .unwrap();
+        let error = decode_kfv(&msg).unwrap_err();
+        assert!(format!("{error:#}").contains("only versions up to 1"));
+
+        let msg = ActorMessage::new(
+            "key",
+            &TaggedPayload {
+                codec: "xml".to_string(),
+                version: 1,
+                payload: String::new(),
+            },
+        )
+        .unwrap();
+        assert!(decode_kfv(&msg).is_err());
+    }
+
+    #[test]
+    fn table_codec_defaults_to_json() {
+        assert_eq!(table_codec("TEST_CODEC_TABLE").name(), "json");
+        set_table_codec("TEST_CODEC_TABLE", "protobuf").unwrap();
+        assert_eq!(table_codec("TEST_CODEC_TABLE").name(), "protobuf");
+        assert!(set_table_codec("TEST_CODEC_TABLE", "xml").is_err());
+    }
+}
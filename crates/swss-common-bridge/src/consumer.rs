@@ -1,15 +1,26 @@
-use std::{collections::HashMap, future::Future, sync::Arc};
-use swbus_actor::ActorMessage;
+use crate::codec::{encode_kfv, table_codec, JsonCodec, PayloadCodec};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use swbus_actor::Result;
 use swbus_edge::{
-    simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient},
-    swbus_proto::swbus::ServicePath,
+    simple_client::{IncomingMessage, MessageBody, MessageId, OutgoingMessage, SimpleSwbusEdgeClient},
+    swbus_proto::swbus::{ServicePath, SwbusErrorCode},
     SwbusEdgeRuntime,
 };
 use swss_common::{
     ConsumerStateTable, FieldValues, KeyOpFieldValues, KeyOperation, SubscriberStateTable, Table, ZmqConsumerStateTable,
 };
-use tokio::task::JoinHandle;
+use tokio::{
+    task::JoinHandle,
+    time::{interval, sleep, MissedTickBehavior},
+};
 use tokio_util::task::AbortOnDropHandle;
+use tracing::{error, info, warn};
 
 pub struct ConsumerBridge {
     _task: AbortOnDropHandle<()>,
@@ -18,13 +29,18 @@ pub struct ConsumerBridge {
 impl ConsumerBridge {
     /// Spawn a consumer table to actor bridge task.
     ///
-    /// `dest_generator` is a function that takes a `&KeyOpFieldValues` read from `table`
+    /// Only the entries of `table` whose key matches `key_filter` are bridged, the others are not
+    /// even cached. `dest_generator` is a function that takes a `&KeyOpFieldValues` read from `table`
     /// and generates the `ServicePath` address and `String` input table key that
-    /// the data will be sent to.
+    /// the data will be sent to. The entries are encoded with the codec of `table`, see
+    /// [`ConsumerTable::codec`]. Updates to a key read from the table together are sent as one, see
+    /// [`coalesced_counts`]. Updates the receiver rejects because its mailbox is full are sent again
+    /// later, as the entry is by then.
     pub fn spawn<T, F, S>(
         rt: Arc<SwbusEdgeRuntime>,
         addr: ServicePath,
         table: T,
+        key_filter: KeyFilter,
         dest_generator: F,
         selector: S,
     ) -> Self
@@ -33,7 +49,7 @@ impl ConsumerBridge {
         F: FnMut(&KeyOpFieldValues) -> (ServicePath, String) + Send + 'static,
         S: Fn(&KeyOpFieldValues) -> bool + Sync + Send + 'static,
     {
-        let task = spawn_consumer_bridge(rt, addr, table, dest_generator, selector);
+        let task = spawn_consumer_bridge(rt, addr, table, key_filter, dest_generator, selector);
         ConsumerBridge {
             _task: AbortOnDropHandle::new(task),
         }
@@ -44,6 +60,7 @@ pub fn spawn_consumer_bridge<T, F, S>(
     rt: Arc<SwbusEdgeRuntime>,
     addr: ServicePath,
     mut table: T,
+    key_filter: KeyFilter,
     mut dest_generator: F,
     selector: S,
 ) -> JoinHandle<()>
@@ -52,60 +69,222 @@ where
     F: FnMut(&KeyOpFieldValues) -> (ServicePath, String) + Send + 'static,
     S: Fn(&KeyOpFieldValues) -> bool + Sync + Send + 'static,
 {
+    let bridge = addr.to_longest_path();
     let swbus = SimpleSwbusEdgeClient::new(rt, addr, false, false);
+    let codec = table.codec();
     tokio::task::spawn(async move {
         let mut table_cache = TableCache::default();
-        let mut send_kfv = async |kfv: KeyOpFieldValues| {
-            // Merge the kfv to get the whole table as an update
-            let kfv = table_cache.merge_kfv(kfv);
+        let mut unanswered = Unanswered::default();
+        let mut resend_rejected = interval(REJECTED_RESEND_INTERVAL);
+        resend_rejected.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // Sends the whole entry of a key, merged from its updates. Returns the id of the request.
+        let mut send_kfv = async |kfv: KeyOpFieldValues, resync: bool| -> Option<MessageId> {
             if !selector(&kfv) {
-                return;
+                return None;
             }
 
             // Use user-provided function to generate Actor's ServicePath and input table key
             let (destination, key) = dest_generator(&kfv);
 
             // Encode the KeyOpFieldValues as an ActorMessage
-            let payload = ActorMessage::new(key, &kfv).expect("encoding ActorMessage").serialize();
+            let mut actor_msg = match encode_kfv(codec.as_ref(), key, &kfv) {
+                Ok(actor_msg) => actor_msg,
+                Err(e) => {
+                    warn!("{bridge}: dropping {}, it can't be encoded: {e:#}", kfv.key);
+                    return None;
+                }
+            };
+            if resync {
+                actor_msg = actor_msg.as_resync();
+            }
 
             // Send the message
-            swbus
+            let id = swbus
                 .send(OutgoingMessage {
                     destination,
-                    body: MessageBody::Request { payload },
+                    body: MessageBody::Request {
+                        payload: actor_msg.serialize().into(),
+                    },
                 })
                 .await
                 .expect("Sending swbus message");
+            Some(id)
         };
 
-        // Send initial/rehydration updates
-        for kfv in table.rehydrate().await {
-            send_kfv(kfv).await;
-        }
-
+        let mut resync = false;
         loop {
-            tokio::select! {
-                // Send all received updates
-                _ = table.read_data() => {
-                    for kfv in table.pops().await {
-                        send_kfv(kfv).await;
+            // Send the whole table, initially for rehydration, and after a reconnect as a resync
+            // because the updates made while the connection was down are lost.
+            let snapshot = match table.rehydrate().await {
+                Ok(kfvs) if resync => table_cache.resync(kfvs),
+                Ok(kfvs) => kfvs,
+                Err(e) => {
+                    warn!("{bridge}: failed to read the table, reconnecting: {e:#}");
+                    if !reconnect(&mut table, &bridge).await {
+                        return;
+                    }
+                    resync = true;
+                    continue;
+                }
+            };
+            for kfv in snapshot {
+                if key_filter.matches(&kfv.key) {
+                    let kfv = table_cache.merge_kfv(kfv);
+                    let key = kfv.key.clone();
+                    if let Some(id) = send_kfv(kfv, resync).await {
+                        unanswered.sent(id, key);
                     }
                 }
+            }
 
-                // Ignore all messages received.
-                // It is a programming error to send a request to a consumer table.
-                // Responses are ignored because we don't resend updates if the receiver fails.
-                maybe_msg = swbus.recv() => {
-                    if maybe_msg.is_none() {
-                        // Swbus shut down, we might as well quit.
-                        break;
+            // Send all received updates, until the connection is lost
+            let error = loop {
+                tokio::select! {
+                    result = table.read_data() => {
+                        let kfvs = match result {
+                            Ok(()) => table.pops().await,
+                            Err(e) => Err(e),
+                        };
+                        match kfvs {
+                            Ok(kfvs) => {
+                                let kfvs: Vec<KeyOpFieldValues> =
+                                    kfvs.into_iter().filter(|kfv| key_filter.matches(&kfv.key)).collect();
+                                let received = kfvs.len();
+                                let kfvs = table_cache.merge_batch(kfvs);
+                                if kfvs.len() < received {
+                                    *COALESCED.lock().unwrap().entry(bridge.clone()).or_insert(0) +=
+                                        (received - kfvs.len()) as u64;
+                                }
+                                for kfv in kfvs {
+                                    let key = kfv.key.clone();
+                                    if let Some(id) = send_kfv(kfv, false).await {
+                                        unanswered.sent(id, key);
+                                    }
+                                }
+                            }
+                            Err(e) => break e,
+                        }
+                    }
+
+                    // Send the entries of the rejected updates again, as they are now
+                    _ = resend_rejected.tick(), if unanswered.has_rejected() => {
+                        for key in unanswered.take_rejected() {
+                            let kfv = table_cache.entry(key.clone());
+                            if let Some(id) = send_kfv(kfv, false).await {
+                                unanswered.sent(id, key);
+                            }
+                        }
+                    }
+
+                    // It is a programming error to send a request to a consumer table, so requests are
+                    // ignored. Responses are ignored too, but for rejections: we don't resend updates
+                    // if the receiver fails to handle them.
+                    maybe_msg = swbus.recv() => {
+                        let Some(msg) = maybe_msg else {
+                            // Swbus shut down, we might as well quit.
+                            return;
+                        };
+                        if let IncomingMessage {
+                            body: MessageBody::Response { request_id, error_code, .. },
+                            ..
+                        } = msg
+                        {
+                            unanswered.answered(request_id, error_code);
+                        }
                     }
                 }
+            };
+            warn!("{bridge}: lost the database connection, reconnecting: {error:#}");
+            if !reconnect(&mut table, &bridge).await {
+                return;
             }
+            resync = true;
         }
     })
 }
 
+/// Keys of the table entries a consumer bridge forwards, as glob patterns or a regex. The patterns
+/// match the whole key.
+#[derive(Debug, Clone, Default)]
+pub struct KeyFilter(Option<Regex>);
+
+impl KeyFilter {
+    /// All keys.
+    pub fn any() -> Self {
+        KeyFilter(None)
+    }
+
+    /// Keys matching any of the glob `patterns`, where `*` matches any characters and `?` matches one.
+    pub fn glob<P: AsRef<str>>(patterns: &[P]) -> Self {
+        let alternatives: Vec<String> = patterns
+            .iter()
+            .map(|pattern| regex::escape(pattern.as_ref()).replace(r"\*", ".*").replace(r"\?", "."))
+            .collect();
+        let regex = Regex::new(&format!("^(?:{})$", alternatives.join("|"))).expect("escaped glob patterns");
+        KeyFilter(Some(regex))
+    }
+
+    /// Keys matching `regex`.
+    pub fn regex(regex: &str) -> Result<Self> {
+        Ok(KeyFilter(Some(Regex::new(&format!("^(?:{regex})$"))?)))
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.0.as_ref().is_none_or(|regex| regex.is_match(key))
+    }
+}
+
+/// Reconnect `table` to the database, retrying until it succeeds. Returns false if the table can't
+/// reconnect, and the bridge has to stop.
+async fn reconnect<T: ConsumerTable>(table: &mut T, bridge: &str) -> bool {
+    if !table.can_reconnect() {
+        error!("{bridge}: lost the table, which can't reconnect, stopping the bridge");
+        return false;
+    }
+    let mut backoff = RECONNECT_BACKOFF;
+    loop {
+        sleep(backoff).await;
+        match table.reconnect().await {
+            Ok(()) => break,
+            Err(e) => warn!("{bridge}: failed to reconnect, retrying in {backoff:?}: {e:#}"),
+        }
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+    let resyncs = {
+        let mut resyncs = RESYNCS.lock().unwrap();
+        let count = resyncs.entry(bridge.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    };
+    info!("{bridge}: reconnected, resyncing the table (resync {resyncs})");
+    true
+}
+
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT_MS: u32 = 3000;
+/// How often the updates rejected by a full mailbox are sent again
+const REJECTED_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+/// Unanswered requests a bridge remembers, the oldest are forgotten beyond that
+const MAX_UNANSWERED: usize = 4096;
+
+/// Number of resyncs after a reconnect per bridge address.
+static RESYNCS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Snapshot of the resync counters.
+pub fn resync_counts() -> BTreeMap<String, u64> {
+    RESYNCS.lock().unwrap().clone()
+}
+
+/// Number of updates per bridge address that were not sent on their own, because a later update
+/// to the same key was read with them.
+static COALESCED: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Snapshot of the coalesced update counters.
+pub fn coalesced_counts() -> BTreeMap<String, u64> {
+    COALESCED.lock().unwrap().clone()
+}
+
 /// An in-memory copy of a table.
 /// We keep a copy so that we can send the entire table for each update, rather than just the updated fields.
 /// This relieves the need for actors to handle partial updates by caching their own copy.
@@ -131,63 +310,197 @@ impl TableCache {
             }
         }
     }
+
+    /// Merge the updates read from the table at once. Returns one update per key with the whole entry
+    /// after all of them, in the order the keys were last updated, so the receiver doesn't handle the
+    /// intermediate states. Every key updated in the batch is sent, even if its entry looks unchanged:
+    /// the cache can't tell what the receiver has, e.g. after it restarted or rejected an update.
+    fn merge_batch(&mut self, kfvs: Vec<KeyOpFieldValues>) -> Vec<KeyOpFieldValues> {
+        let mut last_updates: HashMap<String, usize> = HashMap::new();
+        for (i, kfv) in kfvs.into_iter().enumerate() {
+            last_updates.insert(kfv.key.clone(), i);
+            self.merge_kfv(kfv);
+        }
+
+        let mut keys: Vec<(usize, String)> = last_updates.into_iter().map(|(key, i)| (i, key)).collect();
+        keys.sort_unstable();
+        keys.into_iter().map(|(_, key)| self.entry(key)).collect()
+    }
+
+    /// The whole entry of `key`, or a DEL if it is not in the table.
+    fn entry(&self, key: String) -> KeyOpFieldValues {
+        match self.0.get(&key) {
+            Some(field_values) => KeyOpFieldValues {
+                key,
+                operation: KeyOperation::Set,
+                field_values: field_values.clone(),
+            },
+            None => KeyOpFieldValues {
+                key,
+                operation: KeyOperation::Del,
+                field_values: FieldValues::new(),
+            },
+        }
+    }
+
+    /// Start over from a fresh `snapshot` of the table. Returns the updates to send: a DEL for
+    /// every cached key that is no longer in the table, followed by the snapshot.
+    fn resync(&mut self, snapshot: Vec<KeyOpFieldValues>) -> Vec<KeyOpFieldValues> {
+        let keys: HashSet<&str> = snapshot.iter().map(|kfv| kfv.key.as_str()).collect();
+        let mut kfvs: Vec<KeyOpFieldValues> = self
+            .0
+            .keys()
+            .filter(|key| !keys.contains(key.as_str()))
+            .map(|key| KeyOpFieldValues {
+                key: key.clone(),
+                operation: KeyOperation::Del,
+                field_values: FieldValues::new(),
+            })
+            .collect();
+        // Fields removed while disconnected must not survive the merge
+        self.0.clear();
+        kfvs.extend(snapshot);
+        kfvs
+    }
+}
+
+/// The updates a bridge sent that are not answered yet, and the keys of those the receiver rejected
+/// because its mailbox was full.
+#[derive(Default)]
+struct Unanswered {
+    requests: BTreeMap<MessageId, String>,
+    rejected: HashSet<String>,
+}
+
+impl Unanswered {
+    fn sent(&mut self, id: MessageId, key: String) {
+        self.requests.insert(id, key);
+        // Receivers that never answer must not grow this for good
+        if self.requests.len() > MAX_UNANSWERED {
+            self.requests.pop_first();
+        }
+    }
+
+    fn answered(&mut self, request_id: MessageId, error_code: SwbusErrorCode) {
+        if let Some(key) = self.requests.remove(&request_id) {
+            if error_code == SwbusErrorCode::QueueFull {
+                self.rejected.insert(key);
+            }
+        }
+    }
+
+    fn has_rejected(&self) -> bool {
+        !self.rejected.is_empty()
+    }
+
+    fn take_rejected(&mut self) -> HashSet<String> {
+        std::mem::take(&mut self.rejected)
+    }
 }
 
 pub trait ConsumerTable: Send + 'static {
     /// Wait for updates
-    fn read_data(&mut self) -> impl Future<Output = ()> + Send;
+    fn read_data(&mut self) -> impl Future<Output = Result<()>> + Send;
 
     /// Get updates
-    fn pops(&mut self) -> impl Future<Output = Vec<KeyOpFieldValues>> + Send;
+    fn pops(&mut self) -> impl Future<Output = Result<Vec<KeyOpFieldValues>>> + Send;
 
     /// Dump the table, as if `pops()` returned everything again, for rehydration after a restart
-    fn rehydrate(&mut self) -> impl Future<Output = Vec<KeyOpFieldValues>> + Send;
+    fn rehydrate(&mut self) -> impl Future<Output = Result<Vec<KeyOpFieldValues>>> + Send;
+
+    /// Replace the connection to the database with a new one, subscribing to the table again
+    fn reconnect(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Whether [`reconnect`](Self::reconnect) can work. The bridge of a table that can't stops when it
+    /// loses the connection.
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+
+    /// The codec the entries are sent with
+    fn codec(&self) -> Arc<dyn PayloadCodec> {
+        Arc::new(JsonCodec)
+    }
 }
 
 macro_rules! rehydrate_body {
     (true, $self:ident) => {{
-        let db = $self.db_connector_mut().clone_async().await;
-        let mut tbl = Table::new_async(db, $self.table_name()).await.expect("Table::new");
-        let keys = tbl.get_keys_async().await.expect("Table::get_keys");
+        let db = $self.db_connector_mut().clone_timeout_async(CONNECT_TIMEOUT_MS).await?;
+        let mut tbl = Table::new_async(db, $self.table_name()).await?;
+        let keys = tbl.get_keys_async().await?;
 
         let mut out = Vec::with_capacity(keys.len());
         for key in keys {
-            let field_values = tbl.get_async(&key).await.expect("Table::get").unwrap_or_default();
+            let field_values = tbl.get_async(&key).await?.unwrap_or_default();
             out.push(KeyOpFieldValues {
                 key,
                 operation: KeyOperation::Set,
                 field_values,
             });
         }
-        out
+        Ok(out)
     }};
 
-    (false, self) => {
+    (false, $self:ident) => {
         // This table does not support rehydration.
         // Eg, ZmqConsumerStateTable does not write updates down anywhere,
         // so it's impossible to rehydrate.
-        vec![]
+        Ok(vec![])
+    };
+}
+
+macro_rules! reconnect_body {
+    (true, $t:ty, $self:ident) => {{
+        let db = $self.db_connector_mut().clone_timeout_async(CONNECT_TIMEOUT_MS).await?;
+        let table_name = $self.table_name().to_string();
+        *$self = <$t>::new_async(db, &table_name, None, None).await?;
+        Ok(())
+    }};
+
+    (false, $t:ty, $self:ident) => {
+        // The updates of a ZmqConsumerStateTable come through its ZmqServer, which the table
+        // can't be recreated without.
+        Err(swbus_actor::Error::msg(concat!(stringify!($t), " can't reconnect")))
+    };
+}
+
+macro_rules! codec_body {
+    (true, $self:ident) => {
+        table_codec($self.table_name())
+    };
+
+    (false, $self:ident) => {
+        // Entries of a ZmqConsumerStateTable are always sent as JSON.
+        Arc::new(JsonCodec)
     };
 }
 
 macro_rules! impl_consumertable {
     ($($t:ty [$can_rehydrate:tt])*) => {
         $(impl ConsumerTable for $t {
-            async fn read_data(&mut self) {
-                <$t>::read_data_async(self)
-                    .await
-                    .expect(concat!(stringify!($t::read_data_async), " io error"));
+            async fn read_data(&mut self) -> Result<()> {
+                Ok(<$t>::read_data_async(self).await?)
             }
 
-            async fn pops(&mut self) -> Vec<KeyOpFieldValues> {
-                <$t>::pops_async(self)
-                    .await
-                    .expect(concat!(stringify!($t::pops_async), " threw an exception"))
+            async fn pops(&mut self) -> Result<Vec<KeyOpFieldValues>> {
+                Ok(<$t>::pops_async(self).await?)
             }
 
-            async fn rehydrate(&mut self) -> Vec<KeyOpFieldValues> {
+            async fn rehydrate(&mut self) -> Result<Vec<KeyOpFieldValues>> {
                 rehydrate_body!($can_rehydrate, self)
             }
+
+            async fn reconnect(&mut self) -> Result<()> {
+                reconnect_body!($can_rehydrate, $t, self)
+            }
+
+            fn can_reconnect(&self) -> bool {
+                $can_rehydrate
+            }
+
+            fn codec(&self) -> Arc<dyn PayloadCodec> {
+                codec_body!($can_rehydrate, self)
+            }
         })*
     };
 }
@@ -196,22 +509,323 @@ impl_consumertable! { ConsumerStateTable[true] SubscriberStateTable[true] ZmqCon
 
 #[cfg(test)]
 mod test {
-    use super::{spawn_consumer_bridge, ConsumerTable};
+    use super::{coalesced_counts, resync_counts, spawn_consumer_bridge, ConsumerTable, KeyFilter, Unanswered};
     use crate::producer::ProducerTable;
-    use std::{sync::Arc, time::Duration};
-    use swbus_actor::ActorMessage;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use swbus_actor::{ActorMessage, Result};
     use swbus_edge::{
+        mailbox::{MailboxConfig, OverflowPolicy},
         simple_client::{IncomingMessage, MessageBody, SimpleSwbusEdgeClient},
-        swbus_proto::swbus::ServicePath,
+        swbus_proto::swbus::{ServicePath, SwbusErrorCode},
         SwbusEdgeRuntime,
     };
     use swss_common::{
-        ConsumerStateTable, KeyOpFieldValues, KeyOperation, ProducerStateTable, ZmqClient, ZmqConsumerStateTable,
-        ZmqProducerStateTable, ZmqServer,
+        ConsumerStateTable, CxxString, FieldValues, KeyOpFieldValues, KeyOperation, ProducerStateTable, ZmqClient,
+        ZmqConsumerStateTable, ZmqProducerStateTable, ZmqServer,
     };
     use swss_common_testing::{random_kfvs, random_zmq_endpoint, Redis};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
     use tokio::time::timeout;
 
+    /// Delivers the updates sent to `updates`, where `None` drops the connection, and rehydrates
+    /// from `snapshot`.
+    struct FakeTable {
+        updates: UnboundedReceiver<Option<KeyOpFieldValues>>,
+        pending: Vec<KeyOpFieldValues>,
+        snapshot: Arc<Mutex<Vec<KeyOpFieldValues>>>,
+    }
+
+    impl ConsumerTable for FakeTable {
+        async fn read_data(&mut self) -> Result<()> {
+            match self.updates.recv().await {
+                Some(Some(kfv)) => {
+                    self.pending.push(kfv);
+                    Ok(())
+                }
+                Some(None) => Err(swbus_actor::Error::msg("connection lost")),
+                None => std::future::pending().await,
+            }
+        }
+
+        async fn pops(&mut self) -> Result<Vec<KeyOpFieldValues>> {
+            Ok(std::mem::take(&mut self.pending))
+        }
+
+        async fn rehydrate(&mut self) -> Result<Vec<KeyOpFieldValues>> {
+            Ok(self.snapshot.lock().unwrap().clone())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn set(key: &str, field: &str, value: &str) -> KeyOpFieldValues {
+        KeyOpFieldValues {
+            key: key.to_string(),
+            operation: KeyOperation::Set,
+            field_values: FieldValues::from([(field.to_string(), CxxString::new(value))]),
+        }
+    }
+
+    #[tokio::test]
+    async fn bridge_resyncs_after_reconnect() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
+
+        let (updates_tx, updates) = unbounded_channel();
+        let snapshot = Arc::new(Mutex::new(vec![set("a", "x", "1")]));
+        let table = FakeTable {
+            updates,
+            pending: Vec::new(),
+            snapshot: snapshot.clone(),
+        };
+        let _bridge = spawn_consumer_bridge(
+            rt,
+            sp("resync-bridge"),
+            table,
+            KeyFilter::any(),
+            |kfv| (sp("receiver"), kfv.key.clone()),
+            |_| true,
+        );
+        updates_tx.send(Some(set("b", "y", "1"))).unwrap();
+        let received = timeout(Duration::from_secs(5), receive_n_actor_messages(2, &swbus))
+            .await
+            .unwrap();
+        assert!(received.iter().all(|msg| !msg.resync));
+
+        // While the connection is down, a is rewritten without x and b is deleted
+        *snapshot.lock().unwrap() = vec![set("a", "z", "2")];
+        updates_tx.send(None).unwrap();
+        let received = timeout(Duration::from_secs(5), receive_n_actor_messages(2, &swbus))
+            .await
+            .unwrap();
+        assert!(received.iter().all(|msg| msg.resync));
+        let kfvs: Vec<KeyOpFieldValues> = received.iter().map(|msg| msg.deserialize_data().unwrap()).collect();
+        assert_eq!(
+            kfvs,
+            vec![
+                KeyOpFieldValues {
+                    key: "b".to_string(),
+                    operation: KeyOperation::Del,
+                    field_values: FieldValues::new(),
+                },
+                set("a", "z", "2"),
+            ]
+        );
+        assert_eq!(resync_counts()[&sp("resync-bridge").to_longest_path()], 1);
+
+        // Updates after the resync are sent as usual
+        updates_tx.send(Some(set("a", "x", "3"))).unwrap();
+        let received = timeout(Duration::from_secs(5), receive_n_actor_messages(1, &swbus))
+            .await
+            .unwrap();
+        assert!(!received[0].resync);
+        let mut expected = set("a", "z", "2");
+        expected.field_values.insert("x".to_string(), CxxString::new("3"));
+        assert_eq!(received[0].deserialize_data::<KeyOpFieldValues>().unwrap(), expected);
+    }
+
+    /// Loses the connection on the first read, and can't reconnect.
+    struct LostTable;
+
+    impl ConsumerTable for LostTable {
+        async fn read_data(&mut self) -> Result<()> {
+            Err(swbus_actor::Error::msg("connection lost"))
+        }
+
+        async fn pops(&mut self) -> Result<Vec<KeyOpFieldValues>> {
+            Ok(Vec::new())
+        }
+
+        async fn rehydrate(&mut self) -> Result<Vec<KeyOpFieldValues>> {
+            Ok(Vec::new())
+        }
+
+        async fn reconnect(&mut self) -> Result<()> {
+            unreachable!("LostTable can't reconnect")
+        }
+
+        fn can_reconnect(&self) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn bridge_stops_if_the_table_cannot_reconnect() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let bridge = spawn_consumer_bridge(
+            Arc::new(swbus_edge),
+            sp("lost-bridge"),
+            LostTable,
+            KeyFilter::any(),
+            |kfv| (sp("receiver"), kfv.key.clone()),
+            |_| true,
+        );
+        timeout(Duration::from_secs(5), bridge).await.unwrap().unwrap();
+        assert!(!resync_counts().contains_key(&sp("lost-bridge").to_longest_path()));
+    }
+
+    #[tokio::test]
+    async fn bridge_coalesces_updates_read_together() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
+
+        let (updates_tx, updates) = unbounded_channel();
+        let table = FakeTable {
+            updates,
+            // a, b and a again are read together
+            pending: vec![set("a", "x", "1"), set("b", "y", "1")],
+            snapshot: Arc::new(Mutex::new(Vec::new())),
+        };
+        let _bridge = spawn_consumer_bridge(
+            rt,
+            sp("coalesce-bridge"),
+            table,
+            KeyFilter::any(),
+            |kfv| (sp("receiver"), kfv.key.clone()),
+            |_| true,
+        );
+        updates_tx.send(Some(set("a", "z", "2"))).unwrap();
+        let kfvs = timeout(Duration::from_secs(5), receive_n_messages(2, &swbus))
+            .await
+            .unwrap();
+        let mut a = set("a", "x", "1");
+        a.field_values.insert("z".to_string(), CxxString::new("2"));
+        assert_eq!(kfvs, vec![set("b", "y", "1"), a.clone()]);
+        assert_eq!(coalesced_counts()[&sp("coalesce-bridge").to_longest_path()], 1);
+
+        // Updates read apart are all sent, even writing the same entry again
+        updates_tx.send(Some(set("a", "z", "2"))).unwrap();
+        updates_tx
+            .send(Some(KeyOpFieldValues {
+                key: "b".to_string(),
+                operation: KeyOperation::Del,
+                field_values: FieldValues::new(),
+            }))
+            .unwrap();
+        let received = timeout(Duration::from_secs(5), receive_n_actor_messages(2, &swbus))
+            .await
+            .unwrap();
+        assert_eq!(received[0].deserialize_data::<KeyOpFieldValues>().unwrap(), a);
+        assert_eq!(received[1].key, "b");
+        assert_eq!(coalesced_counts()[&sp("coalesce-bridge").to_longest_path()], 1);
+    }
+
+    #[tokio::test]
+    async fn bridge_resends_rejected_updates() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        // Holds one update, and rejects the others while it does
+        let swbus = SimpleSwbusEdgeClient::with_mailbox(
+            rt.clone(),
+            sp("receiver"),
+            true,
+            false,
+            MailboxConfig {
+                size: 1,
+                overflow: OverflowPolicy::Reject,
+            },
+        );
+
+        let (_updates_tx, updates) = unbounded_channel();
+        let table = FakeTable {
+            updates,
+            pending: Vec::new(),
+            snapshot: Arc::new(Mutex::new(vec![
+                set("a", "x", "1"),
+                set("b", "x", "1"),
+                set("c", "x", "1"),
+            ])),
+        };
+        let _bridge = spawn_consumer_bridge(
+            rt,
+            sp("reject-bridge"),
+            table,
+            KeyFilter::any(),
+            |kfv| (sp("receiver"), kfv.key.clone()),
+            |_| true,
+        );
+        let mut kfvs = timeout(Duration::from_secs(5), receive_n_messages(3, &swbus))
+            .await
+            .unwrap();
+        kfvs.sort_unstable();
+        assert_eq!(kfvs, vec![set("a", "x", "1"), set("b", "x", "1"), set("c", "x", "1")]);
+    }
+
+    #[test]
+    fn rejected_updates_are_resent_once() {
+        let mut unanswered = Unanswered::default();
+        unanswered.sent(1, "a".to_string());
+        unanswered.sent(2, "b".to_string());
+        unanswered.sent(3, "a".to_string());
+        unanswered.answered(1, SwbusErrorCode::QueueFull);
+        unanswered.answered(2, SwbusErrorCode::Ok);
+        unanswered.answered(3, SwbusErrorCode::QueueFull);
+        // unknown or answered already
+        unanswered.answered(2, SwbusErrorCode::QueueFull);
+        assert!(unanswered.has_rejected());
+        assert_eq!(unanswered.take_rejected(), ["a".to_string()].into());
+        assert!(!unanswered.has_rejected());
+    }
+
+    #[test]
+    fn key_filter_matches_whole_keys() {
+        let filter = KeyFilter::glob(&["vdpu0:*", "vdpu1:haset?"]);
+        assert!(filter.matches("vdpu0:haset0"));
+        assert!(filter.matches("vdpu1:haset1"));
+        assert!(!filter.matches("vdpu1:haset10"));
+        assert!(!filter.matches("vdpu10:haset0"));
+        assert!(!filter.matches("vdpu0.haset0x"));
+
+        let filter = KeyFilter::regex(r"vdpu[01]:.+").unwrap();
+        assert!(filter.matches("vdpu1:haset0"));
+        assert!(!filter.matches("vdpu2:haset0"));
+        assert!(!filter.matches("xvdpu1:haset0"));
+        assert!(KeyFilter::regex("vdpu(").is_err());
+
+        assert!(KeyFilter::any().matches("anything"));
+    }
+
+    #[tokio::test]
+    async fn bridge_forwards_only_matching_keys() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
+
+        let (updates_tx, updates) = unbounded_channel();
+        let table = FakeTable {
+            updates,
+            pending: Vec::new(),
+            snapshot: Arc::new(Mutex::new(vec![set("vdpu1:a", "x", "1"), set("vdpu0:a", "x", "1")])),
+        };
+        let _bridge = spawn_consumer_bridge(
+            rt,
+            sp("filter-bridge"),
+            table,
+            KeyFilter::glob(&["vdpu0:*"]),
+            |kfv| (sp("receiver"), kfv.key.clone()),
+            |_| true,
+        );
+        updates_tx.send(Some(set("vdpu1:b", "y", "1"))).unwrap();
+        updates_tx.send(Some(set("vdpu0:b", "y", "1"))).unwrap();
+        let kfvs = timeout(Duration::from_secs(5), receive_n_messages(2, &swbus))
+            .await
+            .unwrap();
+        assert_eq!(kfvs, vec![set("vdpu0:a", "x", "1"), set("vdpu0:b", "y", "1")]);
+        assert!(timeout(Duration::from_millis(100), swbus.recv()).await.is_err());
+    }
+
     #[tokio::test]
     async fn consumer_state_table_bridge() {
         let redis = Redis::start();
@@ -255,6 +869,7 @@ mod test {
             rt.clone(),
             sp("mytable-bridge"),
             consumer_table,
+            KeyFilter::any(),
             |_| (sp("receiver"), "".into()),
             |_| true,
         );
@@ -281,6 +896,7 @@ mod test {
                 rt,
                 sp("mytable-bridge"),
                 rehydrate_table,
+                KeyFilter::any(),
                 |_| (sp("receiver"), "".into()),
                 |_| true,
             );
@@ -296,6 +912,14 @@ mod test {
     }
 
     async fn receive_n_messages(n: usize, swbus: &SimpleSwbusEdgeClient) -> Vec<KeyOpFieldValues> {
+        receive_n_actor_messages(n, swbus)
+            .await
+            .into_iter()
+            .map(|msg| msg.deserialize_data().unwrap())
+            .collect()
+    }
+
+    async fn receive_n_actor_messages(n: usize, swbus: &SimpleSwbusEdgeClient) -> Vec<ActorMessage> {
         let mut received = Vec::new();
         for _ in 0..n {
             let msg = swbus.recv().await.unwrap();
@@ -306,8 +930,7 @@ mod test {
             else {
                 panic!("Did not receive proper message from bridge")
             };
-            let kfv = ActorMessage::deserialize(&payload).unwrap().deserialize_data().unwrap();
-            received.push(kfv);
+            received.push(ActorMessage::deserialize(&payload).unwrap());
         }
         received
     }
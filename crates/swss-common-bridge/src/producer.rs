@@ -1,5 +1,6 @@
-use std::{future::Future, sync::Arc};
-use swbus_actor::ActorMessage;
+use crate::codec::decode_kfv;
+use std::{collections::HashSet, future::Future, sync::Arc, time::Duration};
+use swbus_actor::{ActorMessage, Result};
 use swbus_edge::{
     simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient},
     swbus_proto::swbus::{ServicePath, SwbusErrorCode},
@@ -7,75 +8,260 @@ use swbus_edge::{
 };
 use swss_common::{FieldValues, KeyOpFieldValues, KeyOperation, ProducerStateTable, Table, ZmqProducerStateTable};
 use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
 use tokio_util::task::AbortOnDropHandle;
+use tracing::debug;
 
 pub struct ProducerBridge {
     _task: AbortOnDropHandle<()>,
 }
 
 impl ProducerBridge {
-    pub fn spawn<T>(rt: Arc<SwbusEdgeRuntime>, addr: ServicePath, table: T) -> Self
+    /// Spawn an actor to producer table bridge task.
+    ///
+    /// Writes are applied to the table in batches according to `batch`. Writes that fail are retried
+    /// according to `retry`. A write that still fails after the last attempt is given to `dead_letters`
+    /// and answered with an error, and the bridge moves on.
+    pub fn spawn<T, D>(
+        rt: Arc<SwbusEdgeRuntime>,
+        addr: ServicePath,
+        table: T,
+        retry: RetryPolicy,
+        batch: BatchPolicy,
+        dead_letters: D,
+    ) -> Self
     where
         T: ProducerTable,
+        D: DeadLetterSink,
     {
-        let task = spawn_producer_bridge(rt, addr, table);
+        let task = spawn_producer_bridge(rt, addr, table, retry, batch, dead_letters);
         ProducerBridge {
             _task: AbortOnDropHandle::new(task),
         }
     }
 }
 
-pub fn spawn_producer_bridge<T>(rt: Arc<SwbusEdgeRuntime>, addr: ServicePath, mut table: T) -> JoinHandle<()>
+/// How a failed write to the producer table is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub max_attempts: u32,
+    /// Wait before the first retry. It doubles after every retry, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// How writes to the producer table are batched.
+///
+/// The bridge collects the writes that arrive within `max_delay` of the first one, up to `max_entries`,
+/// and gives them to [`ProducerTable::apply_batch`] at once. The default applies every write as soon as
+/// it arrives.
+#[derive(Clone, Debug)]
+pub struct BatchPolicy {
+    pub max_entries: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        BatchPolicy {
+            max_entries: 1,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// A write the bridge gave up on.
+#[derive(Clone, Debug)]
+pub struct FailedWrite {
+    pub kfv: KeyOpFieldValues,
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+}
+
+/// Where the writes a producer bridge gave up on are recorded.
+pub trait DeadLetterSink: Send + 'static {
+    /// Called when a write to `write.kfv.key` is given up on.
+    fn record(&mut self, write: &FailedWrite) -> impl Future<Output = ()> + Send;
+
+    /// Called when a write to `key` succeeds after an earlier write to it was given up on.
+    fn clear(&mut self, key: &str) -> impl Future<Output = ()> + Send;
+}
+
+pub fn spawn_producer_bridge<T, D>(
+    rt: Arc<SwbusEdgeRuntime>,
+    addr: ServicePath,
+    mut table: T,
+    retry: RetryPolicy,
+    batch: BatchPolicy,
+    mut dead_letters: D,
+) -> JoinHandle<()>
 where
     T: ProducerTable,
+    D: DeadLetterSink,
 {
     let swbus = SimpleSwbusEdgeClient::new(rt, addr, false, false);
     tokio::task::spawn(async move {
-        loop {
+        let mut dead_keys = HashSet::new();
+        let mut shut_down = false;
+        while !shut_down {
             let Some(msg) = swbus.recv().await else {
                 // Swbus shut down, we might as well quit.
                 break;
             };
-
-            let MessageBody::Request { payload } = msg.body else {
-                // There is no reason we'd receive a response, but ignore them anyway.
-                continue;
-            };
-
-            let (error_code, error_message) = match ActorMessage::deserialize(&payload) {
-                Ok(actor_msg) => match actor_msg.deserialize_data::<KeyOpFieldValues>() {
-                    Ok(kfv) => {
-                        table.apply_kfv(kfv).await;
-                        (SwbusErrorCode::Ok, String::new())
+            let mut msgs = vec![msg];
+            let deadline = Instant::now() + batch.max_delay;
+            while msgs.len() < batch.max_entries {
+                match timeout_at(deadline, swbus.recv()).await {
+                    Ok(Some(msg)) => msgs.push(msg),
+                    Ok(None) => {
+                        // Apply what we have before quitting.
+                        shut_down = true;
+                        break;
                     }
-                    Err(e) => (
-                        SwbusErrorCode::InvalidPayload,
-                        format!("Invalid KeyOpFieldValues: {e:#}"),
-                    ),
-                },
-                Err(e) => (SwbusErrorCode::InvalidPayload, format!("Invalid ActorMessage: {e:#}")),
-            };
+                    Err(_) => break,
+                }
+            }
 
-            swbus
-                .send(OutgoingMessage {
-                    destination: msg.source,
-                    body: MessageBody::Response {
-                        request_id: msg.id,
-                        error_code,
-                        error_message,
-                        response_body: None,
+            // Requests and how they are answered, None while their write is to be applied
+            let mut requests = Vec::new();
+            let mut kfvs = Vec::new();
+            for msg in msgs {
+                let MessageBody::Request { payload } = msg.body else {
+                    // There is no reason we'd receive a response, but ignore them anyway.
+                    continue;
+                };
+                let result = match ActorMessage::deserialize(&payload) {
+                    Ok(actor_msg) => match decode_kfv(&actor_msg) {
+                        Ok(kfv) => {
+                            kfvs.push(kfv);
+                            None
+                        }
+                        Err(e) => Some((
+                            SwbusErrorCode::InvalidPayload,
+                            format!("Invalid KeyOpFieldValues: {e:#}"),
+                        )),
                     },
-                })
+                    Err(e) => Some((SwbusErrorCode::InvalidPayload, format!("Invalid ActorMessage: {e:#}"))),
+                };
+                requests.push((msg.source, msg.id, result));
+            }
+
+            let mut results = apply_batch(&mut table, kfvs, &retry, &mut dead_letters, &mut dead_keys)
                 .await
-                .expect("Sending swbus message");
+                .into_iter();
+            for (source, request_id, result) in requests {
+                let (error_code, error_message) = result.or_else(|| results.next()).expect("a result for every write");
+                swbus
+                    .send(OutgoingMessage {
+                        destination: source,
+                        body: MessageBody::Response {
+                            request_id,
+                            error_code,
+                            error_message,
+                            response_body: None,
+                        },
+                    })
+                    .await
+                    .expect("Sending swbus message");
+            }
         }
     })
 }
 
+/// Apply a batch of writes, and return how each is answered. If the batch can't be applied as a
+/// whole, the writes are applied one by one so that each is retried and dead-lettered on its own.
+async fn apply_batch<T, D>(
+    table: &mut T,
+    kfvs: Vec<KeyOpFieldValues>,
+    retry: &RetryPolicy,
+    dead_letters: &mut D,
+    dead_keys: &mut HashSet<String>,
+) -> Vec<(SwbusErrorCode, String)>
+where
+    T: ProducerTable,
+    D: DeadLetterSink,
+{
+    if kfvs.len() > 1 {
+        match table.apply_batch(kfvs.clone()).await {
+            Ok(()) => {
+                let mut results = Vec::with_capacity(kfvs.len());
+                for kfv in &kfvs {
+                    if dead_keys.remove(&kfv.key) {
+                        dead_letters.clear(&kfv.key).await;
+                    }
+                    results.push((SwbusErrorCode::Ok, String::new()));
+                }
+                return results;
+            }
+            Err(e) => debug!(
+                "Failed to apply a batch of {} writes, applying them one by one: {e:#}",
+                kfvs.len()
+            ),
+        }
+    }
+
+    let mut results = Vec::with_capacity(kfvs.len());
+    for kfv in kfvs {
+        let result = match apply_with_retry(table, &kfv, retry).await {
+            Ok(()) => {
+                if dead_keys.remove(&kfv.key) {
+                    dead_letters.clear(&kfv.key).await;
+                }
+                (SwbusErrorCode::Ok, String::new())
+            }
+            Err(write) => {
+                let error_message = format!("Gave up after {} attempts: {}", write.attempts, write.error);
+                dead_letters.record(&write).await;
+                dead_keys.insert(write.kfv.key);
+                (SwbusErrorCode::Fail, error_message)
+            }
+        };
+        results.push(result);
+    }
+    results
+}
+
+async fn apply_with_retry<T: ProducerTable>(
+    table: &mut T,
+    kfv: &KeyOpFieldValues,
+    retry: &RetryPolicy,
+) -> Result<(), FailedWrite> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let error = match table.apply_kfv(kfv.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if attempts >= retry.max_attempts {
+            return Err(FailedWrite {
+                kfv: kfv.clone(),
+                attempts,
+                error: format!("{error:#}"),
+            });
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(retry.max_backoff);
+    }
+}
+
 pub trait ProducerTable: Send + 'static {
-    fn set(&mut self, key: &str, fvs: FieldValues) -> impl Future<Output = ()> + Send;
-    fn del(&mut self, key: &str) -> impl Future<Output = ()> + Send;
-    fn apply_kfv(&mut self, kfv: KeyOpFieldValues) -> impl Future<Output = ()> + Send {
+    fn set(&mut self, key: &str, fvs: FieldValues) -> impl Future<Output = Result<()>> + Send;
+    fn del(&mut self, key: &str) -> impl Future<Output = Result<()>> + Send;
+    fn apply_kfv(&mut self, kfv: KeyOpFieldValues) -> impl Future<Output = Result<()>> + Send {
         async move {
             match kfv.operation {
                 KeyOperation::Set => self.set(&kfv.key, kfv.field_values).await,
@@ -83,21 +269,28 @@ pub trait ProducerTable: Send + 'static {
             }
         }
     }
+
+    /// Apply several writes in order. Tables that can write them at once, e.g. in a single message to
+    /// the consumer, override this; the default applies them one at a time.
+    fn apply_batch(&mut self, kfvs: Vec<KeyOpFieldValues>) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            for kfv in kfvs {
+                self.apply_kfv(kfv).await?;
+            }
+            Ok(())
+        }
+    }
 }
 
 macro_rules! impl_producertable {
     ($($t:ty)*) => {
         $(impl ProducerTable for $t {
-            async fn set(&mut self, key: &str, fvs: FieldValues) {
-                <$t>::set_async(self, key, fvs)
-                    .await
-                    .expect(concat!(stringify!($t::set_async), " threw an exception"))
+            async fn set(&mut self, key: &str, fvs: FieldValues) -> Result<()> {
+                Ok(<$t>::set_async(self, key, fvs).await?)
             }
 
-            async fn del(&mut self, key: &str) {
-                <$t>::del_async(self, key)
-                    .await
-                    .expect(concat!(stringify!($t::del_async), " threw an exception"))
+            async fn del(&mut self, key: &str) -> Result<()> {
+                Ok(<$t>::del_async(self, key).await?)
             }
         })*
     }
@@ -109,22 +302,69 @@ impl_producertable! { ProducerStateTable ZmqProducerStateTable Table }
 mod test {
     use crate::{
         consumer::ConsumerTable,
-        producer::{ProducerBridge, ProducerTable},
+        producer::{BatchPolicy, DeadLetterSink, FailedWrite, ProducerBridge, ProducerTable, RetryPolicy},
     };
     use std::{sync::Arc, time::Duration};
-    use swbus_actor::ActorMessage;
+    use swbus_actor::{ActorMessage, Result};
     use swbus_edge::{
         simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient},
-        swbus_proto::swbus::ServicePath,
+        swbus_proto::swbus::{ServicePath, SwbusErrorCode},
         SwbusEdgeRuntime,
     };
     use swss_common::{
-        ConsumerStateTable, KeyOpFieldValues, ProducerStateTable, ZmqClient, ZmqConsumerStateTable,
-        ZmqProducerStateTable, ZmqServer,
+        ConsumerStateTable, FieldValues, KeyOpFieldValues, KeyOperation, ProducerStateTable, ZmqClient,
+        ZmqConsumerStateTable, ZmqProducerStateTable, ZmqServer,
     };
     use swss_common_testing::{random_kfvs, random_zmq_endpoint, Redis};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
     use tokio::time::timeout;
 
+    /// Records dead letters as `Some(write)` and clears as `None`.
+    struct ChannelSink(UnboundedSender<Option<FailedWrite>>);
+
+    impl DeadLetterSink for ChannelSink {
+        async fn record(&mut self, write: &FailedWrite) {
+            self.0.send(Some(write.clone())).unwrap();
+        }
+
+        async fn clear(&mut self, _key: &str) {
+            self.0.send(None).unwrap();
+        }
+    }
+
+    struct NoDeadLetters;
+
+    impl DeadLetterSink for NoDeadLetters {
+        async fn record(&mut self, write: &FailedWrite) {
+            panic!("unexpected dead letter: {write:?}");
+        }
+
+        async fn clear(&mut self, _key: &str) {}
+    }
+
+    /// Fails every write while `down` is set.
+    struct FlakyTable {
+        down: Arc<std::sync::atomic::AtomicBool>,
+        attempts: u32,
+    }
+
+    impl ProducerTable for FlakyTable {
+        async fn set(&mut self, _key: &str, _fvs: FieldValues) -> Result<()> {
+            self.attempts += 1;
+            if self.down.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(swbus_actor::Error::msg(format!(
+                    "zmq is down (attempt {})",
+                    self.attempts
+                )));
+            }
+            Ok(())
+        }
+
+        async fn del(&mut self, key: &str) -> Result<()> {
+            self.set(key, FieldValues::new()).await
+        }
+    }
+
     #[tokio::test]
     async fn producer_state_table_bridge() {
         let redis = Redis::start();
@@ -145,6 +385,144 @@ mod test {
         timeout(Duration::from_secs(5), run_test(zcst, zpst)).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn failed_writes_are_dead_lettered() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
+
+        let down = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let table = FlakyTable {
+            down: down.clone(),
+            attempts: 0,
+        };
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+        let (sink_tx, mut sink_rx) = unbounded_channel();
+        let _bridge = ProducerBridge::spawn(
+            rt,
+            sp("mytable-bridge"),
+            table,
+            retry,
+            BatchPolicy::default(),
+            ChannelSink(sink_tx),
+        );
+
+        let kfv = KeyOpFieldValues {
+            key: "key0".to_string(),
+            operation: KeyOperation::Set,
+            field_values: FieldValues::new(),
+        };
+        let send_kfv = async || {
+            let msg = OutgoingMessage {
+                destination: sp("mytable-bridge"),
+                body: MessageBody::Request {
+                    payload: encode_kfv(&kfv).into(),
+                },
+            };
+            swbus.send(msg).await.unwrap();
+            match timeout(Duration::from_secs(5), swbus.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .body
+            {
+                MessageBody::Response { error_code, .. } => error_code,
+                _ => panic!("expected a response"),
+            }
+        };
+
+        // The write is given up on after 3 attempts, instead of retrying forever
+        assert_eq!(send_kfv().await, SwbusErrorCode::Fail);
+        let write = sink_rx.recv().await.unwrap().unwrap();
+        assert_eq!(write.kfv, kfv);
+        assert_eq!(write.attempts, 3);
+        assert_eq!(write.error, "zmq is down (attempt 3)");
+
+        // The bridge keeps going, and a later successful write clears the dead letter
+        down.store(false, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(send_kfv().await, SwbusErrorCode::Ok);
+        assert!(sink_rx.recv().await.unwrap().is_none());
+    }
+
+    /// Records the keys of every batch applied.
+    struct BatchRecordingTable(UnboundedSender<Vec<String>>);
+
+    impl ProducerTable for BatchRecordingTable {
+        async fn set(&mut self, key: &str, _fvs: FieldValues) -> Result<()> {
+            self.0.send(vec![key.to_string()]).unwrap();
+            Ok(())
+        }
+
+        async fn del(&mut self, key: &str) -> Result<()> {
+            self.0.send(vec![key.to_string()]).unwrap();
+            Ok(())
+        }
+
+        async fn apply_batch(&mut self, kfvs: Vec<KeyOpFieldValues>) -> Result<()> {
+            self.0.send(kfvs.into_iter().map(|kfv| kfv.key).collect()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_are_batched() {
+        let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
+        swbus_edge.start().await.unwrap();
+        let rt = Arc::new(swbus_edge);
+        let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
+
+        let (batch_tx, mut batch_rx) = unbounded_channel();
+        let batch = BatchPolicy {
+            max_entries: 3,
+            max_delay: Duration::from_millis(200),
+        };
+        let _bridge = ProducerBridge::spawn(
+            rt,
+            sp("mytable-bridge"),
+            BatchRecordingTable(batch_tx),
+            RetryPolicy::default(),
+            batch,
+            NoDeadLetters,
+        );
+
+        for i in 0..5 {
+            let kfv = KeyOpFieldValues {
+                key: format!("key{i}"),
+                operation: KeyOperation::Set,
+                field_values: FieldValues::new(),
+            };
+            let msg = OutgoingMessage {
+                destination: sp("mytable-bridge"),
+                body: MessageBody::Request {
+                    payload: encode_kfv(&kfv).into(),
+                },
+            };
+            swbus.send(msg).await.unwrap();
+        }
+
+        // A full batch goes right away, the rest once the delay is over
+        assert_eq!(batch_rx.recv().await.unwrap(), vec!["key0", "key1", "key2"]);
+        assert_eq!(batch_rx.recv().await.unwrap(), vec!["key3", "key4"]);
+
+        // Every write is answered
+        for _ in 0..5 {
+            match timeout(Duration::from_secs(5), swbus.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .body
+            {
+                MessageBody::Response { error_code, .. } => assert_eq!(error_code, SwbusErrorCode::Ok),
+                _ => panic!("expected a response"),
+            }
+        }
+    }
+
     async fn run_test<C: ConsumerTable, P: ProducerTable>(mut consumer_table: C, producer_table: P) {
         // Setup swbus
         let mut swbus_edge = SwbusEdgeRuntime::new("<none>".to_string(), sp("edge"));
@@ -155,7 +533,14 @@ mod test {
         let swbus = SimpleSwbusEdgeClient::new(rt.clone(), sp("receiver"), true, false);
 
         // Spawn the bridge
-        let _bridge = ProducerBridge::spawn(rt, sp("mytable-bridge"), producer_table);
+        let _bridge = ProducerBridge::spawn(
+            rt,
+            sp("mytable-bridge"),
+            producer_table,
+            RetryPolicy::default(),
+            BatchPolicy::default(),
+            NoDeadLetters,
+        );
 
         // Send some updates to the bridge
         let mut kfvs = random_kfvs();
@@ -163,7 +548,7 @@ mod test {
             let msg = OutgoingMessage {
                 destination: sp("mytable-bridge"),
                 body: MessageBody::Request {
-                    payload: encode_kfv(kfv),
+                    payload: encode_kfv(kfv).into(),
                 },
             };
             swbus.send(msg).await.unwrap();
@@ -172,8 +557,8 @@ mod test {
         // Receive the updates directly
         let mut kfvs_received = Vec::new();
         while kfvs_received.len() < kfvs.len() {
-            consumer_table.read_data().await;
-            kfvs_received.extend(consumer_table.pops().await);
+            consumer_table.read_data().await.unwrap();
+            kfvs_received.extend(consumer_table.pops().await.unwrap());
         }
 
         // Assert we got all the same updates
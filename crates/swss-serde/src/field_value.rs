@@ -13,7 +13,6 @@ pub(crate) fn serialize_field_value<T: Serialize + ?Sized>(value: &T) -> Result<
     value.serialize(FieldValueSerializer)
 }
 
-#[cfg_attr(not(test), allow(dead_code))] // used in tests
 pub(crate) fn deserialize_field_value<T: DeserializeOwned>(fv: Option<&CxxString>) -> Result<T, Error> {
     T::deserialize(FieldValueDeserializer {
         data: fv.map(|s| s.as_bytes()),
@@ -10,7 +10,7 @@ mod table;
 mod test;
 
 use serde::{de::DeserializeOwned, Serialize};
-use swss_common::{FieldValues, Table};
+use swss_common::{CxxString, FieldValues, Table};
 
 /// Purely informational error representing anything that went wrong in `swss-serde`.
 ///
@@ -27,6 +27,11 @@ pub fn from_field_values<T: DeserializeOwned>(fvs: &FieldValues) -> Result<T, Er
     field_values::deserialize_field_values(fvs)
 }
 
+/// Convert a single field value into a rust value. A missing field is passed as `None`.
+pub fn from_field_value<T: DeserializeOwned>(fv: Option<&CxxString>) -> Result<T, Error> {
+    field_value::deserialize_field_value(fv)
+}
+
 /// Write a rust struct into an swss::[`Table`].
 ///
 /// If this function returns an error, the table's field are in an undefined state and should be reset.
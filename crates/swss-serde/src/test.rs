@@ -1,8 +1,9 @@
 use crate::{
     field_value::{deserialize_field_value, serialize_field_value},
-    from_field_values, from_table, to_field_values, to_table,
+    from_field_value, from_field_values, from_table, to_field_values, to_table,
 };
 use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use swss_common::{FieldValues, Table};
 use swss_common_testing::{random_string, Redis};
 
@@ -164,6 +165,21 @@ fn field_value() {
     round_trip!(Some(i32::MIN), Option<i32>);
     round_trip!(Some(i32::MAX), Option<i32>);
     round_trip!(None, Option<i32>);
+    round_trip!(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr);
+    round_trip!(Some("fc00::1".parse().unwrap()), Option<Ipv6Addr>);
+}
+
+#[test]
+fn public_from_field_value() {
+    assert_eq!(
+        from_field_value::<Ipv4Addr>(Some(&"10.0.0.1".into())).unwrap(),
+        Ipv4Addr::new(10, 0, 0, 1)
+    );
+    assert!(from_field_value::<Ipv4Addr>(Some(&"10.0.0".into())).is_err());
+    assert_eq!(
+        from_field_value::<u16>(None).unwrap_err().to_string(),
+        "field is missing"
+    );
 }
 
 #[test]
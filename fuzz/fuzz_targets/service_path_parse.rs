@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use swbus_proto::swbus::ServicePath;
+
+fuzz_target!(|data: &str| {
+    let Ok(sp) = ServicePath::from_string(data) else {
+        return;
+    };
+    // all the forms a service path is printed in for routing and logging
+    let _ = sp.to_string();
+    let _ = sp.to_longest_path();
+    let _ = sp.to_service_prefix();
+    let _ = sp.route_scope();
+});
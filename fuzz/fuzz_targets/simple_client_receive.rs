@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use std::sync::{Arc, LazyLock};
+use swbus_edge::{simple_client::SimpleSwbusEdgeClient, swbus_proto::swbus::*, SwbusEdgeRuntime};
+
+fn client(sink: bool) -> SimpleSwbusEdgeClient {
+    let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/fuzz/0").unwrap();
+    // never started, messages are handed to the client directly
+    let rt = Arc::new(SwbusEdgeRuntime::new("http://127.0.0.1:8080".to_string(), sp.clone()));
+    SimpleSwbusEdgeClient::new(rt, sp, false, sink)
+}
+
+static CLIENT: LazyLock<SimpleSwbusEdgeClient> = LazyLock::new(|| client(false));
+static SINK: LazyLock<SimpleSwbusEdgeClient> = LazyLock::new(|| client(true));
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&sink, data)) = data.split_first() else {
+        return;
+    };
+    let Ok(message) = SwbusMessage::decode(data) else {
+        return;
+    };
+    let client = if sink & 1 == 1 { &*SINK } else { &*CLIENT };
+    client.fuzz_handle_received_message(message);
+});
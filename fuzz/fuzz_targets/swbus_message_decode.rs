@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use swbus_proto::swbus::SwbusMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = SwbusMessage::decode(data) else {
+        return;
+    };
+    // a decoded message encodes back to something that decodes to the same message
+    let encoded = message.encode_to_vec();
+    assert_eq!(SwbusMessage::decode(encoded.as_slice()).unwrap(), message);
+});